@@ -33,16 +33,60 @@ cp target/riscv64imac-unknown-none-elf/$BUILD_MODE/xtra-bootloader build/xtra-bo
 mkdir -p build/boot
 cp target/riscv64imac-unknown-none-elf/$BUILD_MODE/xtra-kernel build/boot/kernel.elf
 
+# Record the Kernel's digest so that the bootloader can tell if the image gets corrupted. If the
+# bootloader was built with XTRA_KERNEL_SIGNING_KEY set, the Kernel must be signed with the matching
+# private key as well, given here as a PEM file in XTRA_KERNEL_SIGNING_PRIVATE_KEY.
+(cd build/boot && sha256sum kernel.elf > kernel.elf.sha256)
+
+rm -f build/boot/kernel.elf.sig
+
+if [ -n "${XTRA_KERNEL_SIGNING_PRIVATE_KEY:-}" ]
+then
+    openssl pkeyutl -sign -rawin \
+        -inkey "$XTRA_KERNEL_SIGNING_PRIVATE_KEY" \
+        -in build/boot/kernel.elf \
+        -out build/boot/kernel.elf.sig
+fi
+
 # Create the mount table for the Kernel to know how to mount the base disk partitions. The
 # bootloader will parse this file and pass the information to the Kernel so that it can mount the
 # required filesystems.
 #
 # Later if we add loadable device drivers the Kernel can search for them in the /boot/ directory.
 cat > build/boot/mount.tbl <<'EOF'
+# Partitions can also be selected with partuuid:<uuid> or label:<label> instead of disk: and pt:.
 mount /     disk:0 pt:1 type:ext2
-mount /boot disk:0 pt:0 type:fat32
+mount /boot disk:0 pt:0 type:fat32 options:noexec
+EOF
+
+# Create the Kernel command line. The bootloader joins the lines of this file together and passes
+# the result to the Kernel, if the file is missing the device tree's bootargs are used instead.
+cat > build/boot/cmdline.txt <<'EOF'
+# For example: loglevel=7 console=ttyS0 root=disk:0,pt:1 rw init=/bin/init maxcpus=4
+loglevel=7 console=ttyS0
+EOF
+
+# Create the boot configuration listing the kernels the bootloader can boot. The fallback entry is
+# booted automatically if the last boot never got far enough to mark itself as successful.
+cat > build/boot/boot.cfg <<'EOF'
+timeout 3
+default xtra
+fallback xtra-safe
+
+entry xtra
+title XTRA-OS
+kernel /kernel.elf
+
+entry xtra-safe
+title XTRA-OS (single CPU, read-only root)
+kernel /kernel.elf
+cmdline loglevel=7 console=ttyS0 ro maxcpus=1
 EOF
 
+# The boot state is a single sector that the bootloader and the Kernel write to directly, so the
+# file has to exist before the first boot.
+dd if=/dev/zero of=build/boot/boot.state bs=512 count=1
+
 
 
 # ---- Setup the user space directories. -----------------------------------------------------------
@@ -58,6 +102,17 @@ mkdir -p build/sys-root/proc
 mkdir -p build/sys-root/mnt
 
 
+# The initial RAM disk holds the files the Kernel needs before it can mount the root filesystem.
+# The bootloader loads it from the /boot partition and the Kernel unpacks it into memory at /.
+rm -rf build/initramfs
+mkdir -p build/initramfs/bin
+mkdir -p build/initramfs/dev
+mkdir -p build/initramfs/lib
+mkdir -p build/initramfs/etc
+
+(cd build/initramfs && find . | cpio --quiet -o -H newc) > build/boot/initrd
+
+
 
 # ---- Create the system disk images. --------------------------------------------------------------
 
@@ -72,7 +127,18 @@ parted -s build/disk0.img set 1 boot on
 dd if=/dev/zero of=build/disk0-part0.img bs=1M count=32
 mkfs.fat -F 32 build/disk0-part0.img
 mcopy -i build/disk0-part0.img build/boot/kernel.elf ::kernel.elf
+mcopy -i build/disk0-part0.img build/boot/kernel.elf.sha256 ::kernel.elf.sha256
+
+if [ -f build/boot/kernel.elf.sig ]
+then
+    mcopy -i build/disk0-part0.img build/boot/kernel.elf.sig ::kernel.elf.sig
+fi
+
 mcopy -i build/disk0-part0.img build/boot/mount.tbl ::mount.tbl
+mcopy -i build/disk0-part0.img build/boot/cmdline.txt ::cmdline.txt
+mcopy -i build/disk0-part0.img build/boot/initrd ::initrd
+mcopy -i build/disk0-part0.img build/boot/boot.cfg ::boot.cfg
+mcopy -i build/disk0-part0.img build/boot/boot.state ::boot.state
 
 # Create the ext2 root filesystem image (~990MB)
 genext2fs -d build/sys-root -b 253952 build/disk0-part1.img
@@ -94,6 +160,12 @@ qemu-system-riscv64 \
     -global virtio-mmio.force-legacy=false \
     -drive file=build/disk0.img,if=none,format=raw,id=x0 \
     -device virtio-blk-device,drive=x0,bus=virtio-mmio-bus.0 \
+    -device virtio-gpu-device,bus=virtio-mmio-bus.1 \
+    -netdev user,id=n0 \
+    -device virtio-net-device,netdev=n0,bus=virtio-mmio-bus.2 \
+    -device virtio-keyboard-device,bus=virtio-mmio-bus.3 \
+    -device virtio-tablet-device,bus=virtio-mmio-bus.4 \
+    -device virtio-rng-device,bus=virtio-mmio-bus.5 \
     -serial stdio \
     -display sdl \
     -smp 4 \
@@ -18,4 +18,35 @@ fn main()
     // Tell rustc to link with our script
     println!("cargo:rustc-link-search={}", out_dir.display());
     println!("cargo:rustc-link-arg=-Tlink.ld");
+
+    // Build in the public key that kernels must be signed with, if one was given. The key is the
+    // raw 32 byte Ed25519 public key written as 64 hex digits.
+    println!("cargo:rerun-if-env-changed=XTRA_KERNEL_SIGNING_KEY");
+
+    let signing_key = match env::var("XTRA_KERNEL_SIGNING_KEY")
+        {
+            Ok(key) if !key.trim().is_empty() =>
+                {
+                    let key = key.trim();
+
+                    if    key.len() != 64
+                       || !key.chars().all(|c| c.is_ascii_hexdigit())
+                    {
+                        panic!("XTRA_KERNEL_SIGNING_KEY must be 64 hex digits.");
+                    }
+
+                    let bytes: Vec<String> = (0..key.len())
+                        .step_by(2)
+                        .map(|index| format!("0x{}", &key[index..index + 2]))
+                        .collect();
+
+                    format!("Some([ {} ])", bytes.join(", "))
+                },
+
+            _ => "None".to_string()
+        };
+
+    fs::write(out_dir.join("signing_key.rs"),
+              format!("const KERNEL_SIGNING_KEY: Option<[u8; ED25519_PUBLIC_KEY_SIZE]> = {};\n",
+                      signing_key)).unwrap();
 }
@@ -186,12 +186,27 @@ impl BlockDevice
         self.virt_device.read_sector(sector, buffer)
     }
 
+    // Perform a polling read of a run of consecutive sectors. The buffer must be a whole number of
+    // sectors long, the read is sent to the device as large requests rather than sector by sector.
+    pub fn read_sectors(&self, sector: u64, buffer: &mut [u8]) -> Result<(), &'static str>
+    {
+        self.virt_device.read_sectors(sector, buffer)
+    }
+
+    // Perform a polling write of a run of consecutive sectors. The buffer must be a whole number of
+    // sectors long.
+    pub fn write_sectors(&self, sector: u64, buffer: &[u8]) -> Result<(), &'static str>
+    {
+        self.virt_device.write_sectors(sector, buffer)
+    }
+
     // Finds a bootable partition on the block device. In this case we expect that the partition is
     // a fat32 partition. It's a very simple implementation that just returns the first fat32
     // partition it finds.
     //
-    // If no fat32 partitions are found, it returns None.
-    pub fn find_bootable_partition(&self, uart: &Uart) -> Option<LegacyPartition>
+    // The partition is returned along with its index in the partition table and the disk signature
+    // from the MBR. If no fat32 partitions are found, it returns None.
+    pub fn find_bootable_partition(&self, uart: &Uart) -> Option<(usize, LegacyPartition, u32)>
     {
         let mut buffer = [0u8; SECTOR_SIZE];
 
@@ -224,13 +239,13 @@ impl BlockDevice
             uart.put_str("Valid MBR found on block device.\n");
         }
 
-        for partition in mbr.partitions()
+        for (index, partition) in mbr.partitions().iter().enumerate()
         {
             // Check if the partition is bootable and has a valid type.
             if partition.is_bootable()
             {
                 uart.put_str("Found bootable partition.\n");
-                return Some(*partition);
+                return Some((index, *partition, mbr.disk_signature()));
             }
         }
 
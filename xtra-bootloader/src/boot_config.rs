@@ -0,0 +1,549 @@
+// Module to read the boot configuration and the record of how the last boot went.
+//
+// The boot configuration is the optional text file boot.cfg at the root of the boot partition. It
+// lists the kernels that can be booted, each with its own mount table, command line and initial
+// RAM disk. Blank lines are ignored and comments start with a '#' at the start of a line. The
+// global settings are:
+//
+//     timeout <seconds>        How long the boot menu waits before booting the default entry.
+//     default <name>           The entry booted if nobody picks one, the first entry otherwise.
+//     fallback <name>          The entry booted if the last boot never completed.
+//
+// Each entry starts with an entry line and is followed by its settings:
+//
+//     entry <name>             Start a new entry with the given name.
+//     title <text>             The text shown in the boot menu, the entry's name otherwise.
+//     kernel <path>            The kernel image, this one is required.
+//     mount <path>             The mount table, mount.tbl next to the kernel by default.
+//     initrd <path>            The initial RAM disk, initrd next to the kernel by default.
+//     cmdline <text>           The kernel command line, replacing any cmdline.txt file.
+//     sha256 <digest>          The SHA-256 digest the kernel image must have, as 64 hex digits.
+//     signature <path>         The file holding the kernel image's Ed25519 signature.
+//
+// Paths that don't start with a '/' are relative to the directory holding the entry's kernel.
+//
+// The boot state is kept in the single sector file boot.state, also at the root of the boot
+// partition. We write to the file's sector directly, so the file has to exist already.
+
+use xtra_kernel_shared::{ boot_info::XTRA_MAX_COMMAND_LINE_LENGTH,
+                          boot_state::{ XtraBootState,
+                                        XTRA_BOOT_STATE_SIZE,
+                                        XTRA_MAX_BOOT_ENTRY_NAME_LENGTH },
+                          mount_table::padded_str };
+
+use crate::{ block_device::BlockDevice,
+             fat32::{ Fat32Volume, FileStream },
+             initrd::INITRD_FILE_NAME,
+             kernel_verify::parse_sha256_digest,
+             mount_table::MOUNT_TABLE_FILE_NAME,
+             sha2::SHA256_DIGEST_SIZE };
+
+
+
+/// The path of the boot configuration on the boot partition.
+pub const BOOT_CONFIG_PATH: &str = "/boot.cfg";
+
+/// The path of the boot state on the boot partition.
+pub const BOOT_STATE_PATH: &str = "/boot.state";
+
+/// The most entries a boot configuration can have, the boot menu selects them with a single digit.
+pub const MAX_BOOT_ENTRIES: usize = 9;
+
+/// The longest title and path we support for an entry.
+const MAX_TITLE_LENGTH: usize = 64;
+const MAX_PATH_LENGTH: usize = 128;
+
+/// The longest line we support in the boot configuration, enough for the cmdline keyword and a
+/// full kernel command line.
+const MAX_LINE_LENGTH: usize = XTRA_MAX_COMMAND_LINE_LENGTH + 16;
+
+/// The name given to the single entry we make up when there is no boot configuration.
+const IMPLICIT_ENTRY_NAME: &str = "default";
+
+
+
+/// Errors found reading the boot configuration, with the line of the file they were found on. The
+/// line is 0 for errors that don't belong to any one line.
+pub struct BootConfigError
+{
+    pub line: usize,
+    pub message: &'static str
+}
+
+
+impl BootConfigError
+{
+    fn new(line: usize, message: &'static str) -> Self
+    {
+        BootConfigError { line, message }
+    }
+}
+
+
+impl From<&'static str> for BootConfigError
+{
+    fn from(message: &'static str) -> Self
+    {
+        BootConfigError::new(0, message)
+    }
+}
+
+
+
+/// A kernel that can be booted, along with the files that go with it.
+#[derive(Clone, Copy)]
+pub struct BootEntry
+{
+    name: [u8; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH],
+    title: [u8; MAX_TITLE_LENGTH],
+    kernel: [u8; MAX_PATH_LENGTH],
+    mount_table: [u8; MAX_PATH_LENGTH],
+    initrd: [u8; MAX_PATH_LENGTH],
+    command_line: [u8; XTRA_MAX_COMMAND_LINE_LENGTH],
+    has_command_line: bool,
+    digest: Option<[u8; SHA256_DIGEST_SIZE]>,
+    signature: [u8; MAX_PATH_LENGTH]
+}
+
+
+impl BootEntry
+{
+    /// Create a blank entry.
+    const fn new() -> Self
+    {
+        BootEntry
+            {
+                name: [0; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH],
+                title: [0; MAX_TITLE_LENGTH],
+                kernel: [0; MAX_PATH_LENGTH],
+                mount_table: [0; MAX_PATH_LENGTH],
+                initrd: [0; MAX_PATH_LENGTH],
+                command_line: [0; XTRA_MAX_COMMAND_LINE_LENGTH],
+                has_command_line: false,
+                digest: None,
+                signature: [0; MAX_PATH_LENGTH]
+            }
+    }
+
+    /// The name the entry is referred to by.
+    pub fn name(&self) -> &str
+    {
+        padded_str(&self.name)
+    }
+
+    /// The text to show for the entry in the boot menu.
+    pub fn title(&self) -> &str
+    {
+        match padded_str(&self.title)
+        {
+            ""    => self.name(),
+            title => title
+        }
+    }
+
+    /// The path of the kernel image.
+    pub fn kernel_path(&self) -> &str
+    {
+        padded_str(&self.kernel)
+    }
+
+    /// The path of the mount table.
+    pub fn mount_table_path(&self) -> &str
+    {
+        match padded_str(&self.mount_table)
+        {
+            ""   => MOUNT_TABLE_FILE_NAME,
+            path => path
+        }
+    }
+
+    /// The path of the initial RAM disk.
+    pub fn initrd_path(&self) -> &str
+    {
+        match padded_str(&self.initrd)
+        {
+            ""   => INITRD_FILE_NAME,
+            path => path
+        }
+    }
+
+    /// The command line given in the configuration, if there was one.
+    pub fn command_line(&self) -> Option<&str>
+    {
+        if self.has_command_line
+        {
+            Some(padded_str(&self.command_line))
+        }
+        else
+        {
+            None
+        }
+    }
+
+    /// The SHA-256 digest given in the configuration, if there was one.
+    pub fn digest(&self) -> Option<&[u8; SHA256_DIGEST_SIZE]>
+    {
+        self.digest.as_ref()
+    }
+
+    /// The path of the signature file given in the configuration, if there was one.
+    pub fn signature_path(&self) -> Option<&str>
+    {
+        match padded_str(&self.signature)
+        {
+            ""   => None,
+            path => Some(path)
+        }
+    }
+}
+
+
+
+/// The boot configuration, the entries that can be booted and how to pick between them.
+pub struct BootConfig
+{
+    pub timeout: u64,  // Seconds to wait in the boot menu.
+
+    default: [u8; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH],
+    fallback: [u8; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH],
+
+    num_entries: usize,
+    entries: [BootEntry; MAX_BOOT_ENTRIES]
+}
+
+
+impl BootConfig
+{
+    /// Create an empty boot configuration.
+    fn new() -> Self
+    {
+        BootConfig
+            {
+                timeout: 0,
+                default: [0; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH],
+                fallback: [0; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH],
+                num_entries: 0,
+                entries: [BootEntry::new(); MAX_BOOT_ENTRIES]
+            }
+    }
+
+    /// Create the configuration used when there is no boot.cfg, a single entry for the kernel at
+    /// the given path with the default files next to it.
+    pub fn implicit(kernel_path: &str) -> Self
+    {
+        let mut config = BootConfig::new();
+
+        copy_padded(&mut config.entries[0].name, IMPLICIT_ENTRY_NAME);
+        copy_padded(&mut config.entries[0].kernel, kernel_path);
+        config.num_entries = 1;
+
+        config
+    }
+
+    /// The entries of the configuration.
+    pub fn entries(&self) -> &[BootEntry]
+    {
+        &self.entries[..self.num_entries]
+    }
+
+    /// Find the index of the entry with the given name.
+    pub fn find(&self, name: &str) -> Option<usize>
+    {
+        self.entries().iter().position(|entry| entry.name() == name)
+    }
+
+    /// The index of the entry to boot if nobody picks one.
+    pub fn default_index(&self) -> usize
+    {
+        self.find(padded_str(&self.default)).unwrap_or(0)
+    }
+
+    /// The index of the entry to boot if the last boot never completed, if one was configured.
+    pub fn fallback_index(&self) -> Option<usize>
+    {
+        self.find(padded_str(&self.fallback))
+    }
+}
+
+
+
+/// Copy a string into a zero padded field. The string must leave room for at least one byte of
+/// padding.
+fn copy_padded(field: &mut [u8], text: &str) -> bool
+{
+    if text.len() >= field.len()
+    {
+        return false;
+    }
+
+    field.fill(0);
+    field[..text.len()].copy_from_slice(text.as_bytes());
+
+    true
+}
+
+
+/// Read the next line of the configuration into the buffer, without its line ending. Returns the
+/// length of the line, or None if we are already at the end of the file.
+fn read_line(file_stream: &mut FileStream,
+             buffer: &mut [u8; MAX_LINE_LENGTH],
+             line_number: usize) -> Result<Option<usize>, BootConfigError>
+{
+    if file_stream.is_eof()
+    {
+        return Ok(None);
+    }
+
+    let mut length: usize = 0;
+
+    while !file_stream.is_eof()
+    {
+        let next = file_stream.read_u8()
+                              .map_err(|message| BootConfigError::new(line_number, message))?;
+
+        if next == b'\n'
+        {
+            break;
+        }
+
+        if length >= MAX_LINE_LENGTH
+        {
+            return Err(BootConfigError::new(line_number, "Line is too long."));
+        }
+
+        buffer[length] = next;
+        length += 1;
+    }
+
+    // Accept DOS line endings too.
+    if    length > 0
+       && buffer[length - 1] == b'\r'
+    {
+        length -= 1;
+    }
+
+    Ok(Some(length))
+}
+
+
+/// Parse a single line of the configuration into the config.
+fn parse_line(text: &str,
+              line_number: usize,
+              config: &mut BootConfig) -> Result<(), BootConfigError>
+{
+    let error = |message| BootConfigError::new(line_number, message);
+
+    let (keyword, value) = match text.split_once(|c: char| c.is_ascii_whitespace())
+        {
+            Some((keyword, value)) => (keyword, value.trim()),
+            None                   => (text, "")
+        };
+
+    if value.is_empty()
+    {
+        return Err(error("Missing value for setting."));
+    }
+
+    match keyword
+    {
+        "timeout" =>
+            {
+                config.timeout = value.parse().map_err(|_| error("Invalid timeout."))?;
+            },
+
+        "default" =>
+            {
+                if !copy_padded(&mut config.default, value)
+                {
+                    return Err(error("Default entry name is too long."));
+                }
+            },
+
+        "fallback" =>
+            {
+                if !copy_padded(&mut config.fallback, value)
+                {
+                    return Err(error("Fallback entry name is too long."));
+                }
+            },
+
+        "entry" =>
+            {
+                if config.num_entries >= MAX_BOOT_ENTRIES
+                {
+                    return Err(error("Too many boot entries."));
+                }
+
+                if config.find(value).is_some()
+                {
+                    return Err(error("Duplicate boot entry name."));
+                }
+
+                let mut entry = BootEntry::new();
+
+                if !copy_padded(&mut entry.name, value)
+                {
+                    return Err(error("Entry name is too long."));
+                }
+
+                config.entries[config.num_entries] = entry;
+                config.num_entries += 1;
+            },
+
+        _ =>
+            {
+                // Everything else belongs to the current entry.
+                if config.num_entries == 0
+                {
+                    return Err(error("Entry setting found before any entry line."));
+                }
+
+                let entry = &mut config.entries[config.num_entries - 1];
+
+                if keyword == "sha256"
+                {
+                    entry.digest = Some(parse_sha256_digest(value)
+                                            .ok_or(error("Invalid SHA-256 digest."))?);
+
+                    return Ok(());
+                }
+
+                let (field, message): (&mut [u8], _) = match keyword
+                    {
+                        "title"     => (&mut entry.title, "Title is too long."),
+                        "kernel"    => (&mut entry.kernel, "Kernel path is too long."),
+                        "mount"     => (&mut entry.mount_table, "Mount table path is too long."),
+                        "initrd"    => (&mut entry.initrd, "Initial RAM disk path is too long."),
+                        "signature" => (&mut entry.signature, "Signature path is too long."),
+
+                        "cmdline" =>
+                            {
+                                entry.has_command_line = true;
+                                (&mut entry.command_line, "Command line is too long.")
+                            },
+
+                        _ => return Err(error("Unknown setting."))
+                    };
+
+                if !copy_padded(field, value)
+                {
+                    return Err(error(message));
+                }
+            }
+    }
+
+    Ok(())
+}
+
+
+/// Read the whole of the configuration from the file stream, and make sure it's complete.
+fn parse_file_stream(file_stream: &mut FileStream) -> Result<BootConfig, BootConfigError>
+{
+    let mut config = BootConfig::new();
+    let mut buffer = [0u8; MAX_LINE_LENGTH];
+    let mut line_number: usize = 0;
+
+    loop
+    {
+        line_number += 1;
+
+        let Some(length) = read_line(file_stream, &mut buffer, line_number)?
+        else
+        {
+            break;
+        };
+
+        let text = str::from_utf8(&buffer[..length])
+            .map_err(|_| BootConfigError::new(line_number, "Line is not valid UTF-8."))?
+            .trim();
+
+        if    text.is_empty()
+           || text.starts_with('#')
+        {
+            continue;
+        }
+
+        parse_line(text, line_number, &mut config)?;
+    }
+
+    if config.num_entries == 0
+    {
+        return Err(BootConfigError::from("No boot entries found."));
+    }
+
+    if config.entries().iter().any(|entry| entry.kernel_path().is_empty())
+    {
+        return Err(BootConfigError::from("Boot entry is missing its kernel."));
+    }
+
+    if    !padded_str(&config.default).is_empty()
+       && config.find(padded_str(&config.default)).is_none()
+    {
+        return Err(BootConfigError::from("Default entry not found."));
+    }
+
+    if    !padded_str(&config.fallback).is_empty()
+       && config.fallback_index().is_none()
+    {
+        return Err(BootConfigError::from("Fallback entry not found."));
+    }
+
+    Ok(config)
+}
+
+
+/// Load the boot configuration from the root of the volume. Returns None if there isn't one.
+pub fn load_boot_config(volume: &Fat32Volume) -> Result<Option<BootConfig>, BootConfigError>
+{
+    let Some((_, config_entry)) = volume.find_path(BOOT_CONFIG_PATH)?
+                                        .filter(|(_, entry)| entry.is_file())
+    else
+    {
+        return Ok(None);
+    };
+
+    let mut config_stream = FileStream::new_from_directory_entry(volume, &config_entry)?;
+
+    parse_file_stream(&mut config_stream).map(Some)
+}
+
+
+/// Read the boot state from the root of the volume. Returns the sector holding the state along with
+/// the state itself, or None if there is no boot state file.
+pub fn read_boot_state(volume: &Fat32Volume,
+                       block_device: &BlockDevice) -> Result<Option<(u64, XtraBootState)>,
+                                                             &'static str>
+{
+    let Some((_, state_entry)) = volume.find_path(BOOT_STATE_PATH)?
+                                       .filter(|(_, entry)| entry.is_file())
+    else
+    {
+        return Ok(None);
+    };
+
+    // We'll be writing straight to the file's first sector, so it has to have one.
+    if    (state_entry.file_size as usize) < XTRA_BOOT_STATE_SIZE
+       || state_entry.first_cluster() < 2
+    {
+        return Err("The boot state file is too small.");
+    }
+
+    let sector = volume.cluster_lba(state_entry.first_cluster());
+    let mut buffer = [0u8; XTRA_BOOT_STATE_SIZE];
+
+    block_device.read_sectors(sector, &mut buffer)?;
+
+    Ok(Some((sector, XtraBootState::from_sector(&buffer))))
+}
+
+
+/// Write the boot state to its sector on the boot device.
+pub fn write_boot_state(block_device: &BlockDevice,
+                        sector: u64,
+                        state: &XtraBootState) -> Result<(), &'static str>
+{
+    let mut buffer = [0u8; XTRA_BOOT_STATE_SIZE];
+
+    state.to_sector(&mut buffer);
+    block_device.write_sectors(sector, &buffer)
+}
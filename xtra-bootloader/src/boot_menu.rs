@@ -0,0 +1,147 @@
+// The interactive boot menu shown on the serial console.
+//
+// We list the entries of the boot configuration and count down to booting the default one. Pressing
+// the number of an entry boots it straight away and pressing enter boots the default. Any other key
+// stops the countdown so that the user can take their time choosing.
+//
+// There's nothing to choose between if there's only one entry, so unless a timeout was configured
+// the menu is skipped entirely.
+
+use crate::{ boot_config::BootConfig,
+             rtc::read_ticks,
+             uart::Uart };
+
+
+
+// The keys we look for while the menu is shown.
+const KEY_ENTER: u8 = b'\r';
+const KEY_NEW_LINE: u8 = b'\n';
+
+
+
+// Convert a key press into the index of the entry it selects, if it selects one at all. Enter
+// selects the default entry.
+fn selected_entry(key: u8, config: &BootConfig, default_index: usize) -> Option<usize>
+{
+    match key
+    {
+        KEY_ENTER | KEY_NEW_LINE => Some(default_index),
+
+        b'1'..=b'9' =>
+            {
+                let index = (key - b'1') as usize;
+
+                if index < config.entries().len()
+                {
+                    Some(index)
+                }
+                else
+                {
+                    None
+                }
+            },
+
+        _ => None
+    }
+}
+
+
+// Print the list of entries, marking the default one.
+fn print_entries(uart: &Uart, config: &BootConfig, default_index: usize)
+{
+    uart.put_str("\nBoot menu:\n");
+
+    for (index, entry) in config.entries().iter().enumerate()
+    {
+        uart.put_str("  ");
+        uart.put_int(index + 1);
+        uart.put_str(") ");
+        uart.put_str(entry.title());
+        uart.put_str(" [");
+        uart.put_str(entry.name());
+        uart.put_str("]");
+
+        if index == default_index
+        {
+            uart.put_str(" (default)");
+        }
+
+        uart.put_str("\n");
+    }
+}
+
+
+// Show the boot menu and wait for the user to pick an entry, or for the timeout to run out. The
+// timebase frequency is the rate of the time CSR, so that we can count down in seconds. Returns the
+// index of the entry to boot.
+pub fn choose_boot_entry(uart: &Uart,
+                         config: &BootConfig,
+                         default_index: usize,
+                         timebase_frequency: u64) -> usize
+{
+    if    config.entries().len() == 1
+       && config.timeout == 0
+    {
+        return default_index;
+    }
+
+    print_entries(uart, config, default_index);
+
+    // Count down to booting the default entry, a second at a time.
+    let start = read_ticks();
+    let mut remaining = config.timeout + 1;
+    let mut interrupted = false;
+
+    while remaining > 0
+    {
+        let elapsed = (read_ticks() - start) / timebase_frequency;
+        let now_remaining = config.timeout.saturating_sub(elapsed);
+
+        if now_remaining != remaining
+        {
+            remaining = now_remaining;
+
+            uart.put_str("\rBooting ");
+            uart.put_str(config.entries()[default_index].title());
+            uart.put_str(" in ");
+            uart.put_int(remaining as usize);
+            uart.put_str(" seconds, press a key to stop... ");
+        }
+
+        if let Some(key) = uart.get_char()
+        {
+            if let Some(index) = selected_entry(key, config, default_index)
+            {
+                uart.put_str("\n");
+                return index;
+            }
+
+            interrupted = true;
+            break;
+        }
+    }
+
+    uart.put_str("\n");
+
+    if !interrupted
+    {
+        return default_index;
+    }
+
+    // The countdown was stopped, so wait for as long as it takes for a choice to be made.
+    uart.put_str("Select an entry 1-");
+    uart.put_int(config.entries().len());
+    uart.put_str(", or press enter for the default: ");
+
+    loop
+    {
+        if let Some(key) = uart.get_char()
+        {
+            if let Some(index) = selected_entry(key, config, default_index)
+            {
+                uart.put_str("\n");
+                return index;
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// Driver for the core local interruptor, (CLINT.) It gives each hart a timer compare register and a
+// software interrupt, which are only reachable from machine mode. The firmware uses them to provide
+// the kernel with the SBI's timer and inter-processor interrupts.
+//
+// QEMU's virt machine lists its CLINT in the device tree, if we can't find it there we fall back to
+// the address QEMU puts it at.
+
+use core::ptr::write_volatile;
+
+use crate::device_tree::DeviceTree;
+
+
+
+// The compatible strings the CLINT can be listed under.
+const CLINT_COMPATIBLE: [&str; 2] = [ "riscv,clint0", "sifive,clint0" ];
+
+// Where QEMU's virt machine puts the CLINT.
+const DEFAULT_CLINT_BASE: usize = 0x0200_0000;
+
+// Offsets of the CLINT's registers. Each hart has a 32-bit software interrupt register and a 64-bit
+// timer compare register.
+const CLINT_MSIP:     usize = 0x0000;
+const CLINT_MTIMECMP: usize = 0x4000;
+
+
+
+// Is the name one of the compatible strings of a CLINT?
+fn is_clint_compatible(name: &[u8]) -> bool
+{
+    CLINT_COMPATIBLE.iter().any(|compatible| name == compatible.as_bytes())
+}
+
+
+
+// The CLINT of the system.
+#[derive(Clone, Copy)]
+pub struct Clint
+{
+    base: usize
+}
+
+
+impl Clint
+{
+    pub const fn new(base: usize) -> Clint
+    {
+        Clint { base }
+    }
+
+    // Find the CLINT in the device tree, falling back to QEMU's address if it isn't listed.
+    pub fn find(device_tree: &DeviceTree) -> Clint
+    {
+        let mut clint_base = DEFAULT_CLINT_BASE;
+
+        device_tree.iterate_blocks(|offset, _name|
+            {
+                let mut base: Option<usize> = None;
+                let mut compatible = false;
+
+                device_tree.iterate_properties(offset, |prop_name, prop_value|
+                    {
+                        match prop_name
+                        {
+                            "compatible" =>
+                                {
+                                    compatible = prop_value.split(|&byte| byte == 0)
+                                                           .any(is_clint_compatible);
+                                },
+
+                            "reg" if prop_value.len() >= 8 =>
+                                {
+                                    let mut bytes = [0u8; 8];
+
+                                    bytes.copy_from_slice(&prop_value[0..8]);
+                                    base = Some(u64::from_be_bytes(bytes) as usize);
+                                },
+
+                            _ => {}
+                        }
+
+                        true
+                    });
+
+                if    compatible
+                   && let Some(base) = base
+                {
+                    clint_base = base;
+
+                    return false;
+                }
+
+                true
+            });
+
+        Clint::new(clint_base)
+    }
+
+    pub fn base(&self) -> usize
+    {
+        self.base
+    }
+
+    // Set the time at which the hart's machine timer interrupt will fire.
+    pub fn set_timer(&self, hart_id: usize, time: u64)
+    {
+        unsafe
+        {
+            write_volatile((self.base + CLINT_MTIMECMP + hart_id * 8) as *mut u64, time);
+        }
+    }
+
+    // Raise a machine software interrupt on the hart.
+    pub fn raise_software_interrupt(&self, hart_id: usize)
+    {
+        unsafe
+        {
+            write_volatile((self.base + CLINT_MSIP + hart_id * 4) as *mut u32, 1);
+        }
+    }
+
+    // Clear the hart's machine software interrupt.
+    pub fn clear_software_interrupt(&self, hart_id: usize)
+    {
+        unsafe
+        {
+            write_volatile((self.base + CLINT_MSIP + hart_id * 4) as *mut u32, 0);
+        }
+    }
+}
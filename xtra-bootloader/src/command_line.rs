@@ -0,0 +1,148 @@
+// Module to find the kernel command line and pass it on to the Kernel.
+//
+// The command line is taken from a cmdline.txt file in the same directory as the kernel image. The
+// file may be split over several lines, which are joined together with spaces, and lines starting
+// with a '#' are comments. If there is no cmdline.txt we fall back to the bootargs property of the
+// device tree's /chosen node, which is where QEMU puts the text given to its -append option.
+//
+// A boot entry in boot.cfg can name a different file, or give the command line directly which then
+// takes the place of both.
+
+use xtra_kernel_shared::boot_info::{ XtraBootInfo, XTRA_MAX_COMMAND_LINE_LENGTH };
+
+use crate::{ device_tree::DeviceTree,
+             fat32::{ Fat32Volume, FileStream } };
+
+
+
+/// The name of the command line file as expected in the same directory as the kernel image.
+pub const COMMAND_LINE_FILE_NAME: &str = "cmdline.txt";
+
+
+
+/// Copy the bootargs property of the device tree's /chosen node into the boot information, if the
+/// property exists. Returns true if a command line was found.
+pub fn load_device_tree_command_line(device_tree: &DeviceTree,
+                                     boot_info: &mut XtraBootInfo) -> Result<bool, &'static str>
+{
+    let mut result = Ok(false);
+
+    device_tree.iterate_blocks(|offset, name|
+        {
+            if name != "chosen"
+            {
+                return true;
+            }
+
+            device_tree.iterate_properties(offset, |prop_name, prop_value|
+                {
+                    if prop_name != "bootargs"
+                    {
+                        return true;
+                    }
+
+                    // The property is a zero terminated string.
+                    let length = prop_value.iter()
+                                           .position(|&byte| byte == 0)
+                                           .unwrap_or(prop_value.len());
+
+                    result = match str::from_utf8(&prop_value[..length])
+                        {
+                            Ok(bootargs) =>
+                                {
+                                    boot_info.set_command_line(bootargs.trim()).map(|_| true)
+                                },
+
+                            Err(_) => Err("The device tree bootargs are not valid UTF-8.")
+                        };
+
+                    false
+                });
+
+            false
+        });
+
+    result
+}
+
+
+/// Read the command line from the file at the given path into the boot information, relative paths
+/// are relative to the directory starting at the given cluster. Returns true if the file was
+/// found, if it wasn't the boot information is left untouched.
+pub fn load_command_line_file(volume: &Fat32Volume,
+                              directory_cluster: usize,
+                              path: &str,
+                              boot_info: &mut XtraBootInfo) -> Result<bool, &'static str>
+{
+    let file_entry = volume.find_path_from(directory_cluster, path)?
+                           .map(|(_, entry)| entry)
+                           .filter(|entry| entry.is_file());
+
+    let Some(file_entry) = file_entry
+    else
+    {
+        return Ok(false);
+    };
+
+    let mut file_stream = FileStream::new_from_directory_entry(volume, &file_entry)?;
+
+    // Build up the command line one line of the file at a time, leaving room for the terminating
+    // zero the Kernel expects.
+    let mut command_line = [0u8; XTRA_MAX_COMMAND_LINE_LENGTH];
+    let mut length: usize = 0;
+    let mut at_line_start = true;
+    let mut in_comment = false;
+
+    while !file_stream.is_eof()
+    {
+        let next = file_stream.read_u8()?;
+
+        if next == b'\n' || next == b'\r'
+        {
+            at_line_start = true;
+            in_comment = false;
+
+            continue;
+        }
+
+        if at_line_start
+        {
+            at_line_start = false;
+            in_comment = next == b'#';
+
+            // Separate the text of this line from that of the last one.
+            if    !in_comment
+               && length > 0
+               && command_line[length - 1] != b' '
+            {
+                if length + 1 >= XTRA_MAX_COMMAND_LINE_LENGTH
+                {
+                    return Err("The kernel command line in cmdline.txt is too long.");
+                }
+
+                command_line[length] = b' ';
+                length += 1;
+            }
+        }
+
+        if in_comment
+        {
+            continue;
+        }
+
+        if length + 1 >= XTRA_MAX_COMMAND_LINE_LENGTH
+        {
+            return Err("The kernel command line in cmdline.txt is too long.");
+        }
+
+        command_line[length] = if next == b'\t' { b' ' } else { next };
+        length += 1;
+    }
+
+    let command_line = str::from_utf8(&command_line[..length])
+        .map_err(|_| "The kernel command line in cmdline.txt is not valid UTF-8.")?;
+
+    boot_info.set_command_line(command_line.trim())?;
+
+    Ok(true)
+}
@@ -0,0 +1,207 @@
+// Access to the machine mode control and status registers, (CSRs,) that the firmware needs to set
+// up the harts for the kernel and to handle the traps that come back to it from supervisor mode.
+
+use core::arch::asm;
+
+
+
+// Generate a function that reads the named CSR.
+macro_rules! read_csr
+{
+    ($function:ident, $csr:literal) =>
+        {
+            #[inline(always)]
+            pub fn $function() -> usize
+            {
+                let value: usize;
+
+                unsafe
+                {
+                    asm!
+                    (
+                        concat!("csrr {0}, ", $csr),
+
+                        out(reg) value,
+                        options(nomem, nostack, preserves_flags)
+                    );
+                }
+
+                value
+            }
+        };
+}
+
+
+// Generate a function that writes a value to the named CSR.
+macro_rules! write_csr
+{
+    ($function:ident, $csr:literal) =>
+        {
+            #[inline(always)]
+            pub fn $function(value: usize)
+            {
+                unsafe
+                {
+                    asm!
+                    (
+                        concat!("csrw ", $csr, ", {0}"),
+
+                        in(reg) value,
+                        options(nomem, nostack, preserves_flags)
+                    );
+                }
+            }
+        };
+}
+
+
+// Generate a function that sets bits in the named CSR, leaving the others alone.
+macro_rules! set_csr_bits
+{
+    ($function:ident, $csr:literal) =>
+        {
+            #[inline(always)]
+            pub fn $function(bits: usize)
+            {
+                unsafe
+                {
+                    asm!
+                    (
+                        concat!("csrs ", $csr, ", {0}"),
+
+                        in(reg) bits,
+                        options(nomem, nostack, preserves_flags)
+                    );
+                }
+            }
+        };
+}
+
+
+// Generate a function that clears bits in the named CSR, leaving the others alone.
+macro_rules! clear_csr_bits
+{
+    ($function:ident, $csr:literal) =>
+        {
+            #[inline(always)]
+            pub fn $function(bits: usize)
+            {
+                unsafe
+                {
+                    asm!
+                    (
+                        concat!("csrc ", $csr, ", {0}"),
+
+                        in(reg) bits,
+                        options(nomem, nostack, preserves_flags)
+                    );
+                }
+            }
+        };
+}
+
+
+
+// Bits of the mstatus register.
+pub const MSTATUS_SIE:            usize = 1 << 1;   // Supervisor interrupts enabled.
+pub const MSTATUS_MIE:            usize = 1 << 3;   // Machine interrupts enabled.
+pub const MSTATUS_SPIE:           usize = 1 << 5;   // Supervisor interrupts enabled before trap.
+pub const MSTATUS_MPIE:           usize = 1 << 7;   // Machine interrupts enabled before trap.
+pub const MSTATUS_MPP_MASK:       usize = 3 << 11;  // Privilege mode before the trap.
+pub const MSTATUS_MPP_SUPERVISOR: usize = 1 << 11;  // The trap came from supervisor mode.
+pub const MSTATUS_MPP_MACHINE:    usize = 3 << 11;  // The trap came from machine mode.
+pub const MSTATUS_MPRV:           usize = 1 << 17;  // Loads and stores use the MPP privilege.
+
+
+// Interrupt bits of the mie, mip and mideleg registers.
+pub const INTERRUPT_SSI: usize = 1 << 1;   // Supervisor software interrupt.
+pub const INTERRUPT_MSI: usize = 1 << 3;   // Machine software interrupt.
+pub const INTERRUPT_STI: usize = 1 << 5;   // Supervisor timer interrupt.
+pub const INTERRUPT_MTI: usize = 1 << 7;   // Machine timer interrupt.
+pub const INTERRUPT_SEI: usize = 1 << 9;   // Supervisor external interrupt.
+
+
+// The top bit of mcause is set if the trap was caused by an interrupt.
+pub const MCAUSE_INTERRUPT: usize = 1 << 63;
+
+// The causes of interrupts, as found in mcause with the interrupt bit cleared.
+pub const CAUSE_MACHINE_SOFTWARE_INTERRUPT: usize = 3;
+pub const CAUSE_MACHINE_TIMER_INTERRUPT:    usize = 7;
+
+// The causes of exceptions, as found in mcause.
+pub const CAUSE_INSTRUCTION_MISALIGNED:    usize = 0;
+pub const CAUSE_INSTRUCTION_ACCESS_FAULT:  usize = 1;
+pub const CAUSE_ILLEGAL_INSTRUCTION:       usize = 2;
+pub const CAUSE_BREAKPOINT:                usize = 3;
+pub const CAUSE_LOAD_MISALIGNED:           usize = 4;
+pub const CAUSE_LOAD_ACCESS_FAULT:         usize = 5;
+pub const CAUSE_STORE_MISALIGNED:          usize = 6;
+pub const CAUSE_STORE_ACCESS_FAULT:        usize = 7;
+pub const CAUSE_USER_ECALL:                usize = 8;
+pub const CAUSE_SUPERVISOR_ECALL:          usize = 9;
+pub const CAUSE_INSTRUCTION_PAGE_FAULT:    usize = 12;
+pub const CAUSE_LOAD_PAGE_FAULT:           usize = 13;
+pub const CAUSE_STORE_PAGE_FAULT:          usize = 15;
+
+
+// Bits of the mcounteren register, they let supervisor mode read the matching counters.
+pub const COUNTER_CYCLE:   usize = 1 << 0;
+pub const COUNTER_TIME:    usize = 1 << 1;
+pub const COUNTER_INSTRET: usize = 1 << 2;
+
+
+// Bits of a PMP configuration entry.
+pub const PMP_R:     usize = 0b_0000_0001;  // Read access.
+pub const PMP_W:     usize = 0b_0000_0010;  // Write access.
+pub const PMP_X:     usize = 0b_0000_0100;  // Execute access.
+pub const PMP_NAPOT: usize = 0b_0001_1000;  // Naturally aligned power of two region.
+
+
+
+// ---- Machine Information Registers -------------------------------------------------------------
+
+read_csr!(read_mvendorid, "mvendorid");
+read_csr!(read_marchid, "marchid");
+read_csr!(read_mimpid, "mimpid");
+read_csr!(read_mhartid, "mhartid");
+
+
+
+// ---- Machine Trap Setup and Handling -----------------------------------------------------------
+
+read_csr!(read_mstatus, "mstatus");
+write_csr!(write_mstatus, "mstatus");
+
+write_csr!(write_medeleg, "medeleg");
+write_csr!(write_mideleg, "mideleg");
+
+write_csr!(write_mie, "mie");
+set_csr_bits!(set_mie_bits, "mie");
+clear_csr_bits!(clear_mie_bits, "mie");
+
+set_csr_bits!(set_mip_bits, "mip");
+clear_csr_bits!(clear_mip_bits, "mip");
+
+write_csr!(write_mtvec, "mtvec");
+write_csr!(write_mscratch, "mscratch");
+write_csr!(write_mcounteren, "mcounteren");
+
+read_csr!(read_mepc, "mepc");
+write_csr!(write_mepc, "mepc");
+
+read_csr!(read_mcause, "mcause");
+read_csr!(read_mtval, "mtval");
+
+
+
+// ---- Machine Memory Protection Registers --------------------------------------------------------
+
+write_csr!(write_pmpcfg0, "pmpcfg0");
+write_csr!(write_pmpaddr0, "pmpaddr0");
+write_csr!(write_pmpaddr1, "pmpaddr1");
+
+
+
+// ---- Supervisor Registers -----------------------------------------------------------------------
+
+write_csr!(write_satp, "satp");
@@ -6,6 +6,8 @@
 
 use core::{ mem::offset_of, ptr, slice::from_raw_parts, str::from_utf8_unchecked };
 
+use xtra_kernel_shared::boot_info::XtraMemoryRange;
+
 use crate::uart::Uart;
 
 
@@ -26,6 +28,16 @@ pub fn validate_dtb(device_tree_ptr: *const u8) -> bool
 }
 
 
+// Get the memory taken up by the device tree blob, its size comes from the blob's header. Anything
+// we load has to stay out of this range.
+pub fn device_tree_range(device_tree_ptr: *const u8) -> XtraMemoryRange
+{
+    let total_size = unsafe { u32::from_be(ptr::read(device_tree_ptr.add(4) as *const u32)) };
+
+    XtraMemoryRange::new(device_tree_ptr as u64, total_size as u64)
+}
+
+
 // The DeviceTree structure represents the device tree blob (DTB) header and provides methods to
 // read and iterate through the device tree structure and properties.
 //
@@ -0,0 +1,575 @@
+// Verification of Ed25519 signatures, as defined in RFC 8032.
+//
+// We only ever verify signatures, never make them, and everything we work with is public. So unlike
+// a general purpose implementation there's no need for constant time arithmetic, which lets us keep
+// things short and simple rather than fast.
+//
+// Field elements are numbers modulo p = 2^255 - 19, held as five 51 bit limbs. Points on the curve
+// are held in extended twisted Edwards coordinates, (X:Y:Z:T) with x = X/Z, y = Y/Z and xy = T/Z.
+
+use crate::sha2::Sha512;
+
+
+
+// The size in bytes of public keys and signatures.
+pub const ED25519_PUBLIC_KEY_SIZE: usize = 32;
+pub const ED25519_SIGNATURE_SIZE: usize = 64;
+
+// The mask for a single 51 bit limb.
+const LIMB_MASK: u64 = (1 << 51) - 1;
+
+// The curve constant d = -121665/121666, little endian.
+const CURVE_D: [u8; 32] =
+    [
+        0xa3, 0x78, 0x59, 0x13, 0xca, 0x4d, 0xeb, 0x75, 0xab, 0xd8, 0x41, 0x41, 0x4d, 0x0a, 0x70,
+        0x00, 0x98, 0xe8, 0x79, 0x77, 0x79, 0x40, 0xc7, 0x8c, 0x73, 0xfe, 0x6f, 0x2b, 0xee, 0x6c,
+        0x03, 0x52
+    ];
+
+// A square root of -1, 2^((p - 1)/4), little endian.
+const SQRT_MINUS_ONE: [u8; 32] =
+    [
+        0xb0, 0xa0, 0x0e, 0x4a, 0x27, 0x1b, 0xee, 0xc4, 0x78, 0xe4, 0x2f, 0xad, 0x06, 0x18, 0x43,
+        0x2f, 0xa7, 0xd7, 0xfb, 0x3d, 0x99, 0x00, 0x4d, 0x2b, 0x0b, 0xdf, 0xc1, 0x4f, 0x80, 0x24,
+        0x83, 0x2b
+    ];
+
+// The exponent p - 2, used to find inverses, little endian.
+const EXPONENT_INVERT: [u8; 32] =
+    [
+        0xeb, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x7f
+    ];
+
+// The exponent (p - 5)/8, used to find square roots, little endian.
+const EXPONENT_SQUARE_ROOT: [u8; 32] =
+    [
+        0xfd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0x0f
+    ];
+
+// The encoding of the base point, (x, 4/5) with x positive.
+const BASE_POINT: [u8; 32] =
+    [
+        0x58, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+        0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+        0x66, 0x66
+    ];
+
+// The order of the base point, L = 2^252 + 27742317777372353535851937790883648493, as 64 bit
+// limbs, least significant first.
+const GROUP_ORDER: [u64; 4] = [ 0x5812631a5cf5d3ed, 0x14def9dea2f79cd6, 0, 0x1000000000000000 ];
+
+// Twice p, limb by limb, added before subtracting so that the limbs don't go negative.
+const TWO_P: [u64; 5] =
+    [
+        0xfffffffffffda, 0xffffffffffffe, 0xffffffffffffe, 0xffffffffffffe, 0xffffffffffffe
+    ];
+
+
+
+// An element of the field of integers modulo p.
+#[derive(Clone, Copy)]
+struct FieldElement([u64; 5]);
+
+
+impl FieldElement
+{
+    const ZERO: FieldElement = FieldElement([ 0, 0, 0, 0, 0 ]);
+    const ONE: FieldElement = FieldElement([ 1, 0, 0, 0, 0 ]);
+
+    // Decode a little endian number, ignoring the top bit.
+    fn from_bytes(bytes: &[u8; 32]) -> Self
+    {
+        let word = |index: usize|
+            {
+                let mut word = [0u8; 8];
+
+                word.copy_from_slice(&bytes[index * 8..index * 8 + 8]);
+                u64::from_le_bytes(word)
+            };
+
+        let words = [ word(0), word(1), word(2), word(3) ];
+
+        FieldElement(
+            [
+                words[0] & LIMB_MASK,
+                ((words[0] >> 51) | (words[1] << 13)) & LIMB_MASK,
+                ((words[1] >> 38) | (words[2] << 26)) & LIMB_MASK,
+                ((words[2] >> 25) | (words[3] << 39)) & LIMB_MASK,
+                (words[3] >> 12) & LIMB_MASK
+            ])
+    }
+
+    // Encode the element as a fully reduced little endian number.
+    fn to_bytes(&self) -> [u8; 32]
+    {
+        let mut limbs = self.carry().carry().0;
+
+        // The limbs now hold a number less than 2p, subtract p if it's at least p. Adding 19 only
+        // carries out of the top limb if that's the case.
+        let mut quotient = (limbs[0] + 19) >> 51;
+
+        for limb in &limbs[1..]
+        {
+            quotient = (limb + quotient) >> 51;
+        }
+
+        limbs[0] += 19 * quotient;
+
+        for index in 0..4
+        {
+            limbs[index + 1] += limbs[index] >> 51;
+            limbs[index] &= LIMB_MASK;
+        }
+
+        limbs[4] &= LIMB_MASK;
+
+        let mut bytes = [0u8; 32];
+        let mut accumulator: u128 = 0;
+        let mut bits = 0;
+        let mut position = 0;
+
+        for limb in limbs
+        {
+            accumulator |= (limb as u128) << bits;
+            bits += 51;
+
+            while    bits >= 8
+                  && position < 32
+            {
+                bytes[position] = accumulator as u8;
+                accumulator >>= 8;
+                bits -= 8;
+                position += 1;
+            }
+        }
+
+        if position < 32
+        {
+            bytes[position] = accumulator as u8;
+        }
+
+        bytes
+    }
+
+    // Carry the excess of each limb into the next, wrapping the top limb's excess around to the
+    // bottom as 2^255 = 19 mod p.
+    fn carry(&self) -> Self
+    {
+        let mut limbs = self.0;
+
+        for index in 0..4
+        {
+            limbs[index + 1] += limbs[index] >> 51;
+            limbs[index] &= LIMB_MASK;
+        }
+
+        limbs[0] += 19 * (limbs[4] >> 51);
+        limbs[4] &= LIMB_MASK;
+
+        FieldElement(limbs)
+    }
+
+    fn add(&self, other: &Self) -> Self
+    {
+        let mut limbs = self.0;
+
+        for (limb, other) in limbs.iter_mut().zip(other.0)
+        {
+            *limb += other;
+        }
+
+        FieldElement(limbs).carry()
+    }
+
+    fn subtract(&self, other: &Self) -> Self
+    {
+        let mut limbs = self.0;
+
+        for index in 0..5
+        {
+            limbs[index] = limbs[index] + TWO_P[index] - other.0[index];
+        }
+
+        FieldElement(limbs).carry()
+    }
+
+    fn negate(&self) -> Self
+    {
+        FieldElement::ZERO.subtract(self)
+    }
+
+    fn multiply(&self, other: &Self) -> Self
+    {
+        let a = self.0;
+        let b = other.0;
+
+        let product = |x: u64, y: u64| x as u128 * y as u128;
+
+        // Multiplying by 2^255 is the same as multiplying by 19, so the parts of the product that
+        // land above the top limb are folded back in multiplied by 19.
+        let b1_19 = b[1] * 19;
+        let b2_19 = b[2] * 19;
+        let b3_19 = b[3] * 19;
+        let b4_19 = b[4] * 19;
+
+        let mut columns =
+            [
+                product(a[0], b[0]) + product(a[1], b4_19) + product(a[2], b3_19)
+                    + product(a[3], b2_19) + product(a[4], b1_19),
+                product(a[0], b[1]) + product(a[1], b[0]) + product(a[2], b4_19)
+                    + product(a[3], b3_19) + product(a[4], b2_19),
+                product(a[0], b[2]) + product(a[1], b[1]) + product(a[2], b[0])
+                    + product(a[3], b4_19) + product(a[4], b3_19),
+                product(a[0], b[3]) + product(a[1], b[2]) + product(a[2], b[1])
+                    + product(a[3], b[0]) + product(a[4], b4_19),
+                product(a[0], b[4]) + product(a[1], b[3]) + product(a[2], b[2])
+                    + product(a[3], b[1]) + product(a[4], b[0])
+            ];
+
+        for index in 0..4
+        {
+            columns[index + 1] += columns[index] >> 51;
+            columns[index] &= LIMB_MASK as u128;
+        }
+
+        columns[0] += 19 * (columns[4] >> 51);
+        columns[4] &= LIMB_MASK as u128;
+
+        columns[1] += columns[0] >> 51;
+        columns[0] &= LIMB_MASK as u128;
+
+        FieldElement(
+            [
+                columns[0] as u64,
+                columns[1] as u64,
+                columns[2] as u64,
+                columns[3] as u64,
+                columns[4] as u64
+            ])
+    }
+
+    fn square(&self) -> Self
+    {
+        self.multiply(self)
+    }
+
+    // Raise the element to a power given as a little endian number.
+    fn power(&self, exponent: &[u8; 32]) -> Self
+    {
+        let mut result = FieldElement::ONE;
+
+        for bit in (0..256).rev()
+        {
+            result = result.square();
+
+            if (exponent[bit / 8] >> (bit % 8)) & 1 == 1
+            {
+                result = result.multiply(self);
+            }
+        }
+
+        result
+    }
+
+    fn invert(&self) -> Self
+    {
+        self.power(&EXPONENT_INVERT)
+    }
+
+    fn is_zero(&self) -> bool
+    {
+        self.to_bytes() == [0; 32]
+    }
+
+    // Is the element "negative", that is is its lowest bit set?
+    fn is_negative(&self) -> bool
+    {
+        self.to_bytes()[0] & 1 == 1
+    }
+
+    fn equals(&self, other: &Self) -> bool
+    {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+
+
+// A point on the curve.
+#[derive(Clone, Copy)]
+struct Point
+{
+    x: FieldElement,
+    y: FieldElement,
+    z: FieldElement,
+    t: FieldElement
+}
+
+
+impl Point
+{
+    // The neutral element, (0, 1).
+    const IDENTITY: Point = Point
+        {
+            x: FieldElement::ZERO,
+            y: FieldElement::ONE,
+            z: FieldElement::ONE,
+            t: FieldElement::ZERO
+        };
+
+    // Decode a point, failing if the encoding isn't that of a point on the curve.
+    fn decompress(bytes: &[u8; 32]) -> Option<Self>
+    {
+        let y = FieldElement::from_bytes(bytes);
+        let sign = bytes[31] >> 7 == 1;
+
+        // The y coordinate must be fully reduced.
+        let mut canonical = y.to_bytes();
+
+        canonical[31] |= bytes[31] & 0x80;
+
+        if canonical != *bytes
+        {
+            return None;
+        }
+
+        // Solve x^2 = (y^2 - 1)/(d y^2 + 1) for x, as in section 5.1.3 of RFC 8032.
+        let d = FieldElement::from_bytes(&CURVE_D);
+        let y_squared = y.square();
+        let u = y_squared.subtract(&FieldElement::ONE);
+        let v = d.multiply(&y_squared).add(&FieldElement::ONE);
+
+        let v_cubed = v.square().multiply(&v);
+        let v_seventh = v_cubed.square().multiply(&v);
+
+        let mut x = u.multiply(&v_cubed)
+                     .multiply(&u.multiply(&v_seventh).power(&EXPONENT_SQUARE_ROOT));
+
+        let v_x_squared = v.multiply(&x.square());
+
+        if !v_x_squared.equals(&u)
+        {
+            if !v_x_squared.equals(&u.negate())
+            {
+                return None;
+            }
+
+            x = x.multiply(&FieldElement::from_bytes(&SQRT_MINUS_ONE));
+        }
+
+        if    x.is_zero()
+           && sign
+        {
+            return None;
+        }
+
+        if x.is_negative() != sign
+        {
+            x = x.negate();
+        }
+
+        Some(Point { x, y, z: FieldElement::ONE, t: x.multiply(&y) })
+    }
+
+    // Encode the point.
+    fn compress(&self) -> [u8; 32]
+    {
+        let z_inverse = self.z.invert();
+        let x = self.x.multiply(&z_inverse);
+        let y = self.y.multiply(&z_inverse);
+
+        let mut bytes = y.to_bytes();
+
+        if x.is_negative()
+        {
+            bytes[31] |= 0x80;
+        }
+
+        bytes
+    }
+
+    // Add two points, the formula is complete so it works for doubling as well.
+    fn add(&self, other: &Self) -> Self
+    {
+        let two_d = FieldElement::from_bytes(&CURVE_D).add(&FieldElement::from_bytes(&CURVE_D));
+
+        let a = self.y.subtract(&self.x).multiply(&other.y.subtract(&other.x));
+        let b = self.y.add(&self.x).multiply(&other.y.add(&other.x));
+        let c = self.t.multiply(&two_d).multiply(&other.t);
+        let d = self.z.add(&self.z).multiply(&other.z);
+
+        let e = b.subtract(&a);
+        let f = d.subtract(&c);
+        let g = d.add(&c);
+        let h = b.add(&a);
+
+        Point
+            {
+                x: e.multiply(&f),
+                y: g.multiply(&h),
+                z: f.multiply(&g),
+                t: e.multiply(&h)
+            }
+    }
+
+    fn negate(&self) -> Self
+    {
+        Point { x: self.x.negate(), y: self.y, z: self.z, t: self.t.negate() }
+    }
+
+    // Multiply the point by a scalar given as a little endian number.
+    fn multiply(&self, scalar: &[u8; 32]) -> Self
+    {
+        let mut result = Point::IDENTITY;
+
+        for bit in (0..256).rev()
+        {
+            result = result.add(&result);
+
+            if (scalar[bit / 8] >> (bit % 8)) & 1 == 1
+            {
+                result = result.add(self);
+            }
+        }
+
+        result
+    }
+}
+
+
+
+// Is the little endian number less than the group order?
+fn is_canonical_scalar(scalar: &[u8; 32]) -> bool
+{
+    for index in (0..4).rev()
+    {
+        let mut word = [0u8; 8];
+
+        word.copy_from_slice(&scalar[index * 8..index * 8 + 8]);
+
+        let word = u64::from_le_bytes(word);
+
+        if word != GROUP_ORDER[index]
+        {
+            return word < GROUP_ORDER[index];
+        }
+    }
+
+    false
+}
+
+
+// Reduce a 512 bit little endian number modulo the group order, one bit at a time.
+fn reduce_scalar(wide: &[u8; 64]) -> [u8; 32]
+{
+    let mut remainder = [0u64; 4];
+
+    for bit in (0..512).rev()
+    {
+        // Shift in the next bit. The remainder is less than 2^253 so this can't overflow.
+        for index in (1..4).rev()
+        {
+            remainder[index] = (remainder[index] << 1) | (remainder[index - 1] >> 63);
+        }
+
+        remainder[0] = (remainder[0] << 1) | ((wide[bit / 8] >> (bit % 8)) & 1) as u64;
+
+        // Subtract the order whenever we reach it.
+        let at_least_order = (0..4).rev()
+                                   .find(|&index| remainder[index] != GROUP_ORDER[index])
+                                   .is_none_or(|index| remainder[index] > GROUP_ORDER[index]);
+
+        if at_least_order
+        {
+            let mut borrow = 0;
+
+            for index in 0..4
+            {
+                let (difference, borrow_1) = remainder[index].overflowing_sub(GROUP_ORDER[index]);
+                let (difference, borrow_2) = difference.overflowing_sub(borrow);
+
+                remainder[index] = difference;
+                borrow = (borrow_1 | borrow_2) as u64;
+            }
+        }
+    }
+
+    let mut bytes = [0u8; 32];
+
+    for (chunk, limb) in bytes.chunks_exact_mut(8).zip(remainder)
+    {
+        chunk.copy_from_slice(&limb.to_le_bytes());
+    }
+
+    bytes
+}
+
+
+
+// Verifies an Ed25519 signature over a message that's given a piece at a time.
+pub struct Ed25519Verifier
+{
+    public_key: [u8; ED25519_PUBLIC_KEY_SIZE],
+    signature: [u8; ED25519_SIGNATURE_SIZE],
+    hash: Sha512
+}
+
+
+impl Ed25519Verifier
+{
+    // Start verifying a signature made with the given public key.
+    pub fn new(public_key: &[u8; ED25519_PUBLIC_KEY_SIZE],
+               signature: &[u8; ED25519_SIGNATURE_SIZE]) -> Self
+    {
+        // The message is hashed along with the signature's R and the public key.
+        let mut hash = Sha512::new();
+
+        hash.update(&signature[..32]);
+        hash.update(public_key);
+
+        Ed25519Verifier { public_key: *public_key, signature: *signature, hash }
+    }
+
+    // Add more of the message.
+    pub fn update(&mut self, data: &[u8])
+    {
+        self.hash.update(data);
+    }
+
+    // Check the signature against the whole of the message, returning true if it's valid. This is
+    // the check of section 5.1.7 of RFC 8032, [S]B = R + [k]A, done as R = [S]B - [k]A.
+    pub fn verify(self) -> bool
+    {
+        let Some(public_key) = Point::decompress(&self.public_key)
+        else
+        {
+            return false;
+        };
+
+        let mut r = [0u8; 32];
+        let mut s = [0u8; 32];
+
+        r.copy_from_slice(&self.signature[..32]);
+        s.copy_from_slice(&self.signature[32..]);
+
+        if !is_canonical_scalar(&s)
+        {
+            return false;
+        }
+
+        let Some(base) = Point::decompress(&BASE_POINT)
+        else
+        {
+            return false;
+        };
+
+        let k = reduce_scalar(&self.hash.finalize());
+        let check = base.multiply(&s).add(&public_key.negate().multiply(&k));
+
+        check.compress() == r
+    }
+}
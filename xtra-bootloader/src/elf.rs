@@ -2,11 +2,15 @@
 // Our elf file parsing and loading code. We also perform validation of the ELF file to ensure it is
 // compatible with the architecture we are running on.
 
-use core::{ mem::transmute, slice::from_raw_parts_mut };
+use core::{ mem::transmute, ptr::read_unaligned, slice::from_raw_parts_mut };
 
-use xtra_kernel_shared::mount_table::XtraMountTable;
+use xtra_kernel_shared::boot_info::{ XtraBootInfo, XtraMemoryRange };
 
-use crate::{ fat32::FileStream, uart::Uart };
+use crate::{ device_tree::device_tree_range,
+             fat32::FileStream,
+             firmware::{ firmware_region, start_supervisor },
+             kernel_verify::{ KernelCheck, KernelVerification },
+             uart::Uart };
 
 
 
@@ -53,13 +57,16 @@ const _ : () =
 
 impl Elf64Header
 {
-    // Read the ELF header from the file stream and return a new Elf64Header instance.
-    pub fn new(file_stream: &mut FileStream) -> Result<Self, &'static str>
+    // Read the ELF header from the start of the file's data and return a new Elf64Header
+    // instance.
+    pub fn new(data: &[u8]) -> Result<Self, &'static str>
     {
-        let mut header = Elf64Header::zeroed();
+        if data.len() < size_of::<Elf64Header>()
+        {
+            return Err("The file is too small to be an ELF file.");
+        }
 
-        file_stream.read_data(&mut header)?;
-        Ok(header)
+        Ok(unsafe { read_unaligned(data.as_ptr() as *const Elf64Header) })
     }
 
     /// Create a new blank ELF header with all fields zeroed.
@@ -183,13 +190,18 @@ const MAX_PROGRAM_HEADERS: usize = 8;
 
 impl Elf64ProgramHeader
 {
-    /// Read the program header from the file stream and return a new Elf64ProgramHeader instance.
-    pub fn new(file_stream: &mut FileStream) -> Result<Self, &'static str>
+    /// Read the program header at the given offset in the file's data and return a new
+    /// Elf64ProgramHeader instance.
+    pub fn new(data: &[u8], offset: usize) -> Result<Self, &'static str>
     {
-        let mut header = Elf64ProgramHeader::zeroed();
+        let end = offset.checked_add(size_of::<Elf64ProgramHeader>());
 
-        file_stream.read_data(&mut header)?;
-        Ok(header)
+        if end.is_none_or(|end| end > data.len())
+        {
+            return Err("ELF program headers must be near the start of the file.");
+        }
+
+        Ok(unsafe { read_unaligned(data[offset..].as_ptr() as *const Elf64ProgramHeader) })
     }
 
     /// Create a new blank program header with all fields zeroed.
@@ -217,11 +229,11 @@ impl Elf64ProgramHeader
 
 
 
-/// Define the function to execute the kernel. It's expected to take the hart ID and device tree
-/// pointer as arguments and never return.
+/// Define the function to execute the kernel. It's expected to take the hart ID, device tree and
+/// boot information pointers as arguments and never return.
 type KernelEntryPoint = extern "C" fn(hart_id: usize,
                                       device_tree_ptr: *const u8,
-                                      mount_table_ptr: *const XtraMountTable) -> !;
+                                      boot_info_ptr: *const XtraBootInfo) -> !;
 
 
 
@@ -269,56 +281,125 @@ fn validate_elf_header(header: &Elf64Header) -> Result<(), &'static str>
 
 
 
-/// Load a single loadable segment from the ELF file into memory at the specified virtual address.
-/// This function assumes that the file stream is already positioned at the start of the segment's
-/// data in the file.
-fn load_segment(program_header: &Elf64ProgramHeader,
-                file_stream: &mut FileStream) -> Result<(), &'static str>
+/// How much of the kernel file we read at a time while loading it. The ELF header and the program
+/// headers have to be within the first read.
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+
+
+unsafe extern "C"
 {
-    let destination_address = program_header.p_vaddr as *mut u8;
-    let position = file_stream.tell();
+    static _kernel_region_start: u8;
+    static _kernel_region_end: u8;
+
+    static _boot_scratch_region_start: u8;
+    static _boot_scratch_region_end: u8;
+}
 
-    // Seek to the segment's offset in the file.
-    file_stream.seek(program_header.p_offset as usize)?;
 
+
+/// Get the range of memory the kernel's image may be loaded into, from the end of the firmware to
+/// the start of the boot scratch area.
+fn kernel_region() -> (usize, usize)
+{
     unsafe
     {
-        let destination_slice = from_raw_parts_mut(destination_address,
-                                                   program_header.p_filesz as usize);
+        (&_kernel_region_start as *const u8 as usize, &_kernel_region_end as *const u8 as usize)
+    }
+}
 
-        file_stream.read_bytes(destination_slice)?;
 
-        // Zero out any remaining memory (p_memsz > p_filesz for BSS sections.)
-        if program_header.p_memsz > program_header.p_filesz
-        {
-            let zero_start = destination_address.offset(program_header.p_filesz as isize);
-            let zero_size  = (program_header.p_memsz - program_header.p_filesz) as usize;
-            let zero_slice = from_raw_parts_mut(zero_start, zero_size);
+/// Get the range of memory holding our boot stack and the boot information for the kernel.
+fn boot_scratch_region() -> (usize, usize)
+{
+    unsafe
+    {
+        (&_boot_scratch_region_start as *const u8 as usize,
+         &_boot_scratch_region_end as *const u8 as usize)
+    }
+}
 
-            zero_slice.fill(0);
-        }
+
+
+/// Make sure a loadable segment can be loaded. Its data has to be within the file and it has to fit
+/// within the kernel's region of memory. It can't be loaded over the firmware, which stays resident
+/// under the kernel, the boot scratch area, the device tree or the modules loaded for the kernel.
+fn validate_segment(program_header: &Elf64ProgramHeader,
+                    file_size: usize,
+                    device_tree_ptr: *const u8,
+                    boot_info: &XtraBootInfo) -> Result<(), &'static str>
+{
+    let data_end = (program_header.p_offset as usize).checked_add(program_header.p_filesz as usize);
+
+    if data_end.is_none_or(|data_end| data_end > file_size)
+    {
+        return Err("ELF segment extends past the end of the file.");
+    }
+
+    // The file's data is copied to the start of the segment, it can't be larger than the segment.
+    if program_header.p_filesz > program_header.p_memsz
+    {
+        return Err("ELF segment has more data in the file than room in memory.");
+    }
+
+    if program_header.p_vaddr.checked_add(program_header.p_memsz).is_none()
+    {
+        return Err("ELF segment extends past the end of memory.");
+    }
+
+    let segment_range = XtraMemoryRange::new(program_header.p_vaddr, program_header.p_memsz);
+
+    let (firmware_start, firmware_end) = firmware_region();
+    let firmware_range = XtraMemoryRange::new(firmware_start as u64,
+                                              (firmware_end - firmware_start) as u64);
+
+    if segment_range.overlaps(&firmware_range)
+    {
+        return Err("ELF segment overlaps the firmware region.");
+    }
+
+    let (scratch_start, scratch_end) = boot_scratch_region();
+    let scratch_range = XtraMemoryRange::new(scratch_start as u64,
+                                             (scratch_end - scratch_start) as u64);
+
+    if segment_range.overlaps(&scratch_range)
+    {
+        return Err("ELF segment overlaps the boot scratch region.");
+    }
+
+    if segment_range.overlaps(&device_tree_range(device_tree_ptr))
+    {
+        return Err("ELF segment overlaps the device tree.");
+    }
+
+    if boot_info.modules().iter().any(|module| module.range.overlaps(&segment_range))
+    {
+        return Err("ELF segment overlaps a boot module.");
     }
 
-    // Restore the old file stream position for the next header.
-    file_stream.seek(position)?;
+    // Anything else that the bootloader has placed in memory is outside of the kernel's region, so
+    // this keeps the kernel from being loaded over the top of it.
+    let (kernel_start, kernel_end) = kernel_region();
+
+    if    segment_range.base < kernel_start as u64
+       || segment_range.end() > kernel_end as u64
+    {
+        return Err("ELF segment is outside of the kernel's region of memory.");
+    }
 
     Ok(())
 }
 
 
 
-/// Stream all loadable segments from the ELF file to the specified load address in memory.
-fn stream_kernel_segments(uart: &Uart,
-                          load_address: *const u8,
-                          elf_header: &Elf64Header,
-                          file_stream: &mut FileStream) -> Result<(), &'static str>
+/// Read and print the program headers, they're read from the first chunk of the file.
+fn read_program_headers(uart: &Uart,
+                        elf_header: &Elf64Header,
+                        data: &[u8])
+    -> Result<[Elf64ProgramHeader; MAX_PROGRAM_HEADERS], &'static str>
 {
-    // Seek to the start of the program header table.
-    file_stream.seek(elf_header.e_phoff as usize)?;
-
     let mut program_headers = [Elf64ProgramHeader::zeroed(); MAX_PROGRAM_HEADERS];
 
-    // Read the program headers from the file stream.
     if elf_header.e_phnum as usize > MAX_PROGRAM_HEADERS
     {
         return Err("Too many program headers in ELF file.");
@@ -330,8 +411,8 @@ fn stream_kernel_segments(uart: &Uart,
 
     for index in 0..elf_header.e_phnum as usize
     {
-        let position = file_stream.tell();
-        program_headers[index] = Elf64ProgramHeader::new(file_stream)?;
+        let position = elf_header.e_phoff as usize + index * size_of::<Elf64ProgramHeader>();
+        program_headers[index] = Elf64ProgramHeader::new(data, position)?;
 
         uart.put_str("  Processing program header: ");
         uart.put_int(index as usize);
@@ -372,29 +453,91 @@ fn stream_kernel_segments(uart: &Uart,
         uart.put_str("\n");
     }
 
-    // Process each program header.
-    for index in 0..elf_header.e_phnum
+    Ok(program_headers)
+}
+
+
+
+/// Copy the parts of the loadable segments that fall within a chunk of the file into place. The
+/// chunk starts at the given offset in the file.
+fn place_segment_data(program_headers: &[Elf64ProgramHeader], offset: usize, data: &[u8])
+{
+    for program_header in program_headers.iter().filter(|header| header.is_loadable())
     {
-        let program_header = program_headers[index as usize];
+        let segment_start = program_header.p_offset as usize;
+        let segment_end = segment_start + program_header.p_filesz as usize;
+
+        let start = segment_start.max(offset);
+        let end = segment_end.min(offset + data.len());
 
-        if program_header.is_loadable()
+        if start >= end
         {
-            load_segment(&program_header, file_stream)?;
+            continue;
+        }
+
+        unsafe
+        {
+            let destination = program_header.p_vaddr as usize + (start - segment_start);
+            let destination_slice = from_raw_parts_mut(destination as *mut u8, end - start);
+
+            destination_slice.copy_from_slice(&data[start - offset..end - offset]);
         }
     }
+}
 
-    Ok(())
+
+
+/// Zero out the memory of the loadable segments that isn't backed by the file, (p_memsz > p_filesz
+/// for BSS sections.)
+fn zero_segment_tails(program_headers: &[Elf64ProgramHeader])
+{
+    for program_header in program_headers.iter().filter(|header| header.is_loadable())
+    {
+        if program_header.p_memsz <= program_header.p_filesz
+        {
+            continue;
+        }
+
+        unsafe
+        {
+            let zero_start = (program_header.p_vaddr + program_header.p_filesz) as *mut u8;
+            let zero_size  = (program_header.p_memsz - program_header.p_filesz) as usize;
+            let zero_slice = from_raw_parts_mut(zero_start, zero_size);
+
+            zero_slice.fill(0);
+        }
+    }
 }
 
 
 
-/// Load the kernel from the file stream.
+/// Load the kernel from the file stream. The file is read once, from start to end, and every byte
+/// of it is run through the kernel's checks as it's read. The loadable segments are copied into
+/// place from those same bytes, so the kernel that's checked is the kernel that's run. If a check
+/// fails the entry point isn't set and the kernel can't be executed.
+///
+/// Every segment is validated before anything is written, and segments may only be placed in the
+/// kernel's own region of memory. So until the checks pass the only memory a bad image can change
+/// is memory the kernel would have been loaded into anyway, the device tree and everything the
+/// bootloader has loaded or still needs are left alone.
 pub fn load_kernel(uart: &Uart,
                    load_address: *const u8,
-                   file_stream: &mut FileStream) -> Result<(), &'static str>
+                   file_stream: &mut FileStream,
+                   mut check: KernelCheck,
+                   device_tree_ptr: *const u8,
+                   boot_info: &XtraBootInfo) -> Result<KernelVerification, &'static str>
 {
-    // Read and validate the ELF header from the file stream.
-    let elf_header = Elf64Header::new(file_stream)?;
+    let file_size = file_stream.size();
+    let mut buffer = [0u8; READ_CHUNK_SIZE];
+
+    // The headers come from the first chunk, we need them before any of the segments can be placed.
+    let count = file_size.min(READ_CHUNK_SIZE);
+
+    file_stream.read_bytes(&mut buffer[..count])?;
+    check.update(&buffer[..count]);
+
+    // Read and validate the ELF header.
+    let elf_header = Elf64Header::new(&buffer[..count])?;
 
     validate_elf_header(&elf_header)?;
 
@@ -414,33 +557,65 @@ pub fn load_kernel(uart: &Uart,
     uart.put_int(elf_header.e_phnum as usize);
     uart.put_str("\n");
 
-    // Load the kernel into memory at the specified load address.
-    stream_kernel_segments(uart, load_address, &elf_header, file_stream)?;
+    let program_headers = read_program_headers(uart, &elf_header, &buffer[..count])?;
+    let program_headers = &program_headers[..elf_header.e_phnum as usize];
+
+    // Make sure every segment can be loaded before we write any of them.
+    for program_header in program_headers.iter().filter(|header| header.is_loadable())
+    {
+        validate_segment(program_header, file_size, device_tree_ptr, boot_info)?;
+    }
+
+    // Load the kernel into memory as the rest of the file streams by.
+    place_segment_data(program_headers, 0, &buffer[..count]);
+
+    let mut offset = count;
+
+    while offset < file_size
+    {
+        let count = (file_size - offset).min(READ_CHUNK_SIZE);
+
+        file_stream.read_bytes(&mut buffer[..count])?;
+        check.update(&buffer[..count]);
+
+        place_segment_data(program_headers, offset, &buffer[..count]);
+
+        offset += count;
+    }
+
+    zero_segment_tails(program_headers);
+
+    // Only once the whole file has been checked do we let the kernel be run.
+    let verification = check.finish()?;
 
     // Get the entry point address from the ELF header.
     let entry_point = elf_header.e_entry;
 
-    // Get the kernel entry point function pointer and finally, call it.
+    // Get the kernel entry point function pointer so that it can be called.
     unsafe
     {
         let kernel_entry: KernelEntryPoint = transmute(entry_point);
         KERNEL_ENTRY_POINT = Some(kernel_entry);
     }
 
-    Ok(())
+    Ok(verification)
 }
 
 
 
-/// Actually execute the kernel's startup code by jumping to the kernel entry point.
-///
-/// TODO: In the future we may want to add some additional kernel parameters here to configure the
-///       kernel startup behavior.
-pub fn execute_kernel(hart_id: usize, device_tree_ptr: *const u8, mount_table: &XtraMountTable) -> !
+/// Actually execute the kernel's startup code by entering the kernel entry point in supervisor
+/// mode. Anything else the kernel needs to know about how it was booted is passed in the boot
+/// information structure.
+pub fn execute_kernel(hart_id: usize,
+                      device_tree_ptr: *const u8,
+                      boot_info_ptr: *const XtraBootInfo) -> !
 {
     if let Some(kernel_entry) = unsafe { KERNEL_ENTRY_POINT }
     {
-        kernel_entry(hart_id, device_tree_ptr, mount_table);
+        start_supervisor(hart_id,
+                         kernel_entry as usize,
+                         device_tree_ptr as usize,
+                         boot_info_ptr as usize);
     }
     else
     {
@@ -1,10 +1,13 @@
 
-// All of our code for managing the FAT32 filesystem. We can iterate the root directory of a given
-// FAT32 filesystem on a given partition of a block device. This code is used to find and stream in
-// the kernel file from the filesystem.
+// All of our code for managing the FAT32 filesystem. We can iterate the directories of a given
+// FAT32 filesystem on a given partition of a block device, decoding VFAT long file names as we go,
+// and resolve paths such as /xtra/kernel-0.3.elf to their directory entries. This code is used to
+// find and stream in the kernel file and its mount table from the filesystem.
 
 use core::{ ptr::addr_of_mut, slice::from_raw_parts_mut };
 
+use xtra_kernel_shared::fat::short_name_checksum;
+
 use crate::{ block_device::{ BlockDevice, SECTOR_SIZE },
              partition_table::{ MasterBootRecord, LegacyPartition } };
 
@@ -373,22 +376,95 @@ impl<'a> Fat32Volume<'a>
         Ok(volume)
     }
 
-    // Load a sector from a FAT cluster from the filesystem into the provided buffer.
-    //
-    // Returns an error if the sector could not be loaded or if the cluster or sector is invalid.
-    pub fn load_sector(&self,
-                       cluster: usize,
-                       sector: usize,
-                       buffer: &mut SectorBuffer) -> FatResult<()>
+    // Get the absolute LBA on the block device of the first sector of a cluster.
+    pub fn cluster_lba(&self, cluster: usize) -> u64
     {
         let first_data_sector = self.reserved_sectors + (self.num_fats * self.fat_size_sectors);
+        let cluster_lba = first_data_sector + ((cluster - 2) * self.sectors_per_cluster);
 
-        let cluster_lba = first_data_sector + ((cluster - 2) * self.sectors_per_cluster) + sector;
-        let absolute_lba = self.partition.start_lba as usize + cluster_lba;
+        (self.partition.start_lba as usize + cluster_lba) as u64
+    }
 
-        self.block_device.read_sector(absolute_lba as u64, buffer)?;
+    // The size of a cluster in bytes.
+    pub fn cluster_size(&self) -> usize
+    {
+        self.sectors_per_cluster * SECTOR_SIZE
+    }
 
-        Ok(())
+    // Is the cluster number one that can hold file data?
+    fn is_valid_cluster(&self, cluster: usize) -> bool
+    {
+        cluster >= 2 && cluster < self.fat.entries.len()
+    }
+
+    // Resolve an absolute path such as /xtra/kernel-0.3.elf to its directory entry. Each component
+    // of the path is matched without regard to case against both the long and short names of the
+    // entries in the directory. Empty and "." components are ignored and ".." moves up a level.
+    //
+    // On success we return the first cluster of the directory holding the entry along with the
+    // entry itself, or None if the path doesn't exist.
+    pub fn find_path(&self, path: &str) -> FatResult<Option<(usize, DirectoryEntry)>>
+    {
+        self.find_path_from(self.root_cluster, path)
+    }
+
+    // Resolve a path the same way as find_path, except that paths that don't start with a '/' are
+    // relative to the directory starting at the given cluster.
+    pub fn find_path_from(&self,
+                          directory_cluster: usize,
+                          path: &str) -> FatResult<Option<(usize, DirectoryEntry)>>
+    {
+        let mut components = path.split('/')
+                                 .filter(|component| !component.is_empty() && *component != ".")
+                                 .peekable();
+
+        if components.peek().is_none()
+        {
+            return Err("Path does not name a file.");
+        }
+
+        let mut directory_cluster = if path.starts_with('/')
+            {
+                self.root_cluster
+            }
+            else
+            {
+                directory_cluster
+            };
+
+        while let Some(component) = components.next()
+        {
+            // Look for the component in the current directory.
+            let mut directory_iterator = DirectoryIterator::new(self, directory_cluster)?;
+
+            let entry = match directory_iterator.find(component)?
+                {
+                    Some(entry) => entry,
+                    None        => return Ok(None)
+                };
+
+            // If this was the last component we've found what we're looking for.
+            if components.peek().is_none()
+            {
+                return Ok(Some((directory_cluster, entry)));
+            }
+
+            // Otherwise we need to descend into the entry, so it had better be a directory.
+            if entry.is_file()
+            {
+                return Err("Path component is not a directory.");
+            }
+
+            // The .. entries of directories in the root directory refer to cluster 0 instead of the
+            // root directory's actual cluster.
+            directory_cluster = match entry.first_cluster()
+                {
+                    0       => self.root_cluster,
+                    cluster => cluster
+                };
+        }
+
+        Ok(None)
     }
 
     // Read a u8 value from the FAT32 volume header. An error is returned if the offset is out of
@@ -439,21 +515,59 @@ impl<'a> Fat32Volume<'a>
 
 
 
+// A run of clusters of a file that are stored one after the other on disk.
+#[derive(Clone, Copy)]
+struct Extent
+{
+    file_cluster: usize,   // The index of the run's first cluster within the file.
+    disk_cluster: usize,   // The cluster number of the run's first cluster on disk.
+    cluster_count: usize   // The number of clusters in the run.
+}
+
+
+
+impl Extent
+{
+    const fn zeroed() -> Self
+    {
+        Extent { file_cluster: 0, disk_cluster: 0, cluster_count: 0 }
+    }
+}
+
+
+
+// The number of extents we keep for each open file. Files on the boot partition are normally
+// written in one go and have only a handful of extents, a file more fragmented than this still
+// works, we just have to walk the FAT for the clusters past the end of the map.
+const MAX_FILE_EXTENTS: usize = 32;
+
+
+
 // Interface for streaming through the data in a file in the FAT32 filesystem. This is also used as
 // the basis for reading directories in the filesystem.
 //
 // Directories are just special files that contain entries for each file in the directory.
+//
+// When the stream is created the file's cluster chain is walked once and recorded as a map of
+// extents, runs of consecutive clusters. Seeking is then just a matter of moving the cursor, and
+// finding the disk location of any part of the file is a binary search of the map. Reads of whole
+// sectors go straight from the disk into the caller's buffer as large requests covering as much of
+// an extent as possible, only partial sectors are read through the stream's sector buffer.
 pub struct FileStream<'a>
 {
-    fat_volume: &'a Fat32Volume<'a>,  // The FAT32 volume we are reading from.
-    start_cluster: usize,             // The starting cluster of the file we are reading.
-    size: usize,                      // The size of the file in bytes we are reading.
-    current_cluster: usize,           // The current cluster we are reading from.
-    current_sector: usize,            // The current sector within the cluster we are reading.
-    current_byte: usize,              // The byte offset into the current sector of the file.
-    absolute_byte: usize,             // The absolute byte offset into the file we are reading.
-    buffer: &'a mut SectorBuffer,     // The buffer for the current sector we are reading.
-    buffer_index: usize               // The index of the sector buffer in the sector cache.
+    fat_volume: &'a Fat32Volume<'a>,           // The FAT32 volume we are reading from.
+    start_cluster: usize,                      // The starting cluster of the file we are reading.
+    size: usize,                               // The size of the file in bytes we are reading.
+    extents: [Extent; MAX_FILE_EXTENTS],       // The map of the file's clusters.
+    extent_count: usize,                       // The number of extents in the map.
+    mapped_clusters: usize,                    // The number of the file's clusters in the map.
+    absolute_byte: usize,                      // The absolute byte offset into the file we are
+                                               //   reading.
+    buffered_sector: Option<usize>,            // The index within the file of the sector held in
+                                               //   the buffer, if any.
+    buffer: &'a mut SectorBuffer,              // The buffer for reading partial sectors.
+    buffer_index: usize                        // The index of the sector buffer in the sector
+                                               //   cache.
 }
 
 
@@ -469,8 +583,8 @@ impl<'a> FileStream<'a>
                start_cluster: usize,
                size: usize) -> FatResult<Self>
     {
-        // Allocate a sector buffer from the sector cache. We will load sectors from the filesystem
-        // into this buffer as we stream through the file.
+        // Allocate a sector buffer from the sector cache. Partial sectors are read through this
+        // buffer.
         let (index, buffer) = get_sector_buffer();
 
         let mut fs = FileStream
@@ -478,19 +592,20 @@ impl<'a> FileStream<'a>
                 fat_volume,
                 start_cluster,
                 size,
-                current_cluster: start_cluster,
-                current_sector: 0,
-                current_byte: 0,
+                extents: [Extent::zeroed(); MAX_FILE_EXTENTS],
+                extent_count: 0,
+                mapped_clusters: 0,
                 absolute_byte: 0,
+                buffered_sector: None,
                 buffer,
                 buffer_index: index
             };
 
-        // Check to see if the file has any data in it. If it does, we will load the first sector
-        // into the buffer so that we are ready to read from it.
+        // Check to see if the file has any data in it. If it does, we build the map of its clusters
+        // so that we are ready to read from it.
         if fs.size != 0
         {
-            fs.load_current_sector()?;
+            fs.build_extent_map()?;
         }
 
         Ok(fs)
@@ -507,21 +622,110 @@ impl<'a> FileStream<'a>
         Self::new(fat_volume, start_cluster, size)
     }
 
+    // Walk the file's cluster chain, recording the runs of consecutive clusters that hold the
+    // file's data. We stop once we have enough clusters to cover the file's size, or when the map
+    // is full.
+    fn build_extent_map(&mut self) -> FatResult<()>
+    {
+        let needed_clusters = self.size.div_ceil(self.fat_volume.cluster_size());
+        let mut cluster = self.start_cluster;
+
+        while self.mapped_clusters < needed_clusters
+        {
+            if !self.fat_volume.is_valid_cluster(cluster)
+            {
+                return Err("Attempt to read outside of the partition.");
+            }
+
+            // Either grow the current extent or start a new one.
+            let last = self.extent_count.checked_sub(1).map(|index| self.extents[index]);
+
+            match last
+            {
+                Some(extent) if extent.disk_cluster + extent.cluster_count == cluster =>
+                    {
+                        self.extents[self.extent_count - 1].cluster_count += 1;
+                    },
+
+                _ if self.extent_count < MAX_FILE_EXTENTS =>
+                    {
+                        self.extents[self.extent_count] = Extent
+                            {
+                                file_cluster: self.mapped_clusters,
+                                disk_cluster: cluster,
+                                cluster_count: 1
+                            };
+
+                        self.extent_count += 1;
+                    },
+
+                // The map is full, the rest of the file will be found by walking the FAT.
+                _ => break
+            }
+
+            self.mapped_clusters += 1;
+
+            if self.mapped_clusters < needed_clusters
+            {
+                cluster = self.fat_volume
+                              .fat
+                              .get_next_cluster(cluster)
+                              .ok_or("File cluster chain is shorter than the file.")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Find where a cluster of the file is stored on disk. Returns the disk cluster along with the
+    // number of clusters that follow it contiguously on disk, including itself.
+    fn locate_cluster(&self, file_cluster: usize) -> FatResult<(usize, usize)>
+    {
+        if file_cluster < self.mapped_clusters
+        {
+            // Find the last extent that starts at or before the cluster.
+            let extents = &self.extents[..self.extent_count];
+            let index = extents.partition_point(|extent| extent.file_cluster <= file_cluster) - 1;
+            let extent = &extents[index];
+            let offset = file_cluster - extent.file_cluster;
+
+            return Ok((extent.disk_cluster + offset, extent.cluster_count - offset));
+        }
+
+        // The cluster is past the end of the map, so walk the FAT from the last mapped cluster.
+        let last = &self.extents[self.extent_count - 1];
+        let mut cluster = last.disk_cluster + last.cluster_count - 1;
+
+        for _ in self.mapped_clusters - 1..file_cluster
+        {
+            cluster = self.fat_volume
+                          .fat
+                          .get_next_cluster(cluster)
+                          .ok_or("File cluster chain is shorter than the file.")?;
+        }
+
+        if !self.fat_volume.is_valid_cluster(cluster)
+        {
+            return Err("Attempt to read outside of the partition.");
+        }
+
+        Ok((cluster, 1))
+    }
+
     // Move the file cursor back to the beginning of the file.
     pub fn reset(&mut self) -> FatResult<()>
     {
-        // Reset the file stream to the beginning of the file.
-        self.current_cluster = self.start_cluster;
-        self.current_sector = 0;
-        self.current_byte = 0;
         self.absolute_byte = 0;
 
-        // Load the first sector again.
-        self.load_current_sector()?;
-
         Ok(())
     }
 
+    // The size of the file in bytes.
+    pub fn size(&self) -> usize
+    {
+        self.size
+    }
+
     // Is the file cursor at the end of the file?
     pub fn is_eof(&self) -> bool
     {
@@ -586,36 +790,63 @@ impl<'a> FileStream<'a>
     // bytes in the slice. If the entire slice can not be filled, an error is returned.
     pub fn read_bytes(&mut self, buffer: &mut [u8]) -> FatResult<()>
     {
-        // Attempt to read the specified number of bytes into the buffer.
-        for index in 0..buffer.len()
+        if buffer.len() > self.size.saturating_sub(self.absolute_byte)
         {
-            // Read the next byte from the file stream.
-            match self.next_byte()?
-            {
-                Some(byte) =>
-                {
-                    // Store the byte in the buffer.
-                    buffer[index] = byte;
-                },
+            return Err("End of file reached before filling buffer.");
+        }
 
-                None =>
-                {
-                    // We have reached the end of the file before filling the buffer.
-                    return Err("End of file reached before filling buffer.");
-                }
-            }
+        let cluster_size = self.fat_volume.cluster_size();
+        let mut done = 0;
 
-            // Advance to the next sector if necessary.
-            if self.is_end_of_sector()
-            {
-                // We have reached the end of the current sector, so we need to advance to the next
-                // sector in the file.
-                if !self.next_sector()?
+        while done < buffer.len()
+        {
+            let remaining = buffer.len() - done;
+            let cluster_offset = self.absolute_byte % cluster_size;
+            let sector_offset = self.absolute_byte % SECTOR_SIZE;
+            let (disk_cluster, contiguous_clusters) = self.locate_cluster(self.absolute_byte
+                                                                          / cluster_size)?;
+
+            let count = if    sector_offset == 0
+                           && remaining >= SECTOR_SIZE
                 {
-                    // We have reached the end of the file chain, so we can not read any more sectors.
-                    return Err("End of file reached.");
+                    // We're on a sector boundary and want at least a whole sector, so read as many
+                    // whole sectors as we can straight into the caller's buffer.
+                    let contiguous_bytes = contiguous_clusters * cluster_size - cluster_offset;
+                    let count = (remaining - remaining % SECTOR_SIZE).min(contiguous_bytes);
+                    let lba = self.fat_volume.cluster_lba(disk_cluster)
+                              + (cluster_offset / SECTOR_SIZE) as u64;
+
+                    self.fat_volume
+                        .block_device
+                        .read_sectors(lba, &mut buffer[done..done + count])?;
+
+                    count
                 }
-            }
+                else
+                {
+                    // Only part of a sector is wanted, so go through the sector buffer.
+                    let file_sector = self.absolute_byte / SECTOR_SIZE;
+
+                    if self.buffered_sector != Some(file_sector)
+                    {
+                        let lba = self.fat_volume.cluster_lba(disk_cluster)
+                                  + (cluster_offset / SECTOR_SIZE) as u64;
+
+                        self.buffered_sector = None;
+                        self.fat_volume.block_device.read_sector(lba, self.buffer)?;
+                        self.buffered_sector = Some(file_sector);
+                    }
+
+                    let count = (SECTOR_SIZE - sector_offset).min(remaining);
+
+                    buffer[done..done + count]
+                        .copy_from_slice(&self.buffer[sector_offset..sector_offset + count]);
+
+                    count
+                };
+
+            done += count;
+            self.absolute_byte += count;
         }
 
         // We've successfully read the entire buffer.
@@ -628,130 +859,17 @@ impl<'a> FileStream<'a>
         self.absolute_byte
     }
 
+    // Move the cursor to the given offset in the file. Nothing is read until the next read, which
+    // finds its place on disk through the extent map.
     pub fn seek(&mut self, offset: usize) -> FatResult<()>
     {
-        // TODO: Replace this with a more efficient seek implementation that does not require
-        // actually reading the file byte by byte.
-
         // Check if the offset is within the bounds of the file.
         if offset > self.size
         {
             return Err("Seek offset is out of bounds.");
         }
 
-        self.reset()?;
-
-        for _ in 0..offset
-        {
-            // Read the next byte from the file stream.
-            if self.next_byte()?.is_none()
-            {
-                // We have reached the end of the file before reaching the offset.
-                return Err("Seek beyond end of file.");
-            }
-        }
-
-        Ok(())
-    }
-
-    // Is the cursor at the end of the current sector?
-    fn is_end_of_sector(&self) -> bool
-    {
-        // Check if we have reached the end of the current sector.
-        self.current_byte >= SECTOR_SIZE
-    }
-
-    // Read the next byte from the file stream, returning None if we are at the end of the file.
-    fn next_byte(&mut self) -> FatResult<Option<u8>>
-    {
-        // Have we hit the end the file?
-        if self.is_eof()
-        {
-            return Ok(None);
-        }
-
-        // Check if we have reached the end of the current sector.
-        if self.is_end_of_sector()
-        {
-            if !self.next_sector()?
-            {
-                return Ok(None);
-            }
-        }
-
-        // Read the next byte from the current sector buffer.
-        let byte = self.buffer[self.current_byte];
-
-        // Advance the file cursor to the next byte.
-        self.absolute_byte += 1;
-        self.current_byte += 1;
-
-        Ok(Some(byte))
-    }
-
-    // Advance the file cursor to the next sector in the file. This will advance to the next cluster
-    // in the file if necessary. If there are no more sectors in the file we return false.
-    //
-    // If the sector can not be read, we return an error.
-    fn next_sector(&mut self) -> FatResult<bool>
-    {
-        // Advance to the next sector in the current cluster.
-        self.current_sector += 1;
-
-        // Check if we have reached the end of the current cluster.
-        if self.current_sector >= self.fat_volume.sectors_per_cluster
-        {
-            // We have reached the end of the current cluster, so we need to move to the next
-            // cluster in the file chain.
-            self.current_sector = 0;
-
-            // Get the next cluster in the file chain.
-            match self.fat_volume.fat.get_next_cluster(self.current_cluster)
-            {
-                Some(next_cluster) =>
-                {
-                    // We have a next cluster, so we can continue reading.
-                    self.current_cluster = next_cluster;
-                },
-
-                None =>
-                {
-                    // We have reached the end of the file chain, so we can not read any more
-                    // sectors.
-                    return Ok(false)
-                }
-            }
-
-            // We have moved to the next cluster so load the now current sector into our buffer.
-            self.load_current_sector()?;
-        }
-
-        Ok(true)
-    }
-
-    // Load the current sector into the sector buffer. It is the responsibility of the caller to
-    // advance the file cursor to the next sector after loading.
-    fn load_current_sector(&mut self) -> FatResult<()>
-    {
-        // Reset the byte offset into the current sector.
-        self.current_byte = 0;
-
-        // Check if we are trying to read outside of the partition. We also make sure we're not
-        // trying to read one of the reserved clusters in the FAT32 filesystem.
-        if    self.current_cluster < 2
-           || self.current_cluster >= self.fat_volume.fat.entries.len()
-        {
-            return Err("Attempt to read outside of the partition.");
-        }
-
-        // Make sure that the sector number is valid.
-        if self.current_sector >= self.fat_volume.sectors_per_cluster
-        {
-            return Err("Attempt to read outside of the current cluster.");
-        }
-
-        // Load the current sector from the FAT32 volume.
-        self.fat_volume.load_sector(self.current_cluster, self.current_sector, self.buffer)?;
+        self.absolute_byte = offset;
 
         Ok(())
     }
@@ -846,14 +964,261 @@ impl DirectoryEntry
 
     pub fn is_end_of_directory(&self) -> bool
     {
-        // Check if the entry is an end-of-directory marker.
-        self.name == [0; 11] && self.file_size == 0
+        // The first free entry at the end of the directory starts with a zero byte.
+        self.name[0] == 0
     }
 
     pub fn is_deleted(&self) -> bool
     {
         // Check if the entry is marked as deleted.
-        self.name[0] == 0xE5
+        self.name[0] == DELETED_ENTRY_MARKER
+    }
+
+    pub fn is_long_name(&self) -> bool
+    {
+        // Long name entries use a combination of attributes that no real file can have.
+        (self.attributes & ATTRIBUTE_LONG_NAME_MASK) == ATTRIBUTE_LONG_NAME
+    }
+
+    pub fn is_volume_label(&self) -> bool
+    {
+        // The volume label is stored as an entry in the root directory, it isn't a real file.
+        (self.attributes & ATTRIBUTE_VOLUME_ID) != 0
+    }
+
+    // Get the raw bytes of the entry. This is how we get at the contents of long name entries which
+    // share the same 32 byte slots as regular entries but have a completely different layout.
+    fn as_bytes(&self) -> &[u8; DIRECTORY_ENTRY_SIZE]
+    {
+        // The structure is packed so it has no alignment requirements or padding.
+        unsafe { &*(self as *const Self as *const [u8; DIRECTORY_ENTRY_SIZE]) }
+    }
+
+    // Compute the checksum of the short name that each of the entry's long name entries carry.
+    fn short_name_checksum(&self) -> u8
+    {
+        short_name_checksum(&self.name)
+    }
+}
+
+
+
+// Constants for decoding the special directory entries.
+const DELETED_ENTRY_MARKER:     u8 = 0xE5;  // First byte of a deleted entry.
+const ESCAPED_DELETED_MARKER:   u8 = 0x05;  // A short name that really starts with 0xE5 is stored
+                                            //   with 0x05 instead.
+const ATTRIBUTE_VOLUME_ID:      u8 = 0x08;  // The entry holds the volume label.
+const ATTRIBUTE_LONG_NAME:      u8 = 0x0F;  // The attributes that mark a long name entry.
+const ATTRIBUTE_LONG_NAME_MASK: u8 = 0x3F;  // Mask of the attributes checked for a long name entry.
+const LAST_LONG_NAME_ENTRY:     u8 = 0x40;  // Flag marking the long name entry holding the end of
+                                            //   the name, it's always stored first.
+
+const LONG_NAME_CHARACTERS:  usize = 13;   // Number of UTF-16 characters in each long name entry.
+const MAX_LONG_NAME_ENTRIES: usize = 20;   // A long name is at most 255 characters, so it can
+                                           //   never need more than 20 entries.
+
+// The byte offsets of the 13 UTF-16 characters within a long name entry.
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_CHARACTERS] = [ 1, 3, 5, 7, 9,
+                                                           14, 16, 18, 20, 22, 24,
+                                                           28, 30 ];
+
+const LONG_NAME_CHECKSUM_OFF: usize = 13;  // Offset of the short name checksum in a long name
+                                           //   entry.
+
+
+
+// Collects the pieces of a VFAT long file name as we iterate through a directory. Long names are
+// stored in a run of special entries just before the short entry they belong to, last piece first,
+// each one holding 13 characters of the name along with a checksum of the short name. If anything
+// about the run doesn't add up we just fall back to the short name.
+struct LongNameBuilder
+{
+    characters: [u16; MAX_LONG_NAME_ENTRIES * LONG_NAME_CHARACTERS],  // The name collected so far.
+    entries: usize,                                                   // Number of entries in the
+                                                                      //   name, 0 if there's none.
+    remaining: usize,                                                 // Sequence number of the next
+                                                                      //   entry we expect.
+    checksum: u8                                                      // Checksum shared by all of
+                                                                      //   the name's entries.
+}
+
+
+
+impl LongNameBuilder
+{
+    fn new() -> Self
+    {
+        LongNameBuilder
+            {
+                characters: [0; MAX_LONG_NAME_ENTRIES * LONG_NAME_CHARACTERS],
+                entries: 0,
+                remaining: 0,
+                checksum: 0
+            }
+    }
+
+    // Forget about any partially collected name.
+    fn reset(&mut self)
+    {
+        self.entries = 0;
+        self.remaining = 0;
+    }
+
+    // Add a long name entry to the name being collected.
+    fn add_entry(&mut self, entry: &DirectoryEntry)
+    {
+        let bytes = entry.as_bytes();
+        let order = bytes[0];
+        let sequence = (order & !LAST_LONG_NAME_ENTRY) as usize;
+        let checksum = bytes[LONG_NAME_CHECKSUM_OFF];
+
+        if (order & LAST_LONG_NAME_ENTRY) != 0
+        {
+            // This is the start of a new name.
+            if    sequence == 0
+               || sequence > MAX_LONG_NAME_ENTRIES
+            {
+                self.reset();
+                return;
+            }
+
+            self.entries = sequence;
+            self.remaining = sequence;
+            self.checksum = checksum;
+        }
+        else if    self.entries == 0
+                || sequence != self.remaining
+                || checksum != self.checksum
+        {
+            // This entry doesn't follow on from the previous one, so the name is broken.
+            self.reset();
+            return;
+        }
+
+        let start = (sequence - 1) * LONG_NAME_CHARACTERS;
+
+        for (index, &offset) in LONG_NAME_OFFSETS.iter().enumerate()
+        {
+            self.characters[start + index] = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        }
+
+        self.remaining -= 1;
+    }
+
+    // Get the completed long name for a short entry, if we have a valid one for it.
+    fn name_for(&self, entry: &DirectoryEntry) -> Option<&[u16]>
+    {
+        if    self.entries == 0
+           || self.remaining != 0
+           || entry.short_name_checksum() != self.checksum
+        {
+            return None;
+        }
+
+        // The name is terminated by a 0 if it doesn't completely fill its last entry.
+        let characters = &self.characters[..self.entries * LONG_NAME_CHARACTERS];
+        let length = characters.iter()
+                               .position(|&character| character == 0)
+                               .unwrap_or(characters.len());
+
+        Some(&characters[..length])
+    }
+}
+
+
+
+// The name of a directory entry as found while iterating a directory. Every entry has a short 8.3
+// name and it may also have a long name.
+pub struct EntryName<'a>
+{
+    pub long_name: Option<&'a [u16]>,  // The long name of the entry as UTF-16, if it has one.
+    pub short_name: &'a [u8; 11]       // The raw, space padded, 8.3 name of the entry.
+}
+
+
+
+impl<'a> EntryName<'a>
+{
+    // Does the entry have the given name? FAT names are not case sensitive and a file can be found
+    // by either its long or short name.
+    pub fn matches(&self, name: &str) -> bool
+    {
+        if let Some(long_name) = self.long_name
+            && Self::long_name_matches(long_name, name)
+        {
+            return true;
+        }
+
+        self.short_name_matches(name)
+    }
+
+    // Compare a UTF-16 long name against the name, ignoring the case of ASCII letters.
+    fn long_name_matches(long_name: &[u16], name: &str) -> bool
+    {
+        let fold_case = |character: u16|
+            {
+                if (b'a' as u16..=b'z' as u16).contains(&character)
+                {
+                    character - 32
+                }
+                else
+                {
+                    character
+                }
+            };
+
+        let mut name_characters = name.encode_utf16();
+
+        for &character in long_name
+        {
+            match name_characters.next()
+            {
+                Some(other) if fold_case(other) == fold_case(character) => continue,
+                _                                                       => return false
+            }
+        }
+
+        name_characters.next().is_none()
+    }
+
+    // Compare the short name against the name. The short name is stored as an 8 character base and
+    // 3 character extension padded with spaces, so we need to rebuild the dotted form first.
+    fn short_name_matches(&self, name: &str) -> bool
+    {
+        let mut buffer = [0u8; 12];
+        let mut length = 0;
+
+        let base_length = self.short_name[..8].iter()
+                                              .rposition(|&byte| byte != b' ')
+                                              .map_or(0, |index| index + 1);
+        let extension_length = self.short_name[8..].iter()
+                                                   .rposition(|&byte| byte != b' ')
+                                                   .map_or(0, |index| index + 1);
+
+        for &byte in &self.short_name[..base_length]
+        {
+            buffer[length] = byte;
+            length += 1;
+        }
+
+        if buffer[0] == ESCAPED_DELETED_MARKER
+        {
+            buffer[0] = DELETED_ENTRY_MARKER;
+        }
+
+        if extension_length > 0
+        {
+            buffer[length] = b'.';
+            length += 1;
+
+            for &byte in &self.short_name[8..8 + extension_length]
+            {
+                buffer[length] = byte;
+                length += 1;
+            }
+        }
+
+        buffer[..length].eq_ignore_ascii_case(name.as_bytes())
     }
 }
 
@@ -910,16 +1275,24 @@ impl<'a> DirectoryIterator<'a>
     }
 
     // Given a function, iterate through the directory entries in the directory file. The callback
-    // function is called once per directory entry. If the callback returns false, the iteration is
-    // stopped. Otherwise the iteration continues until the end of the directory is hit.
+    // function is called once per file or directory with its entry and its name. If the callback
+    // returns false, the iteration is stopped. Otherwise the iteration continues until the end of
+    // the directory is hit.
+    //
+    // Deleted entries, the volume label and the long name entries themselves are not passed to the
+    // callback.
     pub fn iterate<Func>(&mut self, mut callback: Func) -> FatResult<()>
         where
-            Func: FnMut(&DirectoryEntry) -> bool
+            Func: FnMut(&DirectoryEntry, &EntryName) -> bool
     {
         // Make sure we're starting at the beginning of the directory entry list.
         self.file_stream.reset()?;
 
-        loop
+        let mut long_name = LongNameBuilder::new();
+
+        // A directory that completely fills its clusters has no end marker, so we also stop when we
+        // run out of data.
+        while !self.file_stream.is_eof()
         {
             // Try to load the next directory entry from the file stream.
             let entry = DirectoryEntry::new(&mut self.file_stream)?;
@@ -934,11 +1307,32 @@ impl<'a> DirectoryIterator<'a>
             // Skip deleted entries.
             if entry.is_deleted()
             {
+                long_name.reset();
+                continue;
+            }
+
+            // Collect the pieces of long names. Long name entries also have the volume label
+            // attribute set, so check for them first.
+            if entry.is_long_name()
+            {
+                long_name.add_entry(&entry);
                 continue;
             }
 
-            // Call the callback with the current directory entry.
-            if !callback(&entry)
+            // The volume label isn't a real file, so skip it as well.
+            if entry.is_volume_label()
+            {
+                long_name.reset();
+                continue;
+            }
+
+            // Call the callback with the current directory entry and its name.
+            let name = EntryName { long_name: long_name.name_for(&entry), short_name: &entry.name };
+            let keep_going = callback(&entry, &name);
+
+            long_name.reset();
+
+            if !keep_going
             {
                 // The callback returned false, so we stop iterating.
                 break;
@@ -947,4 +1341,25 @@ impl<'a> DirectoryIterator<'a>
 
         Ok(())
     }
+
+    // Find the entry with the given name in the directory, matching either its long or short name.
+    pub fn find(&mut self, name: &str) -> FatResult<Option<DirectoryEntry>>
+    {
+        let mut found = None;
+
+        self.iterate(|entry, entry_name|
+            {
+                if entry_name.matches(name)
+                {
+                    found = Some(*entry);
+                    false
+                }
+                else
+                {
+                    true
+                }
+            })?;
+
+        Ok(found)
+    }
 }
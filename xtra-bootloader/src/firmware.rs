@@ -0,0 +1,488 @@
+// The machine mode side of the firmware. Once the kernel is loaded the bootloader stays resident
+// underneath it, the kernel runs in supervisor mode and everything it can't do itself comes back
+// here as a trap.
+//
+// Before a hart enters the kernel we:
+//
+//  - Protect the firmware's memory with PMP, so that the kernel can't overwrite us. Entry 0 covers
+//    the firmware region with no permissions, entry 1 gives the kernel the rest of the address
+//    space.
+//  - Delegate the supervisor interrupts and all of the exceptions the kernel can handle itself to
+//    supervisor mode.
+//  - Let the kernel read the cycle, time and instruction counters.
+//  - Point the trap vector at our handler and give the hart its own machine mode stack to handle
+//    traps on.
+//
+// What's left of the firmware region after the bootloader's image is divided up into hart areas,
+// one per hart. A hart's area holds the firmware's state for it at the bottom and the stack it runs
+// on in machine mode above that. So the number of harts we support isn't fixed, it's however many
+// areas fit.
+//
+// The traps that reach us are the kernel's SBI calls, made with ecall, the machine timer interrupt
+// the kernel's timer is built on, and the machine software interrupts the harts use to pass
+// requests to each other. Anything else is a bug, so we report it and power off.
+
+use core::arch::{ asm, global_asm, naked_asm };
+
+use crate::{ csr::{ clear_mip_bits,
+                    read_mcause,
+                    read_mepc,
+                    read_mhartid,
+                    read_mstatus,
+                    read_mtval,
+                    write_mcounteren,
+                    write_medeleg,
+                    write_mepc,
+                    write_mideleg,
+                    write_mie,
+                    write_mscratch,
+                    write_mstatus,
+                    write_mtvec,
+                    write_pmpaddr0,
+                    write_pmpaddr1,
+                    write_pmpcfg0,
+                    write_satp,
+                    CAUSE_BREAKPOINT,
+                    CAUSE_ILLEGAL_INSTRUCTION,
+                    CAUSE_INSTRUCTION_ACCESS_FAULT,
+                    CAUSE_INSTRUCTION_MISALIGNED,
+                    CAUSE_INSTRUCTION_PAGE_FAULT,
+                    CAUSE_LOAD_ACCESS_FAULT,
+                    CAUSE_LOAD_MISALIGNED,
+                    CAUSE_LOAD_PAGE_FAULT,
+                    CAUSE_MACHINE_SOFTWARE_INTERRUPT,
+                    CAUSE_MACHINE_TIMER_INTERRUPT,
+                    CAUSE_STORE_ACCESS_FAULT,
+                    CAUSE_STORE_MISALIGNED,
+                    CAUSE_STORE_PAGE_FAULT,
+                    CAUSE_SUPERVISOR_ECALL,
+                    CAUSE_USER_ECALL,
+                    COUNTER_CYCLE,
+                    COUNTER_INSTRET,
+                    COUNTER_TIME,
+                    INTERRUPT_MSI,
+                    INTERRUPT_SEI,
+                    INTERRUPT_SSI,
+                    INTERRUPT_STI,
+                    MCAUSE_INTERRUPT,
+                    MSTATUS_MPIE,
+                    MSTATUS_MPP_MASK,
+                    MSTATUS_MPP_SUPERVISOR,
+                    MSTATUS_MPRV,
+                    MSTATUS_SIE,
+                    PMP_NAPOT,
+                    PMP_R,
+                    PMP_W,
+                    PMP_X },
+             device_tree::DeviceTree,
+             power::power_off,
+             sbi::{ handle_requests,
+                    handle_sbi_call,
+                    handle_timer_interrupt,
+                    set_hart_status,
+                    take_start_request,
+                    HART_STATE_STARTED,
+                    HART_STATE_STOPPED },
+             uart::{ Uart, UART_0_BASE } };
+
+
+
+// The size of each hart's area, _start computes the hart's stack with a shift so it has to be a
+// power of two. Trap handling is short and doesn't need much room.
+pub const HART_AREA_SIZE: usize = 0x4000;
+pub const HART_AREA_SHIFT: usize = HART_AREA_SIZE.trailing_zeros() as usize;
+
+const _: () =
+    {
+        assert!(HART_AREA_SIZE.is_power_of_two(), "The hart area size must be a power of two.");
+    };
+
+// The number of registers saved in the trap frame, x0 to x31. We don't save x0, but keeping its
+// slot means the register number is the index into the frame.
+const TRAP_FRAME_REGISTERS: usize = 32;
+
+// The registers the SBI calls use, by their number.
+const REGISTER_A0: usize = 10;
+const REGISTER_A1: usize = 11;
+const REGISTER_A6: usize = 16;
+const REGISTER_A7: usize = 17;
+
+// The exceptions the kernel handles itself. The only one left with us is the ecall from supervisor
+// mode, which is how the kernel makes its SBI calls.
+const DELEGATED_EXCEPTIONS: usize = (1 << CAUSE_INSTRUCTION_MISALIGNED)
+                                  | (1 << CAUSE_INSTRUCTION_ACCESS_FAULT)
+                                  | (1 << CAUSE_ILLEGAL_INSTRUCTION)
+                                  | (1 << CAUSE_BREAKPOINT)
+                                  | (1 << CAUSE_LOAD_MISALIGNED)
+                                  | (1 << CAUSE_LOAD_ACCESS_FAULT)
+                                  | (1 << CAUSE_STORE_MISALIGNED)
+                                  | (1 << CAUSE_STORE_ACCESS_FAULT)
+                                  | (1 << CAUSE_USER_ECALL)
+                                  | (1 << CAUSE_INSTRUCTION_PAGE_FAULT)
+                                  | (1 << CAUSE_LOAD_PAGE_FAULT)
+                                  | (1 << CAUSE_STORE_PAGE_FAULT);
+
+// All of the supervisor interrupts are the kernel's to handle.
+const DELEGATED_INTERRUPTS: usize = INTERRUPT_SSI | INTERRUPT_STI | INTERRUPT_SEI;
+
+
+
+// The registers of the interrupted code, saved on the trap stack by the trap vector.
+#[repr(C)]
+pub struct TrapFrame
+{
+    registers: [usize; TRAP_FRAME_REGISTERS]
+}
+
+
+impl TrapFrame
+{
+    // Get one of the arguments of an SBI call, a0 to a5.
+    pub fn argument(&self, index: usize) -> usize
+    {
+        self.registers[REGISTER_A0 + index]
+    }
+
+    // Get the extension ID of an SBI call.
+    pub fn extension_id(&self) -> usize
+    {
+        self.registers[REGISTER_A7]
+    }
+
+    // Get the function ID of an SBI call.
+    pub fn function_id(&self) -> usize
+    {
+        self.registers[REGISTER_A6]
+    }
+
+    // Set the values returned by an SBI call.
+    pub fn set_result(&mut self, error: isize, value: usize)
+    {
+        self.registers[REGISTER_A0] = error as usize;
+        self.registers[REGISTER_A1] = value;
+    }
+}
+
+
+
+// The trap vector. While the kernel runs mscratch holds the top of the hart's trap stack, we swap
+// it with the kernel's stack pointer, save the kernel's registers on the trap stack and call the
+// handler with them. Then we put everything back, including mscratch, and return to the kernel.
+//
+// The vector needs to be 4 byte aligned as the low bits of mtvec select the trap mode.
+global_asm!
+(
+    ".pushsection .text.firmware_trap_vector, \"ax\"",
+    ".global firmware_trap_vector",
+    ".align 4",
+
+    "firmware_trap_vector:",
+
+    "csrrw sp, mscratch, sp",           // sp = trap stack, mscratch = kernel's sp.
+    "addi sp, sp, -{frame_size}",       // Make room for the trap frame.
+
+    ".irp reg, 1, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, \
+     25, 26, 27, 28, 29, 30, 31",
+    "sd x\\reg, \\reg * 8(sp)",         // Save every register but sp, which is in mscratch.
+    ".endr",
+
+    "csrr t0, mscratch",                // Save the kernel's sp as well.
+    "sd t0, 2 * 8(sp)",
+
+    "mv a0, sp",                        // firmware_trap_handler(frame)
+    "call firmware_trap_handler",
+
+    "addi t0, sp, {frame_size}",        // Reset mscratch to the top of the trap stack for the next
+    "csrw mscratch, t0",                //  trap.
+
+    ".irp reg, 1, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, \
+     25, 26, 27, 28, 29, 30, 31",
+    "ld x\\reg, \\reg * 8(sp)",         // Restore the registers, the handler may have changed
+    ".endr",                            //  a0 and a1.
+
+    "ld sp, 2 * 8(sp)",                 // Finally restore the kernel's sp and return to it.
+    "mret",
+
+    ".popsection",
+
+    frame_size = const TRAP_FRAME_REGISTERS * 8
+);
+
+
+unsafe extern "C"
+{
+    fn firmware_trap_vector();
+
+    static _firmware_start: u8;
+    static _firmware_end: u8;
+
+    static _hart_areas_start: u8;
+    static _hart_areas_end: u8;
+}
+
+
+
+// Is the address within the firmware's memory?
+pub fn is_firmware_address(address: usize) -> bool
+{
+    let (start, end) = firmware_region();
+
+    address >= start && address < end
+}
+
+
+// Get the range of memory reserved for the firmware.
+pub fn firmware_region() -> (usize, usize)
+{
+    unsafe
+    {
+        (&_firmware_start as *const u8 as usize, &_firmware_end as *const u8 as usize)
+    }
+}
+
+
+// The number of harts the firmware has room for, the hart IDs we support are 0 up to this.
+pub fn max_harts() -> usize
+{
+    let (start, end) = unsafe
+        {
+            (&_hart_areas_start as *const u8 as usize, &_hart_areas_end as *const u8 as usize)
+        };
+
+    (end - start) / HART_AREA_SIZE
+}
+
+
+// Get the address of a hart's area, if the firmware has room for the hart.
+pub fn hart_area(hart_id: usize) -> Option<usize>
+{
+    if hart_id >= max_harts()
+    {
+        return None;
+    }
+
+    Some(unsafe { &_hart_areas_start as *const u8 as usize } + hart_id * HART_AREA_SIZE)
+}
+
+
+// Get the top of a hart's trap stack, the end of its hart area.
+fn trap_stack_top(hart_id: usize) -> usize
+{
+    hart_area(hart_id).expect("Hart has no firmware area.") + HART_AREA_SIZE
+}
+
+
+// Set up the hart's machine mode state for running the kernel in supervisor mode.
+fn setup_hart(hart_id: usize)
+{
+    // A NAPOT PMP region is encoded as its base address with the bits below its size set, less one
+    // bit, all shifted down by two.
+    let (firmware_start, firmware_end) = firmware_region();
+    let firmware_size = firmware_end - firmware_start;
+
+    write_pmpaddr0((firmware_start | ((firmware_size >> 1) - 1)) >> 2);
+    write_pmpaddr1(usize::MAX);
+    write_pmpcfg0(PMP_NAPOT | ((PMP_NAPOT | PMP_R | PMP_W | PMP_X) << 8));
+
+    write_medeleg(DELEGATED_EXCEPTIONS);
+    write_mideleg(DELEGATED_INTERRUPTS);
+    write_mcounteren(COUNTER_CYCLE | COUNTER_TIME | COUNTER_INSTRET);
+
+    write_mtvec(firmware_trap_vector as *const () as usize);
+    write_mscratch(trap_stack_top(hart_id));
+
+    // We only take the software interrupts for now, the timer interrupt is enabled when the kernel
+    // sets a timer.
+    write_mie(INTERRUPT_MSI);
+
+    // The kernel starts out with translation turned off.
+    write_satp(0);
+}
+
+
+// Enter the kernel in supervisor mode at the given address. The hart ID is passed in a0 along with
+// the two arguments in a1 and a2.
+pub fn start_supervisor(hart_id: usize, entry: usize, argument_1: usize, argument_2: usize) -> !
+{
+    setup_hart(hart_id);
+    set_hart_status(hart_id, HART_STATE_STARTED);
+
+    // Return into supervisor mode with its interrupts disabled.
+    let mstatus = read_mstatus() & !(MSTATUS_MPP_MASK | MSTATUS_MPIE | MSTATUS_MPRV | MSTATUS_SIE);
+
+    write_mstatus(mstatus | MSTATUS_MPP_SUPERVISOR);
+    write_mepc(entry);
+
+    unsafe
+    {
+        enter_supervisor_mode(hart_id, argument_1, argument_2);
+    }
+}
+
+
+// Return from machine mode into the address in mepc, with the arguments still in a0 to a2 from the
+// call. The kernel's code may have just been written to memory, so make sure we don't run stale
+// instructions.
+#[unsafe(naked)]
+unsafe extern "C" fn enter_supervisor_mode(hart_id: usize,
+                                           argument_1: usize,
+                                           argument_2: usize) -> !
+{
+    naked_asm!
+    (
+        "fence.i",
+        "mret"
+    );
+}
+
+
+// Park a stopped hart in the firmware until it's asked to start again. This is also where the
+// secondary harts wait from power on until the kernel starts them.
+pub fn park_hart(hart_id: usize) -> !
+{
+    // Any timer or interrupts the kernel had pending on this hart are forgotten. Only the software
+    // interrupt is left enabled, so that it can wake us up.
+    write_mie(INTERRUPT_MSI);
+    clear_mip_bits(INTERRUPT_SSI | INTERRUPT_STI);
+
+    set_hart_status(hart_id, HART_STATE_STOPPED);
+
+    loop
+    {
+        if let Some((start_address, opaque)) = take_start_request(hart_id)
+        {
+            start_supervisor(hart_id, start_address, opaque, 0);
+        }
+
+        // Machine interrupts are disabled in the firmware, but a pending software interrupt still
+        // wakes us up.
+        unsafe
+        {
+            asm!("wfi", options(nomem, nostack, preserves_flags));
+        }
+    }
+}
+
+
+// Go through the harts listed under /cpus in the device tree and let the user know about them.
+// Harts that the firmware doesn't have room for never leave _start, so they can't be used by the
+// kernel.
+pub fn report_harts(uart: &Uart, device_tree: &DeviceTree)
+{
+    let max_harts = max_harts();
+
+    uart.put_str("Firmware has room for ");
+    uart.put_int(max_harts);
+    uart.put_str(" harts.\n");
+
+    device_tree.iterate_blocks(|offset, name|
+        {
+            if    name != "cpu"
+               && !name.starts_with("cpu@")
+            {
+                return true;
+            }
+
+            let mut hart_id: Option<usize> = None;
+            let mut is_cpu = false;
+            let mut is_enabled = true;
+
+            device_tree.iterate_properties(offset, |prop_name, prop_value|
+                {
+                    match prop_name
+                    {
+                        "device_type" => is_cpu = prop_value.starts_with(b"cpu\0"),
+                        "status"      => is_enabled = prop_value.starts_with(b"okay"),
+
+                        "reg" if prop_value.len() >= 4 =>
+                            {
+                                let mut bytes = [0u8; 4];
+
+                                bytes.copy_from_slice(&prop_value[prop_value.len() - 4..]);
+                                hart_id = Some(u32::from_be_bytes(bytes) as usize);
+                            },
+
+                        _ => {}
+                    }
+
+                    true
+                });
+
+            if    is_cpu
+               && let Some(hart_id) = hart_id
+            {
+                uart.put_str("  Hart ");
+                uart.put_int(hart_id);
+
+                if !is_enabled
+                {
+                    uart.put_str(": disabled");
+                }
+                else if hart_id >= max_harts
+                {
+                    uart.put_str(": no room in the firmware, it will not be used");
+                }
+
+                uart.put_str("\n");
+            }
+
+            true
+        });
+}
+
+
+// Report a trap that the firmware can't handle and power off, there's no way for us to recover.
+fn fatal_trap(hart_id: usize, cause: usize) -> !
+{
+    let uart = Uart::new(UART_0_BASE);
+
+    uart.put_str("\n\nUnexpected trap in the firmware on hart ");
+    uart.put_int(hart_id);
+    uart.put_str("!\n");
+    uart.put_str("  mcause: ");
+    uart.put_hex(cause, true);
+    uart.put_str("\n");
+    uart.put_str("  mepc:   ");
+    uart.put_hex(read_mepc(), true);
+    uart.put_str("\n");
+    uart.put_str("  mtval:  ");
+    uart.put_hex(read_mtval(), true);
+    uart.put_str("\n");
+
+    uart.put_str("\nSystem will now power off...\n");
+    power_off();
+}
+
+
+// Handle a trap taken into machine mode, called by the trap vector with the saved registers of the
+// interrupted code.
+#[unsafe(no_mangle)]
+extern "C" fn firmware_trap_handler(frame: &mut TrapFrame)
+{
+    let hart_id = read_mhartid();
+    let cause = read_mcause();
+
+    if cause & MCAUSE_INTERRUPT != 0
+    {
+        match cause & !MCAUSE_INTERRUPT
+        {
+            CAUSE_MACHINE_SOFTWARE_INTERRUPT => handle_requests(hart_id),
+            CAUSE_MACHINE_TIMER_INTERRUPT    => handle_timer_interrupt(),
+            _                                => fatal_trap(hart_id, cause)
+        }
+
+        return;
+    }
+
+    // Only the kernel's SBI calls are expected, a trap from the firmware itself is always a bug.
+    if    cause != CAUSE_SUPERVISOR_ECALL
+       || read_mstatus() & MSTATUS_MPP_MASK != MSTATUS_MPP_SUPERVISOR
+    {
+        fatal_trap(hart_id, cause);
+    }
+
+    // Return to the instruction after the ecall.
+    write_mepc(read_mepc() + 4);
+
+    handle_sbi_call(hart_id, frame);
+}
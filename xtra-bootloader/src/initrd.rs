@@ -0,0 +1,93 @@
+// Module to load the initial RAM disk for the kernel.
+//
+// The initial RAM disk is an optional file named initrd in the same directory as the kernel image,
+// the boot entry in boot.cfg may name a different file.
+// It's a cpio archive that the kernel unpacks into an in memory filesystem, giving it the files it
+// needs before the real root filesystem can be mounted. We don't look inside of it, it's copied as
+// is into memory just past the kernel's heap and its location is handed to the kernel as a boot
+// module. The kernel keeps the memory reserved for as long as it runs, and the ELF loader won't
+// load the kernel over the top of it.
+
+use core::slice;
+
+use xtra_kernel_shared::boot_info::{ XtraBootInfo, XtraBootModuleKind, XtraMemoryRange };
+
+use crate::{ device_tree::{ device_tree_range, DeviceTree },
+             fat32::{ Fat32Volume, FileStream },
+             ram::find_ram_range };
+
+
+
+// The name of the initial RAM disk as expected in the same directory as the kernel image.
+pub const INITRD_FILE_NAME: &str = "initrd";
+
+// Where we load the initial RAM disk. This is the end of the kernel's heap, KERNEL_HEAP_END in the
+// kernel's shared_locations.ld, so it's past both the kernel and the bootloader's boot stack.
+const INITRD_LOAD_ADDRESS: usize = 0x80C0_0000;
+
+// The largest initial RAM disk we will load.
+const MAX_INITRD_SIZE: usize = 64 * 1024 * 1024;
+
+
+
+// Load the initial RAM disk at the given path and record it in the boot information, relative
+// paths are relative to the directory starting at the given cluster. Returns the number of bytes
+// loaded, or None if there is no such file.
+pub fn load_initial_ram_disk(volume: &Fat32Volume,
+                             directory_cluster: usize,
+                             path: &str,
+                             device_tree_ptr: *const u8,
+                             boot_info: &mut XtraBootInfo) -> Result<Option<usize>, &'static str>
+{
+    let file_entry = volume.find_path_from(directory_cluster, path)?
+                           .map(|(_, entry)| entry)
+                           .filter(|entry| entry.is_file());
+
+    let Some(file_entry) = file_entry
+    else
+    {
+        return Ok(None);
+    };
+
+    let size = file_entry.file_size as usize;
+
+    if size == 0
+    {
+        return Err("The initial RAM disk is empty.");
+    }
+
+    if size > MAX_INITRD_SIZE
+    {
+        return Err("The initial RAM disk is too large.");
+    }
+
+    let initrd_range = XtraMemoryRange::new(INITRD_LOAD_ADDRESS as u64, size as u64);
+
+    // The amount of RAM depends on how the machine was configured, so make sure that the whole of
+    // the initial RAM disk fits inside of it.
+    let device_tree = DeviceTree::new(device_tree_ptr);
+    let ram_range = find_ram_range(&device_tree, INITRD_LOAD_ADDRESS);
+
+    if ram_range.is_none_or(|ram_range| initrd_range.end() > ram_range.end())
+    {
+        return Err("The initial RAM disk doesn't fit in RAM.");
+    }
+
+    // QEMU places the device tree near the top of RAM, but make sure that it's out of the way.
+    if initrd_range.overlaps(&device_tree_range(device_tree_ptr))
+    {
+        return Err("The initial RAM disk would overwrite the device tree.");
+    }
+
+    let mut file_stream = FileStream::new_from_directory_entry(volume, &file_entry)?;
+
+    // Read the whole file straight into place.
+    let destination = unsafe { slice::from_raw_parts_mut(INITRD_LOAD_ADDRESS as *mut u8, size) };
+
+    file_stream.read_bytes(destination)?;
+
+    // Recording the module is also what keeps the kernel from being loaded over it.
+    boot_info.add_module(XtraBootModuleKind::InitialRamDisk, INITRD_FILE_NAME, initrd_range)?;
+
+    Ok(Some(size))
+}
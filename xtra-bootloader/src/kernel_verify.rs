@@ -0,0 +1,276 @@
+// Module to make sure that a kernel image is the one we expect before we boot it.
+//
+// A kernel can be checked two ways, both over the whole of the kernel file:
+//
+//  - Against a SHA-256 digest, given by the sha256 setting of its boot entry or found in a file
+//    next to the kernel named after it with .sha256 added, such as kernel.elf.sha256. The file is
+//    in the format written by sha256sum, so anything after the digest is ignored. This catches
+//    images that were corrupted on their way to the disk.
+//
+//  - Against an Ed25519 signature, given by the signature setting of its boot entry or found next
+//    to the kernel with .sig added. The file holds the 64 byte signature as is. Signatures are
+//    checked against the public key given in the XTRA_KERNEL_SIGNING_KEY environment variable
+//    when the bootloader was built. If the bootloader was built with a key every kernel has to be
+//    signed, as otherwise someone tampering with a kernel could just delete its signature.
+//
+// The checks are run over the kernel file as it's loaded, on the same bytes that are copied into
+// memory, so the kernel we check is the kernel we run. Reading it twice would let the image change
+// between the two reads. If a check fails we refuse to boot the kernel.
+
+use crate::{ boot_config::BootEntry,
+             ed25519::{ Ed25519Verifier, ED25519_PUBLIC_KEY_SIZE, ED25519_SIGNATURE_SIZE },
+             fat32::{ Fat32Volume, FileStream },
+             sha2::{ Sha256, SHA256_DIGEST_SIZE } };
+
+
+
+// The public key kernels are signed with, written by build.rs from XTRA_KERNEL_SIGNING_KEY. None
+// if the bootloader was built without one.
+include!(concat!(env!("OUT_DIR"), "/signing_key.rs"));
+
+// The extensions added to the kernel's file name to find its digest and signature files.
+const DIGEST_FILE_EXTENSION: &str = ".sha256";
+const SIGNATURE_FILE_EXTENSION: &str = ".sig";
+
+// The largest digest file we'll read, enough for the digest and the file name sha256sum adds.
+const MAX_DIGEST_FILE_SIZE: usize = 256;
+
+// The longest path we can build for the digest and signature files.
+const MAX_SIDECAR_PATH_LENGTH: usize = 256;
+
+
+
+// What was checked about the kernel image.
+#[derive(Clone, Copy, Default)]
+pub struct KernelVerification
+{
+    pub digest_checked: bool,       // The SHA-256 digest matched.
+    pub signature_checked: bool,    // The Ed25519 signature was valid.
+    pub signature_ignored: bool     // There was a signature, but no key to check it with.
+}
+
+
+
+// Parse a SHA-256 digest written as 64 hex digits.
+pub fn parse_sha256_digest(text: &str) -> Option<[u8; SHA256_DIGEST_SIZE]>
+{
+    if text.len() != SHA256_DIGEST_SIZE * 2
+    {
+        return None;
+    }
+
+    let mut digest = [0u8; SHA256_DIGEST_SIZE];
+
+    for (index, byte) in digest.iter_mut().enumerate()
+    {
+        *byte = u8::from_str_radix(text.get(index * 2..index * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(digest)
+}
+
+
+// Build the path of a file that sits next to the kernel, named after the kernel with an extension
+// added. The path is relative to the kernel's directory.
+fn sidecar_path<'a>(buffer: &'a mut [u8; MAX_SIDECAR_PATH_LENGTH],
+                    kernel_path: &str,
+                    extension: &str) -> Result<&'a str, &'static str>
+{
+    let file_name = kernel_path.rsplit('/').next().unwrap_or(kernel_path);
+    let length = file_name.len() + extension.len();
+
+    if length > buffer.len()
+    {
+        return Err("The kernel's file name is too long.");
+    }
+
+    buffer[..file_name.len()].copy_from_slice(file_name.as_bytes());
+    buffer[file_name.len()..length].copy_from_slice(extension.as_bytes());
+
+    str::from_utf8(&buffer[..length]).map_err(|_| "The kernel's file name is not valid UTF-8.")
+}
+
+
+// Read a small file into the buffer, returning how many bytes were read or None if there is no such
+// file. It's an error for the file to be larger than the buffer.
+fn read_small_file(volume: &Fat32Volume,
+                   directory_cluster: usize,
+                   path: &str,
+                   buffer: &mut [u8]) -> Result<Option<usize>, &'static str>
+{
+    let Some((_, file_entry)) = volume.find_path_from(directory_cluster, path)?
+                                      .filter(|(_, entry)| entry.is_file())
+    else
+    {
+        return Ok(None);
+    };
+
+    let size = file_entry.file_size as usize;
+
+    if size > buffer.len()
+    {
+        return Err("The file is larger than expected.");
+    }
+
+    let mut file_stream = FileStream::new_from_directory_entry(volume, &file_entry)?;
+
+    file_stream.read_bytes(&mut buffer[..size])?;
+
+    Ok(Some(size))
+}
+
+
+// Find the digest the kernel is expected to have, if it has one.
+fn find_digest(volume: &Fat32Volume,
+               kernel_directory: usize,
+               boot_entry: &BootEntry) -> Result<Option<[u8; SHA256_DIGEST_SIZE]>, &'static str>
+{
+    if let Some(digest) = boot_entry.digest()
+    {
+        return Ok(Some(*digest));
+    }
+
+    let mut path_buffer = [0u8; MAX_SIDECAR_PATH_LENGTH];
+    let path = sidecar_path(&mut path_buffer, boot_entry.kernel_path(), DIGEST_FILE_EXTENSION)?;
+
+    let mut buffer = [0u8; MAX_DIGEST_FILE_SIZE];
+
+    let Some(size) = read_small_file(volume, kernel_directory, path, &mut buffer)?
+    else
+    {
+        return Ok(None);
+    };
+
+    // The digest is the first thing in the file, sha256sum follows it with the file's name.
+    let text = str::from_utf8(&buffer[..size]).map_err(|_| "The digest file is not valid UTF-8.")?;
+    let digest = text.split_ascii_whitespace().next().unwrap_or("");
+
+    parse_sha256_digest(digest).map(Some).ok_or("The digest file does not hold a SHA-256 digest.")
+}
+
+
+// Find the kernel's signature, if it has one. A signature file named by the boot entry has to
+// exist, while the one next to the kernel is optional.
+fn find_signature(volume: &Fat32Volume,
+                  kernel_directory: usize,
+                  boot_entry: &BootEntry) -> Result<Option<[u8; ED25519_SIGNATURE_SIZE]>,
+                                                    &'static str>
+{
+    let mut path_buffer = [0u8; MAX_SIDECAR_PATH_LENGTH];
+
+    let (path, required) = match boot_entry.signature_path()
+        {
+            Some(path) => (path, true),
+            None       =>
+                {
+                    let path = sidecar_path(&mut path_buffer,
+                                            boot_entry.kernel_path(),
+                                            SIGNATURE_FILE_EXTENSION)?;
+
+                    (path, false)
+                }
+        };
+
+    let mut signature = [0u8; ED25519_SIGNATURE_SIZE];
+
+    match read_small_file(volume, kernel_directory, path, &mut signature)?
+    {
+        Some(ED25519_SIGNATURE_SIZE) => Ok(Some(signature)),
+        Some(_)                      => Err("The signature file is not 64 bytes long."),
+        None if required             => Err("The signature file was not found."),
+        None                         => Ok(None)
+    }
+}
+
+
+
+// The checks to run over a kernel image, fed the image a piece at a time as it's loaded.
+pub struct KernelCheck
+{
+    digest: Option<[u8; SHA256_DIGEST_SIZE]>,   // The digest the image should have, if any.
+    hash: Sha256,                               // The image's digest so far.
+    verifier: Option<Ed25519Verifier>,          // Checks the image's signature, if it's signed.
+    verification: KernelVerification            // What the checks found out before the image was
+                                                //   read.
+}
+
+
+impl KernelCheck
+{
+    // Find the digest and signature the kernel image has to be checked against. An error means the
+    // kernel must not be booted.
+    pub fn new(volume: &Fat32Volume,
+               kernel_directory: usize,
+               boot_entry: &BootEntry) -> Result<Self, &'static str>
+    {
+        let mut verification = KernelVerification::default();
+
+        let digest = find_digest(volume, kernel_directory, boot_entry)?;
+        let signature = find_signature(volume, kernel_directory, boot_entry)?;
+
+        let verifier = match (KERNEL_SIGNING_KEY, signature)
+            {
+                (Some(key), Some(signature)) => Some(Ed25519Verifier::new(&key, &signature)),
+
+                (Some(_), None) =>
+                    {
+                        return Err("The kernel image is not signed, this bootloader only boots \
+                                    signed kernels.");
+                    },
+
+                (None, signature) =>
+                    {
+                        verification.signature_ignored = signature.is_some();
+                        None
+                    }
+            };
+
+        Ok(KernelCheck { digest, hash: Sha256::new(), verifier, verification })
+    }
+
+    // Run the next piece of the kernel image through the checks. The whole of the file has to be
+    // given, in order.
+    pub fn update(&mut self, data: &[u8])
+    {
+        if self.digest.is_some()
+        {
+            self.hash.update(data);
+        }
+
+        if let Some(verifier) = &mut self.verifier
+        {
+            verifier.update(data);
+        }
+    }
+
+    // Finish the checks once the whole of the kernel image has been given. An error means the
+    // kernel must not be booted.
+    pub fn finish(self) -> Result<KernelVerification, &'static str>
+    {
+        let mut verification = self.verification;
+
+        if let Some(digest) = self.digest
+        {
+            if self.hash.finalize() != digest
+            {
+                return Err("The kernel image does not match its SHA-256 digest, it may be \
+                            corrupted.");
+            }
+
+            verification.digest_checked = true;
+        }
+
+        if let Some(verifier) = self.verifier
+        {
+            if !verifier.verify()
+            {
+                return Err("The kernel image's signature is not valid, it may have been tampered \
+                            with.");
+            }
+
+            verification.signature_checked = true;
+        }
+
+        Ok(verification)
+    }
+}
@@ -28,8 +28,19 @@
 //  - Device Tree Blob (DTB) is passed in as an argument from the host/firmware.
 //  - Block device assumed to be VirtIO-MMIO, FAT32, QEMU default, but will generalize in the
 //    future.
-//  - Kernel image is an ELF file called "kernel.elf" stored in the root of a fat32 partition.
-//  - Bootloader region may be overwritten after handoff to the kernel.
+//  - Kernel images are ELF files listed in boot.cfg at the root of a fat32 partition, or found at
+//    KERNEL_PATH if there is no boot.cfg. Each kernel's mount table, command line and optional
+//    initial RAM disk are stored alongside it unless boot.cfg says otherwise.
+//  - If the last boot never marked itself as successful in boot.state the fallback entry of
+//    boot.cfg is booted instead of the default.
+//  - Kernel images with a SHA-256 digest or Ed25519 signature are checked before they're booted,
+//    and if the bootloader was built with a signing key only signed kernels are booted.
+//  - The kernel is entered in supervisor mode. The bootloader stays resident below it as the
+//    machine mode SBI firmware and protects itself with PMP, only its boot stack and the boot
+//    information in the kernel's heap may be overwritten after handoff to the kernel.
+//  - Only the boot hart, the first one to reach _start, runs the bootloader and enters the kernel.
+//    The other harts wait in the firmware until the kernel starts them with the SBI's HSM
+//    extension.
 
 
 
@@ -57,7 +68,19 @@ mod partition_table;
 mod fat32;
 mod ram;
 mod mount_table;
+mod command_line;
+mod initrd;
+mod boot_config;
+mod boot_menu;
+mod rtc;
+mod sha2;
+mod ed25519;
+mod kernel_verify;
 mod elf;
+mod csr;
+mod clint;
+mod sbi;
+mod firmware;
 
 
 
@@ -66,56 +89,53 @@ mod elf;
 use core::{ arch::{ asm, naked_asm },
             hint::spin_loop,
             panic::PanicInfo,
-            sync::atomic::{ AtomicBool, Ordering } };
+            sync::atomic::AtomicU32 };
 
-use xtra_kernel_shared::mount_table::XtraMountTable;
+use xtra_kernel_shared::{ boot_info::XtraBootInfo,
+                          boot_state::{ XtraBootState, XtraBootStatus } };
 
 // Import the important symbols from our sub-modules.
 use crate::{ block_device::BlockDevice,
+             boot_config::{ load_boot_config, read_boot_state, write_boot_state, BootConfig },
+             boot_menu::choose_boot_entry,
+             clint::Clint,
+             command_line::{ COMMAND_LINE_FILE_NAME,
+                             load_command_line_file,
+                             load_device_tree_command_line },
              device_tree::{ DeviceTree, validate_dtb },
              elf::{ execute_kernel, load_kernel },
-             fat32::{ DirectoryEntry, DirectoryIterator, Fat32Volume, FileStream },
+             fat32::{ Fat32Volume, FileStream },
+             firmware::{ park_hart, report_harts, HART_AREA_SHIFT },
+             initrd::load_initial_ram_disk,
+             kernel_verify::{ KernelCheck, KernelVerification },
              mount_table::load_mount_table,
-             power::{ power_off, wait_for_interrupt },
+             power::power_off,
+             rtc::{ find_rtc, find_timebase_frequency, read_rtc, read_ticks },
+             sbi::{ init_sbi, wait_for_sbi },
              uart::{ Uart, UART_0_BASE },
              virtio::SECTOR_SIZE};
 
 
 
-const KERNEL_FILE_NAME: &[u8; 11] = b"KERNEL  ELF"; // The name of the kernel file as will be
-                                                    // found in the root directory of the FAT32
-                                                    // partition.
+const KERNEL_PATH: &str = "/kernel.elf";  // The path of the kernel image on the FAT32 partition
+                                          // when there is no boot.cfg to list the kernels.
 
 // Hardcode the address we will load the kernel image to in memory. In the future we may want to
 // make this dynamic.
-const KERNEL_LOAD_ADDRESS: usize = 0x8000_0000;   // We are using 5MB after the position where the
-                                                  // bootloader was loaded.
+const KERNEL_LOAD_ADDRESS: usize = 0x8020_0000;   // Right after the firmware region, KERNEL_BASE
+                                                  // in shared_locations.ld.
 
 
-// The maximum number of harts (hardware threads) that we will support in the bootloader. This is
-// because we need to allocate the stack for each hart supported by the bootloader.
-const MAX_SUPPORTED_HARTS: usize = 4;
+/// The boot information handed to the kernel. It's filled in by the boot hart and has to outlive
+/// the bootloader's stack frames, so it's kept in its own section. It's also kept outside of the
+/// firmware region so that the kernel is allowed to read it.
+#[unsafe(link_section = ".boot_info")]
+static mut BOOT_INFO: XtraBootInfo = XtraBootInfo::new();
 
 
-/// Keep track of whether the kernel has been loaded or not. This is used to prevent multiple harts
-/// from jumping into the non-existent kernel.
-static KERNEL_LOADED: AtomicBool = AtomicBool::new(false);
-
-
-
-/// Has the kernel been loaded yet?
-fn is_kernel_loaded() -> bool
-{
-    KERNEL_LOADED.load(Ordering::Acquire)
-}
-
-
-
-/// The kernel has been loaded, let the other hearts know.
-fn set_kernel_loaded()
-{
-    KERNEL_LOADED.store(true, Ordering::Release);
-}
+/// Set by the first hart to reach _start, which makes it the boot hart. We can't assume that hart 0
+/// is the one to boot with, on some systems it's a management core that can't run the kernel.
+static BOOT_HART_CLAIMED: AtomicU32 = AtomicU32::new(0);
 
 
 
@@ -124,6 +144,9 @@ fn set_kernel_loaded()
 // expect main to never return as it is its job to find and load the actual kernel image and
 // transfer control to it.
 //
+// All of the harts start here. The first one to arrive becomes the boot hart and runs main on the
+// boot stack, the rest run secondary_main on the stack in their hart area.
+//
 // If any errors occur in the bootloader we will power off the system. So even in the case of a
 // panic, we will not return from the main function.
 #[unsafe(naked)]
@@ -136,25 +159,22 @@ pub unsafe extern "C" fn _start()
     // proper main function.
     naked_asm!
     (
-        // Check the given hart ID and if it's greater than the maximum supported harts, we will
+        // Check the given hart ID and if the firmware doesn't have a hart area for it, we will
         // park it.
-        "li t0, {max_supported_harts}",
-        "bgeu a0, t0, 1f",
-
-        // Otherwise we'll proceed to start up this hart and compute it's stack area.
-        "la t0, _stack_bottom", // Load the stack bottom as well. Thus giving us the full stack
-                                //  size.
-        "la t1, _stack_top",    // Load the stack top from the linker script.
-        "sub t1, t1, t0",       // Compute the actual size.
-
-        // Figure out how much stack space we have and then divide it up by the number of supported
-        // harts.
-        "li t2, {max_supported_harts}",
-        "divu t1, t1, t2",
-        "addi t2, a0, 1",
-        "mul t1, t1, t2",
-        "add sp, t0, t1",       // Set the proper stack pointer for this hart based on the hart ID
-                                // and the stack size.
+        "la t0, _hart_areas_start",
+        "la t1, _hart_areas_end",
+        "sub t1, t1, t0",
+        "srli t1, t1, {hart_area_shift}",   // t1 = the number of hart areas.
+        "bgeu a0, t1, 3f",
+
+        // The first hart to claim the boot gets to run the bootloader.
+        "la t2, {boot_hart_claimed}",
+        "li t3, 1",
+        "amoswap.w.aq t3, t3, (t2)",
+        "bnez t3, 2f",
+
+        // The boot hart gets the boot stack from the linker script.
+        "la sp, _stack_top",
 
         // The stack has been setup so we can safely jump to the real main function now.
         ".option push",
@@ -165,14 +185,27 @@ pub unsafe extern "C" fn _start()
                                 //  main.
         ".option pop",
 
-        // Ok, if the hart ID is greater than the maximum supported harts, we will just wait for an
-        // interrupt forever. We know the wait will be forever because we disable interrupts first.
-        "1:",
+        // Every other hart runs on the stack at the top of its hart area.
+        "2:",
+        "addi t2, a0, 1",
+        "slli t2, t2, {hart_area_shift}",
+        "add sp, t0, t2",       // sp = _hart_areas_start + (hart_id + 1) * HART_AREA_SIZE.
+
+        ".option push",
+        ".option norelax",
+        "la t0, secondary_main",
+        "jr t0",
+        ".option pop",
+
+        // Ok, if there's no room for the hart, we will just wait for an interrupt forever. We know
+        // the wait will be forever because we disable interrupts first.
+        "3:",
         "csrci mstatus, 0x8",
         "wfi",
-        "j 1b",
+        "j 3b",
 
-        max_supported_harts = const MAX_SUPPORTED_HARTS
+        hart_area_shift = const HART_AREA_SHIFT,
+        boot_hart_claimed = sym BOOT_HART_CLAIMED
     );
 }
 
@@ -247,6 +280,59 @@ fn validate_device_tree(uart: &uart::Uart, device_tree_ptr: *const u8)
 }
 
 
+// Let the user know what was checked about the kernel image once it has been loaded.
+fn write_kernel_verification(uart: &uart::Uart, verification: &KernelVerification)
+{
+    if verification.digest_checked
+    {
+        uart.put_str("  Kernel image matches its SHA-256 digest.\n");
+    }
+
+    if verification.signature_checked
+    {
+        uart.put_str("  Kernel image signature is valid.\n");
+    }
+
+    if verification.signature_ignored
+    {
+        uart.put_str("  Kernel image is signed, but this bootloader has no signing ");
+        uart.put_str("key to check it with.\n");
+    }
+
+    if    !verification.digest_checked
+       && !verification.signature_checked
+    {
+        uart.put_str("  Kernel image has no digest or signature to check.\n");
+    }
+}
+
+
+// Refuse to boot a kernel image that failed its checks, or couldn't be loaded, and shut down the
+// system.
+fn refuse_kernel(uart: &uart::Uart, kernel_path: &str, error: &str) -> !
+{
+    uart.put_str("\nRefusing to boot kernel image ");
+    uart.put_str(kernel_path);
+    uart.put_str(".\n");
+    uart.put_str("Error: ");
+    uart.put_str(error);
+    uart.put_str("\n");
+    uart.put_str("Shutting down system...\n");
+
+    power_off();
+}
+
+
+// Where the harts other than the boot hart go from _start. They wait for the boot hart to get the
+// SBI firmware ready and then park in it, stopped, until the kernel asks for them to be started.
+#[unsafe(no_mangle)]
+pub extern "C" fn secondary_main(hart_id: usize, device_tree_ptr: *const u8) -> !
+{
+    wait_for_sbi();
+    park_hart(hart_id);
+}
+
+
 // The actual Rust level entry point for the bootloader. This function is called indirectly by the
 // host environment to manage the boot process.
 //
@@ -254,45 +340,19 @@ fn validate_device_tree(uart: &uart::Uart, device_tree_ptr: *const u8)
 // the information we find in the DTB for diagnostics.
 //
 // Then we continue on with the boot process, which will involve finding a bootable block device,
-// with a fat32 partition with a kernel.elf file on it. We will then read the kernel image,
+// with a fat32 partition with a kernel image on it. We will then read the kernel image,
 // validate it, and load it into memory. Finally we will jump to the kernel's entry point, passing
-// the hart ID and DTB pointer as arguments.
+// the hart ID, DTB pointer and the boot information we've gathered as arguments.
 //
 // It is expected that this function wi9ll never return, but also that it will never be returned to
 // by the kernel. It is the job of the kernel to take over control of the system and manage the
 // hardware from that point on.
 //
-// In fact it is expected that this bootloader code will be overwritten later by normal OS
-// operation.
+// The kernel is entered in supervisor mode, from then on this code only runs again as the SBI
+// firmware, when the kernel calls into it or one of its machine mode interrupts comes in.
 #[unsafe(no_mangle)]
 pub extern "C" fn main(hart_id: usize, device_tree_ptr: *const u8) -> !
 {
-    // The mount table that we will pass to the Kernel so that it knows what devices/partitions to
-    // mount and where to mount them in the filesystem tree. It will be loaded from the same
-    // directory as the Kernel image itself.
-    let mut mount_table = XtraMountTable::default();
-
-    // Check to make sure that we are running on the boot hart (hart_id 0).
-    if hart_id != 0
-    {
-        // Wait for the boot hart to load the kernel before we attempt to jump into it.
-        while !is_kernel_loaded()
-        {
-            unsafe
-            {
-                asm!("nop", options(nomem, nostack, preserves_flags));
-            }
-        }
-
-        // The kernel has been loaded so jump to it. This function will never return.
-        execute_kernel(hart_id, device_tree_ptr, &mount_table);
-
-        loop
-        {
-            unsafe { wait_for_interrupt(); }
-        }
-    }
-
     // Initialize the UART for logging, and then log the bootloader start message.
     let uart = Uart::init_new(UART_0_BASE);
 
@@ -308,7 +368,38 @@ pub extern "C" fn main(hart_id: usize, device_tree_ptr: *const u8) -> !
     uart.put_str("\n");
     device_tree.print_tree(&uart);
 
-    // Find the first bootable block device.
+    // Everything we learn about the system that the kernel needs to know gets collected here.
+    let mut boot_info = XtraBootInfo::new();
+
+    // Look for a real time clock so that we can tell the kernel when it was started.
+    let rtc_base = find_rtc(&device_tree);
+
+    // The boot menu counts down in seconds, so find out how fast the time CSR runs.
+    let timebase_frequency = find_timebase_frequency(&device_tree);
+
+    // Once the kernel is running we provide its timer and inter-processor interrupts through the
+    // CLINT.
+    let clint = Clint::find(&device_tree);
+
+    // Let the user know which harts the kernel will be able to start.
+    report_harts(&uart, &device_tree);
+
+    if rtc_base.is_none()
+    {
+        uart.put_str("No real time clock found, the boot time will not be known.\n");
+    }
+
+    // Pick up any command line given to us through the device tree, a cmdline.txt file next to the
+    // kernel will replace it.
+    if let Err(error) = load_device_tree_command_line(&device_tree, &mut boot_info)
+    {
+        uart.put_str("Ignoring the device tree's kernel command line.\n");
+        uart.put_str("Error: ");
+        uart.put_str(error);
+        uart.put_str("\n");
+    }
+
+    // Find the first bootable block device. It will be the first disk the kernel finds as well.
     let block_device = BlockDevice::find_first_drive(&uart, device_tree);
 
     if block_device.is_none()
@@ -334,7 +425,17 @@ pub extern "C" fn main(hart_id: usize, device_tree_ptr: *const u8) -> !
         power_off();
     }
 
-    let partition = partition.unwrap();
+    let (partition_index, partition, disk_signature) = partition.unwrap();
+
+    // We only ever boot from the first drive, but the kernel may number its disks differently. So
+    // it's told the disk's signature as well, which doesn't depend on the order disks are found in.
+    boot_info.boot_disk = 0;
+    boot_info.boot_partition = partition_index as u32;
+    boot_info.boot_disk_signature = disk_signature;
+
+    uart.put_str("Disk signature:    ");
+    uart.put_hex(disk_signature as usize, true);
+    uart.put_str("\n");
 
     uart.put_str("Partition information:\n");
     uart.put_str("  Is FAT:          ");
@@ -369,67 +470,211 @@ pub extern "C" fn main(hart_id: usize, device_tree_ptr: *const u8) -> !
 
     uart.put_str("FAT-32 volume info read successfully!\n");
 
-    // Now that we have a valid FAT-32 volume, we can create a directory iterator for the root
-    // directory of the volume.
+    // Now that we have a valid FAT-32 volume, we can find out which kernels we can boot.
     let fat32_volume = fat32_volume.unwrap();
-    let directory_iterator = DirectoryIterator::new(&fat32_volume, fat32_volume.root_cluster);
 
-    // Was the directory iterator initialized successfully?
-    if let Err(e) = directory_iterator
-    {
-        uart.put_str("Failed to initialize directory iterator.\n");
-        uart.put_str("Error: ");
-        uart.put_str(e);
-        uart.put_str("\n");
+    let boot_config = match load_boot_config(&fat32_volume)
+        {
+            Ok(Some(boot_config)) =>
+                {
+                    uart.put_str("Loaded the boot configuration, ");
+                    uart.put_int(boot_config.entries().len());
+                    uart.put_str(" entries.\n");
+
+                    boot_config
+                },
+
+            Ok(None) =>
+                {
+                    uart.put_str("No boot configuration found, booting ");
+                    uart.put_str(KERNEL_PATH);
+                    uart.put_str(".\n");
+
+                    BootConfig::implicit(KERNEL_PATH)
+                },
+
+            Err(error) =>
+                {
+                    uart.put_str("Failed to load the boot configuration.\n");
+                    uart.put_str("Error: ");
+
+                    if error.line != 0
+                    {
+                        uart.put_str("boot.cfg:");
+                        uart.put_int(error.line);
+                        uart.put_str(": ");
+                    }
+
+                    uart.put_str(error.message);
+                    uart.put_str("\n");
+
+                    power_off();
+                }
+        };
+
+    // Find out how the last boot went. Not having a boot state only means that we can't fall back
+    // to another entry, so it's not worth stopping the boot over.
+    //
+    // The kernel finds the boot disk by its signature to mark the boot as successful, so without
+    // one every boot would look like it failed.
+    let boot_state = match read_boot_state(&fat32_volume, &block_device)
+        {
+            Ok(_) if disk_signature == 0 =>
+                {
+                    uart.put_str("The boot disk has no disk signature, fallback is disabled.\n");
 
-        power_off();
-    }
+                    None
+                },
 
-    let mut directory_iterator = directory_iterator.unwrap();
+            Ok(boot_state) => boot_state,
 
-    // Iterate over the entries in the root directory, looking for a file called "kernel.elf".
-    uart.put_str("Searching for kernel image in root directory...\n");
+            Err(error) =>
+                {
+                    uart.put_str("Failed to read the boot state, fallback is disabled.\n");
+                    uart.put_str("Error: ");
+                    uart.put_str(error);
+                    uart.put_str("\n");
 
-    let mut kernel_entry = DirectoryEntry::zeroed();
+                    None
+                }
+        };
 
-    let result = directory_iterator.iterate(|entry|
-        {
-            if    entry.is_file()
-               && entry.name == *KERNEL_FILE_NAME
-            {
-                uart.put_str("Found OS kernel, the file is ");
-                uart.put_int(entry.file_size as usize);
-                uart.put_str(" bytes.\n");
+    // If the last boot never marked itself as successful, boot the fallback entry unless the user
+    // picks something else.
+    let mut default_index = boot_config.default_index();
 
-                // We found the kernel image, so we will return it.
-                kernel_entry = entry.clone();
+    if let Some((_, state)) = &boot_state
+    {
+        if let Some(failed_entry) = state.failed_entry()
+        {
+            uart.put_str("The last boot of ");
+            uart.put_str(failed_entry);
+            uart.put_str(" did not complete.\n");
 
-                false
-            }
-            else
+            if let Some(fallback_index) = boot_config.fallback_index()
             {
-                true
+                default_index = fallback_index;
+
+                uart.put_str("Falling back to ");
+                uart.put_str(boot_config.entries()[fallback_index].name());
+                uart.put_str(".\n");
             }
-        });
+        }
+    }
+
+    let entry_index = choose_boot_entry(&uart, &boot_config, default_index, timebase_frequency);
+    let boot_entry = &boot_config.entries()[entry_index];
+
+    uart.put_str("Booting entry ");
+    uart.put_str(boot_entry.name());
+    uart.put_str(", searching for kernel image ");
+    uart.put_str(boot_entry.kernel_path());
+    uart.put_str("...\n");
+
+    let (kernel_directory, kernel_entry) = match fat32_volume.find_path(boot_entry.kernel_path())
+        {
+            Ok(Some((directory, entry))) if entry.is_file() =>
+                {
+                    uart.put_str("Found OS kernel, the file is ");
+                    uart.put_int(entry.file_size as usize);
+                    uart.put_str(" bytes.\n");
+
+                    (directory, entry)
+                },
+
+            Ok(_) =>
+                {
+                    uart.put_str("Kernel image not found.\n");
+                    power_off();
+                },
+
+            Err(e) =>
+                {
+                    uart.put_str("Failed to search for the kernel image.\n");
+                    uart.put_str("Error: ");
+                    uart.put_str(e);
+                    uart.put_str("\n");
+
+                    power_off();
+                }
+        };
+
+    // Find out what the kernel image has to be checked against before going any further. The checks
+    // themselves are run over the image as it's loaded.
+    uart.put_str("Looking for the kernel image's digest and signature...\n");
+
+    let kernel_check = match KernelCheck::new(&fat32_volume, kernel_directory, boot_entry)
+        {
+            Ok(kernel_check) => kernel_check,
+            Err(error)       => refuse_kernel(&uart, boot_entry.kernel_path(), error)
+        };
 
-    if let Err(e) = result
+    if let Err(error) = boot_info.set_boot_entry(boot_entry.name())
     {
-        uart.put_str("Failed to iterate over root directory.\n");
+        uart.put_str("Failed to record the boot entry.\n");
         uart.put_str("Error: ");
-        uart.put_str(e);
+        uart.put_str(error);
         uart.put_str("\n");
 
         power_off();
     }
 
-    // Attempt to load the mount table from the root directory of the FAT-32 volume.
-    uart.put_str("Attempting to load mount table from root directory...\n");
+    // Attempt to load the entry's mount table, by default it's in the kernel's directory.
+    uart.put_str("Attempting to load mount table ");
+    uart.put_str(boot_entry.mount_table_path());
+    uart.put_str("...\n");
+
+    match load_mount_table(&fat32_volume, kernel_directory, boot_entry.mount_table_path())
+    {
+        Ok(loaded_table) =>
+            {
+                boot_info.mount_table = loaded_table;
+            },
+
+        Err(error) =>
+            {
+                uart.put_str("Failed to load mount table.\n");
+                uart.put_str("Error: ");
+
+                // Errors in the table's text are reported with their position in the file.
+                if error.line != 0
+                {
+                    uart.put_str(boot_entry.mount_table_path());
+                    uart.put_str(":");
+                    uart.put_int(error.line);
+                    uart.put_str(":");
+                    uart.put_int(error.column);
+                    uart.put_str(": ");
+                }
+
+                uart.put_str(error.message);
+                uart.put_str("\n");
+
+                power_off();
+            }
+    }
+
+    // The entry may give the kernel command line itself, otherwise look for it in the kernel's
+    // directory.
+    let command_line_result = match boot_entry.command_line()
+        {
+            Some(command_line) => boot_info.set_command_line(command_line),
 
-    let load_result = load_mount_table(&fat32_volume, &mut directory_iterator);
+            None =>
+                {
+                    uart.put_str("Attempting to load the kernel command line from the kernel's ");
+                    uart.put_str("directory...\n");
 
-    if let Err(error) = load_result
+                    load_command_line_file(&fat32_volume,
+                                           kernel_directory,
+                                           COMMAND_LINE_FILE_NAME,
+                                           &mut boot_info).map(|_| ())
+                }
+        };
+
+    if let Err(error) = command_line_result
     {
-        uart.put_str("Failed to load mount table from root directory.\n");
+        uart.put_str("Failed to load the kernel command line.\n");
         uart.put_str("Error: ");
         uart.put_str(error);
         uart.put_str("\n");
@@ -437,7 +682,76 @@ pub extern "C" fn main(hart_id: usize, device_tree_ptr: *const u8) -> !
         power_off();
     }
 
-    mount_table = load_result.unwrap();
+    uart.put_str("Kernel command line: ");
+    uart.put_str(boot_info.command_line_str());
+    uart.put_str("\n");
+
+    // Load the entry's initial RAM disk, if there is one.
+    uart.put_str("Attempting to load the initial RAM disk ");
+    uart.put_str(boot_entry.initrd_path());
+    uart.put_str("...\n");
+
+    match load_initial_ram_disk(&fat32_volume,
+                                kernel_directory,
+                                boot_entry.initrd_path(),
+                                device_tree_ptr,
+                                &mut boot_info)
+    {
+        Ok(Some(size)) =>
+            {
+                uart.put_str("Loaded the initial RAM disk, ");
+                uart.put_int(size);
+                uart.put_str(" bytes.\n");
+            },
+
+        Ok(None) =>
+            {
+                uart.put_str("No initial RAM disk found.\n");
+            },
+
+        Err(error) =>
+            {
+                uart.put_str("Failed to load the initial RAM disk.\n");
+                uart.put_str("Error: ");
+                uart.put_str(error);
+                uart.put_str("\n");
+
+                power_off();
+            }
+    }
+
+    // Record that we're booting this entry. If the kernel never gets as far as marking the boot as
+    // successful we'll know to use the fallback entry next time.
+    if let Some((sector, last_state)) = boot_state
+    {
+        let mut state = XtraBootState::new();
+
+        state.status = XtraBootStatus::Booting;
+        state.failed_attempts = match last_state.status
+            {
+                XtraBootStatus::Booting => last_state.failed_attempts + 1,
+                _                       => 0
+            };
+
+        let result = state.set_entry(boot_entry.name())
+                          .and_then(|_| write_boot_state(&block_device, sector, &state));
+
+        match result
+        {
+            Ok(()) =>
+                {
+                    boot_info.boot_state_sector = sector;
+                },
+
+            Err(error) =>
+                {
+                    uart.put_str("Failed to write the boot state, fallback is disabled.\n");
+                    uart.put_str("Error: ");
+                    uart.put_str(error);
+                    uart.put_str("\n");
+                }
+        }
+    }
 
     // We have a kernel! So attempt to create a file stream for loading the kernel image.
     let kernel_stream = FileStream::new_from_directory_entry(&fat32_volume, &kernel_entry);
@@ -454,39 +768,47 @@ pub extern "C" fn main(hart_id: usize, device_tree_ptr: *const u8) -> !
 
     let mut kernel_stream = kernel_stream.unwrap();
 
-    // We have a file stream for the kernel image. We can now try to validate and execute the
-    // kernel. Once executed the kernel should never return to the bootloader. In fact it is
-    // expected that the bootloader code will be overwritten by the kernel's runtime data structures
-    // and application memory pages.
-    uart.put_str("Executing kernel image...\n");
+    // We have a file stream for the kernel image. We can now load it, checking it as it's read.
+    // Once executed the kernel should never return to the bootloader, it only calls back into the
+    // firmware part of the bootloader for the SBI.
+    uart.put_str("Loading and verifying kernel image...\n");
 
     let result = load_kernel(&uart,
                              KERNEL_LOAD_ADDRESS as *const u8,
-                             &mut kernel_stream);
-
-    set_kernel_loaded();
-
-    execute_kernel(hart_id, device_tree_ptr, &mount_table);
+                             &mut kernel_stream,
+                             kernel_check,
+                             device_tree_ptr,
+                             &boot_info);
 
-    // Ok, if we got here, something went wrong in trying to execute the kernel.
     match result
     {
-        Ok(()) =>
-            {
-                uart.put_str("Kernel executed successfully, but it should never return to the ");
-                uart.put_str("bootloader.\n");
-            },
+        Ok(verification) => write_kernel_verification(&uart, &verification),
+        Err(error)       => refuse_kernel(&uart, boot_entry.kernel_path(), error)
+    }
 
-        Err(e) =>
-            {
-                uart.put_str("Failed to execute kernel image.\n");
-                uart.put_str("Error: ");
-                uart.put_str(e);
-                uart.put_str("\n");
-            }
+    // Record the time as late as we can, right before handing the boot information over to the
+    // kernel.
+    if let Some(rtc_base) = rtc_base
+    {
+        boot_info.boot_time = read_rtc(rtc_base);
+    }
+
+    boot_info.boot_ticks = read_ticks();
+
+    unsafe
+    {
+        BOOT_INFO = boot_info;
     }
 
-    // Finally shut off the machine.  Whatever happened will require user intervention to fix.
-    uart.put_str("Kernel execution failed, shutting down system...\n");
-    power_off()
+    // Get the firmware ready to serve the kernel before we enter it. This also lets the other harts
+    // park themselves, ready for the kernel to start them.
+    uart.put_str("Starting SBI firmware, CLINT at ");
+    uart.put_hex(clint.base(), true);
+    uart.put_str(".\n");
+
+    init_sbi(clint);
+
+    uart.put_str("Executing kernel image...\n");
+
+    execute_kernel(hart_id, device_tree_ptr, &raw const BOOT_INFO)
 }
@@ -1,315 +1,597 @@
-
 // Module to parse the mount table from the boot drive for passing to the Kernel.
+//
+// The mount table is a text file with one entry per line. Blank lines are ignored and comments
+// start with a '#' and run to the end of the line. Each entry has the form:
+//
+//     mount <path> <partition> type:<fat32|ext2> [options:<option>,<option>,...]
+//
+// Where the partition is selected with one of:
+//
+//     disk:<n> pt:<m>          The index of the disk and the partition on that disk.
+//     partuuid:<uuid>          The partition's UUID, uuid: is accepted as well.
+//     label:<label>            The label of the filesystem on the partition.
+//
+// And the options are ro, rw, noexec, exec, sync, async, optional and defaults.
 
 use xtra_kernel_shared::mount_table::{ XtraFilesystemType,
                                        XtraMountTable,
+                                       XtraMountTableEntry,
+                                       XtraPartitionSelector,
+                                       XTRA_MAX_LABEL_LENGTH,
                                        XTRA_MAX_MOUNT_TABLE_ENTRIES,
-                                       XTRA_MAX_MOUNT_POINT_STRING_LENGTH };
+                                       XTRA_MAX_PARTITION_UUID_LENGTH,
+                                       XTRA_MOUNT_OPTION_NO_EXEC,
+                                       XTRA_MOUNT_OPTION_OPTIONAL,
+                                       XTRA_MOUNT_OPTION_READ_ONLY,
+                                       XTRA_MOUNT_OPTION_SYNC };
+
+use crate::fat32::{ Fat32Volume, FileStream };
+
+
+
+/// The name of the mount table as expected in the same directory as the kernel image, unless the
+/// boot entry names another file.
+pub const MOUNT_TABLE_FILE_NAME: &str = "mount.tbl";
+
 
-use crate::fat32::{ DirectoryEntry, DirectoryIterator, Fat32Volume, FileStream };
+/// The longest line we accept in the mount table, not counting the line ending.
+const MAX_LINE_LENGTH: usize = 256;
 
 
+/// The mount options we understand, the flag they control and whether they set or clear it.
+const MOUNT_OPTIONS: [(&str, u8, bool); 8] =
+    [
+        ("ro",       XTRA_MOUNT_OPTION_READ_ONLY, true),
+        ("rw",       XTRA_MOUNT_OPTION_READ_ONLY, false),
+        ("noexec",   XTRA_MOUNT_OPTION_NO_EXEC,   true),
+        ("exec",     XTRA_MOUNT_OPTION_NO_EXEC,   false),
+        ("sync",     XTRA_MOUNT_OPTION_SYNC,      true),
+        ("async",    XTRA_MOUNT_OPTION_SYNC,      false),
+        ("optional", XTRA_MOUNT_OPTION_OPTIONAL,  true),
+        ("defaults", 0,                           false)
+    ];
 
-/// The name of the mount table as expected in the boot drive's root directory.
-const MOUNT_TABLE_FILE_NAME: &[u8; 11] = b"MOUNT   TBL";
 
 
-/// Consume the file stream until we have a non-whitespace character or the end of the stream.
-fn skip_whitespace(file_stream: &mut FileStream) -> Result<(), &'static str>
+/// An error found while loading the mount table. Errors in the text of the table record the line
+/// and column they were found at, both counting from 1. Errors finding or reading the file itself
+/// have a line of 0.
+#[derive(Clone, Copy)]
+pub struct MountTableError
 {
-    while !file_stream.is_eof()
+    pub line: usize,
+    pub column: usize,
+    pub message: &'static str
+}
+
+
+impl MountTableError
+{
+    fn new(line: usize, column: usize, message: &'static str) -> Self
     {
-        let position = file_stream.tell();
-        let next = file_stream.read_u8()?;
+        MountTableError { line, column, message }
+    }
+}
+
 
-        if !next.is_ascii_whitespace()
+// Errors from the filesystem aren't tied to a position in the table.
+impl From<&'static str> for MountTableError
+{
+    fn from(message: &'static str) -> Self
+    {
+        MountTableError::new(0, 0, message)
+    }
+}
+
+
+
+/// A whitespace separated token from a line of the mount table, along with the column it starts at.
+#[derive(Clone, Copy)]
+struct Token<'a>
+{
+    text: &'a str,
+    column: usize
+}
+
+
+/// Split the text of a line into whitespace separated tokens.
+struct Tokenizer<'a>
+{
+    text: &'a str,
+    position: usize
+}
+
+
+impl<'a> Tokenizer<'a>
+{
+    fn new(text: &'a str) -> Self
+    {
+        Tokenizer { text, position: 0 }
+    }
+}
+
+
+impl<'a> Iterator for Tokenizer<'a>
+{
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>>
+    {
+        let bytes = self.text.as_bytes();
+
+        while    self.position < bytes.len()
+              && bytes[self.position].is_ascii_whitespace()
         {
-            file_stream.seek(position)?;
-            break;
+            self.position += 1;
         }
-    }
 
-    Ok(())
+        if self.position >= bytes.len()
+        {
+            return None;
+        }
+
+        let start = self.position;
+
+        while    self.position < bytes.len()
+              && !bytes[self.position].is_ascii_whitespace()
+        {
+            self.position += 1;
+        }
+
+        Some(Token { text: &self.text[start..self.position], column: start + 1 })
+    }
 }
 
 
-/// Expect the provided string to be present at the current position in the mount stream. If we find
-/// text that isn't the string that's considered an error. But if we reach the end of the stream
-/// then we return not found. If the string matches then we return found.
-///
-/// If the function fails to match the expected string it will rewind the stream back to the
-/// position it started at.
-fn expect_string(file_stream: &mut FileStream, expected_string: &str) -> Result<bool, &'static str>
+
+/// Read the next line of the mount table into the buffer, without its line ending. Returns the
+/// length of the line, or None if we are already at the end of the file.
+fn read_line(file_stream: &mut FileStream,
+             buffer: &mut [u8; MAX_LINE_LENGTH],
+             line_number: usize) -> Result<Option<usize>, MountTableError>
 {
-    let mut current_byte: usize = 0;
-    let position = file_stream.tell();
+    if file_stream.is_eof()
+    {
+        return Ok(None);
+    }
+
+    let mut length: usize = 0;
 
-    while    !file_stream.is_eof()
-          && (current_byte < expected_string.len())
+    while !file_stream.is_eof()
     {
-        let next = file_stream.read_u8()?;
+        let next = file_stream.read_u8()
+                              .map_err(|message| MountTableError::new(line_number,
+                                                                      length + 1,
+                                                                      message))?;
+
+        if next == b'\n'
+        {
+            break;
+        }
 
-        if next != expected_string.as_bytes()[current_byte]
+        if length >= MAX_LINE_LENGTH
         {
-            file_stream.seek(position)?;
-            return Ok(false);
+            return Err(MountTableError::new(line_number, length + 1, "Line is too long."));
         }
 
-        current_byte += 1;
+        buffer[length] = next;
+        length += 1;
     }
 
-    if current_byte < expected_string.len()
+    // Accept DOS line endings too, the table may well have been edited on another system.
+    if    length > 0
+       && buffer[length - 1] == b'\r'
     {
-        file_stream.seek(position)?;
-        Ok(false)
+        length -= 1;
     }
-    else
+
+    Ok(Some(length))
+}
+
+
+/// Copy a string into a zero padded field of a mount table entry. The string must leave room for
+/// at least one byte of padding.
+fn copy_padded(field: &mut [u8], text: &str) -> bool
+{
+    if text.len() >= field.len()
     {
-        Ok(true)
+        return false;
     }
+
+    field.fill(0);
+    field[..text.len()].copy_from_slice(text.as_bytes());
+
+    true
 }
 
 
-/// Read characters from the stream until we hit whitespace, the end of the stream,
-/// or the end of the buffer.
-fn read_until_whitespace(file_stream: &mut FileStream,
-                         buffer: &mut [u8]) -> Result<(), &'static str>
+/// Read a base 10 u8 from a setting's value.
+fn parse_number(token: Token, line_number: usize) -> Result<u8, MountTableError>
 {
-    let mut buffer_index: usize = 0;
+    let mut number: u16 = 0;
 
-    while    !file_stream.is_eof()
-          && (buffer_index < buffer.len())
+    for (index, byte) in token.text.bytes().enumerate()
     {
-        let position = file_stream.tell();
-        let next = file_stream.read_u8()?;
-
-        if !next.is_ascii_whitespace()
+        if !byte.is_ascii_digit()
         {
-            buffer[buffer_index] = next;
-            buffer_index += 1;
+            return Err(MountTableError::new(line_number,
+                                            token.column + index,
+                                            "Expected a number."));
         }
-        else
+
+        number = number * 10 + (byte - b'0') as u16;
+
+        if number > (u8::MAX as u16)
         {
-            file_stream.seek(position)?;
-            break;
+            return Err(MountTableError::new(line_number,
+                                            token.column,
+                                            "Number is too large, it can be at most 255."));
         }
     }
 
-    Ok(())
+    Ok(number as u8)
 }
 
 
-/// Read a numeric uint8 from the text stream. The number is expected to be base 10.
-fn read_number(file_stream: &mut FileStream) -> Result<u8, &'static str>
+/// Read a partition UUID into the entry. UUIDs are made of hex digits and dashes and are stored in
+/// lower case so that the Kernel can compare them directly.
+fn parse_partition_uuid(token: Token,
+                        line_number: usize,
+                        entry: &mut XtraMountTableEntry) -> Result<(), MountTableError>
 {
-    let mut buffer: [u8; 3] = [0; 3];
-    let mut number: u16 = 0;
+    if token.text.len() > XTRA_MAX_PARTITION_UUID_LENGTH
+    {
+        return Err(MountTableError::new(line_number, token.column, "Partition UUID is too long."));
+    }
 
-    read_until_whitespace(file_stream, &mut buffer)?;
+    entry.partition_uuid = [0; XTRA_MAX_PARTITION_UUID_LENGTH];
 
-    for byte in buffer.iter()
+    for (index, byte) in token.text.bytes().enumerate()
     {
-        if *byte == 0
+        if    !byte.is_ascii_hexdigit()
+           && byte != b'-'
         {
-            break;
+            return Err(MountTableError::new(line_number,
+                                            token.column + index,
+                                            "Partition UUIDs may only hold hex digits and '-'."));
         }
 
-        if !byte.is_ascii_digit()
+        entry.partition_uuid[index] = byte.to_ascii_lowercase();
+    }
+
+    Ok(())
+}
+
+
+/// Read the comma separated list of mount options and apply them to the entry's option flags.
+fn parse_options(token: Token,
+                 line_number: usize,
+                 entry: &mut XtraMountTableEntry) -> Result<(), MountTableError>
+{
+    let mut column = token.column;
+
+    for option in token.text.split(',')
+    {
+        if option.is_empty()
         {
-            return Err("Expected a number but found non-digit characters.");
+            return Err(MountTableError::new(line_number, column, "Empty mount option."));
         }
 
-        let digit_value = (byte - b'0') as u16;
-        number = number * 10 + digit_value;
+        let known = MOUNT_OPTIONS.iter().find(|(name, _, _)| *name == option);
 
-        if number > (u8::MAX as u16)
+        match known
         {
-            return Err("Number is too large to fit in a u8.");
+            Some((_, flag, true))  => entry.options |= flag,
+            Some((_, flag, false)) => entry.options &= !flag,
+
+            None =>
+                {
+                    return Err(MountTableError::new(line_number,
+                                                    column,
+                                                    "Unknown mount option, expected ro, rw, \
+                                                     noexec, exec, sync, async, optional or \
+                                                     defaults."));
+                }
         }
+
+        column += option.len() + 1;
     }
 
-    Ok(number as u8)
+    Ok(())
 }
 
 
-/// Read the filesystem type from the mount stream. We expect to see either "fat32" or "ext2" here.
-/// If an unknown filesystem type is found we consume the token and return it as unknown.
-fn read_mount_type_enum(file_stream: &mut FileStream) -> Result<XtraFilesystemType, &'static str>
+/// Read the filesystem type from a setting's value. We expect to see either "fat32" or "ext2".
+fn parse_filesystem_type(token: Token,
+                         line_number: usize) -> Result<XtraFilesystemType, MountTableError>
 {
-    let mount_type = XtraFilesystemType::Unknown;
+    match token.text
+    {
+        "fat32" => Ok(XtraFilesystemType::Fat32),
+        "ext2"  => Ok(XtraFilesystemType::Ext2),
 
-    skip_whitespace(file_stream)?;
+        _ =>
+            {
+                Err(MountTableError::new(line_number,
+                                         token.column,
+                                         "Unknown filesystem type, expected fat32 or ext2."))
+            }
+    }
+}
 
-    // Check to see if we're fat32?
-    let fat32_found = expect_string(file_stream, "fat32")?;
 
-    if fat32_found
+/// Make sure that a setting only appears once in an entry.
+fn check_first_use(found: &mut bool,
+                   token: Token,
+                   line_number: usize) -> Result<(), MountTableError>
+{
+    if *found
     {
-        return Ok(XtraFilesystemType::Fat32);
+        return Err(MountTableError::new(line_number,
+                                        token.column,
+                                        "Setting is given more than once."));
     }
 
-    // Ok, look for ext2 next.
-    let ext2_found = expect_string(file_stream, "ext2")?;
+    *found = true;
 
-    if ext2_found
+    Ok(())
+}
+
+
+/// Switch the entry to the given way of selecting its partition, an entry may only use one of them.
+fn select_partition_by(selector: XtraPartitionSelector,
+                       token: Token,
+                       line_number: usize,
+                       entry: &mut XtraMountTableEntry) -> Result<(), MountTableError>
+{
+    if    entry.selector != XtraPartitionSelector::None
+       && entry.selector != selector
     {
-        return Ok(XtraFilesystemType::Ext2);
+        return Err(MountTableError::new(line_number,
+                                        token.column,
+                                        "Only one of disk: and pt:, partuuid: or label: can be \
+                                         used to select the partition."));
     }
 
-    // Ok, we have no idea of what the filesystem type is, so we just try to consume the next token
-    // and record it as unknown.
-    let mut buffer: [u8; 16] = [0; 16];
-    read_until_whitespace(file_stream, &mut buffer)?;
+    entry.selector = selector;
 
-    Ok(mount_type)
+    Ok(())
 }
 
 
-/// Given a Fat-32 file stream for the mount table, attempt to parse it and populate the provided
-/// mount table structure.
-fn parse_file_stream(file_stream: &mut FileStream,
-                      mount_table: &mut XtraMountTable) -> Result<(), &'static str>
+/// Parse a single line of the mount table, with any comment already removed. Returns true if the
+/// line held an entry, or false if it was blank.
+fn parse_line(text: &str,
+              line_number: usize,
+              entry: &mut XtraMountTableEntry) -> Result<bool, MountTableError>
 {
-    let mut entry_index: usize = 0;
+    let error = |column: usize, message: &'static str|
+        {
+            MountTableError::new(line_number, column, message)
+        };
 
-    loop
-    {
-        // Make sure we're still within the bounds of the mount table entries.
-        if entry_index >= XTRA_MAX_MOUNT_TABLE_ENTRIES
+    // Missing parts of the entry are reported just past the end of the line.
+    let end_column = text.len() + 1;
+    let mut tokens = Tokenizer::new(text);
+
+    // All mount table entries start with the "mount" keyword.
+    let keyword = match tokens.next()
         {
-            break;
-        }
+            Some(keyword) => keyword,
+            None          => return Ok(false)
+        };
 
-        // Allow whitespace before the "mount" keyword for each entry.
-        skip_whitespace(file_stream)?;
+    if keyword.text != "mount"
+    {
+        return Err(error(keyword.column, "Expected the 'mount' keyword."));
+    }
 
-        // All mount table entries start with the "mount" keyword so we expect to see that at the
-        // beginning of each entry. If we don't see it then we've reached the end of the mount table
-        // entries.
-        let found = expect_string(file_stream, "mount")?;
+    // The next token should be the mount point and it always starts with a "/" character.
+    let mount_point = tokens.next().ok_or(error(end_column, "Expected a mount point."))?;
 
-        if !found
+    if !mount_point.text.starts_with('/')
+    {
+        return Err(error(mount_point.column, "Expected mount point starting with '/'."));
+    }
+
+    if !copy_padded(&mut entry.mount_point, mount_point.text)
+    {
+        return Err(error(mount_point.column, "Mount point is too long."));
+    }
+
+    // The rest of the entry is made up of key:value settings that can come in any order.
+    let mut found_disk = false;
+    let mut found_partition = false;
+    let mut found_uuid = false;
+    let mut found_label = false;
+    let mut found_type = false;
+    let mut found_options = false;
+
+    for setting in tokens
+    {
+        let Some((key, value)) = setting.text.split_once(':')
+        else
         {
-            break;
-        }
+            return Err(error(setting.column, "Expected a setting of the form key:value."));
+        };
 
-        // Skip any whitespace after the "mount" keyword.
-        skip_whitespace(file_stream)?;
+        let value = Token { text: value, column: setting.column + key.len() + 1 };
 
-        // The next token should be the mount point and it always starts with a "/" character.
-        let mount_point_found = expect_string(file_stream, "/")?;
+        if value.text.is_empty()
+        {
+            return Err(error(value.column, "Expected a value for the setting."));
+        }
 
-        if !mount_point_found
+        match key
         {
-            return Err("Expected mount point starting with '/' in mount table entry.");
+            "disk" =>
+                {
+                    check_first_use(&mut found_disk, setting, line_number)?;
+                    select_partition_by(XtraPartitionSelector::Index, setting, line_number, entry)?;
+
+                    entry.device = parse_number(value, line_number)?;
+                },
+
+            "pt" =>
+                {
+                    check_first_use(&mut found_partition, setting, line_number)?;
+                    select_partition_by(XtraPartitionSelector::Index, setting, line_number, entry)?;
+
+                    entry.partition = parse_number(value, line_number)?;
+                },
+
+            "partuuid" | "uuid" =>
+                {
+                    check_first_use(&mut found_uuid, setting, line_number)?;
+                    select_partition_by(XtraPartitionSelector::PartitionUuid,
+                                        setting,
+                                        line_number,
+                                        entry)?;
+
+                    parse_partition_uuid(value, line_number, entry)?;
+                },
+
+            "label" =>
+                {
+                    check_first_use(&mut found_label, setting, line_number)?;
+                    select_partition_by(XtraPartitionSelector::Label, setting, line_number, entry)?;
+
+                    if value.text.len() > XTRA_MAX_LABEL_LENGTH
+                    {
+                        return Err(error(value.column, "Label is too long."));
+                    }
+
+                    entry.label = [0; XTRA_MAX_LABEL_LENGTH];
+                    entry.label[..value.text.len()].copy_from_slice(value.text.as_bytes());
+                },
+
+            "type" =>
+                {
+                    check_first_use(&mut found_type, setting, line_number)?;
+
+                    entry.filesystem_type = parse_filesystem_type(value, line_number)?;
+                },
+
+            "options" =>
+                {
+                    check_first_use(&mut found_options, setting, line_number)?;
+                    parse_options(value, line_number, entry)?;
+                },
+
+            _ =>
+                {
+                    return Err(error(setting.column,
+                                     "Unknown setting, expected disk:, pt:, partuuid:, label:, \
+                                      type: or options:."));
+                }
         }
+    }
 
-        // Read the mount string from the stream until we hit whitespace.
-        let mut mount_string: [u8; XTRA_MAX_MOUNT_POINT_STRING_LENGTH]
-            = [0; XTRA_MAX_MOUNT_POINT_STRING_LENGTH];
+    // Make sure that the entry is complete.
+    match entry.selector
+    {
+        XtraPartitionSelector::None =>
+            {
+                return Err(error(end_column,
+                                 "Expected disk: and pt:, partuuid: or label: to select the \
+                                  partition."));
+            },
 
-        mount_string[0] = b'/';
-        read_until_whitespace(file_stream, &mut mount_string[1..])?;
+        XtraPartitionSelector::Index if !found_disk =>
+            {
+                return Err(error(end_column, "Expected a disk: setting to go with pt:."));
+            },
 
-        mount_table.entries[entry_index].mount_point = mount_string;
+        XtraPartitionSelector::Index if !found_partition =>
+            {
+                return Err(error(end_column, "Expected a pt: setting to go with disk:."));
+            },
 
-        // Skip any whitespace after the mount string.
-        skip_whitespace(file_stream)?;
+        _ => {}
+    }
 
-        // Now look for the "disk:" keyword which indicates the device ID for this mount entry.
-        let disk_id_found = expect_string(file_stream, "disk:")?;
+    if !found_type
+    {
+        return Err(error(end_column, "Expected a type: setting."));
+    }
 
-        if !disk_id_found
-        {
-            return Err("Expected 'disk:' keyword in mount table entry.");
-        }
+    Ok(true)
+}
 
-        let disk_index = read_number(file_stream)?;
 
-        mount_table.entries[entry_index].device = disk_index;
-        skip_whitespace(file_stream)?;
+/// Given a Fat-32 file stream for the mount table, attempt to parse it and populate the provided
+/// mount table structure.
+fn parse_file_stream(file_stream: &mut FileStream,
+                     mount_table: &mut XtraMountTable) -> Result<(), MountTableError>
+{
+    let mut buffer = [0u8; MAX_LINE_LENGTH];
+    let mut line_number: usize = 0;
 
-        // Now we're looking for the partition ID which starts with the "pt:" keyword.
-        let partition_id_found = expect_string(file_stream, "pt:")?;
+    loop
+    {
+        line_number += 1;
 
-        if !partition_id_found
-        {
-            return Err("Expected 'pt:' keyword in mount table entry.");
-        }
+        let length = match read_line(file_stream, &mut buffer, line_number)?
+            {
+                Some(length) => length,
+                None         => break
+            };
+
+        let text = core::str::from_utf8(&buffer[..length])
+            .map_err(|error| MountTableError::new(line_number,
+                                                  error.valid_up_to() + 1,
+                                                  "Line is not valid UTF-8."))?;
 
-        let partition_index = read_number(file_stream)?;
-        mount_table.entries[entry_index].partition = partition_index;
-        skip_whitespace(file_stream)?;
+        // Strip off any comment before looking at the rest of the line.
+        let text = match text.find('#')
+            {
+                Some(comment_start) => &text[..comment_start],
+                None                => text
+            };
 
-        // Finally we look for the filesystem type which starts with the "type:" keyword.
-        let filesystem_type_found = expect_string(file_stream, "type:")?;
+        let mut entry = XtraMountTableEntry::new();
 
-        if !filesystem_type_found
+        if !parse_line(text, line_number, &mut entry)?
         {
-            return Err("Expected 'type:' keyword in mount table entry.");
+            continue;
         }
 
-        let type_id = read_mount_type_enum(file_stream)?;
-        mount_table.entries[entry_index].filesystem_type = type_id;
+        // Make sure we're still within the bounds of the mount table entries.
+        if mount_table.num_entries >= XTRA_MAX_MOUNT_TABLE_ENTRIES
+        {
+            return Err(MountTableError::new(line_number,
+                                            1,
+                                            "Too many entries in the mount table."));
+        }
 
-        // We've definitely found a new entry.
+        mount_table.entries[mount_table.num_entries] = entry;
         mount_table.num_entries += 1;
-
-        // Advance to the next entry index.
-        entry_index += 1;
     }
 
     Ok(())
 }
 
 
-/// Attempt to find and load the mount table from the specified directory iterator. If we can't find
-/// the mount table we just return an empty mount table to the caller.
+/// Attempt to find and load the mount table at the given path, relative paths are relative to the
+/// directory starting at the given cluster. If we can't find the mount table we just return an
+/// empty mount table to the caller.
 pub fn load_mount_table(volume: &Fat32Volume,
-                        directory_iterator: &mut DirectoryIterator)
-                        -> Result<XtraMountTable, &'static str>
+                        directory_cluster: usize,
+                        path: &str) -> Result<XtraMountTable, MountTableError>
 {
     let mut mount_table = XtraMountTable::default();
-    let mut table_entry: Option<DirectoryEntry> = None;
+    let table_entry = volume.find_path_from(directory_cluster, path)?
+                            .map(|(_, entry)| entry)
+                            .filter(|entry| entry.is_file());
 
-    let result = directory_iterator.iterate(|entry|
-        {
-            // Loop through the directory entries until we find the mount table or we exhaust the
-            // directory entries.
-            if    entry.is_file()
-               && entry.name == *MOUNT_TABLE_FILE_NAME
-            {
-                table_entry = Some(entry.clone());
-                false
-            }
-            else
-            {
-                true
-            }
-        });
-
-    // Make sure that the directory iteration succeeded. We still have to account for filesystem
-    // errors here even if the iteration itself succeeded.
-    if let Err(error) = result
-    {
-        return Err(error);
-    }
-
-    // Check to see if we found the mount table in the root directory. If we didn't we just return
+    // Check to see if we found the mount table in the directory. If we didn't we just return
     // a blank mount table to the kernel and let it deal with the fact that we don't have any mount
     // points defined.
     if let Some(table_entry) = table_entry
     {
-        // We have a directory entry for the mount table so attempt to create a file stream for it.
-        let mount_stream = FileStream::new_from_directory_entry(volume, &table_entry);
-
-        if let Err(error) = mount_stream
-        {
-            return Err(error);
-        }
-
-        // Parse the mount table from the file stream and return it to the caller.
-        let mut mount_stream = mount_stream.unwrap();
+        // We have a directory entry for the mount table so attempt to create a file stream for it,
+        // then parse the mount table from the file stream and return it to the caller.
+        let mut mount_stream = FileStream::new_from_directory_entry(volume, &table_entry)?;
 
         parse_file_stream(&mut mount_stream, &mut mount_table)?;
     }
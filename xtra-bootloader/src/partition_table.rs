@@ -1,5 +1,6 @@
 
 const BOOT_SIGNATURE:          u16   = 0xAA55;  // Boot signature for MBR.
+const MBR_DISK_SIGNATURE:      usize = 440;     // Offset of the disk signature in the MBR.
 
 const PARTITION_TYPE_EMPTY:    u8    = 0x00;    // Empty partition type.
 const PARTITION_TYPE_FAT32:    u8    = 0x0C;    // FAT32 partition type.
@@ -156,4 +157,13 @@ impl MasterBootRecord
     {
         &self.partitions
     }
+
+    // The disk signature written by the partitioning tool, 0 if it didn't write one. It sits at the
+    // end of the boot code area.
+    pub fn disk_signature(&self) -> u32
+    {
+        u32::from_le_bytes(self.boot_code[MBR_DISK_SIGNATURE..MBR_DISK_SIGNATURE + 4]
+                               .try_into()
+                               .unwrap())
+    }
 }
@@ -0,0 +1,62 @@
+// Finding out where the system's RAM is. The device tree's memory nodes give the ranges of physical
+// memory, we use them to make sure that whatever we load fits inside of RAM before we write it.
+
+use xtra_kernel_shared::boot_info::XtraMemoryRange;
+
+use crate::device_tree::DeviceTree;
+
+
+
+// Each range in a memory node's reg property is a 64-bit address followed by a 64-bit size.
+const MEMORY_RANGE_SIZE: usize = 16;
+
+
+
+// Find the range of RAM that includes the given address, from the reg properties of the device
+// tree's memory nodes. Returns None if the address isn't in RAM.
+pub fn find_ram_range(device_tree: &DeviceTree, address: usize) -> Option<XtraMemoryRange>
+{
+    let mut ram_range = None;
+
+    device_tree.iterate_blocks(|offset, name|
+        {
+            // Memory nodes are named for their first address, memory@80000000 for instance.
+            let node_name = name.split('@').next().unwrap_or(name);
+
+            if node_name != "memory"
+            {
+                return true;
+            }
+
+            device_tree.iterate_properties(offset, |prop_name, prop_value|
+                {
+                    if prop_name != "reg"
+                    {
+                        return true;
+                    }
+
+                    for range in prop_value.chunks_exact(MEMORY_RANGE_SIZE)
+                    {
+                        let mut base = [0u8; 8];
+                        let mut size = [0u8; 8];
+
+                        base.copy_from_slice(&range[0..8]);
+                        size.copy_from_slice(&range[8..16]);
+
+                        let range = XtraMemoryRange::new(u64::from_be_bytes(base),
+                                                         u64::from_be_bytes(size));
+
+                        if range.contains(address as u64)
+                        {
+                            ram_range = Some(range);
+                        }
+                    }
+
+                    ram_range.is_none()
+                });
+
+            ram_range.is_none()
+        });
+
+    ram_range
+}
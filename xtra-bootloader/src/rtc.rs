@@ -0,0 +1,146 @@
+// Reading of the wall clock time so that the bootloader can tell the kernel when the system was
+// booted. QEMU's virt machine provides a Goldfish real time clock, we find it in the device tree
+// and read the time from it.
+//
+// We also measure short delays, such as the boot menu's timeout, with the time CSR. The rate it
+// counts at is given by the timebase-frequency property of the device tree's /cpus node.
+
+use core::{ arch::asm, ptr::read_volatile };
+
+use crate::device_tree::DeviceTree;
+
+
+
+// The compatible string of the Goldfish real time clock.
+const GOLDFISH_RTC_COMPATIBLE: &str = "google,goldfish-rtc";
+
+// Offsets of the clock's registers. Reading the low word latches the high word so that the two
+// halves of the time are consistent.
+const RTC_TIME_LOW:  usize = 0x00;
+const RTC_TIME_HIGH: usize = 0x04;
+
+// The rate of the time CSR if the device tree doesn't tell us, QEMU's virt machine runs it at
+// 10MHz.
+const DEFAULT_TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+
+
+// Find the base address of the Goldfish real time clock in the device tree, if there is one.
+pub fn find_rtc(device_tree: &DeviceTree) -> Option<usize>
+{
+    let mut rtc_base = None;
+
+    device_tree.iterate_blocks(|offset, _name|
+        {
+            let mut base: Option<usize> = None;
+            let mut compatible = false;
+
+            device_tree.iterate_properties(offset, |prop_name, prop_value|
+                {
+                    match prop_name
+                    {
+                        "compatible" =>
+                            {
+                                compatible = prop_value.split(|&byte| byte == 0)
+                                                       .any(|name|
+                                                           {
+                                                               name == GOLDFISH_RTC_COMPATIBLE
+                                                                           .as_bytes()
+                                                           });
+                            },
+
+                        "reg" if prop_value.len() >= 8 =>
+                            {
+                                let mut bytes = [0u8; 8];
+
+                                bytes.copy_from_slice(&prop_value[0..8]);
+                                base = Some(u64::from_be_bytes(bytes) as usize);
+                            },
+
+                        _ => {}
+                    }
+
+                    true
+                });
+
+            if compatible && base.is_some()
+            {
+                rtc_base = base;
+
+                return false;
+            }
+
+            true
+        });
+
+    rtc_base
+}
+
+
+// Read the current time from the real time clock at the given base address, in nanoseconds since
+// the Unix epoch.
+pub fn read_rtc(rtc_base: usize) -> u64
+{
+    unsafe
+    {
+        let low = read_volatile((rtc_base + RTC_TIME_LOW) as *const u32) as u64;
+        let high = read_volatile((rtc_base + RTC_TIME_HIGH) as *const u32) as u64;
+
+        (high << 32) | low
+    }
+}
+
+
+// Read the time CSR, the count of timer ticks since the system was reset.
+pub fn read_ticks() -> u64
+{
+    let ticks: u64;
+
+    unsafe
+    {
+        asm!
+        (
+            "rdtime {ticks}",
+            ticks = out(reg) ticks,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    ticks
+}
+
+
+// Find how many times a second the time CSR counts, from the timebase-frequency property of the
+// device tree's /cpus node.
+pub fn find_timebase_frequency(device_tree: &DeviceTree) -> u64
+{
+    let mut frequency = DEFAULT_TIMEBASE_FREQUENCY;
+
+    device_tree.iterate_blocks(|offset, name|
+        {
+            if name != "cpus"
+            {
+                return true;
+            }
+
+            device_tree.iterate_properties(offset, |prop_name, prop_value|
+                {
+                    if    prop_name == "timebase-frequency"
+                       && prop_value.len() >= 4
+                    {
+                        let mut bytes = [0u8; 4];
+
+                        bytes.copy_from_slice(&prop_value[0..4]);
+                        frequency = u32::from_be_bytes(bytes) as u64;
+
+                        return false;
+                    }
+
+                    true
+                });
+
+            false
+        });
+
+    frequency
+}
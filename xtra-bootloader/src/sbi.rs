@@ -0,0 +1,576 @@
+// The supervisor binary interface, (SBI,) that the bootloader provides to the kernel once it has
+// been booted. The kernel runs in supervisor mode and calls down into the firmware with ecall for
+// the things only machine mode can do.
+//
+// We implement the parts of version 1.0 of the SBI specification the kernel needs:
+//
+//  - Base, to find out what the firmware supports.
+//  - TIME, to program the hart's timer.
+//  - IPI, to send supervisor software interrupts to other harts.
+//  - RFENCE, to have other harts flush their instruction caches and TLBs.
+//  - HSM, to start, stop and query the state of harts.
+//  - SRST, to shut down or reboot the system.
+//
+// Calls pass the extension ID in a7, the function ID in a6 and their arguments in a0 to a5. The
+// error code is returned in a0 and the value in a1.
+//
+// Requests to other harts are left in that hart's state and then the hart is poked with a machine
+// software interrupt through the CLINT so that it picks them up.
+
+use core::{ arch::asm,
+            hint::spin_loop,
+            ptr::write,
+            sync::atomic::{ AtomicBool, AtomicUsize, Ordering } };
+
+use crate::{ clint::Clint,
+             csr::{ clear_mie_bits,
+                    clear_mip_bits,
+                    read_marchid,
+                    read_mimpid,
+                    read_mvendorid,
+                    set_mie_bits,
+                    set_mip_bits,
+                    INTERRUPT_MTI,
+                    INTERRUPT_SSI,
+                    INTERRUPT_STI },
+             firmware::{ hart_area, is_firmware_address, max_harts, park_hart, TrapFrame },
+             power::{ power_off, reset } };
+
+
+
+// The version of the SBI specification we implement, major version in the top bits.
+const SBI_SPEC_VERSION: usize = 1 << 24;
+
+// Our SBI implementation ID, "XTRA" as it's not one of the registered implementations.
+const SBI_IMPLEMENTATION_ID: usize = 0x5854_5241;
+
+// The version of our SBI implementation.
+const SBI_IMPLEMENTATION_VERSION: usize = 1;
+
+
+// The extensions we implement.
+const SBI_EXTENSION_BASE:   usize = 0x10;
+const SBI_EXTENSION_TIME:   usize = 0x5449_4D45;
+const SBI_EXTENSION_IPI:    usize = 0x0073_5049;
+const SBI_EXTENSION_RFENCE: usize = 0x5246_4E43;
+const SBI_EXTENSION_HSM:    usize = 0x0048_534D;
+const SBI_EXTENSION_SRST:   usize = 0x5352_5354;
+
+
+// Functions of the base extension.
+const SBI_BASE_GET_SPEC_VERSION: usize = 0;
+const SBI_BASE_GET_IMPL_ID:      usize = 1;
+const SBI_BASE_GET_IMPL_VERSION: usize = 2;
+const SBI_BASE_PROBE_EXTENSION:  usize = 3;
+const SBI_BASE_GET_MVENDORID:    usize = 4;
+const SBI_BASE_GET_MARCHID:      usize = 5;
+const SBI_BASE_GET_MIMPID:       usize = 6;
+
+// Functions of the TIME, IPI and RFENCE extensions.
+const SBI_TIME_SET_TIMER:                usize = 0;
+const SBI_IPI_SEND_IPI:                  usize = 0;
+const SBI_RFENCE_REMOTE_FENCE_I:         usize = 0;
+const SBI_RFENCE_REMOTE_SFENCE_VMA:      usize = 1;
+const SBI_RFENCE_REMOTE_SFENCE_VMA_ASID: usize = 2;
+
+// Functions of the HSM extension.
+const SBI_HSM_HART_START:      usize = 0;
+const SBI_HSM_HART_STOP:       usize = 1;
+const SBI_HSM_HART_GET_STATUS: usize = 2;
+const SBI_HSM_HART_SUSPEND:    usize = 3;
+
+// Functions and reset types of the SRST extension.
+const SBI_SRST_SYSTEM_RESET:     usize = 0;
+const SBI_SRST_TYPE_SHUTDOWN:    usize = 0;
+const SBI_SRST_TYPE_COLD_REBOOT: usize = 1;
+const SBI_SRST_TYPE_WARM_REBOOT: usize = 2;
+
+// The only kind of suspend we support, the hart waits for an interrupt and carries on.
+const SBI_HSM_SUSPEND_DEFAULT_RETENTIVE: usize = 0;
+
+
+// The error codes returned by SBI calls.
+const SBI_SUCCESS:               isize = 0;
+const SBI_ERR_FAILED:            isize = -1;
+const SBI_ERR_NOT_SUPPORTED:     isize = -2;
+const SBI_ERR_INVALID_PARAM:     isize = -3;
+const SBI_ERR_DENIED:            isize = -4;
+const SBI_ERR_INVALID_ADDRESS:   isize = -5;
+const SBI_ERR_ALREADY_AVAILABLE: isize = -6;
+const SBI_ERR_ALREADY_STARTED:   isize = -7;
+const SBI_ERR_ALREADY_STOPPED:   isize = -8;
+
+
+// The states of a hart as reported by the HSM extension. Harts we've never seen, because they don't
+// exist or never made it into the bootloader, are unavailable.
+pub const HART_STATE_STARTED:       usize = 0;
+pub const HART_STATE_STOPPED:       usize = 1;
+pub const HART_STATE_START_PENDING: usize = 2;
+pub const HART_STATE_STOP_PENDING:  usize = 3;
+pub const HART_STATE_UNAVAILABLE:   usize = usize::MAX;
+
+
+// The requests that can be left for a hart.
+const REQUEST_IPI:        usize = 1 << 0;  // Raise a supervisor software interrupt.
+const REQUEST_FENCE_I:    usize = 1 << 1;  // Flush the instruction cache.
+const REQUEST_SFENCE_VMA: usize = 1 << 2;  // Flush the TLB.
+const REQUEST_START:      usize = 1 << 3;  // Start running the kernel at start_address.
+
+// The requests the sender waits on before returning to the kernel.
+const FENCE_REQUESTS: usize = REQUEST_FENCE_I | REQUEST_SFENCE_VMA;
+
+
+// A hart mask base of all ones means the call is for every hart.
+const HART_MASK_BASE_ALL: usize = usize::MAX;
+
+
+
+// What the firmware knows about each hart, kept at the bottom of the hart's area.
+struct HartState
+{
+    status: AtomicUsize,          // The hart's HSM state.
+    requests: AtomicUsize,        // Requests left for the hart by the other harts.
+    start_address: AtomicUsize,   // Where to start the hart when it's next started.
+    opaque: AtomicUsize           // The value to pass in a1 when the hart is started.
+}
+
+
+impl HartState
+{
+    const fn new() -> HartState
+    {
+        HartState
+            {
+                status: AtomicUsize::new(HART_STATE_UNAVAILABLE),
+                requests: AtomicUsize::new(0),
+                start_address: AtomicUsize::new(0),
+                opaque: AtomicUsize::new(0)
+            }
+    }
+}
+
+
+
+// The base address of the system's CLINT, set before any hart enters the kernel.
+static CLINT_BASE: AtomicUsize = AtomicUsize::new(0);
+
+
+// Set once the hart states are initialized and the CLINT is known. The secondary harts wait for
+// this before they park themselves.
+static SBI_READY: AtomicBool = AtomicBool::new(false);
+
+
+
+// Get the CLINT we use for timers and software interrupts.
+fn clint() -> Clint
+{
+    Clint::new(CLINT_BASE.load(Ordering::Acquire))
+}
+
+
+// Get a hart's state, if it's one of the harts we support.
+fn hart_state(hart_id: usize) -> Option<&'static HartState>
+{
+    hart_area(hart_id).map(|area| unsafe { &*(area as *const HartState) })
+}
+
+
+// Iterate over the states of all of the harts we support, along with their IDs.
+fn hart_states() -> impl Iterator<Item = (usize, &'static HartState)>
+{
+    (0..max_harts()).filter_map(|hart_id| hart_state(hart_id).map(|state| (hart_id, state)))
+}
+
+
+// Set up the SBI before any of the harts enter the kernel. The hart areas aren't part of the
+// bootloader's image, so their contents are unknown until we set them up here. Every hart starts
+// out as unavailable until it checks in with the firmware.
+pub fn init_sbi(clint: Clint)
+{
+    for hart_id in 0..max_harts()
+    {
+        if let Some(area) = hart_area(hart_id)
+        {
+            unsafe { write(area as *mut HartState, HartState::new()); }
+        }
+    }
+
+    CLINT_BASE.store(clint.base(), Ordering::Release);
+    SBI_READY.store(true, Ordering::Release);
+}
+
+
+// Wait for the boot hart to get the SBI ready. The secondary harts wait here right after power on.
+pub fn wait_for_sbi()
+{
+    while !SBI_READY.load(Ordering::Acquire)
+    {
+        spin_loop();
+    }
+}
+
+
+// Record a hart's change of state.
+pub fn set_hart_status(hart_id: usize, status: usize)
+{
+    if let Some(state) = hart_state(hart_id)
+    {
+        state.status.store(status, Ordering::Release);
+    }
+}
+
+
+// A parked hart checks this to see if it has been asked to start. Returns the address to start at
+// and the value to pass to the kernel. Any other requests are dropped, a stopped hart has nothing
+// to interrupt or flush.
+pub fn take_start_request(hart_id: usize) -> Option<(usize, usize)>
+{
+    let state = hart_state(hart_id)?;
+
+    clint().clear_software_interrupt(hart_id);
+
+    let requests = state.requests.swap(0, Ordering::AcqRel);
+
+    if requests & REQUEST_START == 0
+    {
+        return None;
+    }
+
+    Some((state.start_address.load(Ordering::Acquire), state.opaque.load(Ordering::Acquire)))
+}
+
+
+// Call the function for each hart in the mask given to an SBI call. Returns an error if the mask
+// includes harts that we don't know about.
+fn for_each_hart<Func>(hart_mask: usize, hart_mask_base: usize, mut callback: Func) -> isize
+    where
+        Func: FnMut(usize)
+{
+    if hart_mask_base == HART_MASK_BASE_ALL
+    {
+        for (hart_id, state) in hart_states()
+        {
+            if state.status.load(Ordering::Acquire) == HART_STATE_STARTED
+            {
+                callback(hart_id);
+            }
+        }
+
+        return SBI_SUCCESS;
+    }
+
+    for bit in 0..usize::BITS as usize
+    {
+        if hart_mask & (1 << bit) == 0
+        {
+            continue;
+        }
+
+        let hart_id = hart_mask_base.saturating_add(bit);
+
+        match hart_state(hart_id).map(|state| state.status.load(Ordering::Acquire))
+        {
+            Some(HART_STATE_STARTED) => callback(hart_id),
+            Some(HART_STATE_UNAVAILABLE) | None => return SBI_ERR_INVALID_PARAM,
+
+            // Harts that aren't running have nothing to be interrupted or flushed.
+            Some(_) => {}
+        }
+    }
+
+    SBI_SUCCESS
+}
+
+
+// Leave requests for a hart and let it know about them.
+fn send_requests(hart_id: usize, requests: usize)
+{
+    if let Some(state) = hart_state(hart_id)
+    {
+        state.requests.fetch_or(requests, Ordering::AcqRel);
+        clint().raise_software_interrupt(hart_id);
+    }
+}
+
+
+// Carry out the requests left for this hart by the other harts. Called when the hart takes a
+// machine software interrupt.
+pub fn handle_requests(hart_id: usize)
+{
+    let Some(state) = hart_state(hart_id)
+    else
+    {
+        return;
+    };
+
+    clint().clear_software_interrupt(hart_id);
+
+    let requests = state.requests.load(Ordering::Acquire) & !REQUEST_START;
+
+    if requests & REQUEST_FENCE_I != 0
+    {
+        unsafe { asm!("fence.i", options(nostack, preserves_flags)); }
+    }
+
+    // We don't bother flushing just the address range asked for, flushing everything is always
+    // correct.
+    if requests & REQUEST_SFENCE_VMA != 0
+    {
+        unsafe { asm!("sfence.vma", options(nostack, preserves_flags)); }
+    }
+
+    if requests & REQUEST_IPI != 0
+    {
+        set_mip_bits(INTERRUPT_SSI);
+    }
+
+    // Let the senders of any fences know that they're done.
+    state.requests.fetch_and(!requests, Ordering::AcqRel);
+}
+
+
+// Send fence requests to the harts in the mask and wait for all of them to be carried out. While we
+// wait we carry out any requests sent to us, otherwise two harts fencing each other at the same
+// time would wait forever.
+fn remote_fence(hart_id: usize, hart_mask: usize, hart_mask_base: usize, request: usize) -> isize
+{
+    let error = for_each_hart(hart_mask, hart_mask_base, |target| send_requests(target, request));
+
+    if error != SBI_SUCCESS
+    {
+        return error;
+    }
+
+    for (_, state) in hart_states()
+    {
+        while state.requests.load(Ordering::Acquire) & FENCE_REQUESTS != 0
+        {
+            handle_requests(hart_id);
+            spin_loop();
+        }
+    }
+
+    SBI_SUCCESS
+}
+
+
+// The base extension, telling the kernel about the firmware.
+fn base_call(function: usize, frame: &TrapFrame) -> (isize, usize)
+{
+    match function
+    {
+        SBI_BASE_GET_SPEC_VERSION => (SBI_SUCCESS, SBI_SPEC_VERSION),
+        SBI_BASE_GET_IMPL_ID      => (SBI_SUCCESS, SBI_IMPLEMENTATION_ID),
+        SBI_BASE_GET_IMPL_VERSION => (SBI_SUCCESS, SBI_IMPLEMENTATION_VERSION),
+        SBI_BASE_GET_MVENDORID    => (SBI_SUCCESS, read_mvendorid()),
+        SBI_BASE_GET_MARCHID      => (SBI_SUCCESS, read_marchid()),
+        SBI_BASE_GET_MIMPID       => (SBI_SUCCESS, read_mimpid()),
+
+        SBI_BASE_PROBE_EXTENSION =>
+            {
+                let available = matches!(frame.argument(0),
+                                         SBI_EXTENSION_BASE
+                                         | SBI_EXTENSION_TIME
+                                         | SBI_EXTENSION_IPI
+                                         | SBI_EXTENSION_RFENCE
+                                         | SBI_EXTENSION_HSM
+                                         | SBI_EXTENSION_SRST);
+
+                (SBI_SUCCESS, available as usize)
+            },
+
+        _ => (SBI_ERR_NOT_SUPPORTED, 0)
+    }
+}
+
+
+// The TIME extension. The kernel's timer interrupt is raised from our machine timer interrupt, so
+// setting a new time also clears any pending supervisor timer interrupt.
+fn time_call(hart_id: usize, function: usize, frame: &TrapFrame) -> (isize, usize)
+{
+    match function
+    {
+        SBI_TIME_SET_TIMER =>
+            {
+                clint().set_timer(hart_id, frame.argument(0) as u64);
+
+                clear_mip_bits(INTERRUPT_STI);
+                set_mie_bits(INTERRUPT_MTI);
+
+                (SBI_SUCCESS, 0)
+            },
+
+        _ => (SBI_ERR_NOT_SUPPORTED, 0)
+    }
+}
+
+
+// The IPI extension.
+fn ipi_call(function: usize, frame: &TrapFrame) -> (isize, usize)
+{
+    match function
+    {
+        SBI_IPI_SEND_IPI =>
+            {
+                let error = for_each_hart(frame.argument(0),
+                                          frame.argument(1),
+                                          |target| send_requests(target, REQUEST_IPI));
+
+                (error, 0)
+            },
+
+        _ => (SBI_ERR_NOT_SUPPORTED, 0)
+    }
+}
+
+
+// The RFENCE extension. The supervisor fences can be limited to an address range and ASID, but we
+// always flush the whole TLB.
+fn rfence_call(hart_id: usize, function: usize, frame: &TrapFrame) -> (isize, usize)
+{
+    let request = match function
+        {
+            SBI_RFENCE_REMOTE_FENCE_I => REQUEST_FENCE_I,
+
+            SBI_RFENCE_REMOTE_SFENCE_VMA
+            | SBI_RFENCE_REMOTE_SFENCE_VMA_ASID => REQUEST_SFENCE_VMA,
+
+            _ => return (SBI_ERR_NOT_SUPPORTED, 0)
+        };
+
+    (remote_fence(hart_id, frame.argument(0), frame.argument(1), request), 0)
+}
+
+
+// Start a stopped hart running the kernel at the given address.
+fn hart_start(target: usize, start_address: usize, opaque: usize) -> isize
+{
+    let Some(state) = hart_state(target)
+    else
+    {
+        return SBI_ERR_INVALID_PARAM;
+    };
+
+    if is_firmware_address(start_address)
+    {
+        return SBI_ERR_INVALID_ADDRESS;
+    }
+
+    let result = state.status.compare_exchange(HART_STATE_STOPPED,
+                                               HART_STATE_START_PENDING,
+                                               Ordering::AcqRel,
+                                               Ordering::Acquire);
+
+    match result
+    {
+        Ok(_) =>
+            {
+                state.start_address.store(start_address, Ordering::Release);
+                state.opaque.store(opaque, Ordering::Release);
+
+                send_requests(target, REQUEST_START);
+
+                SBI_SUCCESS
+            },
+
+        Err(HART_STATE_UNAVAILABLE) => SBI_ERR_INVALID_PARAM,
+        Err(_)                      => SBI_ERR_ALREADY_AVAILABLE
+    }
+}
+
+
+// The HSM extension. Stopping a hart doesn't return, the hart is parked in the firmware until it's
+// started again.
+fn hsm_call(hart_id: usize, function: usize, frame: &TrapFrame) -> (isize, usize)
+{
+    match function
+    {
+        SBI_HSM_HART_START =>
+            {
+                (hart_start(frame.argument(0), frame.argument(1), frame.argument(2)), 0)
+            },
+
+        SBI_HSM_HART_STOP =>
+            {
+                set_hart_status(hart_id, HART_STATE_STOP_PENDING);
+                park_hart(hart_id);
+            },
+
+        SBI_HSM_HART_GET_STATUS =>
+            {
+                let status = hart_state(frame.argument(0))
+                    .map(|state| state.status.load(Ordering::Acquire));
+
+                match status
+                {
+                    Some(HART_STATE_UNAVAILABLE) | None => (SBI_ERR_INVALID_PARAM, 0),
+                    Some(status)                        => (SBI_SUCCESS, status)
+                }
+            },
+
+        SBI_HSM_HART_SUSPEND =>
+            {
+                if frame.argument(0) != SBI_HSM_SUSPEND_DEFAULT_RETENTIVE
+                {
+                    return (SBI_ERR_NOT_SUPPORTED, 0);
+                }
+
+                unsafe { asm!("wfi", options(nomem, nostack, preserves_flags)); }
+
+                (SBI_SUCCESS, 0)
+            },
+
+        _ => (SBI_ERR_NOT_SUPPORTED, 0)
+    }
+}
+
+
+// The SRST extension.
+fn srst_call(function: usize, frame: &TrapFrame) -> (isize, usize)
+{
+    if function != SBI_SRST_SYSTEM_RESET
+    {
+        return (SBI_ERR_NOT_SUPPORTED, 0);
+    }
+
+    match frame.argument(0)
+    {
+        SBI_SRST_TYPE_SHUTDOWN => power_off(),
+
+        SBI_SRST_TYPE_COLD_REBOOT
+        | SBI_SRST_TYPE_WARM_REBOOT => reset(),
+
+        _ => (SBI_ERR_INVALID_PARAM, 0)
+    }
+}
+
+
+// Handle an SBI call made by the kernel with ecall. The result is written back into the trap frame
+// so that the kernel sees it when we return.
+pub fn handle_sbi_call(hart_id: usize, frame: &mut TrapFrame)
+{
+    let extension = frame.extension_id();
+    let function = frame.function_id();
+
+    let (error, value) = match extension
+        {
+            SBI_EXTENSION_BASE   => base_call(function, frame),
+            SBI_EXTENSION_TIME   => time_call(hart_id, function, frame),
+            SBI_EXTENSION_IPI    => ipi_call(function, frame),
+            SBI_EXTENSION_RFENCE => rfence_call(hart_id, function, frame),
+            SBI_EXTENSION_HSM    => hsm_call(hart_id, function, frame),
+            SBI_EXTENSION_SRST   => srst_call(function, frame),
+            _                    => (SBI_ERR_NOT_SUPPORTED, 0)
+        };
+
+    frame.set_result(error, value);
+}
+
+
+// Handle the hart's machine timer interrupt by passing it on to the kernel as a supervisor timer
+// interrupt. The machine timer interrupt stays disabled until the kernel sets a new time.
+pub fn handle_timer_interrupt()
+{
+    clear_mie_bits(INTERRUPT_MTI);
+    set_mip_bits(INTERRUPT_STI);
+}
@@ -6,7 +6,7 @@ use core::ptr::{ read_volatile, write_volatile };
 pub const UART_0_BASE: usize = 0x1000_0000;
 
 const UART_THR: usize = 0; // Transmit Holding Register.
-//const UART_RBR: usize = 0; // Receive Buffer Register  .
+const UART_RBR: usize = 0; // Receive Buffer Register.
 const UART_IER: usize = 1; // Interrupt Enable Register.
 const UART_LCR: usize = 3; // Line Control Register.
 const UART_LSR: usize = 5; // Line Status Register.
@@ -56,6 +56,18 @@ impl Uart
         self.set_thr(c);
     }
 
+    // Read a character from the UART if one has been received, without waiting for one.
+    pub fn get_char(&self) -> Option<u8>
+    {
+        // Check the Data Ready bit of the Line Status Register.
+        if (self.get_lsr() & 0b_0000_0001) == 0
+        {
+            return None;
+        }
+
+        Some(self.get_rbr())
+    }
+
     pub fn put_str(&self, s: &str)
     {
         for c in s.bytes()
@@ -290,6 +302,14 @@ impl Uart
         }
     }
 
+    fn get_rbr(&self) -> u8
+    {
+        unsafe
+        {
+            read_volatile((self.base + UART_RBR) as *const u8)
+        }
+    }
+
     fn set_thr(&self, thr: u8)
     {
         unsafe
@@ -438,6 +438,7 @@ pub const VIRTIO_BLK_T_IN:             u32   = 0;
 pub const VIRTIO_BLK_T_OUT:            u32   = 1;
 
 // Device feature bits.
+pub const VIRTIO_BLK_F_SIZE_MAX:       u32   =  1;
 pub const VIRTIO_BLK_F_RO:             u32   =  5;
 pub const VIRTIO_BLK_F_SCSI:           u32   =  7;
 pub const VIRTIO_BLK_F_CONFIG_WCE:     u32   = 11;
@@ -457,6 +458,10 @@ pub const VIRTQ_DESC_F_WRITE: u16 = 2;
 pub const QUEUE_SIZE:                  usize = 8;
 pub const PAGE_SIZE:                   usize = 4096;
 
+// The largest single read request we will send to the device. Large reads are split into requests
+// of at most this size, (or smaller if the device has a lower segment size limit.)
+pub const MAX_REQUEST_SECTORS:         usize = 256;
+
 
 
 #[repr(C, align(16))]
@@ -664,7 +669,8 @@ const _: () =
 // with the VirtIO block device using MMIO (Memory-Mapped I/O) registers.
 pub struct VirtIoBlockDevice
 {
-    mmio: MmioDevice  // The MMIO register set for communicating with the VirtIO block device.
+    mmio: MmioDevice,    // The MMIO register set for communicating with the VirtIO block device.
+    max_request: usize   // The largest read request in bytes that we will send to the device.
 }
 
 
@@ -674,7 +680,8 @@ impl VirtIoBlockDevice
     {
         VirtIoBlockDevice
             {
-                mmio: MmioDevice::new(base_address)
+                mmio: MmioDevice::new(base_address),
+                max_request: MAX_REQUEST_SECTORS * SECTOR_SIZE
             }
     }
 
@@ -720,6 +727,18 @@ impl VirtIoBlockDevice
             return Err("feature negotiation failed");
         }
 
+        // If the device limits the size of a single segment, make sure that our requests fit in it.
+        // We send each read as a single data segment.
+        if features & (1 << VIRTIO_BLK_F_SIZE_MAX) != 0
+        {
+            let segment_sectors = self.mmio.max_segment_size() as usize / SECTOR_SIZE;
+
+            if segment_sectors > 0
+            {
+                self.max_request = self.max_request.min(segment_sectors * SECTOR_SIZE);
+            }
+        }
+
         // Initialize the device queue 0.
         self.mmio.set_queue_select(0);
 
@@ -813,9 +832,79 @@ impl VirtIoBlockDevice
         Ok(())
     }
 
+    // Read a single sector from the device.
     pub fn read_sector(&self, sector: u64, buffer: &mut Sector) -> IoResult<()>
     {
-        let request = BlockRequest::new(VIRTIO_BLK_T_IN, sector);
+        self.read_sectors(sector, buffer)
+    }
+
+    // Read a run of consecutive sectors into the buffer, which must be a whole number of sectors
+    // long. The read is issued as few large requests as the device allows, which is much faster
+    // than reading one sector at a time.
+    pub fn read_sectors(&self, sector: u64, buffer: &mut [u8]) -> IoResult<()>
+    {
+        if buffer.len() % SECTOR_SIZE != 0
+        {
+            return Err("Read buffer is not a whole number of sectors.");
+        }
+
+        let mut sector = sector;
+
+        for chunk in buffer.chunks_mut(self.max_request)
+        {
+            self.read_request(sector, chunk)?;
+            sector += (chunk.len() / SECTOR_SIZE) as u64;
+        }
+
+        Ok(())
+    }
+
+    // Write a run of consecutive sectors from the buffer, which must be a whole number of sectors
+    // long. The bootloader only writes small amounts of data, such as the boot state, so unlike
+    // reads no effort is made to batch the requests.
+    pub fn write_sectors(&self, sector: u64, buffer: &[u8]) -> IoResult<()>
+    {
+        if buffer.len() % SECTOR_SIZE != 0
+        {
+            return Err("Write buffer is not a whole number of sectors.");
+        }
+
+        let mut sector = sector;
+
+        for chunk in buffer.chunks(self.max_request)
+        {
+            self.request(VIRTIO_BLK_T_OUT, sector, chunk.as_ptr() as *mut u8, chunk.len())?;
+            sector += (chunk.len() / SECTOR_SIZE) as u64;
+        }
+
+        Ok(())
+    }
+
+    // Send a single read request to the device and wait for it to complete.
+    fn read_request(&self, sector: u64, buffer: &mut [u8]) -> IoResult<()>
+    {
+        self.request(VIRTIO_BLK_T_IN, sector, buffer.as_mut_ptr(), buffer.len())
+    }
+
+    // Send a single read or write request to the device and wait for it to complete. For reads the
+    // device fills in the buffer, for writes it only reads from it.
+    fn request(&self,
+               request_type: u32,
+               sector: u64,
+               buffer: *mut u8,
+               length: usize) -> IoResult<()>
+    {
+        let request = BlockRequest::new(request_type, sector);
+
+        // The data descriptor is only device writable when we're reading.
+        let data_flags = if request_type == VIRTIO_BLK_T_IN
+            {
+                VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT
+            }
+            else
+            {
+                VIRTQ_DESC_F_NEXT
+            };
 
         unsafe
         {
@@ -831,9 +920,9 @@ impl VirtIoBlockDevice
 
             DESCRIPTORS.0[1] = Descriptor
                 {
-                    address: buffer.as_mut_ptr() as u64,
-                    length: SECTOR_SIZE as u32,
-                    flags: VIRTQ_DESC_F_WRITE | VIRTQ_DESC_F_NEXT,
+                    address: buffer as u64,
+                    length: length as u32,
+                    flags: data_flags,
                     next: 2
                 };
 
@@ -0,0 +1,502 @@
+// The boot information handed from the bootloader to the Kernel. Everything the bootloader has
+// learned or set up for the Kernel is gathered into a single structure and a pointer to it is
+// passed to the Kernel's entry point.
+//
+// The structure starts with a magic number, its size and a version number so that the Kernel can
+// detect a bootloader that was built against a different definition of the structure. New fields
+// are only ever added to the end of the structure, and when that happens the version is increased.
+
+use core::{ clone::Clone,
+            cmp::{ Eq, PartialEq },
+            default::Default,
+            fmt::{ Display, Formatter, self },
+            iter::Iterator,
+            marker::Copy,
+            mem::size_of,
+            option::Option::{ self, None, Some },
+            prelude::rust_2024::derive,
+            result::Result::{ self, Err, Ok } };
+
+use crate::{ boot_state::XTRA_MAX_BOOT_ENTRY_NAME_LENGTH,
+             mount_table::{ padded_str, XtraMountTable } };
+
+
+
+/// The magic number found at the start of a valid boot information structure, "XTRABOOT".
+pub const XTRA_BOOT_INFO_MAGIC: u64 = u64::from_le_bytes(*b"XTRABOOT");
+
+
+
+/// The version of the boot information structure defined here.
+pub const XTRA_BOOT_INFO_VERSION: u32 = 3;
+
+
+
+/// The maximum length of the Kernel command line.
+pub const XTRA_MAX_COMMAND_LINE_LENGTH: usize = 512;
+
+
+
+/// The maximum number of modules, such as an initial RAM disk, the bootloader can load.
+pub const XTRA_MAX_BOOT_MODULES: usize = 8;
+
+
+
+/// The maximum length of the name of a boot module.
+pub const XTRA_MAX_BOOT_MODULE_NAME_LENGTH: usize = 32;
+
+
+
+/// The maximum number of memory regions the bootloader can reserve.
+pub const XTRA_MAX_RESERVED_MEMORY_REGIONS: usize = 8;
+
+
+
+/// Value of the boot disk and partition fields when they aren't known.
+pub const XTRA_BOOT_DEVICE_UNKNOWN: u32 = u32::MAX;
+
+
+
+/// A range of physical memory.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct XtraMemoryRange
+{
+    /// The physical address of the start of the range.
+    pub base: u64,
+
+    /// The size of the range in bytes.
+    pub size: u64
+}
+
+
+
+impl XtraMemoryRange
+{
+    /// Creates a new memory range.
+    pub const fn new(base: u64, size: u64) -> XtraMemoryRange
+    {
+        XtraMemoryRange { base, size }
+    }
+
+    /// The address one past the end of the range.
+    pub const fn end(&self) -> u64
+    {
+        self.base + self.size
+    }
+
+    /// Does the range include the given address?
+    pub const fn contains(&self, address: u64) -> bool
+    {
+        address >= self.base && address < self.end()
+    }
+
+    /// Do the two ranges share any addresses?
+    pub const fn overlaps(&self, other: &XtraMemoryRange) -> bool
+    {
+        self.base < other.end() && other.base < self.end()
+    }
+}
+
+
+
+impl Display for XtraMemoryRange
+{
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error>
+    {
+        write!(formatter, "0x{:016x} - 0x{:016x}", self.base, self.end())
+    }
+}
+
+
+
+/// The kinds of modules the bootloader can load for the Kernel.
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum XtraBootModuleKind
+{
+    /// The module slot is unused.
+    None,
+
+    /// An initial RAM disk holding the files the Kernel needs before it can mount the root
+    /// filesystem.
+    InitialRamDisk,
+
+    /// Some other file loaded for the Kernel.
+    Other
+}
+
+
+
+/// A file the bootloader loaded into memory for the Kernel.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XtraBootModule
+{
+    /// What the module is for.
+    pub kind: XtraBootModuleKind,
+
+    /// The name of the module, zero padded.
+    pub name: [u8; XTRA_MAX_BOOT_MODULE_NAME_LENGTH],
+
+    /// Where in memory the module was loaded.
+    pub range: XtraMemoryRange
+}
+
+
+
+impl XtraBootModule
+{
+    /// Creates a new unused module entry.
+    pub const fn new() -> XtraBootModule
+    {
+        XtraBootModule
+            {
+                kind: XtraBootModuleKind::None,
+                name: [0; XTRA_MAX_BOOT_MODULE_NAME_LENGTH],
+                range: XtraMemoryRange::new(0, 0)
+            }
+    }
+
+    /// The name of the module as a string, without the padding.
+    pub fn name_str(&self) -> &str
+    {
+        padded_str(&self.name)
+    }
+}
+
+
+
+/// The default unused module entry.
+impl Default for XtraBootModule
+{
+    fn default() -> XtraBootModule
+    {
+        XtraBootModule::new()
+    }
+}
+
+
+
+/// All of the information the bootloader passes to the Kernel.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XtraBootInfo
+{
+    /// Always XTRA_BOOT_INFO_MAGIC.
+    pub magic: u64,
+
+    /// The size of the structure in bytes, as the bootloader sees it.
+    pub size: u32,
+
+    /// The version of the structure, XTRA_BOOT_INFO_VERSION when it was built.
+    pub version: u32,
+
+    /// The filesystems the Kernel should mount.
+    pub mount_table: XtraMountTable,
+
+    /// The Kernel command line, zero padded.
+    pub command_line: [u8; XTRA_MAX_COMMAND_LINE_LENGTH],
+
+    /// The number of modules that were loaded.
+    pub num_modules: u32,
+
+    /// The modules loaded for the Kernel.
+    pub modules: [XtraBootModule; XTRA_MAX_BOOT_MODULES],
+
+    /// The index of the disk the Kernel was loaded from, or XTRA_BOOT_DEVICE_UNKNOWN.
+    pub boot_disk: u32,
+
+    /// The index of the partition the Kernel was loaded from, or XTRA_BOOT_DEVICE_UNKNOWN.
+    pub boot_partition: u32,
+
+    /// The number of reserved memory regions.
+    pub num_reserved_regions: u32,
+
+    /// Memory the bootloader is still using, or has set aside, that the Kernel must not allocate.
+    pub reserved_regions: [XtraMemoryRange; XTRA_MAX_RESERVED_MEMORY_REGIONS],
+
+    /// The wall clock time the Kernel was started at in nanoseconds since the Unix epoch, or 0 if
+    /// the bootloader couldn't find a real time clock.
+    pub boot_time: u64,
+
+    /// The value of the time CSR when the boot time was read, so that the Kernel can tell how long
+    /// ago that was.
+    pub boot_ticks: u64,
+
+    /// The name of the boot entry the Kernel was booted from, zero padded. Added in version 2.
+    pub boot_entry: [u8; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH],
+
+    /// The sector on the boot disk holding the boot state, where the Kernel marks the boot as
+    /// successful, or 0 if there isn't one. Added in version 2.
+    pub boot_state_sector: u64,
+
+    /// The disk signature from the boot disk's MBR, or 0 if it has none. Disks may be numbered
+    /// differently by the Kernel, so this is how it finds the boot disk again. Added in version 3.
+    pub boot_disk_signature: u32
+}
+
+
+
+impl XtraBootInfo
+{
+    /// Creates a new, empty, boot information structure.
+    pub const fn new() -> XtraBootInfo
+    {
+        XtraBootInfo
+            {
+                magic: XTRA_BOOT_INFO_MAGIC,
+                size: size_of::<XtraBootInfo>() as u32,
+                version: XTRA_BOOT_INFO_VERSION,
+                mount_table: XtraMountTable::new(),
+                command_line: [0; XTRA_MAX_COMMAND_LINE_LENGTH],
+                num_modules: 0,
+                modules: [XtraBootModule::new(); XTRA_MAX_BOOT_MODULES],
+                boot_disk: XTRA_BOOT_DEVICE_UNKNOWN,
+                boot_partition: XTRA_BOOT_DEVICE_UNKNOWN,
+                num_reserved_regions: 0,
+                reserved_regions: [XtraMemoryRange::new(0, 0); XTRA_MAX_RESERVED_MEMORY_REGIONS],
+                boot_time: 0,
+                boot_ticks: 0,
+                boot_entry: [0; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH],
+                boot_state_sector: 0,
+                boot_disk_signature: 0
+            }
+    }
+
+    /// Make sure that the structure was written by a bootloader that agrees with us on its layout.
+    pub fn validate(&self) -> Result<(), &'static str>
+    {
+        if self.magic != XTRA_BOOT_INFO_MAGIC
+        {
+            return Err("Boot information has an invalid magic number.");
+        }
+
+        if self.version != XTRA_BOOT_INFO_VERSION
+        {
+            return Err("Boot information version does not match the Kernel's.");
+        }
+
+        if self.size as usize != size_of::<XtraBootInfo>()
+        {
+            return Err("Boot information size does not match the Kernel's.");
+        }
+
+        if    self.num_modules as usize > XTRA_MAX_BOOT_MODULES
+           || self.num_reserved_regions as usize > XTRA_MAX_RESERVED_MEMORY_REGIONS
+        {
+            return Err("Boot information has too many modules or reserved regions.");
+        }
+
+        Ok(())
+    }
+
+    /// The Kernel command line as a string, without the padding.
+    pub fn command_line_str(&self) -> &str
+    {
+        padded_str(&self.command_line)
+    }
+
+    /// Set the Kernel command line, fails if it's too long to fit.
+    pub fn set_command_line(&mut self, command_line: &str) -> Result<(), &'static str>
+    {
+        if command_line.len() >= XTRA_MAX_COMMAND_LINE_LENGTH
+        {
+            return Err("Kernel command line is too long.");
+        }
+
+        self.command_line = [0; XTRA_MAX_COMMAND_LINE_LENGTH];
+        self.command_line[..command_line.len()].copy_from_slice(command_line.as_bytes());
+
+        Ok(())
+    }
+
+    /// The modules that were loaded.
+    pub fn modules(&self) -> &[XtraBootModule]
+    {
+        &self.modules[..self.num_modules as usize]
+    }
+
+    /// Record a module loaded into memory, fails if there isn't room for it or its name.
+    pub fn add_module(&mut self,
+                      kind: XtraBootModuleKind,
+                      name: &str,
+                      range: XtraMemoryRange) -> Result<(), &'static str>
+    {
+        if self.num_modules as usize >= XTRA_MAX_BOOT_MODULES
+        {
+            return Err("Too many boot modules.");
+        }
+
+        if name.len() >= XTRA_MAX_BOOT_MODULE_NAME_LENGTH
+        {
+            return Err("Boot module name is too long.");
+        }
+
+        let module = &mut self.modules[self.num_modules as usize];
+
+        module.kind = kind;
+        module.name = [0; XTRA_MAX_BOOT_MODULE_NAME_LENGTH];
+        module.name[..name.len()].copy_from_slice(name.as_bytes());
+        module.range = range;
+
+        self.num_modules += 1;
+
+        Ok(())
+    }
+
+    /// Find the first module of the given kind.
+    pub fn find_module(&self, kind: XtraBootModuleKind) -> Option<&XtraBootModule>
+    {
+        self.modules().iter().find(|module| module.kind == kind)
+    }
+
+    /// The memory regions reserved by the bootloader.
+    pub fn reserved_regions(&self) -> &[XtraMemoryRange]
+    {
+        &self.reserved_regions[..self.num_reserved_regions as usize]
+    }
+
+    /// Reserve a region of memory so that the Kernel won't allocate it, fails if there isn't room
+    /// to record it.
+    pub fn add_reserved_region(&mut self, range: XtraMemoryRange) -> Result<(), &'static str>
+    {
+        if self.num_reserved_regions as usize >= XTRA_MAX_RESERVED_MEMORY_REGIONS
+        {
+            return Err("Too many reserved memory regions.");
+        }
+
+        self.reserved_regions[self.num_reserved_regions as usize] = range;
+        self.num_reserved_regions += 1;
+
+        Ok(())
+    }
+
+    /// Is the address part of a loaded module or a reserved region? Pages holding these addresses
+    /// must not be handed out by the Kernel's memory manager.
+    pub fn is_reserved(&self, address: u64) -> bool
+    {
+           self.modules().iter().any(|module| module.range.contains(address))
+        || self.reserved_regions().iter().any(|region| region.contains(address))
+    }
+
+    /// The name of the boot entry the Kernel was booted from, without the padding.
+    pub fn boot_entry_str(&self) -> &str
+    {
+        padded_str(&self.boot_entry)
+    }
+
+    /// Set the name of the boot entry, fails if it's too long to fit.
+    pub fn set_boot_entry(&mut self, name: &str) -> Result<(), &'static str>
+    {
+        if name.len() >= XTRA_MAX_BOOT_ENTRY_NAME_LENGTH
+        {
+            return Err("Boot entry name is too long.");
+        }
+
+        self.boot_entry = [0; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH];
+        self.boot_entry[..name.len()].copy_from_slice(name.as_bytes());
+
+        Ok(())
+    }
+
+    /// The sector holding the boot state, if there is one.
+    pub fn boot_state_sector(&self) -> Option<u64>
+    {
+        match self.boot_state_sector
+        {
+            0      => None,
+            sector => Some(sector)
+        }
+    }
+
+    /// The disk signature of the boot disk, if it has one.
+    pub fn boot_disk_signature(&self) -> Option<u32>
+    {
+        match self.boot_disk_signature
+        {
+            0         => None,
+            signature => Some(signature)
+        }
+    }
+
+    /// The disk and partition the Kernel was loaded from, if known.
+    pub fn boot_device(&self) -> Option<(u32, u32)>
+    {
+        if    self.boot_disk == XTRA_BOOT_DEVICE_UNKNOWN
+           || self.boot_partition == XTRA_BOOT_DEVICE_UNKNOWN
+        {
+            return None;
+        }
+
+        Some((self.boot_disk, self.boot_partition))
+    }
+}
+
+
+
+/// The default empty boot information.
+impl Default for XtraBootInfo
+{
+    fn default() -> XtraBootInfo
+    {
+        XtraBootInfo::new()
+    }
+}
+
+
+
+impl Display for XtraBootInfo
+{
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error>
+    {
+        write!(formatter, "Boot info version {}, ", self.version)?;
+
+        match self.boot_device()
+        {
+            Some((disk, partition)) =>
+                {
+                    write!(formatter, "booted from disk {} partition {}", disk, partition)?;
+
+                    if let Some(signature) = self.boot_disk_signature()
+                    {
+                        write!(formatter, ", disk signature {:08x}", signature)?;
+                    }
+
+                    writeln!(formatter)?;
+                },
+
+            None =>
+                {
+                    writeln!(formatter, "boot device unknown")?;
+                }
+        }
+
+        if !self.boot_entry_str().is_empty()
+        {
+            writeln!(formatter, "Boot entry: {}", self.boot_entry_str())?;
+        }
+
+        writeln!(formatter, "Command line: {}", self.command_line_str())?;
+
+        if self.boot_time != 0
+        {
+            writeln!(formatter,
+                     "Boot time: {} seconds since the epoch",
+                     self.boot_time / 1_000_000_000)?;
+        }
+
+        for module in self.modules()
+        {
+            writeln!(formatter, "Module: {}, {}", module.name_str(), module.range)?;
+        }
+
+        for region in self.reserved_regions()
+        {
+            writeln!(formatter, "Reserved: {}", region)?;
+        }
+
+        Ok(())
+    }
+}
+
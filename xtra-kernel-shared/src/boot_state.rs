@@ -0,0 +1,185 @@
+// The boot state records how the last boot went so that the bootloader can fall back to another
+// boot entry when a kernel fails to come up. It is kept in a single sector of a file on the boot
+// partition.
+//
+// Before handing over to a kernel the bootloader records the entry it's booting and marks the boot
+// as in progress. Once the kernel has come up far enough to be useful it marks the boot as
+// successful. If the bootloader finds a boot still marked as in progress the kernel never got that
+// far, and the fallback entry is used instead.
+
+use core::{ clone::Clone,
+            cmp::{ Eq, PartialEq },
+            default::Default,
+            fmt::{ Display, Formatter, self },
+            marker::Copy,
+            option::Option::{ self, None, Some },
+            prelude::rust_2024::derive,
+            result::Result::{ self, Err, Ok } };
+
+use crate::mount_table::padded_str;
+
+
+
+/// The magic number found at the start of a valid boot state sector, "XTRASTAT".
+pub const XTRA_BOOT_STATE_MAGIC: u64 = u64::from_le_bytes(*b"XTRASTAT");
+
+
+
+/// The size of the boot state on disk, a single sector.
+pub const XTRA_BOOT_STATE_SIZE: usize = 512;
+
+
+
+/// The maximum length of the name of a boot entry.
+pub const XTRA_MAX_BOOT_ENTRY_NAME_LENGTH: usize = 32;
+
+
+
+/// How the last boot went.
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum XtraBootStatus
+{
+    /// Nothing has been recorded yet.
+    Unknown = 0,
+
+    /// The bootloader handed over to the kernel but the kernel hasn't reported back.
+    Booting = 1,
+
+    /// The kernel came up successfully.
+    Successful = 2
+}
+
+
+
+/// The boot state as stored on disk.
+#[derive(Clone, Copy)]
+pub struct XtraBootState
+{
+    /// How the last boot went.
+    pub status: XtraBootStatus,
+
+    /// The number of boots in a row that were started but never marked as successful.
+    pub failed_attempts: u32,
+
+    /// The name of the boot entry that was booted, zero padded.
+    pub entry: [u8; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH]
+}
+
+
+
+impl XtraBootState
+{
+    /// Creates a new boot state with nothing recorded.
+    pub const fn new() -> XtraBootState
+    {
+        XtraBootState
+            {
+                status: XtraBootStatus::Unknown,
+                failed_attempts: 0,
+                entry: [0; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH]
+            }
+    }
+
+    /// Read the boot state from a sector. A sector that has never been written, or holds anything
+    /// else, reads as an unknown state.
+    pub fn from_sector(sector: &[u8; XTRA_BOOT_STATE_SIZE]) -> XtraBootState
+    {
+        let read_u32 = |offset: usize|
+            {
+                u32::from_le_bytes([ sector[offset],
+                                     sector[offset + 1],
+                                     sector[offset + 2],
+                                     sector[offset + 3] ])
+            };
+
+        let mut magic = [0u8; 8];
+
+        magic.copy_from_slice(&sector[0..8]);
+
+        if u64::from_le_bytes(magic) != XTRA_BOOT_STATE_MAGIC
+        {
+            return XtraBootState::new();
+        }
+
+        let status = match read_u32(8)
+            {
+                1 => XtraBootStatus::Booting,
+                2 => XtraBootStatus::Successful,
+                _ => XtraBootStatus::Unknown
+            };
+
+        let mut entry = [0u8; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH];
+
+        entry.copy_from_slice(&sector[16..16 + XTRA_MAX_BOOT_ENTRY_NAME_LENGTH]);
+
+        XtraBootState { status, failed_attempts: read_u32(12), entry }
+    }
+
+    /// Write the boot state into a sector, ready to be written to disk.
+    pub fn to_sector(&self, sector: &mut [u8; XTRA_BOOT_STATE_SIZE])
+    {
+        sector.fill(0);
+
+        sector[0..8].copy_from_slice(&XTRA_BOOT_STATE_MAGIC.to_le_bytes());
+        sector[8..12].copy_from_slice(&(self.status as u32).to_le_bytes());
+        sector[12..16].copy_from_slice(&self.failed_attempts.to_le_bytes());
+        sector[16..16 + XTRA_MAX_BOOT_ENTRY_NAME_LENGTH].copy_from_slice(&self.entry);
+    }
+
+    /// The name of the boot entry as a string, without the padding.
+    pub fn entry_str(&self) -> &str
+    {
+        padded_str(&self.entry)
+    }
+
+    /// Set the name of the boot entry, fails if it's too long to fit.
+    pub fn set_entry(&mut self, name: &str) -> Result<(), &'static str>
+    {
+        if name.len() >= XTRA_MAX_BOOT_ENTRY_NAME_LENGTH
+        {
+            return Err("Boot entry name is too long.");
+        }
+
+        self.entry = [0; XTRA_MAX_BOOT_ENTRY_NAME_LENGTH];
+        self.entry[..name.len()].copy_from_slice(name.as_bytes());
+
+        Ok(())
+    }
+
+    /// The entry of the last boot if it never reported back as successful.
+    pub fn failed_entry(&self) -> Option<&str>
+    {
+        match self.status
+        {
+            XtraBootStatus::Booting => Some(self.entry_str()),
+            _                       => None
+        }
+    }
+}
+
+
+
+/// The default boot state with nothing recorded.
+impl Default for XtraBootState
+{
+    fn default() -> XtraBootState
+    {
+        XtraBootState::new()
+    }
+}
+
+
+
+impl Display for XtraBootStatus
+{
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error>
+    {
+        match self
+        {
+            XtraBootStatus::Unknown    => write!(formatter, "unknown"),
+            XtraBootStatus::Booting    => write!(formatter, "booting"),
+            XtraBootStatus::Successful => write!(formatter, "successful")
+        }
+    }
+}
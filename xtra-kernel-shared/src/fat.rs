@@ -0,0 +1,16 @@
+// Pieces of the FAT filesystem format that both the bootloader's and the kernel's FAT-32 drivers
+// need to agree on.
+
+
+
+/// The length of a short, 8.3, name as stored in a directory entry.
+pub const SHORT_NAME_LENGTH: usize = 11;
+
+
+
+/// The checksum of a short name that each of the long name entries for the same file carry, so
+/// that long name entries orphaned by a system that doesn't know about them can be spotted.
+pub fn short_name_checksum(name: &[u8; SHORT_NAME_LENGTH]) -> u8
+{
+    name.iter().fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
@@ -15,3 +15,20 @@ pub mod device_tree;
 /// Description of the xtra-shared mount table. It allows the bootloader to communicate the system
 /// mount table to the Kernel.
 pub mod mount_table;
+
+
+
+/// The boot information structure the bootloader hands to the Kernel, it carries the mount table
+/// along with everything else the Kernel needs to know about how it was booted.
+pub mod boot_info;
+
+
+
+/// The record of how the last boot went, shared between the bootloader and the Kernel so that the
+/// bootloader can fall back to another kernel when one fails to come up.
+pub mod boot_state;
+
+
+
+/// The parts of the FAT filesystem format shared by the bootloader's and the Kernel's drivers.
+pub mod fat;
@@ -2,6 +2,10 @@
 // The definition of the mount table used by the Kernel to bring in the base file system(s) and to
 // know where to mount them in the filesystem tree. If nothing is mounted in / then the Kernel will
 // fail to boot the operating system.
+//
+// An entry selects its partition in one of three ways: by the index of the disk and partition, by
+// the partition's UUID or by the label of the filesystem on it. The UUID and label are selected by
+// text, it's up to the Kernel to match them against the partitions it finds.
 
 use core::{ clone::Clone,
             cmp::{ Eq, PartialEq },
@@ -24,6 +28,51 @@ pub const XTRA_MAX_MOUNT_POINT_STRING_LENGTH: usize = 64;
 
 
 
+/// The maximum length of a partition UUID string. This is long enough for the canonical text form
+/// of a GPT partition GUID, the shorter MBR form of signature and partition number also fits.
+pub const XTRA_MAX_PARTITION_UUID_LENGTH: usize = 36;
+
+
+
+/// The maximum length of a filesystem label string.
+pub const XTRA_MAX_LABEL_LENGTH: usize = 32;
+
+
+
+/// The filesystem should be mounted read-only.
+pub const XTRA_MOUNT_OPTION_READ_ONLY: u8 = 1 << 0;
+
+/// Programs should not be executed from the filesystem.
+pub const XTRA_MOUNT_OPTION_NO_EXEC: u8 = 1 << 1;
+
+/// Writes to the filesystem should go to disk straight away instead of being cached.
+pub const XTRA_MOUNT_OPTION_SYNC: u8 = 1 << 2;
+
+/// The boot should continue even if the filesystem can't be found or mounted.
+pub const XTRA_MOUNT_OPTION_OPTIONAL: u8 = 1 << 3;
+
+
+
+/// How a mount table entry selects the partition to mount.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum XtraPartitionSelector
+{
+    /// No partition has been selected.
+    None,
+
+    /// The partition is selected by the index of the disk and the partition on that disk.
+    Index,
+
+    /// The partition is selected by its partition UUID.
+    PartitionUuid,
+
+    /// The partition is selected by the label of the filesystem it holds.
+    Label
+}
+
+
+
 /// The types of filesystems that the Xtra Kernel supports.
 #[repr(u8)]
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -72,14 +121,26 @@ pub struct XtraMountTableEntry
     /// Where in the filesystem tree should this device be mounted?
     pub mount_point: [u8; XTRA_MAX_MOUNT_POINT_STRING_LENGTH],
 
-    /// Index of the drive to mount from.
+    /// How the partition to mount is selected.
+    pub selector: XtraPartitionSelector,
+
+    /// Index of the drive to mount from, when selecting by index.
     pub device: u8,
 
-    /// Partition of the drive to mount from.
+    /// Partition of the drive to mount from, when selecting by index.
     pub partition: u8,
 
+    /// The lower case UUID of the partition to mount, when selecting by partition UUID.
+    pub partition_uuid: [u8; XTRA_MAX_PARTITION_UUID_LENGTH],
+
+    /// The label of the filesystem to mount, when selecting by label.
+    pub label: [u8; XTRA_MAX_LABEL_LENGTH],
+
     /// The type of filesystem to expect on this device partition.
-    pub filesystem_type: XtraFilesystemType
+    pub filesystem_type: XtraFilesystemType,
+
+    /// The XTRA_MOUNT_OPTION_* flags for the mount.
+    pub options: u8
 }
 
 
@@ -92,11 +153,39 @@ impl XtraMountTableEntry
         XtraMountTableEntry
             {
                 mount_point: [0; XTRA_MAX_MOUNT_POINT_STRING_LENGTH],
+                selector: XtraPartitionSelector::None,
                 device: u8::MAX,
                 partition: u8::MAX,
-                filesystem_type: XtraFilesystemType::None
+                partition_uuid: [0; XTRA_MAX_PARTITION_UUID_LENGTH],
+                label: [0; XTRA_MAX_LABEL_LENGTH],
+                filesystem_type: XtraFilesystemType::None,
+                options: 0
             }
     }
+
+    /// The mount point as a string, without the padding.
+    pub fn mount_point_str(&self) -> &str
+    {
+        padded_str(&self.mount_point)
+    }
+
+    /// The partition UUID as a string, without the padding.
+    pub fn partition_uuid_str(&self) -> &str
+    {
+        padded_str(&self.partition_uuid)
+    }
+
+    /// The filesystem label as a string, without the padding.
+    pub fn label_str(&self) -> &str
+    {
+        padded_str(&self.label)
+    }
+
+    /// Is the given XTRA_MOUNT_OPTION_* flag set for this entry?
+    pub fn has_option(&self, option: u8) -> bool
+    {
+        (self.options & option) != 0
+    }
 }
 
 
@@ -116,12 +205,47 @@ impl Display for XtraMountTableEntry
 {
     fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error>
     {
-        write!(formatter,
-               "[ {}, Device: {}, Partition: {}, Filesystem Type: {} ]",
-               from_utf8(&self.mount_point).unwrap_or("<invalid utf-8>"),
-               self.device,
-               self.partition,
-               self.filesystem_type)?;
+        write!(formatter, "[ {}, ", self.mount_point_str())?;
+
+        match self.selector
+        {
+            XtraPartitionSelector::None =>
+                {
+                    write!(formatter, "No Partition")?;
+                },
+
+            XtraPartitionSelector::Index =>
+                {
+                    write!(formatter, "Device: {}, Partition: {}", self.device, self.partition)?;
+                },
+
+            XtraPartitionSelector::PartitionUuid =>
+                {
+                    write!(formatter, "Partition UUID: {}", self.partition_uuid_str())?;
+                },
+
+            XtraPartitionSelector::Label =>
+                {
+                    write!(formatter, "Label: {}", self.label_str())?;
+                }
+        }
+
+        write!(formatter, ", Filesystem Type: {}", self.filesystem_type)?;
+
+        let option_names = [ (XTRA_MOUNT_OPTION_READ_ONLY, "ro"),
+                             (XTRA_MOUNT_OPTION_NO_EXEC,   "noexec"),
+                             (XTRA_MOUNT_OPTION_SYNC,      "sync"),
+                             (XTRA_MOUNT_OPTION_OPTIONAL,  "optional") ];
+
+        for (option, name) in option_names
+        {
+            if self.has_option(option)
+            {
+                write!(formatter, ", {}", name)?;
+            }
+        }
+
+        write!(formatter, " ]")?;
 
         Ok(())
     }
@@ -129,6 +253,16 @@ impl Display for XtraMountTableEntry
 
 
 
+/// Get the text of a zero padded string field, invalid UTF-8 is shown as a placeholder.
+pub fn padded_str(bytes: &[u8]) -> &str
+{
+    let length = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+
+    from_utf8(&bytes[..length]).unwrap_or("<invalid utf-8>")
+}
+
+
+
 /// The representation of the mount table.
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -16,12 +16,12 @@ pub use riscv_64::*;
 
 
 
-use crate::arch::csr::read_mhartid;
+use crate::arch::read_hart_id;
 
 
 
 /// Get the index of the core this thread is running on.
 pub fn get_core_index() -> usize
 {
-    read_mhartid() as usize
+    read_hart_id()
 }
@@ -38,161 +38,279 @@ macro_rules! read_csr
 // Generic function for writing a value to a Control Status Register. No validation is done on the
 // CSR number or the value being written. It is up to the caller to ensure we're writing to an
 // existing CSR and that the value is valid for that CSR.
-#[inline(always)]
-fn _write_csr(csr: usize, value: u64)
+macro_rules! write_csr
 {
-    unsafe
-    {
-        asm!
-        (
-            "csrw {0}, {1}",
+    ($csr:expr, $value:expr) =>
+        {{
+            let value: u64 = $value;
 
-            in(reg) value,
-            in(reg) csr,
+            unsafe
+            {
+                asm!
+                (
+                    "csrw {1}, {0}",
+
+                    in(reg) value,
+                    const $csr,
 
-            options(nomem, nostack, preserves_flags)
-        );
-    }
+                    options(nomem, nostack, preserves_flags)
+                );
+            }
+        }};
 }
 
 
 
-// List of CSRs that are available in the RISC-V architecture.
+// Generic function for setting bits in a Control Status Register, leaving the other bits as they
+// are.
+macro_rules! set_csr_bits
+{
+    ($csr:expr, $bits:expr) =>
+        {{
+            let bits: u64 = $bits;
+
+            unsafe
+            {
+                asm!
+                (
+                    "csrs {1}, {0}",
+
+                    in(reg) bits,
+                    const $csr,
+
+                    options(nomem, nostack, preserves_flags)
+                );
+            }
+        }};
+}
+
+
+
+// Generic function for clearing bits in a Control Status Register, leaving the other bits as they
+// are.
+macro_rules! clear_csr_bits
+{
+    ($csr:expr, $bits:expr) =>
+        {{
+            let bits: u64 = $bits;
+
+            unsafe
+            {
+                asm!
+                (
+                    "csrc {1}, {0}",
+
+                    in(reg) bits,
+                    const $csr,
+
+                    options(nomem, nostack, preserves_flags)
+                );
+            }
+        }};
+}
+
+
+
+// List of CSRs that are available to the kernel in the RISC-V architecture. The kernel runs in
+// supervisor mode, the machine mode registers belong to the SBI firmware.
+
+
+// Supervisor Trap Setup.
+const CSR_SSTATUS:       usize = 0x100;  // Supervisor status register.
+const CSR_SIE:           usize = 0x104;  // Supervisor interrupt-enable register.
+const CSR_STVEC:         usize = 0x105;  // Supervisor trap handler base address.
+const CSR_SCOUNTEREN:    usize = 0x106;  // Supervisor counter enable.
+
+
+// Supervisor Trap Handling.
+const CSR_SSCRATCH:      usize = 0x140;  // Scratch register for supervisor trap handlers.
+const CSR_SEPC:          usize = 0x141;  // Supervisor exception program counter.
+const CSR_SCAUSE:        usize = 0x142;  // Supervisor trap cause.
+const CSR_STVAL:         usize = 0x143;  // Supervisor bad address or instruction.
+const CSR_SIP:           usize = 0x144;  // Supervisor interrupt pending.
 
 
-// Machine Information Registers.
-const CSR_MVENDORID:     usize = 0xf11;  // Vendor ID.
-const CSR_MARCHID:       usize = 0xf12;  // Architecture ID.
-const CSR_MIMPID:        usize = 0xf13;  // Implementation ID.
-const CSR_MHARTID:       usize = 0xf14;  // Hardware thread ID.
-const CSR_MCONFIGPTR:    usize = 0xf15;  // Pointer to configuration data structure.
+// Supervisor Protection and Translation.
+const CSR_SATP:          usize = 0x180;  // Supervisor address translation and protection.
 
 
-// Machine Memory Protection Registers.
-const CSR_PMPCFG00:      usize = 0x3A0;  // Physical memory protection configuration.
-const CSR_PMPCFG14:      usize = 0x3ae;  // Physical memory protection configuration.
+// Unprivileged Counters/Timers, readable by the kernel when the firmware allows it.
+const CSR_CYCLE:         usize = 0xc00;  // Cycle counter.
+const CSR_TIME:          usize = 0xc01;  // Timer.
+const CSR_INSTRET:       usize = 0xc02;  // Instructions-retired counter.
 
-const CSR_PMPADDR00:     usize = 0x3b0;  // Physical memory protection address register.
-const CSR_PMPADDR63:     usize = 0x3ef;  // Physical memory protection address register.
 
 
-// Machine counters/timers.
-const CSR_MCYCLE:        usize = 0xb00;  // Machine cycle counter.
-const CSR_MINSTRET:      usize = 0xb02;  // Machine instructions-retired counter.
+// Bits of the sstatus register.
+pub const SSTATUS_SIE:   u64 = 1 << 1;   // Supervisor interrupts enabled.
+pub const SSTATUS_SPIE:  u64 = 1 << 5;   // Supervisor interrupts enabled before the trap.
+pub const SSTATUS_SPP:   u64 = 1 << 8;   // The trap came from supervisor mode.
+pub const SSTATUS_SUM:   u64 = 1 << 18;  // Supervisor may access user pages.
 
 
-// Register collection counts.
-const CSR_PMPCFG_COUNT:  usize = (CSR_PMPCFG14 - CSR_PMPCFG00) / 2;
-const CSR_PMPADDR_COUNT: usize = (CSR_PMPADDR63 - CSR_PMPADDR00) + 1;
+// Bits of the sie and sip registers.
+pub const INTERRUPT_SSI: u64 = 1 << 1;   // Supervisor software interrupt.
+pub const INTERRUPT_STI: u64 = 1 << 5;   // Supervisor timer interrupt.
+pub const INTERRUPT_SEI: u64 = 1 << 9;   // Supervisor external interrupt.
 
 
 
-// ---- Machine Information Registers -------------------------------------------------------------
+// ---- Supervisor Trap Setup ----------------------------------------------------------------------
 
-pub fn read_mvendorid() -> u64
+pub fn read_sstatus() -> u64
 {
-    read_csr!(CSR_MVENDORID)
+    read_csr!(CSR_SSTATUS)
 }
 
 
 
-pub fn read_marchid() -> u64
+pub fn write_sstatus(value: u64)
 {
-    read_csr!(CSR_MARCHID)
+    write_csr!(CSR_SSTATUS, value);
 }
 
 
 
-pub fn read_mimpid() -> u64
+pub fn set_sstatus_bits(bits: u64)
 {
-    read_csr!(CSR_MIMPID)
+    set_csr_bits!(CSR_SSTATUS, bits);
 }
 
 
 
-pub fn read_mhartid() -> u64
+pub fn clear_sstatus_bits(bits: u64)
 {
-    read_csr!(CSR_MHARTID)
+    clear_csr_bits!(CSR_SSTATUS, bits);
 }
 
 
 
-pub fn read_mconfigptr() -> u64
+pub fn read_sie() -> u64
 {
-    read_csr!(CSR_MCONFIGPTR)
+    read_csr!(CSR_SIE)
 }
 
 
 
-// ---- Machine Memory Protection Registers --------------------------------------------------------
+pub fn set_sie_bits(bits: u64)
+{
+    set_csr_bits!(CSR_SIE, bits);
+}
+
+
+
+pub fn clear_sie_bits(bits: u64)
+{
+    clear_csr_bits!(CSR_SIE, bits);
+}
+
+
+
+pub fn read_stvec() -> u64
+{
+    read_csr!(CSR_STVEC)
+}
+
+
+
+pub fn write_stvec(value: u64)
+{
+    write_csr!(CSR_STVEC, value);
+}
+
+
+
+pub fn write_scounteren(value: u64)
+{
+    write_csr!(CSR_SCOUNTEREN, value);
+}
+
+
+
+// ---- Supervisor Trap Handling -------------------------------------------------------------------
 
-const PMP_CFG_R:     u64 = 0b_0000_0001;  // Read access.
-const PMP_CFG_W:     u64 = 0b_0000_0010;  // Write access.
-const PMP_CFG_X:     u64 = 0b_0000_0100;  // Execute access.
-const PMP_CFG_TOR:   u64 = 0b_0000_1000;  // Top-of-range mode.
-const PMP_CFG_NAPOT: u64 = 0b_0001_0000;  // Next address power of two mode.
-const PMP_CFG_L:     u64 = 0b_1000_0000;  // Locked configuration.
+pub fn read_sscratch() -> u64
+{
+    read_csr!(CSR_SSCRATCH)
+}
 
 
 
-/*pub fn read_pmpcfg(index: usize) -> u64
+pub fn write_sscratch(value: u64)
 {
-    if index > CSR_PMPCFG_COUNT
-    {
-        panic!("Invalid PMP configuration index: {}", index);
-    }
+    write_csr!(CSR_SSCRATCH, value);
+}
+
 
-    read_csr!(CSR_PMPCFG00 + (index * 2))
+
+pub fn read_sepc() -> u64
+{
+    read_csr!(CSR_SEPC)
 }
 
 
 
-pub fn write_pmpcfg(index: usize, value: u64)
+pub fn write_sepc(value: u64)
 {
-    if index > CSR_PMPCFG_COUNT
-    {
-        panic!("Invalid PMP configuration index: {}", index);
-    }
+    write_csr!(CSR_SEPC, value);
+}
 
-    write_csr(CSR_PMPCFG00 + (index * 2), value);
+
+
+pub fn read_scause() -> u64
+{
+    read_csr!(CSR_SCAUSE)
 }
 
 
 
-pub fn read_pmpaddr(index: usize) -> u64
+pub fn read_stval() -> u64
 {
-    if index > CSR_PMPADDR_COUNT
-    {
-        panic!("Invalid PMP address index: {}", index);
-    }
+    read_csr!(CSR_STVAL)
+}
+
+
 
-    read_csr!(CSR_PMPADDR00 + index)
+pub fn read_sip() -> u64
+{
+    read_csr!(CSR_SIP)
+}
+
+
+
+pub fn clear_sip_bits(bits: u64)
+{
+    clear_csr_bits!(CSR_SIP, bits);
 }
 
 
 
-pub fn write_pmpaddr(index: usize, value: u64)
+// ---- Supervisor Protection and Translation ------------------------------------------------------
+
+pub fn read_satp() -> u64
 {
-    if index > CSR_PMPADDR_COUNT
-    {
-        panic!("Invalid PMP address index: {}", index);
-    }
+    read_csr!(CSR_SATP)
+}
+
 
-    write_csr(CSR_PMPADDR00 + index, value);
-}*/
 
+// ---- Counters/Timers ----------------------------------------------------------------------------
+
+pub fn read_time() -> u64
+{
+    read_csr!(CSR_TIME)
+}
 
 
-// ---- Machine Counters/Timers --------------------------------------------------------------------
 
 pub fn _read_cycle_counter() -> u64
 {
-    read_csr!(CSR_MCYCLE)
+    read_csr!(CSR_CYCLE)
 }
 
 
 
 pub fn _read_instruction_counter() -> u64
 {
-    read_csr!(CSR_MINSTRET)
+    read_csr!(CSR_INSTRET)
 }
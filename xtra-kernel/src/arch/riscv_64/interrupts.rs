@@ -0,0 +1,178 @@
+// Supervisor mode trap handling for RISC-V. Every trap the firmware delegates to us comes in
+// through the trap vector here, which saves the interrupted code's registers on its stack and hands
+// the trap to the handler.
+//
+// The kernel only runs in supervisor mode for now, so a trap never has to switch stacks. Interrupts
+// are dispatched to the rest of the kernel, any exception is a bug in the kernel and we panic.
+
+use core::arch::global_asm;
+
+use crate::{ arch::csr::{ clear_sstatus_bits,
+                          read_scause,
+                          read_sepc,
+                          read_sstatus,
+                          read_stval,
+                          set_sstatus_bits,
+                          write_stvec,
+                          SSTATUS_SIE },
+             interrupts::handle_external_interrupt,
+             ipi::handle_ipi,
+             timers::handle_timer_interrupt };
+
+
+
+/// The number of registers saved in the trap frame, x0 to x31. We don't save x0, but keeping its
+/// slot means the register number is the index into the frame.
+const TRAP_FRAME_REGISTERS: usize = 32;
+
+/// The top bit of scause is set if the trap was caused by an interrupt.
+const SCAUSE_INTERRUPT: u64 = 1 << 63;
+
+/// The causes of the interrupts we handle, as found in scause with the interrupt bit cleared.
+const CAUSE_SUPERVISOR_SOFTWARE_INTERRUPT: u64 = 1;
+const CAUSE_SUPERVISOR_TIMER_INTERRUPT: u64 = 5;
+const CAUSE_SUPERVISOR_EXTERNAL_INTERRUPT: u64 = 9;
+
+
+
+/// The registers of the interrupted code, saved on its stack by the trap vector.
+#[repr(C)]
+pub struct TrapFrame
+{
+    pub registers: [u64; TRAP_FRAME_REGISTERS]
+}
+
+
+
+// The trap vector. We make room for a trap frame on the current stack, save every register in it
+// and call the handler. Once the handler returns we put everything back and return to the
+// interrupted code.
+//
+// The vector needs to be 4 byte aligned as the low bits of stvec select the trap mode.
+global_asm!
+(
+    ".pushsection .text.supervisor_trap_vector, \"ax\"",
+    ".global supervisor_trap_vector",
+    ".align 4",
+
+    "supervisor_trap_vector:",
+
+    "addi sp, sp, -{frame_size}",       // Make room for the trap frame.
+
+    ".irp reg, 1, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, \
+     25, 26, 27, 28, 29, 30, 31",
+    "sd x\\reg, \\reg * 8(sp)",         // Save every register but sp.
+    ".endr",
+
+    "addi t0, sp, {frame_size}",        // Save the interrupted code's sp as well, for the sake of
+    "sd t0, 2 * 8(sp)",                 //  anyone reading the frame.
+
+    "mv a0, sp",                        // supervisor_trap_handler(frame)
+    "call supervisor_trap_handler",
+
+    ".irp reg, 1, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, \
+     25, 26, 27, 28, 29, 30, 31",
+    "ld x\\reg, \\reg * 8(sp)",         // Restore the registers.
+    ".endr",
+
+    "addi sp, sp, {frame_size}",        // Pop the frame and return to the interrupted code.
+    "sret",
+
+    ".popsection",
+
+    frame_size = const TRAP_FRAME_REGISTERS * 8
+);
+
+
+
+unsafe extern "C"
+{
+    fn supervisor_trap_vector();
+}
+
+
+
+/// Point this hart's trap vector at our handler. Each hart needs to do this for itself.
+pub fn install_trap_vector()
+{
+    write_stvec(supervisor_trap_vector as *const () as u64);
+}
+
+
+
+/// Allow interrupts to be taken on this hart.
+pub fn enable_interrupts()
+{
+    set_sstatus_bits(SSTATUS_SIE);
+}
+
+
+
+/// Stop interrupts from being taken on this hart. Returns whether they were enabled, so that they
+/// can be put back the way they were with restore_interrupts.
+pub fn disable_interrupts() -> bool
+{
+    let were_enabled = read_sstatus() & SSTATUS_SIE != 0;
+
+    clear_sstatus_bits(SSTATUS_SIE);
+
+    were_enabled
+}
+
+
+
+/// Put the interrupts back the way they were before disable_interrupts.
+pub fn restore_interrupts(were_enabled: bool)
+{
+    if were_enabled
+    {
+        enable_interrupts();
+    }
+}
+
+
+
+/// Run a function with the current hart's interrupts disabled, putting them back the way they were
+/// afterwards. Any lock that is also taken by an interrupt handler must be held this way, or the
+/// handler could interrupt the lock's holder and deadlock the hart.
+pub fn with_interrupts_disabled<Func, Value>(function: Func) -> Value
+    where
+        Func: FnOnce() -> Value
+{
+    let were_enabled = disable_interrupts();
+    let result = function();
+
+    restore_interrupts(were_enabled);
+
+    result
+}
+
+
+
+/// Handle a trap, called by the trap vector with the registers of the interrupted code. Interrupts
+/// stay disabled for as long as we're in here.
+#[unsafe(no_mangle)]
+extern "C" fn supervisor_trap_handler(frame: &mut TrapFrame)
+{
+    let cause = read_scause();
+
+    if cause & SCAUSE_INTERRUPT != 0
+    {
+        match cause & !SCAUSE_INTERRUPT
+        {
+            CAUSE_SUPERVISOR_SOFTWARE_INTERRUPT => handle_ipi(),
+            CAUSE_SUPERVISOR_TIMER_INTERRUPT    => handle_timer_interrupt(),
+            CAUSE_SUPERVISOR_EXTERNAL_INTERRUPT => handle_external_interrupt(),
+
+            interrupt => panic!("Unexpected interrupt {} at 0x{:016x}.", interrupt, read_sepc())
+        }
+
+        return;
+    }
+
+    panic!("Unhandled exception {} at 0x{:016x}, stval 0x{:016x}, sp 0x{:016x}.",
+           cause,
+           read_sepc(),
+           read_stval(),
+           frame.registers[2]);
+}
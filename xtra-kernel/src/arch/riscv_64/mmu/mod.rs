@@ -1,7 +1,7 @@
 
 /// Module for the RISC-V 64-bit Memory Management Unit (MMU).
 
-use core::mem::size_of;
+use core::{ arch::asm, mem::size_of };
 
 
 
@@ -20,6 +20,24 @@ const _: () =
 
 
 
+/// Flush any TLB entries this hart holds for a virtual address, in every address space. The other
+/// harts have their own TLBs, see crate::ipi::shootdown_tlb.
+pub fn flush_tlb_page(virtual_address: usize)
+{
+    unsafe
+    {
+        asm!
+        (
+            "sfence.vma {0}, zero",
+
+            in(reg) virtual_address,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+
+
 /// This module provides the implementation of the MMU for the RISC-V 64-bit architecture using the
 /// SV39 page table format. It defines the page table entry structure and the constants used for
 /// managing the page table entries.
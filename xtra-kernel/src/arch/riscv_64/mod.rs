@@ -2,13 +2,18 @@
 // The base of the RISC-V 64-bit architecture module. All of the architecture specific code is
 // included here and in it's sub-modules.
 //
-// This module contains all the architecture specific code for the RISC-V 64-bit architecture.
+// This module contains all the architecture specific code for the RISC-V 64-bit architecture. The
+// kernel runs in supervisor mode on top of an SBI firmware, which does the machine mode work for
+// us.
 
 
 
 /// All of the RISC-V CSR register access functions.
 pub mod csr;
 
+/// Calls into the SBI firmware the kernel runs on top of.
+pub mod sbi;
+
 /// The hardware level MMU support for RISC-V 64-bit.
 pub mod mmu;
 
@@ -17,23 +22,133 @@ pub mod interrupts;
 
 
 
-use crate::{ arch::csr::{ read_marchid, read_mhartid, read_mimpid, read_mvendorid },
+use core::arch::asm;
+
+use crate::{ arch::csr::{ clear_sip_bits,
+                          set_sie_bits,
+                          INTERRUPT_SEI,
+                          INTERRUPT_SSI,
+                          INTERRUPT_STI },
+             arch::sbi::{ implementation_id,
+                          implementation_name,
+                          implementation_version,
+                          machine_architecture_id,
+                          machine_implementation_id,
+                          machine_vendor_id,
+                          probe_extension,
+                          spec_version,
+                          EXTENSION_HSM,
+                          EXTENSION_IPI,
+                          EXTENSION_RFENCE,
+                          EXTENSION_SRST,
+                          EXTENSION_TIME },
              print, println };
 
 
 
+/// Get the ID of the hart we're running on. The mhartid CSR can only be read from machine mode, so
+/// _start keeps the hart ID the firmware handed us in the thread pointer register, which the kernel
+/// never uses for anything else.
+pub fn read_hart_id() -> usize
+{
+    let hart_id: usize;
+
+    unsafe
+    {
+        asm!
+        (
+            "mv {0}, tp",
+
+            out(reg) hart_id,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    hart_id
+}
+
+
+
+/// Let the software interrupts sent by the other harts through the SBI reach this hart. They're
+/// taken as traps once interrupts are enabled in sstatus, until then they only wake the hart from
+/// wait_for_interrupt.
+pub fn enable_software_interrupt()
+{
+    set_sie_bits(INTERRUPT_SSI);
+}
+
+
+
+/// Let this hart's timer interrupt reach it. The timer is programmed through the SBI.
+pub fn enable_timer_interrupt()
+{
+    set_sie_bits(INTERRUPT_STI);
+}
+
+
+
+/// Let the interrupts routed to this hart by the platform's interrupt controller reach it.
+pub fn enable_external_interrupt()
+{
+    set_sie_bits(INTERRUPT_SEI);
+}
+
+
+
+/// Clear the software interrupt pending on this hart, so that the next one can wake it again.
+pub fn clear_software_interrupt()
+{
+    clear_sip_bits(INTERRUPT_SSI);
+}
+
+
+
+/// Put the hart to sleep until an interrupt enabled in sie is pending.
+pub fn wait_for_interrupt()
+{
+    unsafe
+    {
+        asm!("wfi", options(nomem, nostack, preserves_flags));
+    }
+}
+
+
+
 /// Print out information about the running CPU architecture.
 pub fn print_cpu_info()
 {
-    let vendor_id = read_mvendorid();
-    let arch_id   = read_marchid();
-    let imp_id    = read_mimpid();
-    let hart_id   = read_mhartid();
+    let vendor_id = machine_vendor_id();
+    let arch_id   = machine_architecture_id();
+    let imp_id    = machine_implementation_id();
+    let hart_id   = read_hart_id();
+
+    let (sbi_major, sbi_minor) = spec_version();
+    let sbi_id = implementation_id();
 
     println!("RISC-V CPU Information:");
     println!("  Vendor ID:         0x{:x}", vendor_id);
     println!("  Arch ID:           0x{:x}", arch_id);
     println!("  Implementation ID: 0x{:x}", imp_id);
     println!("  Hart ID:           {:02}",  hart_id);
+    println!("  SBI version:       {}.{}", sbi_major, sbi_minor);
+    println!("  SBI firmware:      {}, version 0x{:x}",
+             implementation_name(sbi_id),
+             implementation_version());
+
+    print!("  SBI extensions:    BASE");
+
+    for (extension, name) in [ (EXTENSION_TIME, "TIME"),
+                               (EXTENSION_IPI, "IPI"),
+                               (EXTENSION_RFENCE, "RFENCE"),
+                               (EXTENSION_HSM, "HSM"),
+                               (EXTENSION_SRST, "SRST") ]
+    {
+        if probe_extension(extension)
+        {
+            print!(" {}", name);
+        }
+    }
+
+    println!();
     println!();
 }
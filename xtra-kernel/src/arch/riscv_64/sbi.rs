@@ -0,0 +1,434 @@
+// Calls down into the supervisor binary interface, (SBI,) firmware that the kernel runs on top of.
+// The kernel runs in supervisor mode, so anything that needs machine mode, such as programming
+// the timer, sending inter-processor interrupts, starting harts or resetting the system, is asked
+// of the firmware instead.
+//
+// On QEMU the firmware is our own bootloader, but we only rely on the standard extensions so that
+// the kernel also runs on top of other SBI implementations such as OpenSBI.
+
+use core::{ arch::naked_asm, fmt::{ self, Display, Formatter } };
+
+
+
+/// The base extension, always present.
+pub const EXTENSION_BASE: usize = 0x10;
+
+/// The timer extension.
+pub const EXTENSION_TIME: usize = 0x5449_4D45;
+
+/// The inter-processor interrupt extension.
+pub const EXTENSION_IPI: usize = 0x0073_5049;
+
+/// The remote fence extension.
+pub const EXTENSION_RFENCE: usize = 0x5246_4E43;
+
+/// The hart state management extension.
+pub const EXTENSION_HSM: usize = 0x0048_534D;
+
+/// The system reset extension.
+pub const EXTENSION_SRST: usize = 0x5352_5354;
+
+
+
+/// Passing this as the hart mask base applies a call to every hart in the system.
+pub const HART_MASK_ALL: usize = usize::MAX;
+
+
+
+// Functions of the base extension.
+const BASE_GET_SPEC_VERSION: usize = 0;
+const BASE_GET_IMPL_ID:      usize = 1;
+const BASE_GET_IMPL_VERSION: usize = 2;
+const BASE_PROBE_EXTENSION:  usize = 3;
+const BASE_GET_MVENDORID:    usize = 4;
+const BASE_GET_MARCHID:      usize = 5;
+const BASE_GET_MIMPID:       usize = 6;
+
+// Functions of the TIME, IPI and RFENCE extensions.
+const TIME_SET_TIMER:                usize = 0;
+const IPI_SEND_IPI:                  usize = 0;
+const RFENCE_REMOTE_FENCE_I:         usize = 0;
+const RFENCE_REMOTE_SFENCE_VMA:      usize = 1;
+const RFENCE_REMOTE_SFENCE_VMA_ASID: usize = 2;
+
+// Functions of the HSM and SRST extensions.
+const HSM_HART_START:      usize = 0;
+const HSM_HART_STOP:       usize = 1;
+const HSM_HART_GET_STATUS: usize = 2;
+const SRST_SYSTEM_RESET:   usize = 0;
+
+
+
+/// The errors an SBI call can return.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SbiError
+{
+    Failed,
+    NotSupported,
+    InvalidParameter,
+    Denied,
+    InvalidAddress,
+    AlreadyAvailable,
+    AlreadyStarted,
+    AlreadyStopped,
+
+    /// An error code that isn't in the version of the specification we know about.
+    Unknown(isize)
+}
+
+
+
+impl SbiError
+{
+    /// Convert the error code returned by the firmware.
+    fn from_code(code: isize) -> Self
+    {
+        match code
+        {
+            -1 => SbiError::Failed,
+            -2 => SbiError::NotSupported,
+            -3 => SbiError::InvalidParameter,
+            -4 => SbiError::Denied,
+            -5 => SbiError::InvalidAddress,
+            -6 => SbiError::AlreadyAvailable,
+            -7 => SbiError::AlreadyStarted,
+            -8 => SbiError::AlreadyStopped,
+            _  => SbiError::Unknown(code)
+        }
+    }
+}
+
+
+
+impl Display for SbiError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            SbiError::Failed           => write!(f, "The SBI call failed"),
+            SbiError::NotSupported     => write!(f, "The SBI call is not supported"),
+            SbiError::InvalidParameter => write!(f, "Invalid parameter passed to the SBI call"),
+            SbiError::Denied           => write!(f, "The SBI call was denied"),
+            SbiError::InvalidAddress   => write!(f, "Invalid address passed to the SBI call"),
+            SbiError::AlreadyAvailable => write!(f, "The hart is already available"),
+            SbiError::AlreadyStarted   => write!(f, "The hart has already been started"),
+            SbiError::AlreadyStopped   => write!(f, "The hart has already been stopped"),
+            SbiError::Unknown(code)    => write!(f, "Unknown SBI error {}", code)
+        }
+    }
+}
+
+
+
+/// The state of a hart, as reported by the hart state management extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HartStatus
+{
+    Started,
+    Stopped,
+    StartPending,
+    StopPending,
+    Suspended,
+    SuspendPending,
+    ResumePending,
+
+    /// A state that isn't in the version of the specification we know about.
+    Unknown(usize)
+}
+
+
+
+impl HartStatus
+{
+    /// Convert the state returned by the firmware.
+    fn from_value(value: usize) -> Self
+    {
+        match value
+        {
+            0 => HartStatus::Started,
+            1 => HartStatus::Stopped,
+            2 => HartStatus::StartPending,
+            3 => HartStatus::StopPending,
+            4 => HartStatus::Suspended,
+            5 => HartStatus::SuspendPending,
+            6 => HartStatus::ResumePending,
+            _ => HartStatus::Unknown(value)
+        }
+    }
+}
+
+
+
+/// The kinds of system reset we can ask the firmware for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(usize)]
+pub enum ResetType
+{
+    Shutdown = 0,
+    ColdReboot = 1,
+    WarmReboot = 2
+}
+
+
+
+/// Why we're asking the firmware to reset the system.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(usize)]
+pub enum ResetReason
+{
+    NoReason = 0,
+    SystemFailure = 1
+}
+
+
+
+/// The pair of values every SBI call returns in a0 and a1.
+#[repr(C)]
+struct SbiReturn
+{
+    error: isize,
+    value: usize
+}
+
+
+
+/// Make an SBI call. The calling convention puts the arguments in a0 to a5, the function ID in a6
+/// and the extension ID in a7, which is exactly where the C calling convention puts the parameters
+/// of this function. The result comes back in a0 and a1, the same as returning the two word
+/// structure. So all we need to do is the ecall itself.
+#[unsafe(naked)]
+unsafe extern "C" fn sbi_ecall(argument_0: usize,
+                               argument_1: usize,
+                               argument_2: usize,
+                               argument_3: usize,
+                               argument_4: usize,
+                               argument_5: usize,
+                               function: usize,
+                               extension: usize) -> SbiReturn
+{
+    naked_asm!
+    (
+        "ecall",
+        "ret"
+    );
+}
+
+
+
+/// Make an SBI call with up to five arguments and convert its result.
+fn sbi_call(extension: usize, function: usize, arguments: [usize; 5]) -> Result<usize, SbiError>
+{
+    let result = unsafe
+        {
+            sbi_ecall(arguments[0],
+                      arguments[1],
+                      arguments[2],
+                      arguments[3],
+                      arguments[4],
+                      0,
+                      function,
+                      extension)
+        };
+
+    if result.error == 0
+    {
+        Ok(result.value)
+    }
+    else
+    {
+        Err(SbiError::from_code(result.error))
+    }
+}
+
+
+
+// ---- Base Extension -----------------------------------------------------------------------------
+
+/// Get the version of the SBI specification the firmware implements, as its major and minor
+/// version.
+pub fn spec_version() -> (usize, usize)
+{
+    let version = sbi_call(EXTENSION_BASE, BASE_GET_SPEC_VERSION, [0; 5]).unwrap_or(0);
+
+    ((version >> 24) & 0x7f, version & 0xff_ffff)
+}
+
+
+
+/// Get the ID of the firmware's SBI implementation.
+pub fn implementation_id() -> usize
+{
+    sbi_call(EXTENSION_BASE, BASE_GET_IMPL_ID, [0; 5]).unwrap_or(usize::MAX)
+}
+
+
+
+/// Get the version of the firmware's SBI implementation, its meaning is up to the implementation.
+pub fn implementation_version() -> usize
+{
+    sbi_call(EXTENSION_BASE, BASE_GET_IMPL_VERSION, [0; 5]).unwrap_or(0)
+}
+
+
+
+/// Get the name of an SBI implementation from its ID.
+pub fn implementation_name(id: usize) -> &'static str
+{
+    match id
+    {
+        0           => "Berkeley Boot Loader",
+        1           => "OpenSBI",
+        2           => "Xvisor",
+        3           => "KVM",
+        4           => "RustSBI",
+        5           => "Diosix",
+        6           => "Coffer",
+        7           => "Xen Project",
+        8           => "PolarFire Hart Software Services",
+        0x5854_5241 => "XTRA-OS Bootloader",
+        _           => "Unknown"
+    }
+}
+
+
+
+/// Does the firmware implement the given extension?
+pub fn probe_extension(extension: usize) -> bool
+{
+    sbi_call(EXTENSION_BASE, BASE_PROBE_EXTENSION, [extension, 0, 0, 0, 0]).unwrap_or(0) != 0
+}
+
+
+
+/// Get the machine vendor ID of the CPU, the mvendorid CSR is only readable from machine mode.
+pub fn machine_vendor_id() -> usize
+{
+    sbi_call(EXTENSION_BASE, BASE_GET_MVENDORID, [0; 5]).unwrap_or(0)
+}
+
+
+
+/// Get the machine architecture ID of the CPU.
+pub fn machine_architecture_id() -> usize
+{
+    sbi_call(EXTENSION_BASE, BASE_GET_MARCHID, [0; 5]).unwrap_or(0)
+}
+
+
+
+/// Get the machine implementation ID of the CPU.
+pub fn machine_implementation_id() -> usize
+{
+    sbi_call(EXTENSION_BASE, BASE_GET_MIMPID, [0; 5]).unwrap_or(0)
+}
+
+
+
+// ---- Timer Extension ----------------------------------------------------------------------------
+
+/// Program this hart's timer to raise a supervisor timer interrupt once the time CSR reaches the
+/// given value. This also clears the pending timer interrupt, if any.
+pub fn set_timer(time: u64) -> Result<(), SbiError>
+{
+    sbi_call(EXTENSION_TIME, TIME_SET_TIMER, [time as usize, 0, 0, 0, 0]).map(|_| ())
+}
+
+
+
+// ---- IPI Extension ------------------------------------------------------------------------------
+
+/// Raise a supervisor software interrupt on the harts in the mask. Bit N of the mask is the hart
+/// hart_mask_base + N, or pass HART_MASK_ALL as the base to interrupt every hart.
+pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> Result<(), SbiError>
+{
+    sbi_call(EXTENSION_IPI, IPI_SEND_IPI, [hart_mask, hart_mask_base, 0, 0, 0]).map(|_| ())
+}
+
+
+
+// ---- RFENCE Extension ---------------------------------------------------------------------------
+
+/// Have the harts in the mask flush their instruction caches.
+pub fn remote_fence_i(hart_mask: usize, hart_mask_base: usize) -> Result<(), SbiError>
+{
+    sbi_call(EXTENSION_RFENCE,
+             RFENCE_REMOTE_FENCE_I,
+             [hart_mask, hart_mask_base, 0, 0, 0]).map(|_| ())
+}
+
+
+
+/// Have the harts in the mask flush the TLB entries for a range of virtual addresses.
+pub fn remote_sfence_vma(hart_mask: usize,
+                         hart_mask_base: usize,
+                         start_address: usize,
+                         size: usize) -> Result<(), SbiError>
+{
+    sbi_call(EXTENSION_RFENCE,
+             RFENCE_REMOTE_SFENCE_VMA,
+             [hart_mask, hart_mask_base, start_address, size, 0]).map(|_| ())
+}
+
+
+
+/// Have the harts in the mask flush the TLB entries for a range of virtual addresses in the given
+/// address space.
+pub fn remote_sfence_vma_asid(hart_mask: usize,
+                              hart_mask_base: usize,
+                              start_address: usize,
+                              size: usize,
+                              asid: usize) -> Result<(), SbiError>
+{
+    sbi_call(EXTENSION_RFENCE,
+             RFENCE_REMOTE_SFENCE_VMA_ASID,
+             [hart_mask, hart_mask_base, start_address, size, asid]).map(|_| ())
+}
+
+
+
+// ---- Hart State Management Extension ------------------------------------------------------------
+
+/// Start a stopped hart running in supervisor mode at the given physical address. The hart starts
+/// with its ID in a0 and the opaque value in a1.
+pub fn hart_start(hart_id: usize, start_address: usize, opaque: usize) -> Result<(), SbiError>
+{
+    sbi_call(EXTENSION_HSM, HSM_HART_START, [hart_id, start_address, opaque, 0, 0]).map(|_| ())
+}
+
+
+
+/// Stop the calling hart and return it to the firmware. Only returns if the hart couldn't be
+/// stopped.
+pub fn hart_stop() -> SbiError
+{
+    match sbi_call(EXTENSION_HSM, HSM_HART_STOP, [0; 5])
+    {
+        Ok(_)      => SbiError::Failed,
+        Err(error) => error
+    }
+}
+
+
+
+/// Get the current state of a hart.
+pub fn hart_get_status(hart_id: usize) -> Result<HartStatus, SbiError>
+{
+    sbi_call(EXTENSION_HSM, HSM_HART_GET_STATUS, [hart_id, 0, 0, 0, 0]).map(HartStatus::from_value)
+}
+
+
+
+// ---- System Reset Extension ---------------------------------------------------------------------
+
+/// Reset or shut down the system. Only returns if the firmware couldn't do it.
+pub fn system_reset(reset_type: ResetType, reason: ResetReason) -> SbiError
+{
+    match sbi_call(EXTENSION_SRST,
+                   SRST_SYSTEM_RESET,
+                   [reset_type as usize, reason as usize, 0, 0, 0])
+    {
+        Ok(_)      => SbiError::Failed,
+        Err(error) => error
+    }
+}
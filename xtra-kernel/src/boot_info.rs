@@ -0,0 +1,95 @@
+// The information the bootloader hands over to the kernel. The bootloader's copy lives in memory
+// that the kernel reuses for its heap, so the boot hart copies it into the kernel's own memory as
+// the very first thing it does. From then on the rest of the kernel reads it from here.
+//
+// This is also where we report back to the bootloader that the boot succeeded, so that it doesn't
+// fall back to another kernel the next time the system starts.
+
+use xtra_kernel_shared::{ boot_info::XtraBootInfo,
+                          boot_state::{ XtraBootState, XtraBootStatus, XTRA_BOOT_STATE_SIZE } };
+
+use crate::devices::block_devices::find_disk_by_signature;
+
+
+
+/// Our copy of the boot information. It's written once by the boot hart before any other code can
+/// read it and never changes after that.
+static mut BOOT_INFO: Option<XtraBootInfo> = None;
+
+
+
+/// Copy the boot information out of the bootloader's memory, making sure that it was written by a
+/// bootloader that agrees with us on the layout of the structure.
+pub fn init_boot_info(boot_info_ptr: *const XtraBootInfo) -> Result<(), &'static str>
+{
+    if boot_info_ptr.is_null()
+    {
+        return Err("The bootloader did not pass any boot information.");
+    }
+
+    let boot_info = unsafe { boot_info_ptr.read() };
+
+    boot_info.validate()?;
+
+    unsafe
+    {
+        BOOT_INFO = Some(boot_info);
+    }
+
+    Ok(())
+}
+
+
+
+/// Get the boot information handed to us by the bootloader.
+pub fn get_boot_info() -> &'static XtraBootInfo
+{
+    unsafe
+    {
+        (*(&raw const BOOT_INFO)).as_ref().expect("Boot information not initialized.")
+    }
+}
+
+
+
+/// The wall clock time the kernel was started at, in seconds since the Unix epoch. This is 0 if the
+/// bootloader couldn't find a real time clock.
+pub fn boot_time() -> u64
+{
+    get_boot_info().boot_time / 1_000_000_000
+}
+
+
+
+/// Record in the boot state that the kernel came up successfully, so that the bootloader boots the
+/// same entry next time instead of its fallback. Returns false if the bootloader didn't give us a
+/// boot state to update.
+///
+/// The boot state is written as a raw sector, so the boot disk is found by its disk signature
+/// rather than by its index. If that doesn't pick out exactly one disk nothing is written.
+pub fn mark_boot_successful() -> Result<bool, &'static str>
+{
+    let boot_info = get_boot_info();
+
+    let Some(sector) = boot_info.boot_state_sector()
+    else
+    {
+        return Ok(false);
+    };
+
+    let signature = boot_info.boot_disk_signature()
+                             .ok_or("The boot disk has no disk signature to find it by.")?;
+    let device = find_disk_by_signature(signature)?.device();
+
+    let mut state = XtraBootState::new();
+    let mut buffer = [0u8; XTRA_BOOT_STATE_SIZE];
+
+    state.status = XtraBootStatus::Successful;
+    state.set_entry(boot_info.boot_entry_str())?;
+    state.to_sector(&mut buffer);
+
+    device.write_sectors(sector, &buffer)?;
+    device.flush()?;
+
+    Ok(true)
+}
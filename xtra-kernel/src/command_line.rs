@@ -0,0 +1,484 @@
+// Parsing of the kernel command line passed to us by the bootloader.
+//
+// The command line is a whitespace separated list of parameters, each either a bare flag such as
+// `ro` or a `name=value` pair. Values holding spaces can be quoted, for example
+// `init="/bin/sh -l"`.
+//
+// The parameters the core of the kernel needs during early boot, before the heap is available, are
+// parsed straight away into a `KernelOptions` structure:
+//
+//     loglevel=<0-7>     How much to print, the boot messages are printed at level 6 so anything
+//                        lower hides them. Panics are always printed.
+//     console=ttyS<n>    Which serial port to use for the kernel's output.
+//     root=<device>      The root filesystem, as PARTUUID=<uuid>, LABEL=<label> or
+//                        disk:<n>,pt:<m>.
+//     ro, rw             Mount the root filesystem read-only or read/write.
+//     init=<path>        The program to run as the first process.
+//     maxcpus=<n>        The number of harts to bring up.
+//
+// Any other subsystem can declare its own parameters by registering them in the kernel parameter
+// registry, they are handed their values once the heap is up and the registry has been built.
+
+use core::fmt::{ self, Display, Formatter };
+
+use alloc::collections::BTreeMap;
+
+use crate::{ boot_info::get_boot_info, devices::hid_devices, filesystems, net };
+
+
+
+/// The default log level, everything the kernel prints is shown.
+pub const DEFAULT_LOG_LEVEL: u8 = 7;
+
+/// The highest log level we accept.
+pub const MAX_LOG_LEVEL: u8 = 7;
+
+/// The program run as the first process if the command line doesn't name one.
+pub const DEFAULT_INIT_PATH: &str = "/bin/init";
+
+/// The number of problems with the command line that we remember to report once printing works.
+const MAX_COMMAND_LINE_PROBLEMS: usize = 8;
+
+/// The parameters handled by `KernelOptions`, these are skipped when handing out parameters to the
+/// registry.
+const EARLY_PARAMETERS: [&str; 7] =
+    [ "loglevel", "console", "root", "ro", "rw", "init", "maxcpus" ];
+
+
+
+/// How the root filesystem is selected on the command line.
+#[derive(Clone, Copy)]
+pub enum RootDevice
+{
+    /// The partition with the given partition UUID.
+    PartitionUuid(&'static str),
+
+    /// The partition holding a filesystem with the given label.
+    Label(&'static str),
+
+    /// The partition with the given index on the disk with the given index.
+    Index { disk: u8, partition: u8 }
+}
+
+
+
+impl Display for RootDevice
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            RootDevice::PartitionUuid(uuid)         => write!(formatter, "PARTUUID={}", uuid),
+            RootDevice::Label(label)                => write!(formatter, "LABEL={}", label),
+            RootDevice::Index { disk, partition }   => write!(formatter,
+                                                              "disk:{},pt:{}",
+                                                              disk,
+                                                              partition)
+        }
+    }
+}
+
+
+
+/// A parameter on the command line that couldn't be used.
+#[derive(Clone, Copy)]
+pub struct CommandLineProblem
+{
+    /// The parameter as it appeared on the command line.
+    pub parameter: &'static str,
+
+    /// What was wrong with it.
+    pub message: &'static str
+}
+
+
+
+/// The options the core of the kernel takes from the command line.
+#[derive(Clone, Copy)]
+pub struct KernelOptions
+{
+    /// How much to print, from 0 for nothing but panics to 7 for everything.
+    pub log_level: u8,
+
+    /// The index of the serial port to print to, the first one found if not given.
+    pub console: Option<usize>,
+
+    /// The root filesystem to mount instead of the one in the mount table.
+    pub root: Option<RootDevice>,
+
+    /// Mount the root filesystem read-only, or read/write, instead of as the mount table says.
+    pub root_read_only: Option<bool>,
+
+    /// The program to run as the first process.
+    pub init: &'static str,
+
+    /// The maximum number of harts to bring up, all of them if not given.
+    pub max_cpus: Option<usize>,
+
+    /// Parameters that we couldn't use, kept so that they can be reported once printing works.
+    problems: [Option<CommandLineProblem>; MAX_COMMAND_LINE_PROBLEMS]
+}
+
+
+
+impl KernelOptions
+{
+    /// Create the default options, used for anything not given on the command line.
+    pub const fn new() -> Self
+    {
+        KernelOptions
+            {
+                log_level: DEFAULT_LOG_LEVEL,
+                console: None,
+                root: None,
+                root_read_only: None,
+                init: DEFAULT_INIT_PATH,
+                max_cpus: None,
+                problems: [None; MAX_COMMAND_LINE_PROBLEMS]
+            }
+    }
+
+    /// The problems found while parsing the command line.
+    pub fn problems(&self) -> impl Iterator<Item = &CommandLineProblem>
+    {
+        self.problems.iter().flatten()
+    }
+
+    /// Remember a problem with a parameter, if there are too many the extras are dropped.
+    fn add_problem(&mut self, parameter: &'static str, message: &'static str)
+    {
+        if let Some(slot) = self.problems.iter_mut().find(|slot| slot.is_none())
+        {
+            *slot = Some(CommandLineProblem { parameter, message });
+        }
+    }
+
+    /// Apply a single parameter from the command line. Returns false if it isn't one of ours.
+    fn apply(&mut self, parameter: &'static str, name: &str, value: Option<&'static str>) -> bool
+    {
+        let result = match (name, value)
+            {
+                ("loglevel", Some(value)) =>
+                    {
+                        value.parse::<u8>()
+                             .ok()
+                             .filter(|&level| level <= MAX_LOG_LEVEL)
+                             .map(|level| self.log_level = level)
+                             .ok_or("The log level must be a number from 0 to 7.")
+                    },
+
+                ("console", Some(value)) =>
+                    {
+                        value.strip_prefix("ttyS")
+                             .and_then(|index| index.parse::<usize>().ok())
+                             .map(|index| self.console = Some(index))
+                             .ok_or("The console must be a serial port, ttyS0, ttyS1 and so on.")
+                    },
+
+                ("root", Some(value)) =>
+                    {
+                        parse_root_device(value).map(|root| self.root = Some(root))
+                    },
+
+                ("ro", None) =>
+                    {
+                        self.root_read_only = Some(true);
+                        Ok(())
+                    },
+
+                ("rw", None) =>
+                    {
+                        self.root_read_only = Some(false);
+                        Ok(())
+                    },
+
+                ("init", Some(value)) if value.starts_with('/') =>
+                    {
+                        self.init = value;
+                        Ok(())
+                    },
+
+                ("init", Some(_)) =>
+                    {
+                        Err("The init program must be given as an absolute path.")
+                    },
+
+                ("maxcpus", Some(value)) =>
+                    {
+                        value.parse::<usize>()
+                             .ok()
+                             .filter(|&count| count > 0)
+                             .map(|count| self.max_cpus = Some(count))
+                             .ok_or("The number of CPUs must be a number greater than 0.")
+                    },
+
+                ("ro", Some(_)) | ("rw", Some(_)) =>
+                    {
+                        Err("The parameter is a flag and doesn't take a value.")
+                    },
+
+                (name, None) if EARLY_PARAMETERS.contains(&name) =>
+                    {
+                        Err("The parameter needs a value.")
+                    },
+
+                _ => return false
+            };
+
+        if let Err(message) = result
+        {
+            self.add_problem(parameter, message);
+        }
+
+        true
+    }
+}
+
+
+
+impl Display for KernelOptions
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        write!(formatter, "loglevel={} init={}", self.log_level, self.init)?;
+
+        if let Some(console) = self.console
+        {
+            write!(formatter, " console=ttyS{}", console)?;
+        }
+
+        if let Some(root) = &self.root
+        {
+            write!(formatter, " root={}", root)?;
+        }
+
+        if let Some(read_only) = self.root_read_only
+        {
+            write!(formatter, " {}", if read_only { "ro" } else { "rw" })?;
+        }
+
+        if let Some(max_cpus) = self.max_cpus
+        {
+            write!(formatter, " maxcpus={}", max_cpus)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+
+/// Type for the function that gets called with the value of a kernel parameter, `None` for flags
+/// given without a value.
+pub type ParameterHandler = fn(value: Option<&'static str>) -> Result<(), &'static str>;
+
+
+
+/// A parameter a subsystem accepts on the kernel command line.
+#[derive(Clone, Copy)]
+pub struct KernelParameter
+{
+    /// A short description of the parameter for error messages and documentation.
+    pub description: &'static str,
+
+    /// The function that applies the parameter's value.
+    pub handler: ParameterHandler
+}
+
+
+
+/// The kernel parameter registry type, a mapping from parameter names to the parameters.
+pub type KernelParameterRegistry = BTreeMap<&'static str, KernelParameter>;
+
+
+
+/// The options parsed from the command line. They are written once by the boot hart, before any
+/// other code can read them, and never change after that.
+static mut KERNEL_OPTIONS: KernelOptions = KernelOptions::new();
+
+
+
+/// Split the command line into its parameters. Parameters are separated by whitespace, except for
+/// whitespace within double quotes.
+fn parameters(command_line: &'static str) -> impl Iterator<Item = &'static str>
+{
+    let mut remaining = command_line;
+
+    core::iter::from_fn(move ||
+        {
+            remaining = remaining.trim_start();
+
+            if remaining.is_empty()
+            {
+                return None;
+            }
+
+            let mut in_quotes = false;
+            let end = remaining.char_indices()
+                               .find(|&(_, character)|
+                                   {
+                                       if character == '"'
+                                       {
+                                           in_quotes = !in_quotes;
+                                       }
+
+                                       !in_quotes && character.is_whitespace()
+                                   })
+                               .map_or(remaining.len(), |(index, _)| index);
+
+            let parameter = &remaining[..end];
+
+            remaining = &remaining[end..];
+
+            Some(parameter)
+        })
+}
+
+
+
+/// Split a parameter into its name and value, removing any quotes from around the value.
+fn split_parameter(parameter: &'static str) -> (&'static str, Option<&'static str>)
+{
+    match parameter.split_once('=')
+    {
+        Some((name, value)) =>
+            {
+                let value = value.strip_prefix('"')
+                                 .and_then(|value| value.strip_suffix('"'))
+                                 .unwrap_or(value);
+
+                (name, Some(value))
+            },
+
+        None => (parameter, None)
+    }
+}
+
+
+
+/// Parse the value of the root parameter.
+fn parse_root_device(value: &'static str) -> Result<RootDevice, &'static str>
+{
+    if let Some(uuid) = value.strip_prefix("PARTUUID=")
+    {
+        return Ok(RootDevice::PartitionUuid(uuid));
+    }
+
+    if let Some(label) = value.strip_prefix("LABEL=")
+    {
+        return Ok(RootDevice::Label(label));
+    }
+
+    let index = value.split_once(',')
+                     .and_then(|(disk, partition)|
+                         {
+                             Some((disk.strip_prefix("disk:")?.parse::<u8>().ok()?,
+                                   partition.strip_prefix("pt:")?.parse::<u8>().ok()?))
+                         });
+
+    match index
+    {
+        Some((disk, partition)) => Ok(RootDevice::Index { disk, partition }),
+        None                    => Err("The root device must be given as PARTUUID=<uuid>, \
+                                        LABEL=<label> or disk:<n>,pt:<m>.")
+    }
+}
+
+
+
+/// Parse the options the core of the kernel needs from the command line in the boot information.
+/// This is called during early boot, before printing or the heap are available, problems with the
+/// command line are kept and can be reported later with `report_command_line_problems`.
+pub fn init_kernel_options()
+{
+    let mut options = KernelOptions::new();
+
+    for parameter in parameters(get_boot_info().command_line_str())
+    {
+        let (name, value) = split_parameter(parameter);
+
+        options.apply(parameter, name, value);
+    }
+
+    unsafe
+    {
+        KERNEL_OPTIONS = options;
+    }
+}
+
+
+
+/// Get the options the core of the kernel takes from the command line.
+pub fn get_kernel_options() -> &'static KernelOptions
+{
+    unsafe
+    {
+        &*(&raw const KERNEL_OPTIONS)
+    }
+}
+
+
+
+/// Print out any problems found in the command line while parsing the kernel's options.
+pub fn report_command_line_problems()
+{
+    for problem in get_kernel_options().problems()
+    {
+        println!("Ignoring kernel parameter {}: {}", problem.parameter, problem.message);
+    }
+}
+
+
+
+/// Hand the parameters on the command line to the subsystems that registered them. Parameters that
+/// nobody registered are reported and ignored.
+fn apply_kernel_parameters(registry: &KernelParameterRegistry)
+{
+    for parameter in parameters(get_boot_info().command_line_str())
+    {
+        let (name, value) = split_parameter(parameter);
+
+        if EARLY_PARAMETERS.contains(&name)
+        {
+            continue;
+        }
+
+        match registry.get(name)
+        {
+            Some(kernel_parameter) =>
+                {
+                    if let Err(message) = (kernel_parameter.handler)(value)
+                    {
+                        println!("Ignoring kernel parameter {} ({}): {}",
+                                 parameter,
+                                 kernel_parameter.description,
+                                 message);
+                    }
+                },
+
+            None =>
+                {
+                    println!("Ignoring unknown kernel parameter {}.", parameter);
+                }
+        }
+    }
+}
+
+
+
+/// Build the kernel parameter registry from the parameters declared by the kernel's subsystems and
+/// hand each of them the values given on the command line.
+pub fn initialize_kernel_parameters() -> Result<KernelParameterRegistry, &'static str>
+{
+    let mut registry = KernelParameterRegistry::new();
+
+    // Go through all of the subsystems that take parameters and let them register them. The order
+    // of registration here does not matter.
+    filesystems::register_kernel_parameters(&mut registry)?;
+    hid_devices::register_kernel_parameters(&mut registry)?;
+    net::register_kernel_parameters(&mut registry)?;
+
+    apply_kernel_parameters(&registry);
+
+    Ok(registry)
+}
@@ -0,0 +1,417 @@
+// The block device subsystem. Block device drivers register the disks they manage here, at which
+// point the disk's partition table is read and each partition is made available as a block device
+// in its own right. The filesystems then look disks and partitions up by the indices or partition
+// UUIDs used in the mount table.
+
+use core::fmt::{ self, Display, Formatter };
+
+use alloc::{ format, string::String, sync::Arc, vec::Vec };
+
+use xtra_kernel_shared::device_tree::DeviceTree;
+
+use crate::{ devices::{ bus_devices::virtio_devices::{ VirtioDriverRegistry,
+                                                      VIRTIO_BLOCK_DEVICE_ID },
+                        DeviceDriverRegistry },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// Reading of the legacy MBR partition table found on our disks.
+pub mod partition_table;
+
+/// The driver for VirtIO block devices.
+pub mod virtio_block;
+
+
+
+use crate::devices::block_devices::partition_table::{ MasterBootRecord,
+                                                      PartitionType,
+                                                      MBR_PARTITION_COUNT };
+
+
+
+/// The size of a sector for all of the block devices supported by the kernel.
+pub const SECTOR_SIZE: usize = 512;
+
+
+
+/// Results for block device operations.
+pub type BlockResult<T> = Result<T, &'static str>;
+
+
+
+/// The interface all block devices, physical or partitions of them, expose to the rest of the
+/// kernel.
+///
+/// All reads and writes are done in whole sectors, the size of the buffer given determines how many
+/// sectors are transferred and must be a multiple of `SECTOR_SIZE`.
+pub trait BlockDevice: Send + Sync
+{
+    /// The name of the device, for example vda or vda1.
+    fn name(&self) -> &str;
+
+    /// The total number of sectors on the device.
+    fn sector_count(&self) -> u64;
+
+    /// Is the device write protected?
+    fn is_read_only(&self) -> bool;
+
+    /// Read sectors from the device starting at the given sector into the buffer.
+    fn read_sectors(&self, sector: u64, buffer: &mut [u8]) -> BlockResult<()>;
+
+    /// Write the buffer out to the device starting at the given sector.
+    fn write_sectors(&self, sector: u64, buffer: &[u8]) -> BlockResult<()>;
+
+    /// Make sure that any writes cached by the device have reached stable storage.
+    fn flush(&self) -> BlockResult<()>;
+}
+
+
+
+/// Check that a transfer request is valid for a device of the given size.
+pub fn validate_transfer(sector: u64, length: usize, sector_count: u64) -> BlockResult<u64>
+{
+    if !length.is_multiple_of(SECTOR_SIZE)
+    {
+        return Err("Block transfer size is not a multiple of the sector size.");
+    }
+
+    let count = (length / SECTOR_SIZE) as u64;
+
+    if    sector >= sector_count
+       || count > sector_count - sector
+    {
+        return Err("Block transfer is beyond the end of the device.");
+    }
+
+    Ok(count)
+}
+
+
+
+/// A partition of a disk, exposed as a block device that covers just the partition's sectors.
+pub struct Partition
+{
+    /// The name of the partition, for example vda1.
+    name: String,
+
+    /// The slot of the partition in the disk's partition table.
+    index: usize,
+
+    /// The partition's UUID, for MBR disks this is the disk signature and the partition number in
+    /// the same form Linux uses, for example 1234abcd-02.
+    uuid: String,
+
+    /// The type of the partition as recorded in the partition table.
+    partition_type: PartitionType,
+
+    /// The first sector of the partition on the disk.
+    start_sector: u64,
+
+    /// The number of sectors in the partition.
+    sector_count: u64,
+
+    /// The disk the partition lives on.
+    device: Arc<dyn BlockDevice>
+}
+
+
+
+impl Partition
+{
+    /// The slot of the partition in the disk's partition table.
+    pub fn index(&self) -> usize
+    {
+        self.index
+    }
+
+    /// The partition's UUID as used by the mount table.
+    pub fn uuid(&self) -> &str
+    {
+        &self.uuid
+    }
+
+    /// The type of the partition as recorded in the partition table.
+    pub fn partition_type(&self) -> PartitionType
+    {
+        self.partition_type
+    }
+}
+
+
+
+impl BlockDevice for Partition
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64
+    {
+        self.sector_count
+    }
+
+    fn is_read_only(&self) -> bool
+    {
+        self.device.is_read_only()
+    }
+
+    fn read_sectors(&self, sector: u64, buffer: &mut [u8]) -> BlockResult<()>
+    {
+        validate_transfer(sector, buffer.len(), self.sector_count)?;
+        self.device.read_sectors(self.start_sector + sector, buffer)
+    }
+
+    fn write_sectors(&self, sector: u64, buffer: &[u8]) -> BlockResult<()>
+    {
+        validate_transfer(sector, buffer.len(), self.sector_count)?;
+        self.device.write_sectors(self.start_sector + sector, buffer)
+    }
+
+    fn flush(&self) -> BlockResult<()>
+    {
+        self.device.flush()
+    }
+}
+
+
+
+/// A disk registered with the block device subsystem, along with the partitions found on it.
+pub struct Disk
+{
+    /// The index of the disk, this is the device number used by the mount table.
+    index: usize,
+
+    /// The driver's device for the whole disk.
+    device: Arc<dyn BlockDevice>,
+
+    /// The disk signature from the disk's MBR, if it has a partition table.
+    disk_signature: Option<u32>,
+
+    /// The partitions on the disk, indexed by their slot in the partition table.
+    partitions: [Option<Arc<Partition>>; MBR_PARTITION_COUNT]
+}
+
+
+
+impl Disk
+{
+    /// The index of the disk, this is the device number used by the mount table.
+    pub fn index(&self) -> usize
+    {
+        self.index
+    }
+
+    /// The block device for the disk as a whole.
+    pub fn device(&self) -> Arc<dyn BlockDevice>
+    {
+        self.device.clone()
+    }
+
+    /// The disk signature from the disk's MBR, if it has a partition table.
+    pub fn disk_signature(&self) -> Option<u32>
+    {
+        self.disk_signature
+    }
+
+    /// Get the partition in the given slot of the disk's partition table, if there is one.
+    pub fn partition(&self, index: usize) -> Option<Arc<Partition>>
+    {
+        self.partitions.get(index).cloned().flatten()
+    }
+
+    /// Iterate over the partitions found on the disk.
+    pub fn partitions(&self) -> impl Iterator<Item = &Arc<Partition>>
+    {
+        self.partitions.iter().flatten()
+    }
+}
+
+
+
+impl Display for Disk
+{
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result
+    {
+        write!(formatter,
+               "Disk {}: {}, {} sectors{}",
+               self.index,
+               self.device.name(),
+               self.device.sector_count(),
+               if self.device.is_read_only() { ", read-only" } else { "" })?;
+
+        for partition in self.partitions.iter().flatten()
+        {
+            write!(formatter,
+                   "\n    Partition {}: {}, uuid {}, start {}, {} sectors",
+                   partition.index,
+                   partition.name,
+                   partition.uuid,
+                   partition.start_sector,
+                   partition.sector_count)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+
+/// All of the disks known to the kernel, in the order they were registered.
+static DISKS: SpinMutex<Vec<Arc<Disk>>> = SpinMutex::new(Vec::new());
+
+
+
+/// Register the device driver probes for block devices, such as hard drives, SSDs, etc.
+pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(), &'static str>
+{
+    Ok(())
+}
+
+
+/// Register the drivers for block devices that are found on the VirtIO bus.
+pub fn register_virtio_drivers(registry: &mut VirtioDriverRegistry) -> Result<(), &'static str>
+{
+    registry.insert(VIRTIO_BLOCK_DEVICE_ID, virtio_block::probe_virtio_block_device);
+
+    Ok(())
+}
+
+
+/// Activate and initialize the block devices discovered in the device tree. If any.
+pub fn activate_devices() -> Result<(), &'static str>
+{
+    Ok(())
+}
+
+
+
+/// Generate the name for the next disk to be registered, vda, vdb, etc.
+pub fn next_disk_name(prefix: &str) -> String
+{
+    let index = DISKS.lock().len();
+
+    format!("{}{}", prefix, (b'a' + (index % 26) as u8) as char)
+}
+
+
+
+/// Register a new disk with the block device subsystem. The disk's partition table is read and all
+/// of its partitions are registered along with it.
+///
+/// Disks are numbered in the order they are registered, which matches the order the bootloader
+/// finds them in the device tree.
+pub fn register_disk(device: Arc<dyn BlockDevice>) -> BlockResult<usize>
+{
+    let mut partitions: [Option<Arc<Partition>>; MBR_PARTITION_COUNT] = Default::default();
+    let mut disk_signature = None;
+    let mut sector = [0u8; SECTOR_SIZE];
+
+    device.read_sectors(0, &mut sector)?;
+
+    // A disk without a partition table is still registered, it just doesn't have any partitions.
+    match MasterBootRecord::new(&sector)
+    {
+        Ok(master_boot_record) =>
+            {
+                disk_signature = Some(master_boot_record.disk_signature());
+
+                for (index, entry) in master_boot_record.partitions().iter().enumerate()
+                {
+                    if !entry.is_usable()
+                    {
+                        continue;
+                    }
+
+                    let start_sector = entry.start_lba as u64;
+                    let sector_count = entry.size_in_sectors as u64;
+
+                    if start_sector + sector_count > device.sector_count()
+                    {
+                        println!("  Partition {} of {} extends beyond the end of the disk, \
+                                  ignoring it.",
+                                 index,
+                                 device.name());
+                        continue;
+                    }
+
+                    partitions[index] = Some(Arc::new(Partition
+                        {
+                            name: format!("{}{}", device.name(), index + 1),
+                            index,
+                            uuid: format!("{:08x}-{:02x}",
+                                          master_boot_record.disk_signature(),
+                                          index + 1),
+                            partition_type: entry.partition_type,
+                            start_sector,
+                            sector_count,
+                            device: device.clone()
+                        }));
+                }
+            },
+
+        Err(error) =>
+            {
+                println!("  {}: {}", device.name(), error);
+            }
+    }
+
+    let mut disks = DISKS.lock();
+    let index = disks.len();
+    let disk = Arc::new(Disk { index, device, disk_signature, partitions });
+
+    println!("  {}", disk);
+
+    disks.push(disk);
+
+    Ok(index)
+}
+
+
+
+/// Look up a registered disk by its index.
+pub fn get_disk(index: usize) -> Option<Arc<Disk>>
+{
+    DISKS.lock().get(index).cloned()
+}
+
+
+
+/// Look up a registered disk by the disk signature in its MBR. Fails if no disk has the signature,
+/// or if more than one does, as then there's no telling which of them is meant.
+pub fn find_disk_by_signature(signature: u32) -> BlockResult<Arc<Disk>>
+{
+    let disks = DISKS.lock();
+    let mut matches = disks.iter().filter(|disk| disk.disk_signature == Some(signature));
+
+    match (matches.next(), matches.next())
+    {
+        (Some(disk), None) => Ok(disk.clone()),
+        (None, _)          => Err("No disk has the signature."),
+        (Some(_), Some(_)) => Err("More than one disk has the signature.")
+    }
+}
+
+
+
+/// Get a snapshot of the list of registered disks.
+pub fn disks() -> Vec<Arc<Disk>>
+{
+    DISKS.lock().clone()
+}
+
+
+
+/// Flush the write caches of all of the registered disks.
+pub fn flush_all() -> BlockResult<()>
+{
+    let disks = DISKS.lock().clone();
+
+    for disk in disks
+    {
+        disk.device.flush()?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,148 @@
+// Parsing of the legacy Master Boot Record, (MBR,) partition table. This is the same format that
+// the bootloader uses to find the boot partition, the kernel uses it to split each disk into the
+// partitions that the mount table refers to.
+
+use crate::devices::block_devices::SECTOR_SIZE;
+
+
+
+const BOOT_SIGNATURE:          u16   = 0xAA55;  // Boot signature for MBR.
+
+const PARTITION_TYPE_EMPTY:    u8    = 0x00;    // Empty partition type.
+const PARTITION_TYPE_FAT32:    u8    = 0x0C;    // FAT32 partition type, (LBA.)
+const PARTITION_TYPE_EXTENDED: u8    = 0x05;    // Extended partition type.
+const PARTITION_TYPE_LINUX:    u8    = 0x83;    // Native Linux filesystem partition type.
+
+const MBR_DISK_SIGNATURE:      usize = 440;     // Offset of the disk signature in the MBR.
+const MBR_PARTITION_OFFSET:    usize = 446;     // Offset of the partition entries in the MBR.
+pub const MBR_PARTITION_COUNT: usize = 4;       // Number of partition entries in the MBR.
+const MBR_PARTITION_SIZE:      usize = 16;      // Size of each partition entry in the MBR.
+
+
+
+/// The type of a partition as recorded in the partition table.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PartitionType
+{
+    /// The partition table slot is unused.
+    Empty,
+
+    /// A FAT-32 partition.
+    Fat32,
+
+    /// An extended partition that holds more partitions, we do not support these.
+    Extended,
+
+    /// A native Linux partition, typically holding an Ext2 filesystem.
+    Linux,
+
+    /// Some other partition type.
+    Unknown(u8)
+}
+
+
+
+impl PartitionType
+{
+    fn from_byte(partition_type: u8) -> Self
+    {
+        match partition_type
+        {
+            PARTITION_TYPE_EMPTY    => PartitionType::Empty,
+            PARTITION_TYPE_FAT32    => PartitionType::Fat32,
+            PARTITION_TYPE_EXTENDED => PartitionType::Extended,
+            PARTITION_TYPE_LINUX    => PartitionType::Linux,
+            other                   => PartitionType::Unknown(other)
+        }
+    }
+}
+
+
+
+/// A single entry in the MBR's partition table.
+#[derive(Clone, Copy)]
+pub struct PartitionEntry
+{
+    pub bootable: bool,                 // Is the partition marked as active?
+    pub partition_type: PartitionType,  // Partition type identifier.
+    pub start_lba: u32,                 // Starting sector number (LBA).
+    pub size_in_sectors: u32            // Size in sectors.
+}
+
+
+
+impl PartitionEntry
+{
+    fn new(bytes: &[u8]) -> Self
+    {
+        PartitionEntry
+            {
+                bootable: bytes[0] == 0x80,
+                partition_type: PartitionType::from_byte(bytes[4]),
+                start_lba: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+                size_in_sectors: u32::from_le_bytes(bytes[12..16].try_into().unwrap())
+            }
+    }
+
+    /// Does this entry describe a partition we can use?
+    pub fn is_usable(&self) -> bool
+    {
+           self.partition_type != PartitionType::Empty
+        && self.partition_type != PartitionType::Extended
+        && self.size_in_sectors != 0
+    }
+}
+
+
+
+/// The decoded partition table of a disk.
+#[derive(Clone, Copy)]
+pub struct MasterBootRecord
+{
+    disk_signature: u32,
+    partitions: [PartitionEntry; MBR_PARTITION_COUNT]
+}
+
+
+
+impl MasterBootRecord
+{
+    /// Decode the partition table from the first sector of a disk.
+    pub fn new(bytes: &[u8; SECTOR_SIZE]) -> Result<Self, &'static str>
+    {
+        let boot_signature = u16::from_le_bytes([bytes[510], bytes[511]]);
+
+        if boot_signature != BOOT_SIGNATURE
+        {
+            return Err("Disk does not have a valid MBR partition table.");
+        }
+
+        let entry = |index: usize|
+            {
+                let offset = MBR_PARTITION_OFFSET + index * MBR_PARTITION_SIZE;
+
+                PartitionEntry::new(&bytes[offset..offset + MBR_PARTITION_SIZE])
+            };
+
+        Ok(MasterBootRecord
+            {
+                disk_signature: u32::from_le_bytes(bytes[MBR_DISK_SIGNATURE..MBR_DISK_SIGNATURE + 4]
+                                                       .try_into()
+                                                       .unwrap()),
+                partitions: [entry(0), entry(1), entry(2), entry(3)]
+            })
+    }
+
+    /// The disk signature written by the partitioning tool, used to identify the disk's partitions
+    /// independently of the order the disks are found in.
+    pub fn disk_signature(&self) -> u32
+    {
+        self.disk_signature
+    }
+
+    /// Get the partition entries of the table, indexed by their slot in the table.
+    pub fn partitions(&self) -> &[PartitionEntry; MBR_PARTITION_COUNT]
+    {
+        &self.partitions
+    }
+}
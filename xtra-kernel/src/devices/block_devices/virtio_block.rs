@@ -0,0 +1,248 @@
+// Driver for VirtIO block devices. Requests are made up of a three part descriptor chain, a header
+// describing the request, the data buffer and a status byte written back by the device.
+//
+// For now the driver works in polled mode, a request is submitted and then we spin waiting for the
+// device to hand it back. This keeps the driver usable before the interrupt controller is up.
+
+use core::{ hint::spin_loop, mem::size_of };
+
+use alloc::{ string::String, sync::Arc };
+
+use crate::{ devices::{ block_devices::{ next_disk_name,
+                                         register_disk,
+                                         validate_transfer,
+                                         BlockDevice,
+                                         BlockResult,
+                                         SECTOR_SIZE },
+                        bus_devices::virtio_devices::{ mmio::VirtioMmioDevice,
+                                                       virtqueue::{ VirtQueue,
+                                                                    VirtQueueBuffer } } },
+             locking::spin_mutex::SpinMutex };
+
+
+
+// Feature bits for block devices.
+const VIRTIO_BLK_F_RO:    u64 = 1 << 5;  // The device is read-only.
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;  // The device supports the flush command.
+
+// Request types.
+const VIRTIO_BLK_T_IN:    u32 = 0;       // Read sectors from the device.
+const VIRTIO_BLK_T_OUT:   u32 = 1;       // Write sectors to the device.
+const VIRTIO_BLK_T_FLUSH: u32 = 4;       // Flush the device's write cache.
+
+// Request status values.
+const VIRTIO_BLK_S_OK:     u8 = 0;
+const VIRTIO_BLK_S_IOERR:  u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+// Offsets into the device configuration space.
+const CONFIG_CAPACITY: usize = 0x00;     // The size of the device in 512 byte sectors.
+
+
+
+/// The size of the request queue we ask the device for.
+const REQUEST_QUEUE_SIZE: u16 = 16;
+
+/// The largest number of sectors we will transfer in a single request.
+const MAX_SECTORS_PER_REQUEST: usize = 128;
+
+
+
+/// The header that starts every block request.
+#[repr(C)]
+struct RequestHeader
+{
+    request_type: u32,  // Request type, (read, write or flush.)
+    reserved: u32,      // Reserved by the specification.
+    sector: u64         // Sector number to read/write.
+}
+
+
+
+/// A VirtIO block device.
+pub struct VirtioBlockDevice
+{
+    /// The name of the disk, vda, vdb, etc.
+    name: String,
+
+    /// The device's register interface.
+    device: VirtioMmioDevice,
+
+    /// The device's single request queue.
+    queue: SpinMutex<VirtQueue>,
+
+    /// The size of the device in sectors.
+    sector_count: u64,
+
+    /// The device is write protected.
+    read_only: bool,
+
+    /// The device has a write cache that can be flushed.
+    supports_flush: bool
+}
+
+
+
+impl VirtioBlockDevice
+{
+    /// Perform the VirtIO initialization handshake for the block device and get it ready to accept
+    /// requests.
+    pub fn new(name: String, device: VirtioMmioDevice) -> Result<Self, &'static str>
+    {
+        device.begin_initialization();
+
+        let features = device.negotiate_features(VIRTIO_BLK_F_RO | VIRTIO_BLK_F_FLUSH)?;
+        let queue = device.setup_queue(0, REQUEST_QUEUE_SIZE)?;
+
+        device.finish_initialization();
+
+        Ok(VirtioBlockDevice
+            {
+                name,
+                device,
+                queue: SpinMutex::new(queue),
+                sector_count: device.config_read_u64(CONFIG_CAPACITY),
+                read_only: features & VIRTIO_BLK_F_RO != 0,
+                supports_flush: features & VIRTIO_BLK_F_FLUSH != 0
+            })
+    }
+
+    /// Submit a request to the device and wait for it to complete.
+    fn submit(&self,
+              request_type: u32,
+              sector: u64,
+              data: Option<VirtQueueBuffer>) -> BlockResult<()>
+    {
+        let header = RequestHeader { request_type, reserved: 0, sector };
+        let mut status: u8 = 0xFF;
+
+        let header_buffer = VirtQueueBuffer
+            {
+                address: &header as *const RequestHeader as usize,
+                length: size_of::<RequestHeader>(),
+                device_writable: false
+            };
+
+        let status_buffer = VirtQueueBuffer
+            {
+                address: &mut status as *mut u8 as usize,
+                length: 1,
+                device_writable: true
+            };
+
+        {
+            let mut queue = self.queue.lock();
+
+            let head = match data
+                {
+                    Some(data) => queue.add_buffers(&[header_buffer, data, status_buffer])?,
+                    None       => queue.add_buffers(&[header_buffer, status_buffer])?
+                };
+
+            self.device.notify_queue(queue.index());
+
+            // Only one request is in flight at a time as we hold the queue lock, so the next used
+            // buffer is ours.
+            loop
+            {
+                if let Some(used) = queue.pop_used()
+                {
+                    if used.head != head
+                    {
+                        return Err("VirtIO block device completed an unexpected request.");
+                    }
+
+                    break;
+                }
+
+                spin_loop();
+            }
+
+            self.device.acknowledge_interrupt();
+        }
+
+        match unsafe { core::ptr::read_volatile(&status) }
+        {
+            VIRTIO_BLK_S_OK     => Ok(()),
+            VIRTIO_BLK_S_IOERR  => Err("VirtIO block device reported an I/O error."),
+            VIRTIO_BLK_S_UNSUPP => Err("VirtIO block device does not support the request."),
+            _                   => Err("VirtIO block device returned an unknown status.")
+        }
+    }
+}
+
+
+
+impl BlockDevice for VirtioBlockDevice
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64
+    {
+        self.sector_count
+    }
+
+    fn is_read_only(&self) -> bool
+    {
+        self.read_only
+    }
+
+    fn read_sectors(&self, sector: u64, buffer: &mut [u8]) -> BlockResult<()>
+    {
+        validate_transfer(sector, buffer.len(), self.sector_count)?;
+
+        for (index, chunk) in buffer.chunks_mut(MAX_SECTORS_PER_REQUEST * SECTOR_SIZE).enumerate()
+        {
+            let chunk_sector = sector + (index * MAX_SECTORS_PER_REQUEST) as u64;
+
+            self.submit(VIRTIO_BLK_T_IN, chunk_sector, Some(VirtQueueBuffer::writable(chunk)))?;
+        }
+
+        Ok(())
+    }
+
+    fn write_sectors(&self, sector: u64, buffer: &[u8]) -> BlockResult<()>
+    {
+        if self.read_only
+        {
+            return Err("VirtIO block device is read-only.");
+        }
+
+        validate_transfer(sector, buffer.len(), self.sector_count)?;
+
+        for (index, chunk) in buffer.chunks(MAX_SECTORS_PER_REQUEST * SECTOR_SIZE).enumerate()
+        {
+            let chunk_sector = sector + (index * MAX_SECTORS_PER_REQUEST) as u64;
+
+            self.submit(VIRTIO_BLK_T_OUT, chunk_sector, Some(VirtQueueBuffer::readable(chunk)))?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&self) -> BlockResult<()>
+    {
+        if !self.supports_flush
+        {
+            return Ok(());
+        }
+
+        self.submit(VIRTIO_BLK_T_FLUSH, 0, None)
+    }
+}
+
+
+
+/// Called by the VirtIO bus when it finds a block device. The device is initialized and registered
+/// as a new disk.
+pub fn probe_virtio_block_device(device: VirtioMmioDevice) -> Result<(), &'static str>
+{
+    let block_device = VirtioBlockDevice::new(next_disk_name("vd"), device)?;
+
+    register_disk(Arc::new(block_device))?;
+
+    Ok(())
+}
@@ -14,7 +14,13 @@ use alloc::collections::BTreeMap;
 
 use xtra_kernel_shared::device_tree::DeviceTree;
 
-use crate::devices::DeviceDriverRegistry;
+use crate::devices::{ block_devices,
+                      bus_devices::virtio_devices::VirtioDriverRegistry,
+                      entropy_devices,
+                      graphics_devices,
+                      hid_devices,
+                      network_devices,
+                      DeviceDriverRegistry };
 
 
 
@@ -34,7 +40,8 @@ pub mod virtio_devices;
 pub struct BusDeviceRegistry
 {
     pci_drivers: BTreeMap<usize, &'static str>,
-    usb_drivers: BTreeMap<usize, &'static str>
+    usb_drivers: BTreeMap<usize, &'static str>,
+    virtio_drivers: VirtioDriverRegistry
 }
 
 
@@ -42,6 +49,8 @@ pub struct BusDeviceRegistry
 /// Register the bus device driver probes for bus devices, such as the PCI bus, the USB bus, etc.
 pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(), &'static str>
 {
+    virtio_devices::register_driver_probes(registry)?;
+
     Ok(())
 }
 
@@ -52,10 +61,22 @@ pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(),
 /// discovered in the device tree.
 pub fn activate_devices() -> Result<BusDeviceRegistry, &'static str>
 {
+    virtio_devices::activate_devices()?;
+
+    // Collect the drivers for the device types that can show up on the VirtIO bus.
+    let mut virtio_drivers = VirtioDriverRegistry::new();
+
+    block_devices::register_virtio_drivers(&mut virtio_drivers)?;
+    entropy_devices::register_virtio_drivers(&mut virtio_drivers)?;
+    graphics_devices::register_virtio_drivers(&mut virtio_drivers)?;
+    hid_devices::register_virtio_drivers(&mut virtio_drivers)?;
+    network_devices::register_virtio_drivers(&mut virtio_drivers)?;
+
     Ok(BusDeviceRegistry
         {
             pci_drivers: BTreeMap::new(),
             usb_drivers: BTreeMap::new(),
+            virtio_drivers
         })
 }
 
@@ -65,6 +86,9 @@ pub fn activate_devices() -> Result<BusDeviceRegistry, &'static str>
 /// for the devices that are attached to each of the busses.
 pub fn enumerate_bus_devices(bus_device_registry: BusDeviceRegistry) -> Result<(), &'static str>
 {
+    // Attach the drivers for the devices found on the VirtIO bus.
+    virtio_devices::attach_drivers(&bus_device_registry.virtio_drivers)?;
+
     // Give ownership of the USB device driver registry to the USB bus subsystem so that it can
     // manage device attachment on demand.
 
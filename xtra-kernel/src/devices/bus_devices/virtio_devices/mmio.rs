@@ -0,0 +1,349 @@
+// The VirtIO MMIO transport. Every VirtIO device in the system is exposed through a small window of
+// memory mapped registers, this module wraps those registers and implements the common parts of the
+// device initialization handshake so that the individual device drivers only need to deal with
+// their device specific configuration and queues.
+//
+// We only support the modern, (version 2,) register layout. QEMU is told to not use the legacy
+// layout in run.sh.
+
+use core::{ ptr::{ read_volatile, write_volatile },
+            sync::atomic::{ fence, Ordering } };
+
+use crate::devices::bus_devices::virtio_devices::virtqueue::VirtQueue;
+
+
+
+// VirtIO MMIO device register offsets, (all u32, little-endian.)
+const MAGIC_VALUE:         usize = 0x000;  // 0x74726976 ("virt".)
+const VERSION:             usize = 0x004;  // Device version (1 or 2.)
+const DEVICE_ID:           usize = 0x008;  // Device type (2 = block, 1 = net, etc.)
+const VENDOR_ID:           usize = 0x00C;  // Vendor ID ("QEMU" = 0x554D4551.)
+const DEVICE_FEATURES:     usize = 0x010;  // Device feature bits.
+const DEVICE_FEATURES_SEL: usize = 0x014;  // Selects which 32 feature bits to read.
+const DRIVER_FEATURES:     usize = 0x020;  // Driver feature bits.
+const DRIVER_FEATURES_SEL: usize = 0x024;  // Selects which 32 feature bits to write.
+const QUEUE_SEL:           usize = 0x030;  // Select which queue to access.
+const QUEUE_NUM_MAX:       usize = 0x034;  // Max size of selected queue.
+const QUEUE_NUM:           usize = 0x038;  // Queue size, (<= max.)
+const QUEUE_READY:         usize = 0x044;  // Set to 1 to activate the queue.
+const QUEUE_NOTIFY:        usize = 0x050;  // Notify device that queue has work.
+const INTERRUPT_STATUS:    usize = 0x060;  // IRQ status bits.
+const INTERRUPT_ACK:       usize = 0x064;  // Acknowledge IRQ.
+const STATUS:              usize = 0x070;  // Device/driver status bits.
+const QUEUE_DESC_LOW:      usize = 0x080;  // [31:0] physical address of descriptor table.
+const QUEUE_DESC_HIGH:     usize = 0x084;  // [63:32] physical address of descriptor table.
+const QUEUE_AVAIL_LOW:     usize = 0x090;  // [31:0] physical address of available ring.
+const QUEUE_AVAIL_HIGH:    usize = 0x094;  // [63:32] physical address of available ring.
+const QUEUE_USED_LOW:      usize = 0x0A0;  // [31:0] physical address of used ring.
+const QUEUE_USED_HIGH:     usize = 0x0A4;  // [63:32] physical address of used ring.
+const CONFIG_GENERATION:   usize = 0x0FC;  // Incremented on config change.
+const DEVICE_CONFIG:       usize = 0x100;  // Device specific configuration space.
+
+
+
+/// The magic value found at the start of every VirtIO MMIO register window.
+pub const VIRTIO_MMIO_MAGIC: u32 = 0x74726976;
+
+/// The only register layout version we support.
+pub const VIRTIO_MMIO_MODERN_VERSION: u32 = 2;
+
+
+
+// Device status bits.
+const STATUS_ACKNOWLEDGE: u32 = 0x01;
+const STATUS_DRIVER:      u32 = 0x02;
+const STATUS_DRIVER_OK:   u32 = 0x04;
+const STATUS_FEATURES_OK: u32 = 0x08;
+const STATUS_FAILED:      u32 = 0x80;
+
+
+
+/// Feature bit that every modern device must offer and every modern driver must accept.
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+
+
+/// Interrupt status bit indicating that a queue has been updated by the device.
+pub const VIRTIO_INTERRUPT_USED_BUFFER: u32 = 0x01;
+
+/// Interrupt status bit indicating that the device's configuration space has changed.
+pub const VIRTIO_INTERRUPT_CONFIG_CHANGE: u32 = 0x02;
+
+
+
+/// A VirtIO device found on the MMIO bus. This is a cheap handle to the device's register window
+/// and can be freely copied around.
+#[derive(Clone, Copy)]
+pub struct VirtioMmioDevice
+{
+    /// The base address of the device's register window.
+    base_address: usize,
+
+    /// The size of the register window as reported by the device tree.
+    size: usize,
+
+    /// The interrupt line the device is wired to on the platform's interrupt controller.
+    interrupt: Option<u32>,
+
+    /// The type of device that is attached to this slot.
+    device_id: u32
+}
+
+
+
+impl VirtioMmioDevice
+{
+    /// Create a handle for the VirtIO register window at the given address. This will validate the
+    /// register window and fail if there isn't a VirtIO device to be found there.
+    pub fn new(base_address: usize,
+               size: usize,
+               interrupt: Option<u32>) -> Result<Self, &'static str>
+    {
+        let mut device = VirtioMmioDevice
+            {
+                base_address,
+                size,
+                interrupt,
+                device_id: 0
+            };
+
+        if device.read_register(MAGIC_VALUE) != VIRTIO_MMIO_MAGIC
+        {
+            return Err("Invalid VirtIO MMIO magic value.");
+        }
+
+        if device.read_register(VERSION) != VIRTIO_MMIO_MODERN_VERSION
+        {
+            return Err("Unsupported VirtIO MMIO version, only modern devices are supported.");
+        }
+
+        device.device_id = device.read_register(DEVICE_ID);
+
+        Ok(device)
+    }
+
+    /// The base address of the device's register window.
+    pub fn base_address(&self) -> usize
+    {
+        self.base_address
+    }
+
+    /// The interrupt line for the device, if one was described in the device tree.
+    pub fn interrupt(&self) -> Option<u32>
+    {
+        self.interrupt
+    }
+
+    /// The VirtIO device type, a value of 0 means that the slot is empty.
+    pub fn device_id(&self) -> u32
+    {
+        self.device_id
+    }
+
+    /// The vendor of the device.
+    pub fn vendor_id(&self) -> u32
+    {
+        self.read_register(VENDOR_ID)
+    }
+
+    /// Reset the device and perform the first half of the initialization handshake. Once this
+    /// returns the driver can negotiate features with the device.
+    pub fn begin_initialization(&self)
+    {
+        // Writing 0 to the status register resets the device.
+        self.write_register(STATUS, 0);
+        fence(Ordering::SeqCst);
+
+        self.add_status(STATUS_ACKNOWLEDGE);
+        self.add_status(STATUS_DRIVER);
+    }
+
+    /// Negotiate the feature set with the device. The driver passes in the features it knows how to
+    /// handle and we return the features that both sides agreed to.
+    pub fn negotiate_features(&self, driver_features: u64) -> Result<u64, &'static str>
+    {
+        let device_features = self.device_features();
+
+        if device_features & VIRTIO_F_VERSION_1 == 0
+        {
+            self.fail();
+            return Err("VirtIO device does not support the modern interface.");
+        }
+
+        let features = device_features & (driver_features | VIRTIO_F_VERSION_1);
+
+        self.write_register(DRIVER_FEATURES_SEL, 0);
+        self.write_register(DRIVER_FEATURES, features as u32);
+        self.write_register(DRIVER_FEATURES_SEL, 1);
+        self.write_register(DRIVER_FEATURES, (features >> 32) as u32);
+
+        self.add_status(STATUS_FEATURES_OK);
+
+        // The device gets the final say, if it doesn't like the features it will clear the
+        // FEATURES_OK bit.
+        if self.read_register(STATUS) & STATUS_FEATURES_OK == 0
+        {
+            self.fail();
+            return Err("VirtIO device rejected the negotiated feature set.");
+        }
+
+        Ok(features)
+    }
+
+    /// Create and register a virtqueue with the device. The queue will be the requested size or the
+    /// maximum the device supports, which ever is smaller.
+    pub fn setup_queue(&self, index: u16, requested_size: u16) -> Result<VirtQueue, &'static str>
+    {
+        self.write_register(QUEUE_SEL, index as u32);
+        fence(Ordering::SeqCst);
+
+        if self.read_register(QUEUE_READY) != 0
+        {
+            return Err("VirtIO queue is already in use.");
+        }
+
+        let max_size = self.read_register(QUEUE_NUM_MAX);
+
+        if max_size == 0
+        {
+            return Err("VirtIO queue is not available on the device.");
+        }
+
+        // Queue sizes must be a power of 2.
+        let size = (requested_size as u32).min(max_size);
+        let size = if size.is_power_of_two() { size } else { size.next_power_of_two() >> 1 };
+
+        let queue = VirtQueue::new(index, size as u16)?;
+
+        self.write_register(QUEUE_NUM, size);
+
+        let (descriptors, available, used) = queue.physical_addresses();
+
+        self.write_register(QUEUE_DESC_LOW, descriptors as u32);
+        self.write_register(QUEUE_DESC_HIGH, (descriptors >> 32) as u32);
+        self.write_register(QUEUE_AVAIL_LOW, available as u32);
+        self.write_register(QUEUE_AVAIL_HIGH, (available >> 32) as u32);
+        self.write_register(QUEUE_USED_LOW, used as u32);
+        self.write_register(QUEUE_USED_HIGH, (used >> 32) as u32);
+
+        fence(Ordering::SeqCst);
+        self.write_register(QUEUE_READY, 1);
+
+        Ok(queue)
+    }
+
+    /// Complete the initialization handshake, after this the device is live.
+    pub fn finish_initialization(&self)
+    {
+        self.add_status(STATUS_DRIVER_OK);
+    }
+
+    /// Let the device know that the driver has given up on it.
+    pub fn fail(&self)
+    {
+        self.add_status(STATUS_FAILED);
+    }
+
+    /// Reset the device, stopping all of its queues.
+    pub fn reset(&self)
+    {
+        self.write_register(STATUS, 0);
+    }
+
+    /// Let the device know that there are new buffers available in the given queue.
+    pub fn notify_queue(&self, index: u16)
+    {
+        fence(Ordering::Release);
+        self.write_register(QUEUE_NOTIFY, index as u32);
+    }
+
+    /// Read and acknowledge the device's pending interrupt status bits.
+    pub fn acknowledge_interrupt(&self) -> u32
+    {
+        let status = self.read_register(INTERRUPT_STATUS);
+
+        if status != 0
+        {
+            self.write_register(INTERRUPT_ACK, status);
+        }
+
+        status
+    }
+
+    /// Read a byte from the device specific configuration space.
+    pub fn config_read_u8(&self, offset: usize) -> u8
+    {
+        unsafe { read_volatile((self.base_address + DEVICE_CONFIG + offset) as *const u8) }
+    }
+
+    /// Read a 16-bit value from the device specific configuration space.
+    pub fn config_read_u16(&self, offset: usize) -> u16
+    {
+        unsafe { read_volatile((self.base_address + DEVICE_CONFIG + offset) as *const u16) }
+    }
+
+    /// Read a 32-bit value from the device specific configuration space.
+    pub fn config_read_u32(&self, offset: usize) -> u32
+    {
+        unsafe { read_volatile((self.base_address + DEVICE_CONFIG + offset) as *const u32) }
+    }
+
+    /// Read a 64-bit value from the device specific configuration space. The value is read as two
+    /// halves so we use the configuration generation to make sure we didn't get a torn read.
+    pub fn config_read_u64(&self, offset: usize) -> u64
+    {
+        loop
+        {
+            let generation = self.read_register(CONFIG_GENERATION);
+
+            let low = self.config_read_u32(offset) as u64;
+            let high = self.config_read_u32(offset + 4) as u64;
+
+            if generation == self.read_register(CONFIG_GENERATION)
+            {
+                return (high << 32) | low;
+            }
+        }
+    }
+
+    /// Write a byte into the device specific configuration space.
+    pub fn config_write_u8(&self, offset: usize, value: u8)
+    {
+        unsafe { write_volatile((self.base_address + DEVICE_CONFIG + offset) as *mut u8, value) }
+    }
+
+    /// Write a 32-bit value into the device specific configuration space.
+    pub fn config_write_u32(&self, offset: usize, value: u32)
+    {
+        unsafe { write_volatile((self.base_address + DEVICE_CONFIG + offset) as *mut u32, value) }
+    }
+
+    /// Read the full 64 bits of the device's offered features.
+    fn device_features(&self) -> u64
+    {
+        self.write_register(DEVICE_FEATURES_SEL, 0);
+        let low = self.read_register(DEVICE_FEATURES) as u64;
+
+        self.write_register(DEVICE_FEATURES_SEL, 1);
+        let high = self.read_register(DEVICE_FEATURES) as u64;
+
+        (high << 32) | low
+    }
+
+    /// Set additional bits in the device status register.
+    fn add_status(&self, bits: u32)
+    {
+        let status = self.read_register(STATUS);
+        self.write_register(STATUS, status | bits);
+    }
+
+    fn read_register(&self, offset: usize) -> u32
+    {
+        unsafe { read_volatile((self.base_address + offset) as *const u32) }
+    }
+
+    fn write_register(&self, offset: usize, value: u32)
+    {
+        unsafe { write_volatile((self.base_address + offset) as *mut u32, value) }
+    }
+}
@@ -0,0 +1,169 @@
+// VirtIO devices are discovered as simple memory mapped register windows in the device tree. Each
+// window may or may not have a device attached to it, so we probe all of the windows and keep track
+// of the populated ones. Once the bus subsystem is activated the populated windows are handed off
+// to the VirtIO driver registered for the type of device found in the window.
+
+use alloc::{ collections::BTreeMap, vec::Vec };
+
+use xtra_kernel_shared::device_tree::DeviceTree;
+
+use crate::{ devices::DeviceDriverRegistry,
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// The VirtIO MMIO register interface and the common device initialization handshake.
+pub mod mmio;
+
+/// The split virtqueue implementation shared by all of the VirtIO device drivers.
+pub mod virtqueue;
+
+
+
+use crate::devices::bus_devices::virtio_devices::mmio::VirtioMmioDevice;
+
+
+
+/// VirtIO device type for network cards.
+pub const VIRTIO_NETWORK_DEVICE_ID: u32 = 1;
+
+/// VirtIO device type for block devices.
+pub const VIRTIO_BLOCK_DEVICE_ID: u32 = 2;
+
+/// VirtIO device type for the entropy source.
+pub const VIRTIO_ENTROPY_DEVICE_ID: u32 = 4;
+
+/// VirtIO device type for GPUs.
+pub const VIRTIO_GPU_DEVICE_ID: u32 = 16;
+
+/// VirtIO device type for input devices such as keyboards and mice.
+pub const VIRTIO_INPUT_DEVICE_ID: u32 = 18;
+
+
+
+/// The function called to hand a discovered VirtIO device to its driver.
+pub type VirtioDriverProbeFunction = fn(device: VirtioMmioDevice) -> Result<(), &'static str>;
+
+
+
+/// Mapping of VirtIO device types to the driver that can handle them.
+pub type VirtioDriverRegistry = BTreeMap<u32, VirtioDriverProbeFunction>;
+
+
+
+/// All of the populated VirtIO register windows found in the device tree.
+static DISCOVERED_DEVICES: SpinMutex<Vec<VirtioMmioDevice>> = SpinMutex::new(Vec::new());
+
+
+
+/// Register the driver probe functions for all of the virtio device interfaces in the system.
+pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(), &'static str>
+{
+    registry.insert("virtio_mmio", probe_virtio_mmio);
+
+    Ok(())
+}
+
+
+/// Activate and initialize the virtio devices discovered in the device tree. If any.
+///
+/// All of the devices are reset so that they are in a known state before their drivers get to
+/// them.
+pub fn activate_devices() -> Result<(), &'static str>
+{
+    for device in DISCOVERED_DEVICES.lock().iter()
+    {
+        device.reset();
+    }
+
+    Ok(())
+}
+
+
+
+/// Hand each of the discovered VirtIO devices to the driver registered for its device type.
+///
+/// A driver failing to initialize its device is reported but is not fatal, the rest of the devices
+/// still get their chance.
+pub fn attach_drivers(registry: &VirtioDriverRegistry) -> Result<(), &'static str>
+{
+    // Take a copy of the list so that drivers are free to look at the bus while they initialize.
+    let devices = DISCOVERED_DEVICES.lock().clone();
+
+    for device in devices
+    {
+        match registry.get(&device.device_id())
+        {
+            Some(probe_function) =>
+                {
+                    if let Err(error) = probe_function(device)
+                    {
+                        println!("  Failed to initialize VirtIO device {} at {:#x}: {}",
+                                 device.device_id(),
+                                 device.base_address(),
+                                 error);
+                    }
+                },
+
+            None =>
+                {
+                    println!("  No driver for VirtIO device {} at {:#x}.",
+                             device.device_id(),
+                             device.base_address());
+                }
+        }
+    }
+
+    Ok(())
+}
+
+
+
+/// Probe a virtio_mmio node in the device tree. Most of the MMIO slots QEMU provides are empty, we
+/// only keep track of the ones that actually have a device attached.
+fn probe_virtio_mmio(name: &str,
+                     address: Option<usize>,
+                     device_tree: &DeviceTree,
+                     block_offset: usize) -> Result<(), &'static str>
+{
+    let mut base_address = address;
+    let mut size = 0;
+    let mut interrupt = None;
+
+    device_tree.iterate_properties(block_offset, |property_name, property_value|
+        {
+            match property_name
+            {
+                "reg" if property_value.len() >= 16 =>
+                    {
+                        base_address = Some(u64::from_be_bytes(property_value[0..8]
+                                                                   .try_into()
+                                                                   .unwrap()) as usize);
+                        size = u64::from_be_bytes(property_value[8..16].try_into().unwrap())
+                            as usize;
+                    },
+
+                "interrupts" if property_value.len() >= 4 =>
+                    {
+                        interrupt = Some(u32::from_be_bytes(property_value[0..4]
+                                                                .try_into()
+                                                                .unwrap()));
+                    },
+
+                _ => {}
+            }
+
+            true
+        });
+
+    let base_address = base_address.ok_or("VirtIO MMIO node is missing its register address.")?;
+    let device = VirtioMmioDevice::new(base_address, size, interrupt)?;
+
+    // A device ID of 0 means that nothing is attached to this slot.
+    if device.device_id() != 0
+    {
+        DISCOVERED_DEVICES.lock().push(device);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,380 @@
+// Implementation of the VirtIO split virtqueue. A virtqueue is made up of three parts that are
+// shared with the device, a table of buffer descriptors, a ring of descriptor chains made available
+// to the device by the driver and a ring of descriptor chains the device has finished with.
+//
+// The queue memory is allocated from the kernel heap, which is identity mapped, so that the same
+// addresses work for both the CPU and the device.
+
+use core::{ alloc::Layout,
+            ptr::{ read_volatile, write_volatile },
+            sync::atomic::{ fence, Ordering } };
+
+use alloc::alloc::{ alloc_zeroed, dealloc };
+
+use crate::memory::{ mmu::physical_address_of, PAGE_SIZE };
+
+
+
+/// Descriptor flag marking that the chain continues in the descriptor pointed to by `next`.
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+
+/// Descriptor flag marking that the buffer is written to by the device instead of read from.
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+
+
+/// The in memory layout of a single buffer descriptor as shared with the device.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor
+{
+    address: u64,  // Physical address of the buffer.
+    length: u32,   // Length of the buffer in bytes.
+    flags: u16,    // VIRTQ_DESC_F_* flags.
+    next: u16      // Next descriptor in the chain if VIRTQ_DESC_F_NEXT is set.
+}
+
+
+
+/// A buffer to be handed to the device as part of a descriptor chain.
+#[derive(Clone, Copy)]
+pub struct VirtQueueBuffer
+{
+    /// The kernel address of the buffer, it will be translated to a physical address for the
+    /// device.
+    pub address: usize,
+
+    /// The size of the buffer in bytes.
+    pub length: usize,
+
+    /// Is the device going to write into this buffer? Otherwise the device will read from it.
+    pub device_writable: bool
+}
+
+
+
+impl VirtQueueBuffer
+{
+    /// Create a buffer descriptor for a buffer the device will read from.
+    pub fn readable(buffer: &[u8]) -> Self
+    {
+        VirtQueueBuffer
+            {
+                address: buffer.as_ptr() as usize,
+                length: buffer.len(),
+                device_writable: false
+            }
+    }
+
+    /// Create a buffer descriptor for a buffer the device will write into.
+    pub fn writable(buffer: &mut [u8]) -> Self
+    {
+        VirtQueueBuffer
+            {
+                address: buffer.as_mut_ptr() as usize,
+                length: buffer.len(),
+                device_writable: true
+            }
+    }
+}
+
+
+
+/// Information about a descriptor chain that the device has finished processing.
+#[derive(Clone, Copy)]
+pub struct UsedBuffer
+{
+    /// The head descriptor of the chain, as returned by `add_buffers`.
+    pub head: u16,
+
+    /// The number of bytes the device wrote into the chain's writable buffers.
+    pub length: u32
+}
+
+
+
+/// A split virtqueue shared between the driver and a VirtIO device.
+pub struct VirtQueue
+{
+    /// The index of this queue on its device.
+    index: u16,
+
+    /// The number of descriptors in the queue.
+    size: u16,
+
+    /// The memory backing the queue and its layout.
+    memory: *mut u8,
+    layout: Layout,
+
+    /// Offsets of the three parts of the queue within the memory block.
+    available_offset: usize,
+    used_offset: usize,
+
+    /// Head of the list of free descriptors and how many are on it.
+    free_head: u16,
+    free_count: u16,
+
+    /// The next available ring index we will write to.
+    next_available: u16,
+
+    /// The last used ring index we have processed.
+    last_used: u16,
+
+    /// For each descriptor chain head, the number of descriptors in that chain.
+    chain_lengths: [u16; Self::MAX_SIZE]
+}
+
+
+
+/// The queue is only ever touched through a lock owned by the driver that created it.
+unsafe impl Send for VirtQueue {}
+
+
+
+impl VirtQueue
+{
+    /// The largest queue we are willing to create.
+    pub const MAX_SIZE: usize = 256;
+
+    /// Allocate the memory for a new queue of the given size. The size must be a power of two.
+    pub fn new(index: u16, size: u16) -> Result<Self, &'static str>
+    {
+        if    size == 0
+           || !size.is_power_of_two()
+           || size as usize > Self::MAX_SIZE
+        {
+            return Err("Invalid VirtIO queue size.");
+        }
+
+        let count = size as usize;
+
+        // Descriptors must be 16 byte aligned, the available ring 2 and the used ring 4.
+        let descriptors_size = 16 * count;
+        let available_offset = descriptors_size;
+        let available_size = 6 + 2 * count;
+        let used_offset = (available_offset + available_size + 3) & !3;
+        let used_size = 6 + 8 * count;
+
+        let layout = Layout::from_size_align(used_offset + used_size, PAGE_SIZE)
+            .map_err(|_| "Invalid VirtIO queue layout.")?;
+
+        let memory = unsafe { alloc_zeroed(layout) };
+
+        if memory.is_null()
+        {
+            return Err("Failed to allocate memory for VirtIO queue.");
+        }
+
+        let mut queue = VirtQueue
+            {
+                index,
+                size,
+                memory,
+                layout,
+                available_offset,
+                used_offset,
+                free_head: 0,
+                free_count: size,
+                next_available: 0,
+                last_used: 0,
+                chain_lengths: [0; Self::MAX_SIZE]
+            };
+
+        // Link all of the descriptors into the free list.
+        for descriptor_index in 0..size
+        {
+            let descriptor = queue.descriptor(descriptor_index);
+
+            descriptor.next = (descriptor_index + 1) % size;
+        }
+
+        Ok(queue)
+    }
+
+    /// The index of the queue on its device.
+    pub fn index(&self) -> u16
+    {
+        self.index
+    }
+
+    /// The number of descriptors in the queue.
+    pub fn size(&self) -> u16
+    {
+        self.size
+    }
+
+    /// How many descriptors are currently free for use.
+    pub fn free_descriptors(&self) -> u16
+    {
+        self.free_count
+    }
+
+    /// The physical addresses of the descriptor table, available ring and used ring.
+    pub fn physical_addresses(&self) -> (usize, usize, usize)
+    {
+        let base = physical_address_of(self.memory as usize);
+
+        (base, base + self.available_offset, base + self.used_offset)
+    }
+
+    /// Place a chain of buffers into the queue and make it available to the device. The device is
+    /// not notified, it is up to the caller to do that once it has queued all of its work.
+    ///
+    /// Returns the head descriptor of the chain which will be reported back when the device is
+    /// done with it.
+    pub fn add_buffers(&mut self, buffers: &[VirtQueueBuffer]) -> Result<u16, &'static str>
+    {
+        if buffers.is_empty()
+        {
+            return Err("Can not add an empty buffer chain to a VirtIO queue.");
+        }
+
+        if buffers.len() > self.free_count as usize
+        {
+            return Err("Not enough free descriptors in VirtIO queue.");
+        }
+
+        let head = self.free_head;
+        let mut current = head;
+
+        for (buffer_index, buffer) in buffers.iter().enumerate()
+        {
+            let is_last = buffer_index + 1 == buffers.len();
+            let next_free = self.descriptor(current).next;
+
+            let mut flags = if buffer.device_writable { VIRTQ_DESC_F_WRITE } else { 0 };
+
+            if !is_last
+            {
+                flags |= VIRTQ_DESC_F_NEXT;
+            }
+
+            let descriptor = self.descriptor(current);
+
+            descriptor.address = physical_address_of(buffer.address) as u64;
+            descriptor.length = buffer.length as u32;
+            descriptor.flags = flags;
+            descriptor.next = next_free;
+
+            if !is_last
+            {
+                current = next_free;
+            }
+            else
+            {
+                self.free_head = next_free;
+            }
+        }
+
+        self.free_count -= buffers.len() as u16;
+        self.chain_lengths[head as usize] = buffers.len() as u16;
+
+        // Publish the chain in the available ring, then make sure the ring entry is visible before
+        // the index update.
+        let slot = self.next_available % self.size;
+
+        unsafe
+        {
+            write_volatile(self.available_ring_entry(slot), head);
+        }
+
+        fence(Ordering::SeqCst);
+
+        self.next_available = self.next_available.wrapping_add(1);
+
+        unsafe
+        {
+            write_volatile(self.available_index(), self.next_available);
+        }
+
+        fence(Ordering::SeqCst);
+
+        Ok(head)
+    }
+
+    /// Check if the device has finished with any descriptor chains.
+    pub fn has_used_buffers(&self) -> bool
+    {
+        fence(Ordering::SeqCst);
+        self.last_used != unsafe { read_volatile(self.used_index()) }
+    }
+
+    /// Take the next completed descriptor chain from the used ring, returning its descriptors to
+    /// the free list.
+    pub fn pop_used(&mut self) -> Option<UsedBuffer>
+    {
+        if !self.has_used_buffers()
+        {
+            return None;
+        }
+
+        let slot = self.last_used % self.size;
+        let (id, length) = unsafe
+            {
+                let entry = self.used_ring_entry(slot);
+
+                (read_volatile(entry), read_volatile(entry.add(1)))
+            };
+
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let head = id as u16;
+        self.free_chain(head);
+
+        Some(UsedBuffer { head, length })
+    }
+
+    /// Return the descriptors of a chain back to the free list.
+    fn free_chain(&mut self, head: u16)
+    {
+        let count = self.chain_lengths[head as usize];
+        let mut last = head;
+
+        for _ in 1..count
+        {
+            last = self.descriptor(last).next;
+        }
+
+        let free_head = self.free_head;
+
+        self.descriptor(last).next = free_head;
+        self.free_head = head;
+        self.free_count += count;
+        self.chain_lengths[head as usize] = 0;
+    }
+
+    fn descriptor(&mut self, index: u16) -> &mut Descriptor
+    {
+        unsafe { &mut *(self.memory as *mut Descriptor).add(index as usize) }
+    }
+
+    fn available_index(&self) -> *mut u16
+    {
+        unsafe { (self.memory.add(self.available_offset) as *mut u16).add(1) }
+    }
+
+    fn available_ring_entry(&self, slot: u16) -> *mut u16
+    {
+        unsafe { (self.memory.add(self.available_offset) as *mut u16).add(2 + slot as usize) }
+    }
+
+    fn used_index(&self) -> *const u16
+    {
+        unsafe { (self.memory.add(self.used_offset) as *const u16).add(1) }
+    }
+
+    fn used_ring_entry(&self, slot: u16) -> *const u32
+    {
+        unsafe { (self.memory.add(self.used_offset + 4) as *const u32).add(2 * slot as usize) }
+    }
+}
+
+
+
+impl Drop for VirtQueue
+{
+    fn drop(&mut self)
+    {
+        unsafe { dealloc(self.memory, self.layout) };
+    }
+}
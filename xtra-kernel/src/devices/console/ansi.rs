@@ -0,0 +1,374 @@
+// A parser for the output sent to a terminal, the UTF-8 text and the ANSI, (VT100 and later,)
+// escape sequences mixed into it. The parser is fed a byte at a time and says what each complete
+// character, control code or escape sequence is. It's up to the terminal to act on them.
+//
+// Only the parts of the standard that consoles actually use are recognized, the control strings,
+// (DCS, OSC and the like,) are skipped over rather than parsed.
+
+/// The most parameters kept for a control sequence, any more are ignored.
+pub const MAX_CSI_PARAMETERS: usize = 16;
+
+
+
+/// ESC, which starts every escape sequence.
+pub const ESCAPE: u8 = 0x1b;
+
+// Codes that cancel a sequence in progress.
+const CANCEL:     u8 = 0x18;
+const SUBSTITUTE: u8 = 0x1a;
+
+// Codes that end a control string.
+const BELL:              u8 = 0x07;
+const STRING_TERMINATOR: u8 = b'\\';
+
+
+
+/// A complete piece of a terminal's output.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AnsiAction
+{
+    /// A character to show.
+    Print(char),
+
+    /// A C0 control code, like a newline or backspace.
+    Control(u8),
+
+    /// An escape sequence made up of just ESC and one more byte, like ESC 7.
+    Escape(u8),
+
+    /// A control sequence, ESC [ followed by parameters and a final byte.
+    ControlSequence(ControlSequence)
+}
+
+
+
+/// A control sequence, like ESC [ 1 ; 31 m.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ControlSequence
+{
+    /// The numeric parameters, only the first parameter_count are used.
+    parameters: [u16; MAX_CSI_PARAMETERS],
+    parameter_count: usize,
+
+    /// The private marker, one of < = > ? before the parameters, 0 if there isn't one.
+    pub private_marker: u8,
+
+    /// The byte ending the sequence, which says what the sequence does.
+    pub final_byte: u8
+}
+
+
+
+impl ControlSequence
+{
+    /// The sequence's parameters.
+    pub fn parameters(&self) -> &[u16]
+    {
+        &self.parameters[..self.parameter_count]
+    }
+
+    /// Get a parameter, using the default if it was left out or given as 0.
+    pub fn parameter(&self, index: usize, default: u16) -> u16
+    {
+        match self.parameters().get(index)
+        {
+            Some(&value) if value != 0 => value,
+            _                          => default
+        }
+    }
+}
+
+
+
+/// Where the parser is in the output.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ParserState
+{
+    /// Between sequences, bytes are text.
+    Ground,
+
+    /// After an ESC.
+    Escape,
+
+    /// In a control sequence.
+    ControlSequence,
+
+    /// In a control string, skipped until its terminator.
+    ControlString,
+
+    /// Saw an ESC in a control string, which may be the start of its terminator.
+    ControlStringEscape
+}
+
+
+
+/// The parser, which keeps the partly seen character or sequence between bytes.
+pub struct AnsiParser
+{
+    /// Where the parser is.
+    state: ParserState,
+
+    /// The control sequence being built.
+    sequence: ControlSequence,
+
+    /// The UTF-8 character being put together, and how many more bytes it needs.
+    character: u32,
+    continuation_bytes: usize
+}
+
+
+
+impl AnsiParser
+{
+    /// Create a parser at the start of the output.
+    pub const fn new() -> AnsiParser
+    {
+        AnsiParser
+            {
+                state: ParserState::Ground,
+                sequence: ControlSequence
+                    {
+                        parameters: [0; MAX_CSI_PARAMETERS],
+                        parameter_count: 0,
+                        private_marker: 0,
+                        final_byte: 0
+                    },
+                character: 0,
+                continuation_bytes: 0
+            }
+    }
+
+    /// Forget whatever was partly seen.
+    pub fn reset(&mut self)
+    {
+        *self = AnsiParser::new();
+    }
+
+    /// Feed a byte of output to the parser. Returns what the output means once the byte completes
+    /// something.
+    pub fn feed(&mut self, byte: u8) -> Option<AnsiAction>
+    {
+        // A byte that isn't part of the UTF-8 character in progress means it was cut short.
+        if    self.continuation_bytes > 0
+           && byte & 0xc0 != 0x80
+        {
+            self.continuation_bytes = 0;
+
+            // Keep the byte, so only the broken character is lost.
+            let action = self.feed(byte);
+
+            return action.or(Some(AnsiAction::Print(char::REPLACEMENT_CHARACTER)));
+        }
+
+        match byte
+        {
+            CANCEL | SUBSTITUTE =>
+                {
+                    self.state = ParserState::Ground;
+                    return None;
+                },
+
+            ESCAPE if self.state == ParserState::ControlString =>
+                {
+                    self.state = ParserState::ControlStringEscape;
+                    return None;
+                },
+
+            ESCAPE =>
+                {
+                    self.state = ParserState::Escape;
+                    return None;
+                },
+
+            _ => {}
+        }
+
+        match self.state
+        {
+            ParserState::Ground                => self.ground(byte),
+            ParserState::Escape                => self.escape(byte),
+            ParserState::ControlSequence       => self.control_sequence(byte),
+            ParserState::ControlString         => self.control_string(byte),
+            ParserState::ControlStringEscape   =>
+                {
+                    self.state = match byte
+                        {
+                            STRING_TERMINATOR => ParserState::Ground,
+                            _                 => ParserState::ControlString
+                        };
+
+                    None
+                }
+        }
+    }
+
+    /// Handle a byte of text.
+    fn ground(&mut self, byte: u8) -> Option<AnsiAction>
+    {
+        match byte
+        {
+            0x00..=0x1f | 0x7f => Some(AnsiAction::Control(byte)),
+            0x20..=0x7e        => Some(AnsiAction::Print(byte as char)),
+
+            // A continuation of a UTF-8 character.
+            0x80..=0xbf =>
+                {
+                    if self.continuation_bytes == 0
+                    {
+                        return Some(AnsiAction::Print(char::REPLACEMENT_CHARACTER));
+                    }
+
+                    self.character = (self.character << 6) | (byte & 0x3f) as u32;
+                    self.continuation_bytes -= 1;
+
+                    match self.continuation_bytes
+                    {
+                        0 => Some(AnsiAction::Print(char::from_u32(self.character)
+                                                        .unwrap_or(char::REPLACEMENT_CHARACTER))),
+                        _ => None
+                    }
+                },
+
+            // The start of a UTF-8 character.
+            0xc2..=0xdf => self.start_character(byte & 0x1f, 1),
+            0xe0..=0xef => self.start_character(byte & 0x0f, 2),
+            0xf0..=0xf4 => self.start_character(byte & 0x07, 3),
+
+            _ => Some(AnsiAction::Print(char::REPLACEMENT_CHARACTER))
+        }
+    }
+
+    /// Start putting together a UTF-8 character.
+    fn start_character(&mut self, bits: u8, continuation_bytes: usize) -> Option<AnsiAction>
+    {
+        self.character = bits as u32;
+        self.continuation_bytes = continuation_bytes;
+
+        None
+    }
+
+    /// Handle the byte after an ESC.
+    fn escape(&mut self, byte: u8) -> Option<AnsiAction>
+    {
+        match byte
+        {
+            b'[' =>
+                {
+                    self.state = ParserState::ControlSequence;
+                    self.sequence = AnsiParser::new().sequence;
+                    None
+                },
+
+            // DCS, SOS, OSC, PM and APC start control strings.
+            b'P' | b'X' | b']' | b'^' | b'_' =>
+                {
+                    self.state = ParserState::ControlString;
+                    None
+                },
+
+            // Intermediate bytes, like the ( of a character set selection, are skipped.
+            0x20..=0x2f => None,
+
+            0x30..=0x7e =>
+                {
+                    self.state = ParserState::Ground;
+                    Some(AnsiAction::Escape(byte))
+                },
+
+            0x00..=0x1f => Some(AnsiAction::Control(byte)),
+
+            _ =>
+                {
+                    self.state = ParserState::Ground;
+                    None
+                }
+        }
+    }
+
+    /// Handle a byte of a control sequence.
+    fn control_sequence(&mut self, byte: u8) -> Option<AnsiAction>
+    {
+        let sequence = &mut self.sequence;
+
+        match byte
+        {
+            b'0'..=b'9' =>
+                {
+                    if sequence.parameter_count == 0
+                    {
+                        sequence.parameter_count = 1;
+                    }
+
+                    let index = sequence.parameter_count - 1;
+
+                    if index < MAX_CSI_PARAMETERS
+                    {
+                        let digit = (byte - b'0') as u16;
+
+                        sequence.parameters[index] = sequence.parameters[index]
+                            .saturating_mul(10)
+                            .saturating_add(digit);
+                    }
+
+                    None
+                },
+
+            b';' | b':' =>
+                {
+                    // An empty first parameter still counts.
+                    if sequence.parameter_count == 0
+                    {
+                        sequence.parameter_count = 1;
+                    }
+
+                    if sequence.parameter_count < MAX_CSI_PARAMETERS
+                    {
+                        sequence.parameter_count += 1;
+                    }
+
+                    None
+                },
+
+            b'<'..=b'?' =>
+                {
+                    if sequence.parameter_count == 0
+                    {
+                        sequence.private_marker = byte;
+                    }
+
+                    None
+                },
+
+            // Intermediate bytes are skipped.
+            0x20..=0x2f => None,
+
+            0x40..=0x7e =>
+                {
+                    sequence.final_byte = byte;
+                    self.state = ParserState::Ground;
+
+                    Some(AnsiAction::ControlSequence(*sequence))
+                },
+
+            // Control codes in the middle of a sequence still take effect.
+            0x00..=0x1f => Some(AnsiAction::Control(byte)),
+
+            _ =>
+                {
+                    self.state = ParserState::Ground;
+                    None
+                }
+        }
+    }
+
+    /// Skip a byte of a control string.
+    fn control_string(&mut self, byte: u8) -> Option<AnsiAction>
+    {
+        if byte == BELL
+        {
+            self.state = ParserState::Ground;
+        }
+
+        None
+    }
+}
@@ -0,0 +1,775 @@
+// A console backend that draws text on a display. The framebuffer is divided up into a grid of
+// character cells the size of the font's glyphs, and the console's output is run through an ANSI
+// parser so that programs can move the cursor around, erase parts of the screen and pick colors,
+// the way they can on a VT100 or xterm.
+//
+// A copy of every cell is kept so that a cell can be redrawn without reading the framebuffer back,
+// which is how the cursor, an underline, is taken off of the screen again. Scrolling moves the
+// pixels already drawn rather than redrawing every glyph.
+//
+// Only output is handled here, the console's input comes from the keyboards.
+
+use alloc::{ sync::Arc, vec, vec::Vec };
+
+use crate::{ devices::{ console::{ ansi::{ AnsiAction, AnsiParser, ControlSequence },
+                                   psf_font::PsfFont,
+                                   ConsoleBackend },
+                        graphics_devices::framebuffer::{ Color, Framebuffer, Rect } },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// The columns between tab stops.
+const TAB_WIDTH: usize = 8;
+
+/// The height of the underline cursor in pixels.
+const CURSOR_HEIGHT: usize = 2;
+
+/// The palette entries used for text that hasn't been given a color.
+const DEFAULT_FOREGROUND: u8 = 7;
+const DEFAULT_BACKGROUND: u8 = 0;
+
+
+
+/// The 16 colors of the ANSI palette, the normal colors followed by their bright versions. These
+/// are the colors of the VGA text mode.
+const PALETTE: [Color; 16] =
+    [
+        Color::new(0,   0,   0),
+        Color::new(170, 0,   0),
+        Color::new(0,   170, 0),
+        Color::new(170, 85,  0),
+        Color::new(0,   0,   170),
+        Color::new(170, 0,   170),
+        Color::new(0,   170, 170),
+        Color::new(170, 170, 170),
+        Color::new(85,  85,  85),
+        Color::new(255, 85,  85),
+        Color::new(85,  255, 85),
+        Color::new(255, 255, 85),
+        Color::new(85,  85,  255),
+        Color::new(255, 85,  255),
+        Color::new(85,  255, 255),
+        Color::new(255, 255, 255)
+    ];
+
+
+
+/// A color as the console's output gives it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TextColor
+{
+    /// The console's default color.
+    Default,
+
+    /// An entry of the 256 color xterm palette, the first 16 of which are the ANSI colors.
+    Indexed(u8),
+
+    /// A color given by its components.
+    Rgb(Color)
+}
+
+
+
+/// How the text being written is drawn.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Attributes
+{
+    foreground: TextColor,
+    background: TextColor,
+
+    /// Bold text is drawn in the bright version of the ANSI colors.
+    bold: bool,
+
+    /// Swap the foreground and background colors.
+    inverse: bool
+}
+
+
+
+impl Attributes
+{
+    /// The attributes the console starts with, and goes back to on a reset.
+    const DEFAULT: Attributes = Attributes
+        {
+            foreground: TextColor::Default,
+            background: TextColor::Default,
+            bold: false,
+            inverse: false
+        };
+
+    /// The colors to draw text in, foreground then background.
+    fn colors(&self) -> (Color, Color)
+    {
+        let foreground = match self.foreground
+            {
+                TextColor::Default        => DEFAULT_FOREGROUND,
+                TextColor::Indexed(index) => index,
+                TextColor::Rgb(color)     => return self.arrange(color)
+            };
+
+        // Bold brightens the normal ANSI colors.
+        let foreground = match foreground
+            {
+                0..=7 if self.bold => foreground + 8,
+                _                  => foreground
+            };
+
+        self.arrange(palette_color(foreground))
+    }
+
+    /// The color of the background, which is what erased cells are filled with.
+    fn background_color(&self) -> Color
+    {
+        match self.background
+        {
+            TextColor::Default        => PALETTE[DEFAULT_BACKGROUND as usize],
+            TextColor::Indexed(index) => palette_color(index),
+            TextColor::Rgb(color)     => color
+        }
+    }
+
+    /// An erased cell. It's filled with the background color, never inverted, and keeps the
+    /// foreground color for the cursor to be drawn in.
+    fn blank_cell(&self) -> Cell
+    {
+        let (foreground, background) = Attributes { inverse: false, ..*self }.colors();
+
+        Cell { character: ' ', foreground, background }
+    }
+
+    /// Pair the foreground color with the background, swapping them for inverse text.
+    fn arrange(&self, foreground: Color) -> (Color, Color)
+    {
+        match self.inverse
+        {
+            true  => (self.background_color(), foreground),
+            false => (foreground, self.background_color())
+        }
+    }
+}
+
+
+
+/// One character cell of the screen.
+#[derive(Clone, Copy)]
+struct Cell
+{
+    character: char,
+    foreground: Color,
+    background: Color
+}
+
+
+
+/// The cursor's position and the attributes, as saved by ESC 7 and restored by ESC 8.
+#[derive(Clone, Copy)]
+struct SavedCursor
+{
+    column: usize,
+    row: usize,
+    attributes: Attributes
+}
+
+
+
+/// The state of the text on the screen.
+struct TextScreen
+{
+    /// Where the text is drawn.
+    framebuffer: Arc<Framebuffer>,
+    font: PsfFont,
+
+    /// The size of the screen in character cells.
+    columns: usize,
+    rows: usize,
+
+    /// Every cell on the screen, a row at a time.
+    cells: Vec<Cell>,
+
+    /// The cursor's position.
+    column: usize,
+    row: usize,
+
+    /// A character was written in the last column, the next one goes at the start of the next
+    /// line. Waiting until then means a full line doesn't leave an empty one after it.
+    wrap_pending: bool,
+
+    /// How text is drawn.
+    attributes: Attributes,
+
+    /// The cursor saved by ESC 7.
+    saved: SavedCursor,
+
+    /// Should the cursor be shown, and is it on the screen right now?
+    cursor_visible: bool,
+    cursor_drawn: bool,
+
+    /// The parser for the escape sequences in the output.
+    parser: AnsiParser
+}
+
+
+
+impl TextScreen
+{
+    /// Create a blank screen covering as much of the framebuffer as the font allows.
+    fn new(framebuffer: Arc<Framebuffer>, font: PsfFont) -> Result<TextScreen, &'static str>
+    {
+        let columns = framebuffer.width() / font.width();
+        let rows = framebuffer.height() / font.height();
+
+        if    columns == 0
+           || rows == 0
+        {
+            return Err("The display is too small for the console's font.");
+        }
+
+        let blank = Attributes::DEFAULT.blank_cell();
+
+        framebuffer.clear(blank.background);
+
+        Ok(TextScreen
+            {
+                framebuffer,
+                font,
+                columns,
+                rows,
+                cells: vec![blank; columns * rows],
+                column: 0,
+                row: 0,
+                wrap_pending: false,
+                attributes: Attributes::DEFAULT,
+                saved: SavedCursor { column: 0, row: 0, attributes: Attributes::DEFAULT },
+                cursor_visible: true,
+                cursor_drawn: false,
+                parser: AnsiParser::new()
+            })
+    }
+
+    /// Write output to the screen, leaving the cursor showing where the next character goes.
+    fn write(&mut self, data: &[u8])
+    {
+        self.hide_cursor();
+
+        for &byte in data
+        {
+            match self.parser.feed(byte)
+            {
+                Some(AnsiAction::Print(character))          => self.print(character),
+                Some(AnsiAction::Control(code))             => self.control(code),
+                Some(AnsiAction::Escape(code))              => self.escape(code),
+                Some(AnsiAction::ControlSequence(sequence)) => self.control_sequence(&sequence),
+                None                                        => {}
+            }
+        }
+
+        self.show_cursor();
+    }
+
+    /// Put a character at the cursor and move the cursor along.
+    fn print(&mut self, character: char)
+    {
+        if self.wrap_pending
+        {
+            self.column = 0;
+            self.wrap_pending = false;
+            self.line_feed();
+        }
+
+        let (foreground, background) = self.attributes.colors();
+        let index = self.row * self.columns + self.column;
+
+        self.cells[index] = Cell { character, foreground, background };
+        self.draw_cell(self.column, self.row, false);
+
+        if self.column + 1 == self.columns
+        {
+            self.wrap_pending = true;
+        }
+        else
+        {
+            self.column += 1;
+        }
+    }
+
+    /// Act on a control code.
+    fn control(&mut self, code: u8)
+    {
+        match code
+        {
+            // The console's newlines are just \n, so a line feed also returns the carriage, the
+            // vertical tab and form feed are taken as line feeds too.
+            b'\n' | 0x0b | 0x0c =>
+                {
+                    self.column = 0;
+                    self.line_feed();
+                },
+
+            b'\r' => self.column = 0,
+            0x08  => self.column = self.column.saturating_sub(1),
+
+            b'\t' =>
+                {
+                    let next_stop = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+
+                    self.column = next_stop.min(self.columns - 1);
+                },
+
+            // Everything else, including the bell, is ignored.
+            _ => return
+        }
+
+        self.wrap_pending = false;
+    }
+
+    /// Act on an ESC sequence.
+    fn escape(&mut self, code: u8)
+    {
+        match code
+        {
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'c' => self.reset(),
+            b'D' => self.line_feed(),
+            b'M' => self.reverse_line_feed(),
+
+            b'E' =>
+                {
+                    self.column = 0;
+                    self.line_feed();
+                },
+
+            _ => return
+        }
+
+        self.wrap_pending = false;
+    }
+
+    /// Act on a control sequence.
+    fn control_sequence(&mut self, sequence: &ControlSequence)
+    {
+        // Of the private sequences only showing and hiding the cursor is supported.
+        if sequence.private_marker == b'?'
+        {
+            if sequence.parameters().contains(&25)
+            {
+                match sequence.final_byte
+                {
+                    b'h' => self.cursor_visible = true,
+                    b'l' => self.cursor_visible = false,
+                    _    => {}
+                }
+            }
+
+            return;
+        }
+
+        if sequence.private_marker != 0
+        {
+            return;
+        }
+
+        let count = sequence.parameter(0, 1) as usize;
+
+        match sequence.final_byte
+        {
+            b'A' => self.move_to(self.column, self.row.saturating_sub(count)),
+            b'B' => self.move_to(self.column, self.row.saturating_add(count)),
+            b'C' => self.move_to(self.column.saturating_add(count), self.row),
+            b'D' => self.move_to(self.column.saturating_sub(count), self.row),
+            b'E' => self.move_to(0, self.row.saturating_add(count)),
+            b'F' => self.move_to(0, self.row.saturating_sub(count)),
+            b'G' => self.move_to(count - 1, self.row),
+            b'd' => self.move_to(self.column, count - 1),
+
+            b'H' | b'f' =>
+                {
+                    let column = sequence.parameter(1, 1) as usize;
+
+                    self.move_to(column - 1, count - 1);
+                },
+
+            b'J' => self.erase_display(sequence.parameter(0, 0)),
+            b'K' => self.erase_line(sequence.parameter(0, 0)),
+
+            b'X' =>
+                {
+                    let start = self.row * self.columns + self.column;
+                    let end = start + count.min(self.columns - self.column);
+
+                    self.erase(start, end);
+                },
+
+            b'S' => self.scroll_up(count),
+            b'T' => self.scroll_down(count),
+            b'm' => self.select_graphic_rendition(sequence.parameters()),
+            b's' => self.save_cursor(),
+            b'u' => self.restore_cursor(),
+
+            _ => {}
+        }
+    }
+
+    /// Change how text is drawn, SGR.
+    fn select_graphic_rendition(&mut self, parameters: &[u16])
+    {
+        if parameters.is_empty()
+        {
+            self.attributes = Attributes::DEFAULT;
+            return;
+        }
+
+        let mut index = 0;
+
+        while index < parameters.len()
+        {
+            let attributes = &mut self.attributes;
+
+            match parameters[index]
+            {
+                0  => *attributes = Attributes::DEFAULT,
+                1  => attributes.bold = true,
+                22 => attributes.bold = false,
+                7  => attributes.inverse = true,
+                27 => attributes.inverse = false,
+
+                code @ 30..=37   => attributes.foreground = TextColor::Indexed(code as u8 - 30),
+                code @ 90..=97   => attributes.foreground = TextColor::Indexed(code as u8 - 82),
+                39               => attributes.foreground = TextColor::Default,
+                code @ 40..=47   => attributes.background = TextColor::Indexed(code as u8 - 40),
+                code @ 100..=107 => attributes.background = TextColor::Indexed(code as u8 - 92),
+                49               => attributes.background = TextColor::Default,
+
+                // 256 color and direct color, which take more parameters.
+                code @ (38 | 48) =>
+                    {
+                        let (color, used) = extended_color(&parameters[index + 1..]);
+
+                        if let Some(color) = color
+                        {
+                            match code
+                            {
+                                38 => attributes.foreground = color,
+                                _  => attributes.background = color
+                            }
+                        }
+
+                        index += used;
+                    },
+
+                _ => {}
+            }
+
+            index += 1;
+        }
+    }
+
+    /// Move the cursor, keeping it on the screen.
+    fn move_to(&mut self, column: usize, row: usize)
+    {
+        self.column = column.min(self.columns - 1);
+        self.row = row.min(self.rows - 1);
+        self.wrap_pending = false;
+    }
+
+    /// Move the cursor down a line, scrolling if it's on the last one.
+    fn line_feed(&mut self)
+    {
+        if self.row + 1 == self.rows
+        {
+            self.scroll_up(1);
+        }
+        else
+        {
+            self.row += 1;
+        }
+    }
+
+    /// Move the cursor up a line, scrolling if it's on the first one.
+    fn reverse_line_feed(&mut self)
+    {
+        if self.row == 0
+        {
+            self.scroll_down(1);
+        }
+        else
+        {
+            self.row -= 1;
+        }
+    }
+
+    /// Erase some or all of the screen, ED. 0 erases from the cursor to the end of the screen, 1
+    /// from the start of the screen to the cursor, and 2 or 3 the whole screen.
+    fn erase_display(&mut self, mode: u16)
+    {
+        let cursor = self.row * self.columns + self.column;
+
+        match mode
+        {
+            0     => self.erase(cursor, self.cells.len()),
+            1     => self.erase(0, cursor + 1),
+            2 | 3 => self.erase(0, self.cells.len()),
+            _     => {}
+        }
+    }
+
+    /// Erase some or all of the cursor's line, EL. The modes are the same as erase_display's, but
+    /// limited to the line.
+    fn erase_line(&mut self, mode: u16)
+    {
+        let line = self.row * self.columns;
+        let cursor = line + self.column;
+
+        match mode
+        {
+            0 => self.erase(cursor, line + self.columns),
+            1 => self.erase(line, cursor + 1),
+            2 => self.erase(line, line + self.columns),
+            _ => {}
+        }
+    }
+
+    /// Blank a range of cells, by their index, with the current background color.
+    fn erase(&mut self, start: usize, end: usize)
+    {
+        let blank = self.attributes.blank_cell();
+
+        self.cells[start..end].fill(blank);
+
+        // Fill a row's worth of the range at a time.
+        let mut index = start;
+
+        while index < end
+        {
+            let column = index % self.columns;
+            let row = index / self.columns;
+            let count = (self.columns - column).min(end - index);
+
+            let area = self.cell_area(column, row);
+            let area = Rect::new(area.x, area.y, count * self.font.width(), area.height);
+
+            self.framebuffer.fill_rect(area, blank.background);
+
+            index += count;
+        }
+    }
+
+    /// Scroll the screen up, blank lines coming in at the bottom.
+    fn scroll_up(&mut self, lines: usize)
+    {
+        let lines = lines.min(self.rows);
+        let kept = self.rows - lines;
+
+        self.framebuffer.copy_rect(self.line_area(lines, kept), 0, 0);
+        self.cells.copy_within(lines * self.columns.., 0);
+        self.erase(kept * self.columns, self.cells.len());
+    }
+
+    /// Scroll the screen down, blank lines coming in at the top.
+    fn scroll_down(&mut self, lines: usize)
+    {
+        let lines = lines.min(self.rows);
+        let kept = self.rows - lines;
+
+        self.framebuffer.copy_rect(self.line_area(0, kept), 0, lines * self.font.height());
+        self.cells.copy_within(..kept * self.columns, lines * self.columns);
+        self.erase(0, lines * self.columns);
+    }
+
+    /// Save the cursor's position and the attributes.
+    fn save_cursor(&mut self)
+    {
+        self.saved = SavedCursor
+            {
+                column: self.column,
+                row: self.row,
+                attributes: self.attributes
+            };
+    }
+
+    /// Go back to the saved cursor position and attributes.
+    fn restore_cursor(&mut self)
+    {
+        let saved = self.saved;
+
+        self.attributes = saved.attributes;
+        self.move_to(saved.column, saved.row);
+    }
+
+    /// Put the screen back the way it started, RIS.
+    fn reset(&mut self)
+    {
+        self.attributes = Attributes::DEFAULT;
+        self.saved = SavedCursor { column: 0, row: 0, attributes: Attributes::DEFAULT };
+        self.cursor_visible = true;
+
+        self.erase(0, self.cells.len());
+        self.move_to(0, 0);
+    }
+
+    /// Draw the cursor, if it's meant to be seen.
+    fn show_cursor(&mut self)
+    {
+        if self.cursor_visible
+        {
+            self.draw_cell(self.column, self.row, true);
+            self.cursor_drawn = true;
+        }
+    }
+
+    /// Take the cursor off of the screen, by drawing its cell again without it.
+    fn hide_cursor(&mut self)
+    {
+        if self.cursor_drawn
+        {
+            self.draw_cell(self.column, self.row, false);
+            self.cursor_drawn = false;
+        }
+    }
+
+    /// Draw a cell's character, with the cursor's underline if asked for.
+    fn draw_cell(&self, column: usize, row: usize, with_cursor: bool)
+    {
+        let cell = self.cells[row * self.columns + column];
+        let glyph = self.font.glyph(cell.character);
+        let cursor_top = self.font.height().saturating_sub(CURSOR_HEIGHT);
+
+        self.framebuffer.draw_mask(self.cell_area(column, row),
+                                   cell.foreground,
+                                   cell.background,
+                                   |x, y| glyph.is_set(x, y) || (with_cursor && y >= cursor_top));
+    }
+
+    /// The pixels covered by a cell.
+    fn cell_area(&self, column: usize, row: usize) -> Rect
+    {
+        let (width, height) = (self.font.width(), self.font.height());
+
+        Rect::new(column * width, row * height, width, height)
+    }
+
+    /// The pixels covered by a run of whole lines.
+    fn line_area(&self, first: usize, count: usize) -> Rect
+    {
+        let height = self.font.height();
+
+        Rect::new(0, first * height, self.columns * self.font.width(), count * height)
+    }
+}
+
+
+
+/// A console backend drawing on a framebuffer.
+pub struct FramebufferConsole
+{
+    /// The framebuffer the console draws on.
+    framebuffer: Arc<Framebuffer>,
+
+    /// The text on the screen.
+    screen: SpinMutex<TextScreen>
+}
+
+
+
+impl FramebufferConsole
+{
+    /// Create a console on the framebuffer, drawing its text in the given font. The framebuffer is
+    /// cleared, and has to be big enough for at least one character.
+    pub fn new(framebuffer: Arc<Framebuffer>,
+               font: PsfFont) -> Result<FramebufferConsole, &'static str>
+    {
+        let screen = TextScreen::new(framebuffer.clone(), font)?;
+
+        Ok(FramebufferConsole
+            {
+                framebuffer,
+                screen: SpinMutex::new(screen)
+            })
+    }
+
+    /// The number of characters that fit on a line.
+    pub fn columns(&self) -> usize
+    {
+        self.screen.lock().columns
+    }
+
+    /// The number of lines that fit on the screen.
+    pub fn rows(&self) -> usize
+    {
+        self.screen.lock().rows
+    }
+}
+
+
+
+impl ConsoleBackend for FramebufferConsole
+{
+    fn name(&self) -> &str
+    {
+        self.framebuffer.name()
+    }
+
+    fn write(&self, data: &[u8])
+    {
+        self.screen.lock().write(data);
+
+        // There's no other time the display gets brought up to date, so it's done every write.
+        let _ = self.framebuffer.flush();
+    }
+
+    fn flush(&self)
+    {
+        let _ = self.framebuffer.flush();
+    }
+}
+
+
+
+/// Read the color of an SGR 38 or 48, from the parameters after it. Returns the color, and how many
+/// of the parameters it took. Malformed colors take all of the parameters, as there's no telling
+/// where they end.
+fn extended_color(parameters: &[u16]) -> (Option<TextColor>, usize)
+{
+    let component = |value: u16| value.min(255) as u8;
+
+    match parameters
+    {
+        [ 5, index, .. ] => (Some(TextColor::Indexed(component(*index))), 2),
+
+        [ 2, red, green, blue, .. ] =>
+            {
+                let color = Color::new(component(*red), component(*green), component(*blue));
+
+                (Some(TextColor::Rgb(color)), 4)
+            },
+
+        _ => (None, parameters.len())
+    }
+}
+
+
+/// Look up a color of the 256 color xterm palette. The 16 ANSI colors are followed by a 6x6x6 cube
+/// of colors and then 24 shades of grey.
+fn palette_color(index: u8) -> Color
+{
+    match index
+    {
+        0..=15 => PALETTE[index as usize],
+
+        16..=231 =>
+            {
+                let index = index - 16;
+                let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+
+                Color::new(level(index / 36), level((index / 6) % 6), level(index % 6))
+            },
+
+        _ =>
+            {
+                let grey = 8 + (index - 232) * 10;
+
+                Color::new(grey, grey, grey)
+            }
+    }
+}
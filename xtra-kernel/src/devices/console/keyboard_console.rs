@@ -0,0 +1,41 @@
+// A console backend for the keyboards. It takes the input typed on any of the attached keyboards,
+// already translated through the keymap, and has no output of its own. The keyboards come and go,
+// so the backend is there whether or not any are attached yet.
+
+use crate::devices::{ console::ConsoleBackend, hid_devices::keymaps::read_typed_input };
+
+
+
+/// The console's input from the keyboards.
+pub struct KeyboardConsole;
+
+
+
+impl KeyboardConsole
+{
+    /// Create the keyboard console backend.
+    pub fn new() -> KeyboardConsole
+    {
+        KeyboardConsole
+    }
+}
+
+
+
+impl ConsoleBackend for KeyboardConsole
+{
+    fn name(&self) -> &str
+    {
+        "keyboard"
+    }
+
+    fn write(&self, data: &[u8])
+    {
+        // The keyboards have nowhere to show output.
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> usize
+    {
+        read_typed_input(buffer)
+    }
+}
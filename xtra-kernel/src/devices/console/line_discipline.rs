@@ -0,0 +1,362 @@
+// The console's line discipline, it sits between the raw bytes typed at the console and whoever is
+// reading them. In canonical mode it does the line editing, input is collected a line at a time
+// and only handed over once return is pressed, so that backspace and friends work. In raw mode
+// every byte is handed over as it's typed.
+//
+// The discipline doesn't write anything itself, the echo it produces is handed back to the console
+// to write to its backends.
+
+use alloc::{ collections::VecDeque, vec::Vec };
+
+
+
+/// The longest line that can be edited, anything typed past this is dropped.
+pub const MAX_LINE_LENGTH: usize = 4096;
+
+/// The most input that's kept, ready to be read and being edited together. The heap never gets
+/// memory back, so anything typed past this while nobody is reading is dropped.
+pub const MAX_INPUT_LENGTH: usize = MAX_LINE_LENGTH;
+
+
+
+/// Ctrl-C, interrupts whatever is reading the console.
+const INTERRUPT_KEY: u8 = 0x03;
+
+/// Ctrl-D, ends the input.
+const END_OF_FILE_KEY: u8 = 0x04;
+
+/// Backspace, also sent as Ctrl-H.
+const BACKSPACE_KEY: u8 = 0x08;
+
+/// Ctrl-U, erases the whole line.
+const KILL_LINE_KEY: u8 = 0x15;
+
+/// Ctrl-W, erases the last word.
+const ERASE_WORD_KEY: u8 = 0x17;
+
+/// Delete, what most terminals send for the backspace key.
+const DELETE_KEY: u8 = 0x7f;
+
+/// What we echo to rub out a character on the terminal.
+const RUB_OUT: &[u8] = b"\x08 \x08";
+
+
+
+/// How the console treats its input.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TerminalMode
+{
+    /// Is the input edited and handed over a line at a time, or handed over as it's typed?
+    pub canonical: bool,
+
+    /// Is the input echoed back to the console?
+    pub echo: bool,
+
+    /// Do Ctrl-C and Ctrl-D signal the reader, or are they passed through like any other byte?
+    pub signals: bool
+}
+
+
+
+impl TerminalMode
+{
+    /// The usual mode, line editing with echo and signals.
+    pub const COOKED: TerminalMode = TerminalMode { canonical: true, echo: true, signals: true };
+
+    /// Every byte is passed straight through, for programs that do their own input handling.
+    pub const RAW: TerminalMode = TerminalMode { canonical: false, echo: false, signals: false };
+}
+
+
+
+/// The signals the console's input can raise.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConsoleSignal
+{
+    /// Ctrl-C was pressed, whatever is running at the console should stop.
+    Interrupt
+}
+
+
+
+/// The state of the console's input.
+pub struct LineDiscipline
+{
+    /// How the input is treated.
+    mode: TerminalMode,
+
+    /// The line being edited, in canonical mode.
+    line: Vec<u8>,
+
+    /// The input that's ready to be read.
+    ready: VecDeque<u8>,
+
+    /// Has the end of the input been signalled with Ctrl-D?
+    end_of_file: bool,
+
+    /// The signal raised by the input that hasn't been taken yet.
+    signal: Option<ConsoleSignal>
+}
+
+
+
+impl LineDiscipline
+{
+    /// Create a line discipline in cooked mode with no input.
+    pub const fn new() -> LineDiscipline
+    {
+        LineDiscipline
+            {
+                mode: TerminalMode::COOKED,
+                line: Vec::new(),
+                ready: VecDeque::new(),
+                end_of_file: false,
+                signal: None
+            }
+    }
+
+    /// How the input is currently treated.
+    pub fn mode(&self) -> TerminalMode
+    {
+        self.mode
+    }
+
+    /// Change how the input is treated. Leaving canonical mode hands over whatever part of a line
+    /// was being edited.
+    pub fn set_mode(&mut self, mode: TerminalMode)
+    {
+        if    self.mode.canonical
+           && !mode.canonical
+        {
+            self.ready.extend(self.line.drain(..));
+        }
+
+        self.mode = mode;
+    }
+
+    /// Is there any input ready to be read, or an end of file waiting to be seen?
+    pub fn has_input(&self) -> bool
+    {
+           !self.ready.is_empty()
+        || self.end_of_file
+    }
+
+    /// Take the signal raised by the input, if there is one.
+    pub fn take_signal(&mut self) -> Option<ConsoleSignal>
+    {
+        self.signal.take()
+    }
+
+    /// Throw away all of the input, both ready and being edited.
+    pub fn discard_input(&mut self)
+    {
+        self.line.clear();
+        self.ready.clear();
+        self.end_of_file = false;
+    }
+
+    /// Process a byte typed at the console, anything that needs to be echoed is passed to the echo
+    /// function.
+    pub fn receive(&mut self, byte: u8, echo: &mut dyn FnMut(&[u8]))
+    {
+        if self.mode.signals
+        {
+            match byte
+            {
+                INTERRUPT_KEY =>
+                    {
+                        self.discard_input();
+                        self.signal = Some(ConsoleSignal::Interrupt);
+                        self.echo(b"^C\n", echo);
+
+                        return;
+                    },
+
+                END_OF_FILE_KEY =>
+                    {
+                        // Ctrl-D on a line with something on it hands the line over without a
+                        // newline, on an empty line it's the end of the input.
+                        if self.line.is_empty()
+                        {
+                            self.end_of_file = true;
+                        }
+                        else
+                        {
+                            self.ready.extend(self.line.drain(..));
+                        }
+
+                        return;
+                    },
+
+                _ => {}
+            }
+        }
+
+        if !self.mode.canonical
+        {
+            if self.has_room(1)
+            {
+                self.ready.push_back(byte);
+                self.echo(&[byte], echo);
+            }
+
+            return;
+        }
+
+        match byte
+        {
+            b'\r' | b'\n' =>
+                {
+                    // There's always room for the newline unless raw mode input filled things up.
+                    if self.has_room(1)
+                    {
+                        self.line.push(b'\n');
+                    }
+
+                    self.ready.extend(self.line.drain(..));
+                    self.echo(b"\n", echo);
+                },
+
+            BACKSPACE_KEY | DELETE_KEY => self.erase_character(echo),
+
+            KILL_LINE_KEY =>
+                {
+                    while !self.line.is_empty()
+                    {
+                        self.erase_character(echo);
+                    }
+                },
+
+            ERASE_WORD_KEY =>
+                {
+                    while self.line.last() == Some(&b' ')
+                    {
+                        self.erase_character(echo);
+                    }
+
+                    while    !self.line.is_empty()
+                          && self.line.last() != Some(&b' ')
+                    {
+                        self.erase_character(echo);
+                    }
+                },
+
+            _ =>
+                {
+                    // Room is kept for the newline that ends the line.
+                    if    self.line.len() < MAX_LINE_LENGTH
+                       && self.has_room(2)
+                    {
+                        self.line.push(byte);
+                        self.echo(&[byte], echo);
+                    }
+                }
+        }
+    }
+
+    /// Read the input that's ready into the buffer. Returns None if there's nothing to read yet,
+    /// and Some(0) once, for the end of the input.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Option<usize>
+    {
+        if self.ready.is_empty()
+        {
+            if self.end_of_file
+            {
+                self.end_of_file = false;
+                return Some(0);
+            }
+
+            return None;
+        }
+
+        let mut count = 0;
+
+        while count < buffer.len()
+        {
+            let Some(byte) = self.ready.pop_front()
+            else
+            {
+                break;
+            };
+
+            buffer[count] = byte;
+            count += 1;
+
+            // In canonical mode a read doesn't go past the end of a line.
+            if    self.mode.canonical
+               && byte == b'\n'
+            {
+                break;
+            }
+        }
+
+        Some(count)
+    }
+
+    /// Is there room for more input, on top of what's ready and the line being edited?
+    fn has_room(&self, length: usize) -> bool
+    {
+        self.ready.len() + self.line.len() + length <= MAX_INPUT_LENGTH
+    }
+
+    /// Erase the last character of the line being edited, taking the whole of a UTF-8 character.
+    fn erase_character(&mut self, echo: &mut dyn FnMut(&[u8]))
+    {
+        let Some(mut byte) = self.line.pop()
+        else
+        {
+            return;
+        };
+
+        while    byte & 0xc0 == 0x80
+              && let Some(previous) = self.line.pop()
+        {
+            byte = previous;
+        }
+
+        if !self.mode.echo
+        {
+            return;
+        }
+
+        // Control characters were echoed as two characters, so they take two to rub out.
+        if is_echoed_as_caret(byte)
+        {
+            echo(RUB_OUT);
+        }
+
+        echo(RUB_OUT);
+    }
+
+    /// Echo input back to the console if echo is on. Control characters other than tab and
+    /// newline are echoed in ^X form.
+    fn echo(&self, bytes: &[u8], echo: &mut dyn FnMut(&[u8]))
+    {
+        if !self.mode.echo
+        {
+            return;
+        }
+
+        for &byte in bytes
+        {
+            if is_echoed_as_caret(byte)
+            {
+                echo(&[b'^', byte ^ 0x40]);
+            }
+            else
+            {
+                echo(&[byte]);
+            }
+        }
+    }
+}
+
+
+
+/// Is the byte a control character that's echoed as ^X?
+fn is_echoed_as_caret(byte: u8) -> bool
+{
+       (byte < 0x20 || byte == DELETE_KEY)
+    && byte != b'\t'
+    && byte != b'\n'
+}
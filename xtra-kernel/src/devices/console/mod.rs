@@ -0,0 +1,306 @@
+// The kernel's console. Output written to the console goes out to every one of its backends, so
+// the kernel's log can be shown on a serial port and the display at the same time, while input
+// typed at any backend is run through the console's line discipline before it's read.
+//
+// Until the console is up the print! macros write straight to the UART picked at boot, once the
+// devices are active printing is switched over to the console.
+//
+// The heap doesn't reuse freed memory, so nothing on the path from print! to the backends
+// allocates.
+
+use alloc::sync::Arc;
+
+use crate::{ command_line::get_kernel_options,
+             devices::{ graphics_devices::get_framebuffer, serial_devices::get_serial_port },
+             locking::spin_mutex::SpinMutex,
+             OS_BANNER_STR };
+
+
+
+/// A parser for the ANSI escape sequences in the console's output.
+pub mod ansi;
+
+/// A console backend drawing text on a framebuffer.
+pub mod framebuffer_console;
+
+/// The console's line discipline, the line editing of the console's input.
+pub mod line_discipline;
+
+/// A console backend taking input from the keyboards.
+pub mod keyboard_console;
+
+/// Bitmap fonts for drawing the console's text.
+pub mod psf_font;
+
+/// A console backend on a serial port.
+pub mod serial_console;
+
+
+
+use crate::devices::console::{ framebuffer_console::FramebufferConsole,
+                               keyboard_console::KeyboardConsole,
+                               line_discipline::{ ConsoleSignal, LineDiscipline, TerminalMode },
+                               psf_font::{ PsfFont, BUILT_IN_FONT },
+                               serial_console::SerialConsole };
+
+
+
+/// The most backends the console can have.
+pub const MAX_CONSOLE_BACKENDS: usize = 4;
+
+
+
+/// How much input is read from a backend at a time.
+const INPUT_CHUNK_SIZE: usize = 64;
+
+/// The escape sequence for the color the banner is drawn in on the display, bold cyan.
+const FRAMEBUFFER_BANNER_COLOR: &str = "\x1b[1;36m";
+
+
+
+/// A device the console writes its output to, and may take input from.
+pub trait ConsoleBackend: Send + Sync
+{
+    /// The name of the backend's device.
+    fn name(&self) -> &str;
+
+    /// Write the data out, waiting for the device if need be. Newlines are written as just \n,
+    /// it's up to the backend to turn them into whatever its device expects.
+    fn write(&self, data: &[u8]);
+
+    /// Read whatever input is waiting into the buffer, without waiting for more. Returns the
+    /// number of bytes read. Output only backends don't have to implement this.
+    fn read(&self, buffer: &mut [u8]) -> usize
+    {
+        0
+    }
+
+    /// Wait until everything written has reached the device, usable with interrupts disabled.
+    fn flush(&self)
+    {
+    }
+}
+
+
+
+/// The backends of the console. A fixed size table, so that writing to the console doesn't need
+/// to copy the list out from under the lock.
+static CONSOLE_BACKENDS: SpinMutex<[Option<Arc<dyn ConsoleBackend>>; MAX_CONSOLE_BACKENDS]> =
+    SpinMutex::new([const { None }; MAX_CONSOLE_BACKENDS]);
+
+
+
+/// The console's input. Always locked before the backends when both are needed.
+static LINE_DISCIPLINE: SpinMutex<LineDiscipline> = SpinMutex::new(LineDiscipline::new());
+
+
+
+/// Initialize the console. The serial port picked by the console= parameter becomes the first of
+/// the console's backends, or the first serial port if there isn't one or it doesn't exist. The
+/// first display, if there is one, gets a text console with the OS banner drawn at the top, and
+/// the keyboards provide input.
+///
+/// Later phases of the boot can disconnect a backend from the console to use its device for
+/// something else.
+pub fn activate_devices() -> Result<(), &'static str>
+{
+    let port = get_kernel_options().console
+        .and_then(get_serial_port)
+        .or_else(|| get_serial_port(0));
+
+    if let Some(port) = port
+    {
+        println!("  console: {}", port.name());
+        register_console_backend(Arc::new(SerialConsole::new(port)))?;
+    }
+
+    if let Some(framebuffer) = get_framebuffer(0)
+    {
+        let console = FramebufferConsole::new(framebuffer, PsfFont::parse(BUILT_IN_FONT)?)?;
+
+        println!("  console: {} ({}x{})", console.name(), console.columns(), console.rows());
+
+        // The serial console already showed the banner before the display was up.
+        console.write(FRAMEBUFFER_BANNER_COLOR.as_bytes());
+        console.write(OS_BANNER_STR.as_bytes());
+        console.write(b"\x1b[0m\n");
+
+        register_console_backend(Arc::new(console))?;
+    }
+
+    register_console_backend(Arc::new(KeyboardConsole::new()))?;
+
+    Ok(())
+}
+
+
+
+/// Add a backend to the console, from now on it gets all of the console's output.
+pub fn register_console_backend(backend: Arc<dyn ConsoleBackend>) -> Result<(), &'static str>
+{
+    let mut backends = CONSOLE_BACKENDS.lock();
+
+    if backends.iter().flatten().any(|existing| existing.name() == backend.name())
+    {
+        return Err("The console already has a backend for the device.");
+    }
+
+    let slot = backends.iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or("The console has no room for another backend.")?;
+
+    *slot = Some(backend);
+
+    Ok(())
+}
+
+
+/// Disconnect a backend from the console by its device's name, handing it back. Anything still
+/// queued for the device is sent first.
+pub fn remove_console_backend(name: &str) -> Option<Arc<dyn ConsoleBackend>>
+{
+    let mut backends = CONSOLE_BACKENDS.lock();
+    let slot = backends.iter_mut()
+        .find(|slot| slot.as_ref().is_some_and(|backend| backend.name() == name))?;
+    let backend = slot.take()?;
+
+    backend.flush();
+
+    Some(backend)
+}
+
+
+/// Does the console have anywhere to send its output?
+pub fn has_console_backends() -> bool
+{
+    CONSOLE_BACKENDS.lock().iter().any(Option::is_some)
+}
+
+
+
+/// Write data to every one of the console's backends.
+pub fn write_console(data: &[u8])
+{
+    for backend in CONSOLE_BACKENDS.lock().iter().flatten()
+    {
+        backend.write(data);
+    }
+}
+
+
+/// Wait until everything written to the console has reached its devices.
+pub fn flush_console()
+{
+    for backend in CONSOLE_BACKENDS.lock().iter().flatten()
+    {
+        backend.flush();
+    }
+}
+
+
+/// Flush the console without waiting on its lock, for the panic handler. Returns false if the
+/// console was in use and couldn't be flushed.
+pub fn try_flush_console() -> bool
+{
+    let Some(backends) = CONSOLE_BACKENDS.try_lock()
+    else
+    {
+        return false;
+    };
+
+    for backend in backends.iter().flatten()
+    {
+        backend.flush();
+    }
+
+    true
+}
+
+
+
+/// Read the console's input into the buffer without waiting. In canonical mode input only becomes
+/// readable a line at a time and a read stops at the end of a line.
+///
+/// Returns None if there's nothing to read yet, and Some(0) at the end of the input, after Ctrl-D
+/// is typed on an empty line.
+pub fn read_console(buffer: &mut [u8]) -> Option<usize>
+{
+    poll_console_input();
+
+    LINE_DISCIPLINE.lock().read(buffer)
+}
+
+
+/// Is there console input ready to be read?
+pub fn has_console_input() -> bool
+{
+    poll_console_input();
+
+    LINE_DISCIPLINE.lock().has_input()
+}
+
+
+/// Take the signal raised by the console's input, if there is one.
+pub fn take_console_signal() -> Option<ConsoleSignal>
+{
+    poll_console_input();
+
+    LINE_DISCIPLINE.lock().take_signal()
+}
+
+
+/// Get how the console treats its input.
+pub fn console_mode() -> TerminalMode
+{
+    LINE_DISCIPLINE.lock().mode()
+}
+
+
+/// Change how the console treats its input, for example switching to raw mode.
+pub fn set_console_mode(mode: TerminalMode)
+{
+    LINE_DISCIPLINE.lock().set_mode(mode);
+}
+
+
+/// Throw away any console input that hasn't been read yet.
+pub fn discard_console_input()
+{
+    LINE_DISCIPLINE.lock().discard_input();
+}
+
+
+
+/// Pass the input waiting at each of the backends through the line discipline, echoing it back to
+/// the console as it goes.
+fn poll_console_input()
+{
+    let mut chunk = [0u8; INPUT_CHUNK_SIZE];
+
+    for index in 0..MAX_CONSOLE_BACKENDS
+    {
+        loop
+        {
+            // Read without holding the backends' lock, the echo below needs it.
+            let Some(backend) = CONSOLE_BACKENDS.lock()[index].clone()
+            else
+            {
+                break;
+            };
+
+            let count = backend.read(&mut chunk);
+
+            if count == 0
+            {
+                break;
+            }
+
+            let mut discipline = LINE_DISCIPLINE.lock();
+
+            for &byte in &chunk[..count]
+            {
+                discipline.receive(byte, &mut |echo| write_console(echo));
+            }
+        }
+    }
+}
@@ -0,0 +1,290 @@
+// PC Screen Fonts, the bitmap fonts of the Linux console. Both versions of the format are read, a
+// PSF1 font has 256 or 512 glyphs 8 pixels wide, a PSF2 font any number of glyphs of any size.
+// Either can carry a table of the characters each glyph draws, without one the glyphs are taken to
+// be in character order.
+//
+// The kernel has a font built in so that the console can draw text on a display as soon as one
+// is found.
+
+use alloc::collections::BTreeMap;
+
+
+
+/// The font built into the kernel, an 8x16 rendering of DejaVu Sans Mono Bold. It covers ASCII,
+/// Latin-1, the box drawing characters and a few more.
+pub static BUILT_IN_FONT: &[u8] = include_bytes!("../../../fonts/console-8x16.psf");
+
+
+
+// The PSF1 header.
+const PSF1_MAGIC:       [u8; 2] = [ 0x36, 0x04 ];
+const PSF1_HEADER_SIZE: usize   = 4;
+const PSF1_MODE_512:    u8      = 0x01;    // The font has 512 glyphs instead of 256.
+const PSF1_MODE_HASTAB: u8      = 0x02;    // The font has a unicode table.
+const PSF1_MODE_SEQ:    u8      = 0x04;    // The table has sequences, implies a table.
+const PSF1_SEPARATOR:   u16     = 0xffff;  // Ends a glyph's entries in the unicode table.
+const PSF1_START_SEQ:   u16     = 0xfffe;  // Starts a sequence of characters in the table.
+
+// The PSF2 header.
+const PSF2_MAGIC:             u32   = 0x864a_b572;
+const PSF2_HEADER_SIZE:       usize = 32;
+const PSF2_HAS_UNICODE_TABLE: u32   = 0x01;
+const PSF2_SEPARATOR:         u8    = 0xff;
+const PSF2_START_SEQ:         u8    = 0xfe;
+
+
+
+/// A bitmap font.
+pub struct PsfFont
+{
+    /// The glyphs' bitmaps, one after the other.
+    glyphs: &'static [u8],
+
+    /// How many glyphs there are.
+    glyph_count: usize,
+
+    /// The size of each glyph in pixels.
+    width: usize,
+    height: usize,
+
+    /// The number of bytes in each row of a glyph, and in the whole glyph.
+    bytes_per_row: usize,
+    bytes_per_glyph: usize,
+
+    /// Which glyph draws which character, empty if the glyphs are in character order.
+    characters: BTreeMap<char, usize>,
+
+    /// The glyph drawn for characters the font doesn't have.
+    replacement: usize
+}
+
+
+
+impl PsfFont
+{
+    /// Read a font in either of the PSF formats.
+    pub fn parse(data: &'static [u8]) -> Result<PsfFont, &'static str>
+    {
+        if data.starts_with(&PSF1_MAGIC)
+        {
+            PsfFont::parse_psf1(data)
+        }
+        else if    data.len() >= PSF2_HEADER_SIZE
+                && read_u32(data, 0) == PSF2_MAGIC
+        {
+            PsfFont::parse_psf2(data)
+        }
+        else
+        {
+            Err("The font isn't a PSF font.")
+        }
+    }
+
+    /// The width of every glyph in pixels.
+    pub fn width(&self) -> usize
+    {
+        self.width
+    }
+
+    /// The height of every glyph in pixels.
+    pub fn height(&self) -> usize
+    {
+        self.height
+    }
+
+    /// Get the glyph that draws the character, or the replacement glyph if the font doesn't have
+    /// one.
+    pub fn glyph(&self, character: char) -> Glyph<'_>
+    {
+        let index = match self.characters.is_empty()
+            {
+                true  => Some(character as usize).filter(|&index| index < self.glyph_count),
+                false => self.characters.get(&character).copied()
+            };
+
+        let start = index.unwrap_or(self.replacement) * self.bytes_per_glyph;
+
+        Glyph
+            {
+                bitmap: &self.glyphs[start..start + self.bytes_per_glyph],
+                bytes_per_row: self.bytes_per_row
+            }
+    }
+
+    /// Does the font have a glyph for the character?
+    pub fn has_glyph(&self, character: char) -> bool
+    {
+        match self.characters.is_empty()
+        {
+            true  => (character as usize) < self.glyph_count,
+            false => self.characters.contains_key(&character)
+        }
+    }
+
+    /// Read a PSF1 font.
+    fn parse_psf1(data: &'static [u8]) -> Result<PsfFont, &'static str>
+    {
+        if data.len() < PSF1_HEADER_SIZE
+        {
+            return Err("The font is truncated.");
+        }
+
+        let mode = data[2];
+        let height = data[3] as usize;
+        let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let glyphs_end = PSF1_HEADER_SIZE + glyph_count * height;
+
+        if    height == 0
+           || data.len() < glyphs_end
+        {
+            return Err("The font is truncated.");
+        }
+
+        let mut characters = BTreeMap::new();
+
+        if mode & (PSF1_MODE_HASTAB | PSF1_MODE_SEQ) != 0
+        {
+            let mut entries = data[glyphs_end..]
+                .chunks_exact(2)
+                .map(|bytes| u16::from_le_bytes([ bytes[0], bytes[1] ]));
+
+            for glyph in 0..glyph_count
+            {
+                let mut in_sequence = false;
+
+                for entry in entries.by_ref()
+                {
+                    match entry
+                    {
+                        PSF1_SEPARATOR => break,
+                        PSF1_START_SEQ => in_sequence = true,
+
+                        _ if !in_sequence =>
+                            {
+                                if let Some(character) = char::from_u32(entry as u32)
+                                {
+                                    characters.entry(character).or_insert(glyph);
+                                }
+                            },
+
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        Ok(PsfFont::new(&data[PSF1_HEADER_SIZE..glyphs_end], glyph_count, 8, height, characters))
+    }
+
+    /// Read a PSF2 font.
+    fn parse_psf2(data: &'static [u8]) -> Result<PsfFont, &'static str>
+    {
+        let header_size = read_u32(data, 8) as usize;
+        let flags = read_u32(data, 12);
+        let glyph_count = read_u32(data, 16) as usize;
+        let bytes_per_glyph = read_u32(data, 20) as usize;
+        let height = read_u32(data, 24) as usize;
+        let width = read_u32(data, 28) as usize;
+
+        let glyphs_end = header_size + glyph_count * bytes_per_glyph;
+
+        if    width == 0
+           || height == 0
+           || glyph_count == 0
+           || bytes_per_glyph < width.div_ceil(8) * height
+           || data.len() < glyphs_end
+        {
+            return Err("The font is truncated.");
+        }
+
+        let mut characters = BTreeMap::new();
+
+        if flags & PSF2_HAS_UNICODE_TABLE != 0
+        {
+            let mut table = &data[glyphs_end..];
+
+            for glyph in 0..glyph_count
+            {
+                let end = table.iter().position(|&byte| byte == PSF2_SEPARATOR)
+                    .unwrap_or(table.len());
+
+                // Only the single characters before any sequences are used.
+                let singles = &table[..end];
+                let singles = singles.iter()
+                    .position(|&byte| byte == PSF2_START_SEQ)
+                    .map_or(singles, |start| &singles[..start]);
+
+                if let Ok(string) = core::str::from_utf8(singles)
+                {
+                    for character in string.chars()
+                    {
+                        characters.entry(character).or_insert(glyph);
+                    }
+                }
+
+                table = table.get(end + 1..).unwrap_or(&[]);
+            }
+        }
+
+        Ok(PsfFont::new(&data[header_size..glyphs_end], glyph_count, width, height, characters))
+    }
+
+    /// Put together a font, picking the glyph for the characters it doesn't have.
+    fn new(glyphs: &'static [u8],
+           glyph_count: usize,
+           width: usize,
+           height: usize,
+           characters: BTreeMap<char, usize>) -> PsfFont
+    {
+        let bytes_per_row = width.div_ceil(8);
+
+        let replacement = [ '\u{fffd}', '?' ].iter()
+            .find_map(|character| characters.get(character).copied())
+            .unwrap_or(if glyph_count > '?' as usize { '?' as usize } else { 0 });
+
+        PsfFont
+            {
+                glyphs,
+                glyph_count,
+                width,
+                height,
+                bytes_per_row,
+                bytes_per_glyph: glyphs.len() / glyph_count,
+                characters,
+                replacement
+            }
+    }
+}
+
+
+
+/// The bitmap of one of a font's glyphs.
+pub struct Glyph<'font>
+{
+    /// The rows of the glyph, the leftmost pixel is the top bit of a row's first byte.
+    bitmap: &'font [u8],
+
+    /// The number of bytes in each row.
+    bytes_per_row: usize
+}
+
+
+
+impl Glyph<'_>
+{
+    /// Is the pixel at the position part of the character?
+    pub fn is_set(&self, x: usize, y: usize) -> bool
+    {
+        let byte = self.bitmap.get(y * self.bytes_per_row + x / 8).copied().unwrap_or(0);
+
+        byte & (0x80 >> (x % 8)) != 0
+    }
+}
+
+
+
+/// Read a little endian 32-bit value from the font's data.
+fn read_u32(data: &[u8], offset: usize) -> u32
+{
+    u32::from_le_bytes([ data[offset], data[offset + 1], data[offset + 2], data[offset + 3] ])
+}
@@ -0,0 +1,62 @@
+// A console backend on a serial port. Terminals on the other end of the line expect a carriage
+// return before each newline, so that's added on the way out.
+
+use alloc::sync::Arc;
+
+use crate::devices::{ console::ConsoleBackend, serial_devices::SerialDevice };
+
+
+
+/// A console on a serial port, taking input from the port and sending output down it.
+pub struct SerialConsole
+{
+    /// The port the console is on.
+    port: Arc<dyn SerialDevice>
+}
+
+
+
+impl SerialConsole
+{
+    /// Create a console on the given serial port.
+    pub fn new(port: Arc<dyn SerialDevice>) -> SerialConsole
+    {
+        SerialConsole { port }
+    }
+}
+
+
+
+impl ConsoleBackend for SerialConsole
+{
+    fn name(&self) -> &str
+    {
+        self.port.name()
+    }
+
+    fn write(&self, data: &[u8])
+    {
+        let mut lines = data.split(|&byte| byte == b'\n');
+
+        if let Some(first) = lines.next()
+        {
+            self.port.write_all(first);
+        }
+
+        for line in lines
+        {
+            self.port.write_all(b"\r\n");
+            self.port.write_all(line);
+        }
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> usize
+    {
+        self.port.read(buffer)
+    }
+
+    fn flush(&self)
+    {
+        self.port.flush();
+    }
+}
@@ -1,13 +1,99 @@
+// The CPU devices of the system. There isn't much to drive, but the /cpus node of the device tree
+// and the cpu nodes under it are how we find out which harts the system has, and whether the kernel
+// can run on them. The hart management code uses this list to decide which harts to start.
+
+use core::{ fmt::{ self, Display, Formatter },
+            sync::atomic::{ AtomicU64, Ordering } };
+
+use alloc::{ string::String, vec::Vec };
 
 use xtra_kernel_shared::device_tree::DeviceTree;
 
-use crate::devices::DeviceDriverRegistry;
+use crate::{ devices::{ read_property_cells, read_property_string, DeviceDriverRegistry },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// The MMU types that can run the kernel. The kernel uses Sv39 paging, which every hart with one
+/// of the larger page table formats also supports.
+const SUPPORTED_MMU_TYPES: [&str; 3] = [ "riscv,sv39", "riscv,sv48", "riscv,sv57" ];
+
+/// The frequency to assume if the device tree didn't tell us how fast the time CSR runs. QEMU's
+/// timer runs at 10MHz.
+const DEFAULT_TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+
+
+/// What the device tree tells us about one of the system's harts.
+#[derive(Clone)]
+pub struct CpuDevice
+{
+    /// The ID of the hart, from the node's reg property.
+    pub hart_id: usize,
+
+    /// The instruction set the hart implements, for example rv64imafdc.
+    pub isa: String,
+
+    /// The kind of MMU the hart has, for example riscv,sv39.
+    pub mmu_type: String,
+
+    /// Is the hart available for use? Harts can be listed in the device tree but disabled.
+    pub is_enabled: bool,
+
+    /// The offset of the hart's node in the device tree, the nodes under it, like the hart's
+    /// interrupt controller, follow it.
+    pub node_offset: usize
+}
+
+
+
+impl CpuDevice
+{
+    /// Can the kernel run on this hart?
+    pub fn is_usable(&self) -> bool
+    {
+           self.is_enabled
+        && SUPPORTED_MMU_TYPES.contains(&self.mmu_type.as_str())
+    }
+}
+
+
+
+impl Display for CpuDevice
+{
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result
+    {
+        write!(formatter, "Hart {:02}: {}, {}", self.hart_id, self.isa, self.mmu_type)?;
+
+        if !self.is_enabled
+        {
+            write!(formatter, ", disabled")?;
+        }
+        else if !self.is_usable()
+        {
+            write!(formatter, ", unsupported MMU")?;
+        }
+
+        Ok(())
+    }
+}
+
+
+
+/// All of the harts found in the device tree, sorted by their hart IDs.
+static CPUS: SpinMutex<Vec<CpuDevice>> = SpinMutex::new(Vec::new());
+
+/// The frequency of the time CSR in ticks per second, taken from the /cpus node.
+static TIMEBASE_FREQUENCY: AtomicU64 = AtomicU64::new(0);
 
 
 
 /// Register the driver probe functions for all of the CPUs in the system.
 pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(), &'static str>
 {
+    registry.insert("cpus", probe_cpus);
+    registry.insert("cpu", probe_cpu);
+
     Ok(())
 }
 
@@ -16,5 +102,139 @@ pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(),
 /// pretty weird if we didn't find any CPUs in the system.
 pub fn activate_devices() -> Result<(), &'static str>
 {
+    let cpus = CPUS.lock();
+
+    if cpus.is_empty()
+    {
+        return Err("No CPUs were found in the device tree.");
+    }
+
+    for cpu in cpus.iter()
+    {
+        println!("  {}", cpu);
+    }
+
+    Ok(())
+}
+
+
+
+/// Get a copy of the list of harts found in the device tree.
+pub fn cpus() -> Vec<CpuDevice>
+{
+    CPUS.lock().clone()
+}
+
+
+/// Look up a hart found in the device tree by its ID.
+pub fn find_cpu(hart_id: usize) -> Option<CpuDevice>
+{
+    CPUS.lock().iter().find(|cpu| cpu.hart_id == hart_id).cloned()
+}
+
+
+/// Find the hart whose node in the device tree holds the node at the given offset. The cpu nodes
+/// are siblings, so the node belongs to the last hart that starts before it.
+pub fn find_cpu_containing_node(node_offset: usize) -> Option<CpuDevice>
+{
+    CPUS.lock()
+        .iter()
+        .filter(|cpu| cpu.node_offset < node_offset)
+        .max_by_key(|cpu| cpu.node_offset)
+        .cloned()
+}
+
+
+/// Get the frequency of the time CSR in ticks per second, or a best guess if the device tree didn't
+/// say.
+pub fn timebase_frequency() -> u64
+{
+    match TIMEBASE_FREQUENCY.load(Ordering::Acquire)
+    {
+        0         => DEFAULT_TIMEBASE_FREQUENCY,
+        frequency => frequency
+    }
+}
+
+
+
+/// Probe the /cpus node, it holds the properties that all of the harts share.
+fn probe_cpus(name: &str,
+              address: Option<usize>,
+              device_tree: &DeviceTree,
+              block_offset: usize) -> Result<(), &'static str>
+{
+    device_tree.iterate_properties(block_offset, |property_name, property_value|
+        {
+            if    property_name == "timebase-frequency"
+               && let Some(frequency) = read_property_cells(property_value)
+            {
+                TIMEBASE_FREQUENCY.store(frequency, Ordering::Release);
+            }
+
+            true
+        });
+
+    Ok(())
+}
+
+
+/// Probe one of the cpu nodes, each one describes a single hart.
+fn probe_cpu(name: &str,
+             address: Option<usize>,
+             device_tree: &DeviceTree,
+             block_offset: usize) -> Result<(), &'static str>
+{
+    let mut hart_id = address;
+    let mut is_cpu = false;
+    let mut isa = String::new();
+    let mut mmu_type = String::new();
+    let mut is_enabled = true;
+
+    device_tree.iterate_properties(block_offset, |property_name, property_value|
+        {
+            match property_name
+            {
+                "device_type" => is_cpu = read_property_string(property_value) == "cpu",
+                "reg"         => hart_id = read_property_cells(property_value)
+                                                   .map(|id| id as usize),
+                "riscv,isa"   => isa = read_property_string(property_value),
+                "mmu-type"    => mmu_type = read_property_string(property_value),
+                "status"      => is_enabled = read_property_string(property_value) == "okay",
+
+                // Some device trees give the timebase for each hart instead of in /cpus.
+                "timebase-frequency" =>
+                    {
+                        if let Some(frequency) = read_property_cells(property_value)
+                        {
+                            TIMEBASE_FREQUENCY.store(frequency, Ordering::Release);
+                        }
+                    },
+
+                _ => {}
+            }
+
+            true
+        });
+
+    // The nodes of the cpu-map are also called cpu, but they aren't harts.
+    if !is_cpu
+    {
+        return Ok(());
+    }
+
+    let hart_id = hart_id.ok_or("CPU node is missing its hart ID.")?;
+    let mut cpus = CPUS.lock();
+
+    if cpus.iter().any(|cpu| cpu.hart_id == hart_id)
+    {
+        return Err("Hart is listed more than once in the device tree.");
+    }
+
+    let index = cpus.partition_point(|cpu| cpu.hart_id < hart_id);
+
+    cpus.insert(index,
+                CpuDevice { hart_id, isa, mmu_type, is_enabled, node_offset: block_offset });
+
     Ok(())
 }
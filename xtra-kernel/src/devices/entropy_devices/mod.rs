@@ -0,0 +1,123 @@
+// The entropy device subsystem, for hardware random number generators. The drivers register a
+// device for each generator they drive, and the kernel's random number generator asks them for
+// entropy whenever its input pool is running low.
+//
+// A device doesn't hand its entropy back to whoever asked, it's fed straight into the input pool as
+// it arrives. That's usually from the device's interrupt handler, so the drivers have to let go of
+// their own locks before they pass it on.
+
+use alloc::{ format, string::String, sync::Arc, vec::Vec };
+
+use crate::{ arch::interrupts::with_interrupts_disabled,
+             devices::{ bus_devices::virtio_devices::{ VirtioDriverRegistry,
+                                                       VIRTIO_ENTROPY_DEVICE_ID },
+                        DeviceDriverRegistry },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// Driver for VirtIO entropy devices.
+pub mod virtio_rng;
+
+
+
+/// The interface all entropy devices expose to the random number generator.
+pub trait EntropyDevice: Send + Sync
+{
+    /// The name of the device, for example hwrng0.
+    fn name(&self) -> &str;
+
+    /// Ask the device for more entropy, unless it's already working on a request. What it comes up
+    /// with is added to the input pool once it's ready.
+    fn request_entropy(&self);
+
+    /// Handle whatever the device has done since it was last looked at, for when its interrupt
+    /// isn't available.
+    fn poll(&self);
+}
+
+
+
+/// All of the entropy devices in the system, in the order they were found. Entropy can be asked for
+/// from interrupt handlers, so it's only locked with interrupts disabled.
+static ENTROPY_DEVICES: SpinMutex<Vec<Arc<dyn EntropyDevice>>> = SpinMutex::new(Vec::new());
+
+
+
+/// Register the driver probe functions for all of the entropy device drivers in the system.
+pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(), &'static str>
+{
+    Ok(())
+}
+
+
+/// Register the drivers for the entropy devices that can show up on the VirtIO bus.
+pub fn register_virtio_drivers(registry: &mut VirtioDriverRegistry) -> Result<(), &'static str>
+{
+    registry.insert(VIRTIO_ENTROPY_DEVICE_ID, virtio_rng::probe_virtio_rng_device);
+
+    Ok(())
+}
+
+
+/// Activate and initialize the entropy devices discovered in the device tree. If any.
+pub fn activate_devices() -> Result<(), &'static str>
+{
+    Ok(())
+}
+
+
+
+/// Get the name for the next entropy device to be registered.
+pub fn next_entropy_device_name() -> String
+{
+    with_interrupts_disabled(|| format!("hwrng{}", ENTROPY_DEVICES.lock().len()))
+}
+
+
+/// Make an entropy device available to the random number generator.
+pub fn register_entropy_device(device: Arc<dyn EntropyDevice>)
+{
+    with_interrupts_disabled(|| ENTROPY_DEVICES.lock().push(device));
+}
+
+
+/// Are there any entropy devices in the system?
+pub fn has_entropy_devices() -> bool
+{
+    with_interrupts_disabled(|| !ENTROPY_DEVICES.lock().is_empty())
+}
+
+
+/// Ask every entropy device for more entropy.
+pub fn request_entropy()
+{
+    for_each_entropy_device(|device| device.request_entropy());
+}
+
+
+/// Poll every entropy device, for those whose interrupts couldn't be routed.
+pub fn poll_entropy_devices()
+{
+    for_each_entropy_device(|device| device.poll());
+}
+
+
+/// Run a function for each of the entropy devices. The list isn't held while a device is used, as
+/// the entropy it adds can lead to more being requested.
+fn for_each_entropy_device(function: impl Fn(&dyn EntropyDevice))
+{
+    let mut index = 0;
+
+    let device_at = |index: usize| with_interrupts_disabled(||
+        {
+            ENTROPY_DEVICES.lock().get(index).cloned()
+        });
+
+    while let Some(device) = device_at(index)
+    {
+        index += 1;
+
+        function(device.as_ref());
+    }
+}
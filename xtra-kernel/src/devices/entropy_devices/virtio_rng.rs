@@ -0,0 +1,233 @@
+// Driver for VirtIO entropy devices, what QEMU provides with its virtio-rng-device, which passes on
+// randomness from the host. The device has a single request queue, and each buffer put on it comes
+// back filled with as much entropy as the device had to give.
+//
+// We keep one buffer for the device and only hand it over when the random number generator wants
+// more entropy, otherwise the device would keep interrupting us with entropy nobody needs. Once it
+// comes back the entropy is added to the input pool, and if the pool is still short of what it
+// wants the buffer goes straight back to the device.
+//
+// Requests are completed by the device's interrupt handler. If the device's interrupt can't be
+// routed it's polled instead, whenever the random number generator waits for entropy.
+
+use core::ptr::read_volatile;
+
+use alloc::{ string::String, sync::Arc, vec, vec::Vec };
+
+use crate::{ arch::interrupts::with_interrupts_disabled,
+             devices::{ bus_devices::virtio_devices::{ mmio::VirtioMmioDevice,
+                                                       virtqueue::{ VirtQueue,
+                                                                    VirtQueueBuffer } },
+                        entropy_devices::{ next_entropy_device_name,
+                                           register_entropy_device,
+                                           EntropyDevice } },
+             interrupts::register_interrupt_handler,
+             locking::spin_mutex::SpinMutex,
+             random::{ add_hardware_randomness, needs_entropy } };
+
+
+
+/// The queue requests for entropy are made on.
+const REQUEST_QUEUE: u16 = 0;
+
+/// The size of the request queue we ask the device for. We only ever have one request outstanding.
+const REQUEST_QUEUE_SIZE: u16 = 1;
+
+/// How much entropy we ask the device for at a time.
+const REQUEST_SIZE: usize = 64;
+
+
+
+/// The state of the device, also used by its interrupt handler so it's only ever locked with
+/// interrupts disabled.
+struct RngState
+{
+    /// The queue requests are made on.
+    queue: VirtQueue,
+
+    /// The buffer the device writes its entropy into.
+    buffer: Vec<u8>,
+
+    /// Does the device have the buffer?
+    is_pending: bool
+}
+
+
+
+/// A VirtIO entropy device.
+pub struct VirtioRngDevice
+{
+    /// The name of the device, for example hwrng0.
+    name: String,
+
+    /// The device's register interface.
+    device: VirtioMmioDevice,
+
+    /// The device's state.
+    state: SpinMutex<RngState>
+}
+
+
+
+impl VirtioRngDevice
+{
+    /// Perform the VirtIO initialization handshake for the entropy device and set up its request
+    /// queue.
+    pub fn new(device: VirtioMmioDevice, name: String) -> Result<Self, &'static str>
+    {
+        device.begin_initialization();
+        device.negotiate_features(0)?;
+
+        let queue = device.setup_queue(REQUEST_QUEUE, REQUEST_QUEUE_SIZE)?;
+
+        device.finish_initialization();
+
+        Ok(VirtioRngDevice
+            {
+                name,
+                device,
+                state: SpinMutex::new(RngState
+                    {
+                        queue,
+                        buffer: vec![0u8; REQUEST_SIZE],
+                        is_pending: false
+                    })
+            })
+    }
+
+    /// Take the entropy from a completed request and add it to the input pool, asking for more if
+    /// the pool wants it.
+    fn process_requests(&self)
+    {
+        let mut entropy = [0u8; REQUEST_SIZE];
+
+        let length = with_interrupts_disabled(||
+            {
+                let mut state = self.state.lock();
+                let used = state.queue.pop_used()?;
+                let length = (used.length as usize).min(REQUEST_SIZE);
+
+                state.is_pending = false;
+
+                let buffer = state.buffer.as_ptr() as *const [u8; REQUEST_SIZE];
+
+                entropy = unsafe { read_volatile(buffer) };
+                state.buffer.fill(0);
+
+                Some(length)
+            });
+
+        // The device's lock has been let go of before the pool is taken, as the pool may ask for
+        // more entropy.
+        if let Some(length) = length
+        {
+            add_hardware_randomness(&entropy[..length]);
+            entropy.fill(0);
+
+            if needs_entropy()
+            {
+                self.request_entropy();
+            }
+        }
+    }
+}
+
+
+
+impl EntropyDevice for VirtioRngDevice
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn request_entropy(&self)
+    {
+        with_interrupts_disabled(||
+            {
+                let mut state = self.state.lock();
+                let state = &mut *state;
+
+                if state.is_pending
+                {
+                    return;
+                }
+
+                let buffer = VirtQueueBuffer::writable(&mut state.buffer[..]);
+
+                if state.queue.add_buffers(&[buffer]).is_ok()
+                {
+                    state.is_pending = true;
+                    self.device.notify_queue(REQUEST_QUEUE);
+                }
+            });
+    }
+
+    fn poll(&self)
+    {
+        self.process_requests();
+    }
+}
+
+
+
+/// The active entropy devices. Also searched by the interrupt handler, so it's only locked with
+/// interrupts disabled.
+static VIRTIO_RNG_DEVICES: SpinMutex<Vec<Arc<VirtioRngDevice>>> = SpinMutex::new(Vec::new());
+
+
+
+/// Called by the VirtIO bus when it finds an entropy device. The device is initialized, its
+/// interrupt routed to the current hart and it's made available to the random number generator.
+pub fn probe_virtio_rng_device(device: VirtioMmioDevice) -> Result<(), &'static str>
+{
+    let rng_device = Arc::new(VirtioRngDevice::new(device, next_entropy_device_name())?);
+
+    with_interrupts_disabled(|| VIRTIO_RNG_DEVICES.lock().push(rng_device.clone()));
+
+    println!("  {}: virtio-rng", rng_device.name);
+
+    match device.interrupt()
+    {
+        Some(interrupt) =>
+            {
+                if let Err(error) = register_interrupt_handler(None,
+                                                               interrupt as usize,
+                                                               handle_rng_interrupt)
+                {
+                    println!("  {} will be polled, its interrupt couldn't be routed: {}",
+                             rng_device.name,
+                             error);
+                }
+            },
+
+        None => println!("  {} has no interrupt, it will be polled.", rng_device.name)
+    }
+
+    register_entropy_device(rng_device);
+
+    Ok(())
+}
+
+
+
+/// Handle an interrupt for any of the entropy devices on the interrupt.
+fn handle_rng_interrupt(interrupt_number: usize)
+{
+    let mut index = 0;
+
+    // The device list isn't held while the entropy is added, as adding it can lead to the device
+    // being asked for more.
+    while let Some(rng_device) = VIRTIO_RNG_DEVICES.lock().get(index).cloned()
+    {
+        index += 1;
+
+        if rng_device.device.interrupt() != Some(interrupt_number as u32)
+        {
+            continue;
+        }
+
+        rng_device.device.acknowledge_interrupt();
+        rng_device.process_requests();
+    }
+}
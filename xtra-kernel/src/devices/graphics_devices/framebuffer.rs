@@ -0,0 +1,462 @@
+// Framebuffers, memory the CPU draws pixels into that a display shows. Some displays scan the
+// memory out directly, others, like VirtIO GPUs, keep their own copy that has to be brought up to
+// date. So drawing only changes the memory and marks the area drawn as damaged, and flushing the
+// framebuffer hands the damaged area to the device that shows it.
+//
+// Drawing isn't locked, the framebuffer's user is expected to serialize its own drawing. Only the
+// damage is shared, and that's locked with interrupts disabled, as the console may draw from
+// interrupt context.
+
+use core::{ fmt::{ self, Display, Formatter },
+            ptr::{ copy, write_volatile } };
+
+use alloc::{ string::String, sync::Arc };
+
+use crate::{ arch::interrupts::with_interrupts_disabled, locking::spin_mutex::SpinMutex };
+
+
+
+/// How the pixels are laid out in the framebuffer's memory. The names give the components from the
+/// most significant bit down, of a little endian pixel.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PixelFormat
+{
+    /// 32 bits a pixel, blue in the lowest byte and the top byte unused.
+    Xrgb8888,
+
+    /// 32 bits a pixel, red in the lowest byte and the top byte unused.
+    Xbgr8888,
+
+    /// 16 bits a pixel, 5 bits of red, 6 of green and 5 of blue.
+    Rgb565
+}
+
+
+
+impl PixelFormat
+{
+    /// Look up a format by the name the device tree's simple-framebuffer binding gives it.
+    pub fn from_name(name: &str) -> Option<PixelFormat>
+    {
+        match name
+        {
+            "x8r8g8b8" | "a8r8g8b8" => Some(PixelFormat::Xrgb8888),
+            "x8b8g8r8" | "a8b8g8r8" => Some(PixelFormat::Xbgr8888),
+            "r5g6b5"                => Some(PixelFormat::Rgb565),
+            _                       => None
+        }
+    }
+
+    /// How many bytes each pixel takes.
+    pub fn bytes_per_pixel(&self) -> usize
+    {
+        match self
+        {
+            PixelFormat::Xrgb8888 | PixelFormat::Xbgr8888 => 4,
+            PixelFormat::Rgb565                           => 2
+        }
+    }
+}
+
+
+
+impl Display for PixelFormat
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        let name = match self
+            {
+                PixelFormat::Xrgb8888 => "x8r8g8b8",
+                PixelFormat::Xbgr8888 => "x8b8g8r8",
+                PixelFormat::Rgb565   => "r5g6b5"
+            };
+
+        write!(formatter, "{}", name)
+    }
+}
+
+
+
+/// A color, 8 bits for each component.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Color
+{
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8
+}
+
+
+
+impl Color
+{
+    pub const BLACK: Color = Color::new(0, 0, 0);
+    pub const WHITE: Color = Color::new(255, 255, 255);
+
+    /// Create a color from its components.
+    pub const fn new(red: u8, green: u8, blue: u8) -> Color
+    {
+        Color { red, green, blue }
+    }
+
+    /// Encode the color as a pixel in the given format.
+    pub fn encode(&self, format: PixelFormat) -> u32
+    {
+        let (red, green, blue) = (self.red as u32, self.green as u32, self.blue as u32);
+
+        match format
+        {
+            PixelFormat::Xrgb8888 => (red << 16) | (green << 8) | blue,
+            PixelFormat::Xbgr8888 => (blue << 16) | (green << 8) | red,
+            PixelFormat::Rgb565   => ((red >> 3) << 11) | ((green >> 2) << 5) | (blue >> 3)
+        }
+    }
+}
+
+
+
+/// A rectangle of pixels.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Rect
+{
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+
+
+impl Rect
+{
+    /// Create a rectangle from its top left corner and size.
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Rect
+    {
+        Rect { x, y, width, height }
+    }
+
+    /// Does the rectangle cover no pixels?
+    pub fn is_empty(&self) -> bool
+    {
+           self.width == 0
+        || self.height == 0
+    }
+
+    /// The column just past the right edge.
+    pub fn right(&self) -> usize
+    {
+        self.x + self.width
+    }
+
+    /// The row just past the bottom edge.
+    pub fn bottom(&self) -> usize
+    {
+        self.y + self.height
+    }
+
+    /// The part of the rectangle that's also in the other, empty if they don't overlap.
+    pub fn intersection(&self, other: &Rect) -> Rect
+    {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+
+    /// The smallest rectangle that covers both rectangles.
+    pub fn union(&self, other: &Rect) -> Rect
+    {
+        if self.is_empty()
+        {
+            return *other;
+        }
+
+        if other.is_empty()
+        {
+            return *self;
+        }
+
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+
+        Rect::new(x, y, self.right().max(other.right()) - x, self.bottom().max(other.bottom()) - y)
+    }
+}
+
+
+
+/// The device that shows a framebuffer.
+pub trait FramebufferDevice: Send + Sync
+{
+    /// The name of the kind of device, for the boot messages.
+    fn name(&self) -> &str;
+
+    /// Bring the display up to date with the framebuffer's memory for the given area. Devices
+    /// that scan the memory out directly don't need to do anything.
+    fn flush(&self, area: Rect) -> Result<(), &'static str>;
+}
+
+
+
+/// A framebuffer, and the device that shows it.
+pub struct Framebuffer
+{
+    /// The framebuffer's name, fb0, fb1, etc.
+    name: String,
+
+    /// The kernel address of the framebuffer's memory.
+    address: usize,
+
+    /// The size of the framebuffer in pixels.
+    width: usize,
+    height: usize,
+
+    /// How many bytes there are from the start of one row to the next.
+    stride: usize,
+
+    /// How the pixels are laid out.
+    format: PixelFormat,
+
+    /// The device that shows the framebuffer.
+    device: Arc<dyn FramebufferDevice>,
+
+    /// The area drawn since the last flush. Only locked with interrupts disabled.
+    damage: SpinMutex<Rect>
+}
+
+
+
+impl Framebuffer
+{
+    /// Create a framebuffer for the memory at the given kernel address, which must be mapped for
+    /// the whole height of the framebuffer and stay valid for as long as the framebuffer exists.
+    pub fn new(name: String,
+               address: usize,
+               width: usize,
+               height: usize,
+               stride: usize,
+               format: PixelFormat,
+               device: Arc<dyn FramebufferDevice>) -> Result<Framebuffer, &'static str>
+    {
+        if    address == 0
+           || width == 0
+           || height == 0
+           || stride < width * format.bytes_per_pixel()
+        {
+            return Err("Invalid framebuffer geometry.");
+        }
+
+        Ok(Framebuffer
+            {
+                name,
+                address,
+                width,
+                height,
+                stride,
+                format,
+                device,
+                damage: SpinMutex::new(Rect::default())
+            })
+    }
+
+    /// The framebuffer's name.
+    pub fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    /// The name of the kind of device that shows the framebuffer.
+    pub fn device_name(&self) -> &str
+    {
+        self.device.name()
+    }
+
+    /// The width of the framebuffer in pixels.
+    pub fn width(&self) -> usize
+    {
+        self.width
+    }
+
+    /// The height of the framebuffer in pixels.
+    pub fn height(&self) -> usize
+    {
+        self.height
+    }
+
+    /// How many bytes there are from the start of one row to the next.
+    pub fn stride(&self) -> usize
+    {
+        self.stride
+    }
+
+    /// How the pixels are laid out.
+    pub fn format(&self) -> PixelFormat
+    {
+        self.format
+    }
+
+    /// The rectangle covering the whole framebuffer.
+    pub fn bounds(&self) -> Rect
+    {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// Set a single pixel, pixels outside of the framebuffer are ignored.
+    pub fn put_pixel(&self, x: usize, y: usize, color: Color)
+    {
+        if    x >= self.width
+           || y >= self.height
+        {
+            return;
+        }
+
+        self.write_pixel(x, y, color.encode(self.format));
+        self.add_damage(Rect::new(x, y, 1, 1));
+    }
+
+    /// Fill a rectangle with a color, clipped to the framebuffer.
+    pub fn fill_rect(&self, area: Rect, color: Color)
+    {
+        let area = area.intersection(&self.bounds());
+        let pixel = color.encode(self.format);
+
+        for y in area.y..area.bottom()
+        {
+            for x in area.x..area.right()
+            {
+                self.write_pixel(x, y, pixel);
+            }
+        }
+
+        self.add_damage(area);
+    }
+
+    /// Draw a two color image into a rectangle, clipped to the framebuffer. The mask is asked for
+    /// each pixel, by its position in the rectangle, whether it's in the foreground. This is how
+    /// the glyphs of bitmap fonts are drawn.
+    pub fn draw_mask<Mask>(&self, area: Rect, foreground: Color, background: Color, mask: Mask)
+        where Mask: Fn(usize, usize) -> bool
+    {
+        let clipped = area.intersection(&self.bounds());
+        let foreground = foreground.encode(self.format);
+        let background = background.encode(self.format);
+
+        for y in clipped.y..clipped.bottom()
+        {
+            for x in clipped.x..clipped.right()
+            {
+                let pixel = match mask(x - area.x, y - area.y)
+                    {
+                        true  => foreground,
+                        false => background
+                    };
+
+                self.write_pixel(x, y, pixel);
+            }
+        }
+
+        self.add_damage(clipped);
+    }
+
+    /// Fill the whole framebuffer with a color.
+    pub fn clear(&self, color: Color)
+    {
+        self.fill_rect(self.bounds(), color);
+    }
+
+    /// Copy a rectangle of pixels so that its top left corner is at the given position, the source
+    /// and destination can overlap, which is how scrolling is done. Both are clipped to the
+    /// framebuffer.
+    pub fn copy_rect(&self, source: Rect, x: usize, y: usize)
+    {
+        let source = source.intersection(&self.bounds());
+        let destination = Rect::new(x, y, source.width, source.height)
+            .intersection(&self.bounds());
+
+        if destination.is_empty()
+        {
+            return;
+        }
+
+        let row_bytes = destination.width * self.format.bytes_per_pixel();
+
+        // Copy the rows in the order that doesn't overwrite rows that haven't been copied yet.
+        let copy_row = |row: usize|
+            {
+                let from = self.pixel_address(source.x, source.y + row);
+                let to = self.pixel_address(destination.x, destination.y + row);
+
+                unsafe { copy(from as *const u8, to as *mut u8, row_bytes) };
+            };
+
+        if destination.y <= source.y
+        {
+            (0..destination.height).for_each(copy_row);
+        }
+        else
+        {
+            (0..destination.height).rev().for_each(copy_row);
+        }
+
+        self.add_damage(destination);
+    }
+
+    /// Mark an area as changed, for drawing done some other way than through the framebuffer's
+    /// methods.
+    pub fn add_damage(&self, area: Rect)
+    {
+        let area = area.intersection(&self.bounds());
+
+        if !area.is_empty()
+        {
+            with_interrupts_disabled(||
+                {
+                    let mut damage = self.damage.lock();
+
+                    *damage = damage.union(&area);
+                });
+        }
+    }
+
+    /// The area changed since the last flush, empty if nothing has changed.
+    pub fn damage(&self) -> Rect
+    {
+        with_interrupts_disabled(|| *self.damage.lock())
+    }
+
+    /// Show the changes made since the last flush.
+    pub fn flush(&self) -> Result<(), &'static str>
+    {
+        let damage = with_interrupts_disabled(|| core::mem::take(&mut *self.damage.lock()));
+
+        if damage.is_empty()
+        {
+            return Ok(());
+        }
+
+        self.device.flush(damage)
+    }
+
+    /// The address of a pixel in the framebuffer's memory.
+    fn pixel_address(&self, x: usize, y: usize) -> usize
+    {
+        self.address + y * self.stride + x * self.format.bytes_per_pixel()
+    }
+
+    /// Write an encoded pixel, the position must be in the framebuffer.
+    fn write_pixel(&self, x: usize, y: usize, pixel: u32)
+    {
+        let address = self.pixel_address(x, y);
+
+        unsafe
+        {
+            match self.format.bytes_per_pixel()
+            {
+                2 => write_volatile(address as *mut u16, pixel as u16),
+                _ => write_volatile(address as *mut u32, pixel)
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+// The graphics device subsystem. Graphics drivers register a framebuffer for each display they
+// drive, where the rest of the kernel, like the console, can find them by their index or name.
+//
+// Framebuffers come either from the device tree, set up before the kernel started, or from the
+// GPUs found on the VirtIO bus.
+
+use alloc::{ format, string::String, sync::Arc, vec::Vec };
+
+use xtra_kernel_shared::device_tree::DeviceTree;
+
+use crate::{ devices::{ bus_devices::virtio_devices::{ VirtioDriverRegistry,
+                                                       VIRTIO_GPU_DEVICE_ID },
+                        DeviceDriverRegistry },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// Framebuffers, the displays' memory and the drawing into it.
+pub mod framebuffer;
+
+/// Framebuffers described in the device tree.
+pub mod simple_framebuffer;
+
+/// Driver for VirtIO GPUs.
+pub mod virtio_gpu;
+
+
+
+use crate::devices::graphics_devices::framebuffer::Framebuffer;
+
+
+
+/// All of the framebuffers in the system, in the order they were found.
+static FRAMEBUFFERS: SpinMutex<Vec<Arc<Framebuffer>>> = SpinMutex::new(Vec::new());
+
+
+
+/// Register the driver probe functions for all of the graphics device drivers in the system.
+pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(), &'static str>
+{
+    registry.insert("framebuffer", simple_framebuffer::probe_simple_framebuffer);
+
+    Ok(())
+}
+
+
+/// Register the drivers for the graphics devices that can show up on the VirtIO bus.
+pub fn register_virtio_drivers(registry: &mut VirtioDriverRegistry) -> Result<(), &'static str>
+{
+    registry.insert(VIRTIO_GPU_DEVICE_ID, virtio_gpu::probe_virtio_gpu_device);
+
+    Ok(())
+}
+
+
+/// Activate and initialize the graphics devices discovered in the device tree. If any.
+pub fn activate_devices() -> Result<(), &'static str>
+{
+    simple_framebuffer::activate_devices()
+}
+
+
+
+/// Get the name for the next framebuffer to be registered.
+pub fn next_framebuffer_name() -> String
+{
+    format!("fb{}", FRAMEBUFFERS.lock().len())
+}
+
+
+/// Make a framebuffer available to the rest of the kernel, returning its index.
+pub fn register_framebuffer(framebuffer: Arc<Framebuffer>) -> usize
+{
+    let mut framebuffers = FRAMEBUFFERS.lock();
+
+    framebuffers.push(framebuffer);
+    framebuffers.len() - 1
+}
+
+
+/// Get a framebuffer by its index.
+pub fn get_framebuffer(index: usize) -> Option<Arc<Framebuffer>>
+{
+    FRAMEBUFFERS.lock().get(index).cloned()
+}
+
+
+/// Get a framebuffer by its name.
+pub fn find_framebuffer(name: &str) -> Option<Arc<Framebuffer>>
+{
+    FRAMEBUFFERS.lock().iter().find(|framebuffer| framebuffer.name() == name).cloned()
+}
+
+
+/// Get all of the framebuffers in the system.
+pub fn framebuffers() -> Vec<Arc<Framebuffer>>
+{
+    FRAMEBUFFERS.lock().clone()
+}
@@ -0,0 +1,170 @@
+// Framebuffers set up by the firmware or the bootloader before the kernel starts, described in the
+// device tree by a simple-framebuffer node. The display scans the memory out directly, so there's
+// nothing to do to flush them, which makes them usable from early in the boot.
+
+use alloc::{ string::String, sync::Arc, vec::Vec };
+
+use xtra_kernel_shared::device_tree::DeviceTree;
+
+use crate::{ devices::{ graphics_devices::{ framebuffer::{ Framebuffer,
+                                                           FramebufferDevice,
+                                                           PixelFormat,
+                                                           Rect },
+                                            next_framebuffer_name,
+                                            register_framebuffer },
+                        property_contains_string,
+                        read_property_cells,
+                        read_property_reg,
+                        read_property_string },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// A framebuffer found in the device tree, waiting for the devices to be activated.
+struct ProbedFramebuffer
+{
+    /// The address of the framebuffer's memory.
+    address: usize,
+
+    /// The size of the framebuffer in pixels.
+    width: usize,
+    height: usize,
+
+    /// How many bytes there are from the start of one row to the next.
+    stride: usize,
+
+    /// How the pixels are laid out.
+    format: PixelFormat
+}
+
+
+
+/// The display of a simple framebuffer, which shows the framebuffer's memory as it is.
+struct SimpleFramebufferDevice;
+
+
+
+impl FramebufferDevice for SimpleFramebufferDevice
+{
+    fn name(&self) -> &str
+    {
+        "simple-framebuffer"
+    }
+
+    fn flush(&self, area: Rect) -> Result<(), &'static str>
+    {
+        Ok(())
+    }
+}
+
+
+
+/// The framebuffers found in the device tree.
+static PROBED_FRAMEBUFFERS: SpinMutex<Vec<ProbedFramebuffer>> = SpinMutex::new(Vec::new());
+
+
+
+/// Probe a framebuffer node in the device tree, only simple-framebuffer nodes that are enabled are
+/// used.
+pub fn probe_simple_framebuffer(name: &str,
+                                address: Option<usize>,
+                                device_tree: &DeviceTree,
+                                block_offset: usize) -> Result<(), &'static str>
+{
+    let mut is_compatible = false;
+    let mut is_enabled = true;
+    let mut reg = None;
+    let mut width = None;
+    let mut height = None;
+    let mut stride = None;
+    let mut format_name = String::new();
+
+    device_tree.iterate_properties(block_offset, |property_name, property_value|
+        {
+            let cells = read_property_cells(property_value);
+
+            match property_name
+            {
+                "compatible" =>
+                    {
+                        is_compatible = property_contains_string(property_value,
+                                                                 "simple-framebuffer");
+                    },
+
+                "status" => is_enabled = read_property_string(property_value) == "okay",
+                "reg"    => reg = read_property_reg(property_value),
+                "width"  => width = cells.map(|width| width as usize),
+                "height" => height = cells.map(|height| height as usize),
+                "stride" => stride = cells.map(|stride| stride as usize),
+                "format" => format_name = read_property_string(property_value),
+
+                _ => {}
+            }
+
+            true
+        });
+
+    if    !is_compatible
+       || !is_enabled
+    {
+        return Ok(());
+    }
+
+    let (address, _) = reg.ok_or("Framebuffer is missing its reg property.")?;
+    let format = PixelFormat::from_name(&format_name)
+        .ok_or("Framebuffer has an unsupported pixel format.")?;
+
+    let width = width.ok_or("Framebuffer is missing its width.")?;
+    let height = height.ok_or("Framebuffer is missing its height.")?;
+
+    PROBED_FRAMEBUFFERS.lock().push(ProbedFramebuffer
+        {
+            address,
+            width,
+            height,
+            stride: stride.unwrap_or(width * format.bytes_per_pixel()),
+            format
+        });
+
+    Ok(())
+}
+
+
+/// Register the framebuffers found in the device tree.
+pub fn activate_devices() -> Result<(), &'static str>
+{
+    for probed in PROBED_FRAMEBUFFERS.lock().iter()
+    {
+        let framebuffer = Framebuffer::new(next_framebuffer_name(),
+                                           probed.address,
+                                           probed.width,
+                                           probed.height,
+                                           probed.stride,
+                                           probed.format,
+                                           Arc::new(SimpleFramebufferDevice));
+
+        match framebuffer
+        {
+            Ok(framebuffer) =>
+                {
+                    println!("  {}: {}x{} {} (simple-framebuffer at 0x{:016x})",
+                             framebuffer.name(),
+                             probed.width,
+                             probed.height,
+                             probed.format,
+                             probed.address);
+
+                    register_framebuffer(Arc::new(framebuffer));
+                },
+
+            Err(error) =>
+                {
+                    println!("  Failed to use the framebuffer at 0x{:016x}: {}",
+                             probed.address,
+                             error);
+                }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,492 @@
+// Driver for VirtIO GPUs in 2D mode, what QEMU provides with its virtio-gpu-device. The GPU keeps
+// its own copy of each image, a resource, which is backed by pages of kernel memory. We draw into
+// the pages and then ask the GPU to transfer the damaged area into the resource and flush it to the
+// scanout, the display, that shows the resource.
+//
+// Every enabled scanout gets a resource the size of the display and becomes a framebuffer.
+//
+// Commands are sent on the control queue and waited for, the way the block driver works. The
+// console flushes its framebuffer from wherever it's printed from, so the queue is only locked with
+// interrupts disabled.
+
+use core::{ hint::spin_loop, mem::size_of, ptr::{ read_volatile, write_bytes } };
+
+use alloc::sync::Arc;
+
+use crate::{ arch::interrupts::with_interrupts_disabled,
+             devices::{ bus_devices::virtio_devices::{ mmio::VirtioMmioDevice,
+                                                       virtqueue::{ VirtQueue,
+                                                                    VirtQueueBuffer } },
+                        graphics_devices::{ framebuffer::{ Framebuffer,
+                                                           FramebufferDevice,
+                                                           PixelFormat,
+                                                           Rect },
+                                            next_framebuffer_name,
+                                            register_framebuffer } },
+             locking::spin_mutex::SpinMutex,
+             memory::{ mmu::{ allocate_n_pages, free_n_pages, physical_address_of },
+                       PAGE_SIZE } };
+
+
+
+// Control queue commands.
+const VIRTIO_GPU_CMD_GET_DISPLAY_INFO:        u32 = 0x0100;
+const VIRTIO_GPU_CMD_RESOURCE_CREATE_2D:      u32 = 0x0101;
+const VIRTIO_GPU_CMD_SET_SCANOUT:             u32 = 0x0103;
+const VIRTIO_GPU_CMD_RESOURCE_FLUSH:          u32 = 0x0104;
+const VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D:     u32 = 0x0105;
+const VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+
+// Responses.
+const VIRTIO_GPU_RESP_OK_NODATA:         u32 = 0x1100;
+const VIRTIO_GPU_RESP_OK_DISPLAY_INFO:   u32 = 0x1101;
+const VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY: u32 = 0x1201;
+
+// Resource formats, named by the bytes in memory order. B8G8R8X8 is an x8r8g8b8 pixel.
+const VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM: u32 = 2;
+
+// Offsets into the device configuration space.
+const CONFIG_NUM_SCANOUTS: usize = 0x08;  // How many scanouts the GPU has.
+
+
+
+/// The most scanouts a GPU can have.
+const MAX_SCANOUTS: usize = 16;
+
+/// The size of the control queue we ask the device for.
+const CONTROL_QUEUE_SIZE: u16 = 16;
+
+/// The size of the display used if the GPU doesn't say, for a scanout that's not enabled yet.
+const DEFAULT_WIDTH: u32 = 1024;
+const DEFAULT_HEIGHT: u32 = 768;
+
+
+
+/// The header that starts every command and response.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct ControlHeader
+{
+    command_type: u32,  // The command, or the response type.
+    flags: u32,         // Fencing flags, we don't fence.
+    fence_id: u64,      // The fence to signal.
+    context_id: u32,    // The 3D context, unused in 2D mode.
+    padding: u32
+}
+
+
+
+/// A rectangle as the GPU sees it.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct GpuRect
+{
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32
+}
+
+
+
+/// A scanout's entry in the display info.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct DisplayOne
+{
+    rect: GpuRect,  // The display's size, and its position among the other displays.
+    enabled: u32,   // Is there a display attached?
+    flags: u32
+}
+
+
+
+/// The response to VIRTIO_GPU_CMD_GET_DISPLAY_INFO.
+#[repr(C)]
+struct DisplayInfoResponse
+{
+    header: ControlHeader,
+    displays: [DisplayOne; MAX_SCANOUTS]
+}
+
+
+
+/// VIRTIO_GPU_CMD_RESOURCE_CREATE_2D
+#[repr(C)]
+struct ResourceCreate2d
+{
+    header: ControlHeader,
+    resource_id: u32,
+    format: u32,
+    width: u32,
+    height: u32
+}
+
+
+
+/// One piece of a resource's backing memory.
+#[repr(C)]
+struct MemoryEntry
+{
+    address: u64,  // Physical address of the memory.
+    length: u32,
+    padding: u32
+}
+
+
+
+/// VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING, with the single entry our backing memory needs as it's
+/// physically contiguous.
+#[repr(C)]
+struct ResourceAttachBacking
+{
+    header: ControlHeader,
+    resource_id: u32,
+    entry_count: u32,
+    entry: MemoryEntry
+}
+
+
+
+/// VIRTIO_GPU_CMD_SET_SCANOUT
+#[repr(C)]
+struct SetScanout
+{
+    header: ControlHeader,
+    rect: GpuRect,
+    scanout_id: u32,
+    resource_id: u32
+}
+
+
+
+/// VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D
+#[repr(C)]
+struct TransferToHost2d
+{
+    header: ControlHeader,
+    rect: GpuRect,
+    offset: u64,  // Where the rectangle starts in the backing memory.
+    resource_id: u32,
+    padding: u32
+}
+
+
+
+/// VIRTIO_GPU_CMD_RESOURCE_FLUSH
+#[repr(C)]
+struct ResourceFlush
+{
+    header: ControlHeader,
+    rect: GpuRect,
+    resource_id: u32,
+    padding: u32
+}
+
+
+
+/// A VirtIO GPU.
+pub struct VirtioGpuDevice
+{
+    /// The device's register interface.
+    device: VirtioMmioDevice,
+
+    /// The control queue, only locked with interrupts disabled.
+    control_queue: SpinMutex<VirtQueue>
+}
+
+
+
+impl VirtioGpuDevice
+{
+    /// Perform the VirtIO initialization handshake for the GPU and get it ready for commands.
+    pub fn new(device: VirtioMmioDevice) -> Result<VirtioGpuDevice, &'static str>
+    {
+        device.begin_initialization();
+        device.negotiate_features(0)?;
+
+        let control_queue = device.setup_queue(0, CONTROL_QUEUE_SIZE)?;
+
+        device.finish_initialization();
+
+        Ok(VirtioGpuDevice { device, control_queue: SpinMutex::new(control_queue) })
+    }
+
+    /// The number of scanouts the GPU has.
+    pub fn scanout_count(&self) -> usize
+    {
+        (self.device.config_read_u32(CONFIG_NUM_SCANOUTS) as usize).min(MAX_SCANOUTS)
+    }
+
+    /// Send a command and wait for the device's response.
+    fn submit<Command, Response>(&self,
+                                 command: &Command,
+                                 response: &mut Response) -> Result<(), &'static str>
+    {
+        let command_buffer = VirtQueueBuffer
+            {
+                address: command as *const Command as usize,
+                length: size_of::<Command>(),
+                device_writable: false
+            };
+
+        let response_buffer = VirtQueueBuffer
+            {
+                address: response as *mut Response as usize,
+                length: size_of::<Response>(),
+                device_writable: true
+            };
+
+        with_interrupts_disabled(||
+            {
+                let mut queue = self.control_queue.lock();
+                let head = queue.add_buffers(&[command_buffer, response_buffer])?;
+
+                self.device.notify_queue(queue.index());
+
+                // Only one command is in flight at a time as we hold the queue lock, so the next
+                // used buffer is ours.
+                loop
+                {
+                    if let Some(used) = queue.pop_used()
+                    {
+                        if used.head != head
+                        {
+                            return Err("VirtIO GPU completed an unexpected command.");
+                        }
+
+                        break;
+                    }
+
+                    spin_loop();
+                }
+
+                self.device.acknowledge_interrupt();
+
+                Ok(())
+            })
+    }
+
+    /// Send a command that has no data in its response, and check that it worked.
+    fn command<Command>(&self, command: &Command) -> Result<(), &'static str>
+    {
+        let mut response = ControlHeader::default();
+
+        self.submit(command, &mut response)?;
+
+        match unsafe { read_volatile(&response.command_type) }
+        {
+            VIRTIO_GPU_RESP_OK_NODATA         => Ok(()),
+            VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY => Err("VirtIO GPU is out of memory."),
+            _                                 => Err("VirtIO GPU failed a command.")
+        }
+    }
+
+    /// Ask the GPU about the displays attached to its scanouts.
+    fn display_info(&self) -> Result<[DisplayOne; MAX_SCANOUTS], &'static str>
+    {
+        let command = header(VIRTIO_GPU_CMD_GET_DISPLAY_INFO);
+        let mut response = DisplayInfoResponse
+            {
+                header: ControlHeader::default(),
+                displays: [DisplayOne::default(); MAX_SCANOUTS]
+            };
+
+        self.submit(&command, &mut response)?;
+
+        let response_type = unsafe { read_volatile(&response.header.command_type) };
+
+        if response_type != VIRTIO_GPU_RESP_OK_DISPLAY_INFO
+        {
+            return Err("VirtIO GPU failed to report its displays.");
+        }
+
+        Ok(unsafe { read_volatile(&response.displays) })
+    }
+
+    /// Create a resource backed by the memory at the given kernel address and show it on the
+    /// scanout.
+    fn attach_scanout(&self,
+                      scanout_id: u32,
+                      resource_id: u32,
+                      width: u32,
+                      height: u32,
+                      address: usize) -> Result<(), &'static str>
+    {
+        let rect = GpuRect { x: 0, y: 0, width, height };
+
+        self.command(&ResourceCreate2d
+            {
+                header: header(VIRTIO_GPU_CMD_RESOURCE_CREATE_2D),
+                resource_id,
+                format: VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM,
+                width,
+                height
+            })?;
+
+        self.command(&ResourceAttachBacking
+            {
+                header: header(VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING),
+                resource_id,
+                entry_count: 1,
+                entry: MemoryEntry
+                    {
+                        address: physical_address_of(address) as u64,
+                        length: width * height * 4,
+                        padding: 0
+                    }
+            })?;
+
+        self.command(&SetScanout
+            {
+                header: header(VIRTIO_GPU_CMD_SET_SCANOUT),
+                rect,
+                scanout_id,
+                resource_id
+            })
+    }
+}
+
+
+
+/// One of the GPU's scanouts, showing a framebuffer.
+struct VirtioGpuScanout
+{
+    /// The GPU the scanout is on.
+    gpu: Arc<VirtioGpuDevice>,
+
+    /// The resource holding the framebuffer's image.
+    resource_id: u32,
+
+    /// How many bytes there are from one row of the resource to the next.
+    stride: usize
+}
+
+
+
+impl FramebufferDevice for VirtioGpuScanout
+{
+    fn name(&self) -> &str
+    {
+        "virtio-gpu"
+    }
+
+    fn flush(&self, area: Rect) -> Result<(), &'static str>
+    {
+        let rect = GpuRect
+            {
+                x: area.x as u32,
+                y: area.y as u32,
+                width: area.width as u32,
+                height: area.height as u32
+            };
+
+        self.gpu.command(&TransferToHost2d
+            {
+                header: header(VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D),
+                rect,
+                offset: (area.y * self.stride + area.x * 4) as u64,
+                resource_id: self.resource_id,
+                padding: 0
+            })?;
+
+        self.gpu.command(&ResourceFlush
+            {
+                header: header(VIRTIO_GPU_CMD_RESOURCE_FLUSH),
+                rect,
+                resource_id: self.resource_id,
+                padding: 0
+            })
+    }
+}
+
+
+
+/// Called by the VirtIO bus when it finds a GPU. Each of the GPU's enabled scanouts becomes a
+/// framebuffer. A GPU that doesn't have a display enabled yet still gets one on its first scanout,
+/// so that it has something to show once a display is attached.
+pub fn probe_virtio_gpu_device(device: VirtioMmioDevice) -> Result<(), &'static str>
+{
+    let gpu = Arc::new(VirtioGpuDevice::new(device)?);
+    let displays = gpu.display_info()?;
+    let scanout_count = gpu.scanout_count();
+
+    let mut scanouts = displays.iter()
+        .take(scanout_count)
+        .enumerate()
+        .filter(|(_, display)| display.enabled != 0)
+        .map(|(scanout_id, display)| (scanout_id, display.rect.width, display.rect.height))
+        .peekable();
+
+    if scanouts.peek().is_none()
+    {
+        return create_framebuffer(&gpu, 0, DEFAULT_WIDTH, DEFAULT_HEIGHT);
+    }
+
+    for (scanout_id, width, height) in scanouts
+    {
+        create_framebuffer(&gpu, scanout_id, width, height)?;
+    }
+
+    Ok(())
+}
+
+
+
+/// Allocate the memory for a scanout's framebuffer, attach it to the scanout and register the
+/// framebuffer.
+fn create_framebuffer(gpu: &Arc<VirtioGpuDevice>,
+                      scanout_id: usize,
+                      width: u32,
+                      height: u32) -> Result<(), &'static str>
+{
+    let stride = width as usize * 4;
+    let page_count = (stride * height as usize).div_ceil(PAGE_SIZE);
+
+    let pages = allocate_n_pages(page_count).ok_or("Not enough memory for the framebuffer.")?;
+    let address = pages.head.as_usize();
+
+    unsafe { write_bytes(address as *mut u8, 0, page_count * PAGE_SIZE) };
+
+    // Resource IDs start at 1, 0 means no resource.
+    let resource_id = scanout_id as u32 + 1;
+
+    if let Err(error) = gpu.attach_scanout(scanout_id as u32, resource_id, width, height, address)
+    {
+        free_n_pages(pages);
+        return Err(error);
+    }
+
+    let scanout = Arc::new(VirtioGpuScanout { gpu: gpu.clone(), resource_id, stride });
+
+    let framebuffer = Arc::new(Framebuffer::new(next_framebuffer_name(),
+                                                address,
+                                                width as usize,
+                                                height as usize,
+                                                stride,
+                                                PixelFormat::Xrgb8888,
+                                                scanout)?);
+
+    // Show the cleared framebuffer, so that whatever the display had on it is gone.
+    framebuffer.add_damage(framebuffer.bounds());
+    framebuffer.flush()?;
+
+    println!("  {}: {}x{} {} (virtio-gpu scanout {})",
+             framebuffer.name(),
+             width,
+             height,
+             framebuffer.format(),
+             scanout_id);
+
+    register_framebuffer(framebuffer);
+
+    Ok(())
+}
+
+
+
+/// Create the header for a command.
+fn header(command_type: u32) -> ControlHeader
+{
+    ControlHeader { command_type, .. ControlHeader::default() }
+}
@@ -0,0 +1,203 @@
+// The keyboard layouts built into the kernel. Every layout starts from the keys that are the same
+// everywhere, the function, navigation and modifier keys, and the US layout is the base the others
+// change.
+
+use alloc::{ vec, vec::Vec };
+
+use crate::devices::hid_devices::{ key_codes::*,
+                                   keymaps::{ DeadKey, KeyDefinition, KeySymbol, Keymap },
+                                   KeyCode };
+
+
+
+/// The keys that don't change between layouts.
+const COMMON_KEYS: [(KeyCode, KeySymbol); 38] =
+    [
+        (KEY_ESC,        KeySymbol::Escape),
+        (KEY_BACKSPACE,  KeySymbol::Backspace),
+        (KEY_TAB,        KeySymbol::Tab),
+        (KEY_ENTER,      KeySymbol::Enter),
+        (KEY_SPACE,      KeySymbol::Character(' ')),
+
+        (KEY_LEFTSHIFT,  KeySymbol::Shift),
+        (KEY_RIGHTSHIFT, KeySymbol::Shift),
+        (KEY_LEFTCTRL,   KeySymbol::Control),
+        (KEY_RIGHTCTRL,  KeySymbol::Control),
+        (KEY_LEFTALT,    KeySymbol::Alt),
+        (KEY_RIGHTALT,   KeySymbol::Alt),
+        (KEY_LEFTMETA,   KeySymbol::Meta),
+        (KEY_RIGHTMETA,  KeySymbol::Meta),
+
+        (KEY_CAPSLOCK,   KeySymbol::CapsLock),
+        (KEY_NUMLOCK,    KeySymbol::NumLock),
+        (KEY_SCROLLLOCK, KeySymbol::ScrollLock),
+
+        (KEY_F1,         KeySymbol::Function(1)),
+        (KEY_F2,         KeySymbol::Function(2)),
+        (KEY_F3,         KeySymbol::Function(3)),
+        (KEY_F4,         KeySymbol::Function(4)),
+        (KEY_F5,         KeySymbol::Function(5)),
+        (KEY_F6,         KeySymbol::Function(6)),
+        (KEY_F7,         KeySymbol::Function(7)),
+        (KEY_F8,         KeySymbol::Function(8)),
+        (KEY_F9,         KeySymbol::Function(9)),
+        (KEY_F10,        KeySymbol::Function(10)),
+        (KEY_F11,        KeySymbol::Function(11)),
+        (KEY_F12,        KeySymbol::Function(12)),
+
+        (KEY_HOME,       KeySymbol::Home),
+        (KEY_END,        KeySymbol::End),
+        (KEY_PAGEUP,     KeySymbol::PageUp),
+        (KEY_PAGEDOWN,   KeySymbol::PageDown),
+        (KEY_INSERT,     KeySymbol::Insert),
+        (KEY_DELETE,     KeySymbol::Delete),
+        (KEY_UP,         KeySymbol::Up),
+        (KEY_DOWN,       KeySymbol::Down),
+        (KEY_LEFT,       KeySymbol::Left),
+        (KEY_RIGHT,      KeySymbol::Right)
+    ];
+
+
+
+/// The letter keys, by what they type on a US keyboard.
+const LETTER_KEYS: [(KeyCode, char); 26] =
+    [
+        (KEY_A, 'a'), (KEY_B, 'b'), (KEY_C, 'c'), (KEY_D, 'd'), (KEY_E, 'e'), (KEY_F, 'f'),
+        (KEY_G, 'g'), (KEY_H, 'h'), (KEY_I, 'i'), (KEY_J, 'j'), (KEY_K, 'k'), (KEY_L, 'l'),
+        (KEY_M, 'm'), (KEY_N, 'n'), (KEY_O, 'o'), (KEY_P, 'p'), (KEY_Q, 'q'), (KEY_R, 'r'),
+        (KEY_S, 's'), (KEY_T, 't'), (KEY_U, 'u'), (KEY_V, 'v'), (KEY_W, 'w'), (KEY_X, 'x'),
+        (KEY_Y, 'y'), (KEY_Z, 'z')
+    ];
+
+
+
+/// The rest of the US layout, the digits and punctuation.
+const US_KEYS: [(KeyCode, KeyDefinition); 22] =
+    [
+        (KEY_1,          KeyDefinition::pair('1', '!')),
+        (KEY_2,          KeyDefinition::pair('2', '@')),
+        (KEY_3,          KeyDefinition::pair('3', '#')),
+        (KEY_4,          KeyDefinition::pair('4', '$')),
+        (KEY_5,          KeyDefinition::pair('5', '%')),
+        (KEY_6,          KeyDefinition::pair('6', '^')),
+        (KEY_7,          KeyDefinition::pair('7', '&')),
+        (KEY_8,          KeyDefinition::pair('8', '*')),
+        (KEY_9,          KeyDefinition::pair('9', '(')),
+        (KEY_0,          KeyDefinition::pair('0', ')')),
+        (KEY_MINUS,      KeyDefinition::pair('-', '_')),
+        (KEY_EQUAL,      KeyDefinition::pair('=', '+')),
+        (KEY_LEFTBRACE,  KeyDefinition::pair('[', '{')),
+        (KEY_RIGHTBRACE, KeyDefinition::pair(']', '}')),
+        (KEY_SEMICOLON,  KeyDefinition::pair(';', ':')),
+        (KEY_APOSTROPHE, KeyDefinition::pair('\'', '"')),
+        (KEY_GRAVE,      KeyDefinition::pair('`', '~')),
+        (KEY_BACKSLASH,  KeyDefinition::pair('\\', '|')),
+        (KEY_COMMA,      KeyDefinition::pair(',', '<')),
+        (KEY_DOT,        KeyDefinition::pair('.', '>')),
+        (KEY_SLASH,      KeyDefinition::pair('/', '?')),
+        (KEY_102ND,      KeyDefinition::pair('\\', '|'))
+    ];
+
+
+
+/// The changes the UK layout makes to the US one.
+const UK_KEYS: [(KeyCode, KeyDefinition); 8] =
+    [
+        (KEY_RIGHTALT,   KeyDefinition::symbol(KeySymbol::AltGr)),
+        (KEY_2,          KeyDefinition::pair('2', '"')),
+        (KEY_3,          KeyDefinition::pair('3', '£')),
+        (KEY_4,          KeyDefinition::pair('4', '$').with_alt_gr('€')),
+        (KEY_APOSTROPHE, KeyDefinition::pair('\'', '@')),
+        (KEY_GRAVE,      KeyDefinition::pair('`', '¬').with_alt_gr('¦')),
+        (KEY_BACKSLASH,  KeyDefinition::pair('#', '~')),
+        (KEY_102ND,      KeyDefinition::pair('\\', '|'))
+    ];
+
+
+
+/// The changes the German layout makes to the US one. The acute and grave accents and the
+/// circumflex are dead keys.
+const DE_KEYS: [(KeyCode, KeyDefinition); 26] =
+    [
+        (KEY_RIGHTALT,   KeyDefinition::symbol(KeySymbol::AltGr)),
+        (KEY_2,          KeyDefinition::pair('2', '"').with_alt_gr('²')),
+        (KEY_3,          KeyDefinition::pair('3', '§').with_alt_gr('³')),
+        (KEY_4,          KeyDefinition::pair('4', '$')),
+        (KEY_6,          KeyDefinition::pair('6', '&')),
+        (KEY_7,          KeyDefinition::pair('7', '/').with_alt_gr('{')),
+        (KEY_8,          KeyDefinition::pair('8', '(').with_alt_gr('[')),
+        (KEY_9,          KeyDefinition::pair('9', ')').with_alt_gr(']')),
+        (KEY_0,          KeyDefinition::pair('0', '=').with_alt_gr('}')),
+        (KEY_MINUS,      KeyDefinition::pair('ß', '?').with_alt_gr('\\')),
+        (KEY_EQUAL,      KeyDefinition::dead(DeadKey::Acute, DeadKey::Grave)),
+        (KEY_Q,          KeyDefinition::letter('q', 'Q').with_alt_gr('@')),
+        (KEY_E,          KeyDefinition::letter('e', 'E').with_alt_gr('€')),
+        (KEY_Y,          KeyDefinition::letter('z', 'Z')),
+        (KEY_Z,          KeyDefinition::letter('y', 'Y')),
+        (KEY_M,          KeyDefinition::letter('m', 'M').with_alt_gr('µ')),
+        (KEY_LEFTBRACE,  KeyDefinition::letter('ü', 'Ü')),
+        (KEY_RIGHTBRACE, KeyDefinition::pair('+', '*').with_alt_gr('~')),
+        (KEY_SEMICOLON,  KeyDefinition::letter('ö', 'Ö')),
+        (KEY_APOSTROPHE, KeyDefinition::letter('ä', 'Ä')),
+        (KEY_GRAVE,      KeyDefinition::new([ Some(KeySymbol::Dead(DeadKey::Circumflex)),
+                                              Some(KeySymbol::Character('°')),
+                                              None,
+                                              None ],
+                                            false)),
+        (KEY_BACKSLASH,  KeyDefinition::pair('#', '\'')),
+        (KEY_COMMA,      KeyDefinition::pair(',', ';')),
+        (KEY_DOT,        KeyDefinition::pair('.', ':')),
+        (KEY_SLASH,      KeyDefinition::pair('-', '_')),
+        (KEY_102ND,      KeyDefinition::pair('<', '>').with_alt_gr('|'))
+    ];
+
+
+
+/// Build all of the layouts built into the kernel.
+pub fn built_in_keymaps() -> Vec<Keymap>
+{
+    vec![ us(), uk(), de() ]
+}
+
+
+/// The US layout.
+pub fn us() -> Keymap
+{
+    let mut keymap = Keymap::new("us", "English (US)");
+
+    for (key_code, symbol) in COMMON_KEYS
+    {
+        keymap.set_key(key_code, KeyDefinition::symbol(symbol));
+    }
+
+    for (key_code, letter) in LETTER_KEYS
+    {
+        keymap.set_key(key_code, KeyDefinition::letter(letter, letter.to_ascii_uppercase()));
+    }
+
+    keymap.set_keys(&US_KEYS);
+
+    keymap
+}
+
+
+/// The UK layout.
+pub fn uk() -> Keymap
+{
+    let mut keymap = us().renamed("uk", "English (UK)");
+
+    keymap.set_keys(&UK_KEYS);
+
+    keymap
+}
+
+
+/// The German layout.
+pub fn de() -> Keymap
+{
+    let mut keymap = us().renamed("de", "German");
+
+    keymap.set_keys(&DE_KEYS);
+
+    keymap
+}
@@ -0,0 +1,694 @@
+// The keymap subsystem. The keyboard drivers report which keys are pressed by their key codes, the
+// keymap says what those keys stand for on the user's keyboard layout so that the rest of the
+// kernel can work with characters instead of keys.
+//
+// The subsystem listens to every keyboard. Each key press is translated into a keystroke, which is
+// handed to the registered keystroke handlers and queued as the bytes a terminal would send for it,
+// which is where the console takes its keyboard input from.
+//
+// Holding a key down repeats it. The repeats are generated here from a timer rather than by the
+// keyboards, so that every keyboard repeats at the same rate.
+//
+// The layouts built into the kernel are picked with the keymap= kernel parameter, more layouts can
+// be registered at run time.
+
+use core::sync::atomic::{ AtomicU64, AtomicUsize, Ordering };
+
+use alloc::{ collections::BTreeMap, string::String, sync::Arc, vec::Vec };
+
+use crate::{ arch::interrupts::with_interrupts_disabled,
+             command_line::{ KernelParameter, KernelParameterRegistry },
+             devices::hid_devices::{ register_any_keyboard_handlers,
+                                     HandlerTable,
+                                     KeyCode,
+                                     KeyModifiers,
+                                     KeyboardDevice },
+             locking::spin_mutex::SpinMutex,
+             ring_buffer::RingBuffer,
+             timers::{ cancel_timer, start_timer } };
+
+
+
+/// The keyboard layouts built into the kernel.
+pub mod layouts;
+
+/// Turning key presses into keystrokes.
+pub mod translator;
+
+
+
+use crate::devices::hid_devices::keymaps::translator::{ KeyboardTranslator,
+                                                        Keystroke,
+                                                        LockState,
+                                                        MAX_TERMINAL_SEQUENCE };
+
+
+
+/// The keymap used if the keymap= parameter doesn't pick one.
+pub const DEFAULT_KEYMAP: &str = "us";
+
+/// How long a key has to be held down before it starts repeating.
+pub const DEFAULT_REPEAT_DELAY_MS: u64 = 500;
+
+/// How long between repeats once a key is repeating, about 30 a second.
+pub const DEFAULT_REPEAT_INTERVAL_MS: u64 = 33;
+
+/// How much typed input is kept for the console before it's read.
+const TYPED_INPUT_SIZE: usize = 1024;
+
+
+
+/// The accents a dead key can put on the key typed after it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeadKey
+{
+    Grave,
+    Acute,
+    Circumflex,
+    Tilde,
+    Diaeresis
+}
+
+
+
+impl DeadKey
+{
+    /// The accent on its own, typed when the dead key is followed by space or by a key it can't
+    /// accent.
+    pub fn spacing_character(self) -> char
+    {
+        match self
+        {
+            DeadKey::Grave      => '`',
+            DeadKey::Acute      => '´',
+            DeadKey::Circumflex => '^',
+            DeadKey::Tilde      => '~',
+            DeadKey::Diaeresis  => '¨'
+        }
+    }
+
+    /// Put the accent on a character, None if there's no accented form of the character.
+    pub fn compose(self, base: char) -> Option<char>
+    {
+        const GRAVE: [(char, char); 10] =
+            [ ('a', 'à'), ('e', 'è'), ('i', 'ì'), ('o', 'ò'), ('u', 'ù'),
+              ('A', 'À'), ('E', 'È'), ('I', 'Ì'), ('O', 'Ò'), ('U', 'Ù') ];
+
+        const ACUTE: [(char, char); 12] =
+            [ ('a', 'á'), ('e', 'é'), ('i', 'í'), ('o', 'ó'), ('u', 'ú'), ('y', 'ý'),
+              ('A', 'Á'), ('E', 'É'), ('I', 'Í'), ('O', 'Ó'), ('U', 'Ú'), ('Y', 'Ý') ];
+
+        const CIRCUMFLEX: [(char, char); 10] =
+            [ ('a', 'â'), ('e', 'ê'), ('i', 'î'), ('o', 'ô'), ('u', 'û'),
+              ('A', 'Â'), ('E', 'Ê'), ('I', 'Î'), ('O', 'Ô'), ('U', 'Û') ];
+
+        const TILDE: [(char, char); 6] =
+            [ ('a', 'ã'), ('n', 'ñ'), ('o', 'õ'), ('A', 'Ã'), ('N', 'Ñ'), ('O', 'Õ') ];
+
+        const DIAERESIS: [(char, char); 11] =
+            [ ('a', 'ä'), ('e', 'ë'), ('i', 'ï'), ('o', 'ö'), ('u', 'ü'), ('y', 'ÿ'),
+              ('A', 'Ä'), ('E', 'Ë'), ('I', 'Ï'), ('O', 'Ö'), ('U', 'Ü') ];
+
+        if base == ' '
+        {
+            return Some(self.spacing_character());
+        }
+
+        let table: &[(char, char)] = match self
+            {
+                DeadKey::Grave      => &GRAVE,
+                DeadKey::Acute      => &ACUTE,
+                DeadKey::Circumflex => &CIRCUMFLEX,
+                DeadKey::Tilde      => &TILDE,
+                DeadKey::Diaeresis  => &DIAERESIS
+            };
+
+        table.iter().find(|&&(plain, _)| plain == base).map(|&(_, accented)| accented)
+    }
+}
+
+
+
+/// What a key stands for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeySymbol
+{
+    /// The key types a character.
+    Character(char),
+
+    /// The key accents the next key typed.
+    Dead(DeadKey),
+
+    // Keys that don't type a character.
+    Enter,
+    Backspace,
+    Tab,
+    Escape,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+
+    /// A function key, F1 is 1.
+    Function(u8),
+
+    // The modifiers.
+    Shift,
+    Control,
+    Alt,
+    AltGr,
+    Meta,
+
+    // The lock keys.
+    CapsLock,
+    NumLock,
+    ScrollLock
+}
+
+
+
+impl KeySymbol
+{
+    /// Is this one of the modifier keys?
+    pub fn is_modifier(&self) -> bool
+    {
+        matches!(self,
+                 KeySymbol::Shift | KeySymbol::Control | KeySymbol::Alt | KeySymbol::AltGr |
+                 KeySymbol::Meta)
+    }
+
+    /// Is this one of the lock keys?
+    pub fn is_lock(&self) -> bool
+    {
+        matches!(self, KeySymbol::CapsLock | KeySymbol::NumLock | KeySymbol::ScrollLock)
+    }
+
+    /// Does holding the key down repeat it?
+    pub fn repeats(&self) -> bool
+    {
+           !self.is_modifier()
+        && !self.is_lock()
+        && !matches!(self, KeySymbol::Dead(_))
+    }
+}
+
+
+
+/// What a key stands for on each of its levels.
+#[derive(Clone, Copy, Debug)]
+pub struct KeyDefinition
+{
+    /// The key's symbols, plain, with shift, with AltGr and with shift and AltGr.
+    pub levels: [Option<KeySymbol>; 4],
+
+    /// Does caps lock act as shift for the key? It does for the letters.
+    pub caps_lock: bool
+}
+
+
+
+impl KeyDefinition
+{
+    /// Define a key by its levels.
+    pub const fn new(levels: [Option<KeySymbol>; 4], caps_lock: bool) -> KeyDefinition
+    {
+        KeyDefinition { levels, caps_lock }
+    }
+
+    /// A key that's the same on every level, like the function keys.
+    pub const fn symbol(symbol: KeySymbol) -> KeyDefinition
+    {
+        KeyDefinition::new([ Some(symbol); 4 ], false)
+    }
+
+    /// A letter key, caps lock acts as shift for it.
+    pub const fn letter(lower: char, upper: char) -> KeyDefinition
+    {
+        KeyDefinition::new([ Some(KeySymbol::Character(lower)),
+                             Some(KeySymbol::Character(upper)),
+                             None,
+                             None ],
+                           true)
+    }
+
+    /// A key that types one character, and another with shift.
+    pub const fn pair(plain: char, shifted: char) -> KeyDefinition
+    {
+        KeyDefinition::new([ Some(KeySymbol::Character(plain)),
+                             Some(KeySymbol::Character(shifted)),
+                             None,
+                             None ],
+                           false)
+    }
+
+    /// A dead key, and another with shift.
+    pub const fn dead(plain: DeadKey, shifted: DeadKey) -> KeyDefinition
+    {
+        KeyDefinition::new([ Some(KeySymbol::Dead(plain)),
+                             Some(KeySymbol::Dead(shifted)),
+                             None,
+                             None ],
+                           false)
+    }
+
+    /// The same key, typing a character with AltGr.
+    pub const fn with_alt_gr(self, character: char) -> KeyDefinition
+    {
+        let mut definition = self;
+
+        definition.levels[2] = Some(KeySymbol::Character(character));
+        definition
+    }
+
+    /// What the key stands for with the given modifiers. A level the key doesn't have falls back
+    /// to the level without AltGr, then to the plain level.
+    pub fn symbol_for(&self, shift: bool, alt_gr: bool) -> Option<KeySymbol>
+    {
+        let level = (alt_gr as usize) * 2 + shift as usize;
+
+        self.levels[level].or(self.levels[shift as usize]).or(self.levels[0])
+    }
+}
+
+
+
+/// A keyboard layout, what each key stands for.
+#[derive(Clone)]
+pub struct Keymap
+{
+    /// The short name the layout is picked by, like us or de.
+    name: String,
+
+    /// A description of the layout.
+    description: String,
+
+    /// What each key stands for, keys that aren't here do nothing.
+    keys: BTreeMap<KeyCode, KeyDefinition>
+}
+
+
+
+impl Keymap
+{
+    /// Create an empty keymap.
+    pub fn new(name: &str, description: &str) -> Keymap
+    {
+        Keymap
+            {
+                name: String::from(name),
+                description: String::from(description),
+                keys: BTreeMap::new()
+            }
+    }
+
+    /// Copy the keymap under a new name, for building a layout from another.
+    pub fn renamed(self, name: &str, description: &str) -> Keymap
+    {
+        Keymap { name: String::from(name), description: String::from(description), keys: self.keys }
+    }
+
+    /// The short name the layout is picked by.
+    pub fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    /// A description of the layout.
+    pub fn description(&self) -> &str
+    {
+        &self.description
+    }
+
+    /// Define what a key stands for.
+    pub fn set_key(&mut self, key_code: KeyCode, definition: KeyDefinition)
+    {
+        self.keys.insert(key_code, definition);
+    }
+
+    /// Define what a table of keys stand for.
+    pub fn set_keys(&mut self, keys: &[(KeyCode, KeyDefinition)])
+    {
+        for &(key_code, definition) in keys
+        {
+            self.set_key(key_code, definition);
+        }
+    }
+
+    /// Look up what a key stands for.
+    pub fn key(&self, key_code: KeyCode) -> Option<KeyDefinition>
+    {
+        self.keys.get(&key_code).copied()
+    }
+}
+
+
+
+/// The function called with each keystroke typed on any keyboard.
+pub type KeystrokeHandler = fn(keystroke: &Keystroke);
+
+
+
+/// The key being repeated.
+#[derive(Clone, Copy)]
+struct RepeatState
+{
+    /// The key held down.
+    key_code: KeyCode,
+
+    /// The timer for the next repeat.
+    timer: usize,
+
+    /// Counts the keys repeated, so that a timer for a key that's since been released can tell.
+    generation: usize
+}
+
+
+
+/// The keymaps that can be used, by name.
+static KEYMAPS: SpinMutex<BTreeMap<String, Arc<Keymap>>> = SpinMutex::new(BTreeMap::new());
+
+/// The keymap in use. Read by the keyboards' interrupt handlers, so only locked with interrupts
+/// disabled.
+static ACTIVE_KEYMAP: SpinMutex<Option<Arc<Keymap>>> = SpinMutex::new(None);
+
+/// The keymap asked for by the keymap= parameter.
+static REQUESTED_KEYMAP: SpinMutex<Option<&'static str>> = SpinMutex::new(None);
+
+/// The keyboard state shared by all of the keyboards, only locked with interrupts disabled.
+static TRANSLATOR: SpinMutex<KeyboardTranslator> = SpinMutex::new(KeyboardTranslator::new());
+
+/// The key being repeated, only locked with interrupts disabled.
+static REPEAT: SpinMutex<Option<RepeatState>> = SpinMutex::new(None);
+
+/// Counts the keys repeated.
+static REPEAT_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// The key repeat timing.
+static REPEAT_DELAY_MS: AtomicU64 = AtomicU64::new(DEFAULT_REPEAT_DELAY_MS);
+static REPEAT_INTERVAL_MS: AtomicU64 = AtomicU64::new(DEFAULT_REPEAT_INTERVAL_MS);
+
+/// The typed input waiting for the console, as a terminal would send it. Only locked with
+/// interrupts disabled.
+static TYPED_INPUT: SpinMutex<Option<RingBuffer>> = SpinMutex::new(None);
+
+/// The handlers called with each keystroke.
+static KEYSTROKE_HANDLERS: HandlerTable<KeystrokeHandler> = HandlerTable::new();
+
+
+
+/// Register the kernel parameters accepted by the keymap subsystem.
+pub fn register_kernel_parameters(registry: &mut KernelParameterRegistry)
+    -> Result<(), &'static str>
+{
+    registry.insert("keymap",
+                    KernelParameter
+                        {
+                            description: "the keyboard layout, us, uk or de",
+                            handler: |value|
+                                {
+                                    let name = value.ok_or("Expected keymap=<layout>.")?;
+
+                                    *REQUESTED_KEYMAP.lock() = Some(name);
+
+                                    Ok(())
+                                }
+                        });
+
+    Ok(())
+}
+
+
+/// Initialize the keymap subsystem, register the built in layouts, pick the one asked for on the
+/// command line and start listening to the keyboards.
+pub fn initialize_keymaps() -> Result<(), &'static str>
+{
+    for keymap in layouts::built_in_keymaps()
+    {
+        register_keymap(keymap)?;
+    }
+
+    with_interrupts_disabled(|| *TYPED_INPUT.lock() = Some(RingBuffer::new(TYPED_INPUT_SIZE)));
+
+    let requested = REQUESTED_KEYMAP.lock().unwrap_or(DEFAULT_KEYMAP);
+
+    if set_keymap(requested).is_err()
+    {
+        println!("  Unknown keymap {}, using {}.", requested, DEFAULT_KEYMAP);
+        set_keymap(DEFAULT_KEYMAP)?;
+    }
+
+    if register_any_keyboard_handlers(handle_key_pressed, handle_key_released) == 0
+    {
+        return Err("Couldn't register the keymap's keyboard handlers.");
+    }
+
+    Ok(())
+}
+
+
+
+/// Make a keymap available, replacing any keymap with the same name.
+pub fn register_keymap(keymap: Keymap) -> Result<(), &'static str>
+{
+    if keymap.name().is_empty()
+    {
+        return Err("A keymap needs a name.");
+    }
+
+    KEYMAPS.lock().insert(String::from(keymap.name()), Arc::new(keymap));
+
+    Ok(())
+}
+
+
+/// Switch to a different keymap by its name.
+pub fn set_keymap(name: &str) -> Result<(), &'static str>
+{
+    let keymap = KEYMAPS.lock().get(name).cloned().ok_or("There is no keymap by that name.")?;
+
+    with_interrupts_disabled(||
+        {
+            *ACTIVE_KEYMAP.lock() = Some(keymap);
+            TRANSLATOR.lock().clear_dead_key();
+        });
+
+    Ok(())
+}
+
+
+/// Get the keymap in use.
+pub fn active_keymap() -> Option<Arc<Keymap>>
+{
+    with_interrupts_disabled(|| ACTIVE_KEYMAP.lock().clone())
+}
+
+
+/// Get the names of the keymaps that can be used.
+pub fn keymap_names() -> Vec<String>
+{
+    KEYMAPS.lock().keys().cloned().collect()
+}
+
+
+
+/// Get the state of the lock keys.
+pub fn lock_state() -> LockState
+{
+    with_interrupts_disabled(|| TRANSLATOR.lock().locks())
+}
+
+
+/// Set the state of the lock keys.
+pub fn set_lock_state(locks: LockState)
+{
+    with_interrupts_disabled(|| TRANSLATOR.lock().set_locks(locks));
+}
+
+
+/// Set how long a key is held before it repeats, and how often it repeats after that.
+pub fn set_repeat_rate(delay_ms: u64, interval_ms: u64) -> Result<(), &'static str>
+{
+    if    delay_ms == 0
+       || interval_ms == 0
+    {
+        return Err("The key repeat delay and interval must be more than 0.");
+    }
+
+    REPEAT_DELAY_MS.store(delay_ms, Ordering::Relaxed);
+    REPEAT_INTERVAL_MS.store(interval_ms, Ordering::Relaxed);
+
+    Ok(())
+}
+
+
+
+/// Register a handler to be called with every keystroke typed on any keyboard. It's called from
+/// interrupt context. Returns the handler's ID, or 0 if there's no room for another handler.
+pub fn register_keystroke_handler(handler: KeystrokeHandler) -> usize
+{
+    KEYSTROKE_HANDLERS.register(handler)
+}
+
+
+/// Unregister a keystroke handler by the ID it was registered with.
+pub fn unregister_keystroke_handler(handler_id: usize)
+{
+    KEYSTROKE_HANDLERS.unregister(handler_id);
+}
+
+
+/// Read the input typed on the keyboards, as a terminal would send it. Returns how many bytes were
+/// read.
+pub fn read_typed_input(buffer: &mut [u8]) -> usize
+{
+    with_interrupts_disabled(||
+        {
+            TYPED_INPUT.lock().as_mut().map_or(0, |input| input.pop_slice(buffer))
+        })
+}
+
+
+
+/// Called when a key is pressed on any keyboard.
+fn handle_key_pressed(keyboard: &dyn KeyboardDevice, key_code: KeyCode, modifiers: &KeyModifiers)
+{
+    stop_repeat();
+
+    let Some(keystroke) = translate_key(key_code, false)
+    else
+    {
+        return;
+    };
+
+    if keystroke.symbol.repeats()
+    {
+        start_repeat(key_code);
+    }
+
+    deliver_keystroke(&keystroke);
+}
+
+
+/// Called when a key is released on any keyboard.
+fn handle_key_released(keyboard: &dyn KeyboardDevice, key_code: KeyCode, modifiers: &KeyModifiers)
+{
+    with_interrupts_disabled(|| TRANSLATOR.lock().key_released(key_code));
+
+    let is_repeating = with_interrupts_disabled(||
+        {
+            REPEAT.lock().is_some_and(|repeat| repeat.key_code == key_code)
+        });
+
+    if is_repeating
+    {
+        stop_repeat();
+    }
+}
+
+
+/// Translate a key press through the active keymap.
+fn translate_key(key_code: KeyCode, is_repeat: bool) -> Option<Keystroke>
+{
+    with_interrupts_disabled(||
+        {
+            let keymap = ACTIVE_KEYMAP.lock().clone()?;
+
+            TRANSLATOR.lock().key_pressed(&keymap, key_code, is_repeat)
+        })
+}
+
+
+/// Hand a keystroke to the handlers and queue it for the console.
+fn deliver_keystroke(keystroke: &Keystroke)
+{
+    KEYSTROKE_HANDLERS.for_each(|handler| handler(keystroke));
+
+    let mut sequence = [0u8; MAX_TERMINAL_SEQUENCE];
+    let length = keystroke.encode_for_terminal(&mut sequence);
+
+    with_interrupts_disabled(||
+        {
+            if let Some(input) = TYPED_INPUT.lock().as_mut()
+            {
+                // Drop the whole sequence if it doesn't fit, rather than half of it.
+                if input.capacity() - input.len() >= length
+                {
+                    input.push_slice(&sequence[..length]);
+                }
+            }
+        });
+}
+
+
+
+/// Start repeating a key once it's been held for the repeat delay.
+fn start_repeat(key_code: KeyCode)
+{
+    let generation = REPEAT_GENERATION.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+
+    let Ok(timer) = start_timer(REPEAT_DELAY_MS.load(Ordering::Relaxed), repeat_key, generation)
+    else
+    {
+        return;
+    };
+
+    with_interrupts_disabled(|| *REPEAT.lock() = Some(RepeatState { key_code, timer, generation }));
+}
+
+
+/// Stop repeating the key being repeated, if any.
+fn stop_repeat()
+{
+    if let Some(repeat) = with_interrupts_disabled(|| REPEAT.lock().take())
+    {
+        cancel_timer(repeat.timer);
+    }
+}
+
+
+/// The repeat timer, type the key being repeated again and start the timer for the next repeat.
+fn repeat_key(generation: usize)
+{
+    let key_code = with_interrupts_disabled(||
+        {
+            REPEAT.lock()
+                .filter(|repeat| repeat.generation == generation)
+                .map(|repeat| repeat.key_code)
+        });
+
+    let Some(key_code) = key_code
+    else
+    {
+        return;
+    };
+
+    if let Some(keystroke) = translate_key(key_code, true)
+    {
+        deliver_keystroke(&keystroke);
+    }
+
+    let interval = REPEAT_INTERVAL_MS.load(Ordering::Relaxed);
+
+    match start_timer(interval, repeat_key, generation)
+    {
+        Ok(timer) =>
+            {
+                with_interrupts_disabled(||
+                    {
+                        if let Some(repeat) = REPEAT.lock().as_mut()
+                           && repeat.generation == generation
+                        {
+                            repeat.timer = timer;
+                        }
+                    });
+            },
+
+        Err(_) => stop_repeat()
+    }
+}
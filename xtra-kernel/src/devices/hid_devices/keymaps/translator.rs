@@ -0,0 +1,409 @@
+// Turns key presses into keystrokes, the symbol the key stands for in the keymap and the text it
+// types. The translator keeps the state that carries from one key to the next, which keys are
+// held down, the lock keys and any dead key waiting for the key it accents.
+//
+// The modifiers are worked out from the symbols of the keys being held down, so it's up to the
+// keymap which keys are which modifier. Right alt is alt on a US keyboard and AltGr on most others.
+
+use crate::devices::hid_devices::{ key_codes::*,
+                                   keymaps::{ DeadKey, KeySymbol, Keymap },
+                                   KeyCode,
+                                   KeyModifiers,
+                                   KeyboardState,
+                                   MAX_PRESSED_KEYS };
+
+
+
+/// The most bytes of text a single keystroke can type, a dead key that doesn't combine with the
+/// key after it types both characters.
+pub const MAX_KEYSTROKE_TEXT: usize = 8;
+
+/// The most bytes a keystroke is sent to a terminal as.
+pub const MAX_TERMINAL_SEQUENCE: usize = 16;
+
+
+
+/// The escape sequences terminals expect for the function keys, F1 to F12.
+const FUNCTION_KEY_SEQUENCES: [&[u8]; 12] =
+    [
+        b"\x1bOP",   b"\x1bOQ",   b"\x1bOR",   b"\x1bOS",
+        b"\x1b[15~", b"\x1b[17~", b"\x1b[18~", b"\x1b[19~",
+        b"\x1b[20~", b"\x1b[21~", b"\x1b[23~", b"\x1b[24~"
+    ];
+
+
+
+/// The state of the lock keys.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct LockState
+{
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool
+}
+
+
+
+/// The modifier keys held down, as the keymap sees them.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct ModifierState
+{
+    pub shift: bool,
+    pub control: bool,
+    pub alt: bool,
+    pub alt_gr: bool,
+    pub meta: bool
+}
+
+
+
+impl From<ModifierState> for KeyModifiers
+{
+    fn from(modifiers: ModifierState) -> KeyModifiers
+    {
+        KeyModifiers
+            {
+                shift: modifiers.shift,
+                ctrl: modifiers.control,
+                alt: modifiers.alt || modifiers.alt_gr,
+                meta: modifiers.meta
+            }
+    }
+}
+
+
+
+/// A key press, translated through the keymap.
+#[derive(Clone, Copy, Debug)]
+pub struct Keystroke
+{
+    /// The key that was pressed.
+    pub key_code: KeyCode,
+
+    /// What the key stands for with the modifiers and locks applied.
+    pub symbol: KeySymbol,
+
+    /// The modifiers held down when the key was pressed.
+    pub modifiers: ModifierState,
+
+    /// The state of the lock keys after the key was pressed.
+    pub locks: LockState,
+
+    /// Is this the key repeating from being held down, rather than a new press?
+    pub is_repeat: bool,
+
+    /// The text the key typed, as UTF-8.
+    text: [u8; MAX_KEYSTROKE_TEXT],
+    text_length: usize
+}
+
+
+
+impl Keystroke
+{
+    /// The text the key typed, empty for keys that don't type anything, like the modifiers and
+    /// dead keys.
+    pub fn text(&self) -> &str
+    {
+        core::str::from_utf8(&self.text[..self.text_length]).unwrap_or("")
+    }
+
+    /// Encode the keystroke the way a terminal sends it, into the buffer. Returns how many bytes
+    /// were written.
+    ///
+    /// Control with a letter sends the control character and alt sends an escape before the key,
+    /// the keys that don't type anything send their usual escape sequences.
+    pub fn encode_for_terminal(&self, buffer: &mut [u8; MAX_TERMINAL_SEQUENCE]) -> usize
+    {
+        let sequence: &[u8] = match self.symbol
+            {
+                KeySymbol::Enter      => b"\r",
+                KeySymbol::Backspace  => b"\x7f",
+                KeySymbol::Tab        => b"\t",
+                KeySymbol::Escape     => b"\x1b",
+                KeySymbol::Up         => b"\x1b[A",
+                KeySymbol::Down       => b"\x1b[B",
+                KeySymbol::Right      => b"\x1b[C",
+                KeySymbol::Left       => b"\x1b[D",
+                KeySymbol::Home       => b"\x1b[H",
+                KeySymbol::End        => b"\x1b[F",
+                KeySymbol::Insert     => b"\x1b[2~",
+                KeySymbol::Delete     => b"\x1b[3~",
+                KeySymbol::PageUp     => b"\x1b[5~",
+                KeySymbol::PageDown   => b"\x1b[6~",
+
+                KeySymbol::Function(number) if (1..=12).contains(&number) =>
+                    {
+                        FUNCTION_KEY_SEQUENCES[number as usize - 1]
+                    },
+
+                _ => &self.text[..self.text_length]
+            };
+
+        if sequence.is_empty()
+        {
+            return 0;
+        }
+
+        let mut length = 0;
+
+        if self.modifiers.alt
+        {
+            buffer[0] = 0x1b;
+            length = 1;
+        }
+
+        match (self.modifiers.control, sequence)
+        {
+            (true, &[b' ' | b'@']) => buffer[length] = 0,
+            (true, &[b'?'])        => buffer[length] = 0x7f,
+
+            (true, &[byte]) if    byte.is_ascii_alphabetic()
+                               || (b'['..=b'_').contains(&byte) =>
+                {
+                    buffer[length] = byte.to_ascii_uppercase() & 0x1f;
+                },
+
+            _ =>
+                {
+                    buffer[length..length + sequence.len()].copy_from_slice(sequence);
+                    return length + sequence.len();
+                }
+        }
+
+        length + 1
+    }
+
+    /// Add a character to the text the key typed.
+    fn push_character(&mut self, character: char)
+    {
+        if self.text_length + character.len_utf8() <= MAX_KEYSTROKE_TEXT
+        {
+            character.encode_utf8(&mut self.text[self.text_length..]);
+            self.text_length += character.len_utf8();
+        }
+    }
+}
+
+
+
+/// The keyboard state carried from one key to the next.
+pub struct KeyboardTranslator
+{
+    /// The keys held down.
+    pressed: KeyboardState,
+
+    /// The state of the lock keys.
+    locks: LockState,
+
+    /// The dead key waiting for the key it accents.
+    dead_key: Option<DeadKey>
+}
+
+
+
+impl KeyboardTranslator
+{
+    /// Create a translator with no keys down and the locks off.
+    pub const fn new() -> KeyboardTranslator
+    {
+        KeyboardTranslator
+            {
+                pressed: KeyboardState
+                    {
+                        keys: [0; MAX_PRESSED_KEYS],
+                        key_count: 0,
+                        modifiers: KeyModifiers
+                            {
+                                shift: false,
+                                ctrl: false,
+                                alt: false,
+                                meta: false
+                            }
+                    },
+                locks: LockState { caps_lock: false, num_lock: false, scroll_lock: false },
+                dead_key: None
+            }
+    }
+
+    /// The state of the lock keys.
+    pub fn locks(&self) -> LockState
+    {
+        self.locks
+    }
+
+    /// Set the state of the lock keys.
+    pub fn set_locks(&mut self, locks: LockState)
+    {
+        self.locks = locks;
+    }
+
+    /// Forget any dead key that's waiting, for when the keymap changes.
+    pub fn clear_dead_key(&mut self)
+    {
+        self.dead_key = None;
+    }
+
+    /// Work out which modifiers are held down, from what the keys held down are in the keymap.
+    pub fn modifiers(&self, keymap: &Keymap) -> ModifierState
+    {
+        let mut modifiers = ModifierState::default();
+
+        for &key_code in self.pressed.pressed_keys()
+        {
+            match keymap.key(key_code).and_then(|definition| definition.levels[0])
+            {
+                Some(KeySymbol::Shift)   => modifiers.shift = true,
+                Some(KeySymbol::Control) => modifiers.control = true,
+                Some(KeySymbol::Alt)     => modifiers.alt = true,
+                Some(KeySymbol::AltGr)   => modifiers.alt_gr = true,
+                Some(KeySymbol::Meta)    => modifiers.meta = true,
+                _                        => {}
+            }
+        }
+
+        modifiers
+    }
+
+    /// Translate a key press. Returns None for keys the keymap doesn't know.
+    pub fn key_pressed(&mut self, keymap: &Keymap, key_code: KeyCode, is_repeat: bool)
+        -> Option<Keystroke>
+    {
+        if !is_repeat
+        {
+            self.pressed.press(key_code);
+        }
+
+        let modifiers = self.modifiers(keymap);
+        let symbol = keypad_symbol(key_code, self.locks.num_lock)
+            .or_else(|| self.keymap_symbol(keymap, key_code, modifiers))?;
+
+        if !is_repeat
+        {
+            match symbol
+            {
+                KeySymbol::CapsLock   => self.locks.caps_lock = !self.locks.caps_lock,
+                KeySymbol::NumLock    => self.locks.num_lock = !self.locks.num_lock,
+                KeySymbol::ScrollLock => self.locks.scroll_lock = !self.locks.scroll_lock,
+                _                     => {}
+            }
+        }
+
+        let mut keystroke = Keystroke
+            {
+                key_code,
+                symbol,
+                modifiers,
+                locks: self.locks,
+                is_repeat,
+                text: [0; MAX_KEYSTROKE_TEXT],
+                text_length: 0
+            };
+
+        match symbol
+        {
+            KeySymbol::Dead(dead_key) =>
+                {
+                    // Pressing a dead key twice types the accent itself.
+                    match self.dead_key.take()
+                    {
+                        Some(waiting) =>
+                            {
+                                keystroke.push_character(waiting.spacing_character());
+
+                                if waiting != dead_key
+                                {
+                                    self.dead_key = Some(dead_key);
+                                }
+                            },
+
+                        None => self.dead_key = Some(dead_key)
+                    }
+                },
+
+            KeySymbol::Character(character) =>
+                {
+                    match self.dead_key.take()
+                    {
+                        Some(dead_key) =>
+                            {
+                                match dead_key.compose(character)
+                                {
+                                    Some(composed) => keystroke.push_character(composed),
+                                    None =>
+                                        {
+                                            keystroke.push_character(dead_key.spacing_character());
+                                            keystroke.push_character(character);
+                                        }
+                                }
+                            },
+
+                        None => keystroke.push_character(character)
+                    }
+                },
+
+            // The modifiers and locks don't use up a waiting dead key, so that it can accent a
+            // capital letter.
+            symbol if symbol.is_modifier() || symbol.is_lock() => {},
+
+            _ => self.dead_key = None
+        }
+
+        Some(keystroke)
+    }
+
+    /// Note a key being released.
+    pub fn key_released(&mut self, key_code: KeyCode)
+    {
+        self.pressed.release(key_code);
+    }
+
+    /// Look the key up in the keymap. Caps lock acts as shift for the keys that say so, usually
+    /// the letters.
+    fn keymap_symbol(&self,
+                     keymap: &Keymap,
+                     key_code: KeyCode,
+                     modifiers: ModifierState) -> Option<KeySymbol>
+    {
+        let definition = keymap.key(key_code)?;
+        let shift = modifiers.shift ^ (definition.caps_lock && self.locks.caps_lock);
+
+        definition.symbol_for(shift, modifiers.alt_gr)
+    }
+}
+
+
+
+/// The symbols of the keypad, which are the same for every layout. With num lock on the keypad
+/// types numbers, with it off it's a second set of navigation keys.
+fn keypad_symbol(key_code: KeyCode, num_lock: bool) -> Option<KeySymbol>
+{
+    let (number, navigation) = match key_code
+        {
+            KEY_KP0 => ('0', KeySymbol::Insert),
+            KEY_KP1 => ('1', KeySymbol::End),
+            KEY_KP2 => ('2', KeySymbol::Down),
+            KEY_KP3 => ('3', KeySymbol::PageDown),
+            KEY_KP4 => ('4', KeySymbol::Left),
+            KEY_KP5 => ('5', KeySymbol::Character('5')),
+            KEY_KP6 => ('6', KeySymbol::Right),
+            KEY_KP7 => ('7', KeySymbol::Home),
+            KEY_KP8 => ('8', KeySymbol::Up),
+            KEY_KP9 => ('9', KeySymbol::PageUp),
+            KEY_KPDOT => ('.', KeySymbol::Delete),
+
+            KEY_KPSLASH    => return Some(KeySymbol::Character('/')),
+            KEY_KPASTERISK => return Some(KeySymbol::Character('*')),
+            KEY_KPMINUS    => return Some(KeySymbol::Character('-')),
+            KEY_KPPLUS     => return Some(KeySymbol::Character('+')),
+            KEY_KPENTER    => return Some(KeySymbol::Enter),
+
+            _ => return None
+        };
+
+    match num_lock
+    {
+        true  => Some(KeySymbol::Character(number)),
+        false => Some(navigation)
+    }
+}
@@ -5,14 +5,60 @@
 // perspective, or from a global perspective where the caller doesn't care about which specific
 // device the input is coming from. The latter is useful for things like the console subsystem where
 // we just want to know when a key is pressed and don't care which keyboard it came from.
+//
+// The devices report their input from their interrupt handlers, so the event handlers are called
+// in interrupt context. They need to be quick, and they must only take locks that are held with
+// interrupts disabled everywhere else.
+
+use core::sync::atomic::{ AtomicUsize, Ordering };
+
+use alloc::{ sync::Arc, vec::Vec };
 
 use xtra_kernel_shared::device_tree::DeviceTree;
 
-use crate::devices::DeviceDriverRegistry;
+use crate::{ arch::interrupts::with_interrupts_disabled,
+             command_line::KernelParameterRegistry,
+             devices::{ bus_devices::virtio_devices::{ VirtioDriverRegistry,
+                                                       VIRTIO_INPUT_DEVICE_ID },
+                        DeviceDriverRegistry },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// The codes of the keys and buttons reported by the devices.
+pub mod key_codes;
+
+/// Translating the keys pressed into characters with the user's keyboard layout.
+pub mod keymaps;
+
+
+
+use crate::devices::hid_devices::key_codes::{ KEY_LEFTALT,
+                                              KEY_LEFTCTRL,
+                                              KEY_LEFTMETA,
+                                              KEY_LEFTSHIFT,
+                                              KEY_RIGHTALT,
+                                              KEY_RIGHTCTRL,
+                                              KEY_RIGHTMETA,
+                                              KEY_RIGHTSHIFT };
+
+
+
+/// Identifies a key, the codes are listed in the key_codes module.
+pub type KeyCode = u16;
+
+
+
+/// The most keys a keyboard keeps track of being held down at once.
+pub const MAX_PRESSED_KEYS: usize = 16;
+
+/// The most sets of event handlers that can be registered with a device, or for any device.
+pub const MAX_EVENT_HANDLERS: usize = 8;
 
 
 
 /// The special modifier keys and their states that are relevant for keyboard input handling.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
 pub struct KeyModifiers
 {
     pub shift: bool,
@@ -23,48 +69,147 @@ pub struct KeyModifiers
 
 
 
+impl KeyModifiers
+{
+    /// Work out the modifiers from the keys being held down. Either of the left and right keys
+    /// count.
+    pub fn from_pressed_keys(keys: &[KeyCode]) -> KeyModifiers
+    {
+        let is_down = |left, right| keys.iter().any(|&key| key == left || key == right);
+
+        KeyModifiers
+            {
+                shift: is_down(KEY_LEFTSHIFT, KEY_RIGHTSHIFT),
+                ctrl: is_down(KEY_LEFTCTRL, KEY_RIGHTCTRL),
+                alt: is_down(KEY_LEFTALT, KEY_RIGHTALT),
+                meta: is_down(KEY_LEFTMETA, KEY_RIGHTMETA)
+            }
+    }
+}
+
+
+
+/// The keys held down on a keyboard at a given point in time.
+#[derive(Clone, Copy, Default)]
+pub struct KeyboardState
+{
+    /// The keys held down, in the order they were pressed. Only the first key_count are used.
+    pub keys: [KeyCode; MAX_PRESSED_KEYS],
+
+    /// How many keys are held down.
+    pub key_count: usize,
+
+    /// The state of the modifier keys.
+    pub modifiers: KeyModifiers
+}
+
+
+
+impl KeyboardState
+{
+    /// The keys held down.
+    pub fn pressed_keys(&self) -> &[KeyCode]
+    {
+        &self.keys[..self.key_count]
+    }
+
+    /// Is the key held down?
+    pub fn is_pressed(&self, key: KeyCode) -> bool
+    {
+        self.pressed_keys().contains(&key)
+    }
+
+    /// Record a key being pressed, returns false if it was already down or there's no room to keep
+    /// track of it.
+    pub fn press(&mut self, key: KeyCode) -> bool
+    {
+        if    self.is_pressed(key)
+           || self.key_count == MAX_PRESSED_KEYS
+        {
+            return false;
+        }
+
+        self.keys[self.key_count] = key;
+        self.key_count += 1;
+        self.modifiers = KeyModifiers::from_pressed_keys(self.pressed_keys());
+
+        true
+    }
+
+    /// Record a key being released, returns false if it wasn't down.
+    pub fn release(&mut self, key: KeyCode) -> bool
+    {
+        let Some(index) = self.pressed_keys().iter().position(|&pressed| pressed == key)
+        else
+        {
+            return false;
+        };
+
+        self.keys.copy_within(index + 1..self.key_count, index);
+        self.key_count -= 1;
+        self.modifiers = KeyModifiers::from_pressed_keys(self.pressed_keys());
+
+        true
+    }
+}
+
+
+
 /// Callback fired when an attached keyboard device detects a key press.
 pub type KeyboardPressedHandler = fn(keyboard: &dyn KeyboardDevice,
-                                     scan_code: &[u8],
+                                     key_code: KeyCode,
                                      modifiers: &KeyModifiers);
 
 /// Callback fired when an attached keyboard device detects a key release.
 pub type KeyboardReleasedHandler = fn(keyboard: &dyn KeyboardDevice,
-                                      scan_code: &[u8],
+                                      key_code: KeyCode,
                                       modifiers: &KeyModifiers);
 
 
 
+/// A keyboard's pressed and released handlers, as registered together.
+pub type KeyboardHandlers = (KeyboardPressedHandler, KeyboardReleasedHandler);
+
+
+
 /// The generic interface for a keyboard device driver. This can be implemented by any keyboard
 /// device that happens to be attached to the system, regardless of the underlying hardware or
 /// communication protocol.
-pub trait KeyboardDevice
+///
+/// Keyboards are shared with their interrupt handlers, so they keep their state behind locks and
+/// everything here works through a shared reference.
+pub trait KeyboardDevice: Send + Sync
 {
     /// Unique identifier for the keyboard device, this can be used by the caller to keep track of
     /// multiple attached keyboard devices and their associated event handlers.
     fn keyboard_id(&self) -> usize;
 
+    /// The name of the device, as reported by the device.
+    fn name(&self) -> &str;
+
     /// Polling interface, Read the current state of the keys pressed down on the keyboard along
-    /// with the state of the modifier keys. It is up to the caller to keep track of key releases.
-    fn read_keys(&self) -> Option<(&[u8], KeyModifiers)>;
+    /// with the state of the modifier keys, None if no keys are down. It is up to the caller to
+    /// keep track of key releases.
+    fn read_keys(&self) -> Option<KeyboardState>;
 
     /// Event driven interface. Register callback handlers for key press and release events.
     ///
-    /// This method returns a handler ID that can be used to unregister the event handlers later.
-    fn register_key_handlers(&mut self,
+    /// This method returns a handler ID that can be used to unregister the event handlers later,
+    /// or 0 if the keyboard has no room for more handlers.
+    fn register_key_handlers(&self,
                              pressed_handler: KeyboardPressedHandler,
                              released_handler: KeyboardReleasedHandler) -> usize;
 
     /// Unregister the event handlers for key press and release events using the handler ID returned
     /// from the `register_key_handlers` function.
-    fn unregister_key_handlers(&mut self, handler_id: usize);
+    fn unregister_key_handlers(&self, handler_id: usize);
 }
 
 
 
 /// Maximum number of buttons that we will support on a mouse device, this is used to size the
 /// button array in the `MouseState` struct.
-const MAX_MOUSE_BUTTONS: usize = 16;
+pub const MAX_MOUSE_BUTTONS: usize = 16;
 
 
 
@@ -82,7 +227,20 @@ pub type MouseButtonReleasedHandler = fn(mouse: &dyn MouseDevice, button: usize)
 
 
 
+/// A mouse's handlers, as registered together.
+#[derive(Clone, Copy)]
+pub struct MouseHandlers
+{
+    pub moved: MouseMovedHandler,
+    pub scrolled: MouseScrolledHandler,
+    pub button_pressed: MouseButtonPressedHandler,
+    pub button_released: MouseButtonReleasedHandler
+}
+
+
+
 /// The state of a mouse device at a given point in time.
+#[derive(Clone, Copy, Default)]
 pub struct MouseState
 {
     /// The x movement delta of the mouse since the last time it was read.
@@ -95,8 +253,12 @@ pub struct MouseState
     /// position of the wheel since the last time it was read.
     pub scroll: isize,
 
+    /// The absolute position of the pointer in the device's own units, for devices like tablets
+    /// that report one.
+    pub position: Option<(isize, isize)>,
+
     /// The state of the buttons on the mouse, this is a slice of all of the buttons on the mouse.
-    /// where 0 is left, 1, the right, and 3 is the middle button. This slice can contain any
+    /// where 0 is left, 1, the right, and 2 is the middle button. This slice can contain any
     /// number of additional buttons depending on the mouse itself.
     pub buttons: [bool; MAX_MOUSE_BUTTONS]
 }
@@ -106,24 +268,101 @@ pub struct MouseState
 /// The generic interface for a mouse device driver. This can be implemented by any mouse or
 /// pointing device that happens to be attached to the system, regardless of the underlying hardware
 /// or communication protocol.
-pub trait MouseDevice
+///
+/// Like keyboards, mice are shared with their interrupt handlers and work through a shared
+/// reference.
+pub trait MouseDevice: Send + Sync
 {
     /// Unique identifier for the mouse device.
     fn mouse_id(&self) -> usize;
 
-    /// Polling interface, read the current state of the mouse device.
+    /// The name of the device, as reported by the device.
+    fn name(&self) -> &str;
+
+    /// Polling interface, read the current state of the mouse device. The movement and scrolling
+    /// are reset with each read.
     fn read_state(&self) -> MouseState;
 
     /// Event driven interface. Register callback handlers for mouse movement, scroll, and button
-    /// events.
-    fn register_mouse_handlers(&mut self,
+    /// events. Returns 0 if the mouse has no room for more handlers.
+    fn register_mouse_handlers(&self,
                                moved_handler: MouseMovedHandler,
                                scrolled_handler: MouseScrolledHandler,
                                button_pressed_handler: MouseButtonPressedHandler,
                                button_released_handler: MouseButtonReleasedHandler) -> usize;
 
     /// Unregister the event handler functions for the mouse.
-    fn unregister_mouse_handlers(&mut self, handler_id: usize);
+    fn unregister_mouse_handlers(&self, handler_id: usize);
+}
+
+
+
+/// A fixed size table of event handlers. The handlers are copied out of the table before they're
+/// called, so calling them doesn't allocate and they're free to register and unregister handlers
+/// themselves.
+pub struct HandlerTable<Handlers: Copy>
+{
+    /// The registered handlers, and their IDs.
+    entries: SpinMutex<[Option<(usize, Handlers)>; MAX_EVENT_HANDLERS]>
+}
+
+
+
+impl<Handlers: Copy> HandlerTable<Handlers>
+{
+    /// Create an empty handler table.
+    pub const fn new() -> Self
+    {
+        HandlerTable { entries: SpinMutex::new([None; MAX_EVENT_HANDLERS]) }
+    }
+
+    /// Add handlers to the table, returning their ID or 0 if the table is full.
+    pub fn register(&self, handlers: Handlers) -> usize
+    {
+        with_interrupts_disabled(||
+            {
+                let mut entries = self.entries.lock();
+
+                let Some(slot) = entries.iter_mut().find(|slot| slot.is_none())
+                else
+                {
+                    return 0;
+                };
+
+                let handler_id = NEXT_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
+
+                *slot = Some((handler_id, handlers));
+
+                handler_id
+            })
+    }
+
+    /// Remove handlers from the table by their ID.
+    pub fn unregister(&self, handler_id: usize)
+    {
+        with_interrupts_disabled(||
+            {
+                for slot in self.entries.lock().iter_mut()
+                {
+                    if matches!(slot, Some((id, _)) if *id == handler_id)
+                    {
+                        *slot = None;
+                    }
+                }
+            });
+    }
+
+    /// Call a function with each of the registered handlers.
+    pub fn for_each<Function>(&self, mut function: Function)
+        where Function: FnMut(Handlers)
+    {
+        let entries = with_interrupts_disabled(|| *self.entries.lock());
+
+        for (_, handlers) in entries.iter().flatten()
+        {
+            function(*handlers);
+        }
+    }
 }
 
 
@@ -134,6 +373,36 @@ pub mod usb_keyboard;
 /// Specific driver for USB mice.
 pub mod usb_mouse;
 
+/// Driver for VirtIO input devices, keyboards, mice and tablets.
+pub mod virtio_input;
+
+
+
+/// The IDs handed out to the HID devices as they're attached.
+static NEXT_DEVICE_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// The IDs handed out to registered handlers, 0 is never used so that it can mean failure.
+static NEXT_HANDLER_ID: AtomicUsize = AtomicUsize::new(1);
+
+
+
+/// The keyboards attached to the system.
+static KEYBOARDS: SpinMutex<Vec<Arc<dyn KeyboardDevice>>> = SpinMutex::new(Vec::new());
+
+/// The mice attached to the system.
+static MICE: SpinMutex<Vec<Arc<dyn MouseDevice>>> = SpinMutex::new(Vec::new());
+
+
+
+/// The handlers called when a device is attached or detached.
+static ATTACHMENT_HANDLERS: HandlerTable<HidAttachmentHandler> = HandlerTable::new();
+
+/// The handlers called for the events of every keyboard.
+static ANY_KEYBOARD_HANDLERS: HandlerTable<KeyboardHandlers> = HandlerTable::new();
+
+/// The handlers called for the events of every mouse.
+static ANY_MOUSE_HANDLERS: HandlerTable<MouseHandlers> = HandlerTable::new();
+
 
 
 /// Register the device driver probes for HID devices, such as keyboards, mice, touch pads, etc.
@@ -143,13 +412,31 @@ pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(),
 }
 
 
-/// Activate and initialize the HID devices discovered in the device tree. If any.
-pub fn activate_devices() -> Result<(), &'static str>
+/// Register the drivers for the HID devices that can show up on the VirtIO bus.
+pub fn register_virtio_drivers(registry: &mut VirtioDriverRegistry) -> Result<(), &'static str>
 {
+    registry.insert(VIRTIO_INPUT_DEVICE_ID, virtio_input::probe_virtio_input_device);
+
     Ok(())
 }
 
 
+/// Register the kernel parameters accepted by the HID subsystem.
+pub fn register_kernel_parameters(registry: &mut KernelParameterRegistry)
+    -> Result<(), &'static str>
+{
+    keymaps::register_kernel_parameters(registry)
+}
+
+
+/// Activate and initialize the HID devices discovered in the device tree. If any. The keymaps are
+/// set up first so that they're listening when the keyboards are attached.
+pub fn activate_devices() -> Result<(), &'static str>
+{
+    keymaps::initialize_keymaps()
+}
+
+
 
 /// A borrowed view of an attached HID device while enumerating the currently attached devices.
 pub enum HidDevice<'device>
@@ -184,7 +471,7 @@ pub type HidAttachmentHandler = for<'device> fn(attachment: Attachment<'device>)
 /// supported HID device is attached or detached.
 pub fn register_hid_attachment_handler(handler: HidAttachmentHandler) -> usize
 {
-    0
+    ATTACHMENT_HANDLERS.register(handler)
 }
 
 
@@ -193,6 +480,7 @@ pub fn register_hid_attachment_handler(handler: HidAttachmentHandler) -> usize
 /// `register_hid_attachment_handler` function.
 pub fn unregister_hid_attachment_handler(handler_id: usize)
 {
+    ATTACHMENT_HANDLERS.unregister(handler_id);
 }
 
 
@@ -202,7 +490,7 @@ pub fn unregister_hid_attachment_handler(handler_id: usize)
 pub fn register_any_keyboard_handlers(pressed_handler: KeyboardPressedHandler,
                                       released_handler: KeyboardReleasedHandler) -> usize
 {
-    0
+    ANY_KEYBOARD_HANDLERS.register((pressed_handler, released_handler))
 }
 
 
@@ -211,6 +499,7 @@ pub fn register_any_keyboard_handlers(pressed_handler: KeyboardPressedHandler,
 /// `register_any_keyboard_handlers` function.
 pub fn unregister_any_keyboard_handlers(handler_id: usize)
 {
+    ANY_KEYBOARD_HANDLERS.unregister(handler_id);
 }
 
 
@@ -222,7 +511,13 @@ pub fn register_any_mouse_handlers(moved_handler: MouseMovedHandler,
                                    button_pressed_handler: MouseButtonPressedHandler,
                                    button_released_handler: MouseButtonReleasedHandler) -> usize
 {
-    0
+    ANY_MOUSE_HANDLERS.register(MouseHandlers
+        {
+            moved: moved_handler,
+            scrolled: scrolled_handler,
+            button_pressed: button_pressed_handler,
+            button_released: button_released_handler
+        })
 }
 
 
@@ -231,6 +526,7 @@ pub fn register_any_mouse_handlers(moved_handler: MouseMovedHandler,
 /// `register_any_mouse_handlers` function.
 pub fn unregister_any_mouse_handlers(handler_id: usize)
 {
+    ANY_MOUSE_HANDLERS.unregister(handler_id);
 }
 
 
@@ -242,7 +538,24 @@ pub fn unregister_any_mouse_handlers(handler_id: usize)
 pub fn with_attached_keyboard<Handler>(keyboard_id: usize, callback: Handler) -> bool
     where Handler: FnOnce(&dyn KeyboardDevice)
 {
-    false
+    let keyboard = with_interrupts_disabled(||
+        {
+            KEYBOARDS.lock()
+                .iter()
+                .find(|keyboard| keyboard.keyboard_id() == keyboard_id)
+                .cloned()
+        });
+
+    match keyboard
+    {
+        Some(keyboard) =>
+            {
+                callback(keyboard.as_ref());
+                true
+            },
+
+        None => false
+    }
 }
 
 
@@ -252,7 +565,21 @@ pub fn with_attached_keyboard<Handler>(keyboard_id: usize, callback: Handler) ->
 pub fn with_attached_mouse<Handler>(mouse_id: usize, callback: Handler) -> bool
     where Handler: FnOnce(&dyn MouseDevice)
 {
-    false
+    let mouse = with_interrupts_disabled(||
+        {
+            MICE.lock().iter().find(|mouse| mouse.mouse_id() == mouse_id).cloned()
+        });
+
+    match mouse
+    {
+        Some(mouse) =>
+            {
+                callback(mouse.as_ref());
+                true
+            },
+
+        None => false
+    }
 }
 
 
@@ -260,9 +587,15 @@ pub fn with_attached_mouse<Handler>(mouse_id: usize, callback: Handler) -> bool
 /// Enumerate all of the attached keyboards and run the provided callback function for each one.
 ///
 /// If there are no keyboards attached to the system then the callback will not be called at all.
-pub fn enumerate_attached_keyboards<Handler>(enumerator: Handler)
+pub fn enumerate_attached_keyboards<Handler>(mut enumerator: Handler)
     where Handler: FnMut(&dyn KeyboardDevice)
 {
+    let keyboards = with_interrupts_disabled(|| KEYBOARDS.lock().clone());
+
+    for keyboard in keyboards
+    {
+        enumerator(keyboard.as_ref());
+    }
 }
 
 
@@ -270,9 +603,15 @@ pub fn enumerate_attached_keyboards<Handler>(enumerator: Handler)
 /// Enumerate all of the attached mice and run the provided callback function for each one.
 ///
 /// If there are no mice attached to the system then the callback will not be called at all.
-pub fn enumerate_attached_mice<Handler>(enumerator: Handler)
+pub fn enumerate_attached_mice<Handler>(mut enumerator: Handler)
     where Handler: FnMut(&dyn MouseDevice)
 {
+    let mice = with_interrupts_disabled(|| MICE.lock().clone());
+
+    for mouse in mice
+    {
+        enumerator(mouse.as_ref());
+    }
 }
 
 
@@ -294,3 +633,128 @@ pub fn enumerate_attached_hid_devices<Handler>(mut enumerator: Handler)
             enumerator(HidDevice::Mouse(mouse))
         });
 }
+
+
+
+/// Get a new ID for a HID device, as returned by its keyboard_id or mouse_id.
+pub fn next_hid_device_id() -> usize
+{
+    NEXT_DEVICE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+
+
+/// Called by a driver when it attaches a keyboard, makes it available to the rest of the kernel
+/// and lets the attachment handlers know about it.
+pub fn attach_keyboard(keyboard: Arc<dyn KeyboardDevice>)
+{
+    with_interrupts_disabled(|| KEYBOARDS.lock().push(keyboard.clone()));
+
+    ATTACHMENT_HANDLERS.for_each(|handler|
+        {
+            handler(Attachment::Attached(HidDevice::Keyboard(keyboard.as_ref())))
+        });
+}
+
+
+/// Called by a driver when one of its keyboards goes away.
+pub fn detach_keyboard(keyboard_id: usize)
+{
+    let keyboard = with_interrupts_disabled(||
+        {
+            let mut keyboards = KEYBOARDS.lock();
+            let index = keyboards.iter()
+                .position(|keyboard| keyboard.keyboard_id() == keyboard_id)?;
+
+            Some(keyboards.remove(index))
+        });
+
+    if let Some(keyboard) = keyboard
+    {
+        ATTACHMENT_HANDLERS.for_each(|handler|
+            {
+                handler(Attachment::Detached(HidDevice::Keyboard(keyboard.as_ref())))
+            });
+    }
+}
+
+
+/// Called by a driver when it attaches a mouse, makes it available to the rest of the kernel and
+/// lets the attachment handlers know about it.
+pub fn attach_mouse(mouse: Arc<dyn MouseDevice>)
+{
+    with_interrupts_disabled(|| MICE.lock().push(mouse.clone()));
+
+    ATTACHMENT_HANDLERS.for_each(|handler|
+        {
+            handler(Attachment::Attached(HidDevice::Mouse(mouse.as_ref())))
+        });
+}
+
+
+/// Called by a driver when one of its mice goes away.
+pub fn detach_mouse(mouse_id: usize)
+{
+    let mouse = with_interrupts_disabled(||
+        {
+            let mut mice = MICE.lock();
+            let index = mice.iter().position(|mouse| mouse.mouse_id() == mouse_id)?;
+
+            Some(mice.remove(index))
+        });
+
+    if let Some(mouse) = mouse
+    {
+        ATTACHMENT_HANDLERS.for_each(|handler|
+            {
+                handler(Attachment::Detached(HidDevice::Mouse(mouse.as_ref())))
+            });
+    }
+}
+
+
+
+/// Called by the keyboard drivers when a key is pressed, to call the handlers for any keyboard.
+pub fn report_key_pressed(keyboard: &dyn KeyboardDevice,
+                          key_code: KeyCode,
+                          modifiers: &KeyModifiers)
+{
+    ANY_KEYBOARD_HANDLERS.for_each(|(pressed, _)| pressed(keyboard, key_code, modifiers));
+}
+
+
+/// Called by the keyboard drivers when a key is released, to call the handlers for any keyboard.
+pub fn report_key_released(keyboard: &dyn KeyboardDevice,
+                           key_code: KeyCode,
+                           modifiers: &KeyModifiers)
+{
+    ANY_KEYBOARD_HANDLERS.for_each(|(_, released)| released(keyboard, key_code, modifiers));
+}
+
+
+/// Called by the mouse drivers when a mouse moves, to call the handlers for any mouse.
+pub fn report_mouse_moved(mouse: &dyn MouseDevice, delta_x: isize, delta_y: isize)
+{
+    ANY_MOUSE_HANDLERS.for_each(|handlers| (handlers.moved)(mouse, delta_x, delta_y));
+}
+
+
+/// Called by the mouse drivers when a mouse's wheel scrolls, to call the handlers for any mouse.
+pub fn report_mouse_scrolled(mouse: &dyn MouseDevice, delta: isize)
+{
+    ANY_MOUSE_HANDLERS.for_each(|handlers| (handlers.scrolled)(mouse, delta));
+}
+
+
+/// Called by the mouse drivers when a button is pressed, to call the handlers for any mouse.
+pub fn report_mouse_button_pressed(mouse: &dyn MouseDevice, button: usize)
+{
+    ANY_MOUSE_HANDLERS.for_each(|handlers| (handlers.button_pressed)(mouse, button));
+}
+
+
+/// Called by the mouse drivers when a button is released, to call the handlers for any mouse.
+pub fn report_mouse_button_released(mouse: &dyn MouseDevice, button: usize)
+{
+    ANY_MOUSE_HANDLERS.for_each(|handlers| (handlers.button_released)(mouse, button));
+}
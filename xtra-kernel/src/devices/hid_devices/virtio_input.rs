@@ -0,0 +1,688 @@
+// Driver for VirtIO input devices, the keyboards, mice and tablets QEMU provides with its
+// virtio-keyboard-device, virtio-mouse-device and virtio-tablet-device. The devices report Linux
+// evdev style events, a type, a code and a value, in buffers we keep in the device's event queue.
+// A batch of events is ended by a SYN_REPORT event.
+//
+// What kind of device we've got is worked out from the event types it says it reports. Devices
+// with relative or absolute axes are mice, the rest are keyboards if they have the letter keys.
+//
+// The events are handled by the device's interrupt handler, which calls the event handlers. If the
+// device's interrupt can't be routed it's polled instead, whenever its state is read.
+
+use core::ptr::read_volatile;
+
+use alloc::{ string::String, sync::Arc, vec, vec::Vec };
+
+use crate::{ arch::interrupts::with_interrupts_disabled,
+             devices::{ bus_devices::virtio_devices::{ mmio::VirtioMmioDevice,
+                                                       virtqueue::{ VirtQueue,
+                                                                    VirtQueueBuffer } },
+                        hid_devices::{ attach_keyboard,
+                                       attach_mouse,
+                                       key_codes::{ BTN_MOUSE, BTN_TOUCH, KEY_A },
+                                       next_hid_device_id,
+                                       report_key_pressed,
+                                       report_key_released,
+                                       report_mouse_button_pressed,
+                                       report_mouse_button_released,
+                                       report_mouse_moved,
+                                       report_mouse_scrolled,
+                                       HandlerTable,
+                                       KeyCode,
+                                       KeyModifiers,
+                                       KeyboardDevice,
+                                       KeyboardHandlers,
+                                       KeyboardPressedHandler,
+                                       KeyboardReleasedHandler,
+                                       KeyboardState,
+                                       MouseButtonPressedHandler,
+                                       MouseButtonReleasedHandler,
+                                       MouseDevice,
+                                       MouseHandlers,
+                                       MouseMovedHandler,
+                                       MouseScrolledHandler,
+                                       MouseState,
+                                       MAX_MOUSE_BUTTONS } },
+             interrupts::register_interrupt_handler,
+             locking::spin_mutex::SpinMutex };
+
+
+
+// Configuration space selectors.
+const VIRTIO_INPUT_CFG_ID_NAME: u8 = 0x01;  // The name of the device.
+const VIRTIO_INPUT_CFG_EV_BITS: u8 = 0x11;  // Bitmap of the codes of an event type.
+
+// Offsets into the device configuration space.
+const CONFIG_SELECT:    usize = 0x00;       // Which information to show in the data field.
+const CONFIG_SUBSELECT: usize = 0x01;       // Further selects the information, like the type.
+const CONFIG_SIZE:      usize = 0x02;       // How many bytes of data there are.
+const CONFIG_DATA:      usize = 0x08;       // The selected information.
+
+// Event types.
+const EV_SYN: u16 = 0x00;                   // Marks the end of a batch of events.
+const EV_KEY: u16 = 0x01;                   // A key or button changed state.
+const EV_REL: u16 = 0x02;                   // Movement along a relative axis.
+const EV_ABS: u16 = 0x03;                   // A new position on an absolute axis.
+
+// Event codes.
+const SYN_REPORT: u16 = 0x00;               // The end of a batch of events.
+const REL_X:      u16 = 0x00;
+const REL_Y:      u16 = 0x01;
+const REL_WHEEL:  u16 = 0x08;
+const ABS_X:      u16 = 0x00;
+const ABS_Y:      u16 = 0x01;
+
+
+
+/// The queue the device reports its events in.
+const EVENT_QUEUE: u16 = 0;
+
+/// The size of the event queue we ask the device for, also how many events can be buffered.
+const EVENT_QUEUE_SIZE: u16 = 64;
+
+/// The size of an event as written by the device, a u16 type, u16 code and u32 value.
+const EVENT_SIZE: usize = 8;
+
+
+
+/// An event reported by the device.
+#[derive(Clone, Copy)]
+struct InputEvent
+{
+    event_type: u16,
+    code: u16,
+    value: u32
+}
+
+
+
+impl InputEvent
+{
+    /// Decode an event from the little endian bytes the device wrote.
+    fn from_bytes(bytes: [u8; EVENT_SIZE]) -> InputEvent
+    {
+        InputEvent
+            {
+                event_type: u16::from_le_bytes([bytes[0], bytes[1]]),
+                code: u16::from_le_bytes([bytes[2], bytes[3]]),
+                value: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]])
+            }
+    }
+}
+
+
+
+/// The kinds of input device we drive.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum InputKind
+{
+    Keyboard,
+    Mouse
+}
+
+
+
+/// Something that happened on the device that the event handlers need to hear about. They're told
+/// once the device's lock has been released, so that they can read the device's state.
+enum Notification
+{
+    KeyPressed(KeyCode, KeyModifiers),
+    KeyReleased(KeyCode, KeyModifiers),
+    ButtonPressed(usize),
+    ButtonReleased(usize),
+
+    /// The movement and scrolling of a batch of events.
+    Report { delta_x: isize, delta_y: isize, scroll: isize }
+}
+
+
+
+/// The state of the device, also used by its interrupt handler so it's only ever locked with
+/// interrupts disabled.
+struct InputState
+{
+    /// The queue the device reports its events in.
+    queue: VirtQueue,
+
+    /// The buffers the device writes its events into, one per descriptor in the queue.
+    events: Vec<u8>,
+
+    /// Which buffer each descriptor chain in the queue is for, by the chain's head.
+    head_buffers: Vec<usize>,
+
+    /// The keys held down, for keyboards.
+    keyboard: KeyboardState,
+
+    /// The state of the mouse since it was last read, for mice.
+    mouse: MouseState,
+
+    /// The movement and scrolling of the batch of events being received.
+    pending_x: isize,
+    pending_y: isize,
+    pending_scroll: isize
+}
+
+
+
+impl InputState
+{
+    /// Hand an event buffer to the device to fill in.
+    fn queue_buffer(&mut self, buffer_index: usize) -> Result<(), &'static str>
+    {
+        let start = buffer_index * EVENT_SIZE;
+        let buffer = VirtQueueBuffer::writable(&mut self.events[start..start + EVENT_SIZE]);
+        let head = self.queue.add_buffers(&[buffer])?;
+
+        self.head_buffers[head as usize] = buffer_index;
+
+        Ok(())
+    }
+
+    /// Read an event the device has written into one of the buffers.
+    fn read_event(&self, buffer_index: usize) -> InputEvent
+    {
+        let event = self.events[buffer_index * EVENT_SIZE..].as_ptr() as *const [u8; EVENT_SIZE];
+        let bytes = unsafe { read_volatile(event) };
+
+        InputEvent::from_bytes(bytes)
+    }
+
+    /// Update the keyboard for an event, returning what the handlers need to be told about.
+    fn handle_keyboard_event(&mut self, event: InputEvent) -> Option<Notification>
+    {
+        if event.event_type != EV_KEY
+        {
+            return None;
+        }
+
+        // A value of 0 is a release, 1 a press and 2 the key repeating. The device's repeats are
+        // ignored, the keymaps repeat the keys themselves so that every keyboard repeats alike.
+        match event.value
+        {
+            0 =>
+                {
+                    self.keyboard.release(event.code);
+
+                    Some(Notification::KeyReleased(event.code, self.keyboard.modifiers))
+                },
+
+            1 =>
+                {
+                    self.keyboard.press(event.code);
+
+                    Some(Notification::KeyPressed(event.code, self.keyboard.modifiers))
+                },
+
+            _ => None
+        }
+    }
+
+    /// Update the mouse for an event, returning what the handlers need to be told about.
+    fn handle_mouse_event(&mut self, event: InputEvent) -> Option<Notification>
+    {
+        let value = event.value as i32 as isize;
+
+        match (event.event_type, event.code)
+        {
+            (EV_REL, REL_X)     => self.pending_x += value,
+            (EV_REL, REL_Y)     => self.pending_y += value,
+            (EV_REL, REL_WHEEL) => self.pending_scroll += value,
+
+            // Absolute positions are turned into movement as well, so that tablets can be used
+            // like mice.
+            (EV_ABS, ABS_X) =>
+                {
+                    let (x, y) = self.mouse.position.unwrap_or((value, 0));
+
+                    self.pending_x += value - x;
+                    self.mouse.position = Some((value, y));
+                },
+
+            (EV_ABS, ABS_Y) =>
+                {
+                    let (x, y) = self.mouse.position.unwrap_or((0, value));
+
+                    self.pending_y += value - y;
+                    self.mouse.position = Some((x, value));
+                },
+
+            (EV_KEY, code) =>
+                {
+                    // Tablets report a touch rather than a left button press.
+                    let button = if code == BTN_TOUCH
+                        {
+                            0
+                        }
+                        else
+                        {
+                            (code as usize).wrapping_sub(BTN_MOUSE as usize)
+                        };
+
+                    if button >= MAX_MOUSE_BUTTONS
+                    {
+                        return None;
+                    }
+
+                    let is_pressed = event.value != 0;
+
+                    self.mouse.buttons[button] = is_pressed;
+
+                    return Some(if is_pressed
+                        {
+                            Notification::ButtonPressed(button)
+                        }
+                        else
+                        {
+                            Notification::ButtonReleased(button)
+                        });
+                },
+
+            (EV_SYN, SYN_REPORT) =>
+                {
+                    let (delta_x, delta_y, scroll) = (self.pending_x,
+                                                      self.pending_y,
+                                                      self.pending_scroll);
+
+                    self.pending_x = 0;
+                    self.pending_y = 0;
+                    self.pending_scroll = 0;
+
+                    self.mouse.delta_x += delta_x;
+                    self.mouse.delta_y += delta_y;
+                    self.mouse.scroll += scroll;
+
+                    if    delta_x != 0
+                       || delta_y != 0
+                       || scroll != 0
+                    {
+                        return Some(Notification::Report { delta_x, delta_y, scroll });
+                    }
+                },
+
+            _ => {}
+        }
+
+        None
+    }
+}
+
+
+
+/// A VirtIO keyboard, mouse or tablet.
+pub struct VirtioInputDevice
+{
+    /// The device's ID as a keyboard or mouse.
+    id: usize,
+
+    /// The name the device reports for itself.
+    name: String,
+
+    /// What kind of device this is.
+    kind: InputKind,
+
+    /// The device's register interface.
+    device: VirtioMmioDevice,
+
+    /// The device's state.
+    state: SpinMutex<InputState>,
+
+    /// The handlers for the keyboard's events.
+    key_handlers: HandlerTable<KeyboardHandlers>,
+
+    /// The handlers for the mouse's events.
+    mouse_handlers: HandlerTable<MouseHandlers>
+}
+
+
+
+impl VirtioInputDevice
+{
+    /// Perform the VirtIO initialization handshake for the input device, work out what kind of
+    /// device it is and fill its event queue.
+    pub fn new(device: VirtioMmioDevice) -> Result<Self, &'static str>
+    {
+        device.begin_initialization();
+        device.negotiate_features(0)?;
+
+        let name = read_device_name(&device);
+
+        let kind = if    has_event_type(&device, EV_REL)
+                      || has_event_type(&device, EV_ABS)
+            {
+                InputKind::Mouse
+            }
+            else if has_key(&device, KEY_A)
+            {
+                InputKind::Keyboard
+            }
+            else
+            {
+                device.fail();
+                return Err("VirtIO input device isn't a keyboard, mouse or tablet.");
+            };
+
+        let queue = device.setup_queue(EVENT_QUEUE, EVENT_QUEUE_SIZE)?;
+        let buffer_count = queue.size() as usize;
+
+        let mut state = InputState
+            {
+                queue,
+                events: vec![0u8; buffer_count * EVENT_SIZE],
+                head_buffers: vec![0; buffer_count],
+                keyboard: KeyboardState::default(),
+                mouse: MouseState::default(),
+                pending_x: 0,
+                pending_y: 0,
+                pending_scroll: 0
+            };
+
+        for buffer_index in 0..buffer_count
+        {
+            state.queue_buffer(buffer_index)?;
+        }
+
+        device.finish_initialization();
+        device.notify_queue(EVENT_QUEUE);
+
+        Ok(VirtioInputDevice
+            {
+                id: next_hid_device_id(),
+                name,
+                kind,
+                device,
+                state: SpinMutex::new(state),
+                key_handlers: HandlerTable::new(),
+                mouse_handlers: HandlerTable::new()
+            })
+    }
+
+    /// Handle all of the events the device has reported, calling the event handlers as we go.
+    fn process_events(&self)
+    {
+        while let Some(notification) = self.next_event()
+        {
+            if let Some(notification) = notification
+            {
+                self.notify(notification);
+            }
+        }
+    }
+
+    /// Take the next event from the device and hand its buffer back. Returns None if there are no
+    /// more events, otherwise what the handlers need to be told about the event, if anything.
+    fn next_event(&self) -> Option<Option<Notification>>
+    {
+        with_interrupts_disabled(||
+            {
+                let mut state = self.state.lock();
+                let used = state.queue.pop_used()?;
+                let buffer_index = state.head_buffers[used.head as usize];
+                let event = state.read_event(buffer_index);
+
+                if state.queue_buffer(buffer_index).is_ok()
+                {
+                    self.device.notify_queue(EVENT_QUEUE);
+                }
+
+                Some(match self.kind
+                    {
+                        InputKind::Keyboard => state.handle_keyboard_event(event),
+                        InputKind::Mouse    => state.handle_mouse_event(event)
+                    })
+            })
+    }
+
+    /// Tell this device's handlers and the handlers for any device about something that happened.
+    fn notify(&self, notification: Notification)
+    {
+        match notification
+        {
+            Notification::KeyPressed(key_code, modifiers) =>
+                {
+                    self.key_handlers.for_each(|(pressed, _)| pressed(self, key_code, &modifiers));
+                    report_key_pressed(self, key_code, &modifiers);
+                },
+
+            Notification::KeyReleased(key_code, modifiers) =>
+                {
+                    self.key_handlers.for_each(|(_, released)| released(self,
+                                                                        key_code,
+                                                                        &modifiers));
+                    report_key_released(self, key_code, &modifiers);
+                },
+
+            Notification::ButtonPressed(button) =>
+                {
+                    self.mouse_handlers.for_each(|handlers| (handlers.button_pressed)(self,
+                                                                                      button));
+                    report_mouse_button_pressed(self, button);
+                },
+
+            Notification::ButtonReleased(button) =>
+                {
+                    self.mouse_handlers.for_each(|handlers| (handlers.button_released)(self,
+                                                                                       button));
+                    report_mouse_button_released(self, button);
+                },
+
+            Notification::Report { delta_x, delta_y, scroll } =>
+                {
+                    if    delta_x != 0
+                       || delta_y != 0
+                    {
+                        self.mouse_handlers.for_each(|handlers| (handlers.moved)(self,
+                                                                                 delta_x,
+                                                                                 delta_y));
+                        report_mouse_moved(self, delta_x, delta_y);
+                    }
+
+                    if scroll != 0
+                    {
+                        self.mouse_handlers.for_each(|handlers| (handlers.scrolled)(self, scroll));
+                        report_mouse_scrolled(self, scroll);
+                    }
+                }
+        }
+    }
+}
+
+
+
+impl KeyboardDevice for VirtioInputDevice
+{
+    fn keyboard_id(&self) -> usize
+    {
+        self.id
+    }
+
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn read_keys(&self) -> Option<KeyboardState>
+    {
+        self.process_events();
+
+        let keyboard = with_interrupts_disabled(|| self.state.lock().keyboard);
+
+        (keyboard.key_count != 0).then_some(keyboard)
+    }
+
+    fn register_key_handlers(&self,
+                             pressed_handler: KeyboardPressedHandler,
+                             released_handler: KeyboardReleasedHandler) -> usize
+    {
+        self.key_handlers.register((pressed_handler, released_handler))
+    }
+
+    fn unregister_key_handlers(&self, handler_id: usize)
+    {
+        self.key_handlers.unregister(handler_id);
+    }
+}
+
+
+
+impl MouseDevice for VirtioInputDevice
+{
+    fn mouse_id(&self) -> usize
+    {
+        self.id
+    }
+
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn read_state(&self) -> MouseState
+    {
+        self.process_events();
+
+        with_interrupts_disabled(||
+            {
+                let mut state = self.state.lock();
+                let mouse = state.mouse;
+
+                state.mouse.delta_x = 0;
+                state.mouse.delta_y = 0;
+                state.mouse.scroll = 0;
+
+                mouse
+            })
+    }
+
+    fn register_mouse_handlers(&self,
+                               moved_handler: MouseMovedHandler,
+                               scrolled_handler: MouseScrolledHandler,
+                               button_pressed_handler: MouseButtonPressedHandler,
+                               button_released_handler: MouseButtonReleasedHandler) -> usize
+    {
+        self.mouse_handlers.register(MouseHandlers
+            {
+                moved: moved_handler,
+                scrolled: scrolled_handler,
+                button_pressed: button_pressed_handler,
+                button_released: button_released_handler
+            })
+    }
+
+    fn unregister_mouse_handlers(&self, handler_id: usize)
+    {
+        self.mouse_handlers.unregister(handler_id);
+    }
+}
+
+
+
+/// The active input devices. Also searched by the interrupt handler, so it's only locked with
+/// interrupts disabled.
+static INPUT_DEVICES: SpinMutex<Vec<Arc<VirtioInputDevice>>> = SpinMutex::new(Vec::new());
+
+
+
+/// Called by the VirtIO bus when it finds an input device. The device is initialized, its interrupt
+/// routed to the current hart and it's attached as a keyboard or mouse.
+pub fn probe_virtio_input_device(device: VirtioMmioDevice) -> Result<(), &'static str>
+{
+    let input_device = Arc::new(VirtioInputDevice::new(device)?);
+
+    with_interrupts_disabled(|| INPUT_DEVICES.lock().push(input_device.clone()));
+
+    let kind = match input_device.kind
+        {
+            InputKind::Keyboard => "keyboard",
+            InputKind::Mouse    => "mouse"
+        };
+
+    println!("  input{}: {} ({})", input_device.id, input_device.name, kind);
+
+    match device.interrupt()
+    {
+        Some(interrupt) =>
+            {
+                if let Err(error) = register_interrupt_handler(None,
+                                                               interrupt as usize,
+                                                               handle_input_interrupt)
+                {
+                    println!("  input{} will be polled, its interrupt couldn't be routed: {}",
+                             input_device.id,
+                             error);
+                }
+            },
+
+        None => println!("  input{} has no interrupt, it will be polled.", input_device.id)
+    }
+
+    match input_device.kind
+    {
+        InputKind::Keyboard => attach_keyboard(input_device),
+        InputKind::Mouse    => attach_mouse(input_device)
+    }
+
+    Ok(())
+}
+
+
+
+/// Handle an interrupt for any of the input devices on the interrupt.
+fn handle_input_interrupt(interrupt_number: usize)
+{
+    let mut index = 0;
+
+    // The device list isn't held while the events are handled, so that the handlers are free to
+    // look at the devices.
+    while let Some(input_device) = INPUT_DEVICES.lock().get(index).cloned()
+    {
+        index += 1;
+
+        if input_device.device.interrupt() != Some(interrupt_number as u32)
+        {
+            continue;
+        }
+
+        input_device.device.acknowledge_interrupt();
+        input_device.process_events();
+    }
+}
+
+
+
+/// Select a piece of the device's configuration information, returning how big it is.
+fn select_config(device: &VirtioMmioDevice, select: u8, subselect: u8) -> usize
+{
+    device.config_write_u8(CONFIG_SELECT, select);
+    device.config_write_u8(CONFIG_SUBSELECT, subselect);
+
+    device.config_read_u8(CONFIG_SIZE) as usize
+}
+
+
+/// Read the name the device reports for itself.
+fn read_device_name(device: &VirtioMmioDevice) -> String
+{
+    let size = select_config(device, VIRTIO_INPUT_CFG_ID_NAME, 0);
+    let bytes: Vec<u8> = (0..size).map(|offset| device.config_read_u8(CONFIG_DATA + offset))
+                                  .collect();
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+
+/// Does the device report events of the given type?
+fn has_event_type(device: &VirtioMmioDevice, event_type: u16) -> bool
+{
+    select_config(device, VIRTIO_INPUT_CFG_EV_BITS, event_type as u8) != 0
+}
+
+
+/// Does the device have the given key?
+fn has_key(device: &VirtioMmioDevice, key: KeyCode) -> bool
+{
+    let size = select_config(device, VIRTIO_INPUT_CFG_EV_BITS, EV_KEY as u8);
+    let byte_index = key as usize / 8;
+
+       byte_index < size
+    && device.config_read_u8(CONFIG_DATA + byte_index) & (1 << (key % 8)) != 0
+}
@@ -0,0 +1,186 @@
+// The interrupt controllers of the system. Device interrupts are routed to the harts by the
+// platform level interrupt controller, (PLIC,) while each hart has its own local interrupt
+// controller described by an interrupt-controller node under its cpu node. We don't drive the
+// harts' controllers, the kernel talks to them through the CSRs, but the PLIC refers to them by
+// their phandles to say which of its contexts belongs to which hart.
+//
+// The rest of the kernel doesn't talk to the PLIC directly, it goes through the functions here so
+// that other interrupt controllers can be supported later.
+
+use alloc::collections::BTreeMap;
+
+use xtra_kernel_shared::device_tree::DeviceTree;
+
+use crate::{ devices::{ cpu_devices::find_cpu_containing_node,
+                        property_contains_string,
+                        read_property_cells,
+                        DeviceDriverRegistry },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// The driver for the RISC-V platform level interrupt controller.
+pub mod plic;
+
+
+
+use crate::devices::interrupt_controllers::plic::{ with_plic, DEFAULT_PRIORITY };
+
+
+
+/// The phandles of the harts' local interrupt controllers, mapped to the IDs of their harts.
+static HART_INTERRUPT_CONTROLLERS: SpinMutex<BTreeMap<u32, usize>> =
+    SpinMutex::new(BTreeMap::new());
+
+
+
+/// Register the driver probe functions for all of the interrupt controllers in the system.
+pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(), &'static str>
+{
+    registry.insert("plic", probe_interrupt_controller);
+    registry.insert("interrupt-controller", probe_interrupt_controller);
+
+    Ok(())
+}
+
+
+/// Activate and initialize the interrupt controllers discovered in the device tree. If any. Though,
+/// it would be a highly unusual system if we didn't find any interrupt controllers in the device
+/// tree.
+pub fn activate_devices() -> Result<(), &'static str>
+{
+    plic::activate()
+}
+
+
+
+/// Find the hart a local interrupt controller belongs to, by the controller's phandle.
+pub fn hart_for_interrupt_controller(phandle: u32) -> Option<usize>
+{
+    HART_INTERRUPT_CONTROLLERS.lock().get(&phandle).copied()
+}
+
+
+
+/// Route an interrupt source to a hart and enable it.
+pub fn enable_source(hart_id: usize, source: usize) -> Result<(), &'static str>
+{
+    with_plic(|plic|
+        {
+            let context = plic.context(hart_id).ok_or("The hart can't take device interrupts.")?;
+
+            if    source == 0
+               || source > plic.source_count()
+            {
+                return Err("No such interrupt source.");
+            }
+
+            plic.set_priority(source, DEFAULT_PRIORITY);
+            plic.set_enabled(context, source, true);
+
+            Ok(())
+        })
+        .unwrap_or(Err("There is no interrupt controller for device interrupts."))
+}
+
+
+/// Stop an interrupt source from interrupting a hart.
+pub fn disable_source(hart_id: usize, source: usize) -> Result<(), &'static str>
+{
+    with_plic(|plic|
+        {
+            let context = plic.context(hart_id).ok_or("The hart can't take device interrupts.")?;
+
+            plic.set_enabled(context, source, false);
+
+            Ok(())
+        })
+        .unwrap_or(Err("There is no interrupt controller for device interrupts."))
+}
+
+
+/// Set the priority threshold of a hart, only sources with a higher priority interrupt it. A
+/// threshold of 0 lets every enabled source through.
+pub fn set_hart_threshold(hart_id: usize, threshold: u32) -> Result<(), &'static str>
+{
+    with_plic(|plic|
+        {
+            let context = plic.context(hart_id).ok_or("The hart can't take device interrupts.")?;
+
+            plic.set_threshold(context, threshold);
+
+            Ok(())
+        })
+        .unwrap_or(Err("There is no interrupt controller for device interrupts."))
+}
+
+
+/// Claim the next device interrupt pending for a hart, called from the interrupt handler.
+pub fn claim_interrupt(hart_id: usize) -> Option<usize>
+{
+    with_plic(|plic| plic.context(hart_id).and_then(|context| plic.claim(context))).flatten()
+}
+
+
+/// Let the interrupt controller know a claimed interrupt has been handled.
+pub fn complete_interrupt(hart_id: usize, source: usize)
+{
+    with_plic(|plic|
+        {
+            if let Some(context) = plic.context(hart_id)
+            {
+                plic.complete(context, source);
+            }
+        });
+}
+
+
+
+/// Probe a node that may be an interrupt controller. The nodes of both the PLIC and the harts'
+/// local controllers can be called interrupt-controller, so we go by what they're compatible with.
+fn probe_interrupt_controller(name: &str,
+                              address: Option<usize>,
+                              device_tree: &DeviceTree,
+                              block_offset: usize) -> Result<(), &'static str>
+{
+    let mut is_hart_controller = false;
+    let mut is_plic = false;
+    let mut phandle = None;
+
+    device_tree.iterate_properties(block_offset, |property_name, property_value|
+        {
+            match property_name
+            {
+                "compatible" =>
+                    {
+                        is_hart_controller = property_contains_string(property_value,
+                                                                      "riscv,cpu-intc");
+                        is_plic = plic::is_compatible(property_value);
+                    },
+
+                "phandle" | "linux,phandle" => phandle = read_property_cells(property_value),
+
+                _ => {}
+            }
+
+            true
+        });
+
+    if is_plic
+    {
+        return plic::probe(device_tree, block_offset);
+    }
+
+    if !is_hart_controller
+    {
+        return Ok(());
+    }
+
+    let phandle = phandle.ok_or("Hart interrupt controller has no phandle.")?;
+    let cpu = find_cpu_containing_node(block_offset)
+        .ok_or("Hart interrupt controller isn't under a cpu node.")?;
+
+    HART_INTERRUPT_CONTROLLERS.lock().insert(phandle as u32, cpu.hart_id);
+
+    Ok(())
+}
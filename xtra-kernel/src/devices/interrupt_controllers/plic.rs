@@ -0,0 +1,293 @@
+// The driver for the RISC-V platform level interrupt controller, (PLIC.) The PLIC collects the
+// interrupts raised by the system's devices and routes them to the harts. Each hart has a context
+// for each privilege mode it can take interrupts in, a context has its own set of enabled sources,
+// a priority threshold and a claim register to find out which source is interrupting it.
+//
+// The kernel only uses the supervisor mode contexts. Which context belongs to which hart is
+// described by the PLIC's interrupts-extended property, a list of hart interrupt controllers and
+// the cause they see the context's interrupts as, in context order.
+
+use core::ptr::{ read_volatile, write_volatile };
+
+use alloc::{ collections::BTreeMap, vec::Vec };
+
+use xtra_kernel_shared::device_tree::DeviceTree;
+
+use crate::{ arch::interrupts::with_interrupts_disabled,
+             devices::{ interrupt_controllers::hart_for_interrupt_controller,
+                        property_contains_string,
+                        read_property_cells },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// The compatible strings of the PLICs we can drive.
+const COMPATIBLE_PLICS: [&str; 2] = [ "riscv,plic0", "sifive,plic-1.0.0" ];
+
+/// Offsets of the PLIC's register blocks.
+const PRIORITY_BASE: usize = 0x00_0000;           // One 32-bit priority per source.
+const ENABLE_BASE: usize = 0x00_2000;             // A bitmap of the enabled sources per context.
+const ENABLE_CONTEXT_STRIDE: usize = 0x80;
+const CONTEXT_BASE: usize = 0x20_0000;            // Threshold and claim registers per context.
+const CONTEXT_STRIDE: usize = 0x1000;
+
+/// Offsets of the registers within a context's block.
+const CONTEXT_THRESHOLD: usize = 0x0;
+const CONTEXT_CLAIM: usize = 0x4;                 // Read to claim, write to complete.
+
+/// The most interrupt sources a PLIC can have, source 0 is reserved to mean no interrupt.
+const MAX_SOURCES: usize = 1024;
+
+/// The cause a hart sees a supervisor external interrupt as, used to pick out the supervisor
+/// contexts from interrupts-extended.
+const SUPERVISOR_EXTERNAL_INTERRUPT: u32 = 9;
+
+/// The priority given to every enabled source, we don't prioritize between devices yet.
+pub const DEFAULT_PRIORITY: u32 = 1;
+
+
+
+/// A PLIC as found in the device tree, before its contexts have been matched with the harts.
+struct ProbedPlic
+{
+    /// Where the PLIC's registers start.
+    base_address: usize,
+
+    /// How many interrupt sources the PLIC has, not counting the reserved source 0.
+    source_count: usize,
+
+    /// The hart interrupt controller phandle and cause of each of the PLIC's contexts.
+    contexts: Vec<(u32, u32)>
+}
+
+
+
+/// An active PLIC.
+pub struct Plic
+{
+    /// Where the PLIC's registers start.
+    base_address: usize,
+
+    /// How many interrupt sources the PLIC has, not counting the reserved source 0.
+    source_count: usize,
+
+    /// The supervisor mode context of each hart, indexed by hart ID.
+    contexts: BTreeMap<usize, usize>
+}
+
+
+
+impl Plic
+{
+    /// How many interrupt sources the PLIC has.
+    pub fn source_count(&self) -> usize
+    {
+        self.source_count
+    }
+
+    /// Get the supervisor context of a hart, if the PLIC can interrupt it.
+    pub fn context(&self, hart_id: usize) -> Option<usize>
+    {
+        self.contexts.get(&hart_id).copied()
+    }
+
+    /// Set the priority of an interrupt source, a priority of 0 means the source never interrupts.
+    pub fn set_priority(&self, source: usize, priority: u32)
+    {
+        self.write(PRIORITY_BASE + source * 4, priority);
+    }
+
+    /// Enable or disable an interrupt source for a context.
+    pub fn set_enabled(&self, context: usize, source: usize, is_enabled: bool)
+    {
+        let offset = ENABLE_BASE + context * ENABLE_CONTEXT_STRIDE + (source / 32) * 4;
+        let bit = 1 << (source % 32);
+        let value = self.read(offset);
+
+        self.write(offset, if is_enabled { value | bit } else { value & !bit });
+    }
+
+    /// Set the priority a source needs to exceed to interrupt a context.
+    pub fn set_threshold(&self, context: usize, threshold: u32)
+    {
+        self.write(CONTEXT_BASE + context * CONTEXT_STRIDE + CONTEXT_THRESHOLD, threshold);
+    }
+
+    /// Claim the highest priority interrupt pending for a context, if there is one.
+    pub fn claim(&self, context: usize) -> Option<usize>
+    {
+        match self.read(CONTEXT_BASE + context * CONTEXT_STRIDE + CONTEXT_CLAIM)
+        {
+            0      => None,
+            source => Some(source as usize)
+        }
+    }
+
+    /// Tell the PLIC we're done with a claimed interrupt, so the source can interrupt again.
+    pub fn complete(&self, context: usize, source: usize)
+    {
+        self.write(CONTEXT_BASE + context * CONTEXT_STRIDE + CONTEXT_CLAIM, source as u32);
+    }
+
+    /// Read one of the PLIC's registers.
+    fn read(&self, offset: usize) -> u32
+    {
+        unsafe { read_volatile((self.base_address + offset) as *const u32) }
+    }
+
+    /// Write one of the PLIC's registers.
+    fn write(&self, offset: usize, value: u32)
+    {
+        unsafe { write_volatile((self.base_address + offset) as *mut u32, value) }
+    }
+}
+
+
+
+/// The PLICs found in the device tree.
+static PROBED_PLICS: SpinMutex<Vec<ProbedPlic>> = SpinMutex::new(Vec::new());
+
+/// The PLIC in use. Its lock is also taken by the interrupt handlers, so it must only be held with
+/// interrupts disabled.
+static PLIC: SpinMutex<Option<Plic>> = SpinMutex::new(None);
+
+
+
+/// Read a big endian 32-bit cell.
+fn read_u32(bytes: &[u8]) -> u32
+{
+    u32::from_be_bytes(bytes.try_into().unwrap())
+}
+
+
+/// Is the node compatible with a PLIC we can drive?
+pub fn is_compatible(compatible: &[u8]) -> bool
+{
+    COMPATIBLE_PLICS.iter().any(|name| property_contains_string(compatible, name))
+}
+
+
+/// Record a PLIC found in the device tree, it's set up once all of the harts' interrupt
+/// controllers are known.
+pub fn probe(device_tree: &DeviceTree, block_offset: usize) -> Result<(), &'static str>
+{
+    let mut base_address = None;
+    let mut source_count = None;
+    let mut contexts = Vec::new();
+
+    device_tree.iterate_properties(block_offset, |property_name, property_value|
+        {
+            match property_name
+            {
+                "reg" if property_value.len() >= 8 =>
+                    {
+                        let cell_size = if property_value.len() >= 16 { 8 } else { 4 };
+
+                        base_address = read_property_cells(&property_value[..cell_size]);
+                    },
+
+                "riscv,ndev" => source_count = read_property_cells(property_value),
+
+                "interrupts-extended" =>
+                    {
+                        contexts = property_value.chunks_exact(8)
+                                                 .map(|pair| (read_u32(&pair[..4]),
+                                                              read_u32(&pair[4..])))
+                                                 .collect();
+                    },
+
+                _ => {}
+            }
+
+            true
+        });
+
+    let base_address = base_address.ok_or("PLIC is missing its reg property.")? as usize;
+    let source_count = source_count.ok_or("PLIC doesn't say how many sources it has.")? as usize;
+
+    if source_count >= MAX_SOURCES
+    {
+        return Err("PLIC claims to have more sources than a PLIC can have.");
+    }
+
+    PROBED_PLICS.lock().push(ProbedPlic { base_address, source_count, contexts });
+
+    Ok(())
+}
+
+
+/// Set up the PLIC found in the device tree. Every source starts out disabled with a priority of 0
+/// and every hart's threshold is 0, so that a source interrupts once it's been given a priority and
+/// enabled. A system without a PLIC is reported, its devices will have to be polled.
+pub fn activate() -> Result<(), &'static str>
+{
+    let mut probed = PROBED_PLICS.lock();
+
+    if probed.is_empty()
+    {
+        println!("  No PLIC found, device interrupts are not available.");
+        return Ok(());
+    }
+
+    if probed.len() > 1
+    {
+        println!("  Found {} PLICs, only the first one will be used.", probed.len());
+    }
+
+    let probed_plic = probed.remove(0);
+    let mut contexts = BTreeMap::new();
+
+    for (context, &(phandle, cause)) in probed_plic.contexts.iter().enumerate()
+    {
+        if cause != SUPERVISOR_EXTERNAL_INTERRUPT
+        {
+            continue;
+        }
+
+        if let Some(hart_id) = hart_for_interrupt_controller(phandle)
+        {
+            contexts.insert(hart_id, context);
+        }
+    }
+
+    let plic = Plic
+        {
+            base_address: probed_plic.base_address,
+            source_count: probed_plic.source_count,
+            contexts
+        };
+
+    for source in 1..=plic.source_count
+    {
+        plic.set_priority(source, 0);
+    }
+
+    for &context in plic.contexts.values()
+    {
+        for source in 1..=plic.source_count
+        {
+            plic.set_enabled(context, source, false);
+        }
+
+        plic.set_threshold(context, 0);
+    }
+
+    println!("  PLIC at 0x{:016x}, {} sources, routed to {} harts.",
+             plic.base_address,
+             plic.source_count,
+             plic.contexts.len());
+
+    with_interrupts_disabled(|| *PLIC.lock() = Some(plic));
+
+    Ok(())
+}
+
+
+/// Run a function with the active PLIC, if there is one. Interrupts are disabled while the PLIC is
+/// in use.
+pub fn with_plic<Func, Value>(function: Func) -> Option<Value>
+    where
+        Func: FnOnce(&Plic) -> Value
+{
+    with_interrupts_disabled(|| PLIC.lock().as_ref().map(function))
+}
@@ -4,7 +4,9 @@
 // physical device drivers and virtual devices drivers that sit on top of the physical devices,
 // such as console device drivers.
 
-use alloc::{ format, collections::BTreeMap };
+use core::str::from_utf8;
+
+use alloc::{ format, collections::BTreeMap, string::{ String, ToString } };
 
 use xtra_kernel_shared::device_tree::{ DeviceTree };
 
@@ -22,6 +24,9 @@ pub mod console;
 /// CPU devices, such as the CPU cores, the CPU cache, etc.
 pub mod cpu_devices;
 
+/// Entropy devices, the hardware random number generators that seed the kernel's own generator.
+pub mod entropy_devices;
+
 /// Graphics devices, such as GPUs, display controllers, etc.
 pub mod graphics_devices;
 
@@ -57,11 +62,12 @@ pub mod timer_devices;
 /// the driver will need to manage the device.
 ///
 /// The function will be called with the device tree name and the address of the device if it is
-/// specified in the name. The device tree object is supplied so that the driver can perform device
-/// specific parsing of the device tree block.
+/// specified in the name. The device tree object and the offset of the node's block within it are
+/// supplied so that the driver can perform device specific parsing of the node's properties.
 pub type DriverProbeFunction = fn(name: &str,
                                   address: Option<usize>,
-                                  device_tree: &DeviceTree) -> Result<(), &'static str>;
+                                  device_tree: &DeviceTree,
+                                  block_offset: usize) -> Result<(), &'static str>;
 
 
 /// The device driver registry type, this is a mapping from device tree node names to the driver
@@ -74,6 +80,54 @@ pub type DeviceDriverRegistry = BTreeMap<&'static str, DriverProbeFunction>;
 
 
 
+/// Read a big endian cell value of one or two cells from a device tree property.
+pub fn read_property_cells(value: &[u8]) -> Option<u64>
+{
+    match value.len()
+    {
+        4 => Some(u32::from_be_bytes(value.try_into().unwrap()) as u64),
+        8 => Some(u64::from_be_bytes(value.try_into().unwrap())),
+        _ => None
+    }
+}
+
+
+/// Read a reg property holding one address and one size, each of one or two cells. Only the first
+/// range is read if there are more.
+pub fn read_property_reg(value: &[u8]) -> Option<(usize, usize)>
+{
+    let cell_size = match value.len()
+        {
+            8 | 12                  => 4,
+            length if length >= 16  => 8,
+            _                       => return None
+        };
+
+    let base_address = read_property_cells(&value[..cell_size])?;
+    let size = read_property_cells(&value[cell_size..cell_size * 2])?;
+
+    Some((base_address as usize, size as usize))
+}
+
+
+/// Read a device tree property holding a zero terminated string.
+pub fn read_property_string(value: &[u8]) -> String
+{
+    let end = value.iter().position(|&byte| byte == 0).unwrap_or(value.len());
+
+    from_utf8(&value[..end]).unwrap_or("").to_string()
+}
+
+
+/// Check if a device tree property holding a list of zero terminated strings, like compatible,
+/// contains the given string.
+pub fn property_contains_string(value: &[u8], string: &str) -> bool
+{
+    value.split(|&byte| byte == 0).any(|entry| entry == string.as_bytes())
+}
+
+
+
 /// Initialize the device registry, this will set up the data structures for storing the device
 /// driver to device tree block mappings.
 pub fn initialize_device_registry() -> Result<DeviceDriverRegistry, &'static str>
@@ -88,6 +142,7 @@ pub fn initialize_device_registry() -> Result<DeviceDriverRegistry, &'static str
     block_devices::register_driver_probes(&mut registry)?;
     bus_devices::register_driver_probes(&mut registry)?;
     cpu_devices::register_driver_probes(&mut registry)?;
+    entropy_devices::register_driver_probes(&mut registry)?;
     graphics_devices::register_driver_probes(&mut registry)?;
     interrupt_controllers::register_driver_probes(&mut registry)?;
     mmio_devices::register_driver_probes(&mut registry)?;
@@ -145,7 +200,7 @@ pub fn walk_device_tree(device_tree: &DeviceTree,
             // probe function to initialize the device.
             if let Some(probe_function) = device_registry.get::<str>(name)
             {
-                let result = probe_function(name, address, device_tree)
+                let result = probe_function(name, address, device_tree, tree_offset)
                     .map_err(|err|
                         {
                             format!("Failed to initialize device driver for node {}: {}", name, err)
@@ -190,6 +245,9 @@ pub fn activate_devices() -> Result<(), &'static str>
     serial_devices::activate_devices()?;
     test_devices::activate_devices()?;
     graphics_devices::activate_devices()?;
+    hid_devices::activate_devices()?;
+    network_devices::activate_devices()?;
+    entropy_devices::activate_devices()?;
 
     // Now that we've initialized the core physical devices we can now go to the attached device
     // buses and probe them for their attached devices.
@@ -0,0 +1,270 @@
+// The network device subsystem. Network drivers register a device for each network card they
+// drive, where the protocol stack can find them by their index or name. A device moves whole
+// Ethernet frames, the stack hands it frames to send and sets a handler that's called with each
+// frame the device receives.
+//
+// The frames are received by the drivers' interrupt handlers, so the receive handlers are called
+// in interrupt context. They need to be quick, and they must only take locks that are held with
+// interrupts disabled everywhere else.
+
+use core::fmt::{ self, Display, Formatter };
+
+use alloc::{ format, string::String, sync::Arc, vec::Vec };
+
+use xtra_kernel_shared::device_tree::DeviceTree;
+
+use crate::{ devices::{ bus_devices::virtio_devices::{ VirtioDriverRegistry,
+                                                       VIRTIO_NETWORK_DEVICE_ID },
+                        DeviceDriverRegistry },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// Driver for VirtIO network cards.
+pub mod virtio_net;
+
+
+
+/// The size of an Ethernet header, the destination and source addresses and the EtherType.
+pub const ETHERNET_HEADER_SIZE: usize = 14;
+
+/// The MTU of a standard Ethernet network, used when the device doesn't say otherwise.
+pub const DEFAULT_MTU: usize = 1500;
+
+
+
+/// Results for network device operations.
+pub type NetworkResult<T> = Result<T, &'static str>;
+
+
+
+/// An Ethernet MAC address.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Debug)]
+pub struct MacAddress(pub [u8; 6]);
+
+
+
+impl MacAddress
+{
+    /// The address every device on the network receives.
+    pub const BROADCAST: MacAddress = MacAddress([ 0xff; 6 ]);
+
+    /// Is this the broadcast address?
+    pub fn is_broadcast(&self) -> bool
+    {
+        *self == MacAddress::BROADCAST
+    }
+
+    /// Is this a multicast address, (which includes the broadcast address?)
+    pub fn is_multicast(&self) -> bool
+    {
+        self.0[0] & 0x01 != 0
+    }
+}
+
+
+
+impl Display for MacAddress
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        let [ a, b, c, d, e, f ] = self.0;
+
+        write!(formatter, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, f)
+    }
+}
+
+
+
+/// A request to have a checksum filled in as a frame is sent, the way TCP and UDP checksums are
+/// offloaded. The ones' complement checksum of the frame from `start` to its end is stored at
+/// `start + offset`, both counted from the start of the frame.
+///
+/// As with the hardware doing it, the checksum field has to already hold the checksum of the
+/// protocol's pseudo header, which gets folded in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ChecksumRequest
+{
+    pub start: usize,
+    pub offset: usize
+}
+
+
+
+/// The counts of a device's traffic since it was attached.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct NetworkStatistics
+{
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+
+    /// Frames the device received that were malformed.
+    pub rx_errors: u64,
+
+    /// Frames received with no handler to take them.
+    pub rx_dropped: u64,
+
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+
+    /// Frames the device couldn't be given, because they were malformed.
+    pub tx_errors: u64,
+
+    /// Frames not sent because the device's transmit queue was full.
+    pub tx_dropped: u64
+}
+
+
+
+/// The handler called with each frame a device receives, and whether the frame's TCP or UDP
+/// checksum is known to be good. The frame starts with its Ethernet header.
+pub type FrameReceivedHandler = fn(device: &dyn NetworkDevice, frame: &[u8], checksum_valid: bool);
+
+
+
+/// The interface all network devices expose to the protocol stack.
+pub trait NetworkDevice: Send + Sync
+{
+    /// The name of the device, for example eth0.
+    fn name(&self) -> &str;
+
+    /// The device's MAC address.
+    fn mac_address(&self) -> MacAddress;
+
+    /// The largest payload a frame can carry, not counting the Ethernet header.
+    fn mtu(&self) -> usize;
+
+    /// Is the device connected to a network?
+    fn is_link_up(&self) -> bool;
+
+    /// Can the device fill in checksums itself? Checksum requests are honored either way, but
+    /// without the device's help the checksum is worked out as the frame is sent.
+    fn has_checksum_offload(&self) -> bool;
+
+    /// Queue a frame, starting with its Ethernet header, to be sent. The frame is copied so the
+    /// buffer can be reused as soon as this returns.
+    fn send_frame(&self, frame: &[u8], checksum: Option<ChecksumRequest>) -> NetworkResult<()>;
+
+    /// Set the handler called with the frames the device receives, or take it away. Without a
+    /// handler the frames are dropped.
+    fn set_receive_handler(&self, handler: Option<FrameReceivedHandler>);
+
+    /// Handle whatever the device has done since it was last looked at, for when its interrupt
+    /// isn't available.
+    fn poll(&self);
+
+    /// The counts of the device's traffic.
+    fn statistics(&self) -> NetworkStatistics;
+}
+
+
+
+/// All of the network devices in the system, in the order they were found.
+static NETWORK_DEVICES: SpinMutex<Vec<Arc<dyn NetworkDevice>>> = SpinMutex::new(Vec::new());
+
+
+
+/// Register the driver probe functions for all of the network device drivers in the system.
+pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(), &'static str>
+{
+    Ok(())
+}
+
+
+/// Register the drivers for the network devices that can show up on the VirtIO bus.
+pub fn register_virtio_drivers(registry: &mut VirtioDriverRegistry) -> Result<(), &'static str>
+{
+    registry.insert(VIRTIO_NETWORK_DEVICE_ID, virtio_net::probe_virtio_net_device);
+
+    Ok(())
+}
+
+
+/// Activate and initialize the network devices discovered in the device tree. If any.
+pub fn activate_devices() -> Result<(), &'static str>
+{
+    Ok(())
+}
+
+
+
+/// Get the name for the next network device to be registered.
+pub fn next_network_device_name() -> String
+{
+    format!("eth{}", NETWORK_DEVICES.lock().len())
+}
+
+
+/// Make a network device available to the protocol stack, returning its index.
+pub fn register_network_device(device: Arc<dyn NetworkDevice>) -> usize
+{
+    let mut devices = NETWORK_DEVICES.lock();
+
+    devices.push(device);
+    devices.len() - 1
+}
+
+
+/// Get a network device by its index.
+pub fn get_network_device(index: usize) -> Option<Arc<dyn NetworkDevice>>
+{
+    NETWORK_DEVICES.lock().get(index).cloned()
+}
+
+
+/// Get a network device by its name.
+pub fn find_network_device(name: &str) -> Option<Arc<dyn NetworkDevice>>
+{
+    NETWORK_DEVICES.lock().iter().find(|device| device.name() == name).cloned()
+}
+
+
+/// Get all of the network devices in the system.
+pub fn network_devices() -> Vec<Arc<dyn NetworkDevice>>
+{
+    NETWORK_DEVICES.lock().clone()
+}
+
+
+
+/// Fill in a checksum for a device that can't do it itself. Requests that don't fit in the frame
+/// are refused.
+pub fn fill_in_checksum(frame: &mut [u8], request: ChecksumRequest) -> NetworkResult<()>
+{
+    let field = request.start + request.offset;
+
+    if    request.start > frame.len()
+       || field + 2 > frame.len()
+    {
+        return Err("The checksum request is outside of the frame.");
+    }
+
+    let checksum = !ones_complement_sum(&frame[request.start..]);
+
+    frame[field..field + 2].copy_from_slice(&checksum.to_be_bytes());
+
+    Ok(())
+}
+
+
+/// The 16-bit ones' complement sum of the data, as used by the Internet checksums. An odd last byte
+/// is padded with a zero.
+pub fn ones_complement_sum(data: &[u8]) -> u16
+{
+    let mut words = data.chunks_exact(2);
+    let mut sum: u64 = words.by_ref()
+        .map(|word| u16::from_be_bytes([ word[0], word[1] ]) as u64)
+        .sum();
+
+    if let [ last ] = words.remainder()
+    {
+        sum += (*last as u64) << 8;
+    }
+
+    while sum > 0xffff
+    {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    sum as u16
+}
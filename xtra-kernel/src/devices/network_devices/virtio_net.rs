@@ -0,0 +1,661 @@
+// Driver for VirtIO network cards, what QEMU provides with its virtio-net-device. The device has a
+// receive queue that we keep full of empty buffers for it to write frames into, and a transmit
+// queue we put the frames to send on. Every frame in either direction is preceded by a small
+// header, which is where checksum offloading is arranged.
+//
+// The buffers are a fixed pool of page memory, as the kernel heap doesn't reuse freed memory. A
+// received frame is handed to the receive handler straight out of its buffer, which then goes back
+// on the receive queue. Frames to send are copied into a free transmit buffer, which is free again
+// once the device reports it's done with it.
+//
+// Frames are received and transmit buffers reclaimed by the device's interrupt handler. If the
+// device's interrupt can't be routed it's polled instead, whenever a frame is sent or the device
+// is polled.
+
+use core::{ ptr::read_volatile,
+            slice::from_raw_parts_mut,
+            sync::atomic::{ AtomicBool, Ordering } };
+
+use alloc::{ string::String, sync::Arc, vec, vec::Vec };
+
+use crate::{ arch::interrupts::with_interrupts_disabled,
+             devices::{ bus_devices::virtio_devices::{ mmio::{ VirtioMmioDevice,
+                                                               VIRTIO_INTERRUPT_CONFIG_CHANGE },
+                                                       virtqueue::{ VirtQueue,
+                                                                    VirtQueueBuffer } },
+                        network_devices::{ fill_in_checksum,
+                                           next_network_device_name,
+                                           register_network_device,
+                                           ChecksumRequest,
+                                           FrameReceivedHandler,
+                                           MacAddress,
+                                           NetworkDevice,
+                                           NetworkResult,
+                                           NetworkStatistics,
+                                           DEFAULT_MTU,
+                                           ETHERNET_HEADER_SIZE } },
+             interrupts::register_interrupt_handler,
+             locking::spin_mutex::SpinMutex,
+             memory::{ mmu::allocate_n_pages, PAGE_SIZE } };
+
+
+
+// Feature bits for network devices.
+const VIRTIO_NET_F_CSUM:       u64 = 1 << 0;   // The device can fill in checksums we send.
+const VIRTIO_NET_F_GUEST_CSUM: u64 = 1 << 1;   // We can take frames with partial checksums.
+const VIRTIO_NET_F_MTU:        u64 = 1 << 3;   // The device reports its MTU.
+const VIRTIO_NET_F_MAC:        u64 = 1 << 5;   // The device has a MAC address.
+const VIRTIO_NET_F_STATUS:     u64 = 1 << 16;  // The device reports its link status.
+
+// Frame header flags.
+const VIRTIO_NET_HDR_F_NEEDS_CSUM: u8 = 0x01;  // The checksum is only partly done.
+const VIRTIO_NET_HDR_F_DATA_VALID: u8 = 0x02;  // The device checked the checksum.
+
+// Link status bits.
+const VIRTIO_NET_S_LINK_UP: u16 = 0x01;
+
+// Offsets into the device configuration space.
+const CONFIG_MAC:    usize = 0x00;             // The device's MAC address.
+const CONFIG_STATUS: usize = 0x06;             // The link status.
+const CONFIG_MTU:    usize = 0x0a;             // The largest MTU the device supports.
+
+
+
+/// The queue the device writes received frames into.
+const RECEIVE_QUEUE: u16 = 0;
+
+/// The queue we put frames to send on.
+const TRANSMIT_QUEUE: u16 = 1;
+
+/// The sizes of the queues we ask the device for, also how many buffers each gets.
+const RECEIVE_QUEUE_SIZE: u16 = 128;
+const TRANSMIT_QUEUE_SIZE: u16 = 64;
+
+/// The size of each buffer, room for the header and a full sized frame.
+const BUFFER_SIZE: usize = 2048;
+
+/// The size of the header that precedes each frame.
+const HEADER_SIZE: usize = 12;
+
+/// The largest MTU the buffers have room for.
+const MAX_MTU: usize = BUFFER_SIZE - HEADER_SIZE - ETHERNET_HEADER_SIZE;
+
+
+
+/// The header that precedes every frame sent or received.
+#[derive(Clone, Copy, Default)]
+struct NetHeader
+{
+    flags: u8,             // VIRTIO_NET_HDR_F_* flags.
+    gso_type: u8,          // Segmentation offload, which we don't use.
+    header_length: u16,
+    gso_size: u16,
+    checksum_start: u16,   // Where the partial checksum starts.
+    checksum_offset: u16,  // Where the checksum goes, from checksum_start.
+    buffer_count: u16      // Only used by devices that merge receive buffers.
+}
+
+
+
+impl NetHeader
+{
+    /// Decode a header from the little endian bytes the device wrote.
+    fn from_bytes(bytes: [u8; HEADER_SIZE]) -> NetHeader
+    {
+        let field = |offset: usize| u16::from_le_bytes([ bytes[offset], bytes[offset + 1] ]);
+
+        NetHeader
+            {
+                flags: bytes[0],
+                gso_type: bytes[1],
+                header_length: field(2),
+                gso_size: field(4),
+                checksum_start: field(6),
+                checksum_offset: field(8),
+                buffer_count: field(10)
+            }
+    }
+
+    /// Encode the header for the device.
+    fn to_bytes(self) -> [u8; HEADER_SIZE]
+    {
+        let mut bytes = [0u8; HEADER_SIZE];
+
+        bytes[0] = self.flags;
+        bytes[1] = self.gso_type;
+        bytes[2..4].copy_from_slice(&self.header_length.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.gso_size.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.checksum_start.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.checksum_offset.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.buffer_count.to_le_bytes());
+
+        bytes
+    }
+}
+
+
+
+/// The receive queue, also used by the interrupt handler so it's only ever locked with interrupts
+/// disabled.
+struct ReceiveState
+{
+    /// The queue the device writes received frames into.
+    queue: VirtQueue,
+
+    /// Which buffer each descriptor chain in the queue is for, by the chain's head.
+    head_buffers: Vec<usize>
+}
+
+
+
+impl ReceiveState
+{
+    /// Hand an empty buffer to the device to receive a frame into.
+    fn queue_buffer(&mut self, buffer_index: usize, address: usize) -> Result<(), &'static str>
+    {
+        let buffer = unsafe { from_raw_parts_mut(address as *mut u8, BUFFER_SIZE) };
+        let head = self.queue.add_buffers(&[ VirtQueueBuffer::writable(buffer) ])?;
+
+        self.head_buffers[head as usize] = buffer_index;
+
+        Ok(())
+    }
+}
+
+
+
+/// The transmit queue, also used by the interrupt handler so it's only ever locked with interrupts
+/// disabled.
+struct TransmitState
+{
+    /// The queue we put frames to send on.
+    queue: VirtQueue,
+
+    /// Which buffer each descriptor chain in the queue is for, by the chain's head.
+    head_buffers: Vec<usize>,
+
+    /// The buffers that aren't on the queue. Never grows past its first capacity, so it doesn't
+    /// allocate.
+    free_buffers: Vec<usize>
+}
+
+
+
+impl TransmitState
+{
+    /// Take back the buffers of the frames the device has finished sending.
+    fn reclaim_buffers(&mut self)
+    {
+        while let Some(used) = self.queue.pop_used()
+        {
+            let buffer_index = self.head_buffers[used.head as usize];
+
+            self.free_buffers.push(buffer_index);
+        }
+    }
+}
+
+
+
+/// A VirtIO network card.
+pub struct VirtioNetDevice
+{
+    /// The name of the device, eth0, eth1, etc.
+    name: String,
+
+    /// The device's register interface.
+    device: VirtioMmioDevice,
+
+    /// The features agreed with the device.
+    features: u64,
+
+    /// The device's MAC address.
+    mac_address: MacAddress,
+
+    /// The largest payload of a frame.
+    mtu: usize,
+
+    /// Is the device connected to a network?
+    link_up: AtomicBool,
+
+    /// The kernel addresses of the receive and transmit buffers, each pool one buffer after the
+    /// other.
+    receive_buffers: usize,
+    transmit_buffers: usize,
+
+    /// The receive queue.
+    receive: SpinMutex<ReceiveState>,
+
+    /// The transmit queue.
+    transmit: SpinMutex<TransmitState>,
+
+    /// The handler given the frames received. Only locked with interrupts disabled.
+    receive_handler: SpinMutex<Option<FrameReceivedHandler>>,
+
+    /// The counts of the device's traffic. Only locked with interrupts disabled, and always after
+    /// the queues.
+    statistics: SpinMutex<NetworkStatistics>
+}
+
+
+
+impl VirtioNetDevice
+{
+    /// Perform the VirtIO initialization handshake for the network card, read its configuration
+    /// and fill its receive queue.
+    pub fn new(device: VirtioMmioDevice, name: String) -> Result<Self, &'static str>
+    {
+        device.begin_initialization();
+
+        let features = device.negotiate_features(  VIRTIO_NET_F_CSUM
+                                                 | VIRTIO_NET_F_GUEST_CSUM
+                                                 | VIRTIO_NET_F_MTU
+                                                 | VIRTIO_NET_F_MAC
+                                                 | VIRTIO_NET_F_STATUS)?;
+
+        // A device without an address of its own gets a locally administered one, made from its
+        // register address so that each card's is different.
+        let mac_address = if features & VIRTIO_NET_F_MAC != 0
+            {
+                MacAddress(core::array::from_fn(|index| device.config_read_u8(CONFIG_MAC + index)))
+            }
+            else
+            {
+                let [ .., a, b, c, d ] = (device.base_address() as u64).to_be_bytes();
+
+                MacAddress([ 0x02, 0x00, a, b, c, d ])
+            };
+
+        let mtu = if features & VIRTIO_NET_F_MTU != 0
+            {
+                (device.config_read_u16(CONFIG_MTU) as usize).min(MAX_MTU)
+            }
+            else
+            {
+                DEFAULT_MTU
+            };
+
+        let receive_queue = device.setup_queue(RECEIVE_QUEUE, RECEIVE_QUEUE_SIZE)?;
+        let transmit_queue = device.setup_queue(TRANSMIT_QUEUE, TRANSMIT_QUEUE_SIZE)?;
+
+        let receive_count = receive_queue.size() as usize;
+        let transmit_count = transmit_queue.size() as usize;
+
+        // Both pools come from the one allocation, the buffers evenly divide the pages so none of
+        // them cross a page boundary.
+        let page_count = ((receive_count + transmit_count) * BUFFER_SIZE).div_ceil(PAGE_SIZE);
+        let pages = allocate_n_pages(page_count)
+            .ok_or("Not enough memory for the network buffers.")?;
+
+        let receive_buffers = pages.head.as_usize();
+        let transmit_buffers = receive_buffers + receive_count * BUFFER_SIZE;
+
+        let mut receive = ReceiveState
+            {
+                queue: receive_queue,
+                head_buffers: vec![0; receive_count]
+            };
+
+        for buffer_index in 0..receive_count
+        {
+            receive.queue_buffer(buffer_index, receive_buffers + buffer_index * BUFFER_SIZE)?;
+        }
+
+        let transmit = TransmitState
+            {
+                queue: transmit_queue,
+                head_buffers: vec![0; transmit_count],
+                free_buffers: (0..transmit_count).rev().collect()
+            };
+
+        let net_device = VirtioNetDevice
+            {
+                name,
+                device,
+                features,
+                mac_address,
+                mtu,
+                link_up: AtomicBool::new(true),
+                receive_buffers,
+                transmit_buffers,
+                receive: SpinMutex::new(receive),
+                transmit: SpinMutex::new(transmit),
+                receive_handler: SpinMutex::new(None),
+                statistics: SpinMutex::new(NetworkStatistics::default())
+            };
+
+        net_device.update_link_status();
+
+        device.finish_initialization();
+        device.notify_queue(RECEIVE_QUEUE);
+
+        Ok(net_device)
+    }
+
+    /// Handle everything the device has done, the frames received and the frames sent.
+    fn process_queues(&self)
+    {
+        self.process_received_frames();
+
+        with_interrupts_disabled(|| self.transmit.lock().reclaim_buffers());
+    }
+
+    /// Hand each frame the device has received to the receive handler, and its buffer back to the
+    /// device.
+    fn process_received_frames(&self)
+    {
+        loop
+        {
+            // The buffer is off of the queue, so it's ours to use without holding the lock.
+            let used = with_interrupts_disabled(||
+                {
+                    let mut receive = self.receive.lock();
+                    let used = receive.queue.pop_used()?;
+
+                    Some((receive.head_buffers[used.head as usize], used.length as usize))
+                });
+
+            let Some((buffer_index, length)) = used
+            else
+            {
+                break;
+            };
+
+            let address = self.receive_buffers + buffer_index * BUFFER_SIZE;
+            let buffer = unsafe { from_raw_parts_mut(address as *mut u8, length.min(BUFFER_SIZE)) };
+
+            self.deliver_frame(buffer);
+
+            with_interrupts_disabled(||
+                {
+                    if self.receive.lock().queue_buffer(buffer_index, address).is_ok()
+                    {
+                        self.device.notify_queue(RECEIVE_QUEUE);
+                    }
+                });
+        }
+    }
+
+    /// Pass a received frame, still preceded by its header, to the receive handler.
+    fn deliver_frame(&self, buffer: &mut [u8])
+    {
+        if buffer.len() < HEADER_SIZE + ETHERNET_HEADER_SIZE
+        {
+            with_interrupts_disabled(|| self.statistics.lock().rx_errors += 1);
+            return;
+        }
+
+        let header = NetHeader::from_bytes(unsafe { read_volatile(buffer.as_ptr() as *const _) });
+        let frame = &mut buffer[HEADER_SIZE..];
+
+        // A partial checksum, from a sender on the same host, is finished here so that the
+        // protocol stack never sees one.
+        let checksum_valid = if header.flags & VIRTIO_NET_HDR_F_NEEDS_CSUM != 0
+            {
+                let request = ChecksumRequest
+                    {
+                        start: header.checksum_start as usize,
+                        offset: header.checksum_offset as usize
+                    };
+
+                fill_in_checksum(frame, request).is_ok()
+            }
+            else
+            {
+                header.flags & VIRTIO_NET_HDR_F_DATA_VALID != 0
+            };
+
+        let handler = with_interrupts_disabled(||
+            {
+                let handler = *self.receive_handler.lock();
+                let mut statistics = self.statistics.lock();
+
+                match handler
+                {
+                    Some(_) =>
+                        {
+                            statistics.rx_packets += 1;
+                            statistics.rx_bytes += frame.len() as u64;
+                        },
+
+                    None => statistics.rx_dropped += 1
+                }
+
+                handler
+            });
+
+        if let Some(handler) = handler
+        {
+            handler(self, frame, checksum_valid);
+        }
+    }
+
+    /// Re-read the link status, devices that don't report it are always up.
+    fn update_link_status(&self)
+    {
+        let link_up =    self.features & VIRTIO_NET_F_STATUS == 0
+                      || self.device.config_read_u16(CONFIG_STATUS) & VIRTIO_NET_S_LINK_UP != 0;
+
+        self.link_up.store(link_up, Ordering::Release);
+    }
+
+    /// Count a frame that couldn't be sent, as an error or as dropped.
+    fn count_failed_send(&self, dropped: bool)
+    {
+        with_interrupts_disabled(||
+            {
+                let mut statistics = self.statistics.lock();
+
+                match dropped
+                {
+                    true  => statistics.tx_dropped += 1,
+                    false => statistics.tx_errors += 1
+                }
+            });
+    }
+}
+
+
+
+impl NetworkDevice for VirtioNetDevice
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn mac_address(&self) -> MacAddress
+    {
+        self.mac_address
+    }
+
+    fn mtu(&self) -> usize
+    {
+        self.mtu
+    }
+
+    fn is_link_up(&self) -> bool
+    {
+        self.link_up.load(Ordering::Acquire)
+    }
+
+    fn has_checksum_offload(&self) -> bool
+    {
+        self.features & VIRTIO_NET_F_CSUM != 0
+    }
+
+    fn send_frame(&self, frame: &[u8], checksum: Option<ChecksumRequest>) -> NetworkResult<()>
+    {
+        if    frame.len() < ETHERNET_HEADER_SIZE
+           || frame.len() > ETHERNET_HEADER_SIZE + self.mtu
+        {
+            self.count_failed_send(false);
+            return Err("The frame's size is outside of what the device can send.");
+        }
+
+        let mut header = NetHeader::default();
+        let mut software_checksum = None;
+
+        if let Some(request) = checksum
+        {
+            if    request.start > u16::MAX as usize
+               || request.offset > u16::MAX as usize
+               || request.start + request.offset + 2 > frame.len()
+            {
+                self.count_failed_send(false);
+                return Err("The checksum request is outside of the frame.");
+            }
+
+            if self.has_checksum_offload()
+            {
+                header.flags = VIRTIO_NET_HDR_F_NEEDS_CSUM;
+                header.checksum_start = request.start as u16;
+                header.checksum_offset = request.offset as u16;
+            }
+            else
+            {
+                software_checksum = Some(request);
+            }
+        }
+
+        let result = with_interrupts_disabled(||
+            {
+                let mut transmit = self.transmit.lock();
+
+                transmit.reclaim_buffers();
+
+                let buffer_index = transmit.free_buffers.pop()
+                    .ok_or("The device's transmit queue is full.")?;
+
+                let address = self.transmit_buffers + buffer_index * BUFFER_SIZE;
+                let buffer = unsafe { from_raw_parts_mut(address as *mut u8,
+                                                         HEADER_SIZE + frame.len()) };
+
+                buffer[..HEADER_SIZE].copy_from_slice(&header.to_bytes());
+                buffer[HEADER_SIZE..].copy_from_slice(frame);
+
+                if let Some(request) = software_checksum
+                {
+                    fill_in_checksum(&mut buffer[HEADER_SIZE..], request)?;
+                }
+
+                let head = match transmit.queue.add_buffers(&[ VirtQueueBuffer::readable(buffer) ])
+                    {
+                        Ok(head) => head,
+
+                        Err(error) =>
+                            {
+                                transmit.free_buffers.push(buffer_index);
+                                return Err(error);
+                            }
+                    };
+
+                transmit.head_buffers[head as usize] = buffer_index;
+                self.device.notify_queue(TRANSMIT_QUEUE);
+
+                let mut statistics = self.statistics.lock();
+
+                statistics.tx_packets += 1;
+                statistics.tx_bytes += frame.len() as u64;
+
+                Ok(())
+            });
+
+        if result.is_err()
+        {
+            self.count_failed_send(true);
+        }
+
+        result
+    }
+
+    fn set_receive_handler(&self, handler: Option<FrameReceivedHandler>)
+    {
+        with_interrupts_disabled(|| *self.receive_handler.lock() = handler);
+    }
+
+    fn poll(&self)
+    {
+        self.update_link_status();
+        self.process_queues();
+    }
+
+    fn statistics(&self) -> NetworkStatistics
+    {
+        with_interrupts_disabled(|| *self.statistics.lock())
+    }
+}
+
+
+
+/// The active VirtIO network cards. Also searched by the interrupt handler, so it's only locked
+/// with interrupts disabled.
+static VIRTIO_NET_DEVICES: SpinMutex<Vec<Arc<VirtioNetDevice>>> = SpinMutex::new(Vec::new());
+
+
+
+/// Called by the VirtIO bus when it finds a network card. The device is initialized, its interrupt
+/// routed to the current hart and it's registered with the network device subsystem.
+pub fn probe_virtio_net_device(device: VirtioMmioDevice) -> Result<(), &'static str>
+{
+    let net_device = Arc::new(VirtioNetDevice::new(device, next_network_device_name())?);
+
+    with_interrupts_disabled(|| VIRTIO_NET_DEVICES.lock().push(net_device.clone()));
+
+    println!("  {}: {}, mtu {}, link {}{} (virtio-net)",
+             net_device.name,
+             net_device.mac_address,
+             net_device.mtu,
+             if net_device.is_link_up() { "up" } else { "down" },
+             if net_device.has_checksum_offload() { ", checksum offload" } else { "" });
+
+    match device.interrupt()
+    {
+        Some(interrupt) =>
+            {
+                if let Err(error) = register_interrupt_handler(None,
+                                                               interrupt as usize,
+                                                               handle_net_interrupt)
+                {
+                    println!("  {} will be polled, its interrupt couldn't be routed: {}",
+                             net_device.name,
+                             error);
+                }
+            },
+
+        None => println!("  {} has no interrupt, it will be polled.", net_device.name)
+    }
+
+    register_network_device(net_device);
+
+    Ok(())
+}
+
+
+
+/// Handle an interrupt for any of the network cards on the interrupt.
+fn handle_net_interrupt(interrupt_number: usize)
+{
+    let mut index = 0;
+
+    // The device list isn't held while the frames are handled, so that the receive handlers are
+    // free to look at the devices.
+    while let Some(net_device) = VIRTIO_NET_DEVICES.lock().get(index).cloned()
+    {
+        index += 1;
+
+        if net_device.device.interrupt() != Some(interrupt_number as u32)
+        {
+            continue;
+        }
+
+        let status = net_device.device.acknowledge_interrupt();
+
+        if status & VIRTIO_INTERRUPT_CONFIG_CHANGE != 0
+        {
+            net_device.update_link_status();
+        }
+
+        net_device.process_queues();
+    }
+}
@@ -1,28 +1,259 @@
+// The system power control devices. Most boards don't have a dedicated power controller, instead
+// the device tree describes a syscon-poweroff and a syscon-reboot node which say which value to
+// write to which register of a system controller, (syscon,) to cut the power or reset the board.
+// On QEMU's virt machine the syscon is the SiFive test device.
+//
+// The syscon and the power nodes can appear in any order in the device tree, so the probes only
+// record what they find and the power nodes are matched up with their syscon once the whole tree
+// has been walked.
+//
+// Before the power is cut the system is shut down in an orderly way. The other harts are parked,
+// the filesystems are synced and the disks are told to flush their write caches. If no syscon
+// describes how to power off or reboot we fall back to asking the SBI firmware to do it.
+
+use core::{ fmt::{ self, Display, Formatter },
+            hint::spin_loop,
+            ptr::{ read_volatile, write_volatile } };
+
+use alloc::vec::Vec;
 
 use xtra_kernel_shared::device_tree::DeviceTree;
 
-use crate::devices::DeviceDriverRegistry;
+use crate::{ arch::{ csr::read_time,
+                     interrupts::disable_interrupts,
+                     sbi::{ probe_extension,
+                            system_reset,
+                            ResetReason,
+                            ResetType,
+                            EXTENSION_SRST } },
+             devices::{ block_devices::flush_all,
+                        cpu_devices::timebase_frequency,
+                        property_contains_string,
+                        read_property_cells,
+                        read_property_reg,
+                        DeviceDriverRegistry },
+             filesystems::mount::sync_all,
+             harts::park_other_harts,
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// How long to wait for the power to go after triggering a power control, in milliseconds. Most
+/// controllers act immediately, the rest get a little time before we decide it didn't work.
+const POWER_CONTROL_TIMEOUT_MS: u64 = 100;
+
+
+
+/// What a power control does to the system.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PowerAction
+{
+    /// Turn the system off.
+    PowerOff,
+
+    /// Reset the system and start it up again.
+    Reboot
+}
+
+
+
+impl Display for PowerAction
+{
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result
+    {
+        match self
+        {
+            PowerAction::PowerOff => write!(formatter, "power off"),
+            PowerAction::Reboot   => write!(formatter, "reboot")
+        }
+    }
+}
+
+
+
+/// A system controller, a block of registers that other nodes of the device tree refer to by its
+/// phandle.
+#[derive(Clone, Copy)]
+struct Syscon
+{
+    /// The handle the other nodes use to refer to the syscon.
+    phandle: u32,
+
+    /// Where the syscon's registers start.
+    base_address: usize,
+
+    /// The size of the syscon's register block.
+    size: usize
+}
+
+
+
+/// A syscon-poweroff or syscon-reboot node as found in the device tree, before it's been matched
+/// with its syscon.
+#[derive(Clone, Copy)]
+struct SysconPowerNode
+{
+    /// What writing the value does.
+    action: PowerAction,
+
+    /// The phandle of the syscon holding the register.
+    regmap: u32,
+
+    /// The offset of the register in the syscon.
+    offset: usize,
+
+    /// The value to write to the register.
+    value: u32,
+
+    /// Which bits of the register the value is written to.
+    mask: u32
+}
+
+
+
+/// A power control ready for use, the register to write to and what to write to it.
+#[derive(Clone, Copy)]
+pub struct PowerControl
+{
+    /// What the control does to the system.
+    pub action: PowerAction,
+
+    /// The address of the register to write.
+    pub address: usize,
+
+    /// The value to write to the register.
+    pub value: u32,
+
+    /// Which bits of the register the value is written to.
+    pub mask: u32
+}
+
+
+
+impl PowerControl
+{
+    /// Write the control's value to its register. If the mask only covers some of the register the
+    /// other bits are left as they are.
+    fn trigger(&self)
+    {
+        let register = self.address as *mut u32;
+
+        unsafe
+        {
+            let value = if self.mask == u32::MAX
+                {
+                    self.value
+                }
+                else
+                {
+                    (read_volatile(register) & !self.mask) | (self.value & self.mask)
+                };
+
+            write_volatile(register, value);
+        }
+    }
+}
+
+
+
+impl Display for PowerControl
+{
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result
+    {
+        write!(formatter,
+               "{}: write 0x{:08x} to 0x{:016x}, mask 0x{:08x}",
+               self.action,
+               self.value,
+               self.address,
+               self.mask)
+    }
+}
+
 
 
+/// The syscons found in the device tree.
+static SYSCONS: SpinMutex<Vec<Syscon>> = SpinMutex::new(Vec::new());
 
-/// Register the driver probe functions for all of the block device drivers in the system.
+/// The syscon-poweroff and syscon-reboot nodes found in the device tree.
+static POWER_NODES: SpinMutex<Vec<SysconPowerNode>> = SpinMutex::new(Vec::new());
+
+/// The power controls that were matched with their syscons.
+static POWER_CONTROLS: SpinMutex<Vec<PowerControl>> = SpinMutex::new(Vec::new());
+
+
+
+/// Register the driver probe functions for all of the power device drivers in the system.
 pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(), &'static str>
 {
+    registry.insert("test", probe_syscon);
+    registry.insert("syscon", probe_syscon);
+    registry.insert("poweroff", probe_power_node);
+    registry.insert("reboot", probe_power_node);
+
     Ok(())
 }
 
 
-/// Activate and initialize the block devices discovered in the device tree. If any.
+/// Activate the power devices discovered in the device tree, matching the power nodes up with the
+/// syscons they refer to. Power nodes without a usable syscon are reported and left out, we can
+/// still fall back to the firmware.
 pub fn activate_devices() -> Result<(), &'static str>
 {
+    let syscons = SYSCONS.lock();
+    let mut controls = POWER_CONTROLS.lock();
+
+    for node in POWER_NODES.lock().iter()
+    {
+        let Some(syscon) = syscons.iter().find(|syscon| syscon.phandle == node.regmap)
+        else
+        {
+            println!("  No syscon found for the {} control, regmap {}.", node.action, node.regmap);
+            continue;
+        };
+
+        if node.offset + size_of::<u32>() > syscon.size
+        {
+            println!("  The {} control's register is outside of its syscon.", node.action);
+            continue;
+        }
+
+        let control = PowerControl
+            {
+                action: node.action,
+                address: syscon.base_address + node.offset,
+                value: node.value,
+                mask: node.mask
+            };
+
+        println!("  Power control, {}", control);
+
+        controls.push(control);
+    }
+
+    if controls.is_empty()
+    {
+        println!("  No power controls found, the SBI firmware will be used to power off.");
+    }
+
     Ok(())
 }
 
 
+/// Get the power controls that were found, if any.
+pub fn power_controls() -> Vec<PowerControl>
+{
+    POWER_CONTROLS.lock().clone()
+}
+
+
+
 /// Power down the system. This should never return if it succeeds.
 pub fn power_down_system() -> Result<!, &'static str>
 {
-    Err("System power down has not been implemented.")
+    println!("Powering down the system...");
+
+    shut_down_system();
+    perform_power_action(PowerAction::PowerOff)
 }
 
 
@@ -36,5 +267,186 @@ pub fn suspend_system() -> Result<(), &'static str>
 /// Reboot the system. This should never return if it succeeds.
 pub fn reboot_system() -> Result<!, &'static str>
 {
-    Err("System reboot has not been implemented.")
+    println!("Rebooting the system...");
+
+    shut_down_system();
+    perform_power_action(PowerAction::Reboot)
+}
+
+
+
+/// Bring the system to a state where it's safe to cut the power. The other harts are parked so that
+/// nothing changes underneath us, then every filesystem is synced and the disks are told to write
+/// out their caches. Failures are reported, but we carry on shutting down regardless.
+///
+/// The boot hart can't be parked, so this is best called from the boot hart.
+fn shut_down_system()
+{
+    println!("  Stopping the other harts...");
+    park_other_harts();
+
+    println!("  Syncing filesystems...");
+
+    if let Err(error) = sync_all()
+    {
+        println!("  Failed to sync the filesystems: {}", error);
+    }
+
+    println!("  Flushing disk caches...");
+
+    if let Err(error) = flush_all()
+    {
+        println!("  Failed to flush the disk caches: {}", error);
+    }
+
+    disable_interrupts();
+}
+
+
+/// Trigger the power controls for the action, falling back to the SBI firmware if there are none or
+/// none of them worked.
+fn perform_power_action(action: PowerAction) -> Result<!, &'static str>
+{
+    let controls: Vec<PowerControl> = power_controls().into_iter()
+                                                      .filter(|control| control.action == action)
+                                                      .collect();
+
+    for control in controls
+    {
+        control.trigger();
+
+        let deadline = read_time() + timebase_frequency() * POWER_CONTROL_TIMEOUT_MS / 1000;
+
+        while read_time() < deadline
+        {
+            spin_loop();
+        }
+
+        println!("The {} control at 0x{:016x} didn't work.", action, control.address);
+    }
+
+    if !probe_extension(EXTENSION_SRST)
+    {
+        return Err("No power control is available for this system.");
+    }
+
+    let reset_type = match action
+        {
+            PowerAction::PowerOff => ResetType::Shutdown,
+            PowerAction::Reboot   => ResetType::ColdReboot
+        };
+
+    let error = system_reset(reset_type, ResetReason::NoReason);
+
+    println!("The SBI firmware failed to {} the system: {}", action, error);
+
+    Err("The system could not be powered off or rebooted.")
+}
+
+
+
+/// Probe a node that may be a syscon. Only nodes compatible with syscon are, the test device for
+/// instance is only a syscon on some machines.
+fn probe_syscon(name: &str,
+                address: Option<usize>,
+                device_tree: &DeviceTree,
+                block_offset: usize) -> Result<(), &'static str>
+{
+    let mut is_syscon = false;
+    let mut phandle = None;
+    let mut reg = None;
+
+    device_tree.iterate_properties(block_offset, |property_name, property_value|
+        {
+            match property_name
+            {
+                "compatible"                => is_syscon = property_contains_string(property_value,
+                                                                                    "syscon"),
+                "phandle" | "linux,phandle" => phandle = read_property_cells(property_value),
+                "reg"                       => reg = read_property_reg(property_value),
+                _                           => {}
+            }
+
+            true
+        });
+
+    if !is_syscon
+    {
+        return Ok(());
+    }
+
+    let phandle = phandle.ok_or("Syscon has no phandle, nothing can refer to it.")?;
+    let (base_address, size) = reg.ok_or("Syscon is missing its reg property.")?;
+
+    SYSCONS.lock().push(Syscon { phandle: phandle as u32, base_address, size });
+
+    Ok(())
+}
+
+
+/// Probe a poweroff or reboot node, they say how to use a syscon to power off or reset the system.
+fn probe_power_node(name: &str,
+                    address: Option<usize>,
+                    device_tree: &DeviceTree,
+                    block_offset: usize) -> Result<(), &'static str>
+{
+    let mut action = None;
+    let mut regmap = None;
+    let mut offset = None;
+    let mut value = None;
+    let mut mask = None;
+
+    device_tree.iterate_properties(block_offset, |property_name, property_value|
+        {
+            match property_name
+            {
+                "compatible" =>
+                    {
+                        if property_contains_string(property_value, "syscon-poweroff")
+                        {
+                            action = Some(PowerAction::PowerOff);
+                        }
+                        else if property_contains_string(property_value, "syscon-reboot")
+                        {
+                            action = Some(PowerAction::Reboot);
+                        }
+                    },
+
+                "regmap" => regmap = read_property_cells(property_value),
+                "offset" => offset = read_property_cells(property_value),
+                "value"  => value = read_property_cells(property_value),
+                "mask"   => mask = read_property_cells(property_value),
+                _        => {}
+            }
+
+            true
+        });
+
+    let Some(action) = action
+    else
+    {
+        return Ok(());
+    };
+
+    let regmap = regmap.ok_or("Power control is missing its regmap.")?;
+    let offset = offset.ok_or("Power control is missing its register offset.")?;
+
+    // The binding allows the value to be left out, in which case the mask is written.
+    let (value, mask) = match (value, mask)
+        {
+            (Some(value), mask) => (value as u32, mask.map_or(u32::MAX, |mask| mask as u32)),
+            (None, Some(mask))  => (mask as u32, mask as u32),
+            (None, None)        => return Err("Power control has no value to write.")
+        };
+
+    POWER_NODES.lock().push(SysconPowerNode
+        {
+            action,
+            regmap: regmap as u32,
+            offset: offset as usize,
+            value,
+            mask
+        });
+
+    Ok(())
 }
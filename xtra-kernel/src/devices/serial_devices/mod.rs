@@ -0,0 +1,202 @@
+// The serial device subsystem. Serial device drivers register every port they find here, where the
+// rest of the kernel, like the console, can find them by their index or name.
+//
+// The ports are interrupt driven where the system allows it. Input is queued by the port's
+// interrupt handler until it's read, and output is queued until the device is ready for it, so
+// neither reading nor writing has to wait on the device.
+
+use core::{ fmt::{ self, Display, Formatter }, hint::spin_loop };
+
+use alloc::{ format, string::String, sync::Arc, vec::Vec };
+
+use xtra_kernel_shared::device_tree::DeviceTree;
+
+use crate::{ devices::DeviceDriverRegistry, locking::spin_mutex::SpinMutex };
+
+
+
+/// The driver for NS16550 compatible UARTs.
+pub mod ns16550;
+
+
+
+/// The baud rate a port runs at if the device tree doesn't say.
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+
+
+/// The parity bit sent with each character.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Parity
+{
+    None,
+    Odd,
+    Even
+}
+
+
+
+/// The line settings of a serial port.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LineConfig
+{
+    /// How many bits per second are sent down the line.
+    pub baud_rate: u32,
+
+    /// How many bits each character has, 5 to 8.
+    pub data_bits: u8,
+
+    /// The parity sent with each character.
+    pub parity: Parity,
+
+    /// How many stop bits follow each character, 1 or 2.
+    pub stop_bits: u8
+}
+
+
+
+impl Default for LineConfig
+{
+    /// The usual 115200 baud, 8 data bits, no parity and 1 stop bit.
+    fn default() -> Self
+    {
+        LineConfig
+            {
+                baud_rate: DEFAULT_BAUD_RATE,
+                data_bits: 8,
+                parity: Parity::None,
+                stop_bits: 1
+            }
+    }
+}
+
+
+
+impl Display for LineConfig
+{
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result
+    {
+        let parity = match self.parity
+            {
+                Parity::None => 'N',
+                Parity::Odd  => 'O',
+                Parity::Even => 'E'
+            };
+
+        write!(formatter, "{} {}{}{}", self.baud_rate, self.data_bits, parity, self.stop_bits)
+    }
+}
+
+
+
+/// The interface all serial ports expose to the rest of the kernel.
+pub trait SerialDevice: Send + Sync
+{
+    /// The name of the port, for example ttyS0.
+    fn name(&self) -> &str;
+
+    /// The port's current line settings.
+    fn line_config(&self) -> LineConfig;
+
+    /// Change the port's line settings.
+    fn set_line_config(&self, config: LineConfig) -> Result<(), &'static str>;
+
+    /// Read whatever input is waiting into the buffer, without waiting for more. Returns the
+    /// number of bytes read.
+    fn read(&self, buffer: &mut [u8]) -> usize;
+
+    /// Queue as much of the data for sending as there's room for, without waiting. Returns the
+    /// number of bytes queued.
+    fn write(&self, data: &[u8]) -> usize;
+
+    /// Queue all of the data for sending, waiting for room if the output queue fills up.
+    fn write_all(&self, data: &[u8])
+    {
+        let mut remaining = data;
+
+        while !remaining.is_empty()
+        {
+            let written = self.write(remaining);
+
+            if written == 0
+            {
+                spin_loop();
+            }
+
+            remaining = &remaining[written..];
+        }
+    }
+
+    /// Wait until all of the queued output has been handed to the device. This works with
+    /// interrupts disabled, so that whatever is queued can still be sent out during a panic.
+    fn flush(&self);
+}
+
+
+
+/// All of the serial ports in the system, in the order they were found.
+static SERIAL_PORTS: SpinMutex<Vec<Arc<dyn SerialDevice>>> = SpinMutex::new(Vec::new());
+
+
+
+/// Register the driver probe functions for all of the serial device drivers in the system.
+pub fn register_driver_probes(registry: &mut DeviceDriverRegistry) -> Result<(), &'static str>
+{
+    registry.insert("serial", ns16550::probe_ns16550);
+    registry.insert("uart", ns16550::probe_ns16550);
+
+    Ok(())
+}
+
+
+/// Activate and initialize the serial devices discovered in the device tree. If any.
+pub fn activate_devices() -> Result<(), &'static str>
+{
+    ns16550::activate_devices()?;
+
+    for port in SERIAL_PORTS.lock().iter()
+    {
+        println!("  {}: {}", port.name(), port.line_config());
+    }
+
+    Ok(())
+}
+
+
+
+/// Get the name for the next serial port to be registered.
+pub fn next_port_name() -> String
+{
+    format!("ttyS{}", SERIAL_PORTS.lock().len())
+}
+
+
+/// Make a serial port available to the rest of the kernel, returning its index.
+pub fn register_serial_port(port: Arc<dyn SerialDevice>) -> usize
+{
+    let mut ports = SERIAL_PORTS.lock();
+
+    ports.push(port);
+    ports.len() - 1
+}
+
+
+/// Get a serial port by its index.
+pub fn get_serial_port(index: usize) -> Option<Arc<dyn SerialDevice>>
+{
+    SERIAL_PORTS.lock().get(index).cloned()
+}
+
+
+/// Get a serial port by its name.
+pub fn find_serial_port(name: &str) -> Option<Arc<dyn SerialDevice>>
+{
+    SERIAL_PORTS.lock().iter().find(|port| port.name() == name).cloned()
+}
+
+
+/// Get all of the serial ports in the system.
+pub fn serial_ports() -> Vec<Arc<dyn SerialDevice>>
+{
+    SERIAL_PORTS.lock().clone()
+}
@@ -0,0 +1,612 @@
+// The driver for NS16550 compatible UARTs, the serial ports found on most RISC-V boards and on
+// QEMU's virt machine. Every compatible node in the device tree becomes a serial port.
+//
+// The UART's FIFOs are enabled and it interrupts us through the PLIC when input arrives or when
+// its transmit FIFO empties. The interrupt handler moves the input into the port's input ring
+// buffer and refills the transmit FIFO from the output ring buffer. If the port's interrupt can't
+// be routed to us the port still works, it's just polled whenever it's read or written.
+//
+// The port's state is also used by the interrupt handler, so it's only ever locked with interrupts
+// disabled.
+
+use core::{ hint::spin_loop, ptr::{ read_volatile, write_volatile } };
+
+use alloc::{ string::String, sync::Arc, vec::Vec };
+
+use xtra_kernel_shared::device_tree::DeviceTree;
+
+use crate::{ arch::interrupts::with_interrupts_disabled,
+             devices::{ property_contains_string,
+                        read_property_cells,
+                        read_property_reg,
+                        read_property_string,
+                        serial_devices::{ next_port_name,
+                                          register_serial_port,
+                                          LineConfig,
+                                          Parity,
+                                          SerialDevice } },
+             interrupts::register_interrupt_handler,
+             locking::spin_mutex::SpinMutex,
+             ring_buffer::RingBuffer };
+
+
+
+/// The compatible strings of the UARTs we can drive.
+const COMPATIBLE_UARTS: [&str; 2] = [ "ns16550a", "ns16550" ];
+
+/// How much input and output is queued for each port.
+const INPUT_BUFFER_SIZE: usize = 4096;
+const OUTPUT_BUFFER_SIZE: usize = 4096;
+
+/// How many bytes the UART's transmit FIFO holds.
+const FIFO_SIZE: usize = 16;
+
+/// Indices of the UART's registers.
+const UART_RBR: usize = 0;  // Receive Buffer Register, (read.)
+const UART_THR: usize = 0;  // Transmit Holding Register, (write.)
+const UART_DLL: usize = 0;  // Divisor Latch Low, (with LCR_DLAB set.)
+const UART_IER: usize = 1;  // Interrupt Enable Register.
+const UART_DLM: usize = 1;  // Divisor Latch High, (with LCR_DLAB set.)
+const UART_IIR: usize = 2;  // Interrupt Identification Register, (read.)
+const UART_FCR: usize = 2;  // FIFO Control Register, (write.)
+const UART_LCR: usize = 3;  // Line Control Register.
+const UART_MCR: usize = 4;  // Modem Control Register.
+const UART_LSR: usize = 5;  // Line Status Register.
+
+/// Interrupt Enable Register bits.
+const IER_RX_AVAILABLE: u8 = 0x01;  // Input is waiting, or the receive FIFO timed out.
+const IER_TX_EMPTY: u8 = 0x02;      // The transmit holding register is empty.
+const IER_LINE_STATUS: u8 = 0x04;   // An overrun, parity or framing error.
+
+/// Interrupt Identification Register bits.
+const IIR_NO_INTERRUPT: u8 = 0x01;
+
+/// FIFO Control Register bits.
+const FCR_ENABLE: u8 = 0x01;
+const FCR_CLEAR_RX: u8 = 0x02;
+const FCR_CLEAR_TX: u8 = 0x04;
+const FCR_RX_TRIGGER_8: u8 = 0x80;  // Interrupt once 8 bytes are in the receive FIFO.
+
+/// Line Control Register bits.
+const LCR_TWO_STOP_BITS: u8 = 0x04;
+const LCR_PARITY_ENABLE: u8 = 0x08;
+const LCR_EVEN_PARITY: u8 = 0x10;
+const LCR_DLAB: u8 = 0x80;          // Divisor Latch Access Bit.
+
+/// Modem Control Register bits.
+const MCR_DTR: u8 = 0x01;
+const MCR_RTS: u8 = 0x02;
+const MCR_OUT2: u8 = 0x08;          // Connects the UART's interrupt line on PC style boards.
+
+/// Line Status Register bits.
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_THR_EMPTY: u8 = 0x20;
+
+
+
+/// A UART as found in the device tree, it becomes a port when it's activated.
+struct ProbedUart
+{
+    /// Where the UART's registers start.
+    base_address: usize,
+
+    /// How far apart the registers are, as a power of two.
+    register_shift: usize,
+
+    /// How wide each register is in bytes, 1 or 4.
+    register_width: usize,
+
+    /// The frequency of the UART's input clock, if known.
+    clock_frequency: Option<u32>,
+
+    /// The UART's interrupt on the PLIC, if it has one.
+    interrupt: Option<usize>,
+
+    /// The line settings to start the port with.
+    config: LineConfig
+}
+
+
+
+/// The parts of a port that change as it's used.
+struct PortState
+{
+    /// The port's line settings.
+    config: LineConfig,
+
+    /// Input received but not yet read.
+    input: RingBuffer,
+
+    /// Output written but not yet sent.
+    output: RingBuffer,
+
+    /// How many bytes of input were dropped because nobody was reading them.
+    dropped_input: usize,
+
+    /// Is the port's interrupt being handled? If not the port is polled.
+    is_interrupt_driven: bool
+}
+
+
+
+/// One NS16550 UART.
+pub struct Ns16550
+{
+    /// The name of the port, for example ttyS0.
+    name: String,
+
+    /// Where the UART's registers start.
+    base_address: usize,
+
+    /// How far apart the registers are, as a power of two.
+    register_shift: usize,
+
+    /// How wide each register is in bytes, 1 or 4.
+    register_width: usize,
+
+    /// The frequency of the UART's input clock, used to set the baud rate.
+    clock_frequency: Option<u32>,
+
+    /// The UART's interrupt on the PLIC, if it has one.
+    interrupt: Option<usize>,
+
+    /// The port's state, only locked with interrupts disabled.
+    state: SpinMutex<PortState>
+}
+
+
+
+impl Ns16550
+{
+    /// Create the port for a UART found in the device tree, it's left untouched until it's
+    /// initialized.
+    fn new(name: String, probed: &ProbedUart) -> Ns16550
+    {
+        Ns16550
+            {
+                name,
+                base_address: probed.base_address,
+                register_shift: probed.register_shift,
+                register_width: probed.register_width,
+                clock_frequency: probed.clock_frequency,
+                interrupt: probed.interrupt,
+                state: SpinMutex::new(PortState
+                    {
+                        config: probed.config,
+                        input: RingBuffer::new(INPUT_BUFFER_SIZE),
+                        output: RingBuffer::new(OUTPUT_BUFFER_SIZE),
+                        dropped_input: 0,
+                        is_interrupt_driven: false
+                    })
+            }
+    }
+
+    /// How many bytes of input have been dropped because nobody was reading them.
+    pub fn dropped_input(&self) -> usize
+    {
+        with_interrupts_disabled(|| self.state.lock().dropped_input)
+    }
+
+    /// Reset the UART and set it up with the port's line settings. The UART's interrupts are left
+    /// off until its interrupt handler has been registered.
+    fn initialize(&self) -> Result<(), &'static str>
+    {
+        with_interrupts_disabled(||
+            {
+                let state = self.state.lock();
+
+                self.write_register(UART_IER, 0);
+                self.program_line(&state.config)?;
+
+                self.write_register(UART_FCR,
+                                    FCR_ENABLE | FCR_CLEAR_RX | FCR_CLEAR_TX | FCR_RX_TRIGGER_8);
+                self.write_register(UART_MCR, MCR_DTR | MCR_RTS | MCR_OUT2);
+
+                // Throw away anything left over from before the reset.
+                while self.read_register(UART_LSR) & LSR_DATA_READY != 0
+                {
+                    self.read_register(UART_RBR);
+                }
+
+                Ok(())
+            })
+    }
+
+    /// Switch the port over to being interrupt driven, once its interrupt handler is registered.
+    fn enable_interrupts(&self)
+    {
+        with_interrupts_disabled(||
+            {
+                let mut state = self.state.lock();
+
+                state.is_interrupt_driven = true;
+
+                self.write_register(UART_IER, IER_RX_AVAILABLE | IER_LINE_STATUS);
+                self.transmit(&mut state);
+            });
+    }
+
+    /// Program the UART's line settings. The baud rate can only be changed if we know the UART's
+    /// clock frequency, otherwise we leave the divisor as the firmware set it.
+    fn program_line(&self, config: &LineConfig) -> Result<(), &'static str>
+    {
+        if    config.data_bits < 5
+           || config.data_bits > 8
+        {
+            return Err("The UART only supports 5 to 8 data bits.");
+        }
+
+        if    config.stop_bits != 1
+           && config.stop_bits != 2
+        {
+            return Err("The UART only supports 1 or 2 stop bits.");
+        }
+
+        let mut line = config.data_bits - 5;
+
+        if config.stop_bits == 2
+        {
+            line |= LCR_TWO_STOP_BITS;
+        }
+
+        match config.parity
+        {
+            Parity::None => {},
+            Parity::Odd  => line |= LCR_PARITY_ENABLE,
+            Parity::Even => line |= LCR_PARITY_ENABLE | LCR_EVEN_PARITY
+        }
+
+        if let Some(clock_frequency) = self.clock_frequency
+        {
+            if config.baud_rate == 0
+            {
+                return Err("The baud rate can't be 0.");
+            }
+
+            let divisor = clock_frequency / (16 * config.baud_rate);
+
+            if    divisor == 0
+               || divisor > 0xffff
+            {
+                return Err("The UART's clock can't run at that baud rate.");
+            }
+
+            self.write_register(UART_LCR, LCR_DLAB);
+            self.write_register(UART_DLL, (divisor & 0xff) as u8);
+            self.write_register(UART_DLM, (divisor >> 8) as u8);
+        }
+
+        self.write_register(UART_LCR, line);
+
+        Ok(())
+    }
+
+    /// Handle an interrupt from the UART, moving input into the input buffer and output from the
+    /// output buffer until the UART has nothing more to tell us.
+    fn handle_interrupt(&self)
+    {
+        let mut state = self.state.lock();
+
+        while self.read_register(UART_IIR) & IIR_NO_INTERRUPT == 0
+        {
+            self.receive(&mut state);
+            self.transmit(&mut state);
+        }
+    }
+
+    /// Move the input waiting in the receive FIFO into the input buffer. Reading the line status
+    /// also clears any line errors the UART is reporting.
+    fn receive(&self, state: &mut PortState)
+    {
+        while self.read_register(UART_LSR) & LSR_DATA_READY != 0
+        {
+            let byte = self.read_register(UART_RBR);
+
+            if !state.input.push(byte)
+            {
+                state.dropped_input += 1;
+            }
+        }
+    }
+
+    /// Fill the transmit FIFO from the output buffer if it's empty. When interrupt driven the
+    /// transmit interrupt is left on for as long as there's output waiting.
+    fn transmit(&self, state: &mut PortState)
+    {
+        if self.read_register(UART_LSR) & LSR_THR_EMPTY != 0
+        {
+            for _ in 0..FIFO_SIZE
+            {
+                let Some(byte) = state.output.pop()
+                else
+                {
+                    break;
+                };
+
+                self.write_register(UART_THR, byte);
+            }
+        }
+
+        if state.is_interrupt_driven
+        {
+            let mut enabled = IER_RX_AVAILABLE | IER_LINE_STATUS;
+
+            if !state.output.is_empty()
+            {
+                enabled |= IER_TX_EMPTY;
+            }
+
+            self.write_register(UART_IER, enabled);
+        }
+    }
+
+    /// Read one of the UART's registers.
+    fn read_register(&self, register: usize) -> u8
+    {
+        let address = self.base_address + (register << self.register_shift);
+
+        unsafe
+        {
+            match self.register_width
+            {
+                4 => read_volatile(address as *const u32) as u8,
+                _ => read_volatile(address as *const u8)
+            }
+        }
+    }
+
+    /// Write one of the UART's registers.
+    fn write_register(&self, register: usize, value: u8)
+    {
+        let address = self.base_address + (register << self.register_shift);
+
+        unsafe
+        {
+            match self.register_width
+            {
+                4 => write_volatile(address as *mut u32, value as u32),
+                _ => write_volatile(address as *mut u8, value)
+            }
+        }
+    }
+}
+
+
+
+impl SerialDevice for Ns16550
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn line_config(&self) -> LineConfig
+    {
+        with_interrupts_disabled(|| self.state.lock().config)
+    }
+
+    fn set_line_config(&self, config: LineConfig) -> Result<(), &'static str>
+    {
+        with_interrupts_disabled(||
+            {
+                let mut state = self.state.lock();
+
+                if    self.clock_frequency.is_none()
+                   && config.baud_rate != state.config.baud_rate
+                {
+                    return Err("The UART's clock frequency is unknown, its baud rate is fixed.");
+                }
+
+                self.program_line(&config)?;
+                state.config = config;
+
+                Ok(())
+            })
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> usize
+    {
+        with_interrupts_disabled(||
+            {
+                let mut state = self.state.lock();
+
+                if !state.is_interrupt_driven
+                {
+                    self.receive(&mut state);
+                }
+
+                state.input.pop_slice(buffer)
+            })
+    }
+
+    fn write(&self, data: &[u8]) -> usize
+    {
+        with_interrupts_disabled(||
+            {
+                let mut state = self.state.lock();
+                let written = state.output.push_slice(data);
+
+                self.transmit(&mut state);
+
+                written
+            })
+    }
+
+    fn flush(&self)
+    {
+        with_interrupts_disabled(||
+            {
+                let mut state = self.state.lock();
+
+                while !state.output.is_empty()
+                {
+                    self.transmit(&mut state);
+                    spin_loop();
+                }
+            })
+    }
+}
+
+
+
+/// The UARTs found in the device tree.
+static PROBED_UARTS: SpinMutex<Vec<ProbedUart>> = SpinMutex::new(Vec::new());
+
+/// The active ports. Also searched by the interrupt handler, so it's only locked with interrupts
+/// disabled.
+static PORTS: SpinMutex<Vec<Arc<Ns16550>>> = SpinMutex::new(Vec::new());
+
+
+
+/// Probe a serial node, it becomes a port if it's a UART we can drive.
+pub fn probe_ns16550(name: &str,
+                     address: Option<usize>,
+                     device_tree: &DeviceTree,
+                     block_offset: usize) -> Result<(), &'static str>
+{
+    let mut is_compatible = false;
+    let mut is_enabled = true;
+    let mut reg = None;
+    let mut register_shift = 0;
+    let mut register_width = 1;
+    let mut clock_frequency = None;
+    let mut interrupt = None;
+    let mut config = LineConfig::default();
+
+    device_tree.iterate_properties(block_offset, |property_name, property_value|
+        {
+            let cells = read_property_cells(property_value);
+
+            match property_name
+            {
+                "compatible" =>
+                    {
+                        is_compatible = COMPATIBLE_UARTS.iter()
+                                                        .any(|uart| property_contains_string(
+                                                                        property_value,
+                                                                        uart));
+                    },
+
+                "status"          => is_enabled = read_property_string(property_value) == "okay",
+                "reg"             => reg = read_property_reg(property_value),
+                "reg-shift"       => register_shift = cells.unwrap_or(0) as usize,
+                "reg-io-width"    => register_width = cells.unwrap_or(1) as usize,
+                "clock-frequency" => clock_frequency = cells.map(|frequency| frequency as u32),
+                "current-speed"   => config.baud_rate = cells.unwrap_or(0) as u32,
+
+                // Only the first cell matters, it's the UART's source on the PLIC.
+                "interrupts" if property_value.len() >= 4 =>
+                    {
+                        interrupt = read_property_cells(&property_value[..4])
+                            .map(|source| source as usize);
+                    },
+
+                _ => {}
+            }
+
+            true
+        });
+
+    if    !is_compatible
+       || !is_enabled
+    {
+        return Ok(());
+    }
+
+    let (base_address, _) = reg.ok_or("UART is missing its reg property.")?;
+
+    if    register_width != 1
+       && register_width != 4
+    {
+        return Err("UART registers must be 1 or 4 bytes wide.");
+    }
+
+    if config.baud_rate == 0
+    {
+        config = LineConfig::default();
+    }
+
+    PROBED_UARTS.lock().push(ProbedUart
+        {
+            base_address,
+            register_shift,
+            register_width,
+            clock_frequency: clock_frequency.filter(|&frequency| frequency != 0),
+            interrupt,
+            config
+        });
+
+    Ok(())
+}
+
+
+/// Initialize the UARTs found in the device tree and register them as serial ports. Each port's
+/// interrupt is routed to the boot hart, a port whose interrupt can't be routed is polled instead.
+pub fn activate_devices() -> Result<(), &'static str>
+{
+    // The interrupts whose handler has been registered. Ports can share an interrupt, in which case
+    // the ones after the first only need their interrupts enabled.
+    let mut handled_interrupts = Vec::new();
+
+    for probed in PROBED_UARTS.lock().iter()
+    {
+        let port = Arc::new(Ns16550::new(next_port_name(), probed));
+
+        if let Err(error) = port.initialize()
+        {
+            println!("  Failed to initialize the UART at 0x{:016x}: {}", port.base_address, error);
+            continue;
+        }
+
+        register_serial_port(port.clone());
+
+        with_interrupts_disabled(|| PORTS.lock().push(port.clone()));
+
+        let Some(interrupt) = port.interrupt
+        else
+        {
+            println!("  {} has no interrupt, it will be polled.", port.name);
+            continue;
+        };
+
+        if handled_interrupts.contains(&interrupt)
+        {
+            port.enable_interrupts();
+            continue;
+        }
+
+        // Only a port whose handler really was registered is switched over to interrupts, one
+        // without a handler would stall waiting for interrupts that are never handled.
+        match register_interrupt_handler(None, interrupt, handle_uart_interrupt)
+        {
+            Ok(()) =>
+                {
+                    handled_interrupts.push(interrupt);
+                    port.enable_interrupts();
+                },
+
+            Err(error) =>
+                {
+                    println!("  {} will be polled, its interrupt couldn't be routed: {}",
+                             port.name,
+                             error);
+                }
+        }
+    }
+
+    Ok(())
+}
+
+
+
+/// Handle an interrupt for any of the UARTs on the interrupt.
+fn handle_uart_interrupt(interrupt_number: usize)
+{
+    for port in PORTS.lock().iter()
+    {
+        if port.interrupt == Some(interrupt_number)
+        {
+            port.handle_interrupt();
+        }
+    }
+}
@@ -0,0 +1,228 @@
+// A write back cache of filesystem blocks. Filesystem drivers read and modify their on disk
+// structures through the cache so that repeated access to the same metadata blocks doesn't go back
+// to the device every time, and so that a batch of changes can be written out together when the
+// filesystem is synced.
+//
+// The cache has a fixed number of entries whose buffers are allocated once up front, when it is
+// full the least recently used block is written back, (if needed,) and its buffer reused.
+
+use alloc::{ sync::Arc, vec, vec::Vec };
+
+use crate::{ devices::block_devices::{ BlockDevice, SECTOR_SIZE },
+             filesystems::{ FsError, FsResult } };
+
+
+
+/// Marker for a cache entry that doesn't currently hold a block.
+const UNUSED_ENTRY: u64 = u64::MAX;
+
+
+
+/// A single cached block.
+struct CacheEntry
+{
+    block: u64,       // The block held by the entry, or UNUSED_ENTRY.
+    data: Vec<u8>,    // The contents of the block.
+    dirty: bool,      // Has the block been changed since it was read?
+    last_used: u64    // When the entry was last accessed, for picking an entry to evict.
+}
+
+
+
+/// A cache of fixed size blocks read from a block device.
+pub struct BlockCache
+{
+    /// The device the blocks live on.
+    device: Arc<dyn BlockDevice>,
+
+    /// The size of a block in bytes, a multiple of the device's sector size.
+    block_size: usize,
+
+    /// The cached blocks.
+    entries: Vec<CacheEntry>,
+
+    /// Counter used to track how recently entries were used.
+    clock: u64
+}
+
+
+
+impl BlockCache
+{
+    /// Create a new cache for the device, holding up to `capacity` blocks of `block_size` bytes.
+    pub fn new(device: Arc<dyn BlockDevice>,
+               block_size: usize,
+               capacity: usize) -> FsResult<Self>
+    {
+        if    block_size == 0
+           || !block_size.is_multiple_of(SECTOR_SIZE)
+           || capacity == 0
+        {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let entries = (0..capacity).map(|_|
+            {
+                CacheEntry
+                    {
+                        block: UNUSED_ENTRY,
+                        data: vec![0; block_size],
+                        dirty: false,
+                        last_used: 0
+                    }
+            })
+            .collect();
+
+        Ok(BlockCache
+            {
+                device,
+                block_size,
+                entries,
+                clock: 0
+            })
+    }
+
+    /// The size of the cached blocks in bytes.
+    pub fn block_size(&self) -> usize
+    {
+        self.block_size
+    }
+
+    /// The device the cache reads from.
+    pub fn device(&self) -> &Arc<dyn BlockDevice>
+    {
+        &self.device
+    }
+
+    /// Get read access to a block, reading it from the device if it isn't already cached.
+    pub fn read(&mut self, block: u64) -> FsResult<&[u8]>
+    {
+        let index = self.load(block, true)?;
+
+        Ok(&self.entries[index].data)
+    }
+
+    /// Get write access to a block, reading it from the device if it isn't already cached. The
+    /// block is marked as dirty and will be written back later.
+    pub fn write(&mut self, block: u64) -> FsResult<&mut [u8]>
+    {
+        let index = self.load(block, true)?;
+        let entry = &mut self.entries[index];
+
+        entry.dirty = true;
+
+        Ok(&mut entry.data)
+    }
+
+    /// Get write access to a block whose previous contents don't matter, the block is zeroed
+    /// instead of being read from the device.
+    pub fn zeroed(&mut self, block: u64) -> FsResult<&mut [u8]>
+    {
+        let index = self.load(block, false)?;
+        let entry = &mut self.entries[index];
+
+        entry.data.fill(0);
+        entry.dirty = true;
+
+        Ok(&mut entry.data)
+    }
+
+    /// Copy part of a block into the given buffer.
+    pub fn read_into(&mut self, block: u64, offset: usize, buffer: &mut [u8]) -> FsResult<()>
+    {
+        let data = self.read(block)?;
+
+        buffer.copy_from_slice(&data[offset..offset + buffer.len()]);
+
+        Ok(())
+    }
+
+    /// Copy the given buffer into part of a block.
+    pub fn write_from(&mut self, block: u64, offset: usize, buffer: &[u8]) -> FsResult<()>
+    {
+        let data = self.write(block)?;
+
+        data[offset..offset + buffer.len()].copy_from_slice(buffer);
+
+        Ok(())
+    }
+
+    /// Drop a block from the cache without writing it back, used when a block is freed.
+    pub fn discard(&mut self, block: u64)
+    {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.block == block)
+        {
+            entry.block = UNUSED_ENTRY;
+            entry.dirty = false;
+        }
+    }
+
+    /// Write all of the dirty blocks back to the device and flush the device's own cache.
+    pub fn flush(&mut self) -> FsResult<()>
+    {
+        for index in 0..self.entries.len()
+        {
+            self.write_back(index)?;
+        }
+
+        self.device.flush()?;
+
+        Ok(())
+    }
+
+    /// Find the block in the cache or load it into a free or evicted entry, returning the entry's
+    /// index.
+    fn load(&mut self, block: u64, read: bool) -> FsResult<usize>
+    {
+        self.clock += 1;
+
+        if let Some(index) = self.entries.iter().position(|entry| entry.block == block)
+        {
+            self.entries[index].last_used = self.clock;
+            return Ok(index);
+        }
+
+        // Pick the least recently used entry, unused entries have a last used time of 0 so they
+        // are picked first.
+        let index = self.entries
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, entry)| entry.last_used)
+                        .map(|(index, _)| index)
+                        .unwrap();
+
+        self.write_back(index)?;
+
+        let sectors_per_block = (self.block_size / SECTOR_SIZE) as u64;
+        let entry = &mut self.entries[index];
+
+        entry.block = UNUSED_ENTRY;
+
+        if read
+        {
+            self.device.read_sectors(block * sectors_per_block, &mut entry.data)?;
+        }
+
+        entry.block = block;
+        entry.dirty = false;
+        entry.last_used = self.clock;
+
+        Ok(index)
+    }
+
+    /// Write an entry back to the device if it is dirty.
+    fn write_back(&mut self, index: usize) -> FsResult<()>
+    {
+        let sectors_per_block = (self.block_size / SECTOR_SIZE) as u64;
+        let entry = &mut self.entries[index];
+
+        if    entry.dirty
+           && entry.block != UNUSED_ENTRY
+        {
+            self.device.write_sectors(entry.block * sectors_per_block, &entry.data)?;
+            entry.dirty = false;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+// Directory entries, (dentries,) tie names to inodes and form the in memory tree that path
+// resolution walks. Looking up a name fills the cache so that walking the same path again doesn't
+// need to go back to the filesystem. A dentry also records if another filesystem has been mounted
+// on top of it.
+
+use alloc::{ collections::BTreeMap, string::{ String, ToString }, sync::{ Arc, Weak } };
+
+use crate::{ filesystems::{ inode::Inode, mount::Mount, FsResult },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// A cached name to inode mapping within a filesystem's directory tree.
+pub struct Dentry
+{
+    /// The name of the entry within its parent directory. The root of a filesystem is named "/".
+    name: String,
+
+    /// The inode the name refers to.
+    inode: Arc<dyn Inode>,
+
+    /// The directory this entry lives in, the root of a filesystem has no parent.
+    parent: Option<Weak<Dentry>>,
+
+    /// The entries of this directory that have been looked up so far.
+    children: SpinMutex<BTreeMap<String, Arc<Dentry>>>,
+
+    /// A filesystem that has been mounted on top of this entry, if any.
+    mounted: SpinMutex<Option<Arc<Mount>>>
+}
+
+
+
+impl Dentry
+{
+    /// Create the root entry for a filesystem.
+    pub fn new_root(inode: Arc<dyn Inode>) -> Arc<Dentry>
+    {
+        Arc::new(Dentry
+            {
+                name: "/".to_string(),
+                inode,
+                parent: None,
+                children: SpinMutex::new(BTreeMap::new()),
+                mounted: SpinMutex::new(None)
+            })
+    }
+
+    /// The name of the entry within its parent directory.
+    pub fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    /// The inode the entry refers to.
+    pub fn inode(&self) -> Arc<dyn Inode>
+    {
+        self.inode.clone()
+    }
+
+    /// The directory containing this entry, or `None` for the root of a filesystem.
+    pub fn parent(&self) -> Option<Arc<Dentry>>
+    {
+        self.parent.as_ref().and_then(|parent| parent.upgrade())
+    }
+
+    /// Find the named child of this directory, asking the filesystem if it isn't already cached.
+    pub fn lookup(self: &Arc<Self>, name: &str) -> FsResult<Arc<Dentry>>
+    {
+        if let Some(child) = self.children.lock().get(name)
+        {
+            return Ok(child.clone());
+        }
+
+        let inode = self.inode.lookup(name)?;
+
+        Ok(self.add_child(name, inode))
+    }
+
+    /// Add a newly created inode to the cache as a child of this directory.
+    pub fn add_child(self: &Arc<Self>, name: &str, inode: Arc<dyn Inode>) -> Arc<Dentry>
+    {
+        let child = Arc::new(Dentry
+            {
+                name: name.to_string(),
+                inode,
+                parent: Some(Arc::downgrade(self)),
+                children: SpinMutex::new(BTreeMap::new()),
+                mounted: SpinMutex::new(None)
+            });
+
+        self.children.lock().insert(name.to_string(), child.clone());
+
+        child
+    }
+
+    /// Drop a child from the cache, this is done when it has been removed from the filesystem.
+    pub fn forget_child(&self, name: &str)
+    {
+        self.children.lock().remove(name);
+    }
+
+    /// Get the filesystem mounted on top of this entry, if any.
+    pub fn mounted(&self) -> Option<Arc<Mount>>
+    {
+        self.mounted.lock().clone()
+    }
+
+    /// Set or clear the filesystem mounted on top of this entry.
+    pub fn set_mounted(&self, mount: Option<Arc<Mount>>)
+    {
+        *self.mounted.lock() = mount;
+    }
+}
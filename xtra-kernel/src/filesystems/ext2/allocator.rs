@@ -0,0 +1,207 @@
+// Allocation of blocks and inodes. Each block group has a bitmap block for its blocks and another
+// for its inodes. Allocation searches the bitmaps starting from a preferred group so that an
+// inode's blocks, (and a directory's children,) tend to end up close together on the disk.
+//
+// The group descriptors are updated as we go, the superblock's totals are written out when the
+// filesystem is synced.
+
+use crate::filesystems::{ ext2::Ext2State, FsError, FsResult };
+
+
+
+/// Find the first clear bit in a bitmap, looking at no more than `limit` bits.
+fn find_clear_bit(bitmap: &[u8], limit: usize) -> Option<usize>
+{
+    for (index, &byte) in bitmap.iter().enumerate()
+    {
+        if index * 8 >= limit
+        {
+            break;
+        }
+
+        if byte != 0xff
+        {
+            let bit = index * 8 + byte.trailing_ones() as usize;
+
+            return if bit < limit { Some(bit) } else { None };
+        }
+    }
+
+    None
+}
+
+
+
+/// Allocate a block, preferring the given group. The block's contents are left as they are, callers
+/// are expected to initialize it.
+pub fn allocate_block(state: &mut Ext2State, preferred_group: u32) -> FsResult<u32>
+{
+    state.check_writable()?;
+
+    let group_count = state.groups.len() as u32;
+
+    for offset in 0..group_count
+    {
+        let group = (preferred_group + offset) % group_count;
+
+        if state.groups[group as usize].free_blocks_count == 0
+        {
+            continue;
+        }
+
+        let bitmap_block = state.groups[group as usize].block_bitmap as u64;
+        let limit = state.superblock.blocks_in_group(group) as usize;
+        let bit = match find_clear_bit(state.cache.read(bitmap_block)?, limit)
+            {
+                Some(bit) => bit,
+                None      => continue
+            };
+
+        state.cache.write(bitmap_block)?[bit / 8] |= 1 << (bit % 8);
+
+        state.groups[group as usize].free_blocks_count -= 1;
+        state.superblock.free_blocks_count -= 1;
+        state.write_group(group)?;
+
+        return Ok(state.superblock.first_data_block
+                  + group * state.superblock.blocks_per_group
+                  + bit as u32);
+    }
+
+    Err(FsError::NoSpace)
+}
+
+
+
+/// Return a block to the free pool.
+pub fn free_block(state: &mut Ext2State, block: u32) -> FsResult<()>
+{
+    state.check_writable()?;
+
+    if    block < state.superblock.first_data_block
+       || block >= state.superblock.blocks_count
+    {
+        return Err(FsError::Corrupted("Attempt to free a block outside of the filesystem."));
+    }
+
+    let relative = block - state.superblock.first_data_block;
+    let group = relative / state.superblock.blocks_per_group;
+    let bit = (relative % state.superblock.blocks_per_group) as usize;
+    let bitmap_block = state.groups[group as usize].block_bitmap as u64;
+
+    let bitmap = state.cache.write(bitmap_block)?;
+
+    if bitmap[bit / 8] & (1 << (bit % 8)) == 0
+    {
+        return Err(FsError::Corrupted("Attempt to free a block that is already free."));
+    }
+
+    bitmap[bit / 8] &= !(1 << (bit % 8));
+
+    state.groups[group as usize].free_blocks_count += 1;
+    state.superblock.free_blocks_count += 1;
+    state.write_group(group)?;
+
+    // Any cached copy of the block is now meaningless, don't let it get written back over whatever
+    // the block is used for next.
+    state.cache.discard(block as u64);
+
+    Ok(())
+}
+
+
+
+/// Allocate an inode, preferring the given group. The inode table entry is left for the caller to
+/// fill in.
+pub fn allocate_inode(state: &mut Ext2State,
+                      preferred_group: u32,
+                      is_directory: bool) -> FsResult<u32>
+{
+    state.check_writable()?;
+
+    let group_count = state.groups.len() as u32;
+    let inodes_per_group = state.superblock.inodes_per_group;
+
+    for offset in 0..group_count
+    {
+        let group = (preferred_group + offset) % group_count;
+
+        if state.groups[group as usize].free_inodes_count == 0
+        {
+            continue;
+        }
+
+        // The reserved inodes at the start of the first group are always marked as used so a
+        // simple search for a clear bit never finds them.
+        let bitmap_block = state.groups[group as usize].inode_bitmap as u64;
+        let bit = match find_clear_bit(state.cache.read(bitmap_block)?, inodes_per_group as usize)
+            {
+                Some(bit) => bit,
+                None      => continue
+            };
+
+        let number = group * inodes_per_group + bit as u32 + 1;
+
+        if number < state.superblock.first_inode
+        {
+            return Err(FsError::Corrupted("Reserved inode is marked as free."));
+        }
+
+        state.cache.write(bitmap_block)?[bit / 8] |= 1 << (bit % 8);
+
+        let descriptor = &mut state.groups[group as usize];
+
+        descriptor.free_inodes_count -= 1;
+
+        if is_directory
+        {
+            descriptor.used_dirs_count += 1;
+        }
+
+        state.superblock.free_inodes_count -= 1;
+        state.write_group(group)?;
+
+        return Ok(number);
+    }
+
+    Err(FsError::NoSpace)
+}
+
+
+
+/// Return an inode to the free pool.
+pub fn free_inode(state: &mut Ext2State, number: u32, is_directory: bool) -> FsResult<()>
+{
+    state.check_writable()?;
+
+    if    number < state.superblock.first_inode
+       || number > state.superblock.inodes_count
+    {
+        return Err(FsError::Corrupted("Attempt to free a reserved or invalid inode."));
+    }
+
+    let group = state.group_of_inode(number);
+    let bit = ((number - 1) % state.superblock.inodes_per_group) as usize;
+    let bitmap_block = state.groups[group as usize].inode_bitmap as u64;
+
+    let bitmap = state.cache.write(bitmap_block)?;
+
+    if bitmap[bit / 8] & (1 << (bit % 8)) == 0
+    {
+        return Err(FsError::Corrupted("Attempt to free an inode that is already free."));
+    }
+
+    bitmap[bit / 8] &= !(1 << (bit % 8));
+
+    let descriptor = &mut state.groups[group as usize];
+
+    descriptor.free_inodes_count += 1;
+
+    if is_directory
+    {
+        descriptor.used_dirs_count = descriptor.used_dirs_count.saturating_sub(1);
+    }
+
+    state.superblock.free_inodes_count += 1;
+    state.write_group(group)
+}
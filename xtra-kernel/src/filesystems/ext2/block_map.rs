@@ -0,0 +1,355 @@
+// Mapping of an inode's logical blocks to blocks on the disk. The first 12 blocks of a file are
+// pointed to directly from the inode, after that come a single indirect block of pointers, a double
+// indirect block of pointers to pointer blocks and finally a triple indirect block.
+//
+// A pointer of 0 is a hole, reading it gives zeros and writing to it allocates a block.
+
+use crate::filesystems::{ ext2::{ allocator::{ allocate_block, free_block },
+                                  disk_inode::{ DiskInode,
+                                                DIRECT_BLOCKS,
+                                                DOUBLE_INDIRECT_BLOCK,
+                                                SINGLE_INDIRECT_BLOCK,
+                                                TRIPLE_INDIRECT_BLOCK },
+                                  Ext2State },
+                          read_u32,
+                          write_u32,
+                          FsError,
+                          FsResult };
+
+
+
+/// Where a logical block's pointer lives, which inode slot to start from and the indices to follow
+/// through the indirect blocks.
+struct BlockPath
+{
+    slot: usize,           // The inode's block pointer to start from.
+    indices: [usize; 3],   // Index within each level of indirect block.
+    depth: usize           // How many levels of indirection there are.
+}
+
+
+
+/// The number of block pointers that fit in a block.
+fn pointers_per_block(state: &Ext2State) -> u64
+{
+    (state.block_size() / 4) as u64
+}
+
+
+
+/// The largest number of blocks a single inode can address.
+pub fn max_blocks(state: &Ext2State) -> u64
+{
+    let pointers = pointers_per_block(state);
+
+    DIRECT_BLOCKS as u64 + pointers + pointers * pointers + pointers * pointers * pointers
+}
+
+
+
+/// Work out the path through the block pointers to a logical block.
+fn block_path(state: &Ext2State, logical: u64) -> FsResult<BlockPath>
+{
+    let pointers = pointers_per_block(state);
+    let mut index = logical;
+
+    if index < DIRECT_BLOCKS as u64
+    {
+        return Ok(BlockPath { slot: index as usize, indices: [0; 3], depth: 0 });
+    }
+
+    index -= DIRECT_BLOCKS as u64;
+
+    if index < pointers
+    {
+        return Ok(BlockPath
+            {
+                slot: SINGLE_INDIRECT_BLOCK,
+                indices: [index as usize, 0, 0],
+                depth: 1
+            });
+    }
+
+    index -= pointers;
+
+    if index < pointers * pointers
+    {
+        return Ok(BlockPath
+            {
+                slot: DOUBLE_INDIRECT_BLOCK,
+                indices: [(index / pointers) as usize, (index % pointers) as usize, 0],
+                depth: 2
+            });
+    }
+
+    index -= pointers * pointers;
+
+    if index < pointers * pointers * pointers
+    {
+        return Ok(BlockPath
+            {
+                slot: TRIPLE_INDIRECT_BLOCK,
+                indices: [(index / (pointers * pointers)) as usize,
+                          ((index / pointers) % pointers) as usize,
+                          (index % pointers) as usize],
+                depth: 3
+            });
+    }
+
+    Err(FsError::FileTooLarge)
+}
+
+
+
+/// Make sure a block pointer read from the disk is within the filesystem.
+fn check_block(state: &Ext2State, block: u32) -> FsResult<u32>
+{
+    if    block != 0
+       && (   block < state.superblock.first_data_block
+           || block >= state.superblock.blocks_count)
+    {
+        return Err(FsError::Corrupted("Block pointer outside of the filesystem."));
+    }
+
+    Ok(block)
+}
+
+
+
+/// Find the disk block holding a logical block of the inode, 0 if the block is a hole.
+pub fn lookup_block(state: &mut Ext2State, inode: &DiskInode, logical: u64) -> FsResult<u32>
+{
+    let path = block_path(state, logical)?;
+    let mut block = check_block(state, inode.block(path.slot))?;
+
+    for &index in &path.indices[..path.depth]
+    {
+        if block == 0
+        {
+            return Ok(0);
+        }
+
+        let pointer = read_u32(state.cache.read(block as u64)?, index * 4);
+
+        block = check_block(state, pointer)?;
+    }
+
+    Ok(block)
+}
+
+
+
+/// Allocate a new zero filled block for the inode, counting it in the inode's block total.
+fn allocate_zeroed_block(state: &mut Ext2State,
+                         inode: &mut DiskInode,
+                         preferred_group: u32) -> FsResult<u32>
+{
+    let block = allocate_block(state, preferred_group)?;
+
+    state.cache.zeroed(block as u64)?;
+    inode.set_blocks(inode.blocks() + (state.block_size() / 512) as u32);
+
+    Ok(block)
+}
+
+
+
+/// Find the disk block holding a logical block of the inode, allocating it, and any indirect blocks
+/// needed to reach it, if it is a hole. New blocks are allocated close to the inode's group and are
+/// zero filled.
+pub fn map_block(state: &mut Ext2State,
+                 inode_number: u32,
+                 inode: &mut DiskInode,
+                 logical: u64) -> FsResult<u32>
+{
+    let path = block_path(state, logical)?;
+    let group = state.group_of_inode(inode_number);
+    let mut block = check_block(state, inode.block(path.slot))?;
+
+    if block == 0
+    {
+        block = allocate_zeroed_block(state, inode, group)?;
+        inode.set_block(path.slot, block);
+    }
+
+    for &index in &path.indices[..path.depth]
+    {
+        let pointer = read_u32(state.cache.read(block as u64)?, index * 4);
+        let pointer = check_block(state, pointer)?;
+
+        block = if pointer == 0
+            {
+                let new_block = allocate_zeroed_block(state, inode, group)?;
+
+                write_u32(state.cache.write(block as u64)?, index * 4, new_block);
+                new_block
+            }
+            else
+            {
+                pointer
+            };
+    }
+
+    Ok(block)
+}
+
+
+
+/// Release a block that belonged to the inode, removing it from the inode's block total.
+fn release_block(state: &mut Ext2State, inode: &mut DiskInode, block: u32) -> FsResult<()>
+{
+    free_block(state, block)?;
+    inode.set_blocks(inode.blocks().saturating_sub((state.block_size() / 512) as u32));
+
+    Ok(())
+}
+
+
+
+/// Free the blocks reachable from an indirect block that map logical blocks from `first` onward,
+/// where `first` is relative to the start of the area the indirect block covers. Entries that get
+/// freed are cleared in the indirect block.
+fn free_indirect(state: &mut Ext2State,
+                 inode: &mut DiskInode,
+                 block: u32,
+                 level: u32,
+                 first: u64) -> FsResult<()>
+{
+    let pointers = pointers_per_block(state);
+    let span = pointers.pow(level - 1);
+
+    for index in 0..pointers as usize
+    {
+        let start = index as u64 * span;
+
+        if start + span <= first
+        {
+            continue;
+        }
+
+        let pointer = read_u32(state.cache.read(block as u64)?, index * 4);
+        let pointer = check_block(state, pointer)?;
+
+        if pointer == 0
+        {
+            continue;
+        }
+
+        let child_first = first.saturating_sub(start);
+
+        if level > 1
+        {
+            free_indirect(state, inode, pointer, level - 1, child_first)?;
+        }
+
+        if child_first == 0
+        {
+            release_block(state, inode, pointer)?;
+            write_u32(state.cache.write(block as u64)?, index * 4, 0);
+        }
+    }
+
+    Ok(())
+}
+
+
+
+/// Free all of the inode's blocks that map logical block `first` and beyond, along with any
+/// indirect blocks that are no longer needed.
+pub fn free_blocks_from(state: &mut Ext2State, inode: &mut DiskInode, first: u64) -> FsResult<()>
+{
+    for slot in first.min(DIRECT_BLOCKS as u64) as usize..DIRECT_BLOCKS
+    {
+        let block = check_block(state, inode.block(slot))?;
+
+        if block != 0
+        {
+            release_block(state, inode, block)?;
+            inode.set_block(slot, 0);
+        }
+    }
+
+    let pointers = pointers_per_block(state);
+    let mut area_start = DIRECT_BLOCKS as u64;
+
+    for (level, slot) in [ (1, SINGLE_INDIRECT_BLOCK),
+                           (2, DOUBLE_INDIRECT_BLOCK),
+                           (3, TRIPLE_INDIRECT_BLOCK) ]
+    {
+        let area_size = pointers.pow(level);
+        let block = check_block(state, inode.block(slot))?;
+
+        if    block != 0
+           && first < area_start + area_size
+        {
+            let relative_first = first.saturating_sub(area_start);
+
+            free_indirect(state, inode, block, level, relative_first)?;
+
+            if relative_first == 0
+            {
+                release_block(state, inode, block)?;
+                inode.set_block(slot, 0);
+            }
+        }
+
+        area_start += area_size;
+    }
+
+    Ok(())
+}
+
+
+
+/// Call the visitor for every block the inode refers to, data and indirect blocks alike. The
+/// visitor returns false for blocks that shouldn't be descended into, for example because they are
+/// outside of the filesystem.
+pub fn visit_blocks(state: &mut Ext2State,
+                    inode: &DiskInode,
+                    visitor: &mut dyn FnMut(u32) -> bool) -> FsResult<()>
+{
+    fn visit_indirect(state: &mut Ext2State,
+                      block: u32,
+                      level: u32,
+                      visitor: &mut dyn FnMut(u32) -> bool) -> FsResult<()>
+    {
+        for index in 0..state.block_size() / 4
+        {
+            let pointer = read_u32(state.cache.read(block as u64)?, index * 4);
+
+            if    pointer != 0
+               && visitor(pointer)
+               && level > 1
+            {
+                visit_indirect(state, pointer, level - 1, visitor)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    for slot in 0..DIRECT_BLOCKS
+    {
+        let block = inode.block(slot);
+
+        if block != 0
+        {
+            visitor(block);
+        }
+    }
+
+    for (level, slot) in [ (1, SINGLE_INDIRECT_BLOCK),
+                           (2, DOUBLE_INDIRECT_BLOCK),
+                           (3, TRIPLE_INDIRECT_BLOCK) ]
+    {
+        let block = inode.block(slot);
+
+        if    block != 0
+           && visitor(block)
+        {
+            visit_indirect(state, block, level, visitor)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,414 @@
+// Directories in ext2 are files made up of a linked list of variable length entries. Each entry
+// holds the inode number, the length of the record, the length of the name and, when the
+// filesystem has the filetype feature, the type of the inode. Records never cross a block boundary
+// and the last record in a block stretches to the end of the block.
+//
+// Removed entries are either merged into the record before them or, if they are first in the
+// block, have their inode number set to 0.
+
+use alloc::{ string::String, vec::Vec };
+
+use crate::filesystems::{ ext2::{ block_map::{ lookup_block, map_block },
+                                  disk_inode::DiskInode,
+                                  superblock::EXT2_FEATURE_INCOMPAT_FILETYPE,
+                                  Ext2State },
+                          inode::FileType,
+                          read_u16,
+                          read_u32,
+                          write_u16,
+                          write_u32,
+                          FsError,
+                          FsResult };
+
+
+
+/// The size of the fixed part of a directory entry.
+const ENTRY_HEADER_SIZE: usize = 8;
+
+
+
+// The type codes stored in directory entries when the filetype feature is enabled.
+const FT_UNKNOWN: u8 = 0;
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_CHRDEV: u8 = 3;
+const FT_BLKDEV: u8 = 4;
+const FT_FIFO: u8 = 5;
+const FT_SOCK: u8 = 6;
+const FT_SYMLINK: u8 = 7;
+
+
+
+/// Convert a VFS file type to a directory entry type code.
+fn type_code(file_type: FileType) -> u8
+{
+    match file_type
+    {
+        FileType::Regular         => FT_REG_FILE,
+        FileType::Directory       => FT_DIR,
+        FileType::CharacterDevice => FT_CHRDEV,
+        FileType::BlockDevice     => FT_BLKDEV,
+        FileType::Fifo            => FT_FIFO,
+        FileType::Socket          => FT_SOCK,
+        FileType::SymbolicLink    => FT_SYMLINK
+    }
+}
+
+
+
+/// Convert a directory entry type code to a VFS file type, `None` if the type isn't recorded.
+pub fn file_type_of_code(code: u8) -> Option<FileType>
+{
+    match code
+    {
+        FT_REG_FILE => Some(FileType::Regular),
+        FT_DIR      => Some(FileType::Directory),
+        FT_CHRDEV   => Some(FileType::CharacterDevice),
+        FT_BLKDEV   => Some(FileType::BlockDevice),
+        FT_FIFO     => Some(FileType::Fifo),
+        FT_SOCK     => Some(FileType::Socket),
+        FT_SYMLINK  => Some(FileType::SymbolicLink),
+        _           => None
+    }
+}
+
+
+
+/// The space a record with a name of the given length needs, rounded up to 4 bytes.
+fn record_size(name_length: usize) -> usize
+{
+    (ENTRY_HEADER_SIZE + name_length + 3) & !3
+}
+
+
+
+/// A directory entry as found in a directory block.
+pub struct EntryView<'a>
+{
+    /// The inode the entry refers to, 0 for an unused record.
+    pub inode: u32,
+
+    /// The length of the whole record, including any unused space after the name.
+    pub record_length: usize,
+
+    /// The entry's name.
+    pub name: &'a [u8],
+
+    /// The type code of the inode, FT_UNKNOWN if the filesystem doesn't record it.
+    pub type_code: u8
+}
+
+
+
+/// Where an entry was found, the disk block and the offset of the record within it.
+#[derive(Clone, Copy)]
+pub struct EntryLocation
+{
+    pub block: u32,
+    pub offset: usize
+}
+
+
+
+/// Call `visitor` for every record in the directory, stopping as soon as it returns a value.
+pub fn for_each_entry<R>(state: &mut Ext2State,
+                         directory: &DiskInode,
+                         mut visitor: impl FnMut(EntryLocation, &EntryView) -> Option<R>)
+    -> FsResult<Option<R>>
+{
+    let block_size = state.block_size();
+    let has_file_type = state.superblock.has_incompat_feature(EXT2_FEATURE_INCOMPAT_FILETYPE);
+    let block_count = directory.size().div_ceil(block_size as u64);
+
+    for logical in 0..block_count
+    {
+        let block = lookup_block(state, directory, logical)?;
+
+        if block == 0
+        {
+            return Err(FsError::Corrupted("Hole in directory."));
+        }
+
+        let data = state.cache.read(block as u64)?;
+        let mut offset = 0;
+
+        while offset < block_size
+        {
+            if offset + ENTRY_HEADER_SIZE > block_size
+            {
+                return Err(FsError::Corrupted("Truncated directory entry."));
+            }
+
+            let record_length = read_u16(data, offset + 4) as usize;
+            let name_length = data[offset + 6] as usize;
+
+            if    record_length < ENTRY_HEADER_SIZE
+               || !record_length.is_multiple_of(4)
+               || offset + record_length > block_size
+               || ENTRY_HEADER_SIZE + name_length > record_length
+            {
+                return Err(FsError::Corrupted("Invalid directory entry length."));
+            }
+
+            let name_start = offset + ENTRY_HEADER_SIZE;
+
+            let entry = EntryView
+                {
+                    inode: read_u32(data, offset),
+                    record_length,
+                    name: &data[name_start..name_start + name_length],
+                    type_code: if has_file_type { data[offset + 7] } else { FT_UNKNOWN }
+                };
+
+            if let Some(result) = visitor(EntryLocation { block, offset }, &entry)
+            {
+                return Ok(Some(result));
+            }
+
+            offset += record_length;
+        }
+    }
+
+    Ok(None)
+}
+
+
+
+/// Write a record into a directory block.
+fn write_entry(data: &mut [u8],
+               offset: usize,
+               inode: u32,
+               record_length: usize,
+               name: &[u8],
+               type_code: u8)
+{
+    write_u32(data, offset, inode);
+    write_u16(data, offset + 4, record_length as u16);
+    data[offset + 6] = name.len() as u8;
+    data[offset + 7] = type_code;
+    data[offset + ENTRY_HEADER_SIZE..offset + ENTRY_HEADER_SIZE + name.len()].copy_from_slice(name);
+}
+
+
+
+/// The type code to record in a directory entry, filesystems without the filetype feature always
+/// store 0.
+fn entry_type_code(state: &Ext2State, file_type: FileType) -> u8
+{
+    if state.superblock.has_incompat_feature(EXT2_FEATURE_INCOMPAT_FILETYPE)
+    {
+        type_code(file_type)
+    }
+    else
+    {
+        FT_UNKNOWN
+    }
+}
+
+
+
+/// Find the named entry, returning the inode number and type code it records.
+pub fn find_entry(state: &mut Ext2State,
+                  directory: &DiskInode,
+                  name: &str) -> FsResult<Option<(u32, u8)>>
+{
+    for_each_entry(state, directory, |_, entry|
+        {
+            if    entry.inode != 0
+               && entry.name == name.as_bytes()
+            {
+                Some((entry.inode, entry.type_code))
+            }
+            else
+            {
+                None
+            }
+        })
+}
+
+
+
+/// Read all of the entries of a directory, returning their names, inode numbers and type codes. The
+/// . and .. entries are included.
+pub fn list_entries(state: &mut Ext2State,
+                    directory: &DiskInode) -> FsResult<Vec<(String, u32, u8)>>
+{
+    let mut entries = Vec::new();
+
+    for_each_entry::<()>(state, directory, |_, entry|
+        {
+            if entry.inode != 0
+            {
+                entries.push((String::from_utf8_lossy(entry.name).into(),
+                              entry.inode,
+                              entry.type_code));
+            }
+
+            None
+        })?;
+
+    Ok(entries)
+}
+
+
+
+/// Check if a directory only holds its . and .. entries.
+pub fn is_empty(state: &mut Ext2State, directory: &DiskInode) -> FsResult<bool>
+{
+    let found = for_each_entry(state, directory, |_, entry|
+        {
+            if    entry.inode != 0
+               && entry.name != b"."
+               && entry.name != b".."
+            {
+                Some(())
+            }
+            else
+            {
+                None
+            }
+        })?;
+
+    Ok(found.is_none())
+}
+
+
+
+/// Add an entry to a directory, reusing free space in an existing record where possible and growing
+/// the directory by a block otherwise. The caller is responsible for writing the directory's inode
+/// back if it changed.
+pub fn add_entry(state: &mut Ext2State,
+                 directory_number: u32,
+                 directory: &mut DiskInode,
+                 name: &str,
+                 inode: u32,
+                 file_type: FileType) -> FsResult<()>
+{
+    let needed = record_size(name.len());
+
+    // Look for a record that is either unused and big enough or has enough slack after its name to
+    // be split in two.
+    let space = for_each_entry(state, directory, |location, entry|
+        {
+            if    entry.inode == 0
+               && entry.record_length >= needed
+            {
+                Some((location, None))
+            }
+            else if    entry.inode != 0
+                    && entry.record_length - record_size(entry.name.len()) >= needed
+            {
+                Some((location, Some((record_size(entry.name.len()), entry.record_length))))
+            }
+            else
+            {
+                None
+            }
+        })?;
+
+    let name = name.as_bytes();
+    let type_code = entry_type_code(state, file_type);
+
+    match space
+    {
+        Some((location, None)) =>
+            {
+                let data = state.cache.write(location.block as u64)?;
+                let record_length = read_u16(data, location.offset + 4) as usize;
+
+                write_entry(data, location.offset, inode, record_length, name, type_code);
+            },
+
+        Some((location, Some((used_length, record_length)))) =>
+            {
+                let data = state.cache.write(location.block as u64)?;
+
+                write_u16(data, location.offset + 4, used_length as u16);
+                write_entry(data,
+                            location.offset + used_length,
+                            inode,
+                            record_length - used_length,
+                            name,
+                            type_code);
+            },
+
+        None =>
+            {
+                let block_size = state.block_size();
+                let logical = directory.size() / block_size as u64;
+                let block = map_block(state, directory_number, directory, logical)?;
+                let data = state.cache.write(block as u64)?;
+
+                write_entry(data, 0, inode, block_size, name, type_code);
+                directory.set_size((logical + 1) * block_size as u64);
+            }
+    }
+
+    Ok(())
+}
+
+
+
+/// Remove the named entry from a directory, returning the inode number it referred to.
+pub fn remove_entry(state: &mut Ext2State, directory: &DiskInode, name: &str) -> FsResult<u32>
+{
+    let mut previous: Option<EntryLocation> = None;
+
+    let found = for_each_entry(state, directory, |location, entry|
+        {
+            if    previous.is_some_and(|previous| previous.block != location.block)
+               || location.offset == 0
+            {
+                previous = None;
+            }
+
+            if    entry.inode != 0
+               && entry.name == name.as_bytes()
+            {
+                return Some((location, previous, entry.inode, entry.record_length));
+            }
+
+            previous = Some(location);
+
+            None
+        })?;
+
+    let (location, previous, inode, record_length) = found.ok_or(FsError::NotFound)?;
+    let data = state.cache.write(location.block as u64)?;
+
+    match previous
+    {
+        Some(previous) =>
+            {
+                let previous_length = read_u16(data, previous.offset + 4) as usize;
+
+                write_u16(data, previous.offset + 4, (previous_length + record_length) as u16);
+            },
+
+        None =>
+            {
+                write_u32(data, location.offset, 0);
+            }
+    }
+
+    Ok(inode)
+}
+
+
+
+/// Fill in the first block of a new directory with its . and .. entries.
+pub fn initialize_directory(state: &mut Ext2State,
+                            block: u32,
+                            directory: u32,
+                            parent: u32) -> FsResult<()>
+{
+    let block_size = state.block_size();
+    let dot_length = record_size(1);
+    let type_code = entry_type_code(state, FileType::Directory);
+    let data = state.cache.write(block as u64)?;
+
+    write_entry(data, 0, directory, dot_length, b".", type_code);
+    write_entry(data, dot_length, parent, block_size - dot_length, b"..", type_code);
+
+    Ok(())
+}
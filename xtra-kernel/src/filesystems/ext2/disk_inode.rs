@@ -0,0 +1,315 @@
+// The on disk ext2 inode. Like the superblock the raw bytes are kept so that fields we don't
+// interpret, (the OS specific values, the extended attribute block and so on,) survive being
+// written back.
+//
+// Inodes may be larger than 128 bytes on newer filesystems, the extra space holds data we don't use
+// so only the first 128 bytes are ever read or written.
+
+use crate::filesystems::{ inode::FileType, read_u16, read_u32, write_u16, write_u32 };
+
+
+
+/// The part of the on disk inode that we work with.
+pub const DISK_INODE_SIZE: usize = 128;
+
+/// The number of direct block pointers in an inode.
+pub const DIRECT_BLOCKS: usize = 12;
+
+/// Index of the single indirect block pointer.
+pub const SINGLE_INDIRECT_BLOCK: usize = 12;
+
+/// Index of the double indirect block pointer.
+pub const DOUBLE_INDIRECT_BLOCK: usize = 13;
+
+/// Index of the triple indirect block pointer.
+pub const TRIPLE_INDIRECT_BLOCK: usize = 14;
+
+/// Total number of block pointers in an inode.
+pub const BLOCK_POINTERS: usize = 15;
+
+/// Symbolic links with targets shorter than this are stored directly in the block pointers.
+pub const FAST_SYMBOLIC_LINK_SIZE: usize = BLOCK_POINTERS * 4;
+
+
+
+/// Mask for the file type bits of the mode.
+pub const S_IFMT: u16 = 0xf000;
+
+pub const S_IFSOCK: u16 = 0xc000;
+pub const S_IFLNK: u16 = 0xa000;
+pub const S_IFREG: u16 = 0x8000;
+pub const S_IFBLK: u16 = 0x6000;
+pub const S_IFDIR: u16 = 0x4000;
+pub const S_IFCHR: u16 = 0x2000;
+pub const S_IFIFO: u16 = 0x1000;
+
+/// Mask for the permission bits of the mode, including setuid, setgid and sticky.
+pub const PERMISSION_MASK: u16 = 0o7777;
+
+
+
+// Offsets of the fields within the on disk inode.
+const MODE_OFFSET: usize = 0;
+const UID_OFFSET: usize = 2;
+const SIZE_OFFSET: usize = 4;
+const ATIME_OFFSET: usize = 8;
+const CTIME_OFFSET: usize = 12;
+const MTIME_OFFSET: usize = 16;
+const DTIME_OFFSET: usize = 20;
+const GID_OFFSET: usize = 24;
+const LINKS_COUNT_OFFSET: usize = 26;
+const BLOCKS_OFFSET: usize = 28;
+const BLOCK_OFFSET: usize = 40;
+const SIZE_HIGH_OFFSET: usize = 108;
+const UID_HIGH_OFFSET: usize = 120;
+const GID_HIGH_OFFSET: usize = 122;
+
+
+
+/// Convert the type bits of an inode mode to the VFS file type.
+pub fn file_type_from_mode(mode: u16) -> Option<FileType>
+{
+    match mode & S_IFMT
+    {
+        S_IFSOCK => Some(FileType::Socket),
+        S_IFLNK  => Some(FileType::SymbolicLink),
+        S_IFREG  => Some(FileType::Regular),
+        S_IFBLK  => Some(FileType::BlockDevice),
+        S_IFDIR  => Some(FileType::Directory),
+        S_IFCHR  => Some(FileType::CharacterDevice),
+        S_IFIFO  => Some(FileType::Fifo),
+        _        => None
+    }
+}
+
+
+
+/// Convert a VFS file type to the type bits of an inode mode.
+pub fn mode_from_file_type(file_type: FileType) -> u16
+{
+    match file_type
+    {
+        FileType::Socket          => S_IFSOCK,
+        FileType::SymbolicLink    => S_IFLNK,
+        FileType::Regular         => S_IFREG,
+        FileType::BlockDevice     => S_IFBLK,
+        FileType::Directory       => S_IFDIR,
+        FileType::CharacterDevice => S_IFCHR,
+        FileType::Fifo            => S_IFIFO
+    }
+}
+
+
+
+/// An inode as stored in the inode table.
+#[derive(Clone)]
+pub struct DiskInode
+{
+    raw: [u8; DISK_INODE_SIZE]
+}
+
+
+
+impl DiskInode
+{
+    /// Decode an inode from the start of the given bytes.
+    pub fn new(bytes: &[u8]) -> Self
+    {
+        DiskInode { raw: bytes[..DISK_INODE_SIZE].try_into().unwrap() }
+    }
+
+    /// Create a blank inode of the given type and permissions.
+    pub fn new_empty(file_type: FileType, permissions: u16, time: u32) -> Self
+    {
+        let mut inode = DiskInode { raw: [0; DISK_INODE_SIZE] };
+
+        inode.set_mode(mode_from_file_type(file_type) | (permissions & PERMISSION_MASK));
+        inode.set_access_time(time);
+        inode.set_change_time(time);
+        inode.set_modify_time(time);
+
+        inode
+    }
+
+    /// Encode the inode into the start of the given bytes.
+    pub fn write(&self, bytes: &mut [u8])
+    {
+        bytes[..DISK_INODE_SIZE].copy_from_slice(&self.raw);
+    }
+
+    /// The full mode, type and permission bits.
+    pub fn mode(&self) -> u16
+    {
+        read_u16(&self.raw, MODE_OFFSET)
+    }
+
+    pub fn set_mode(&mut self, mode: u16)
+    {
+        write_u16(&mut self.raw, MODE_OFFSET, mode);
+    }
+
+    /// The type of the inode, `None` if the mode holds an unknown type.
+    pub fn file_type(&self) -> Option<FileType>
+    {
+        file_type_from_mode(self.mode())
+    }
+
+    pub fn is_directory(&self) -> bool
+    {
+        self.mode() & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_regular_file(&self) -> bool
+    {
+        self.mode() & S_IFMT == S_IFREG
+    }
+
+    pub fn is_symbolic_link(&self) -> bool
+    {
+        self.mode() & S_IFMT == S_IFLNK
+    }
+
+    /// Is the inode a symbolic link with the target stored in the block pointers?
+    pub fn is_fast_symbolic_link(&self) -> bool
+    {
+        self.is_symbolic_link() && self.blocks() == 0
+    }
+
+    /// Do the block pointers of this inode refer to data blocks? Fast symbolic links and device
+    /// nodes use the space for other purposes.
+    pub fn has_data_blocks(&self) -> bool
+    {
+        match self.mode() & S_IFMT
+        {
+            S_IFREG | S_IFDIR => true,
+            S_IFLNK           => !self.is_fast_symbolic_link(),
+            _                 => false
+        }
+    }
+
+    pub fn user_id(&self) -> u32
+    {
+        read_u16(&self.raw, UID_OFFSET) as u32 | (read_u16(&self.raw, UID_HIGH_OFFSET) as u32) << 16
+    }
+
+    pub fn set_user_id(&mut self, user_id: u32)
+    {
+        write_u16(&mut self.raw, UID_OFFSET, user_id as u16);
+        write_u16(&mut self.raw, UID_HIGH_OFFSET, (user_id >> 16) as u16);
+    }
+
+    pub fn group_id(&self) -> u32
+    {
+        read_u16(&self.raw, GID_OFFSET) as u32 | (read_u16(&self.raw, GID_HIGH_OFFSET) as u32) << 16
+    }
+
+    pub fn set_group_id(&mut self, group_id: u32)
+    {
+        write_u16(&mut self.raw, GID_OFFSET, group_id as u16);
+        write_u16(&mut self.raw, GID_HIGH_OFFSET, (group_id >> 16) as u16);
+    }
+
+    /// The size of the inode's data. The high half of the size is only used by regular files, for
+    /// directories the field holds the directory ACL instead.
+    pub fn size(&self) -> u64
+    {
+        let low = read_u32(&self.raw, SIZE_OFFSET) as u64;
+
+        if self.is_regular_file()
+        {
+            low | (read_u32(&self.raw, SIZE_HIGH_OFFSET) as u64) << 32
+        }
+        else
+        {
+            low
+        }
+    }
+
+    pub fn set_size(&mut self, size: u64)
+    {
+        write_u32(&mut self.raw, SIZE_OFFSET, size as u32);
+
+        if self.is_regular_file()
+        {
+            write_u32(&mut self.raw, SIZE_HIGH_OFFSET, (size >> 32) as u32);
+        }
+    }
+
+    pub fn access_time(&self) -> u32
+    {
+        read_u32(&self.raw, ATIME_OFFSET)
+    }
+
+    pub fn set_access_time(&mut self, time: u32)
+    {
+        write_u32(&mut self.raw, ATIME_OFFSET, time);
+    }
+
+    pub fn change_time(&self) -> u32
+    {
+        read_u32(&self.raw, CTIME_OFFSET)
+    }
+
+    pub fn set_change_time(&mut self, time: u32)
+    {
+        write_u32(&mut self.raw, CTIME_OFFSET, time);
+    }
+
+    pub fn modify_time(&self) -> u32
+    {
+        read_u32(&self.raw, MTIME_OFFSET)
+    }
+
+    pub fn set_modify_time(&mut self, time: u32)
+    {
+        write_u32(&mut self.raw, MTIME_OFFSET, time);
+    }
+
+    pub fn set_delete_time(&mut self, time: u32)
+    {
+        write_u32(&mut self.raw, DTIME_OFFSET, time);
+    }
+
+    pub fn links_count(&self) -> u16
+    {
+        read_u16(&self.raw, LINKS_COUNT_OFFSET)
+    }
+
+    pub fn set_links_count(&mut self, count: u16)
+    {
+        write_u16(&mut self.raw, LINKS_COUNT_OFFSET, count);
+    }
+
+    /// The number of 512 byte sectors allocated to the inode, including indirect blocks.
+    pub fn blocks(&self) -> u32
+    {
+        read_u32(&self.raw, BLOCKS_OFFSET)
+    }
+
+    pub fn set_blocks(&mut self, blocks: u32)
+    {
+        write_u32(&mut self.raw, BLOCKS_OFFSET, blocks);
+    }
+
+    /// Get one of the 15 block pointers.
+    pub fn block(&self, index: usize) -> u32
+    {
+        read_u32(&self.raw, BLOCK_OFFSET + index * 4)
+    }
+
+    pub fn set_block(&mut self, index: usize, block: u32)
+    {
+        write_u32(&mut self.raw, BLOCK_OFFSET + index * 4, block);
+    }
+
+    /// The raw bytes of the block pointers, where fast symbolic links keep their target.
+    pub fn block_bytes(&self) -> &[u8]
+    {
+        &self.raw[BLOCK_OFFSET..BLOCK_OFFSET + FAST_SYMBOLIC_LINK_SIZE]
+    }
+
+    pub fn block_bytes_mut(&mut self) -> &mut [u8]
+    {
+        &mut self.raw[BLOCK_OFFSET..BLOCK_OFFSET + FAST_SYMBOLIC_LINK_SIZE]
+    }
+}
@@ -0,0 +1,472 @@
+// A consistency check of an ext2 filesystem, run at mount time when asked for or when the
+// filesystem wasn't cleanly unmounted.
+//
+// The check walks every inode in use and every directory to work out which blocks are really in
+// use and how many links each inode really has, then compares that against the bitmaps, the link
+// counts and the free counts recorded on disk. Problems that can be fixed without risking data,
+// the free counts, link counts that are too high or low and blocks in use but marked free, are
+// repaired when the filesystem is writable. Anything else is reported and the filesystem is mounted
+// read-only so that the damage doesn't get worse.
+
+use core::fmt::Arguments;
+
+use alloc::{ vec, vec::Vec };
+
+use crate::filesystems::{ current_time,
+                          ext2::{ block_map::visit_blocks,
+                                  directory::for_each_entry,
+                                  group_descriptor::GROUP_DESCRIPTOR_SIZE,
+                                  superblock::{ EXT2_ERROR_FS,
+                                                EXT2_FEATURE_RO_COMPAT_SPARSE_SUPER,
+                                                EXT2_VALID_FS },
+                                  Ext2State,
+                                  EXT2_ROOT_INODE },
+                          FsResult };
+
+
+
+/// The most problems of a single check that get printed, a badly damaged filesystem could
+/// otherwise flood the console.
+const MAX_REPORTED_PROBLEMS: usize = 20;
+
+/// The largest block size we support, used to size the bitmap buffer.
+const MAX_BLOCK_SIZE: usize = 4096;
+
+
+
+/// Tally of the problems found by the check.
+#[derive(Default)]
+struct Report
+{
+    problems: usize,   // Total problems found.
+    unrepaired: usize  // Problems that couldn't or weren't repaired.
+}
+
+
+
+impl Report
+{
+    /// Record and print a problem.
+    fn problem(&mut self, repaired: bool, message: Arguments)
+    {
+        self.problems += 1;
+
+        if !repaired
+        {
+            self.unrepaired += 1;
+        }
+
+        if self.problems <= MAX_REPORTED_PROBLEMS
+        {
+            println!("    {}{}", message, if repaired { " (repaired)" } else { "" });
+        }
+    }
+}
+
+
+
+/// A simple bitmap used to track what the check finds in use.
+struct Bitmap
+{
+    bits: Vec<u8>
+}
+
+
+
+impl Bitmap
+{
+    fn new(size: usize) -> Self
+    {
+        Bitmap { bits: vec![0; size.div_ceil(8)] }
+    }
+
+    fn get(&self, index: usize) -> bool
+    {
+        self.bits[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    fn set(&mut self, index: usize)
+    {
+        self.bits[index / 8] |= 1 << (index % 8);
+    }
+}
+
+
+
+/// Test a bit in an on disk bitmap block.
+fn bit_is_set(bitmap: &[u8], bit: usize) -> bool
+{
+    bitmap[bit / 8] & (1 << (bit % 8)) != 0
+}
+
+
+
+/// Is x a power of the base?
+fn is_power_of(mut x: u32, base: u32) -> bool
+{
+    while x > 1 && x.is_multiple_of(base)
+    {
+        x /= base;
+    }
+
+    x == 1
+}
+
+
+
+/// Does the group hold a copy of the superblock and group descriptor table? With the sparse
+/// superblock feature only groups 0, 1 and powers of 3, 5 and 7 do.
+fn has_superblock_copy(state: &Ext2State, group: u32) -> bool
+{
+    if state.superblock.feature_ro_compat & EXT2_FEATURE_RO_COMPAT_SPARSE_SUPER == 0
+    {
+        return true;
+    }
+
+    group <= 1 || is_power_of(group, 3) || is_power_of(group, 5) || is_power_of(group, 7)
+}
+
+
+
+/// Check the filesystem, repairing what we safely can when it's writable.
+pub fn check_filesystem(state: &mut Ext2State) -> FsResult<()>
+{
+    let repair = !state.read_only;
+    let mut report = Report::default();
+
+    let block_size = state.block_size();
+    let first_data_block = state.superblock.first_data_block;
+    let blocks_count = state.superblock.blocks_count;
+    let blocks_per_group = state.superblock.blocks_per_group;
+    let inodes_count = state.superblock.inodes_count;
+    let inodes_per_group = state.superblock.inodes_per_group;
+    let group_count = state.groups.len() as u32;
+
+    // Mark the blocks used by the filesystem's own structures.
+    let mut used_blocks = Bitmap::new(blocks_count as usize);
+    let table_blocks = (group_count as usize * GROUP_DESCRIPTOR_SIZE).div_ceil(block_size) as u32;
+    let inode_table_blocks =
+        (inodes_per_group as usize * state.superblock.inode_size as usize).div_ceil(block_size);
+
+    for group in 0..group_count
+    {
+        let descriptor = state.groups[group as usize];
+
+        if has_superblock_copy(state, group)
+        {
+            let first = first_data_block + group * blocks_per_group;
+
+            for block in first..=first + table_blocks
+            {
+                used_blocks.set(block as usize);
+            }
+        }
+
+        for block in [ descriptor.block_bitmap, descriptor.inode_bitmap ]
+        {
+            if block >= blocks_count
+            {
+                report.problem(false, format_args!("Group {} has an invalid bitmap.", group));
+                continue;
+            }
+
+            used_blocks.set(block as usize);
+        }
+
+        for index in 0..inode_table_blocks
+        {
+            let block = descriptor.inode_table as usize + index;
+
+            if block >= blocks_count as usize
+            {
+                report.problem(false, format_args!("Group {} has an invalid inode table.", group));
+                break;
+            }
+
+            used_blocks.set(block);
+        }
+    }
+
+    if report.unrepaired != 0
+    {
+        state.read_only = true;
+        state.superblock.state |= EXT2_ERROR_FS;
+
+        println!("    The filesystem layout is damaged, mounting read-only.");
+        return Ok(());
+    }
+
+    // Pass 1: walk every inode in use, marking the blocks it refers to.
+    let mut used_inodes = Bitmap::new(inodes_count as usize + 1);
+    let mut directories = Vec::new();
+    let mut directories_per_group = vec![0u16; group_count as usize];
+
+    for number in 1..=inodes_count
+    {
+        let group = (number - 1) / inodes_per_group;
+        let bit = ((number - 1) % inodes_per_group) as usize;
+        let bitmap_block = state.groups[group as usize].inode_bitmap as u64;
+
+        if !bit_is_set(state.cache.read(bitmap_block)?, bit)
+        {
+            continue;
+        }
+
+        used_inodes.set(number as usize);
+
+        let inode = state.read_inode(number)?;
+
+        if inode.mode() == 0
+        {
+            continue;
+        }
+
+        if inode.is_directory()
+        {
+            directories.push(number);
+            directories_per_group[group as usize] += 1;
+        }
+
+        if !inode.has_data_blocks()
+        {
+            continue;
+        }
+
+        visit_blocks(state, &inode, &mut |block|
+            {
+                if    block < first_data_block
+                   || block >= blocks_count
+                {
+                    report.problem(false,
+                                   format_args!("Inode {} refers to invalid block {}.",
+                                                number,
+                                                block));
+                    return false;
+                }
+
+                if used_blocks.get(block as usize)
+                {
+                    report.problem(false,
+                                   format_args!("Inode {} refers to block {} which is already in \
+                                                 use.",
+                                                number,
+                                                block));
+                    return false;
+                }
+
+                used_blocks.set(block as usize);
+                true
+            })?;
+    }
+
+    // Pass 2: walk the directories, counting the links to each inode.
+    let mut link_counts = vec![0u16; inodes_count as usize + 1];
+
+    for &directory in &directories
+    {
+        let inode = state.read_inode(directory)?;
+
+        let result = for_each_entry::<()>(state, &inode, |_, entry|
+            {
+                if entry.inode == 0
+                {
+                    return None;
+                }
+
+                if    entry.inode > inodes_count
+                   || !used_inodes.get(entry.inode as usize)
+                {
+                    report.problem(false,
+                                   format_args!("Directory {} has an entry for unused inode {}.",
+                                                directory,
+                                                entry.inode));
+                }
+                else
+                {
+                    link_counts[entry.inode as usize] += 1;
+                }
+
+                None
+            });
+
+        if let Err(error) = result
+        {
+            report.problem(false, format_args!("Directory {}: {}", directory, error));
+        }
+    }
+
+    // Pass 3: compare the link counts. Inodes with no links at all are reported but left alone so
+    // that nothing gets thrown away.
+    let first_inode = state.superblock.first_inode;
+
+    for number in 1..=inodes_count
+    {
+        if    !used_inodes.get(number as usize)
+           || (number < first_inode && number != EXT2_ROOT_INODE)
+        {
+            continue;
+        }
+
+        let mut inode = state.read_inode(number)?;
+        let counted = link_counts[number as usize];
+
+        if inode.mode() == 0
+        {
+            report.problem(false, format_args!("Inode {} is marked in use but is empty.", number));
+        }
+        else if counted == 0
+        {
+            report.problem(false, format_args!("Inode {} is not in any directory.", number));
+        }
+        else if counted != inode.links_count()
+        {
+            report.problem(repair,
+                           format_args!("Inode {} has link count {}, should be {}.",
+                                        number,
+                                        inode.links_count(),
+                                        counted));
+
+            if repair
+            {
+                inode.set_links_count(counted);
+                state.write_inode(number, &inode)?;
+            }
+        }
+    }
+
+    // Pass 4: compare the bitmaps and free counts against what we found.
+    let mut total_free_blocks = 0;
+    let mut total_free_inodes = 0;
+
+    for group in 0..group_count
+    {
+        let bitmap_block = state.groups[group as usize].block_bitmap as u64;
+        let first = first_data_block + group * blocks_per_group;
+        let mut bitmap = [0u8; MAX_BLOCK_SIZE];
+        let mut bitmap_changed = false;
+        let mut free_blocks = 0;
+        let mut unaccounted = 0;
+
+        state.cache.read_into(bitmap_block, 0, &mut bitmap[..block_size])?;
+
+        for bit in 0..state.superblock.blocks_in_group(group) as usize
+        {
+            let on_disk = bit_is_set(&bitmap, bit);
+            let in_use = used_blocks.get(first as usize + bit);
+
+            if in_use && !on_disk
+            {
+                report.problem(repair,
+                               format_args!("Block {} is in use but marked free.",
+                                            first as usize + bit));
+
+                if repair
+                {
+                    bitmap[bit / 8] |= 1 << (bit % 8);
+                    bitmap_changed = true;
+                }
+                else
+                {
+                    free_blocks += 1;
+                }
+            }
+            else if !on_disk
+            {
+                free_blocks += 1;
+            }
+            else if !in_use
+            {
+                unaccounted += 1;
+            }
+        }
+
+        if bitmap_changed
+        {
+            state.cache.write_from(bitmap_block, 0, &bitmap[..block_size])?;
+        }
+
+        // Blocks marked used that nothing refers to only waste space, leave them be.
+        if unaccounted != 0
+        {
+            println!("    Group {} has {} allocated blocks that are not in use.",
+                     group,
+                     unaccounted);
+        }
+
+        let free_inodes = (0..inodes_per_group as usize)
+            .filter(|&bit|
+                {
+                    let number = group as usize * inodes_per_group as usize + bit + 1;
+
+                    number <= inodes_count as usize && !used_inodes.get(number)
+                })
+            .count() as u32;
+
+        let descriptor = state.groups[group as usize];
+
+        if    descriptor.free_blocks_count as u32 != free_blocks
+           || descriptor.free_inodes_count as u32 != free_inodes
+           || descriptor.used_dirs_count != directories_per_group[group as usize]
+        {
+            report.problem(repair, format_args!("Group {} has wrong summary counts.", group));
+
+            if repair
+            {
+                let descriptor = &mut state.groups[group as usize];
+
+                descriptor.free_blocks_count = free_blocks as u16;
+                descriptor.free_inodes_count = free_inodes as u16;
+                descriptor.used_dirs_count = directories_per_group[group as usize];
+
+                state.write_group(group)?;
+            }
+        }
+
+        total_free_blocks += free_blocks;
+        total_free_inodes += free_inodes;
+    }
+
+    if    state.superblock.free_blocks_count != total_free_blocks
+       || state.superblock.free_inodes_count != total_free_inodes
+    {
+        report.problem(repair, format_args!("Superblock has wrong free counts."));
+
+        if repair
+        {
+            state.superblock.free_blocks_count = total_free_blocks;
+            state.superblock.free_inodes_count = total_free_inodes;
+        }
+    }
+
+    if report.problems > MAX_REPORTED_PROBLEMS
+    {
+        println!("    ...and {} more.", report.problems - MAX_REPORTED_PROBLEMS);
+    }
+
+    if report.unrepaired == 0
+    {
+        println!("    Filesystem is clean.");
+
+        if repair
+        {
+            state.superblock.state = EXT2_VALID_FS;
+            state.superblock.mount_count = 0;
+            state.superblock.last_check_time = current_time() as u32;
+            state.sync()?;
+        }
+    }
+    else
+    {
+        println!("    {} problems could not be repaired, mounting read-only.", report.unrepaired);
+
+        if repair
+        {
+            state.superblock.state |= EXT2_ERROR_FS;
+            state.sync()?;
+        }
+
+        state.read_only = true;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+// The ext2 block group descriptors. The filesystem is split into groups of blocks, each with its
+// own block and inode bitmaps and slice of the inode table. The descriptor table following the
+// superblock tells us where each group keeps those structures.
+
+use crate::filesystems::{ read_u16, read_u32, write_u16, write_u32 };
+
+
+
+/// The size of a single on disk group descriptor.
+pub const GROUP_DESCRIPTOR_SIZE: usize = 32;
+
+
+
+/// A decoded block group descriptor.
+#[derive(Clone, Copy)]
+pub struct GroupDescriptor
+{
+    pub block_bitmap: u32,       // Block holding the group's block usage bitmap.
+    pub inode_bitmap: u32,       // Block holding the group's inode usage bitmap.
+    pub inode_table: u32,        // First block of the group's inode table.
+    pub free_blocks_count: u16,  // Unallocated blocks in the group.
+    pub free_inodes_count: u16,  // Unallocated inodes in the group.
+    pub used_dirs_count: u16     // Number of directories in the group.
+}
+
+
+
+impl GroupDescriptor
+{
+    /// Decode a group descriptor from its on disk form.
+    pub fn new(bytes: &[u8]) -> Self
+    {
+        GroupDescriptor
+            {
+                block_bitmap: read_u32(bytes, 0),
+                inode_bitmap: read_u32(bytes, 4),
+                inode_table: read_u32(bytes, 8),
+                free_blocks_count: read_u16(bytes, 12),
+                free_inodes_count: read_u16(bytes, 14),
+                used_dirs_count: read_u16(bytes, 16)
+            }
+    }
+
+    /// Encode the descriptor into its on disk form. Only the fields that we change are written so
+    /// the reserved bytes are left as they were.
+    pub fn write(&self, bytes: &mut [u8])
+    {
+        write_u32(bytes, 0, self.block_bitmap);
+        write_u32(bytes, 4, self.inode_bitmap);
+        write_u32(bytes, 8, self.inode_table);
+        write_u16(bytes, 12, self.free_blocks_count);
+        write_u16(bytes, 14, self.free_inodes_count);
+        write_u16(bytes, 16, self.used_dirs_count);
+    }
+}
@@ -0,0 +1,688 @@
+// The VFS view of an ext2 inode. The object only holds the inode number, every operation locks the
+// filesystem and reads the current on disk inode through the block cache, so there is never a stale
+// copy of an inode to keep in sync.
+
+use alloc::{ string::String, sync::Arc, vec::Vec };
+
+use crate::filesystems::{ current_time,
+                          ext2::{ allocator::{ allocate_inode, free_inode },
+                                  block_map::{ free_blocks_from,
+                                               lookup_block,
+                                               map_block,
+                                               max_blocks },
+                                  directory::{ add_entry,
+                                               file_type_of_code,
+                                               find_entry,
+                                               initialize_directory,
+                                               is_empty,
+                                               list_entries,
+                                               remove_entry },
+                                  disk_inode::{ DiskInode,
+                                                FAST_SYMBOLIC_LINK_SIZE,
+                                                PERMISSION_MASK,
+                                                S_IFMT },
+                                  superblock::EXT2_FEATURE_RO_COMPAT_LARGE_FILE,
+                                  Ext2Filesystem,
+                                  Ext2State },
+                          inode::{ DirectoryEntry, FileType, Inode, Metadata },
+                          path::MAX_NAME_LENGTH,
+                          FsError,
+                          FsResult };
+
+
+
+/// Files on filesystems without the large file feature are limited to 2GB.
+const SMALL_FILE_LIMIT: u64 = (1 << 31) - 1;
+
+/// The permissions given to new symbolic links.
+const SYMBOLIC_LINK_MODE: u16 = 0o777;
+
+
+
+/// An inode on a mounted ext2 filesystem.
+pub struct Ext2Inode
+{
+    filesystem: Arc<Ext2Filesystem>,  // The filesystem the inode lives on.
+    number: u32                       // The inode's number.
+}
+
+
+
+impl Ext2Inode
+{
+    /// Create the VFS object for an inode on the filesystem.
+    pub fn new(filesystem: Arc<Ext2Filesystem>, number: u32) -> Self
+    {
+        Ext2Inode { filesystem, number }
+    }
+
+    /// Read the inode and make sure that it is a directory.
+    fn read_directory_inode(&self, state: &mut Ext2State) -> FsResult<DiskInode>
+    {
+        let inode = state.read_inode(self.number)?;
+
+        if !inode.is_directory()
+        {
+            return Err(FsError::NotADirectory);
+        }
+
+        Ok(inode)
+    }
+
+    /// Read the inode for a data operation, making sure it is a regular file.
+    fn read_file_inode(&self, state: &mut Ext2State) -> FsResult<DiskInode>
+    {
+        let inode = state.read_inode(self.number)?;
+
+        if inode.is_directory()
+        {
+            return Err(FsError::IsADirectory);
+        }
+
+        if !inode.is_regular_file()
+        {
+            return Err(FsError::InvalidArgument);
+        }
+
+        Ok(inode)
+    }
+
+    /// Apply a change to the inode's metadata and write it back.
+    fn update(&self, change: impl FnOnce(&mut DiskInode)) -> FsResult<()>
+    {
+        let mut state = self.filesystem.lock();
+
+        state.check_writable()?;
+
+        let mut inode = state.read_inode(self.number)?;
+
+        change(&mut inode);
+        inode.set_change_time(current_time() as u32);
+
+        state.write_inode(self.number, &inode)
+    }
+}
+
+
+
+/// The largest file the filesystem can hold.
+fn max_file_size(state: &Ext2State) -> u64
+{
+    let addressable = max_blocks(state) * state.block_size() as u64;
+
+    if state.superblock.feature_ro_compat & EXT2_FEATURE_RO_COMPAT_LARGE_FILE != 0
+    {
+        addressable
+    }
+    else
+    {
+        addressable.min(SMALL_FILE_LIMIT)
+    }
+}
+
+
+
+/// Read from an inode's data blocks, holes read back as zeros.
+fn read_data(state: &mut Ext2State,
+             inode: &DiskInode,
+             offset: u64,
+             buffer: &mut [u8]) -> FsResult<usize>
+{
+    let size = inode.size();
+
+    if offset >= size
+    {
+        return Ok(0);
+    }
+
+    let block_size = state.block_size() as u64;
+    let length = (buffer.len() as u64).min(size - offset) as usize;
+    let mut done = 0;
+
+    while done < length
+    {
+        let position = offset + done as u64;
+        let block_offset = (position % block_size) as usize;
+        let count = (block_size as usize - block_offset).min(length - done);
+        let block = lookup_block(state, inode, position / block_size)?;
+        let destination = &mut buffer[done..done + count];
+
+        if block == 0
+        {
+            destination.fill(0);
+        }
+        else
+        {
+            state.cache.read_into(block as u64, block_offset, destination)?;
+        }
+
+        done += count;
+    }
+
+    Ok(length)
+}
+
+
+
+/// Write to an inode's data blocks, allocating blocks as needed and growing the recorded size.
+fn write_data(state: &mut Ext2State,
+              number: u32,
+              inode: &mut DiskInode,
+              offset: u64,
+              buffer: &[u8]) -> FsResult<usize>
+{
+    let end = offset.checked_add(buffer.len() as u64).ok_or(FsError::FileTooLarge)?;
+
+    if end > max_file_size(state)
+    {
+        return Err(FsError::FileTooLarge);
+    }
+
+    let block_size = state.block_size() as u64;
+    let mut done = 0;
+
+    while done < buffer.len()
+    {
+        let position = offset + done as u64;
+        let block_offset = (position % block_size) as usize;
+        let count = (block_size as usize - block_offset).min(buffer.len() - done);
+
+        let block = match map_block(state, number, inode, position / block_size)
+            {
+                Ok(block) => block,
+
+                // Report a partial write if we ran out of space part way through.
+                Err(FsError::NoSpace) if done > 0 => break,
+                Err(error) => return Err(error)
+            };
+
+        state.cache.write_from(block as u64, block_offset, &buffer[done..done + count])?;
+        done += count;
+    }
+
+    let end = offset + done as u64;
+
+    if end > inode.size()
+    {
+        inode.set_size(end);
+    }
+
+    Ok(done)
+}
+
+
+
+/// Release all of an inode's storage and the inode itself once its last link is gone.
+fn release_inode(state: &mut Ext2State, number: u32, inode: &mut DiskInode) -> FsResult<()>
+{
+    if inode.has_data_blocks()
+    {
+        free_blocks_from(state, inode, 0)?;
+    }
+
+    inode.set_size(0);
+    inode.set_delete_time(current_time() as u32);
+    state.write_inode(number, inode)?;
+
+    free_inode(state, number, inode.is_directory())
+}
+
+
+
+/// Allocate and initialize a new inode, then link it into the given directory.
+fn create_inode(state: &mut Ext2State,
+                directory_number: u32,
+                directory: &mut DiskInode,
+                name: &str,
+                file_type: FileType,
+                mode: u16,
+                initialize: impl FnOnce(&mut Ext2State, u32, &mut DiskInode) -> FsResult<()>)
+    -> FsResult<u32>
+{
+    let is_directory = file_type == FileType::Directory;
+    let group = state.group_of_inode(directory_number);
+    let number = allocate_inode(state, group, is_directory)?;
+    let now = current_time() as u32;
+
+    let mut inode = DiskInode::new_empty(file_type, mode, now);
+
+    inode.set_user_id(directory.user_id());
+    inode.set_group_id(directory.group_id());
+    inode.set_links_count(if is_directory { 2 } else { 1 });
+
+    // Set up the contents of the new inode, then add it to the directory. If anything fails along
+    // the way give back whatever was allocated.
+    let result = initialize(state, number, &mut inode)
+        .and_then(|_| state.write_inode(number, &inode))
+        .and_then(|_| add_entry(state, directory_number, directory, name, number, file_type));
+
+    if let Err(error) = result
+    {
+        let _ = release_inode(state, number, &mut inode);
+        return Err(error);
+    }
+
+    if is_directory
+    {
+        directory.set_links_count(directory.links_count() + 1);
+    }
+
+    directory.set_modify_time(now);
+    directory.set_change_time(now);
+    state.write_inode(directory_number, directory)?;
+
+    Ok(number)
+}
+
+
+
+/// Give a new directory its first block holding the . and .. entries.
+fn initialize_directory_inode(state: &mut Ext2State,
+                              number: u32,
+                              inode: &mut DiskInode,
+                              parent: u32) -> FsResult<()>
+{
+    let block = map_block(state, number, inode, 0)?;
+
+    initialize_directory(state, block, number, parent)?;
+    inode.set_size(state.block_size() as u64);
+
+    Ok(())
+}
+
+
+
+/// Store the target of a new symbolic link. Short targets are stored in place of the block
+/// pointers, longer ones get a data block of their own.
+fn store_link_target(state: &mut Ext2State,
+                     number: u32,
+                     inode: &mut DiskInode,
+                     target: &str) -> FsResult<()>
+{
+    if target.len() < FAST_SYMBOLIC_LINK_SIZE
+    {
+        inode.block_bytes_mut()[..target.len()].copy_from_slice(target.as_bytes());
+    }
+    else
+    {
+        let block = map_block(state, number, inode, 0)?;
+
+        state.cache.write_from(block as u64, 0, target.as_bytes())?;
+    }
+
+    inode.set_size(target.len() as u64);
+
+    Ok(())
+}
+
+
+
+/// Check a name for a new directory entry.
+fn check_new_name(state: &mut Ext2State, directory: &DiskInode, name: &str) -> FsResult<()>
+{
+    if    name.is_empty()
+       || name == "."
+       || name == ".."
+       || name.contains('/')
+    {
+        return Err(FsError::InvalidPath);
+    }
+
+    if name.len() > MAX_NAME_LENGTH
+    {
+        return Err(FsError::NameTooLong);
+    }
+
+    if find_entry(state, directory, name)?.is_some()
+    {
+        return Err(FsError::AlreadyExists);
+    }
+
+    Ok(())
+}
+
+
+
+impl Inode for Ext2Inode
+{
+    fn metadata(&self) -> FsResult<Metadata>
+    {
+        let mut state = self.filesystem.lock();
+        let inode = state.read_inode(self.number)?;
+        let file_type = inode.file_type()
+                             .ok_or(FsError::Corrupted("Inode has an unknown file type."))?;
+
+        Ok(Metadata
+            {
+                inode_number: self.number as u64,
+                file_type,
+                mode: inode.mode() & PERMISSION_MASK,
+                user_id: inode.user_id(),
+                group_id: inode.group_id(),
+                size: inode.size(),
+                link_count: inode.links_count() as u32,
+                access_time: inode.access_time() as u64,
+                modify_time: inode.modify_time() as u64,
+                change_time: inode.change_time() as u64,
+                block_size: state.block_size() as u32,
+                blocks: inode.blocks() as u64
+            })
+    }
+
+    fn set_mode(&self, mode: u16) -> FsResult<()>
+    {
+        self.update(|inode|
+            {
+                inode.set_mode((inode.mode() & S_IFMT) | (mode & PERMISSION_MASK));
+            })
+    }
+
+    fn set_owner(&self, user_id: u32, group_id: u32) -> FsResult<()>
+    {
+        self.update(|inode|
+            {
+                inode.set_user_id(user_id);
+                inode.set_group_id(group_id);
+            })
+    }
+
+    fn set_times(&self, access_time: u64, modify_time: u64) -> FsResult<()>
+    {
+        self.update(|inode|
+            {
+                inode.set_access_time(access_time as u32);
+                inode.set_modify_time(modify_time as u32);
+            })
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> FsResult<usize>
+    {
+        let mut state = self.filesystem.lock();
+        let inode = self.read_file_inode(&mut state)?;
+
+        read_data(&mut state, &inode, offset, buffer)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> FsResult<usize>
+    {
+        let mut state = self.filesystem.lock();
+
+        state.check_writable()?;
+
+        let mut inode = self.read_file_inode(&mut state)?;
+        let written = write_data(&mut state, self.number, &mut inode, offset, buffer);
+
+        // Blocks may have been allocated even if the write failed part way, so always save the
+        // inode.
+        let now = current_time() as u32;
+
+        inode.set_modify_time(now);
+        inode.set_change_time(now);
+        state.write_inode(self.number, &inode)?;
+
+        written
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()>
+    {
+        let mut state = self.filesystem.lock();
+
+        state.check_writable()?;
+
+        let mut inode = self.read_file_inode(&mut state)?;
+
+        if size > max_file_size(&state)
+        {
+            return Err(FsError::FileTooLarge);
+        }
+
+        let block_size = state.block_size() as u64;
+
+        if size < inode.size()
+        {
+            free_blocks_from(&mut state, &mut inode, size.div_ceil(block_size))?;
+
+            // Clear the tail of the new last block so that growing the file again reads back zeros
+            // instead of the old data.
+            let tail = (size % block_size) as usize;
+
+            if tail != 0
+            {
+                let block = lookup_block(&mut state, &inode, size / block_size)?;
+
+                if block != 0
+                {
+                    state.cache.write(block as u64)?[tail..].fill(0);
+                }
+            }
+        }
+
+        let now = current_time() as u32;
+
+        inode.set_size(size);
+        inode.set_modify_time(now);
+        inode.set_change_time(now);
+
+        state.write_inode(self.number, &inode)
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>>
+    {
+        let mut state = self.filesystem.lock();
+        let directory = self.read_directory_inode(&mut state)?;
+        let (number, _) = find_entry(&mut state, &directory, name)?.ok_or(FsError::NotFound)?;
+
+        drop(state);
+
+        Ok(self.filesystem.inode(number))
+    }
+
+    fn read_directory(&self) -> FsResult<Vec<DirectoryEntry>>
+    {
+        let mut state = self.filesystem.lock();
+        let directory = self.read_directory_inode(&mut state)?;
+        let mut entries = Vec::new();
+
+        for (name, number, type_code) in list_entries(&mut state, &directory)?
+        {
+            if    name == "."
+               || name == ".."
+            {
+                continue;
+            }
+
+            // Filesystems without the filetype feature don't record the type in the entry, so we
+            // have to go look at the inode.
+            let file_type = match file_type_of_code(type_code)
+                {
+                    Some(file_type) => file_type,
+                    None            =>
+                        {
+                            state.read_inode(number)?
+                                 .file_type()
+                                 .ok_or(FsError::Corrupted("Inode has an unknown file type."))?
+                        }
+                };
+
+            entries.push(DirectoryEntry { name, inode_number: number as u64, file_type });
+        }
+
+        Ok(entries)
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u16) -> FsResult<Arc<dyn Inode>>
+    {
+        let mut state = self.filesystem.lock();
+
+        state.check_writable()?;
+
+        let mut directory = self.read_directory_inode(&mut state)?;
+
+        check_new_name(&mut state, &directory, name)?;
+
+        let parent = self.number;
+
+        let number = match file_type
+            {
+                FileType::Regular =>
+                    {
+                        create_inode(&mut state,
+                                     parent,
+                                     &mut directory,
+                                     name,
+                                     file_type,
+                                     mode,
+                                     |_, _, _| Ok(()))?
+                    },
+
+                FileType::Directory =>
+                    {
+                        create_inode(&mut state,
+                                     parent,
+                                     &mut directory,
+                                     name,
+                                     file_type,
+                                     mode,
+                                     |state, number, inode|
+                                     {
+                                         initialize_directory_inode(state, number, inode, parent)
+                                     })?
+                    },
+
+                _ => return Err(FsError::Unsupported)
+            };
+
+        drop(state);
+
+        Ok(self.filesystem.inode(number))
+    }
+
+    fn symbolic_link(&self, name: &str, target: &str) -> FsResult<Arc<dyn Inode>>
+    {
+        let mut state = self.filesystem.lock();
+
+        state.check_writable()?;
+
+        let mut directory = self.read_directory_inode(&mut state)?;
+
+        check_new_name(&mut state, &directory, name)?;
+
+        if    target.is_empty()
+           || target.len() >= state.block_size()
+        {
+            return Err(FsError::NameTooLong);
+        }
+
+        let number = create_inode(&mut state,
+                                  self.number,
+                                  &mut directory,
+                                  name,
+                                  FileType::SymbolicLink,
+                                  SYMBOLIC_LINK_MODE,
+                                  |state, number, inode|
+                                  {
+                                      store_link_target(state, number, inode, target)
+                                  })?;
+
+        drop(state);
+
+        Ok(self.filesystem.inode(number))
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()>
+    {
+        if    name == "."
+           || name == ".."
+        {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let mut state = self.filesystem.lock();
+
+        state.check_writable()?;
+
+        let mut directory = self.read_directory_inode(&mut state)?;
+        let (number, _) = find_entry(&mut state, &directory, name)?.ok_or(FsError::NotFound)?;
+        let mut inode = state.read_inode(number)?;
+
+        if    inode.is_directory()
+           && !is_empty(&mut state, &inode)?
+        {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+
+        remove_entry(&mut state, &directory, name)?;
+
+        let now = current_time() as u32;
+
+        // A directory loses both its entry in the parent and its own . entry, and the parent loses
+        // the child's .. entry.
+        if inode.is_directory()
+        {
+            inode.set_links_count(0);
+            directory.set_links_count(directory.links_count().saturating_sub(1));
+        }
+        else
+        {
+            inode.set_links_count(inode.links_count().saturating_sub(1));
+        }
+
+        directory.set_modify_time(now);
+        directory.set_change_time(now);
+        state.write_inode(self.number, &directory)?;
+
+        inode.set_change_time(now);
+
+        if inode.links_count() == 0
+        {
+            release_inode(&mut state, number, &mut inode)
+        }
+        else
+        {
+            state.write_inode(number, &inode)
+        }
+    }
+
+    fn read_link(&self) -> FsResult<String>
+    {
+        let mut state = self.filesystem.lock();
+        let inode = state.read_inode(self.number)?;
+
+        if !inode.is_symbolic_link()
+        {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let size = inode.size() as usize;
+
+        if inode.is_fast_symbolic_link()
+        {
+            let target = inode.block_bytes()
+                              .get(..size)
+                              .ok_or(FsError::Corrupted("Symbolic link target too long."))?;
+
+            return Ok(String::from_utf8_lossy(target).into());
+        }
+
+        if size >= state.block_size()
+        {
+            return Err(FsError::Corrupted("Symbolic link target too long."));
+        }
+
+        let block = lookup_block(&mut state, &inode, 0)?;
+
+        if block == 0
+        {
+            return Err(FsError::Corrupted("Symbolic link has no target block."));
+        }
+
+        let data = state.cache.read(block as u64)?;
+
+        Ok(String::from_utf8_lossy(&data[..size]).into())
+    }
+
+    fn sync(&self) -> FsResult<()>
+    {
+        self.filesystem.lock().sync()
+    }
+}
@@ -0,0 +1,378 @@
+// Driver for the second extended filesystem, ext2, the native filesystem of the xtra system
+// partition.
+//
+// All of the on disk structures are reached through a write back block cache. The mutable state of
+// a mounted filesystem, the cache, superblock and group descriptors, lives behind a single lock
+// that every operation takes for its whole duration. This keeps the driver simple at the cost of
+// concurrency, which is fine for now as the kernel only has a handful of filesystem users.
+
+use alloc::{ string::String, sync::{ Arc, Weak }, vec, vec::Vec };
+
+use crate::{ devices::block_devices::{ BlockDevice, SECTOR_SIZE },
+             filesystems::{ block_cache::BlockCache,
+                            current_time,
+                            ext2::{ disk_inode::DiskInode,
+                                    group_descriptor::{ GroupDescriptor, GROUP_DESCRIPTOR_SIZE },
+                                    inode::Ext2Inode,
+                                    superblock::{ Superblock,
+                                                  EXT2_ERROR_FS,
+                                                  EXT2_SUPPORTED_INCOMPAT,
+                                                  EXT2_VALID_FS,
+                                                  SUPERBLOCK_OFFSET,
+                                                  SUPERBLOCK_SIZE } },
+                            inode::Inode,
+                            mount::MountOptions,
+                            Filesystem,
+                            FilesystemDriver,
+                            FilesystemDriverRegistry,
+                            FilesystemStatistics,
+                            FsError,
+                            FsResult },
+             locking::spin_mutex::{ SpinMutex, SpinMutexGuard } };
+
+
+
+/// The superblock and its related constants.
+pub mod superblock;
+
+/// The block group descriptor table.
+pub mod group_descriptor;
+
+/// The on disk inode structure.
+pub mod disk_inode;
+
+/// Mapping of file blocks to filesystem blocks through the direct and indirect block pointers.
+pub mod block_map;
+
+/// Allocation of blocks and inodes from the group bitmaps.
+pub mod allocator;
+
+/// Reading and modifying directory entries.
+pub mod directory;
+
+/// The VFS inode implementation for ext2.
+pub mod inode;
+
+/// The consistency check that can be run at mount time.
+pub mod fsck;
+
+
+
+/// The inode number of the root directory.
+pub const EXT2_ROOT_INODE: u32 = 2;
+
+/// The number of blocks kept in the block cache of each mounted filesystem.
+const CACHE_BLOCKS: usize = 64;
+
+
+
+/// The mutable state of a mounted ext2 filesystem, protected by the filesystem's lock.
+pub struct Ext2State
+{
+    /// Cache of the filesystem's blocks.
+    pub cache: BlockCache,
+
+    /// The decoded superblock.
+    pub superblock: Superblock,
+
+    /// The decoded group descriptors, one per block group.
+    pub groups: Vec<GroupDescriptor>,
+
+    /// Is the filesystem mounted read-only?
+    pub read_only: bool
+}
+
+
+
+impl Ext2State
+{
+    /// The size of a filesystem block in bytes.
+    pub fn block_size(&self) -> usize
+    {
+        self.superblock.block_size
+    }
+
+    /// Fail with `FsError::ReadOnly` if the filesystem can't be changed.
+    pub fn check_writable(&self) -> FsResult<()>
+    {
+        if self.read_only
+        {
+            return Err(FsError::ReadOnly);
+        }
+
+        Ok(())
+    }
+
+    /// The block group that an inode belongs to.
+    pub fn group_of_inode(&self, number: u32) -> u32
+    {
+        (number - 1) / self.superblock.inodes_per_group
+    }
+
+    /// Find the block and offset within it of an inode's on disk structure.
+    fn inode_location(&self, number: u32) -> FsResult<(u64, usize)>
+    {
+        if    number == 0
+           || number > self.superblock.inodes_count
+        {
+            return Err(FsError::Corrupted("Inode number out of range."));
+        }
+
+        let group = self.group_of_inode(number) as usize;
+        let index = ((number - 1) % self.superblock.inodes_per_group) as usize;
+        let offset = index * self.superblock.inode_size as usize;
+        let block_size = self.block_size();
+
+        let block = self.groups[group].inode_table as u64 + (offset / block_size) as u64;
+
+        Ok((block, offset % block_size))
+    }
+
+    /// Read an inode from the inode table.
+    pub fn read_inode(&mut self, number: u32) -> FsResult<DiskInode>
+    {
+        let (block, offset) = self.inode_location(number)?;
+        let data = self.cache.read(block)?;
+
+        Ok(DiskInode::new(&data[offset..]))
+    }
+
+    /// Write an inode back to the inode table.
+    pub fn write_inode(&mut self, number: u32, inode: &DiskInode) -> FsResult<()>
+    {
+        self.check_writable()?;
+
+        let (block, offset) = self.inode_location(number)?;
+        let data = self.cache.write(block)?;
+
+        inode.write(&mut data[offset..]);
+
+        Ok(())
+    }
+
+    /// Write a group's descriptor back into the descriptor table.
+    pub fn write_group(&mut self, group: u32) -> FsResult<()>
+    {
+        let block_size = self.block_size();
+        let offset = group as usize * GROUP_DESCRIPTOR_SIZE;
+        let block = (self.superblock.first_data_block + 1) as u64 + (offset / block_size) as u64;
+        let data = self.cache.write(block)?;
+
+        self.groups[group as usize].write(&mut data[offset % block_size..]);
+
+        Ok(())
+    }
+
+    /// Write the superblock back to the disk. Only the primary copy is updated, the backups only
+    /// hold the static layout information which we never change.
+    pub fn write_superblock(&mut self) -> FsResult<()>
+    {
+        let block_size = self.block_size();
+        let block = (SUPERBLOCK_OFFSET / block_size) as u64;
+        let offset = SUPERBLOCK_OFFSET % block_size;
+
+        let bytes = self.superblock.encode();
+
+        self.cache.write_from(block, offset, bytes)
+    }
+
+    /// Write all of the filesystem's metadata and cached blocks back to the device.
+    pub fn sync(&mut self) -> FsResult<()>
+    {
+        if !self.read_only
+        {
+            self.superblock.write_time = current_time() as u32;
+            self.write_superblock()?;
+        }
+
+        self.cache.flush()
+    }
+}
+
+
+
+/// A mounted ext2 filesystem.
+pub struct Ext2Filesystem
+{
+    /// A reference to ourselves, handed to the inodes we create so they can find their way back.
+    this: Weak<Ext2Filesystem>,
+
+    /// The mutable state of the filesystem.
+    state: SpinMutex<Ext2State>
+}
+
+
+
+impl Ext2Filesystem
+{
+    /// Lock the filesystem's state for the duration of an operation.
+    pub fn lock(&self) -> SpinMutexGuard<'_, Ext2State>
+    {
+        self.state.lock()
+    }
+
+    /// Create the VFS inode object for an inode number.
+    pub fn inode(&self, number: u32) -> Arc<Ext2Inode>
+    {
+        Arc::new(Ext2Inode::new(self.this.upgrade().unwrap(), number))
+    }
+}
+
+
+
+impl Filesystem for Ext2Filesystem
+{
+    fn name(&self) -> &'static str
+    {
+        "ext2"
+    }
+
+    fn root(&self) -> FsResult<Arc<dyn Inode>>
+    {
+        let root = self.inode(EXT2_ROOT_INODE);
+
+        if !root.metadata()?.is_directory()
+        {
+            return Err(FsError::Corrupted("The root inode is not a directory."));
+        }
+
+        Ok(root)
+    }
+
+    fn statistics(&self) -> FsResult<FilesystemStatistics>
+    {
+        let state = self.lock();
+        let superblock = &state.superblock;
+
+        Ok(FilesystemStatistics
+            {
+                block_size: superblock.block_size as u32,
+                total_blocks: superblock.blocks_count as u64,
+                free_blocks: superblock.free_blocks_count as u64,
+                total_inodes: superblock.inodes_count as u64,
+                free_inodes: superblock.free_inodes_count as u64
+            })
+    }
+
+    fn sync(&self) -> FsResult<()>
+    {
+        self.lock().sync()
+    }
+
+    /// Mark the filesystem as cleanly unmounted, unless errors were found while it was mounted.
+    fn unmount(&self) -> FsResult<()>
+    {
+        let mut state = self.lock();
+
+        if    !state.read_only
+           && state.superblock.state & EXT2_ERROR_FS == 0
+        {
+            state.superblock.state |= EXT2_VALID_FS;
+        }
+
+        state.sync()
+    }
+}
+
+
+
+/// Register the ext2 driver with the VFS.
+pub fn register_filesystem_driver(registry: &mut FilesystemDriverRegistry)
+{
+    registry.insert("ext2",
+                    FilesystemDriver
+                        {
+                            mount: mount_ext2,
+                            read_label: read_ext2_label
+                        });
+}
+
+
+
+/// Mount the ext2 filesystem found on the device.
+fn mount_ext2(device: Arc<dyn BlockDevice>, options: &MountOptions) -> FsResult<Arc<dyn Filesystem>>
+{
+    // Read the superblock directly from the device, we need the block size from it before we can
+    // create the cache.
+    let mut raw = vec![0; SUPERBLOCK_SIZE];
+
+    device.read_sectors((SUPERBLOCK_OFFSET / SECTOR_SIZE) as u64, &mut raw)?;
+
+    let superblock = Superblock::new(raw)?;
+
+    if superblock.feature_incompat & !EXT2_SUPPORTED_INCOMPAT != 0
+    {
+        return Err(FsError::Unsupported);
+    }
+
+    let read_only = options.read_only || superblock.requires_read_only();
+
+    if read_only && !options.read_only
+    {
+        println!("  ext2: {} uses unsupported features, mounting read-only.", device.name());
+    }
+
+    let cache = BlockCache::new(device.clone(), superblock.block_size, CACHE_BLOCKS)?;
+
+    let mut state = Ext2State
+        {
+            cache,
+            superblock,
+            groups: Vec::new(),
+            read_only
+        };
+
+    // Load the group descriptor table, it starts in the block following the superblock.
+    let block_size = state.block_size();
+    let first_table_block = (state.superblock.first_data_block + 1) as u64;
+
+    for group in 0..state.superblock.group_count() as usize
+    {
+        let offset = group * GROUP_DESCRIPTOR_SIZE;
+        let data = state.cache.read(first_table_block + (offset / block_size) as u64)?;
+
+        state.groups.push(GroupDescriptor::new(&data[offset % block_size..]));
+    }
+
+    // Check the filesystem if we've been asked to, or if it wasn't cleanly unmounted or has known
+    // errors.
+    let superblock = &state.superblock;
+    let needs_check = superblock.state & EXT2_VALID_FS == 0
+                      || superblock.state & EXT2_ERROR_FS != 0
+                      || (   superblock.max_mount_count > 0
+                          && superblock.mount_count >= superblock.max_mount_count as u16);
+
+    if options.check || needs_check
+    {
+        println!("  ext2: Checking {}...", device.name());
+        fsck::check_filesystem(&mut state)?;
+    }
+
+    // Record the mount. While mounted read/write the filesystem is marked as not cleanly unmounted
+    // so that a crash will cause it to be checked on the next mount.
+    if !state.read_only
+    {
+        state.superblock.mount_count = state.superblock.mount_count.wrapping_add(1);
+        state.superblock.mount_time = current_time() as u32;
+        state.superblock.state &= !EXT2_VALID_FS;
+        state.sync()?;
+    }
+
+    Ok(Arc::new_cyclic(|this| Ext2Filesystem
+        {
+            this: this.clone(),
+            state: SpinMutex::new(state)
+        }))
+}
+
+
+
+/// Read the volume label of the ext2 filesystem found on the device.
+fn read_ext2_label(device: &dyn BlockDevice) -> FsResult<String>
+{
+    let mut raw = vec![0; SUPERBLOCK_SIZE];
+
+    device.read_sectors((SUPERBLOCK_OFFSET / SECTOR_SIZE) as u64, &mut raw)?;
+
+    Ok(Superblock::new(raw)?.volume_label())
+}
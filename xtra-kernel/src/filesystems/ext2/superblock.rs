@@ -0,0 +1,230 @@
+// The ext2 superblock, the 1024 byte structure found 1024 bytes into the partition that describes
+// the layout and state of the whole filesystem.
+//
+// We keep the raw bytes of the superblock around so that when it is written back any fields we
+// don't know about are preserved untouched.
+
+use alloc::{ format, string::String, vec::Vec };
+
+use crate::filesystems::{ read_u16, read_u32, write_u16, write_u32, FsError, FsResult };
+
+
+
+/// The byte offset of the superblock from the start of the partition.
+pub const SUPERBLOCK_OFFSET: usize = 1024;
+
+/// The size of the superblock in bytes.
+pub const SUPERBLOCK_SIZE: usize = 1024;
+
+/// The magic number identifying an ext2 filesystem.
+pub const EXT2_MAGIC: u16 = 0xEF53;
+
+
+
+/// Filesystem state, cleanly unmounted.
+pub const EXT2_VALID_FS: u16 = 1;
+
+/// Filesystem state, errors have been detected.
+pub const EXT2_ERROR_FS: u16 = 2;
+
+
+
+/// Revision 0 filesystems have fixed inode sizes and no feature flags.
+pub const EXT2_GOOD_OLD_REV: u32 = 0;
+
+/// The inode size used by revision 0 filesystems.
+pub const EXT2_GOOD_OLD_INODE_SIZE: u16 = 128;
+
+/// The first non-reserved inode on revision 0 filesystems.
+pub const EXT2_GOOD_OLD_FIRST_INODE: u32 = 11;
+
+
+
+/// Incompatible feature, directory entries record the type of the inode they refer to.
+pub const EXT2_FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+
+/// The incompatible features we know how to handle, any others prevent mounting.
+pub const EXT2_SUPPORTED_INCOMPAT: u32 = EXT2_FEATURE_INCOMPAT_FILETYPE;
+
+/// Read-only compatible feature, superblock backups are only kept in some groups.
+pub const EXT2_FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+
+/// Read-only compatible feature, files can be larger than 2GB.
+pub const EXT2_FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+/// The read-only compatible features we can write with, any others force a read-only mount.
+pub const EXT2_SUPPORTED_RO_COMPAT: u32 =   EXT2_FEATURE_RO_COMPAT_SPARSE_SUPER
+                                          | EXT2_FEATURE_RO_COMPAT_LARGE_FILE;
+
+
+
+/// The decoded ext2 superblock.
+pub struct Superblock
+{
+    raw: Vec<u8>,                     // The raw bytes as read from disk.
+
+    pub inodes_count: u32,            // Total number of inodes.
+    pub blocks_count: u32,            // Total number of blocks.
+    pub reserved_blocks_count: u32,   // Blocks reserved for the super user.
+    pub free_blocks_count: u32,       // Number of unallocated blocks.
+    pub free_inodes_count: u32,       // Number of unallocated inodes.
+    pub first_data_block: u32,        // Block holding the superblock, 1 for 1K blocks else 0.
+    pub block_size: usize,            // Size of a block in bytes.
+    pub blocks_per_group: u32,        // Number of blocks in each group.
+    pub inodes_per_group: u32,        // Number of inodes in each group.
+    pub mount_time: u32,              // Last mount time.
+    pub write_time: u32,              // Last write time.
+    pub mount_count: u16,             // Mounts since the last check.
+    pub max_mount_count: i16,         // Mounts allowed before a check is required.
+    pub state: u16,                   // EXT2_VALID_FS or EXT2_ERROR_FS.
+    pub last_check_time: u32,         // Time of the last consistency check.
+    pub revision: u32,                // Revision level.
+    pub first_inode: u32,             // First non-reserved inode.
+    pub inode_size: u16,              // Size of the on disk inode structure.
+    pub feature_compat: u32,          // Compatible feature set.
+    pub feature_incompat: u32,        // Incompatible feature set.
+    pub feature_ro_compat: u32,       // Read-only compatible feature set.
+    pub uuid: [u8; 16],               // Filesystem UUID.
+    pub volume_name: [u8; 16]         // Volume label.
+}
+
+
+
+impl Superblock
+{
+    /// Decode and validate the superblock from its raw bytes.
+    pub fn new(raw: Vec<u8>) -> FsResult<Self>
+    {
+        if raw.len() != SUPERBLOCK_SIZE
+        {
+            return Err(FsError::InvalidArgument);
+        }
+
+        if read_u16(&raw, 56) != EXT2_MAGIC
+        {
+            return Err(FsError::Corrupted("Bad ext2 superblock magic number."));
+        }
+
+        let log_block_size = read_u32(&raw, 24);
+
+        if log_block_size > 2
+        {
+            return Err(FsError::Corrupted("Unsupported ext2 block size."));
+        }
+
+        let revision = read_u32(&raw, 76);
+
+        let (first_inode, inode_size, feature_compat, feature_incompat, feature_ro_compat) =
+            if revision == EXT2_GOOD_OLD_REV
+            {
+                (EXT2_GOOD_OLD_FIRST_INODE, EXT2_GOOD_OLD_INODE_SIZE, 0, 0, 0)
+            }
+            else
+            {
+                (read_u32(&raw, 84),
+                 read_u16(&raw, 88),
+                 read_u32(&raw, 92),
+                 read_u32(&raw, 96),
+                 read_u32(&raw, 100))
+            };
+
+        let superblock = Superblock
+            {
+                inodes_count: read_u32(&raw, 0),
+                blocks_count: read_u32(&raw, 4),
+                reserved_blocks_count: read_u32(&raw, 8),
+                free_blocks_count: read_u32(&raw, 12),
+                free_inodes_count: read_u32(&raw, 16),
+                first_data_block: read_u32(&raw, 20),
+                block_size: 1024 << log_block_size,
+                blocks_per_group: read_u32(&raw, 32),
+                inodes_per_group: read_u32(&raw, 40),
+                mount_time: read_u32(&raw, 44),
+                write_time: read_u32(&raw, 48),
+                mount_count: read_u16(&raw, 52),
+                max_mount_count: read_u16(&raw, 54) as i16,
+                state: read_u16(&raw, 58),
+                last_check_time: read_u32(&raw, 64),
+                revision,
+                first_inode,
+                inode_size,
+                feature_compat,
+                feature_incompat,
+                feature_ro_compat,
+                uuid: raw[104..120].try_into().unwrap(),
+                volume_name: raw[120..136].try_into().unwrap(),
+                raw
+            };
+
+        if    superblock.blocks_per_group == 0
+           || superblock.inodes_per_group == 0
+           || superblock.inode_size < EXT2_GOOD_OLD_INODE_SIZE
+           || !superblock.inode_size.is_power_of_two()
+           || superblock.inode_size as usize > superblock.block_size
+        {
+            return Err(FsError::Corrupted("Invalid ext2 superblock geometry."));
+        }
+
+        Ok(superblock)
+    }
+
+    /// Encode the superblock back into its raw form, ready to be written to disk.
+    pub fn encode(&mut self) -> &[u8]
+    {
+        write_u32(&mut self.raw, 12, self.free_blocks_count);
+        write_u32(&mut self.raw, 16, self.free_inodes_count);
+        write_u32(&mut self.raw, 44, self.mount_time);
+        write_u32(&mut self.raw, 48, self.write_time);
+        write_u16(&mut self.raw, 52, self.mount_count);
+        write_u16(&mut self.raw, 58, self.state);
+        write_u32(&mut self.raw, 64, self.last_check_time);
+
+        &self.raw
+    }
+
+    /// The number of block groups in the filesystem.
+    pub fn group_count(&self) -> u32
+    {
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group)
+    }
+
+    /// The number of blocks that belong to the given group, the last group may be short.
+    pub fn blocks_in_group(&self, group: u32) -> u32
+    {
+        let first = group * self.blocks_per_group;
+        let total = self.blocks_count - self.first_data_block;
+
+        (total - first).min(self.blocks_per_group)
+    }
+
+    /// Does the filesystem have the given incompatible feature?
+    pub fn has_incompat_feature(&self, feature: u32) -> bool
+    {
+        self.feature_incompat & feature != 0
+    }
+
+    /// Does the filesystem use features that we can't safely write with?
+    pub fn requires_read_only(&self) -> bool
+    {
+        self.feature_ro_compat & !EXT2_SUPPORTED_RO_COMPAT != 0
+    }
+
+    /// The volume label as a string.
+    pub fn volume_label(&self) -> String
+    {
+        let length = self.volume_name.iter().position(|&byte| byte == 0).unwrap_or(16);
+
+        String::from_utf8_lossy(&self.volume_name[..length]).into()
+    }
+
+    /// The filesystem UUID in its usual textual form.
+    pub fn uuid_string(&self) -> String
+    {
+        let u = &self.uuid;
+
+        format!("{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-\
+                 {:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+                u[0], u[1], u[2], u[3], u[4], u[5], u[6], u[7],
+                u[8], u[9], u[10], u[11], u[12], u[13], u[14], u[15])
+    }
+}
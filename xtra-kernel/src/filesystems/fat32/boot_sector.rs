@@ -0,0 +1,219 @@
+// The FAT32 boot sector, (the BIOS parameter block,) and the FSInfo sector. The boot sector
+// describes the layout of the volume: where the FATs are, how big the clusters are and where the
+// root directory starts. The FSInfo sector caches the number of free clusters and a hint for where
+// to start looking for one so that the whole FAT doesn't need to be scanned on every mount.
+
+use alloc::string::String;
+
+use crate::filesystems::{ read_u16, read_u32, write_u32, FsError, FsResult };
+
+
+
+// Offsets of the boot sector fields that we use.
+const BYTES_PER_SECTOR_OFFSET: usize = 0x0b;
+const SECTORS_PER_CLUSTER_OFFSET: usize = 0x0d;
+const RESERVED_SECTORS_OFFSET: usize = 0x0e;
+const FAT_COUNT_OFFSET: usize = 0x10;
+const ROOT_ENTRIES_OFFSET: usize = 0x11;
+const TOTAL_SECTORS_16_OFFSET: usize = 0x13;
+const FAT_SIZE_16_OFFSET: usize = 0x16;
+const TOTAL_SECTORS_32_OFFSET: usize = 0x20;
+const FAT_SIZE_32_OFFSET: usize = 0x24;
+const EXTENDED_FLAGS_OFFSET: usize = 0x28;
+const ROOT_CLUSTER_OFFSET: usize = 0x2c;
+const FSINFO_SECTOR_OFFSET: usize = 0x30;
+const VOLUME_ID_OFFSET: usize = 0x43;
+const VOLUME_LABEL_OFFSET: usize = 0x47;
+const SIGNATURE_OFFSET: usize = 0x1fe;
+
+
+
+/// The signature found at the end of the boot sector.
+const BOOT_SIGNATURE: u16 = 0xaa55;
+
+/// When set in the extended flags only the active FAT is used, otherwise all FATs are mirrored.
+const MIRRORING_DISABLED: u16 = 0x0080;
+
+/// Mask for the active FAT number in the extended flags.
+const ACTIVE_FAT_MASK: u16 = 0x000f;
+
+
+
+// Offsets and signatures of the FSInfo sector.
+const FSINFO_LEAD_SIGNATURE_OFFSET: usize = 0;
+const FSINFO_STRUCT_SIGNATURE_OFFSET: usize = 484;
+const FSINFO_FREE_COUNT_OFFSET: usize = 488;
+const FSINFO_NEXT_FREE_OFFSET: usize = 492;
+const FSINFO_TRAIL_SIGNATURE_OFFSET: usize = 508;
+
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_TRAIL_SIGNATURE: u32 = 0xaa55_0000;
+
+/// The value the FSInfo sector uses for an unknown free count or next free hint.
+pub const FSINFO_UNKNOWN: u32 = 0xffff_ffff;
+
+
+
+/// The decoded FAT32 boot sector.
+pub struct BootSector
+{
+    pub bytes_per_sector: usize,    // Size of a logical sector.
+    pub sectors_per_cluster: usize, // Number of sectors in a cluster.
+    pub reserved_sectors: u32,      // Sectors before the first FAT.
+    pub fat_count: u32,             // Number of copies of the FAT.
+    pub fat_size: u32,              // Size of each FAT in sectors.
+    pub total_sectors: u32,         // Total sectors in the volume.
+    pub root_cluster: u32,          // First cluster of the root directory.
+    pub fsinfo_sector: u32,         // Sector holding the FSInfo structure, 0 if there isn't one.
+    pub mirrored: bool,             // Are changes written to all of the FATs?
+    pub active_fat: u32,            // The FAT to use when mirroring is disabled.
+    pub volume_id: u32,             // Volume serial number.
+    pub volume_label: [u8; 11]      // Volume label, padded with spaces.
+}
+
+
+
+impl BootSector
+{
+    /// Decode and validate the boot sector, rejecting volumes that aren't FAT32.
+    pub fn new(sector: &[u8]) -> FsResult<Self>
+    {
+        if read_u16(sector, SIGNATURE_OFFSET) != BOOT_SIGNATURE
+        {
+            return Err(FsError::Corrupted("Bad FAT boot sector signature."));
+        }
+
+        let total_sectors_16 = read_u16(sector, TOTAL_SECTORS_16_OFFSET) as u32;
+        let extended_flags = read_u16(sector, EXTENDED_FLAGS_OFFSET);
+
+        let boot_sector = BootSector
+            {
+                bytes_per_sector: read_u16(sector, BYTES_PER_SECTOR_OFFSET) as usize,
+                sectors_per_cluster: sector[SECTORS_PER_CLUSTER_OFFSET] as usize,
+                reserved_sectors: read_u16(sector, RESERVED_SECTORS_OFFSET) as u32,
+                fat_count: sector[FAT_COUNT_OFFSET] as u32,
+                fat_size: read_u32(sector, FAT_SIZE_32_OFFSET),
+                total_sectors: if total_sectors_16 != 0
+                    {
+                        total_sectors_16
+                    }
+                    else
+                    {
+                        read_u32(sector, TOTAL_SECTORS_32_OFFSET)
+                    },
+                root_cluster: read_u32(sector, ROOT_CLUSTER_OFFSET),
+                fsinfo_sector: read_u16(sector, FSINFO_SECTOR_OFFSET) as u32,
+                mirrored: extended_flags & MIRRORING_DISABLED == 0,
+                active_fat: (extended_flags & ACTIVE_FAT_MASK) as u32,
+                volume_id: read_u32(sector, VOLUME_ID_OFFSET),
+                volume_label: sector[VOLUME_LABEL_OFFSET..VOLUME_LABEL_OFFSET + 11]
+                                  .try_into()
+                                  .unwrap()
+            };
+
+        // FAT12 and FAT16 volumes have a fixed root directory and a 16 bit FAT size.
+        if    read_u16(sector, ROOT_ENTRIES_OFFSET) != 0
+           || read_u16(sector, FAT_SIZE_16_OFFSET) != 0
+           || boot_sector.fat_size == 0
+        {
+            return Err(FsError::Unsupported);
+        }
+
+        if    !matches!(boot_sector.bytes_per_sector, 512 | 1024 | 2048 | 4096)
+           || !boot_sector.sectors_per_cluster.is_power_of_two()
+           || boot_sector.fat_count == 0
+           || boot_sector.active_fat >= boot_sector.fat_count
+           || boot_sector.first_data_sector() >= boot_sector.total_sectors
+           || boot_sector.root_cluster < 2
+           || boot_sector.root_cluster >= boot_sector.cluster_count() + 2
+        {
+            return Err(FsError::Corrupted("Invalid FAT32 boot sector geometry."));
+        }
+
+        Ok(boot_sector)
+    }
+
+    /// The first sector of the data area, where cluster 2 starts.
+    pub fn first_data_sector(&self) -> u32
+    {
+        self.reserved_sectors + self.fat_count * self.fat_size
+    }
+
+    /// The number of data clusters in the volume. Valid cluster numbers run from 2 to
+    /// cluster_count + 1.
+    pub fn cluster_count(&self) -> u32
+    {
+        let data_sectors = self.total_sectors.saturating_sub(self.first_data_sector());
+        let clusters = data_sectors / self.sectors_per_cluster as u32;
+
+        // The FAT itself may not have room for every cluster the data area could hold.
+        let fat_entries = (self.fat_size as usize * self.bytes_per_sector / 4) as u32;
+
+        clusters.min(fat_entries.saturating_sub(2))
+    }
+
+    /// The size of a cluster in bytes.
+    pub fn cluster_size(&self) -> usize
+    {
+        self.bytes_per_sector * self.sectors_per_cluster
+    }
+
+    /// The first sector of a cluster.
+    pub fn cluster_sector(&self, cluster: u32) -> u64
+    {
+        self.first_data_sector() as u64
+            + (cluster as u64 - 2) * self.sectors_per_cluster as u64
+    }
+
+    /// The first sector of the FAT copy with the given index.
+    pub fn fat_sector(&self, fat: u32) -> u64
+    {
+        (self.reserved_sectors + fat * self.fat_size) as u64
+    }
+
+    /// The volume label with the padding removed.
+    pub fn label(&self) -> String
+    {
+        String::from_utf8_lossy(&self.volume_label).trim_end().into()
+    }
+}
+
+
+
+/// The contents of the FSInfo sector.
+#[derive(Clone, Copy)]
+pub struct FsInfo
+{
+    pub free_count: u32,  // Number of free clusters, or FSINFO_UNKNOWN.
+    pub next_free: u32    // Where to start searching for a free cluster, or FSINFO_UNKNOWN.
+}
+
+
+
+impl FsInfo
+{
+    /// Decode the FSInfo sector, `None` if the signatures don't match.
+    pub fn new(sector: &[u8]) -> Option<Self>
+    {
+        if    read_u32(sector, FSINFO_LEAD_SIGNATURE_OFFSET) != FSINFO_LEAD_SIGNATURE
+           || read_u32(sector, FSINFO_STRUCT_SIGNATURE_OFFSET) != FSINFO_STRUCT_SIGNATURE
+           || read_u32(sector, FSINFO_TRAIL_SIGNATURE_OFFSET) != FSINFO_TRAIL_SIGNATURE
+        {
+            return None;
+        }
+
+        Some(FsInfo
+            {
+                free_count: read_u32(sector, FSINFO_FREE_COUNT_OFFSET),
+                next_free: read_u32(sector, FSINFO_NEXT_FREE_OFFSET)
+            })
+    }
+
+    /// Write the free count and hint back into the FSInfo sector.
+    pub fn write(&self, sector: &mut [u8])
+    {
+        write_u32(sector, FSINFO_FREE_COUNT_OFFSET, self.free_count);
+        write_u32(sector, FSINFO_NEXT_FREE_OFFSET, self.next_free);
+    }
+}
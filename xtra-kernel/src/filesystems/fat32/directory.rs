@@ -0,0 +1,1047 @@
+// FAT directories are cluster chains holding an array of 32 byte entries. Each file has a short
+// 8.3 entry holding its attributes, first cluster, size and timestamps. Names that don't fit the
+// 8.3 form are stored as VFAT long names, a run of extra entries placed just before the short entry
+// that each hold 13 UTF-16 characters of the name along with a checksum of the short name that ties
+// them together.
+//
+// An entry starting with 0xe5 is free and one starting with 0 marks the end of the directory.
+
+use core::fmt::{ self, Display, Formatter };
+
+use alloc::{ string::String, vec::Vec };
+
+use xtra_kernel_shared::fat::short_name_checksum;
+
+use crate::filesystems::{ fat32::{ table::{ allocate_cluster,
+                                            cluster_at,
+                                            chain_length,
+                                            next_cluster },
+                                   timestamp::{ from_unix_time, to_unix_time },
+                                   Fat32State },
+                          read_u16,
+                          read_u32,
+                          write_u16,
+                          write_u32,
+                          FsError,
+                          FsResult };
+
+
+
+/// The size of a directory entry.
+pub const ENTRY_SIZE: usize = 32;
+
+/// The FAT specification limits directories to this many entries.
+const MAX_DIRECTORY_ENTRIES: u32 = 65536;
+
+
+
+// Entry attribute bits.
+pub const ATTRIBUTE_READ_ONLY: u8 = 0x01;
+pub const ATTRIBUTE_HIDDEN: u8 = 0x02;
+pub const ATTRIBUTE_SYSTEM: u8 = 0x04;
+pub const ATTRIBUTE_VOLUME_ID: u8 = 0x08;
+pub const ATTRIBUTE_DIRECTORY: u8 = 0x10;
+pub const ATTRIBUTE_ARCHIVE: u8 = 0x20;
+
+/// The attribute combination that marks a long name entry.
+const ATTRIBUTE_LONG_NAME: u8 = 0x0f;
+
+/// Mask of the attribute bits that are checked when looking for a long name entry.
+const ATTRIBUTE_LONG_NAME_MASK: u8 = 0x3f;
+
+
+
+/// First byte of a free entry.
+const DELETED_MARKER: u8 = 0xe5;
+
+/// First byte of the entry that ends the directory.
+const END_MARKER: u8 = 0x00;
+
+/// A short name really starting with 0xe5 is stored with 0x05 instead.
+const ESCAPED_DELETED_MARKER: u8 = 0x05;
+
+/// Set in the sequence number of the long name entry holding the end of the name.
+const LAST_LONG_ENTRY: u8 = 0x40;
+
+/// Flags used by Windows NT and later to record all lower case short names without a long name.
+const LOWER_CASE_BASE: u8 = 0x08;
+const LOWER_CASE_EXTENSION: u8 = 0x10;
+
+
+
+/// The number of UTF-16 characters held by each long name entry.
+const LONG_NAME_CHARACTERS: usize = 13;
+
+/// The longest a long name can be in UTF-16 characters.
+const MAX_LONG_NAME: usize = 255;
+
+/// The most long name entries a single name can need.
+const MAX_LONG_ENTRIES: usize = MAX_LONG_NAME.div_ceil(LONG_NAME_CHARACTERS);
+
+/// Where the characters of the name are within a long name entry.
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_CHARACTERS] = [ 1, 3, 5, 7, 9,
+                                                           14, 16, 18, 20, 22, 24,
+                                                           28, 30 ];
+
+
+
+// Offsets of the fields in a short entry.
+const NAME_OFFSET: usize = 0;
+const ATTRIBUTES_OFFSET: usize = 11;
+const CASE_OFFSET: usize = 12;
+const CREATE_TIME_OFFSET: usize = 14;
+const CREATE_DATE_OFFSET: usize = 16;
+const ACCESS_DATE_OFFSET: usize = 18;
+const CLUSTER_HIGH_OFFSET: usize = 20;
+const MODIFY_TIME_OFFSET: usize = 22;
+const MODIFY_DATE_OFFSET: usize = 24;
+const CLUSTER_LOW_OFFSET: usize = 26;
+const SIZE_OFFSET: usize = 28;
+
+// Offsets of the fields in a long name entry that aren't characters.
+const LONG_ORDER_OFFSET: usize = 0;
+const LONG_CHECKSUM_OFFSET: usize = 13;
+
+
+
+/// A short directory entry.
+#[derive(Clone)]
+pub struct ShortEntry
+{
+    raw: [u8; ENTRY_SIZE]
+}
+
+
+
+impl ShortEntry
+{
+    /// Decode an entry from its on disk form.
+    pub fn new(bytes: &[u8]) -> Self
+    {
+        ShortEntry { raw: bytes[..ENTRY_SIZE].try_into().unwrap() }
+    }
+
+    /// Create a new entry with the given attributes, all timestamps are set to the given time.
+    pub fn new_empty(attributes: u8, time: u64) -> Self
+    {
+        let mut entry = ShortEntry { raw: [0; ENTRY_SIZE] };
+
+        entry.raw[ATTRIBUTES_OFFSET] = attributes;
+        entry.set_create_time(time);
+        entry.set_modify_time(time);
+        entry.set_access_time(time);
+
+        entry
+    }
+
+    /// The raw 11 byte short name.
+    pub fn short_name(&self) -> [u8; 11]
+    {
+        self.raw[NAME_OFFSET..NAME_OFFSET + 11].try_into().unwrap()
+    }
+
+    fn set_short_name(&mut self, name: &[u8; 11], case: u8)
+    {
+        self.raw[NAME_OFFSET..NAME_OFFSET + 11].copy_from_slice(name);
+        self.raw[CASE_OFFSET] = case;
+    }
+
+    pub fn attributes(&self) -> u8
+    {
+        self.raw[ATTRIBUTES_OFFSET]
+    }
+
+    pub fn set_attributes(&mut self, attributes: u8)
+    {
+        self.raw[ATTRIBUTES_OFFSET] = attributes;
+    }
+
+    pub fn is_directory(&self) -> bool
+    {
+        self.attributes() & ATTRIBUTE_DIRECTORY != 0
+    }
+
+    pub fn is_read_only(&self) -> bool
+    {
+        self.attributes() & ATTRIBUTE_READ_ONLY != 0
+    }
+
+    /// Is this the . or .. entry of a directory?
+    pub fn is_dot_entry(&self) -> bool
+    {
+        self.raw[NAME_OFFSET] == b'.'
+    }
+
+    pub fn first_cluster(&self) -> u32
+    {
+        (read_u16(&self.raw, CLUSTER_HIGH_OFFSET) as u32) << 16
+            | read_u16(&self.raw, CLUSTER_LOW_OFFSET) as u32
+    }
+
+    pub fn set_first_cluster(&mut self, cluster: u32)
+    {
+        write_u16(&mut self.raw, CLUSTER_HIGH_OFFSET, (cluster >> 16) as u16);
+        write_u16(&mut self.raw, CLUSTER_LOW_OFFSET, cluster as u16);
+    }
+
+    pub fn size(&self) -> u32
+    {
+        read_u32(&self.raw, SIZE_OFFSET)
+    }
+
+    pub fn set_size(&mut self, size: u32)
+    {
+        write_u32(&mut self.raw, SIZE_OFFSET, size);
+    }
+
+    pub fn create_time(&self) -> u64
+    {
+        to_unix_time(read_u16(&self.raw, CREATE_DATE_OFFSET),
+                     read_u16(&self.raw, CREATE_TIME_OFFSET))
+    }
+
+    pub fn set_create_time(&mut self, time: u64)
+    {
+        let (date, time) = from_unix_time(time);
+
+        write_u16(&mut self.raw, CREATE_DATE_OFFSET, date);
+        write_u16(&mut self.raw, CREATE_TIME_OFFSET, time);
+    }
+
+    pub fn modify_time(&self) -> u64
+    {
+        to_unix_time(read_u16(&self.raw, MODIFY_DATE_OFFSET),
+                     read_u16(&self.raw, MODIFY_TIME_OFFSET))
+    }
+
+    pub fn set_modify_time(&mut self, time: u64)
+    {
+        let (date, time) = from_unix_time(time);
+
+        write_u16(&mut self.raw, MODIFY_DATE_OFFSET, date);
+        write_u16(&mut self.raw, MODIFY_TIME_OFFSET, time);
+    }
+
+    /// The access time, FAT only records the date.
+    pub fn access_time(&self) -> u64
+    {
+        to_unix_time(read_u16(&self.raw, ACCESS_DATE_OFFSET), 0)
+    }
+
+    pub fn set_access_time(&mut self, time: u64)
+    {
+        write_u16(&mut self.raw, ACCESS_DATE_OFFSET, from_unix_time(time).0);
+    }
+}
+
+
+
+/// Where a short entry lives, the first cluster of the directory holding it and its index within
+/// the directory.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct EntryLocation
+{
+    pub directory: u32,
+    pub index: u32
+}
+
+
+
+/// An entry found while reading a directory.
+#[derive(Clone)]
+pub struct FatEntry
+{
+    /// Where the short entry lives.
+    pub location: EntryLocation,
+
+    /// The index of the first entry belonging to the file, the first long name entry if it has a
+    /// long name.
+    pub first_index: u32,
+
+    /// The short entry itself.
+    pub entry: ShortEntry
+}
+
+
+
+/// The name of an entry as found in the directory, either its long name or its short name.
+pub struct EntryName<'a>
+{
+    long: Option<&'a [u16]>,  // The long name, if there is a valid one.
+    short: [u8; 11],          // The raw short name.
+    case: u8                  // The lower case flags for the short name.
+}
+
+
+
+impl<'a> EntryName<'a>
+{
+    /// Format the short name as it would be displayed, returning the buffer and its used length.
+    fn short_display(&self) -> ([u8; 12], usize)
+    {
+        let mut buffer = [0u8; 12];
+        let mut length = 0;
+
+        let base_length = self.short[..8].iter()
+                                         .rposition(|&byte| byte != b' ')
+                                         .map_or(0, |i| i + 1);
+        let extension_length = self.short[8..].iter()
+                                              .rposition(|&byte| byte != b' ')
+                                              .map_or(0, |i| i + 1);
+
+        for (index, &byte) in self.short[..base_length].iter().enumerate()
+        {
+            let byte = if    index == 0
+                          && byte == ESCAPED_DELETED_MARKER
+                {
+                    DELETED_MARKER
+                }
+                else
+                {
+                    byte
+                };
+
+            buffer[length] = if self.case & LOWER_CASE_BASE != 0
+                {
+                    byte.to_ascii_lowercase()
+                }
+                else
+                {
+                    byte
+                };
+
+            length += 1;
+        }
+
+        if extension_length > 0
+        {
+            buffer[length] = b'.';
+            length += 1;
+
+            for &byte in &self.short[8..8 + extension_length]
+            {
+                buffer[length] = if self.case & LOWER_CASE_EXTENSION != 0
+                    {
+                        byte.to_ascii_lowercase()
+                    }
+                    else
+                    {
+                        byte
+                    };
+
+                length += 1;
+            }
+        }
+
+        (buffer, length)
+    }
+
+    /// Does the entry match the name? FAT names are compared without regard to case, and either
+    /// the long or short name of an entry can be used to find it.
+    pub fn matches(&self, name: &str) -> bool
+    {
+        if let Some(long) = self.long
+        {
+            let mut units = name.encode_utf16();
+            let long_matches = long.iter().all(|&unit|
+                {
+                    units.next().is_some_and(|other| fold_case(unit) == fold_case(other))
+                })
+                && units.next().is_none();
+
+            if long_matches
+            {
+                return true;
+            }
+        }
+
+        let (buffer, length) = self.short_display();
+
+        buffer[..length].eq_ignore_ascii_case(name.as_bytes())
+    }
+}
+
+
+
+impl<'a> Display for EntryName<'a>
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        match self.long
+        {
+            Some(long) =>
+                {
+                    for character in char::decode_utf16(long.iter().copied())
+                    {
+                        write!(formatter,
+                               "{}",
+                               character.unwrap_or(char::REPLACEMENT_CHARACTER))?;
+                    }
+
+                    Ok(())
+                },
+
+            None =>
+                {
+                    let (buffer, length) = self.short_display();
+
+                    // Bytes outside of ASCII are in the volume's OEM code page, which we don't
+                    // know, show them as Latin-1.
+                    for &byte in &buffer[..length]
+                    {
+                        write!(formatter, "{}", byte as char)?;
+                    }
+
+                    Ok(())
+                }
+        }
+    }
+}
+
+
+
+/// Fold ASCII letters to upper case for case insensitive comparison of UTF-16 names.
+fn fold_case(unit: u16) -> u16
+{
+    if (b'a' as u16..=b'z' as u16).contains(&unit)
+    {
+        unit - 32
+    }
+    else
+    {
+        unit
+    }
+}
+
+
+
+/// Call the visitor for every 32 byte slot in the directory with the slot's index, stopping as soon
+/// as it returns a value.
+fn for_each_slot<R>(state: &mut Fat32State,
+                    directory: u32,
+                    mut visitor: impl FnMut(u32, &[u8]) -> Option<R>) -> FsResult<Option<R>>
+{
+    let entries_per_sector = state.boot.bytes_per_sector / ENTRY_SIZE;
+    let mut cluster = Some(directory);
+    let mut index = 0;
+
+    while let Some(current) = cluster
+    {
+        let first_sector = state.boot.cluster_sector(current);
+
+        for sector in 0..state.boot.sectors_per_cluster as u64
+        {
+            let data = state.cache.read(first_sector + sector)?;
+
+            for slot in data.chunks_exact(ENTRY_SIZE).take(entries_per_sector)
+            {
+                if let Some(result) = visitor(index, slot)
+                {
+                    return Ok(Some(result));
+                }
+
+                index += 1;
+            }
+        }
+
+        if index > MAX_DIRECTORY_ENTRIES
+        {
+            return Err(FsError::Corrupted("Directory is too large."));
+        }
+
+        cluster = next_cluster(state, current)?;
+    }
+
+    Ok(None)
+}
+
+
+
+/// Find the sector and offset holding a slot of a directory.
+fn slot_location(state: &mut Fat32State, directory: u32, index: u32) -> FsResult<(u64, usize)>
+{
+    let entries_per_cluster = (state.boot.cluster_size() / ENTRY_SIZE) as u32;
+    let cluster = cluster_at(state, directory, (index / entries_per_cluster) as u64)?
+                      .ok_or(FsError::Corrupted("Directory entry past the end of the chain."))?;
+    let byte_offset = (index % entries_per_cluster) as usize * ENTRY_SIZE;
+    let bytes_per_sector = state.boot.bytes_per_sector;
+
+    Ok((state.boot.cluster_sector(cluster) + (byte_offset / bytes_per_sector) as u64,
+        byte_offset % bytes_per_sector))
+}
+
+
+
+/// Write a 32 byte slot of a directory.
+fn write_slot(state: &mut Fat32State, directory: u32, index: u32, slot: &[u8]) -> FsResult<()>
+{
+    state.check_writable()?;
+
+    let (sector, offset) = slot_location(state, directory, index)?;
+
+    state.cache.write_from(sector, offset, slot)
+}
+
+
+
+/// Call the visitor for every file in the directory, including . and .., with the entry and its
+/// name. Long names are collected from the entries before each short entry and only used if their
+/// sequence and checksum are intact.
+pub fn for_each_entry<R>(state: &mut Fat32State,
+                         directory: u32,
+                         mut visitor: impl FnMut(&FatEntry, &EntryName) -> Option<R>)
+    -> FsResult<Option<R>>
+{
+    let mut long_name = [0u16; MAX_LONG_ENTRIES * LONG_NAME_CHARACTERS];
+    let mut long_entries = 0;   // Number of long entries in the current name, 0 if there isn't one.
+    let mut remaining = 0;      // The sequence number of the next long entry we expect.
+    let mut checksum = 0;       // The checksum all of the current long entries must share.
+    let mut first_index = 0;    // Index of the first long entry of the current name.
+
+    let result = for_each_slot(state, directory, |index, slot|
+        {
+            let marker = slot[0];
+            let attributes = slot[ATTRIBUTES_OFFSET];
+
+            if marker == END_MARKER
+            {
+                return Some(None);
+            }
+
+            if marker == DELETED_MARKER
+            {
+                long_entries = 0;
+                return None;
+            }
+
+            if attributes & ATTRIBUTE_LONG_NAME_MASK == ATTRIBUTE_LONG_NAME
+            {
+                let order = slot[LONG_ORDER_OFFSET];
+                let sequence = (order & !LAST_LONG_ENTRY) as usize;
+
+                if order & LAST_LONG_ENTRY != 0
+                {
+                    if    sequence == 0
+                       || sequence > MAX_LONG_ENTRIES
+                    {
+                        long_entries = 0;
+                        return None;
+                    }
+
+                    long_entries = sequence;
+                    remaining = sequence;
+                    checksum = slot[LONG_CHECKSUM_OFFSET];
+                    first_index = index;
+                }
+                else if    long_entries == 0
+                        || sequence != remaining
+                        || slot[LONG_CHECKSUM_OFFSET] != checksum
+                {
+                    long_entries = 0;
+                    return None;
+                }
+
+                let start = (sequence - 1) * LONG_NAME_CHARACTERS;
+
+                for (position, &offset) in LONG_NAME_OFFSETS.iter().enumerate()
+                {
+                    long_name[start + position] = read_u16(slot, offset);
+                }
+
+                remaining -= 1;
+                return None;
+            }
+
+            // Skip the volume label, it isn't a file.
+            if attributes & ATTRIBUTE_VOLUME_ID != 0
+            {
+                long_entries = 0;
+                return None;
+            }
+
+            let entry = ShortEntry::new(slot);
+            let short = entry.short_name();
+
+            let has_long_name =    long_entries != 0
+                                && remaining == 0
+                                && short_name_checksum(&short) == checksum;
+
+            let long = if has_long_name
+                {
+                    let characters = &long_name[..long_entries * LONG_NAME_CHARACTERS];
+                    let length = characters.iter()
+                                           .position(|&unit| unit == 0)
+                                           .unwrap_or(characters.len());
+
+                    Some(&characters[..length])
+                }
+                else
+                {
+                    None
+                };
+
+            let found = FatEntry
+                {
+                    location: EntryLocation { directory, index },
+                    first_index: if has_long_name { first_index } else { index },
+                    entry
+                };
+
+            let name = EntryName { long, short, case: slot[CASE_OFFSET] };
+            let result = visitor(&found, &name);
+
+            long_entries = 0;
+
+            result.map(Some)
+        })?;
+
+    Ok(result.flatten())
+}
+
+
+
+/// Find the named entry in a directory. The . and .. entries are never matched.
+pub fn find_entry(state: &mut Fat32State, directory: u32, name: &str) -> FsResult<Option<FatEntry>>
+{
+    for_each_entry(state, directory, |entry, entry_name|
+        {
+            if    !entry.entry.is_dot_entry()
+               && entry_name.matches(name)
+            {
+                Some(entry.clone())
+            }
+            else
+            {
+                None
+            }
+        })
+}
+
+
+
+/// Read all of the entries of a directory other than . and .., along with their names.
+pub fn list_entries(state: &mut Fat32State, directory: u32) -> FsResult<Vec<(String, FatEntry)>>
+{
+    use alloc::string::ToString;
+
+    let mut entries = Vec::new();
+
+    for_each_entry::<()>(state, directory, |entry, name|
+        {
+            if !entry.entry.is_dot_entry()
+            {
+                entries.push((name.to_string(), entry.clone()));
+            }
+
+            None
+        })?;
+
+    Ok(entries)
+}
+
+
+
+/// Check that a directory holds nothing but its . and .. entries.
+pub fn is_empty(state: &mut Fat32State, directory: u32) -> FsResult<bool>
+{
+    let found = for_each_entry(state, directory, |entry, _|
+        {
+            if entry.entry.is_dot_entry() { None } else { Some(()) }
+        })?;
+
+    Ok(found.is_none())
+}
+
+
+
+/// Read the short entry at a location.
+pub fn read_entry(state: &mut Fat32State, location: EntryLocation) -> FsResult<ShortEntry>
+{
+    let (sector, offset) = slot_location(state, location.directory, location.index)?;
+    let data = state.cache.read(sector)?;
+
+    Ok(ShortEntry::new(&data[offset..]))
+}
+
+
+
+/// Write the short entry at a location back to the directory.
+pub fn write_entry(state: &mut Fat32State,
+                   location: EntryLocation,
+                   entry: &ShortEntry) -> FsResult<()>
+{
+    write_slot(state, location.directory, location.index, &entry.raw)
+}
+
+
+
+/// Can the byte appear in a short name?
+fn is_short_name_character(byte: u8) -> bool
+{
+    byte.is_ascii_uppercase()
+        || byte.is_ascii_digit()
+        || byte >= 0x80
+        || b"!#$%&'()-@^_`{}~".contains(&byte)
+}
+
+
+
+/// Check that a name can be used for a new file.
+fn validate_name(name: &str) -> FsResult<()>
+{
+    if    name.is_empty()
+       || name == "."
+       || name == ".."
+       || name.ends_with('.')
+       || name.ends_with(' ')
+       || name.chars().any(|character| character < ' ' || "\"*/:<>?\\|".contains(character))
+    {
+        return Err(FsError::InvalidPath);
+    }
+
+    if name.encode_utf16().count() > MAX_LONG_NAME
+    {
+        return Err(FsError::NameTooLong);
+    }
+
+    Ok(())
+}
+
+
+
+/// If the name can be stored exactly as a short name, (possibly using the lower case flags,) return
+/// that short name and the flags.
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)>
+{
+    let bytes = name.as_bytes();
+
+    if !name.is_ascii()
+    {
+        return None;
+    }
+
+    let (base, extension) = match name.find('.')
+        {
+            Some(dot) => (&bytes[..dot], &bytes[dot + 1..]),
+            None      => (bytes, &bytes[bytes.len()..])
+        };
+
+    if    base.is_empty()
+       || base.len() > 8
+       || extension.len() > 3
+    {
+        return None;
+    }
+
+    let mut case = 0;
+
+    for (part, flag) in [ (base, LOWER_CASE_BASE), (extension, LOWER_CASE_EXTENSION) ]
+    {
+        let has_lower = part.iter().any(|byte| byte.is_ascii_lowercase());
+        let has_upper = part.iter().any(|byte| byte.is_ascii_uppercase());
+
+        if has_lower && has_upper
+        {
+            return None;
+        }
+
+        if has_lower
+        {
+            case |= flag;
+        }
+
+        if !part.iter().all(|byte| is_short_name_character(byte.to_ascii_uppercase()))
+        {
+            return None;
+        }
+    }
+
+    let mut short = [b' '; 11];
+
+    for (index, byte) in base.iter().enumerate()
+    {
+        short[index] = byte.to_ascii_uppercase();
+    }
+
+    for (index, byte) in extension.iter().enumerate()
+    {
+        short[8 + index] = byte.to_ascii_uppercase();
+    }
+
+    Some((short, case))
+}
+
+
+
+/// Generate a unique short name for a name that needs a long name, in the usual BASE~N.EXT form.
+fn generate_short_name(state: &mut Fat32State, directory: u32, name: &str) -> FsResult<[u8; 11]>
+{
+    // Convert a character to the form it takes in a short name, dropping spaces and dots.
+    fn convert(character: char) -> Option<u8>
+    {
+        match character
+        {
+            ' ' | '.' => None,
+            _ =>
+                {
+                    let upper = character.to_ascii_uppercase();
+
+                    if    upper.is_ascii()
+                       && is_short_name_character(upper as u8)
+                    {
+                        Some(upper as u8)
+                    }
+                    else
+                    {
+                        Some(b'_')
+                    }
+                }
+        }
+    }
+
+    let trimmed = name.trim_start_matches('.');
+    let (base, extension) = match trimmed.rfind('.')
+        {
+            Some(dot) => (&trimmed[..dot], &trimmed[dot + 1..]),
+            None      => (trimmed, "")
+        };
+
+    let mut basis = [b' '; 11];
+    let mut base_length = 0;
+
+    for byte in base.chars().filter_map(convert).take(8)
+    {
+        basis[base_length] = byte;
+        base_length += 1;
+    }
+
+    for (index, byte) in extension.chars().filter_map(convert).take(3).enumerate()
+    {
+        basis[8 + index] = byte;
+    }
+
+    if base_length == 0
+    {
+        basis[0] = b'_';
+        base_length = 1;
+    }
+
+    // Try numeric tails until we find one that isn't used in the directory.
+    for number in 1..1_000_000u32
+    {
+        let mut digits = [0u8; 7];
+        let mut digit_count = 0;
+        let mut value = number;
+
+        while value > 0
+        {
+            digits[digit_count] = b'0' + (value % 10) as u8;
+            digit_count += 1;
+            value /= 10;
+        }
+
+        let tail_length = digit_count + 1;
+        let keep = base_length.min(8 - tail_length);
+        let mut candidate = basis;
+
+        candidate[keep] = b'~';
+
+        for index in 0..digit_count
+        {
+            candidate[keep + 1 + index] = digits[digit_count - 1 - index];
+        }
+
+        candidate[keep + tail_length..8].fill(b' ');
+
+        let in_use = for_each_entry(state, directory, |entry, _|
+            {
+                if entry.entry.short_name() == candidate { Some(()) } else { None }
+            })?;
+
+        if in_use.is_none()
+        {
+            return Ok(candidate);
+        }
+    }
+
+    Err(FsError::AlreadyExists)
+}
+
+
+
+/// Add a new entry to a directory. The short name and long name entries are generated from the
+/// name, the rest of the entry is taken from `entry`. The directory is grown by a cluster if it
+/// doesn't have enough free entries in a row.
+pub fn add_entry(state: &mut Fat32State,
+                 directory: u32,
+                 name: &str,
+                 entry: &ShortEntry) -> FsResult<EntryLocation>
+{
+    state.check_writable()?;
+    validate_name(name)?;
+
+    let (short, case, long_entries) = match exact_short_name(name)
+        {
+            Some((short, case)) => (short, case, 0),
+            None                =>
+                {
+                    let short = generate_short_name(state, directory, name)?;
+                    let length = name.encode_utf16().count();
+
+                    (short, 0, length.div_ceil(LONG_NAME_CHARACTERS))
+                }
+        };
+
+    // Find a run of free slots big enough for the entries.
+    let needed = long_entries as u32 + 1;
+    let mut run_start = 0;
+    let mut run_length = 0;
+    let mut slot_count = 0;
+
+    for_each_slot(state, directory, |index, slot|
+        {
+            slot_count = index + 1;
+
+            if    slot[0] == END_MARKER
+               || slot[0] == DELETED_MARKER
+            {
+                if run_length == 0
+                {
+                    run_start = index;
+                }
+
+                run_length += 1;
+
+                if run_length == needed
+                {
+                    return Some(());
+                }
+            }
+            else
+            {
+                run_length = 0;
+            }
+
+            None
+        })?;
+
+    // Grow the directory if needed, a free run at the end of the directory carries on into the new
+    // clusters.
+    let entries_per_cluster = (state.boot.cluster_size() / ENTRY_SIZE) as u32;
+
+    while run_length < needed
+    {
+        if slot_count + entries_per_cluster > MAX_DIRECTORY_ENTRIES
+        {
+            return Err(FsError::NoSpace);
+        }
+
+        let length = chain_length(state, directory)?;
+        let last = cluster_at(state, directory, length - 1)?
+                       .ok_or(FsError::Corrupted("Directory chain changed while growing."))?;
+
+        allocate_cluster(state, Some(last))?;
+
+        if run_length == 0
+        {
+            run_start = slot_count;
+        }
+
+        run_length += entries_per_cluster;
+        slot_count += entries_per_cluster;
+    }
+
+    // Write the long name entries, they're stored in reverse order with the end of the name first.
+    let checksum = short_name_checksum(&short);
+    let mut units = [0u16; MAX_LONG_ENTRIES * LONG_NAME_CHARACTERS];
+    let mut unit_count = 0;
+
+    for unit in name.encode_utf16()
+    {
+        units[unit_count] = unit;
+        unit_count += 1;
+    }
+
+    for slot_index in 0..long_entries
+    {
+        let sequence = long_entries - slot_index;
+        let mut slot = [0u8; ENTRY_SIZE];
+
+        let last_flag = if slot_index == 0 { LAST_LONG_ENTRY } else { 0 };
+
+        slot[LONG_ORDER_OFFSET] = sequence as u8 | last_flag;
+        slot[ATTRIBUTES_OFFSET] = ATTRIBUTE_LONG_NAME;
+        slot[LONG_CHECKSUM_OFFSET] = checksum;
+
+        // The name is terminated with a 0 if it doesn't fill the last entry, the rest of the entry
+        // is padded with 0xffff.
+        for (position, &offset) in LONG_NAME_OFFSETS.iter().enumerate()
+        {
+            let character = (sequence - 1) * LONG_NAME_CHARACTERS + position;
+            let unit = if character < unit_count
+                {
+                    units[character]
+                }
+                else if character == unit_count
+                {
+                    0
+                }
+                else
+                {
+                    0xffff
+                };
+
+            write_u16(&mut slot, offset, unit);
+        }
+
+        write_slot(state, directory, run_start + slot_index as u32, &slot)?;
+    }
+
+    let mut entry = entry.clone();
+
+    entry.set_short_name(&short, case);
+
+    let location = EntryLocation { directory, index: run_start + long_entries as u32 };
+
+    write_entry(state, location, &entry)?;
+
+    Ok(location)
+}
+
+
+
+/// Remove an entry, and its long name entries, from its directory.
+pub fn remove_entry(state: &mut Fat32State, entry: &FatEntry) -> FsResult<()>
+{
+    for index in entry.first_index..=entry.location.index
+    {
+        let (sector, offset) = slot_location(state, entry.location.directory, index)?;
+
+        state.check_writable()?;
+        state.cache.write(sector)?[offset] = DELETED_MARKER;
+    }
+
+    Ok(())
+}
+
+
+
+/// Fill in the . and .. entries of a newly allocated directory cluster. The parent of a directory
+/// in the root directory is recorded as cluster 0.
+pub fn initialize_directory(state: &mut Fat32State,
+                            cluster: u32,
+                            parent: u32,
+                            time: u64) -> FsResult<()>
+{
+    for (index, name, target) in [ (0, b".          ", cluster), (1, b"..         ", parent) ]
+    {
+        let mut entry = ShortEntry::new_empty(ATTRIBUTE_DIRECTORY, time);
+
+        entry.set_short_name(name, 0);
+        entry.set_first_cluster(target);
+
+        write_slot(state, cluster, index, &entry.raw)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,652 @@
+// The VFS view of a file or directory on a FAT32 volume. FAT doesn't have inodes, everything the
+// VFS needs to know about a file is kept in its directory entry, so the object records where the
+// entry lives. The root directory has no entry at all, its first cluster comes from the boot
+// sector.
+//
+// As with ext2 every operation locks the filesystem and re-reads the entry through the cache.
+//
+// FAT has no owners or permission bits, files are reported as owned by root with permissions
+// derived from the read-only attribute.
+
+use alloc::{ sync::Arc, vec::Vec };
+
+use crate::filesystems::{ current_time,
+                          fat32::{ directory::{ add_entry,
+                                                find_entry,
+                                                initialize_directory,
+                                                is_empty,
+                                                list_entries,
+                                                read_entry,
+                                                remove_entry,
+                                                write_entry,
+                                                EntryLocation,
+                                                ShortEntry,
+                                                ATTRIBUTE_ARCHIVE,
+                                                ATTRIBUTE_DIRECTORY,
+                                                ATTRIBUTE_READ_ONLY },
+                                   table::{ allocate_cluster,
+                                            chain_length,
+                                            cluster_at,
+                                            free_chain,
+                                            next_cluster,
+                                            truncate_chain },
+                                   Fat32Filesystem,
+                                   Fat32State },
+                          inode::{ DirectoryEntry, FileType, Inode, Metadata },
+                          FsError,
+                          FsResult };
+
+
+
+/// The largest file FAT32 can hold, the size field is 32 bits.
+const MAX_FILE_SIZE: u64 = 0xffff_ffff;
+
+/// The inode number reported for the root directory.
+const ROOT_INODE_NUMBER: u64 = 1;
+
+/// Permissions reported for directories and files, and for files with the read-only attribute.
+const DIRECTORY_MODE: u16 = 0o755;
+const FILE_MODE: u16 = 0o644;
+const READ_ONLY_DIRECTORY_MODE: u16 = 0o555;
+const READ_ONLY_FILE_MODE: u16 = 0o444;
+
+/// Mask of the write permission bits.
+const WRITE_PERMISSIONS: u16 = 0o222;
+
+
+
+/// A file or directory on a mounted FAT32 filesystem.
+pub struct Fat32Inode
+{
+    filesystem: Arc<Fat32Filesystem>,   // The filesystem the file lives on.
+    location: Option<EntryLocation>     // Where the file's entry is, `None` for the root directory.
+}
+
+
+
+impl Fat32Inode
+{
+    /// Create the VFS object for a file on the filesystem.
+    pub fn new(filesystem: Arc<Fat32Filesystem>, location: Option<EntryLocation>) -> Self
+    {
+        Fat32Inode { filesystem, location }
+    }
+
+    /// The number reported to the VFS, derived from where the entry is stored.
+    fn inode_number(&self) -> u64
+    {
+        match self.location
+        {
+            Some(location) => (location.directory as u64) << 32 | location.index as u64,
+            None           => ROOT_INODE_NUMBER
+        }
+    }
+
+    /// Find the first cluster of the directory, making sure that this is a directory.
+    fn directory_cluster(&self, state: &mut Fat32State) -> FsResult<u32>
+    {
+        let Some(location) = self.location
+        else
+        {
+            return Ok(state.boot.root_cluster);
+        };
+
+        let entry = read_entry(state, location)?;
+
+        if !entry.is_directory()
+        {
+            return Err(FsError::NotADirectory);
+        }
+
+        match entry.first_cluster()
+        {
+            0       => Err(FsError::Corrupted("Directory has no clusters.")),
+            cluster => Ok(cluster)
+        }
+    }
+
+    /// Read the entry for a data operation, making sure this is a regular file.
+    fn file_entry(&self, state: &mut Fat32State) -> FsResult<(EntryLocation, ShortEntry)>
+    {
+        let location = self.location.ok_or(FsError::IsADirectory)?;
+        let entry = read_entry(state, location)?;
+
+        if entry.is_directory()
+        {
+            return Err(FsError::IsADirectory);
+        }
+
+        Ok((location, entry))
+    }
+
+    /// Apply a change to the entry and write it back.
+    fn update(&self, change: impl FnOnce(&mut ShortEntry)) -> FsResult<()>
+    {
+        let location = self.location.ok_or(FsError::Unsupported)?;
+        let mut state = self.filesystem.lock();
+
+        state.check_writable()?;
+
+        let mut entry = read_entry(&mut state, location)?;
+
+        change(&mut entry);
+
+        write_entry(&mut state, location, &entry)
+    }
+
+    /// Create the VFS object for an entry in this directory.
+    fn child(&self, location: EntryLocation) -> Arc<dyn Inode>
+    {
+        Arc::new(Fat32Inode::new(self.filesystem.clone(), Some(location)))
+    }
+}
+
+
+
+/// The file type of an entry.
+fn file_type_of(entry: &ShortEntry) -> FileType
+{
+    if entry.is_directory() { FileType::Directory } else { FileType::Regular }
+}
+
+
+
+/// Read from a file's clusters.
+fn read_data(state: &mut Fat32State,
+             entry: &ShortEntry,
+             offset: u64,
+             buffer: &mut [u8]) -> FsResult<usize>
+{
+    let size = entry.size() as u64;
+
+    if    offset >= size
+       || entry.first_cluster() == 0
+    {
+        return Ok(0);
+    }
+
+    let cluster_size = state.boot.cluster_size() as u64;
+    let bytes_per_sector = state.boot.bytes_per_sector as u64;
+    let length = (buffer.len() as u64).min(size - offset) as usize;
+    let mut cluster = cluster_at(state, entry.first_cluster(), offset / cluster_size)?
+                          .ok_or(FsError::Corrupted("File is shorter than its size."))?;
+    let mut done = 0;
+
+    while done < length
+    {
+        let position = offset + done as u64;
+        let cluster_offset = position % cluster_size;
+
+        if    done > 0
+           && cluster_offset == 0
+        {
+            cluster = next_cluster(state, cluster)?
+                          .ok_or(FsError::Corrupted("File is shorter than its size."))?;
+        }
+
+        let sector = state.boot.cluster_sector(cluster) + cluster_offset / bytes_per_sector;
+        let sector_offset = (position % bytes_per_sector) as usize;
+        let count = (bytes_per_sector as usize - sector_offset).min(length - done);
+
+        state.cache.read_into(sector, sector_offset, &mut buffer[done..done + count])?;
+        done += count;
+    }
+
+    Ok(length)
+}
+
+
+
+/// Write to a file's clusters, allocating and linking new clusters as needed and growing the
+/// recorded size. When `data` is `None` the range is filled with zeros.
+fn write_data(state: &mut Fat32State,
+              entry: &mut ShortEntry,
+              offset: u64,
+              length: usize,
+              data: Option<&[u8]>) -> FsResult<usize>
+{
+    let end = offset.checked_add(length as u64).ok_or(FsError::FileTooLarge)?;
+
+    if end > MAX_FILE_SIZE
+    {
+        return Err(FsError::FileTooLarge);
+    }
+
+    if length == 0
+    {
+        return Ok(0);
+    }
+
+    if entry.first_cluster() == 0
+    {
+        entry.set_first_cluster(allocate_cluster(state, None)?);
+    }
+
+    let cluster_size = state.boot.cluster_size() as u64;
+    let bytes_per_sector = state.boot.bytes_per_sector as u64;
+
+    // Walk to the cluster holding the offset, extending the chain if the file doesn't reach it yet.
+    let mut cluster = entry.first_cluster();
+
+    for _ in 0..offset / cluster_size
+    {
+        cluster = match next_cluster(state, cluster)?
+            {
+                Some(next) => next,
+                None       => allocate_cluster(state, Some(cluster))?
+            };
+    }
+
+    let mut done = 0;
+
+    while done < length
+    {
+        let position = offset + done as u64;
+        let cluster_offset = position % cluster_size;
+
+        if    done > 0
+           && cluster_offset == 0
+        {
+            cluster = match next_cluster(state, cluster)?
+                {
+                    Some(next) => next,
+                    None       =>
+                        {
+                            match allocate_cluster(state, Some(cluster))
+                            {
+                                Ok(next) => next,
+
+                                // Report a partial write if we ran out of space part way through.
+                                Err(FsError::NoSpace) => break,
+                                Err(error) => return Err(error)
+                            }
+                        }
+                };
+        }
+
+        let sector = state.boot.cluster_sector(cluster) + cluster_offset / bytes_per_sector;
+        let sector_offset = (position % bytes_per_sector) as usize;
+        let count = (bytes_per_sector as usize - sector_offset).min(length - done);
+
+        match data
+        {
+            Some(data) => state.cache.write_from(sector, sector_offset, &data[done..done + count])?,
+            None       => state.cache.write(sector)?[sector_offset..sector_offset + count].fill(0)
+        }
+
+        done += count;
+    }
+
+    let end = offset + done as u64;
+
+    if end > entry.size() as u64
+    {
+        entry.set_size(end as u32);
+    }
+
+    Ok(done)
+}
+
+
+
+/// Change the size of a file, freeing clusters past the new end or zero filling the new space.
+fn resize(state: &mut Fat32State, entry: &mut ShortEntry, size: u64) -> FsResult<()>
+{
+    let old_size = entry.size() as u64;
+
+    if size > old_size
+    {
+        let length = (size - old_size) as usize;
+
+        if write_data(state, entry, old_size, length, None)? < length
+        {
+            return Err(FsError::NoSpace);
+        }
+
+        return Ok(());
+    }
+
+    let first = entry.first_cluster();
+
+    if first != 0
+    {
+        if size == 0
+        {
+            free_chain(state, first)?;
+            entry.set_first_cluster(0);
+        }
+        else
+        {
+            let cluster_size = state.boot.cluster_size() as u64;
+            let last = cluster_at(state, first, (size - 1) / cluster_size)?
+                           .ok_or(FsError::Corrupted("File is shorter than its size."))?;
+
+            truncate_chain(state, last)?;
+        }
+    }
+
+    entry.set_size(size as u32);
+
+    Ok(())
+}
+
+
+
+impl Inode for Fat32Inode
+{
+    fn metadata(&self) -> FsResult<Metadata>
+    {
+        let mut state = self.filesystem.lock();
+        let cluster_size = state.boot.cluster_size() as u64;
+
+        let (entry, size, allocated) = match self.location
+            {
+                Some(location) =>
+                    {
+                        let entry = read_entry(&mut state, location)?;
+                        let clusters = match entry.first_cluster()
+                            {
+                                0     => 0,
+                                first => chain_length(&mut state, first)?
+                            };
+                        let allocated = clusters * cluster_size;
+                        let size = if entry.is_directory()
+                            {
+                                allocated
+                            }
+                            else
+                            {
+                                entry.size() as u64
+                            };
+
+                        (Some(entry), size, allocated)
+                    },
+
+                None =>
+                    {
+                        let root_cluster = state.boot.root_cluster;
+                        let allocated = chain_length(&mut state, root_cluster)? * cluster_size;
+
+                        (None, allocated, allocated)
+                    }
+            };
+
+        let is_directory = entry.as_ref().is_none_or(|entry| entry.is_directory());
+        let is_read_only = entry.as_ref().is_some_and(|entry| entry.is_read_only());
+
+        let mode = match (is_directory, is_read_only)
+            {
+                (true, false)  => DIRECTORY_MODE,
+                (true, true)   => READ_ONLY_DIRECTORY_MODE,
+                (false, false) => FILE_MODE,
+                (false, true)  => READ_ONLY_FILE_MODE
+            };
+
+        let (access_time, modify_time) = entry.as_ref()
+                                              .map_or((0, 0), |entry| (entry.access_time(),
+                                                                       entry.modify_time()));
+
+        Ok(Metadata
+            {
+                inode_number: self.inode_number(),
+                file_type: if is_directory { FileType::Directory } else { FileType::Regular },
+                mode,
+                user_id: 0,
+                group_id: 0,
+                size,
+                link_count: 1,
+                access_time,
+                modify_time,
+                change_time: modify_time,
+                block_size: cluster_size as u32,
+                blocks: allocated / 512
+            })
+    }
+
+    /// FAT can only record whether a file is writable, so that's all we take from the mode.
+    fn set_mode(&self, mode: u16) -> FsResult<()>
+    {
+        self.update(|entry|
+            {
+                let attributes = entry.attributes() & !ATTRIBUTE_READ_ONLY;
+
+                entry.set_attributes(if mode & WRITE_PERMISSIONS == 0
+                    {
+                        attributes | ATTRIBUTE_READ_ONLY
+                    }
+                    else
+                    {
+                        attributes
+                    });
+            })
+    }
+
+    fn set_times(&self, access_time: u64, modify_time: u64) -> FsResult<()>
+    {
+        self.update(|entry|
+            {
+                entry.set_access_time(access_time);
+                entry.set_modify_time(modify_time);
+            })
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> FsResult<usize>
+    {
+        let mut state = self.filesystem.lock();
+        let (_, entry) = self.file_entry(&mut state)?;
+
+        read_data(&mut state, &entry, offset, buffer)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> FsResult<usize>
+    {
+        let mut state = self.filesystem.lock();
+
+        state.check_writable()?;
+
+        let (location, mut entry) = self.file_entry(&mut state)?;
+
+        if entry.is_read_only()
+        {
+            return Err(FsError::PermissionDenied);
+        }
+
+        // Writing past the end of the file leaves a gap that must read back as zeros, FAT has no
+        // holes so fill it in first.
+        let size = entry.size() as u64;
+
+        let result = if offset > size
+            {
+                let gap = (offset - size) as usize;
+
+                match write_data(&mut state, &mut entry, size, gap, None)
+                {
+                    Ok(written) if written < gap => Err(FsError::NoSpace),
+                    Ok(_)                         => write_data(&mut state,
+                                                                &mut entry,
+                                                                offset,
+                                                                buffer.len(),
+                                                                Some(buffer)),
+                    Err(error)                    => Err(error)
+                }
+            }
+            else
+            {
+                write_data(&mut state, &mut entry, offset, buffer.len(), Some(buffer))
+            };
+
+        // Clusters may have been allocated even if the write failed part way, so always save the
+        // entry.
+        entry.set_modify_time(current_time());
+        entry.set_attributes(entry.attributes() | ATTRIBUTE_ARCHIVE);
+        write_entry(&mut state, location, &entry)?;
+
+        result
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()>
+    {
+        let mut state = self.filesystem.lock();
+
+        state.check_writable()?;
+
+        let (location, mut entry) = self.file_entry(&mut state)?;
+
+        if entry.is_read_only()
+        {
+            return Err(FsError::PermissionDenied);
+        }
+
+        if size > MAX_FILE_SIZE
+        {
+            return Err(FsError::FileTooLarge);
+        }
+
+        let result = resize(&mut state, &mut entry, size);
+
+        entry.set_modify_time(current_time());
+        entry.set_attributes(entry.attributes() | ATTRIBUTE_ARCHIVE);
+        write_entry(&mut state, location, &entry)?;
+
+        result
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>>
+    {
+        let mut state = self.filesystem.lock();
+        let directory = self.directory_cluster(&mut state)?;
+        let found = find_entry(&mut state, directory, name)?.ok_or(FsError::NotFound)?;
+
+        drop(state);
+
+        Ok(self.child(found.location))
+    }
+
+    fn read_directory(&self) -> FsResult<Vec<DirectoryEntry>>
+    {
+        let mut state = self.filesystem.lock();
+        let directory = self.directory_cluster(&mut state)?;
+        let mut entries = Vec::new();
+
+        for (name, found) in list_entries(&mut state, directory)?
+        {
+            let location = found.location;
+
+            entries.push(DirectoryEntry
+                {
+                    name,
+                    inode_number: (location.directory as u64) << 32 | location.index as u64,
+                    file_type: file_type_of(&found.entry)
+                });
+        }
+
+        Ok(entries)
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u16) -> FsResult<Arc<dyn Inode>>
+    {
+        let mut state = self.filesystem.lock();
+
+        state.check_writable()?;
+
+        let directory = self.directory_cluster(&mut state)?;
+
+        if find_entry(&mut state, directory, name)?.is_some()
+        {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let now = current_time();
+        let read_only = if mode & WRITE_PERMISSIONS == 0 { ATTRIBUTE_READ_ONLY } else { 0 };
+
+        let location = match file_type
+            {
+                FileType::Regular =>
+                    {
+                        let entry = ShortEntry::new_empty(ATTRIBUTE_ARCHIVE | read_only, now);
+
+                        add_entry(&mut state, directory, name, &entry)?
+                    },
+
+                FileType::Directory =>
+                    {
+                        // The .. entry of a directory in the root refers to cluster 0, not the root
+                        // directory's real cluster.
+                        let parent = if self.location.is_some() { directory } else { 0 };
+                        let cluster = allocate_cluster(&mut state, None)?;
+                        let mut entry = ShortEntry::new_empty(ATTRIBUTE_DIRECTORY | read_only, now);
+
+                        entry.set_first_cluster(cluster);
+
+                        // Give the cluster back if we can't set the directory up.
+                        let result = initialize_directory(&mut state, cluster, parent, now)
+                            .and_then(|_| add_entry(&mut state, directory, name, &entry));
+
+                        match result
+                        {
+                            Ok(location) => location,
+                            Err(error)   =>
+                                {
+                                    let _ = free_chain(&mut state, cluster);
+                                    return Err(error);
+                                }
+                        }
+                    },
+
+                _ => return Err(FsError::Unsupported)
+            };
+
+        // The root directory has no entry to record its modification time in.
+        if let Some(own_location) = self.location
+        {
+            let mut own_entry = read_entry(&mut state, own_location)?;
+
+            own_entry.set_modify_time(now);
+            write_entry(&mut state, own_location, &own_entry)?;
+        }
+
+        drop(state);
+
+        Ok(self.child(location))
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()>
+    {
+        let mut state = self.filesystem.lock();
+
+        state.check_writable()?;
+
+        let directory = self.directory_cluster(&mut state)?;
+        let found = find_entry(&mut state, directory, name)?.ok_or(FsError::NotFound)?;
+        let first = found.entry.first_cluster();
+
+        if    found.entry.is_directory()
+           && first != 0
+           && !is_empty(&mut state, first)?
+        {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+
+        remove_entry(&mut state, &found)?;
+
+        if first != 0
+        {
+            free_chain(&mut state, first)?;
+        }
+
+        if let Some(own_location) = self.location
+        {
+            let mut own_entry = read_entry(&mut state, own_location)?;
+
+            own_entry.set_modify_time(current_time());
+            write_entry(&mut state, own_location, &own_entry)?;
+        }
+
+        Ok(())
+    }
+
+    fn sync(&self) -> FsResult<()>
+    {
+        self.filesystem.lock().sync()
+    }
+}
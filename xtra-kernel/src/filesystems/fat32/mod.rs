@@ -0,0 +1,252 @@
+// Driver for the FAT32 filesystem, used for the boot partition so that the bootloader, kernel
+// images and boot configuration can be shared with any other system that can read a FAT volume.
+//
+// VFAT long file names are supported for both reading and writing. The free cluster count and the
+// allocation hint are kept in memory and written back to the FSInfo sector when the filesystem is
+// synced.
+//
+// Like the ext2 driver all of the filesystem's mutable state lives behind a single lock and all
+// access to the disk goes through a write back cache, here one of logical sectors.
+
+use alloc::{ string::String, sync::{ Arc, Weak } };
+
+use crate::{ devices::block_devices::{ BlockDevice, SECTOR_SIZE },
+             filesystems::{ block_cache::BlockCache,
+                            fat32::{ boot_sector::{ BootSector, FsInfo, FSINFO_UNKNOWN },
+                                     inode::Fat32Inode,
+                                     table::{ count_free_clusters, free_count_from_fsinfo } },
+                            inode::Inode,
+                            mount::MountOptions,
+                            Filesystem,
+                            FilesystemDriver,
+                            FilesystemDriverRegistry,
+                            FilesystemStatistics,
+                            FsError,
+                            FsResult },
+             locking::spin_mutex::{ SpinMutex, SpinMutexGuard } };
+
+
+
+/// The boot sector and FSInfo sector.
+pub mod boot_sector;
+
+/// The file allocation table and cluster chains.
+pub mod table;
+
+/// Reading and modifying directory entries, including long file names.
+pub mod directory;
+
+/// Conversion between FAT timestamps and Unix time.
+pub mod timestamp;
+
+/// The VFS inode implementation for FAT32.
+pub mod inode;
+
+
+
+/// The number of sectors kept in the cache of each mounted filesystem.
+const CACHE_SECTORS: usize = 64;
+
+
+
+/// The mutable state of a mounted FAT32 filesystem, protected by the filesystem's lock.
+pub struct Fat32State
+{
+    /// Cache of the filesystem's logical sectors.
+    pub cache: BlockCache,
+
+    /// The decoded boot sector.
+    pub boot: BootSector,
+
+    /// Does the volume have a valid FSInfo sector to keep up to date?
+    pub has_fsinfo: bool,
+
+    /// The number of free clusters.
+    pub free_count: u32,
+
+    /// Where to start searching for the next free cluster.
+    pub next_free: u32,
+
+    /// Is the filesystem mounted read-only?
+    pub read_only: bool
+}
+
+
+
+impl Fat32State
+{
+    /// Fail with `FsError::ReadOnly` if the filesystem can't be changed.
+    pub fn check_writable(&self) -> FsResult<()>
+    {
+        if self.read_only
+        {
+            return Err(FsError::ReadOnly);
+        }
+
+        Ok(())
+    }
+
+    /// Write the free cluster information and all cached sectors back to the device.
+    pub fn sync(&mut self) -> FsResult<()>
+    {
+        if    self.has_fsinfo
+           && !self.read_only
+        {
+            let fsinfo = FsInfo { free_count: self.free_count, next_free: self.next_free };
+            let sector = self.cache.write(self.boot.fsinfo_sector as u64)?;
+
+            fsinfo.write(sector);
+        }
+
+        self.cache.flush()
+    }
+}
+
+
+
+/// A mounted FAT32 filesystem.
+pub struct Fat32Filesystem
+{
+    /// A reference to ourselves, handed to the inodes we create so they can find their way back.
+    this: Weak<Fat32Filesystem>,
+
+    /// The mutable state of the filesystem.
+    state: SpinMutex<Fat32State>
+}
+
+
+
+impl Fat32Filesystem
+{
+    /// Lock the filesystem's state for the duration of an operation.
+    pub fn lock(&self) -> SpinMutexGuard<'_, Fat32State>
+    {
+        self.state.lock()
+    }
+
+    /// Get a strong reference to the filesystem for a new inode.
+    pub fn arc(&self) -> Arc<Fat32Filesystem>
+    {
+        self.this.upgrade().unwrap()
+    }
+}
+
+
+
+impl Filesystem for Fat32Filesystem
+{
+    fn name(&self) -> &'static str
+    {
+        "fat32"
+    }
+
+    fn root(&self) -> FsResult<Arc<dyn Inode>>
+    {
+        Ok(Arc::new(Fat32Inode::new(self.arc(), None)))
+    }
+
+    fn statistics(&self) -> FsResult<FilesystemStatistics>
+    {
+        let state = self.lock();
+
+        Ok(FilesystemStatistics
+            {
+                block_size: state.boot.cluster_size() as u32,
+                total_blocks: state.boot.cluster_count() as u64,
+                free_blocks: state.free_count as u64,
+                total_inodes: 0,
+                free_inodes: 0
+            })
+    }
+
+    fn sync(&self) -> FsResult<()>
+    {
+        self.lock().sync()
+    }
+}
+
+
+
+/// Register the FAT32 driver with the VFS.
+pub fn register_filesystem_driver(registry: &mut FilesystemDriverRegistry)
+{
+    registry.insert("fat32",
+                    FilesystemDriver
+                        {
+                            mount: mount_fat32,
+                            read_label: read_fat32_label
+                        });
+}
+
+
+
+/// Mount the FAT32 filesystem found on the device.
+fn mount_fat32(device: Arc<dyn BlockDevice>,
+               options: &MountOptions) -> FsResult<Arc<dyn Filesystem>>
+{
+    // Read the boot sector directly from the device, we need the logical sector size from it before
+    // we can create the cache.
+    let mut raw = [0u8; SECTOR_SIZE];
+
+    device.read_sectors(0, &mut raw)?;
+
+    let boot = BootSector::new(&raw)?;
+    let read_only = options.read_only || device.is_read_only();
+    let cache = BlockCache::new(device.clone(), boot.bytes_per_sector, CACHE_SECTORS)?;
+
+    let mut state = Fat32State
+        {
+            cache,
+            boot,
+            has_fsinfo: false,
+            free_count: 0,
+            next_free: FSINFO_UNKNOWN,
+            read_only
+        };
+
+    // Load the free cluster information from the FSInfo sector. If it's missing or the count isn't
+    // known we have to count the free clusters ourselves.
+    let fsinfo_sector = state.boot.fsinfo_sector;
+    let fsinfo = if    fsinfo_sector != 0
+                    && fsinfo_sector < state.boot.reserved_sectors
+        {
+            FsInfo::new(state.cache.read(fsinfo_sector as u64)?)
+        }
+        else
+        {
+            None
+        };
+
+    state.has_fsinfo = fsinfo.is_some();
+
+    let known_free = fsinfo.and_then(|fsinfo| free_count_from_fsinfo(&state, fsinfo.free_count));
+
+    state.next_free = fsinfo.map_or(FSINFO_UNKNOWN, |fsinfo| fsinfo.next_free);
+    state.free_count = match known_free
+        {
+            Some(free_count) => free_count,
+            None             =>
+                {
+                    println!("  fat32: Counting free clusters on {}...", device.name());
+                    count_free_clusters(&mut state)?
+                }
+        };
+
+    Ok(Arc::new_cyclic(|this| Fat32Filesystem
+        {
+            this: this.clone(),
+            state: SpinMutex::new(state)
+        }))
+}
+
+
+
+/// Read the volume label of the FAT32 filesystem found on the device.
+fn read_fat32_label(device: &dyn BlockDevice) -> FsResult<String>
+{
+    let mut raw = [0u8; SECTOR_SIZE];
+
+    device.read_sectors(0, &mut raw)?;
+
+    Ok(BootSector::new(&raw)?.label())
+}
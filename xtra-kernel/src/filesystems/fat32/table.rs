@@ -0,0 +1,311 @@
+// The file allocation table. Every cluster in the volume has a 32 bit entry in the FAT, (of which
+// only the low 28 bits are used,) holding the number of the next cluster in the chain, an end of
+// chain marker or 0 if the cluster is free. Files and directories are stored as chains of clusters
+// starting from the cluster recorded in their directory entry.
+//
+// The volume normally keeps two copies of the FAT which are kept in step with each other.
+
+use crate::filesystems::{ fat32::{ boot_sector::FSINFO_UNKNOWN, Fat32State },
+                          read_u32,
+                          write_u32,
+                          FsError,
+                          FsResult };
+
+
+
+/// Mask for the part of an entry that holds the cluster number.
+const ENTRY_MASK: u32 = 0x0fff_ffff;
+
+/// Entries at or above this value mark the end of a chain.
+const END_OF_CHAIN_START: u32 = 0x0fff_fff8;
+
+/// The value written to mark the end of a chain.
+const END_OF_CHAIN: u32 = 0x0fff_ffff;
+
+/// Marker for a cluster that has been found to be bad.
+const BAD_CLUSTER: u32 = 0x0fff_fff7;
+
+/// Marker for a free cluster.
+const FREE_CLUSTER: u32 = 0;
+
+/// The first valid data cluster.
+pub const FIRST_CLUSTER: u32 = 2;
+
+
+
+/// Find the sector and offset within it holding a cluster's entry in the given FAT.
+fn entry_location(state: &Fat32State, fat: u32, cluster: u32) -> (u64, usize)
+{
+    let byte_offset = cluster as usize * 4;
+    let bytes_per_sector = state.boot.bytes_per_sector;
+
+    (state.boot.fat_sector(fat) + (byte_offset / bytes_per_sector) as u64,
+     byte_offset % bytes_per_sector)
+}
+
+
+
+/// Is the cluster number one that can hold data?
+pub fn is_valid_cluster(state: &Fat32State, cluster: u32) -> bool
+{
+    cluster >= FIRST_CLUSTER && cluster < state.boot.cluster_count() + FIRST_CLUSTER
+}
+
+
+
+/// Read a cluster's FAT entry.
+fn read_entry(state: &mut Fat32State, cluster: u32) -> FsResult<u32>
+{
+    let fat = if state.boot.mirrored { 0 } else { state.boot.active_fat };
+    let (sector, offset) = entry_location(state, fat, cluster);
+
+    Ok(read_u32(state.cache.read(sector)?, offset) & ENTRY_MASK)
+}
+
+
+
+/// Write a cluster's FAT entry, to every copy of the FAT when mirroring. The reserved top bits of
+/// the entry are preserved.
+fn write_entry(state: &mut Fat32State, cluster: u32, value: u32) -> FsResult<()>
+{
+    state.check_writable()?;
+
+    let fats = if state.boot.mirrored
+        {
+            0..state.boot.fat_count
+        }
+        else
+        {
+            state.boot.active_fat..state.boot.active_fat + 1
+        };
+
+    for fat in fats
+    {
+        let (sector, offset) = entry_location(state, fat, cluster);
+        let data = state.cache.write(sector)?;
+        let old = read_u32(data, offset);
+
+        write_u32(data, offset, (old & !ENTRY_MASK) | (value & ENTRY_MASK));
+    }
+
+    Ok(())
+}
+
+
+
+/// Find the cluster following the given one in its chain, `None` at the end of the chain.
+pub fn next_cluster(state: &mut Fat32State, cluster: u32) -> FsResult<Option<u32>>
+{
+    let entry = read_entry(state, cluster)?;
+
+    if entry >= END_OF_CHAIN_START
+    {
+        return Ok(None);
+    }
+
+    if    entry == FREE_CLUSTER
+       || entry == BAD_CLUSTER
+       || !is_valid_cluster(state, entry)
+    {
+        return Err(FsError::Corrupted("Invalid cluster in FAT chain."));
+    }
+
+    Ok(Some(entry))
+}
+
+
+
+/// Find the cluster at the given position in a chain, `None` if the chain is shorter than that.
+pub fn cluster_at(state: &mut Fat32State, first: u32, index: u64) -> FsResult<Option<u32>>
+{
+    let mut cluster = first;
+
+    for _ in 0..index
+    {
+        cluster = match next_cluster(state, cluster)?
+            {
+                Some(next) => next,
+                None       => return Ok(None)
+            };
+    }
+
+    Ok(Some(cluster))
+}
+
+
+
+/// Count the clusters in a chain.
+pub fn chain_length(state: &mut Fat32State, first: u32) -> FsResult<u64>
+{
+    let mut length = 1;
+    let mut cluster = first;
+
+    while let Some(next) = next_cluster(state, cluster)?
+    {
+        length += 1;
+        cluster = next;
+
+        if length > state.boot.cluster_count() as u64
+        {
+            return Err(FsError::Corrupted("Loop in FAT chain."));
+        }
+    }
+
+    Ok(length)
+}
+
+
+
+/// Fill a cluster with zeros.
+pub fn zero_cluster(state: &mut Fat32State, cluster: u32) -> FsResult<()>
+{
+    let first_sector = state.boot.cluster_sector(cluster);
+
+    for sector in 0..state.boot.sectors_per_cluster as u64
+    {
+        state.cache.zeroed(first_sector + sector)?;
+    }
+
+    Ok(())
+}
+
+
+
+/// Allocate a zero filled cluster, linking it on to the end of a chain if `previous` is given. The
+/// search for a free cluster starts from the FSInfo hint.
+pub fn allocate_cluster(state: &mut Fat32State, previous: Option<u32>) -> FsResult<u32>
+{
+    state.check_writable()?;
+
+    if state.free_count == 0
+    {
+        return Err(FsError::NoSpace);
+    }
+
+    let cluster_count = state.boot.cluster_count();
+    let start = if is_valid_cluster(state, state.next_free)
+        {
+            state.next_free
+        }
+        else
+        {
+            FIRST_CLUSTER
+        };
+
+    for offset in 0..cluster_count
+    {
+        let cluster = FIRST_CLUSTER + (start - FIRST_CLUSTER + offset) % cluster_count;
+
+        if read_entry(state, cluster)? != FREE_CLUSTER
+        {
+            continue;
+        }
+
+        write_entry(state, cluster, END_OF_CHAIN)?;
+        zero_cluster(state, cluster)?;
+
+        if let Some(previous) = previous
+        {
+            write_entry(state, previous, cluster)?;
+        }
+
+        state.next_free = cluster + 1;
+        state.free_count = state.free_count.saturating_sub(1);
+
+        return Ok(cluster);
+    }
+
+    state.free_count = 0;
+
+    Err(FsError::NoSpace)
+}
+
+
+
+/// Free every cluster in the chain starting at `first`.
+pub fn free_chain(state: &mut Fat32State, first: u32) -> FsResult<()>
+{
+    state.check_writable()?;
+
+    let mut cluster = Some(first);
+    let mut freed = 0;
+
+    while let Some(current) = cluster
+    {
+        if freed > state.boot.cluster_count()
+        {
+            return Err(FsError::Corrupted("Loop in FAT chain."));
+        }
+
+        cluster = next_cluster(state, current)?;
+        write_entry(state, current, FREE_CLUSTER)?;
+
+        // Don't write back cached data for a cluster that no longer belongs to anything.
+        let first_sector = state.boot.cluster_sector(current);
+
+        for sector in 0..state.boot.sectors_per_cluster as u64
+        {
+            state.cache.discard(first_sector + sector);
+        }
+
+        freed += 1;
+    }
+
+    state.free_count += freed;
+
+    if first < state.next_free
+    {
+        state.next_free = first;
+    }
+
+    Ok(())
+}
+
+
+
+/// Cut a chain short so that `last` becomes its final cluster, freeing the rest.
+pub fn truncate_chain(state: &mut Fat32State, last: u32) -> FsResult<()>
+{
+    if let Some(next) = next_cluster(state, last)?
+    {
+        write_entry(state, last, END_OF_CHAIN)?;
+        free_chain(state, next)?;
+    }
+
+    Ok(())
+}
+
+
+
+/// Count the free clusters by scanning the whole FAT, used when the FSInfo sector doesn't have a
+/// trustworthy count.
+pub fn count_free_clusters(state: &mut Fat32State) -> FsResult<u32>
+{
+    let mut free = 0;
+
+    for cluster in FIRST_CLUSTER..state.boot.cluster_count() + FIRST_CLUSTER
+    {
+        if read_entry(state, cluster)? == FREE_CLUSTER
+        {
+            free += 1;
+        }
+    }
+
+    Ok(free)
+}
+
+
+
+/// Convert an FSInfo value to a free count, treating out of range values as unknown.
+pub fn free_count_from_fsinfo(state: &Fat32State, value: u32) -> Option<u32>
+{
+    if    value == FSINFO_UNKNOWN
+       || value > state.boot.cluster_count()
+    {
+        None
+    }
+    else
+    {
+        Some(value)
+    }
+}
@@ -0,0 +1,91 @@
+// Conversion between FAT timestamps and Unix time. FAT stores local dates and times packed into 16
+// bit fields, the date counts years from 1980 and the time has a two second resolution. We treat
+// the stored times as UTC.
+
+/// Seconds between the Unix epoch and the FAT epoch, 1980-01-01.
+const FAT_EPOCH: u64 = 315_532_800;
+
+/// The latest time a FAT date can represent, 2107-12-31 23:59:58.
+const FAT_LAST_TIME: u64 = 4_354_819_198;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+
+
+/// Convert a count of days since the Unix epoch to a (year, month, day) civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32)
+{
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096)
+                      / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+
+
+/// Convert a civil date to a count of days since the Unix epoch.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64
+{
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+
+
+/// Convert a FAT date and time to seconds since the Unix epoch. Dates that were never set read as
+/// the FAT epoch.
+pub fn to_unix_time(date: u16, time: u16) -> u64
+{
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0x0f) as u32;
+    let day = (date & 0x1f) as u32;
+
+    if    month == 0
+       || month > 12
+       || day == 0
+    {
+        return FAT_EPOCH;
+    }
+
+    let hours = (time >> 11) as u64;
+    let minutes = ((time >> 5) & 0x3f) as u64;
+    let seconds = ((time & 0x1f) * 2) as u64;
+
+    days_from_civil(year, month, day) as u64 * SECONDS_PER_DAY
+        + hours * 3600
+        + minutes * 60
+        + seconds
+}
+
+
+
+/// Convert seconds since the Unix epoch to a FAT (date, time) pair, clamping to the range FAT can
+/// represent.
+pub fn from_unix_time(time: u64) -> (u16, u16)
+{
+    let time = time.clamp(FAT_EPOCH, FAT_LAST_TIME);
+    let (year, month, day) = civil_from_days((time / SECONDS_PER_DAY) as i64);
+    let seconds_of_day = time % SECONDS_PER_DAY;
+
+    let date = ((year - 1980) as u16) << 9 | (month as u16) << 5 | day as u16;
+    let time = ((seconds_of_day / 3600) as u16) << 11
+               | (((seconds_of_day / 60) % 60) as u16) << 5
+               | ((seconds_of_day % 60) / 2) as u16;
+
+    (date, time)
+}
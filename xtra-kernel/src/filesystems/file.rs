@@ -0,0 +1,374 @@
+// Open files. An open file remembers where in the directory tree it was opened, what it was opened
+// for and the current position within the file. Reads and writes go through the file to the inode
+// that it refers to.
+
+use alloc::{ sync::Arc, vec::Vec };
+
+use crate::{ filesystems::{ inode::{ DirectoryEntry, FileType, Inode, Metadata },
+                            path::{ resolve, resolve_parent, PathLocation },
+                            FsError,
+                            FsResult },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// The permissions given to files created by `open` when the caller doesn't care.
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+
+
+
+/// Builder for the options a file is opened with.
+#[derive(Default)]
+pub struct OpenOptionsBuilder
+{
+    read: bool,
+    write: bool,
+    append: bool,
+    create: bool,
+    exclusive: bool,
+    truncate: bool,
+    directory: bool,
+    mode: Option<u16>
+}
+
+
+
+impl OpenOptionsBuilder
+{
+    pub fn read(mut self) -> Self
+    {
+        self.read = true;
+        self
+    }
+
+    pub fn write(mut self) -> Self
+    {
+        self.write = true;
+        self
+    }
+
+    pub fn append(mut self) -> Self
+    {
+        self.write = true;
+        self.append = true;
+        self
+    }
+
+    pub fn create(mut self) -> Self
+    {
+        self.create = true;
+        self
+    }
+
+    pub fn exclusive(mut self) -> Self
+    {
+        self.exclusive = true;
+        self
+    }
+
+    pub fn truncate(mut self) -> Self
+    {
+        self.truncate = true;
+        self
+    }
+
+    pub fn directory(mut self) -> Self
+    {
+        self.directory = true;
+        self
+    }
+
+    pub fn mode(mut self, mode: u16) -> Self
+    {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub fn build(self) -> OpenOptions
+    {
+        OpenOptions
+            {
+                read: self.read,
+                write: self.write,
+                append: self.append,
+                create: self.create,
+                exclusive: self.exclusive,
+                truncate: self.truncate,
+                directory: self.directory,
+                mode: self.mode.unwrap_or(DEFAULT_FILE_MODE)
+            }
+    }
+}
+
+
+
+/// The options a file is opened with.
+#[derive(Clone, Copy)]
+pub struct OpenOptions
+{
+    /// The file can be read from.
+    pub read: bool,
+
+    /// The file can be written to.
+    pub write: bool,
+
+    /// All writes go to the end of the file.
+    pub append: bool,
+
+    /// Create the file if it doesn't exist.
+    pub create: bool,
+
+    /// Together with create, fail if the file already exists.
+    pub exclusive: bool,
+
+    /// Throw away the existing contents of the file.
+    pub truncate: bool,
+
+    /// Fail if the path doesn't name a directory.
+    pub directory: bool,
+
+    /// The permissions to give the file if it is created.
+    pub mode: u16
+}
+
+
+
+impl OpenOptions
+{
+    /// Create a new `OpenOptions` object that opens an existing file for reading.
+    pub fn new() -> Self
+    {
+        Self::builder().read().build()
+    }
+
+    /// Create a new `OpenOptionsBuilder` to build an `OpenOptions` object with custom values.
+    pub fn builder() -> OpenOptionsBuilder
+    {
+        OpenOptionsBuilder::default()
+    }
+}
+
+
+
+impl Default for OpenOptions
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+
+
+/// Where a seek is relative to.
+#[derive(Clone, Copy)]
+pub enum SeekFrom
+{
+    /// From the start of the file.
+    Start(u64),
+
+    /// From the current position.
+    Current(i64),
+
+    /// From the end of the file.
+    End(i64)
+}
+
+
+
+/// A file opened through the VFS.
+pub struct File
+{
+    /// Where the file lives in the directory tree.
+    location: PathLocation,
+
+    /// What the file was opened for.
+    options: OpenOptions,
+
+    /// The current read/write position within the file.
+    position: SpinMutex<u64>
+}
+
+
+
+impl File
+{
+    /// Where the file lives in the directory tree.
+    pub fn location(&self) -> &PathLocation
+    {
+        &self.location
+    }
+
+    /// What the file was opened for.
+    pub fn options(&self) -> OpenOptions
+    {
+        self.options
+    }
+
+    /// The inode the file refers to.
+    pub fn inode(&self) -> Arc<dyn Inode>
+    {
+        self.location.dentry.inode()
+    }
+
+    /// Get the current metadata of the file.
+    pub fn metadata(&self) -> FsResult<Metadata>
+    {
+        self.inode().metadata()
+    }
+
+    /// Read from the current position, advancing it by the number of bytes read.
+    pub fn read(&self, buffer: &mut [u8]) -> FsResult<usize>
+    {
+        if !self.options.read
+        {
+            return Err(FsError::BadFileMode);
+        }
+
+        let mut position = self.position.lock();
+        let count = self.inode().read_at(*position, buffer)?;
+
+        *position += count as u64;
+
+        Ok(count)
+    }
+
+    /// Write at the current position, (or the end of the file when appending,) advancing the
+    /// position by the number of bytes written.
+    pub fn write(&self, buffer: &[u8]) -> FsResult<usize>
+    {
+        if !self.options.write
+        {
+            return Err(FsError::BadFileMode);
+        }
+
+        self.location.mount.check_writable()?;
+
+        let inode = self.inode();
+        let mut position = self.position.lock();
+
+        if self.options.append
+        {
+            *position = inode.metadata()?.size;
+        }
+
+        let count = inode.write_at(*position, buffer)?;
+
+        *position += count as u64;
+
+        self.location.mount.sync_if_requested()?;
+
+        Ok(count)
+    }
+
+    /// Move the current position, returning the new position.
+    pub fn seek(&self, from: SeekFrom) -> FsResult<u64>
+    {
+        let mut position = self.position.lock();
+
+        let new_position = match from
+            {
+                SeekFrom::Start(offset)   => Some(offset),
+                SeekFrom::Current(offset) => position.checked_add_signed(offset),
+                SeekFrom::End(offset)     => self.metadata()?.size.checked_add_signed(offset)
+            };
+
+        *position = new_position.ok_or(FsError::InvalidArgument)?;
+
+        Ok(*position)
+    }
+
+    /// Change the size of the file.
+    pub fn truncate(&self, size: u64) -> FsResult<()>
+    {
+        if !self.options.write
+        {
+            return Err(FsError::BadFileMode);
+        }
+
+        self.location.mount.check_writable()?;
+        self.inode().truncate(size)?;
+
+        self.location.mount.sync_if_requested()
+    }
+
+    /// Read all of the entries of a directory.
+    pub fn read_directory(&self) -> FsResult<Vec<DirectoryEntry>>
+    {
+        self.inode().read_directory()
+    }
+
+    /// Write any cached data for the file back to its storage.
+    pub fn sync(&self) -> FsResult<()>
+    {
+        self.inode().sync()
+    }
+}
+
+
+
+/// Open the file at the given path.
+pub fn open(path: &str, options: OpenOptions) -> FsResult<Arc<File>>
+{
+    let location = match resolve(path, true)
+        {
+            Ok(location) =>
+                {
+                    if    options.create
+                       && options.exclusive
+                    {
+                        return Err(FsError::AlreadyExists);
+                    }
+
+                    location
+                },
+
+            Err(FsError::NotFound) if options.create =>
+                {
+                    let (parent, name) = resolve_parent(path)?;
+
+                    parent.mount.check_writable()?;
+
+                    let inode = parent.dentry
+                                      .inode()
+                                      .create(&name, FileType::Regular, options.mode)?;
+                    let dentry = parent.dentry.add_child(&name, inode);
+
+                    parent.mount.sync_if_requested()?;
+
+                    PathLocation { mount: parent.mount, dentry }
+                },
+
+            Err(error) => return Err(error)
+        };
+
+    let metadata = location.dentry.inode().metadata()?;
+
+    if metadata.is_directory()
+    {
+        if options.write
+        {
+            return Err(FsError::IsADirectory);
+        }
+    }
+    else if options.directory
+    {
+        return Err(FsError::NotADirectory);
+    }
+
+    if    options.truncate
+       && options.write
+       && metadata.size != 0
+    {
+        location.mount.check_writable()?;
+        location.dentry.inode().truncate(0)?;
+        location.mount.sync_if_requested()?;
+    }
+
+    Ok(Arc::new(File
+        {
+            location,
+            options,
+            position: SpinMutex::new(0)
+        }))
+}
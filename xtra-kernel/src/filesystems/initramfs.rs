@@ -0,0 +1,398 @@
+// Support for the initial RAM disk, (initramfs.) The bootloader can load a cpio archive in the newc
+// format into memory alongside the kernel. At boot we unpack it into an in memory filesystem that
+// is mounted at / so that early userspace and drivers are available before the real root
+// filesystem has been found. Once the real root is mounted it replaces the initramfs.
+//
+// The archive isn't copied, the files in the in memory filesystem refer straight to their data
+// within it. The bootloader reserves the archive's memory so the kernel never reuses it.
+//
+// Several archives may be concatenated together, each one ends with a TRAILER!!! entry and may be
+// followed by zero padding.
+
+use core::{ slice, str::from_utf8 };
+
+use alloc::{ collections::BTreeMap, sync::Arc };
+
+use xtra_kernel_shared::boot_info::XtraBootModuleKind;
+
+use crate::{ boot_info::get_boot_info,
+             filesystems::{ inode::{ FileType, Inode },
+                            mount::{ mount_filesystem, Mount, MountOptions },
+                            ramfs::{ RamFilesystem, RamInode },
+                            FsError,
+                            FsResult },
+             random::device::RandomDevice };
+
+
+
+/// The magic number at the start of every newc header, and that of the variant with checksums.
+const NEWC_MAGIC: &[u8] = b"070701";
+const NEWC_CRC_MAGIC: &[u8] = b"070702";
+
+/// The size of a newc header, the magic number followed by 13 fields of 8 hex digits.
+const NEWC_HEADER_SIZE: usize = 110;
+
+/// The name of the entry that marks the end of an archive.
+const TRAILER_NAME: &str = "TRAILER!!!";
+
+/// The file type bits of a Unix mode and the values for each type.
+const S_IFMT:   u32 = 0o170000;
+const S_IFSOCK: u32 = 0o140000;
+const S_IFLNK:  u32 = 0o120000;
+const S_IFREG:  u32 = 0o100000;
+const S_IFBLK:  u32 = 0o060000;
+const S_IFDIR:  u32 = 0o040000;
+const S_IFCHR:  u32 = 0o020000;
+const S_IFIFO:  u32 = 0o010000;
+
+/// The permission bits of a Unix mode.
+const PERMISSION_MASK: u32 = 0o7777;
+
+/// The permissions given to directories that the archive doesn't have an entry of their own for.
+const DEFAULT_DIRECTORY_MODE: u16 = 0o755;
+
+
+
+/// A single entry read from the archive.
+struct CpioEntry
+{
+    /// The inode number of the entry within the archive, used to find hard links.
+    inode_number: u32,
+
+    /// The Unix mode of the entry, its type and permissions.
+    mode: u32,
+
+    /// The owning user and group.
+    user_id: u32,
+    group_id: u32,
+
+    /// The number of names the entry has within the archive.
+    link_count: u32,
+
+    /// The modification time in seconds since the Unix epoch.
+    modify_time: u32,
+
+    /// The path of the entry.
+    name: &'static str,
+
+    /// The entry's data, the target for symbolic links.
+    data: &'static [u8]
+}
+
+
+
+/// Reads the entries of a newc cpio archive one at a time.
+struct CpioReader
+{
+    archive: &'static [u8],  // The whole of the archive.
+    offset: usize            // Where the next header starts.
+}
+
+
+
+impl CpioReader
+{
+    /// Create a new reader for the archive.
+    fn new(archive: &'static [u8]) -> Self
+    {
+        CpioReader { archive, offset: 0 }
+    }
+
+    /// Read the next entry of the archive, or `None` at the end of the archive. Trailer entries
+    /// are returned like any other entry.
+    fn next_entry(&mut self) -> FsResult<Option<CpioEntry>>
+    {
+        // Skip any padding between concatenated archives.
+        while    self.offset < self.archive.len()
+              && self.archive[self.offset] == 0
+        {
+            self.offset += 1;
+        }
+
+        if self.offset >= self.archive.len()
+        {
+            return Ok(None);
+        }
+
+        let header = self.bytes(self.offset, NEWC_HEADER_SIZE)?;
+
+        if    &header[..6] != NEWC_MAGIC
+           && &header[..6] != NEWC_CRC_MAGIC
+        {
+            return Err(FsError::Corrupted("Initial RAM disk is not a newc cpio archive."));
+        }
+
+        let field = |index: usize| parse_hex(&header[6 + index * 8..6 + (index + 1) * 8]);
+
+        let inode_number = field(0)?;
+        let mode = field(1)?;
+        let user_id = field(2)?;
+        let group_id = field(3)?;
+        let link_count = field(4)?;
+        let modify_time = field(5)?;
+        let file_size = field(6)? as usize;
+        let name_size = field(11)? as usize;
+
+        // The name includes its terminating zero, and both it and the data are padded to a
+        // multiple of 4 bytes.
+        let name_start = self.offset + NEWC_HEADER_SIZE;
+        let name_bytes = self.bytes(name_start, name_size)?;
+
+        let name = match name_bytes.split_last()
+            {
+                Some((0, name)) => from_utf8(name)
+                    .map_err(|_| FsError::Corrupted("Initial RAM disk has an invalid file name."))?,
+
+                _ => return Err(FsError::Corrupted("Initial RAM disk has an invalid file name."))
+            };
+
+        let data_start = (name_start + name_size).next_multiple_of(4);
+        let data = self.bytes(data_start, file_size)?;
+
+        self.offset = (data_start + file_size).next_multiple_of(4);
+
+        Ok(Some(CpioEntry
+            {
+                inode_number,
+                mode,
+                user_id,
+                group_id,
+                link_count,
+                modify_time,
+                name,
+                data
+            }))
+    }
+
+    /// Get a range of the archive's bytes, failing if the archive is too short.
+    fn bytes(&self, start: usize, length: usize) -> FsResult<&'static [u8]>
+    {
+        let archive = self.archive;
+
+        start.checked_add(length)
+             .filter(|&end| end <= archive.len())
+             .map(|end| &archive[start..end])
+             .ok_or(FsError::Corrupted("Initial RAM disk archive is truncated."))
+    }
+}
+
+
+
+/// Parse one of the 8 digit hex fields of a newc header.
+fn parse_hex(digits: &[u8]) -> FsResult<u32>
+{
+    from_utf8(digits).ok()
+                     .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                     .ok_or(FsError::Corrupted("Initial RAM disk has an invalid header."))
+}
+
+
+
+/// Get the type of file from a Unix mode.
+fn file_type_of_mode(mode: u32) -> FsResult<FileType>
+{
+    match mode & S_IFMT
+    {
+        S_IFREG  => Ok(FileType::Regular),
+        S_IFDIR  => Ok(FileType::Directory),
+        S_IFLNK  => Ok(FileType::SymbolicLink),
+        S_IFCHR  => Ok(FileType::CharacterDevice),
+        S_IFBLK  => Ok(FileType::BlockDevice),
+        S_IFIFO  => Ok(FileType::Fifo),
+        S_IFSOCK => Ok(FileType::Socket),
+        _        => Err(FsError::Corrupted("Initial RAM disk entry has an unknown file type."))
+    }
+}
+
+
+
+/// Find the directory the path refers to, creating any missing directories along the way.
+fn make_directories(root: &Arc<RamInode>, path: &str) -> FsResult<Arc<RamInode>>
+{
+    let mut directory = root.clone();
+
+    for name in path.split('/').filter(|name| !name.is_empty())
+    {
+        directory = match directory.child(name)?
+            {
+                Some(child) => child,
+                None        =>
+                    {
+                        let child = RamInode::new_directory(DEFAULT_DIRECTORY_MODE, false);
+
+                        directory.insert(name, child.clone())?;
+                        child
+                    }
+            };
+    }
+
+    Ok(directory)
+}
+
+
+
+/// Unpack all of the entries of the archive into the directory tree under root. Returns the
+/// number of entries unpacked.
+fn unpack(archive: &'static [u8], root: &Arc<RamInode>) -> FsResult<usize>
+{
+    let mut reader = CpioReader::new(archive);
+    let mut hard_links: BTreeMap<u32, Arc<RamInode>> = BTreeMap::new();
+    let mut count = 0;
+
+    while let Some(entry) = reader.next_entry()?
+    {
+        if entry.name == TRAILER_NAME
+        {
+            // Inode numbers are only unique within one of the concatenated archives.
+            hard_links.clear();
+            continue;
+        }
+
+        let file_type = file_type_of_mode(entry.mode)?;
+        let mode = (entry.mode & PERMISSION_MASK) as u16;
+        let path = entry.name.trim_start_matches("./").trim_start_matches('/');
+
+        // The archive's entry for its top directory describes our root.
+        if    path.is_empty()
+           || path == "."
+        {
+            root.set_mode(mode)?;
+            root.set_attributes(entry.user_id, entry.group_id, entry.modify_time as u64);
+            continue;
+        }
+
+        let (parent_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let parent = make_directories(root, parent_path)?;
+
+        let inode = match file_type
+            {
+                // A directory may have been created already for an entry within it.
+                FileType::Directory => match parent.child(name)?
+                    {
+                        Some(existing) if existing.is_directory() =>
+                            {
+                                existing.set_mode(mode)?;
+                                existing
+                            },
+
+                        _ =>
+                            {
+                                let directory = RamInode::new_directory(mode, false);
+
+                                parent.insert(name, directory.clone())?;
+                                directory
+                            }
+                    },
+
+                // Files with several names share an inode, the data is stored with the last of
+                // the names in the archive.
+                FileType::Regular if entry.link_count > 1 =>
+                    {
+                        match hard_links.get(&entry.inode_number)
+                        {
+                            Some(file) =>
+                                {
+                                    parent.link(name, file.clone())?;
+
+                                    if !entry.data.is_empty()
+                                    {
+                                        file.set_data(entry.data)?;
+                                    }
+
+                                    file.clone()
+                                },
+
+                            None =>
+                                {
+                                    let file = RamInode::new_file(mode, entry.data, false);
+
+                                    parent.insert(name, file.clone())?;
+                                    hard_links.insert(entry.inode_number, file.clone());
+                                    file
+                                }
+                        }
+                    },
+
+                FileType::Regular =>
+                    {
+                        let file = RamInode::new_file(mode, entry.data, false);
+
+                        parent.insert(name, file.clone())?;
+                        file
+                    },
+
+                FileType::SymbolicLink =>
+                    {
+                        let target = from_utf8(entry.data)
+                            .map_err(|_| FsError::Corrupted("Initial RAM disk has an invalid \
+                                                             symbolic link."))?;
+
+                        let link = RamInode::new_symbolic_link(target, false);
+
+                        parent.insert(name, link.clone())?;
+                        link
+                    },
+
+                _ =>
+                    {
+                        let node = RamInode::new_special(file_type, mode, false);
+
+                        parent.insert(name, node.clone())?;
+                        node
+                    }
+            };
+
+        inode.set_attributes(entry.user_id, entry.group_id, entry.modify_time as u64);
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+
+
+/// Give the kernel's own devices their places under /dev, replacing any device nodes the archive
+/// had for them.
+fn add_devices(root: &Arc<RamInode>) -> FsResult<()>
+{
+    let dev = make_directories(root, "dev")?;
+
+    dev.insert("random", RamInode::new_device(RandomDevice::random(), false)?)?;
+    dev.insert("urandom", RamInode::new_device(RandomDevice::urandom(), false)?)?;
+
+    Ok(())
+}
+
+
+
+/// If the bootloader loaded an initial RAM disk, unpack it into an in memory filesystem and mount
+/// it at /. Returns `None` if there is no initial RAM disk.
+pub fn mount_initramfs() -> FsResult<Option<Arc<Mount>>>
+{
+    let Some(module) = get_boot_info().find_module(XtraBootModuleKind::InitialRamDisk)
+    else
+    {
+        return Ok(None);
+    };
+
+    // The bootloader has reserved the archive's memory, so it stays valid for as long as the
+    // kernel runs.
+    let archive = unsafe
+        {
+            slice::from_raw_parts(module.range.base as usize as *const u8,
+                                  module.range.size as usize)
+        };
+
+    let root = RamInode::new_directory(DEFAULT_DIRECTORY_MODE, false);
+    let count = unpack(archive, &root)?;
+
+    add_devices(&root)?;
+
+    println!("  Unpacked {} entries from initial RAM disk {} at {}.",
+             count,
+             module.name_str(),
+             module.range);
+
+    mount_filesystem(RamFilesystem::new(root), "/", "initramfs", MountOptions::default())
+        .map(Some)
+}
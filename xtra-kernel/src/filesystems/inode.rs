@@ -0,0 +1,225 @@
+// The inode is the VFS's view of a single object in a filesystem, a file, a directory, a symbolic
+// link and so on. Each filesystem driver implements the `Inode` trait for its own on disk objects
+// and the rest of the kernel works with them through that trait.
+
+use core::fmt::{ self, Display, Formatter };
+
+use alloc::{ string::String, sync::Arc, vec::Vec };
+
+use crate::filesystems::{ FsError, FsResult };
+
+
+
+/// The type of object an inode represents.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileType
+{
+    /// A regular file of bytes.
+    Regular,
+
+    /// A directory of other inodes.
+    Directory,
+
+    /// A symbolic link to another path.
+    SymbolicLink,
+
+    /// A character device node.
+    CharacterDevice,
+
+    /// A block device node.
+    BlockDevice,
+
+    /// A named pipe.
+    Fifo,
+
+    /// A named socket.
+    Socket
+}
+
+
+
+impl Display for FileType
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            FileType::Regular         => write!(formatter, "file"),
+            FileType::Directory       => write!(formatter, "directory"),
+            FileType::SymbolicLink    => write!(formatter, "symlink"),
+            FileType::CharacterDevice => write!(formatter, "character device"),
+            FileType::BlockDevice     => write!(formatter, "block device"),
+            FileType::Fifo            => write!(formatter, "fifo"),
+            FileType::Socket          => write!(formatter, "socket")
+        }
+    }
+}
+
+
+
+/// The information about an inode that is common to all filesystems. Filesystems that don't have a
+/// concept for one of the fields fill in a reasonable default.
+#[derive(Clone, Copy)]
+pub struct Metadata
+{
+    /// The filesystem's number for the inode.
+    pub inode_number: u64,
+
+    /// What kind of object the inode represents.
+    pub file_type: FileType,
+
+    /// The permission bits of the inode, (the lower 12 bits of a Unix mode.)
+    pub mode: u16,
+
+    /// The owning user.
+    pub user_id: u32,
+
+    /// The owning group.
+    pub group_id: u32,
+
+    /// The size of the inode's data in bytes.
+    pub size: u64,
+
+    /// The number of directory entries referring to the inode.
+    pub link_count: u32,
+
+    /// Last access time in seconds since the Unix epoch.
+    pub access_time: u64,
+
+    /// Last modification time in seconds since the Unix epoch.
+    pub modify_time: u64,
+
+    /// Last metadata change time in seconds since the Unix epoch.
+    pub change_time: u64,
+
+    /// The preferred size for I/O on this inode.
+    pub block_size: u32,
+
+    /// The number of 512 byte blocks allocated to the inode.
+    pub blocks: u64
+}
+
+
+
+impl Metadata
+{
+    /// Is the inode a directory?
+    pub fn is_directory(&self) -> bool
+    {
+        self.file_type == FileType::Directory
+    }
+
+    /// Is the inode a symbolic link?
+    pub fn is_symbolic_link(&self) -> bool
+    {
+        self.file_type == FileType::SymbolicLink
+    }
+}
+
+
+
+/// A single entry as read from a directory.
+#[derive(Clone)]
+pub struct DirectoryEntry
+{
+    /// The name of the entry within the directory.
+    pub name: String,
+
+    /// The filesystem's number for the inode the entry refers to.
+    pub inode_number: u64,
+
+    /// The type of the inode the entry refers to.
+    pub file_type: FileType
+}
+
+
+
+/// The interface every filesystem object exposes to the VFS.
+///
+/// Most operations only make sense for some types of inode, the defaults report the appropriate
+/// error so that a filesystem only needs to implement what its inodes actually support.
+pub trait Inode: Send + Sync
+{
+    /// Get the current metadata of the inode.
+    fn metadata(&self) -> FsResult<Metadata>;
+
+    /// Change the permission bits of the inode.
+    fn set_mode(&self, mode: u16) -> FsResult<()>
+    {
+        Err(FsError::Unsupported)
+    }
+
+    /// Change the owning user and group of the inode.
+    fn set_owner(&self, user_id: u32, group_id: u32) -> FsResult<()>
+    {
+        Err(FsError::Unsupported)
+    }
+
+    /// Change the access and modification times of the inode.
+    fn set_times(&self, access_time: u64, modify_time: u64) -> FsResult<()>
+    {
+        Err(FsError::Unsupported)
+    }
+
+    /// Read from the inode's data at the given offset. Returns the number of bytes read, which will
+    /// be 0 at the end of the file.
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> FsResult<usize>
+    {
+        Err(FsError::IsADirectory)
+    }
+
+    /// Write to the inode's data at the given offset, growing the inode if needed. Returns the
+    /// number of bytes written.
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> FsResult<usize>
+    {
+        Err(FsError::IsADirectory)
+    }
+
+    /// Change the size of the inode's data, freeing or zero filling as needed.
+    fn truncate(&self, size: u64) -> FsResult<()>
+    {
+        Err(FsError::IsADirectory)
+    }
+
+    /// Find the named entry in a directory.
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>>
+    {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Read all of the entries of a directory, not including the . and .. entries.
+    fn read_directory(&self) -> FsResult<Vec<DirectoryEntry>>
+    {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Create a new regular file or directory within a directory.
+    fn create(&self, name: &str, file_type: FileType, mode: u16) -> FsResult<Arc<dyn Inode>>
+    {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Create a new symbolic link within a directory.
+    fn symbolic_link(&self, name: &str, target: &str) -> FsResult<Arc<dyn Inode>>
+    {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Remove the named entry from a directory. Directories must be empty to be removed.
+    fn unlink(&self, name: &str) -> FsResult<()>
+    {
+        Err(FsError::NotADirectory)
+    }
+
+    /// Read the target path of a symbolic link.
+    fn read_link(&self) -> FsResult<String>
+    {
+        Err(FsError::InvalidArgument)
+    }
+
+    /// Write any cached state of the inode back to its storage.
+    fn sync(&self) -> FsResult<()>
+    {
+        Ok(())
+    }
+}
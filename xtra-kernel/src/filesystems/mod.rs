@@ -1,5 +1,804 @@
+// The virtual filesystem, (VFS,) layer of the kernel. The individual filesystem drivers implement
+// the `Filesystem` and `Inode` traits and the VFS ties them together into a single directory tree.
+// The rest of the kernel only ever works with paths, open files and the traits defined here.
+//
+// At boot the mount table handed to us by the bootloader is used to find the partitions holding the
+// system's filesystems and mount them where they belong. The root= and ro/rw kernel parameters can
+// override how the root filesystem is found and mounted. If the bootloader loaded an initial RAM
+// disk it is mounted at / first, and replaced by the real root filesystem once that is found.
 
-pub fn initialize_filesystems() -> Result<(), &'static str>
+use core::{ fmt::{ self, Debug, Display, Formatter },
+            str::from_utf8,
+            sync::atomic::{ AtomicBool, Ordering } };
+
+use alloc::{ collections::BTreeMap, format, string::String, sync::Arc, vec::Vec };
+
+use xtra_kernel_shared::mount_table::{ XtraFilesystemType,
+                                       XtraMountTable,
+                                       XtraMountTableEntry,
+                                       XtraPartitionSelector,
+                                       XTRA_MOUNT_OPTION_NO_EXEC,
+                                       XTRA_MOUNT_OPTION_OPTIONAL,
+                                       XTRA_MOUNT_OPTION_READ_ONLY,
+                                       XTRA_MOUNT_OPTION_SYNC };
+
+use crate::{ boot_info::boot_time,
+             command_line::{ get_kernel_options,
+                             KernelParameter,
+                             KernelParameterRegistry,
+                             RootDevice },
+             devices::block_devices::{ disks, get_disk, BlockDevice, Partition },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// The inode trait that filesystems implement for their files, directories and links.
+pub mod inode;
+
+/// The directory entry cache used to walk the directory tree.
+pub mod dentry;
+
+/// The table of mounted filesystems.
+pub mod mount;
+
+/// Resolution of paths to locations in the directory tree.
+pub mod path;
+
+/// Files opened through the VFS.
+pub mod file;
+
+/// The block cache shared by the disk based filesystem drivers.
+pub mod block_cache;
+
+/// The ext2 filesystem driver.
+pub mod ext2;
+
+/// The FAT32 filesystem driver.
+pub mod fat32;
+
+/// The in memory filesystem.
+pub mod ramfs;
+
+/// Unpacking of the initial RAM disk.
+pub mod initramfs;
+
+
+
+use crate::filesystems::{ initramfs::mount_initramfs,
+                          inode::{ DirectoryEntry, FileType, Inode, Metadata },
+                          mount::{ mount_filesystem,
+                                   root_location,
+                                   switch_root,
+                                   Mount,
+                                   MountOptions },
+                          path::{ resolve, resolve_parent } };
+
+
+
+/// The errors that can be reported by the VFS and the filesystem drivers.
+#[derive(Clone, PartialEq, Eq)]
+pub enum FsError
+{
+    /// The path or name does not exist.
+    NotFound,
+
+    /// An entry with the name already exists.
+    AlreadyExists,
+
+    /// A directory was needed but something else was found.
+    NotADirectory,
+
+    /// The operation can not be performed on a directory.
+    IsADirectory,
+
+    /// The directory still has entries in it.
+    DirectoryNotEmpty,
+
+    /// The filesystem is mounted read-only or the device is write protected.
+    ReadOnly,
+
+    /// The caller does not have permission to perform the operation.
+    PermissionDenied,
+
+    /// The file was not opened in a mode that allows the operation.
+    BadFileMode,
+
+    /// The path is not valid.
+    InvalidPath,
+
+    /// A name or path is too long.
+    NameTooLong,
+
+    /// Too many symbolic links were followed while resolving a path.
+    TooManySymbolicLinks,
+
+    /// There is no free space or inodes left on the filesystem.
+    NoSpace,
+
+    /// The file would grow beyond the largest size the filesystem supports.
+    FileTooLarge,
+
+    /// The file or filesystem is in use.
+    Busy,
+
+    /// An argument to the operation is not valid.
+    InvalidArgument,
+
+    /// The filesystem does not support the operation.
+    Unsupported,
+
+    /// The path is not the root of a mounted filesystem.
+    NotMounted,
+
+    /// Nothing has been mounted at /.
+    NoRootFilesystem,
+
+    /// The mount table refers to a disk that the kernel did not find.
+    DeviceNotFound { device: usize },
+
+    /// The mount table refers to a partition that doesn't exist on the disk.
+    PartitionNotFound { device: usize, partition: usize },
+
+    /// No partition has the UUID given in the mount table.
+    PartitionUuidNotFound { uuid: String },
+
+    /// No partition holds a filesystem with the label given in the mount table.
+    LabelNotFound { label: String },
+
+    /// The mount table entry doesn't specify a filesystem type we know of.
+    UnknownFilesystemType,
+
+    /// There isn't a driver registered for the filesystem.
+    NoFilesystemDriver { name: String },
+
+    /// The filesystem's on disk structures are damaged.
+    Corrupted(&'static str),
+
+    /// The underlying device reported an error.
+    Io(&'static str)
+}
+
+
+
+impl Display for FsError
+{
+    /// Format the filesystem error for display to the user when needed.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            FsError::NotFound             => write!(f, "No such file or directory"),
+            FsError::AlreadyExists        => write!(f, "File exists"),
+            FsError::NotADirectory        => write!(f, "Not a directory"),
+            FsError::IsADirectory         => write!(f, "Is a directory"),
+            FsError::DirectoryNotEmpty    => write!(f, "Directory not empty"),
+            FsError::ReadOnly             => write!(f, "Read-only filesystem"),
+            FsError::PermissionDenied     => write!(f, "Permission denied"),
+            FsError::BadFileMode          => write!(f, "File not opened for this operation"),
+            FsError::InvalidPath          => write!(f, "Invalid path"),
+            FsError::NameTooLong          => write!(f, "File name too long"),
+            FsError::TooManySymbolicLinks => write!(f, "Too many levels of symbolic links"),
+            FsError::NoSpace              => write!(f, "No space left on device"),
+            FsError::FileTooLarge         => write!(f, "File too large"),
+            FsError::Busy                 => write!(f, "Device or resource busy"),
+            FsError::InvalidArgument      => write!(f, "Invalid argument"),
+            FsError::Unsupported          => write!(f, "Operation not supported"),
+            FsError::NotMounted           => write!(f, "Not a mount point"),
+            FsError::NoRootFilesystem     => write!(f, "No root filesystem mounted"),
+
+            FsError::DeviceNotFound { device } =>
+                write!(f, "Disk {} was not found", device),
+
+            FsError::PartitionNotFound { device, partition } =>
+                write!(f, "Partition {} was not found on disk {}", partition, device),
+
+            FsError::PartitionUuidNotFound { uuid } =>
+                write!(f, "No partition has the UUID {}", uuid),
+
+            FsError::LabelNotFound { label } =>
+                write!(f, "No filesystem has the label {}", label),
+
+            FsError::UnknownFilesystemType =>
+                write!(f, "Unknown filesystem type"),
+
+            FsError::NoFilesystemDriver { name } =>
+                write!(f, "No driver for filesystem type {}", name),
+
+            FsError::Corrupted(message) =>
+                write!(f, "Filesystem corrupted: {}", message),
+
+            FsError::Io(message) =>
+                write!(f, "I/O error: {}", message)
+        }
+    }
+}
+
+
+
+impl Debug for FsError
+{
+    /// Format the filesystem error for debugging purposes.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        Display::fmt(self, f)
+    }
+}
+
+
+
+/// Errors from the block device layer are reported as I/O errors.
+impl From<&'static str> for FsError
+{
+    fn from(message: &'static str) -> Self
+    {
+        FsError::Io(message)
+    }
+}
+
+
+
+/// The result type used throughout the filesystem code.
+pub type FsResult<T> = Result<T, FsError>;
+
+
+
+/// Usage information for a mounted filesystem.
+#[derive(Clone, Copy, Default)]
+pub struct FilesystemStatistics
+{
+    /// The size of the filesystem's allocation unit in bytes.
+    pub block_size: u32,
+
+    /// The total number of blocks in the filesystem.
+    pub total_blocks: u64,
+
+    /// The number of unallocated blocks.
+    pub free_blocks: u64,
+
+    /// The total number of inodes in the filesystem, 0 if the filesystem doesn't have a fixed
+    /// number.
+    pub total_inodes: u64,
+
+    /// The number of unallocated inodes.
+    pub free_inodes: u64
+}
+
+
+
+/// The interface a mounted filesystem instance exposes to the VFS.
+pub trait Filesystem: Send + Sync
+{
+    /// The name of the filesystem type, for example ext2.
+    fn name(&self) -> &'static str;
+
+    /// Get the root directory of the filesystem.
+    fn root(&self) -> FsResult<Arc<dyn Inode>>;
+
+    /// Get the usage information for the filesystem.
+    fn statistics(&self) -> FsResult<FilesystemStatistics>;
+
+    /// Write all cached data back to the underlying device.
+    fn sync(&self) -> FsResult<()>;
+
+    /// Called when the filesystem is being unmounted, by default it just syncs the filesystem.
+    fn unmount(&self) -> FsResult<()>
+    {
+        self.sync()
+    }
+}
+
+
+
+/// Type for the function that gets called to mount a filesystem found on a block device.
+pub type MountFunction = fn(device: Arc<dyn BlockDevice>,
+                            options: &MountOptions) -> FsResult<Arc<dyn Filesystem>>;
+
+/// Type for the function that reads the label of a filesystem on a block device without mounting
+/// it, used to find the partitions the mount table selects by label.
+pub type LabelFunction = fn(device: &dyn BlockDevice) -> FsResult<String>;
+
+
+
+/// The functions a filesystem driver provides to the VFS.
+#[derive(Clone, Copy)]
+pub struct FilesystemDriver
+{
+    /// Mount the filesystem found on a block device.
+    pub mount: MountFunction,
+
+    /// Read the label of the filesystem found on a block device.
+    pub read_label: LabelFunction
+}
+
+
+
+/// The filesystem driver registry type, a mapping from filesystem type names to the drivers that
+/// handle them.
+pub type FilesystemDriverRegistry = BTreeMap<&'static str, FilesystemDriver>;
+
+
+
+/// The filesystem drivers compiled into the kernel.
+static FILESYSTEM_DRIVERS: SpinMutex<FilesystemDriverRegistry> = SpinMutex::new(BTreeMap::new());
+
+
+
+/// Set by the fsck kernel parameter to have every filesystem checked when it is mounted.
+static FORCE_CHECK: AtomicBool = AtomicBool::new(false);
+
+
+
+/// Register the kernel parameters accepted by the filesystem subsystem.
+pub fn register_kernel_parameters(registry: &mut KernelParameterRegistry)
+    -> Result<(), &'static str>
 {
+    registry.insert("fsck",
+                    KernelParameter
+                        {
+                            description: "check filesystems when mounting them",
+                            handler: |value|
+                                {
+                                    let force = match value
+                                        {
+                                            None | Some("1") | Some("force") => true,
+                                            Some("0")                        => false,
+                                            Some(_) => return Err("Expected fsck, fsck=0 or \
+                                                                   fsck=1.")
+                                        };
+
+                                    FORCE_CHECK.store(force, Ordering::Relaxed);
+
+                                    Ok(())
+                                }
+                        });
+
     Ok(())
 }
+
+
+
+/// Initialize the filesystem subsystem and mount all of the filesystems listed in the mount table
+/// given to us by the bootloader.
+///
+/// Every entry in the table must be mountable unless it's marked as optional, a missing disk or
+/// partition is reported as an error so that the boot fails clearly instead of running without part
+/// of the system.
+pub fn initialize_filesystems(mount_table: &XtraMountTable) -> FsResult<()>
+{
+    // Register the drivers for all of the filesystems we support. The order of registration here
+    // does not matter.
+    {
+        let mut registry = FILESYSTEM_DRIVERS.lock();
+
+        *registry = FilesystemDriverRegistry::new();
+
+        ext2::register_filesystem_driver(&mut registry);
+        fat32::register_filesystem_driver(&mut registry);
+    }
+
+    // Collect the entries and sort them so that parents are always mounted before their children.
+    let mut entries = Vec::new();
+
+    for entry in &mount_table.entries[..mount_table.num_entries]
+    {
+        entries.push((mount_point_of(entry)?, *entry));
+    }
+
+    entries.sort_by_key(|(mount_point, _)|
+        {
+            mount_point.split('/').filter(|component| !component.is_empty()).count()
+        });
+
+    apply_root_options(&mut entries)?;
+
+    // Mount the initial RAM disk, if there is one, so that it's available until the real root
+    // filesystem is mounted on top of it. Without a root entry in the mount table we keep running
+    // from the initial RAM disk.
+    let initramfs = mount_initramfs()?;
+
+    if let Some(mount) = &initramfs
+    {
+        println!("  Mounted {}.", mount);
+    }
+
+    if    initramfs.is_none()
+       && !entries.iter().any(|(mount_point, _)| mount_point == "/")
+    {
+        return Err(FsError::NoRootFilesystem);
+    }
+
+    for (mount_point, entry) in entries
+    {
+        match mount_table_entry(&mount_point, &entry)
+        {
+            Ok(mount) =>
+                {
+                    println!("  Mounted {}.", mount);
+                },
+
+            Err(error) if entry.has_option(XTRA_MOUNT_OPTION_OPTIONAL) =>
+                {
+                    println!("  Skipping optional mount {}: {}.", mount_point, error);
+                },
+
+            Err(error) => return Err(error)
+        }
+    }
+
+    // The root filesystem may have been marked as optional, but we can't go on without it.
+    root_location()?;
+
+    Ok(())
+}
+
+
+
+/// Read a little endian u16 from a byte buffer.
+pub fn read_u16(bytes: &[u8], offset: usize) -> u16
+{
+    u16::from_le_bytes([ bytes[offset], bytes[offset + 1] ])
+}
+
+/// Read a little endian u32 from a byte buffer.
+pub fn read_u32(bytes: &[u8], offset: usize) -> u32
+{
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Write a little endian u16 into a byte buffer.
+pub fn write_u16(bytes: &mut [u8], offset: usize, value: u16)
+{
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Write a little endian u32 into a byte buffer.
+pub fn write_u32(bytes: &mut [u8], offset: usize, value: u32)
+{
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+
+
+/// The current time in seconds since the Unix epoch, used to stamp filesystem changes. The kernel
+/// doesn't keep a clock of its own yet so for now everything is stamped with the time the system
+/// was booted, as read by the bootloader.
+pub fn current_time() -> u64
+{
+    boot_time()
+}
+
+
+
+/// Look up the driver registered for the named filesystem type.
+fn filesystem_driver(filesystem_name: &str) -> FsResult<FilesystemDriver>
+{
+    FILESYSTEM_DRIVERS.lock()
+                      .get(filesystem_name)
+                      .copied()
+                      .ok_or_else(|| FsError::NoFilesystemDriver { name: filesystem_name.into() })
+}
+
+
+
+/// Mount the filesystem on a block device at the given path using the driver registered for the
+/// named filesystem type. If something, such as the initial RAM disk, is already mounted at / and
+/// the path is / the new filesystem replaces it.
+pub fn mount_device(device: Arc<dyn BlockDevice>,
+                    filesystem_name: &str,
+                    path: &str,
+                    options: MountOptions) -> FsResult<Arc<Mount>>
+{
+    let mount_function = filesystem_driver(filesystem_name)?.mount;
+
+    let options = if device.is_read_only()
+        {
+            options.to_builder().read_only().build()
+        }
+        else
+        {
+            options
+        };
+
+    let filesystem = mount_function(device.clone(), &options)?;
+
+    if    path == "/"
+       && root_location().is_ok()
+    {
+        return switch_root(filesystem, device.name(), options);
+    }
+
+    mount_filesystem(filesystem, path, device.name(), options)
+}
+
+
+
+/// Create a new directory.
+pub fn make_directory(path: &str, mode: u16) -> FsResult<()>
+{
+    let (parent, name) = resolve_parent(path)?;
+
+    parent.mount.check_writable()?;
+
+    if parent.dentry.lookup(&name).is_ok()
+    {
+        return Err(FsError::AlreadyExists);
+    }
+
+    let inode = parent.dentry.inode().create(&name, FileType::Directory, mode)?;
+
+    parent.dentry.add_child(&name, inode);
+    parent.mount.sync_if_requested()
+}
+
+
+
+/// Create a symbolic link at `path` pointing to `target`.
+pub fn symbolic_link(target: &str, path: &str) -> FsResult<()>
+{
+    let (parent, name) = resolve_parent(path)?;
+
+    parent.mount.check_writable()?;
+
+    if parent.dentry.lookup(&name).is_ok()
+    {
+        return Err(FsError::AlreadyExists);
+    }
+
+    let inode = parent.dentry.inode().symbolic_link(&name, target)?;
+
+    parent.dentry.add_child(&name, inode);
+    parent.mount.sync_if_requested()
+}
+
+
+
+/// Remove a file, link or empty directory.
+pub fn remove(path: &str) -> FsResult<()>
+{
+    let (parent, name) = resolve_parent(path)?;
+
+    parent.mount.check_writable()?;
+
+    let child = parent.dentry.lookup(&name)?;
+
+    if child.mounted().is_some()
+    {
+        return Err(FsError::Busy);
+    }
+
+    parent.dentry.inode().unlink(&name)?;
+    parent.dentry.forget_child(&name);
+
+    parent.mount.sync_if_requested()
+}
+
+
+
+/// Read the target of a symbolic link.
+pub fn read_link(path: &str) -> FsResult<String>
+{
+    resolve(path, false)?.dentry.inode().read_link()
+}
+
+
+
+/// Get the metadata of the object at the given path, following symbolic links.
+pub fn metadata(path: &str) -> FsResult<Metadata>
+{
+    resolve(path, true)?.dentry.inode().metadata()
+}
+
+
+
+/// Get the metadata of the object at the given path without following a final symbolic link.
+pub fn link_metadata(path: &str) -> FsResult<Metadata>
+{
+    resolve(path, false)?.dentry.inode().metadata()
+}
+
+
+
+/// Read the entries of the directory at the given path.
+pub fn read_directory(path: &str) -> FsResult<Vec<DirectoryEntry>>
+{
+    resolve(path, true)?.dentry.inode().read_directory()
+}
+
+
+
+/// Extract the mount point path from a mount table entry.
+fn mount_point_of(entry: &XtraMountTableEntry) -> FsResult<String>
+{
+    let length = entry.mount_point
+                      .iter()
+                      .position(|&byte| byte == 0)
+                      .unwrap_or(entry.mount_point.len());
+
+    let mount_point = from_utf8(&entry.mount_point[..length]).map_err(|_| FsError::InvalidPath)?;
+
+    if !mount_point.starts_with('/')
+    {
+        return Err(FsError::InvalidPath);
+    }
+
+    Ok(mount_point.into())
+}
+
+
+
+/// Copy a string into a zero padded field of a mount table entry.
+fn copy_padded(field: &mut [u8], value: &str) -> FsResult<()>
+{
+    if value.len() > field.len()
+    {
+        return Err(FsError::InvalidArgument);
+    }
+
+    field.fill(0);
+    field[..value.len()].copy_from_slice(value.as_bytes());
+
+    Ok(())
+}
+
+
+
+/// Apply the root= and ro/rw kernel parameters to the root entry of the sorted mount table entries.
+/// If the mount table has no root entry, but the command line names a root device, an entry for an
+/// ext2 filesystem is added for it.
+fn apply_root_options(entries: &mut Vec<(String, XtraMountTableEntry)>) -> FsResult<()>
+{
+    let options = get_kernel_options();
+
+    if    options.root.is_none()
+       && options.root_read_only.is_none()
+    {
+        return Ok(());
+    }
+
+    let root_index = match entries.iter().position(|(mount_point, _)| mount_point == "/")
+        {
+            Some(index) => index,
+
+            None if options.root.is_some() =>
+                {
+                    let mut entry = XtraMountTableEntry::new();
+
+                    entry.mount_point[0] = b'/';
+                    entry.filesystem_type = XtraFilesystemType::Ext2;
+
+                    // The root is always the first entry once they're sorted.
+                    entries.insert(0, ("/".into(), entry));
+
+                    0
+                },
+
+            None => return Ok(())
+        };
+
+    let entry = &mut entries[root_index].1;
+
+    match options.root
+    {
+        Some(RootDevice::PartitionUuid(uuid)) =>
+            {
+                entry.selector = XtraPartitionSelector::PartitionUuid;
+                copy_padded(&mut entry.partition_uuid, uuid)?;
+            },
+
+        Some(RootDevice::Label(label)) =>
+            {
+                entry.selector = XtraPartitionSelector::Label;
+                copy_padded(&mut entry.label, label)?;
+            },
+
+        Some(RootDevice::Index { disk, partition }) =>
+            {
+                entry.selector = XtraPartitionSelector::Index;
+                entry.device = disk;
+                entry.partition = partition;
+            },
+
+        None => {}
+    }
+
+    match options.root_read_only
+    {
+        Some(true)  => entry.options |= XTRA_MOUNT_OPTION_READ_ONLY,
+        Some(false) => entry.options &= !XTRA_MOUNT_OPTION_READ_ONLY,
+        None        => {}
+    }
+
+    println!("  Root filesystem from the command line: {}", entry);
+
+    Ok(())
+}
+
+
+
+/// Find the partition selected by a mount table entry. Partitions can be selected by the index of
+/// their disk and their slot in its partition table, by their partition UUID or by the label of the
+/// filesystem they hold.
+fn find_partition(entry: &XtraMountTableEntry, filesystem_name: &str) -> FsResult<Arc<Partition>>
+{
+    match entry.selector
+    {
+        XtraPartitionSelector::Index =>
+            {
+                let device = entry.device as usize;
+                let partition = entry.partition as usize;
+
+                let disk = get_disk(device).ok_or(FsError::DeviceNotFound { device })?;
+
+                disk.partition(partition).ok_or(FsError::PartitionNotFound { device, partition })
+            },
+
+        XtraPartitionSelector::PartitionUuid =>
+            {
+                let uuid = entry.partition_uuid_str();
+
+                disks().iter()
+                       .flat_map(|disk| disk.partitions())
+                       .find(|partition| partition.uuid() == uuid)
+                       .cloned()
+                       .ok_or_else(|| FsError::PartitionUuidNotFound { uuid: uuid.into() })
+            },
+
+        XtraPartitionSelector::Label =>
+            {
+                // Only the driver for the expected filesystem type is asked to read the label, a
+                // partition that holds some other filesystem simply won't match.
+                let label = entry.label_str();
+                let read_label = filesystem_driver(filesystem_name)?.read_label;
+
+                disks().iter()
+                       .flat_map(|disk| disk.partitions())
+                       .find(|partition|
+                           {
+                               read_label(partition.as_ref()).is_ok_and(|found| found == label)
+                           })
+                       .cloned()
+                       .ok_or_else(|| FsError::LabelNotFound { label: label.into() })
+            },
+
+        XtraPartitionSelector::None => Err(FsError::InvalidArgument)
+    }
+}
+
+
+
+/// Find the partition for a mount table entry and mount its filesystem.
+fn mount_table_entry(mount_point: &str, entry: &XtraMountTableEntry) -> FsResult<Arc<Mount>>
+{
+    let filesystem_name = match entry.filesystem_type
+        {
+            XtraFilesystemType::Fat32 => "fat32",
+            XtraFilesystemType::Ext2  => "ext2",
+            _                         => return Err(FsError::UnknownFilesystemType)
+        };
+
+    let partition = find_partition(entry, filesystem_name)?;
+
+    let mut options = MountOptions::builder();
+
+    if entry.has_option(XTRA_MOUNT_OPTION_READ_ONLY)
+    {
+        options = options.read_only();
+    }
+
+    if entry.has_option(XTRA_MOUNT_OPTION_NO_EXEC)
+    {
+        options = options.no_exec();
+    }
+
+    if entry.has_option(XTRA_MOUNT_OPTION_SYNC)
+    {
+        options = options.sync();
+    }
+
+    if FORCE_CHECK.load(Ordering::Relaxed)
+    {
+        options = options.check();
+    }
+
+    mount_device(partition, filesystem_name, mount_point, options.build())
+}
@@ -0,0 +1,448 @@
+// The kernel's table of mounted filesystems. Each mount ties a filesystem instance to a location in
+// the directory tree, the root mount sits at / and all other mounts hang off of directories in the
+// filesystems mounted before them.
+
+use core::fmt::{ self, Display, Formatter };
+
+use alloc::{ string::{ String, ToString }, sync::{ Arc, Weak }, vec::Vec };
+
+use crate::{ filesystems::{ dentry::Dentry,
+                            path::{ resolve, PathLocation },
+                            Filesystem,
+                            FsError,
+                            FsResult },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// Builder for the options of a mount. Every option starts off, and is turned on by calling its
+/// method.
+#[derive(Default)]
+pub struct MountOptionsBuilder
+{
+    read_only: bool,
+    no_exec: bool,
+    sync: bool,
+    check: bool
+}
+
+
+
+impl MountOptionsBuilder
+{
+    /// Mount the filesystem read only.
+    pub fn read_only(mut self) -> Self
+    {
+        self.read_only = true;
+        self
+    }
+
+    /// Don't allow programs to be executed from the filesystem.
+    pub fn no_exec(mut self) -> Self
+    {
+        self.no_exec = true;
+        self
+    }
+
+    /// Write changes through to the device as soon as they are made.
+    pub fn sync(mut self) -> Self
+    {
+        self.sync = true;
+        self
+    }
+
+    /// Check the filesystem for consistency before mounting it.
+    pub fn check(mut self) -> Self
+    {
+        self.check = true;
+        self
+    }
+
+    /// Create the `MountOptions` with the options that were chosen.
+    pub fn build(self) -> MountOptions
+    {
+        MountOptions
+            {
+                read_only: self.read_only,
+                no_exec: self.no_exec,
+                sync: self.sync,
+                check: self.check
+            }
+    }
+}
+
+
+
+/// The options a filesystem is mounted with.
+#[derive(Clone, Copy, Default)]
+pub struct MountOptions
+{
+    /// No changes may be made to the filesystem.
+    pub read_only: bool,
+
+    /// Programs may not be executed from the filesystem.
+    pub no_exec: bool,
+
+    /// Changes are written through to the device as soon as they are made.
+    pub sync: bool,
+
+    /// Check the filesystem for consistency before mounting it, for filesystems that support it.
+    pub check: bool
+}
+
+
+
+impl MountOptions
+{
+    /// Create a new `MountOptionsBuilder` to build a `MountOptions` object with custom values.
+    pub fn builder() -> MountOptionsBuilder
+    {
+        MountOptionsBuilder::default()
+    }
+
+    /// Create a `MountOptionsBuilder` starting from these options, to turn more of them on.
+    pub fn to_builder(self) -> MountOptionsBuilder
+    {
+        MountOptionsBuilder
+            {
+                read_only: self.read_only,
+                no_exec: self.no_exec,
+                sync: self.sync,
+                check: self.check
+            }
+    }
+}
+
+
+
+impl Display for MountOptions
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        write!(formatter, "{}", if self.read_only { "ro" } else { "rw" })?;
+
+        if self.no_exec
+        {
+            write!(formatter, ",noexec")?;
+        }
+
+        if self.sync
+        {
+            write!(formatter, ",sync")?;
+        }
+
+        if self.check
+        {
+            write!(formatter, ",check")?;
+        }
+
+        Ok(())
+    }
+}
+
+
+
+/// A filesystem mounted into the directory tree.
+pub struct Mount
+{
+    /// The path the filesystem is mounted on.
+    path: String,
+
+    /// A description of where the filesystem came from, for example the partition's name.
+    source: String,
+
+    /// The mounted filesystem.
+    filesystem: Arc<dyn Filesystem>,
+
+    /// The root directory entry of the mounted filesystem.
+    root: Arc<Dentry>,
+
+    /// The mount this one is mounted within and the entry in it that is covered. Both are `None`
+    /// for the root mount.
+    parent: Option<Weak<Mount>>,
+    mount_point: Option<Arc<Dentry>>,
+
+    /// The options the filesystem was mounted with.
+    options: MountOptions
+}
+
+
+
+impl Mount
+{
+    /// The path the filesystem is mounted on.
+    pub fn path(&self) -> &str
+    {
+        &self.path
+    }
+
+    /// Where the filesystem came from.
+    pub fn source(&self) -> &str
+    {
+        &self.source
+    }
+
+    /// The mounted filesystem.
+    pub fn filesystem(&self) -> Arc<dyn Filesystem>
+    {
+        self.filesystem.clone()
+    }
+
+    /// The root directory entry of the mounted filesystem.
+    pub fn root(&self) -> Arc<Dentry>
+    {
+        self.root.clone()
+    }
+
+    /// The mount this one lives within, `None` for the root mount.
+    pub fn parent(&self) -> Option<Arc<Mount>>
+    {
+        self.parent.as_ref().and_then(|parent| parent.upgrade())
+    }
+
+    /// The directory entry in the parent mount that this mount covers.
+    pub fn mount_point(&self) -> Option<Arc<Dentry>>
+    {
+        self.mount_point.clone()
+    }
+
+    /// The options the filesystem was mounted with.
+    pub fn options(&self) -> MountOptions
+    {
+        self.options
+    }
+
+    /// Write a change through to the device if the filesystem is mounted with the sync option.
+    /// Called after every operation that modifies the filesystem.
+    pub fn sync_if_requested(&self) -> FsResult<()>
+    {
+        if self.options.sync
+        {
+            self.filesystem.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Fail with `FsError::ReadOnly` if the mount doesn't allow changes.
+    pub fn check_writable(&self) -> FsResult<()>
+    {
+        if self.options.read_only
+        {
+            return Err(FsError::ReadOnly);
+        }
+
+        Ok(())
+    }
+}
+
+
+
+impl Display for Mount
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        write!(formatter,
+               "{} on {} type {} ({})",
+               self.source,
+               self.path,
+               self.filesystem.name(),
+               self.options)
+    }
+}
+
+
+
+/// The filesystem mounted at /, if any.
+static ROOT_MOUNT: SpinMutex<Option<Arc<Mount>>> = SpinMutex::new(None);
+
+/// All of the active mounts, in the order they were mounted.
+static MOUNTS: SpinMutex<Vec<Arc<Mount>>> = SpinMutex::new(Vec::new());
+
+
+
+/// Get the location of the root directory of the whole directory tree.
+pub fn root_location() -> FsResult<PathLocation>
+{
+    let mount = ROOT_MOUNT.lock().clone().ok_or(FsError::NoRootFilesystem)?;
+    let dentry = mount.root();
+
+    Ok(PathLocation { mount, dentry })
+}
+
+
+
+/// Mount a filesystem at the given path. The path must be an existing directory that doesn't
+/// already have something mounted on it, unless it is / and no root filesystem is mounted yet.
+pub fn mount_filesystem(filesystem: Arc<dyn Filesystem>,
+                        path: &str,
+                        source: &str,
+                        options: MountOptions) -> FsResult<Arc<Mount>>
+{
+    let root = Dentry::new_root(filesystem.root()?);
+
+    let mount = if path == "/"
+        {
+            let mut root_mount = ROOT_MOUNT.lock();
+
+            if root_mount.is_some()
+            {
+                return Err(FsError::Busy);
+            }
+
+            let mount = Arc::new(Mount
+                {
+                    path: path.to_string(),
+                    source: source.to_string(),
+                    filesystem,
+                    root,
+                    parent: None,
+                    mount_point: None,
+                    options
+                });
+
+            *root_mount = Some(mount.clone());
+
+            mount
+        }
+        else
+        {
+            let location = resolve(path, true)?;
+
+            if !location.dentry.inode().metadata()?.is_directory()
+            {
+                return Err(FsError::NotADirectory);
+            }
+
+            if location.dentry.mounted().is_some()
+            {
+                return Err(FsError::Busy);
+            }
+
+            let mount = Arc::new(Mount
+                {
+                    path: path.to_string(),
+                    source: source.to_string(),
+                    filesystem,
+                    root,
+                    parent: Some(Arc::downgrade(&location.mount)),
+                    mount_point: Some(location.dentry.clone()),
+                    options
+                });
+
+            location.dentry.set_mounted(Some(mount.clone()));
+
+            mount
+        };
+
+    MOUNTS.lock().push(mount.clone());
+
+    Ok(mount)
+}
+
+
+
+/// Replace the filesystem mounted at / with another one, as is done when switching from the initial
+/// RAM disk to the real root filesystem. Nothing else may be mounted within the old root, it is
+/// unmounted once the new root is in place.
+pub fn switch_root(filesystem: Arc<dyn Filesystem>,
+                   source: &str,
+                   options: MountOptions) -> FsResult<Arc<Mount>>
+{
+    let root = Dentry::new_root(filesystem.root()?);
+    let mut mounts = MOUNTS.lock();
+    let mut root_mount = ROOT_MOUNT.lock();
+
+    let old_root = root_mount.clone().ok_or(FsError::NoRootFilesystem)?;
+
+    let has_children = mounts.iter()
+                             .filter_map(|other| other.parent())
+                             .any(|parent| Arc::ptr_eq(&parent, &old_root));
+
+    if has_children
+    {
+        return Err(FsError::Busy);
+    }
+
+    let mount = Arc::new(Mount
+        {
+            path: "/".to_string(),
+            source: source.to_string(),
+            filesystem,
+            root,
+            parent: None,
+            mount_point: None,
+            options
+        });
+
+    *root_mount = Some(mount.clone());
+
+    mounts.retain(|other| !Arc::ptr_eq(other, &old_root));
+    mounts.push(mount.clone());
+
+    drop(root_mount);
+    drop(mounts);
+
+    old_root.filesystem.unmount()?;
+
+    Ok(mount)
+}
+
+
+
+/// Unmount the filesystem mounted at the given path. Any filesystems mounted within it need to be
+/// unmounted first.
+pub fn unmount(path: &str) -> FsResult<()>
+{
+    let location = resolve(path, true)?;
+    let mount = location.mount;
+
+    if !Arc::ptr_eq(&location.dentry, &mount.root)
+    {
+        return Err(FsError::NotMounted);
+    }
+
+    let mut mounts = MOUNTS.lock();
+
+    let has_children = mounts.iter()
+                             .filter_map(|other| other.parent())
+                             .any(|parent| Arc::ptr_eq(&parent, &mount));
+
+    if has_children
+    {
+        return Err(FsError::Busy);
+    }
+
+    mount.filesystem.unmount()?;
+
+    match &mount.mount_point
+    {
+        Some(mount_point) => mount_point.set_mounted(None),
+        None              => *ROOT_MOUNT.lock() = None
+    }
+
+    mounts.retain(|other| !Arc::ptr_eq(other, &mount));
+
+    Ok(())
+}
+
+
+
+/// Get a snapshot of the list of active mounts.
+pub fn mounts() -> Vec<Arc<Mount>>
+{
+    MOUNTS.lock().clone()
+}
+
+
+
+/// Write all cached data for all mounted filesystems back to their storage.
+pub fn sync_all() -> FsResult<()>
+{
+    for mount in mounts()
+    {
+        mount.filesystem.sync()?;
+    }
+
+    Ok(())
+}
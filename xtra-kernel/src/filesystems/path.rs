@@ -0,0 +1,226 @@
+// Path resolution for the VFS. A path is walked one component at a time from either the root of the
+// directory tree or a starting directory. Along the way we cross into mounted filesystems, step
+// back out of them for .. and follow symbolic links.
+
+use alloc::{ string::{ String, ToString }, sync::Arc };
+
+use crate::filesystems::{ dentry::Dentry,
+                          inode::FileType,
+                          mount::{ root_location, Mount },
+                          FsError,
+                          FsResult };
+
+
+
+/// The maximum number of symbolic links followed while resolving a single path.
+pub const MAX_SYMBOLIC_LINK_DEPTH: usize = 8;
+
+/// The longest path we are willing to resolve.
+pub const MAX_PATH_LENGTH: usize = 4096;
+
+/// The longest single name within a path.
+pub const MAX_NAME_LENGTH: usize = 255;
+
+
+
+/// A resolved location in the directory tree. We need to keep track of the mount as well as the
+/// directory entry so that .. can step back out of a mounted filesystem.
+#[derive(Clone)]
+pub struct PathLocation
+{
+    /// The mount the entry belongs to.
+    pub mount: Arc<Mount>,
+
+    /// The entry itself.
+    pub dentry: Arc<Dentry>
+}
+
+
+
+impl PathLocation
+{
+    /// The location of the directory containing this one. The parent of the root of the tree is
+    /// the root itself.
+    pub fn parent(&self) -> PathLocation
+    {
+        if Arc::ptr_eq(&self.dentry, &self.mount.root())
+        {
+            // At the root of a mounted filesystem .. is the parent of the covered directory.
+            match (self.mount.parent(), self.mount.mount_point())
+            {
+                (Some(mount), Some(dentry)) => PathLocation { mount, dentry }.parent(),
+                _                           => self.clone()
+            }
+        }
+        else
+        {
+            match self.dentry.parent()
+            {
+                Some(dentry) => PathLocation { mount: self.mount.clone(), dentry },
+                None         => self.clone()
+            }
+        }
+    }
+
+    /// If a filesystem is mounted on this location, move to the root of that filesystem. Mounts can
+    /// be stacked so we keep going until we reach the top.
+    fn cross_mounts(mut self) -> PathLocation
+    {
+        while let Some(mount) = self.dentry.mounted()
+        {
+            let dentry = mount.root();
+
+            self = PathLocation { mount, dentry };
+        }
+
+        self
+    }
+}
+
+
+
+/// Resolve an absolute path to its location in the directory tree. If `follow_final_link` is set
+/// and the path names a symbolic link the link is followed, otherwise the link itself is returned.
+pub fn resolve(path: &str, follow_final_link: bool) -> FsResult<PathLocation>
+{
+    resolve_at(None, path, follow_final_link)
+}
+
+
+
+/// Resolve a path relative to the given starting directory. Absolute paths ignore the starting
+/// directory, relative paths without one are resolved from the root.
+pub fn resolve_at(start: Option<&PathLocation>,
+                  path: &str,
+                  follow_final_link: bool) -> FsResult<PathLocation>
+{
+    walk(start, path, follow_final_link, 0)
+}
+
+
+
+/// Resolve everything but the final component of a path, returning the location of the containing
+/// directory along with the final name. This is what the operations that create or remove entries
+/// need.
+pub fn resolve_parent(path: &str) -> FsResult<(PathLocation, String)>
+{
+    let trimmed = path.trim_end_matches('/');
+
+    let (directory, name) = match trimmed.rfind('/')
+        {
+            Some(index) => (&trimmed[..index + 1], &trimmed[index + 1..]),
+            None        => ("", trimmed)
+        };
+
+    validate_name(name)?;
+
+    if    name == "."
+       || name == ".."
+    {
+        return Err(FsError::InvalidPath);
+    }
+
+    let location = walk(None, directory, true, 0)?;
+
+    if !location.dentry.inode().metadata()?.is_directory()
+    {
+        return Err(FsError::NotADirectory);
+    }
+
+    Ok((location, name.to_string()))
+}
+
+
+
+/// Check that a single name is usable as a directory entry.
+pub fn validate_name(name: &str) -> FsResult<()>
+{
+    if    name.is_empty()
+       || name.contains('/')
+       || name.contains('\0')
+    {
+        return Err(FsError::InvalidPath);
+    }
+
+    if name.len() > MAX_NAME_LENGTH
+    {
+        return Err(FsError::NameTooLong);
+    }
+
+    Ok(())
+}
+
+
+
+/// Walk the components of a path. The depth counts how many symbolic links we have followed to get
+/// here.
+fn walk(start: Option<&PathLocation>,
+        path: &str,
+        follow_final_link: bool,
+        depth: usize) -> FsResult<PathLocation>
+{
+    if path.len() > MAX_PATH_LENGTH
+    {
+        return Err(FsError::NameTooLong);
+    }
+
+    let mut location = match start
+        {
+            Some(start) if !path.starts_with('/') => start.clone(),
+            _                                     => root_location()?.cross_mounts()
+        };
+
+    let mut components = path.split('/').filter(|component| !component.is_empty()).peekable();
+
+    while let Some(component) = components.next()
+    {
+        let is_final = components.peek().is_none();
+
+        match component
+        {
+            "." => continue,
+
+            ".." =>
+                {
+                    location = location.parent();
+                    continue;
+                },
+
+            name =>
+                {
+                    validate_name(name)?;
+
+                    if !location.dentry.inode().metadata()?.is_directory()
+                    {
+                        return Err(FsError::NotADirectory);
+                    }
+
+                    let dentry = location.dentry.lookup(name)?;
+                    let next = PathLocation { mount: location.mount.clone(), dentry }
+                        .cross_mounts();
+
+                    let file_type = next.dentry.inode().metadata()?.file_type;
+
+                    if    file_type == FileType::SymbolicLink
+                       && (!is_final || follow_final_link)
+                    {
+                        if depth >= MAX_SYMBOLIC_LINK_DEPTH
+                        {
+                            return Err(FsError::TooManySymbolicLinks);
+                        }
+
+                        // Relative link targets are resolved from the directory holding the link.
+                        let target = next.dentry.inode().read_link()?;
+
+                        location = walk(Some(&location), &target, true, depth + 1)?;
+                    }
+                    else
+                    {
+                        location = next;
+                    }
+                }
+        }
+    }
+
+    Ok(location)
+}
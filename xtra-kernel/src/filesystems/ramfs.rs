@@ -0,0 +1,715 @@
+// An in memory filesystem. The whole directory tree lives in the kernel's heap and is lost when the
+// filesystem is unmounted, it's used to hold the contents of the initial RAM disk until the real
+// root filesystem is mounted.
+//
+// The kernel's heap is small, so file data can also refer to memory outside of it that lives for as
+// long as the kernel does, such as the initial RAM disk image loaded by the bootloader. That data
+// is only copied into the heap when the file is first changed.
+
+use core::sync::atomic::{ AtomicU64, Ordering };
+
+use alloc::{ collections::BTreeMap, string::{ String, ToString }, sync::Arc, vec::Vec };
+
+use crate::{ filesystems::{ current_time,
+                            inode::{ DirectoryEntry, FileType, Inode, Metadata },
+                            path::MAX_NAME_LENGTH,
+                            Filesystem,
+                            FilesystemStatistics,
+                            FsError,
+                            FsResult },
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// The block size reported for the filesystem and its inodes.
+const BLOCK_SIZE: u32 = 4096;
+
+/// The largest file the filesystem will hold.
+const MAX_FILE_SIZE: u64 = 1 << 32;
+
+/// The permissions given to new symbolic links.
+const SYMBOLIC_LINK_MODE: u16 = 0o777;
+
+
+
+/// The source of inode numbers, shared by all of the in memory filesystems.
+static NEXT_INODE_NUMBER: AtomicU64 = AtomicU64::new(1);
+
+
+
+/// The contents of a regular file.
+enum FileData
+{
+    /// The file's data is held in memory that outlives the kernel's use of it, such as the initial
+    /// RAM disk.
+    Borrowed(&'static [u8]),
+
+    /// The file's data has been copied into, or was written to, the heap.
+    Owned(Vec<u8>)
+}
+
+
+
+impl FileData
+{
+    /// The file's data as a slice.
+    fn as_slice(&self) -> &[u8]
+    {
+        match self
+        {
+            FileData::Borrowed(data) => data,
+            FileData::Owned(data)    => data
+        }
+    }
+
+    /// Get the file's data for changing it, copying it into the heap first if needed.
+    fn make_owned(&mut self) -> &mut Vec<u8>
+    {
+        if let FileData::Borrowed(data) = self
+        {
+            *self = FileData::Owned(data.to_vec());
+        }
+
+        match self
+        {
+            FileData::Owned(data)   => data,
+            FileData::Borrowed(_)   => unreachable!()
+        }
+    }
+}
+
+
+
+/// What an inode holds, depending on its type.
+enum Contents
+{
+    /// The data of a regular file.
+    File(FileData),
+
+    /// The entries of a directory.
+    Directory(BTreeMap<String, Arc<RamInode>>),
+
+    /// The target of a symbolic link.
+    SymbolicLink(String),
+
+    /// Device nodes, fifos and sockets have no contents of their own.
+    Special,
+
+    /// A device node whose reads and writes are handled by a device in the kernel.
+    Device(Arc<dyn Inode>)
+}
+
+
+
+/// The mutable state of an inode.
+struct RamInodeState
+{
+    /// The inode's metadata, the size and block count are computed from the contents when read.
+    metadata: Metadata,
+
+    /// The inode's contents.
+    contents: Contents
+}
+
+
+
+/// An inode of an in memory filesystem.
+pub struct RamInode
+{
+    /// The inode's state, each inode has its own lock.
+    state: SpinMutex<RamInodeState>,
+
+    /// Is the filesystem the inode belongs to read-only?
+    read_only: bool
+}
+
+
+
+impl RamInode
+{
+    /// Create a new inode of the given type, owned by root.
+    fn new(file_type: FileType, mode: u16, contents: Contents, read_only: bool) -> Arc<RamInode>
+    {
+        let now = current_time();
+
+        let metadata = Metadata
+            {
+                inode_number: NEXT_INODE_NUMBER.fetch_add(1, Ordering::Relaxed),
+                file_type,
+                mode,
+                user_id: 0,
+                group_id: 0,
+                size: 0,
+                link_count: if file_type == FileType::Directory { 2 } else { 1 },
+                access_time: now,
+                modify_time: now,
+                change_time: now,
+                block_size: BLOCK_SIZE,
+                blocks: 0
+            };
+
+        Arc::new(RamInode
+            {
+                state: SpinMutex::new(RamInodeState { metadata, contents }),
+                read_only
+            })
+    }
+
+    /// Create a new, empty, directory.
+    pub fn new_directory(mode: u16, read_only: bool) -> Arc<RamInode>
+    {
+        RamInode::new(FileType::Directory, mode, Contents::Directory(BTreeMap::new()), read_only)
+    }
+
+    /// Create a new regular file holding the given data. The data isn't copied until the file is
+    /// changed.
+    pub fn new_file(mode: u16, data: &'static [u8], read_only: bool) -> Arc<RamInode>
+    {
+        RamInode::new(FileType::Regular, mode, Contents::File(FileData::Borrowed(data)), read_only)
+    }
+
+    /// Create a new symbolic link to the given target.
+    pub fn new_symbolic_link(target: &str, read_only: bool) -> Arc<RamInode>
+    {
+        RamInode::new(FileType::SymbolicLink,
+                      SYMBOLIC_LINK_MODE,
+                      Contents::SymbolicLink(target.to_string()),
+                      read_only)
+    }
+
+    /// Create a new device node, fifo or socket.
+    pub fn new_special(file_type: FileType, mode: u16, read_only: bool) -> Arc<RamInode>
+    {
+        RamInode::new(file_type, mode, Contents::Special, read_only)
+    }
+
+    /// Create a device node that passes its reads and writes on to a device in the kernel. The node
+    /// takes its type and permissions from the device.
+    pub fn new_device(device: Arc<dyn Inode>, read_only: bool) -> FsResult<Arc<RamInode>>
+    {
+        let metadata = device.metadata()?;
+
+        Ok(RamInode::new(metadata.file_type, metadata.mode, Contents::Device(device), read_only))
+    }
+
+    /// Set the owner and times of the inode, used when filling the filesystem from an archive.
+    pub fn set_attributes(&self, user_id: u32, group_id: u32, time: u64)
+    {
+        let metadata = &mut self.state.lock().metadata;
+
+        metadata.user_id = user_id;
+        metadata.group_id = group_id;
+        metadata.access_time = time;
+        metadata.modify_time = time;
+        metadata.change_time = time;
+    }
+
+    /// Find the named entry in a directory, keeping its concrete type.
+    pub fn child(&self, name: &str) -> FsResult<Option<Arc<RamInode>>>
+    {
+        match &self.state.lock().contents
+        {
+            Contents::Directory(entries) => Ok(entries.get(name).cloned()),
+            _                            => Err(FsError::NotADirectory)
+        }
+    }
+
+    /// Add a new inode to a directory under the given name. An existing entry with the name is
+    /// replaced, unless either of them is a directory.
+    pub fn insert(&self, name: &str, inode: Arc<RamInode>) -> FsResult<()>
+    {
+        check_name(name)?;
+
+        let is_directory = inode.is_directory();
+        let mut state = self.state.lock();
+        let now = current_time();
+
+        let Contents::Directory(entries) = &mut state.contents
+        else
+        {
+            return Err(FsError::NotADirectory);
+        };
+
+        if let Some(existing) = entries.get(name)
+        {
+            if    is_directory
+               || existing.is_directory()
+            {
+                return Err(FsError::AlreadyExists);
+            }
+
+            let mut existing = existing.state.lock();
+
+            existing.metadata.link_count -= 1;
+            existing.metadata.change_time = now;
+        }
+
+        entries.insert(name.to_string(), inode);
+
+        // The new directory's .. entry refers back to us.
+        if is_directory
+        {
+            state.metadata.link_count += 1;
+        }
+
+        state.metadata.modify_time = now;
+        state.metadata.change_time = now;
+
+        Ok(())
+    }
+
+    /// Add another name for an existing regular file, a hard link, to a directory.
+    pub fn link(&self, name: &str, inode: Arc<RamInode>) -> FsResult<()>
+    {
+        if inode.is_directory()
+        {
+            return Err(FsError::IsADirectory);
+        }
+
+        inode.state.lock().metadata.link_count += 1;
+
+        let result = self.insert(name, inode.clone());
+
+        if result.is_err()
+        {
+            inode.state.lock().metadata.link_count -= 1;
+        }
+
+        result
+    }
+
+    /// Is the inode a directory?
+    pub fn is_directory(&self) -> bool
+    {
+        matches!(self.state.lock().contents, Contents::Directory(_))
+    }
+
+    /// Replace the data of a regular file, used to fill in the data of hard linked files.
+    pub fn set_data(&self, data: &'static [u8]) -> FsResult<()>
+    {
+        match &mut self.state.lock().contents
+        {
+            Contents::File(file_data) =>
+                {
+                    *file_data = FileData::Borrowed(data);
+                    Ok(())
+                },
+
+            Contents::Directory(_) => Err(FsError::IsADirectory),
+            _                      => Err(FsError::InvalidArgument)
+        }
+    }
+
+    /// The device behind a device node, if the inode is one.
+    fn device(&self) -> Option<Arc<dyn Inode>>
+    {
+        match &self.state.lock().contents
+        {
+            Contents::Device(device) => Some(device.clone()),
+            _                        => None
+        }
+    }
+
+    /// Fail with `FsError::ReadOnly` if the inode can't be changed.
+    fn check_writable(&self) -> FsResult<()>
+    {
+        if self.read_only
+        {
+            return Err(FsError::ReadOnly);
+        }
+
+        Ok(())
+    }
+
+    /// Apply a change to the inode's metadata.
+    fn update(&self, change: impl FnOnce(&mut Metadata)) -> FsResult<()>
+    {
+        self.check_writable()?;
+
+        let metadata = &mut self.state.lock().metadata;
+
+        change(metadata);
+        metadata.change_time = current_time();
+
+        Ok(())
+    }
+
+    /// The number of bytes of data and directory entries held by the inode and, for directories,
+    /// everything below it.
+    fn used_bytes(&self) -> u64
+    {
+        match &self.state.lock().contents
+        {
+            Contents::File(data)         => data.as_slice().len() as u64,
+            Contents::SymbolicLink(link) => link.len() as u64,
+            Contents::Special            => 0,
+            Contents::Device(_)          => 0,
+            Contents::Directory(entries) =>
+                {
+                    entries.iter()
+                           .map(|(name, inode)| name.len() as u64 + inode.used_bytes())
+                           .sum()
+                }
+        }
+    }
+}
+
+
+
+/// Make sure a name can be used for a new directory entry.
+fn check_name(name: &str) -> FsResult<()>
+{
+    if    name.is_empty()
+       || name == "."
+       || name == ".."
+       || name.contains('/')
+    {
+        return Err(FsError::InvalidPath);
+    }
+
+    if name.len() > MAX_NAME_LENGTH
+    {
+        return Err(FsError::NameTooLong);
+    }
+
+    Ok(())
+}
+
+
+
+impl Inode for RamInode
+{
+    fn metadata(&self) -> FsResult<Metadata>
+    {
+        let state = self.state.lock();
+        let size = match &state.contents
+            {
+                Contents::File(data)         => data.as_slice().len() as u64,
+                Contents::SymbolicLink(link) => link.len() as u64,
+                Contents::Directory(entries) => entries.len() as u64 * BLOCK_SIZE as u64,
+                Contents::Special            => 0,
+                Contents::Device(_)          => 0
+            };
+
+        Ok(Metadata
+            {
+                size,
+                blocks: size.div_ceil(BLOCK_SIZE as u64) * (BLOCK_SIZE as u64 / 512),
+                ..state.metadata
+            })
+    }
+
+    fn set_mode(&self, mode: u16) -> FsResult<()>
+    {
+        self.update(|metadata| metadata.mode = mode)
+    }
+
+    fn set_owner(&self, user_id: u32, group_id: u32) -> FsResult<()>
+    {
+        self.update(|metadata|
+            {
+                metadata.user_id = user_id;
+                metadata.group_id = group_id;
+            })
+    }
+
+    fn set_times(&self, access_time: u64, modify_time: u64) -> FsResult<()>
+    {
+        self.update(|metadata|
+            {
+                metadata.access_time = access_time;
+                metadata.modify_time = modify_time;
+            })
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> FsResult<usize>
+    {
+        // The device is used without our lock held, its reads may have to wait.
+        if let Some(device) = self.device()
+        {
+            return device.read_at(offset, buffer);
+        }
+
+        let state = self.state.lock();
+
+        let data = match &state.contents
+            {
+                Contents::File(data)   => data.as_slice(),
+                Contents::Directory(_) => return Err(FsError::IsADirectory),
+                _                      => return Err(FsError::InvalidArgument)
+            };
+
+        if offset >= data.len() as u64
+        {
+            return Ok(0);
+        }
+
+        let offset = offset as usize;
+        let length = buffer.len().min(data.len() - offset);
+
+        buffer[..length].copy_from_slice(&data[offset..offset + length]);
+
+        Ok(length)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> FsResult<usize>
+    {
+        // Writing to a device doesn't change the filesystem, so it's allowed even when read-only.
+        if let Some(device) = self.device()
+        {
+            return device.write_at(offset, buffer);
+        }
+
+        self.check_writable()?;
+
+        let end = offset.checked_add(buffer.len() as u64).ok_or(FsError::FileTooLarge)?;
+
+        if end > MAX_FILE_SIZE
+        {
+            return Err(FsError::FileTooLarge);
+        }
+
+        let mut state = self.state.lock();
+
+        let data = match &mut state.contents
+            {
+                Contents::File(data)   => data.make_owned(),
+                Contents::Directory(_) => return Err(FsError::IsADirectory),
+                _                      => return Err(FsError::InvalidArgument)
+            };
+
+        let (offset, end) = (offset as usize, end as usize);
+
+        if data.len() < end
+        {
+            data.resize(end, 0);
+        }
+
+        data[offset..end].copy_from_slice(buffer);
+
+        state.metadata.modify_time = current_time();
+        state.metadata.change_time = state.metadata.modify_time;
+
+        Ok(buffer.len())
+    }
+
+    fn truncate(&self, size: u64) -> FsResult<()>
+    {
+        if let Some(device) = self.device()
+        {
+            return device.truncate(size);
+        }
+
+        self.check_writable()?;
+
+        if size > MAX_FILE_SIZE
+        {
+            return Err(FsError::FileTooLarge);
+        }
+
+        let mut state = self.state.lock();
+
+        match &mut state.contents
+        {
+            Contents::File(data)   => data.make_owned().resize(size as usize, 0),
+            Contents::Directory(_) => return Err(FsError::IsADirectory),
+            _                      => return Err(FsError::InvalidArgument)
+        }
+
+        state.metadata.modify_time = current_time();
+        state.metadata.change_time = state.metadata.modify_time;
+
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>>
+    {
+        match self.child(name)?
+        {
+            Some(inode) => Ok(inode),
+            None        => Err(FsError::NotFound)
+        }
+    }
+
+    fn read_directory(&self) -> FsResult<Vec<DirectoryEntry>>
+    {
+        let state = self.state.lock();
+
+        let Contents::Directory(entries) = &state.contents
+        else
+        {
+            return Err(FsError::NotADirectory);
+        };
+
+        Ok(entries.iter()
+                  .map(|(name, inode)|
+                      {
+                          let metadata = &inode.state.lock().metadata;
+
+                          DirectoryEntry
+                              {
+                                  name: name.clone(),
+                                  inode_number: metadata.inode_number,
+                                  file_type: metadata.file_type
+                              }
+                      })
+                  .collect())
+    }
+
+    fn create(&self, name: &str, file_type: FileType, mode: u16) -> FsResult<Arc<dyn Inode>>
+    {
+        self.check_writable()?;
+
+        if self.child(name)?.is_some()
+        {
+            return Err(FsError::AlreadyExists);
+        }
+
+        let inode = match file_type
+            {
+                FileType::Regular      => RamInode::new_file(mode, &[], false),
+                FileType::Directory    => RamInode::new_directory(mode, false),
+                FileType::SymbolicLink => return Err(FsError::InvalidArgument),
+                _                      => RamInode::new_special(file_type, mode, false)
+            };
+
+        self.insert(name, inode.clone())?;
+
+        Ok(inode)
+    }
+
+    fn symbolic_link(&self, name: &str, target: &str) -> FsResult<Arc<dyn Inode>>
+    {
+        self.check_writable()?;
+
+        if self.child(name)?.is_some()
+        {
+            return Err(FsError::AlreadyExists);
+        }
+
+        if target.is_empty()
+        {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let inode = RamInode::new_symbolic_link(target, false);
+
+        self.insert(name, inode.clone())?;
+
+        Ok(inode)
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()>
+    {
+        if    name == "."
+           || name == ".."
+        {
+            return Err(FsError::InvalidArgument);
+        }
+
+        self.check_writable()?;
+
+        let mut state = self.state.lock();
+
+        let Contents::Directory(entries) = &mut state.contents
+        else
+        {
+            return Err(FsError::NotADirectory);
+        };
+
+        let inode = entries.get(name).cloned().ok_or(FsError::NotFound)?;
+
+        {
+            let mut child = inode.state.lock();
+
+            match &child.contents
+            {
+                Contents::Directory(child_entries) if !child_entries.is_empty() =>
+                    {
+                        return Err(FsError::DirectoryNotEmpty);
+                    },
+
+                Contents::Directory(_) => child.metadata.link_count = 0,
+                _                      => child.metadata.link_count -= 1
+            }
+
+            child.metadata.change_time = current_time();
+        }
+
+        entries.remove(name);
+
+        if inode.is_directory()
+        {
+            state.metadata.link_count -= 1;
+        }
+
+        state.metadata.modify_time = current_time();
+        state.metadata.change_time = state.metadata.modify_time;
+
+        Ok(())
+    }
+
+    fn read_link(&self) -> FsResult<String>
+    {
+        match &self.state.lock().contents
+        {
+            Contents::SymbolicLink(target) => Ok(target.clone()),
+            _                              => Err(FsError::InvalidArgument)
+        }
+    }
+}
+
+
+
+/// A mounted in memory filesystem.
+pub struct RamFilesystem
+{
+    /// The root directory of the filesystem.
+    root: Arc<RamInode>
+}
+
+
+
+impl RamFilesystem
+{
+    /// Create a new in memory filesystem with the given root directory.
+    pub fn new(root: Arc<RamInode>) -> Arc<RamFilesystem>
+    {
+        Arc::new(RamFilesystem { root })
+    }
+}
+
+
+
+impl Filesystem for RamFilesystem
+{
+    fn name(&self) -> &'static str
+    {
+        "ramfs"
+    }
+
+    fn root(&self) -> FsResult<Arc<dyn Inode>>
+    {
+        Ok(self.root.clone())
+    }
+
+    fn statistics(&self) -> FsResult<FilesystemStatistics>
+    {
+        let used_blocks = self.root.used_bytes().div_ceil(BLOCK_SIZE as u64);
+
+        // The filesystem grows as needed, so it's always full.
+        Ok(FilesystemStatistics
+            {
+                block_size: BLOCK_SIZE,
+                total_blocks: used_blocks,
+                free_blocks: 0,
+                total_inodes: 0,
+                free_inodes: 0
+            })
+    }
+
+    fn sync(&self) -> FsResult<()>
+    {
+        Ok(())
+    }
+}
@@ -0,0 +1,479 @@
+// Management of the harts, (hardware threads,) the kernel runs on. The boot hart is the only one
+// the firmware starts for us, it finds the rest through the cpu nodes of the device tree and starts
+// them once the kernel is initialized.
+//
+// Harts are started and stopped through the SBI's hart state management, (HSM,) extension. Each
+// hart gets a stack allocated for it from the heap the first time it's started and enters the
+// kernel at _secondary_start with a pointer to its Hart record, which is where it finds its stack.
+//
+// A running hart can be parked, which hands it back to the firmware until it's restarted. A hart
+// can only stop itself, so parking another hart sends it an IPI call that stops it. A parked hart
+// keeps its stack, when it's restarted it starts over at _secondary_start with a fresh stack frame.
+
+use core::{ fmt::{ self, Display, Formatter },
+            hint::spin_loop,
+            sync::atomic::{ AtomicUsize, Ordering } };
+
+use alloc::{ boxed::Box, collections::BTreeMap, sync::Arc, vec, vec::Vec };
+
+use crate::{ arch::{ csr::read_time,
+                     get_core_index,
+                     sbi::{ hart_get_status,
+                            hart_start,
+                            hart_stop,
+                            probe_extension,
+                            HartStatus,
+                            EXTENSION_HSM } },
+             command_line::get_kernel_options,
+             devices::cpu_devices::{ cpus, timebase_frequency },
+             ipi::{ call_on_harts, unregister_current_hart, CallMode, IpiTarget },
+             locking::spin_mutex::SpinMutex,
+             STACK_SIZE };
+
+
+
+/// How long we give a hart to start or stop before we give up on it, in milliseconds.
+const HART_TIMEOUT_MS: u64 = 1000;
+
+
+
+
+unsafe extern "C"
+{
+    /// Where the secondary harts enter the kernel, found in main.rs.
+    fn _secondary_start();
+}
+
+
+
+/// The states a hart goes through as far as the kernel is concerned.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(usize)]
+pub enum HartState
+{
+    /// The hart has never been started.
+    Offline,
+
+    /// We've asked the firmware to start the hart and are waiting for it to check in.
+    Starting,
+
+    /// The hart is running the kernel.
+    Online,
+
+    /// The hart has been asked to park and hasn't stopped yet.
+    Parking,
+
+    /// The hart has been handed back to the firmware, it can be restarted.
+    Parked
+}
+
+
+
+/// The states a hart can be started from.
+const STARTABLE_STATES: [HartState; 3] =
+    [ HartState::Offline, HartState::Parked, HartState::Parking ];
+
+
+
+impl HartState
+{
+    /// Convert the value stored in a hart record back into a state.
+    fn from_value(value: usize) -> HartState
+    {
+        match value
+        {
+            0 => HartState::Offline,
+            1 => HartState::Starting,
+            2 => HartState::Online,
+            3 => HartState::Parking,
+            _ => HartState::Parked
+        }
+    }
+}
+
+
+
+impl Display for HartState
+{
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result
+    {
+        let name = match self
+            {
+                HartState::Offline  => "offline",
+                HartState::Starting => "starting",
+                HartState::Online   => "online",
+                HartState::Parking  => "parking",
+                HartState::Parked   => "parked"
+            };
+
+        write!(formatter, "{}", name)
+    }
+}
+
+
+
+/// The kernel's record of one of the harts.
+///
+/// **WARNING**: _secondary_start loads the stack pointer from the first field, so stack_top must
+///              stay where it is.
+#[repr(C)]
+pub struct Hart
+{
+    /// The top of the hart's stack, 0 for the boot hart which has its own stack.
+    stack_top: usize,
+
+    /// The ID of the hart.
+    hart_id: usize,
+
+    /// The hart's current HartState.
+    state: AtomicUsize,
+
+    /// The memory of the hart's stack, kept for as long as the hart exists.
+    stack: Option<Box<[u8]>>
+}
+
+
+
+impl Hart
+{
+    /// The ID of the hart.
+    pub fn hart_id(&self) -> usize
+    {
+        self.hart_id
+    }
+
+    /// The hart's current state.
+    pub fn state(&self) -> HartState
+    {
+        HartState::from_value(self.state.load(Ordering::Acquire))
+    }
+
+    /// Move the hart to a new state.
+    fn set_state(&self, state: HartState)
+    {
+        self.state.store(state as usize, Ordering::Release);
+    }
+
+    /// A hart that parks itself can't tell anyone when it's done, so once the firmware reports a
+    /// parking hart as stopped we record it as parked.
+    fn refresh_state(&self) -> HartState
+    {
+        if    self.state() == HartState::Parking
+           && matches!(hart_get_status(self.hart_id), Ok(HartStatus::Stopped))
+        {
+            let _ = self.state.compare_exchange(HartState::Parking as usize,
+                                                HartState::Parked as usize,
+                                                Ordering::AcqRel,
+                                                Ordering::Acquire);
+        }
+
+        self.state()
+    }
+}
+
+
+
+/// The ID of the hart the kernel was booted on.
+static BOOT_HART_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// All of the harts we know about, indexed by their hart IDs.
+static HARTS: SpinMutex<BTreeMap<usize, Arc<Hart>>> = SpinMutex::new(BTreeMap::new());
+
+
+
+/// Remember which hart the kernel was booted on. Called by the boot hart before anything else.
+pub fn set_boot_hart(hart_id: usize)
+{
+    BOOT_HART_ID.store(hart_id, Ordering::Release);
+}
+
+
+/// The ID of the hart the kernel was booted on.
+pub fn boot_hart_id() -> usize
+{
+    BOOT_HART_ID.load(Ordering::Acquire)
+}
+
+
+/// Is the current hart the one the kernel was booted on?
+pub fn is_boot_hart() -> bool
+{
+    get_core_index() == boot_hart_id()
+}
+
+
+
+/// Get the current state of a hart, if it's one we know about.
+pub fn hart_state(hart_id: usize) -> Option<HartState>
+{
+    let hart = HARTS.lock().get(&hart_id).cloned()?;
+
+    Some(hart.refresh_state())
+}
+
+
+/// Get the IDs of the harts that are currently running the kernel.
+pub fn online_harts() -> Vec<usize>
+{
+    HARTS.lock()
+         .values()
+         .filter(|hart| hart.state() == HartState::Online)
+         .map(|hart| hart.hart_id)
+         .collect()
+}
+
+
+
+/// Create the records for the harts found in the device tree and start them. The number of harts
+/// brought up, including the boot hart, can be limited with maxcpus= on the command line.
+///
+/// A hart that fails to start is reported and skipped, the system can carry on without it.
+pub fn start_secondary_harts()
+{
+    let boot_hart_id = boot_hart_id();
+
+    {
+        let mut harts = HARTS.lock();
+
+        harts.insert(boot_hart_id,
+                     Arc::new(Hart
+                         {
+                             stack_top: 0,
+                             hart_id: boot_hart_id,
+                             state: AtomicUsize::new(HartState::Online as usize),
+                             stack: None
+                         }));
+
+        for cpu in cpus()
+        {
+            if    cpu.hart_id == boot_hart_id
+               || !cpu.is_usable()
+            {
+                continue;
+            }
+
+            let stack = vec![0u8; STACK_SIZE].into_boxed_slice();
+            let stack_top = (stack.as_ptr() as usize + STACK_SIZE) & !0xf;
+
+            harts.insert(cpu.hart_id,
+                         Arc::new(Hart
+                             {
+                                 stack_top,
+                                 hart_id: cpu.hart_id,
+                                 state: AtomicUsize::new(HartState::Offline as usize),
+                                 stack: Some(stack)
+                             }));
+        }
+    }
+
+    if !probe_extension(EXTENSION_HSM)
+    {
+        println!("The SBI firmware can't start harts, only the boot hart will be used.");
+        return;
+    }
+
+    let max_cpus = get_kernel_options().max_cpus.unwrap_or(usize::MAX);
+    let hart_ids: Vec<usize> = HARTS.lock().keys().copied().collect();
+    let mut started = 1;
+
+    for hart_id in hart_ids
+    {
+        if hart_id == boot_hart_id
+        {
+            continue;
+        }
+
+        if started >= max_cpus
+        {
+            println!("Hart {:02} is not used, maxcpus={}.", hart_id, max_cpus);
+            continue;
+        }
+
+        match start_hart(hart_id)
+        {
+            Ok(())     => started += 1,
+            Err(error) => println!("Failed to start hart {:02}: {}", hart_id, error)
+        }
+    }
+
+    println!("{} of {} harts are online.", started, HARTS.lock().len());
+}
+
+
+
+/// Start a hart that is offline or has been parked, and wait for it to come online.
+pub fn start_hart(hart_id: usize) -> Result<(), &'static str>
+{
+    let hart = HARTS.lock().get(&hart_id).cloned().ok_or("Unknown hart.")?;
+
+    // Claim the hart so that nobody else tries to start it at the same time.
+    let previous_state = hart.refresh_state();
+
+    if !STARTABLE_STATES.contains(&previous_state)
+    {
+        return Err("The hart is already running.");
+    }
+
+    let result = hart.state.compare_exchange(previous_state as usize,
+                                             HartState::Starting as usize,
+                                             Ordering::AcqRel,
+                                             Ordering::Acquire);
+
+    if result.is_err()
+    {
+        return Err("The hart is already being started or parked.");
+    }
+
+    let hart_ptr = Arc::as_ptr(&hart) as usize;
+
+    if let Err(error) = hart_start(hart_id, _secondary_start as *const () as usize, hart_ptr)
+    {
+        println!("Hart {:02} could not be started by the firmware: {}", hart_id, error);
+
+        // A parking hart may not have made it back to the firmware yet.
+        hart.set_state(previous_state);
+        return Err("The firmware refused to start the hart.");
+    }
+
+    if !wait_for(|| hart.state() == HartState::Online)
+    {
+        // The hart may still turn up later, it will mark itself online when it does.
+        return Err("Timed out waiting for the hart to come online.");
+    }
+
+    Ok(())
+}
+
+
+
+/// Park a running hart, handing it back to the firmware until it's restarted. If it's the current
+/// hart this doesn't return until the hart is restarted, and then it's through _secondary_start.
+///
+/// The boot hart can't be parked, it's the one hart we know we can always count on.
+pub fn park_hart(hart_id: usize) -> Result<(), &'static str>
+{
+    if hart_id == boot_hart_id()
+    {
+        return Err("The boot hart can't be parked.");
+    }
+
+    let hart = HARTS.lock().get(&hart_id).cloned().ok_or("Unknown hart.")?;
+
+    if hart.state() != HartState::Online
+    {
+        return Err("The hart isn't running.");
+    }
+
+    if hart_id == get_core_index()
+    {
+        park_current_hart(hart);
+
+        return Err("The hart is already being parked.");
+    }
+
+    // Only the hart can stop itself, so ask it to. The call runs in the hart's trap handler and
+    // never returns, so there's no point in waiting for it.
+    let parking_hart = hart.clone();
+
+    call_on_harts(IpiTarget::Hart(hart_id),
+                  CallMode::Asynchronous,
+                  move || park_current_hart(parking_hart.clone()))?;
+
+    // The hart is only really gone once the firmware has it.
+    if !wait_for(|| hart.refresh_state() == HartState::Parked)
+    {
+        return Err("Timed out waiting for the hart to park.");
+    }
+
+    Ok(())
+}
+
+
+
+/// Park every online hart other than the current one and the boot hart, as the first step of
+/// shutting the system down. Harts that fail to park are reported and left running.
+pub fn park_other_harts()
+{
+    let current_hart = get_core_index();
+
+    for hart_id in online_harts()
+    {
+        if    hart_id == current_hart
+           || hart_id == boot_hart_id()
+        {
+            continue;
+        }
+
+        if let Err(error) = park_hart(hart_id)
+        {
+            println!("Failed to park hart {:02}: {}", hart_id, error);
+        }
+    }
+}
+
+
+
+/// Called by a secondary hart as it enters the kernel, to let the hart that started it know that
+/// it's up and running.
+pub fn hart_online(hart_id: usize)
+{
+    if let Some(hart) = HARTS.lock().get(&hart_id)
+    {
+        hart.set_state(HartState::Online);
+    }
+}
+
+
+
+/// Hand the current hart back to the firmware. The hart becomes parked once the firmware reports it
+/// as stopped. If the hart is already being parked by someone else we leave them to it.
+///
+/// This is run from the hart's trap handler when another hart asks it to park, so it takes the
+/// hart's record instead of looking it up.
+fn park_current_hart(hart: Arc<Hart>)
+{
+    let result = hart.state.compare_exchange(HartState::Online as usize,
+                                             HartState::Parking as usize,
+                                             Ordering::AcqRel,
+                                             Ordering::Acquire);
+
+    if result.is_err()
+    {
+        return;
+    }
+
+    let hart_id = hart.hart_id;
+
+    drop(hart);
+
+    // Nobody can be waiting on a call that's still in our mailbox once we've stopped.
+    unregister_current_hart();
+
+    // If stopping works we never come back here, a restarted hart goes through _secondary_start.
+    let error = hart_stop();
+
+    panic!("Hart {:02} failed to park itself: {}", hart_id, error);
+}
+
+
+
+/// Wait for the condition to become true, giving up after HART_TIMEOUT_MS. Returns whether the
+/// condition was met.
+fn wait_for<Func>(mut condition: Func) -> bool
+    where
+        Func: FnMut() -> bool
+{
+    let deadline = read_time() + timebase_frequency() * HART_TIMEOUT_MS / 1000;
+
+    while !condition()
+    {
+        if read_time() >= deadline
+        {
+            return false;
+        }
+
+        spin_loop();
+    }
+
+    true
+}
@@ -1,37 +1,173 @@
+// The kernel's interrupt subsystem. The architecture code takes the traps, this is where the rest
+// of the kernel sets up and manages the interrupts it wants to handle.
+//
+// Device drivers register a handler for their device's interrupt number, (its source on the
+// interrupt controller,) and pick the hart the interrupt is routed to. When a device interrupts the
+// hart the handler is looked up and called from the trap handler, so handlers must be quick and
+// must only take locks that are held with interrupts disabled everywhere else.
 
+use alloc::collections::BTreeMap;
 
-pub type InterruptHandler = fn() -> ();
+use crate::{ arch::{ enable_external_interrupt,
+                     enable_software_interrupt,
+                     enable_timer_interrupt,
+                     get_core_index,
+                     interrupts::{ enable_interrupts as enable_hart_interrupts,
+                                   install_trap_vector,
+                                   with_interrupts_disabled } },
+             devices::interrupt_controllers::{ claim_interrupt,
+                                               complete_interrupt,
+                                               disable_source,
+                                               enable_source,
+                                               set_hart_threshold },
+             ipi::register_current_hart,
+             locking::spin_mutex::SpinMutex,
+             random::add_interrupt_randomness };
 
 
 
+/// The function called to handle a device interrupt, it's given the interrupt number so that one
+/// driver can handle the interrupts of all of its devices.
+pub type InterruptHandler = fn(interrupt_number: usize) -> ();
+
+
+
+/// The threshold that masks every device interrupt on a hart, the PLIC's highest priority.
+const MASK_ALL_THRESHOLD: u32 = 7;
+
+
+
+/// The registered interrupt handlers, indexed by interrupt number.
+static INTERRUPT_HANDLERS: SpinMutex<BTreeMap<usize, InterruptHandler>> =
+    SpinMutex::new(BTreeMap::new());
+
+
+
+/// Initialize the interrupt subsystem, called on the boot hart. The secondary harts set themselves
+/// up with initialize_hart_interrupts as they come online.
 pub fn initialize_interrupts() -> Result<(), &'static str>
 {
-     Ok(())
+    initialize_hart_interrupts();
+
+    Ok(())
 }
 
 
-pub fn enable_interrupts(core_id: Option<usize>)
+/// Get the current hart ready to take interrupts. Once its trap vector is installed and it has a
+/// mailbox the hart can be sent calls by the other harts, so that's what we turn on first.
+pub fn initialize_hart_interrupts()
 {
+    install_trap_vector();
+    register_current_hart();
+
+    enable_software_interrupt();
+    enable_timer_interrupt();
+    enable_external_interrupt();
+    enable_hart_interrupts();
 }
 
 
-pub fn disable_interrupts(core_id: Option<usize>)
+
+/// Let device interrupts reach a hart, or the current hart if none is given.
+pub fn enable_interrupts(core_id: Option<usize>) -> Result<(), &'static str>
 {
+    set_hart_threshold(core_id.unwrap_or_else(get_core_index), 0)
 }
 
 
+/// Stop all device interrupts from reaching a hart, or the current hart if none is given. The
+/// interrupts stay pending until they're enabled again.
+pub fn disable_interrupts(core_id: Option<usize>) -> Result<(), &'static str>
+{
+    set_hart_threshold(core_id.unwrap_or_else(get_core_index), MASK_ALL_THRESHOLD)
+}
+
+
+/// Register the handler for a device interrupt and route the interrupt to a hart, or the current
+/// hart if none is given. An interrupt can only have one handler.
 pub fn register_interrupt_handler(core_id: Option<usize>,
                                   interrupt_number: usize,
-                                  handler: InterruptHandler)
+                                  handler: InterruptHandler) -> Result<(), &'static str>
+{
+    let hart_id = core_id.unwrap_or_else(get_core_index);
+
+    with_interrupts_disabled(||
+        {
+            let mut handlers = INTERRUPT_HANDLERS.lock();
+
+            if handlers.contains_key(&interrupt_number)
+            {
+                return Err("The interrupt already has a handler.");
+            }
+
+            handlers.insert(interrupt_number, handler);
+
+            Ok(())
+        })?;
+
+    let result = enable_source(hart_id, interrupt_number);
+
+    if result.is_err()
+    {
+        with_interrupts_disabled(|| INTERRUPT_HANDLERS.lock().remove(&interrupt_number));
+    }
+
+    result
+}
+
+
+/// Stop handling a device interrupt and remove its handler.
+pub fn remove_interrupt_handler(core_id: Option<usize>,
+                                interrupt_number: usize) -> Result<(), &'static str>
 {
+    let hart_id = core_id.unwrap_or_else(get_core_index);
+
+    disable_source(hart_id, interrupt_number)?;
+    with_interrupts_disabled(|| INTERRUPT_HANDLERS.lock().remove(&interrupt_number));
+
+    Ok(())
 }
 
 
-pub fn remove_interrupt_handler(core_id: Option<usize>, interrupt_number: usize)
+/// Stop a device interrupt from reaching its hart while keeping its handler.
+pub fn pause_interrupt_handler(core_id: Option<usize>,
+                               interrupt_number: usize) -> Result<(), &'static str>
 {
+    disable_source(core_id.unwrap_or_else(get_core_index), interrupt_number)
 }
 
 
-pub fn pause_interrupt_handler(core_id: Option<usize>, interrupt_number: usize)
+/// Let a paused device interrupt reach its hart again.
+pub fn resume_interrupt_handler(core_id: Option<usize>,
+                                interrupt_number: usize) -> Result<(), &'static str>
 {
+    enable_source(core_id.unwrap_or_else(get_core_index), interrupt_number)
+}
+
+
+
+/// Handle the device interrupts pending for the current hart, called from the trap handler. Each
+/// one is claimed from the interrupt controller, handled and then completed so that the device can
+/// interrupt again.
+///
+/// An interrupt without a handler shouldn't be possible, if one turns up anyway it's disabled so
+/// that it can't keep interrupting the hart.
+pub fn handle_external_interrupt()
+{
+    let hart_id = get_core_index();
+
+    while let Some(interrupt_number) = claim_interrupt(hart_id)
+    {
+        let handler = INTERRUPT_HANDLERS.lock().get(&interrupt_number).copied();
+
+        add_interrupt_randomness(interrupt_number);
+
+        match handler
+        {
+            Some(handler) => handler(interrupt_number),
+            None          => { let _ = disable_source(hart_id, interrupt_number); }
+        }
+
+        complete_interrupt(hart_id, interrupt_number);
+    }
 }
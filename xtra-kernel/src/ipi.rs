@@ -0,0 +1,338 @@
+// Inter-processor interrupts, (IPIs,) let one hart ask the others to do something for it. The
+// kernel uses them to halt the system on a panic, to shoot down stale TLB entries when a page is
+// unmapped, to wake up a hart so that its scheduler can look for work, and to park harts for a
+// clean shutdown.
+//
+// Each hart has a mailbox of calls waiting to be run on it. Sending a call puts it in the mailbox
+// of every hart it's for and raises a software interrupt on them through the SBI. The interrupted
+// hart runs everything in its mailbox from its trap handler. A synchronous call waits for every
+// hart to finish running it, an asynchronous one returns as soon as the call has been delivered.
+// A hart that's being parked closes its mailbox and runs what's left in it before it stops, calls
+// sent to it after that fail.
+//
+// The calls are run in interrupt context, so they must not take any lock the interrupted code could
+// be holding. The mailbox locks themselves are only ever taken with interrupts disabled.
+//
+// A panic can't rely on any of that, the heap or the mailboxes may be what's broken. So panics are
+// broadcast with a flag and a bare IPI to every hart, which halts any hart that sees the flag set.
+
+use core::{ hint::spin_loop, sync::atomic::{ AtomicBool, AtomicUsize, Ordering } };
+
+use alloc::{ boxed::Box, collections::{ BTreeMap, VecDeque }, sync::Arc, vec, vec::Vec };
+
+use crate::{ arch::{ clear_software_interrupt,
+                     get_core_index,
+                     interrupts::{ disable_interrupts, with_interrupts_disabled },
+                     mmu::flush_tlb_page,
+                     sbi::{ send_ipi, HART_MASK_ALL },
+                     wait_for_interrupt },
+             harts::online_harts,
+             locking::spin_mutex::SpinMutex };
+
+
+
+/// Which harts a call should be run on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IpiTarget
+{
+    /// Just the one hart, which may be the current one.
+    Hart(usize),
+
+    /// Every online hart, including the current one.
+    AllHarts,
+
+    /// Every online hart except the current one.
+    OtherHarts
+}
+
+
+
+/// Whether sending a call waits for it to be run.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CallMode
+{
+    /// Wait until every target hart has finished running the call.
+    Synchronous,
+
+    /// Return as soon as the call has been delivered to the target harts.
+    Asynchronous
+}
+
+
+
+/// A call sent to one or more harts.
+struct IpiCall
+{
+    /// The function to run on each of the target harts.
+    function: Box<dyn Fn() + Send + Sync>,
+
+    /// How many of the target harts have yet to run the call.
+    remaining: AtomicUsize
+}
+
+
+
+/// The calls waiting to be run on a hart.
+struct Mailbox
+{
+    /// The calls, in the order they were sent.
+    calls: VecDeque<Arc<IpiCall>>,
+
+    /// Is the hart taking calls? A hart that's being parked closes its mailbox, so that nobody
+    /// waits on a call it will never run.
+    is_open: bool
+}
+
+
+
+/// The mailboxes of the harts that can take calls, indexed by their hart IDs.
+static MAILBOXES: SpinMutex<BTreeMap<usize, Arc<SpinMutex<Mailbox>>>> =
+    SpinMutex::new(BTreeMap::new());
+
+/// Set once the kernel has panicked, every hart that sees it halts.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+
+
+/// Create the mailbox for the current hart so that it can be sent calls. Each hart does this for
+/// itself as it sets up its interrupts, a restarted hart opens its old mailbox again.
+pub fn register_current_hart()
+{
+    let hart_id = get_core_index();
+
+    with_interrupts_disabled(||
+        {
+            let mut mailboxes = MAILBOXES.lock();
+            let mailbox = mailboxes.entry(hart_id).or_insert_with(||
+                {
+                    Arc::new(SpinMutex::new(Mailbox { calls: VecDeque::new(), is_open: false }))
+                });
+
+            mailbox.lock().is_open = true;
+        });
+}
+
+
+
+/// Stop the current hart from taking calls, as it's about to be parked. Calls sent from now on are
+/// refused, and the ones already waiting are run so that a synchronous caller isn't left waiting
+/// for a hart that has stopped.
+pub fn unregister_current_hart()
+{
+    let hart_id = get_core_index();
+    let mailbox = with_interrupts_disabled(|| MAILBOXES.lock().get(&hart_id).cloned());
+
+    if let Some(mailbox) = mailbox
+    {
+        with_interrupts_disabled(|| mailbox.lock().is_open = false);
+    }
+
+    run_pending_calls();
+}
+
+
+
+/// Run a function on the target harts. Harts that aren't online are skipped, except that an offline
+/// hart asked for by name is an error.
+///
+/// If the current hart is one of the targets the function is run on it directly. While waiting for
+/// a synchronous call the current hart keeps running the calls sent to it, so two harts calling
+/// each other at the same time don't deadlock.
+pub fn call_on_harts<Func>(target: IpiTarget, mode: CallMode, function: Func)
+    -> Result<(), &'static str>
+    where
+        Func: Fn() + Send + Sync + 'static
+{
+    let current_hart = get_core_index();
+    let online = online_harts();
+
+    let (runs_here, others): (bool, Vec<usize>) = match target
+        {
+            IpiTarget::Hart(hart_id) if hart_id == current_hart => (true, Vec::new()),
+
+            IpiTarget::Hart(hart_id) =>
+                {
+                    if !online.contains(&hart_id)
+                    {
+                        return Err("The hart isn't online.");
+                    }
+
+                    (false, vec![ hart_id ])
+                },
+
+            IpiTarget::AllHarts | IpiTarget::OtherHarts =>
+                {
+                    let others = online.into_iter()
+                                       .filter(|&hart_id| hart_id != current_hart)
+                                       .collect();
+
+                    (target == IpiTarget::AllHarts, others)
+                }
+        };
+
+    let call = Arc::new(IpiCall
+        {
+            function: Box::new(function),
+            remaining: AtomicUsize::new(others.len())
+        });
+
+    let mut result = Ok(());
+
+    for hart_id in others
+    {
+        if let Err(error) = deliver(&call, hart_id)
+        {
+            println!("Failed to send a call to hart {:02}: {}", hart_id, error);
+            result = Err("The call could not be delivered to every hart.");
+        }
+    }
+
+    if runs_here
+    {
+        (call.function)();
+    }
+
+    if mode == CallMode::Synchronous
+    {
+        while call.remaining.load(Ordering::Acquire) != 0
+        {
+            halt_if_panicking();
+            run_pending_calls();
+
+            spin_loop();
+        }
+    }
+
+    result
+}
+
+
+
+/// Flush the TLB entries for a virtual address on every online hart, waiting until they're all
+/// done. Called whenever a page is unmapped, so that no hart can keep using the old mapping.
+pub fn shootdown_tlb(virtual_address: usize)
+{
+    let result = call_on_harts(IpiTarget::AllHarts,
+                               CallMode::Synchronous,
+                               move || flush_tlb_page(virtual_address));
+
+    if let Err(error) = result
+    {
+        println!("TLB shootdown of 0x{:016x} failed: {}", virtual_address, error);
+    }
+}
+
+
+
+/// Stop every other hart from running the kernel, called by the panic handler. This doesn't wait
+/// for the harts to stop, and doesn't need the heap or any locks.
+pub fn halt_other_harts()
+{
+    PANICKING.store(true, Ordering::Release);
+
+    let _ = send_ipi(0, HART_MASK_ALL);
+}
+
+
+
+/// Handle a software interrupt raised on the current hart, called from the trap handler.
+pub fn handle_ipi()
+{
+    // Clear the interrupt first, so that a call sent while we're busy raises it again.
+    clear_software_interrupt();
+
+    halt_if_panicking();
+    run_pending_calls();
+}
+
+
+
+/// Run every call waiting in the current hart's mailbox.
+fn run_pending_calls()
+{
+    let hart_id = get_core_index();
+    let mailbox = with_interrupts_disabled(|| MAILBOXES.lock().get(&hart_id).cloned());
+
+    let Some(mailbox) = mailbox
+    else
+    {
+        return;
+    };
+
+    while let Some(call) = with_interrupts_disabled(|| mailbox.lock().calls.pop_front())
+    {
+        (call.function)();
+        call.remaining.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+
+
+/// Put a call in a hart's mailbox and interrupt the hart. If the hart isn't taking calls, or the
+/// interrupt can't be sent, the call is counted as done so a synchronous caller isn't left waiting
+/// for it.
+fn deliver(call: &Arc<IpiCall>, hart_id: usize) -> Result<(), &'static str>
+{
+    let mailbox = with_interrupts_disabled(|| MAILBOXES.lock().get(&hart_id).cloned());
+
+    let Some(mailbox) = mailbox
+    else
+    {
+        call.remaining.fetch_sub(1, Ordering::AcqRel);
+        return Err("The hart has no mailbox.");
+    };
+
+    let is_open = with_interrupts_disabled(||
+        {
+            let mut mailbox = mailbox.lock();
+
+            if mailbox.is_open
+            {
+                mailbox.calls.push_back(call.clone());
+            }
+
+            mailbox.is_open
+        });
+
+    if !is_open
+    {
+        call.remaining.fetch_sub(1, Ordering::AcqRel);
+        return Err("The hart isn't taking calls.");
+    }
+
+    if send_ipi(1, hart_id).is_ok()
+    {
+        return Ok(());
+    }
+
+    with_interrupts_disabled(||
+        {
+            let calls = &mut mailbox.lock().calls;
+
+            if let Some(index) = calls.iter().position(|queued| Arc::ptr_eq(queued, call))
+            {
+                calls.remove(index);
+                call.remaining.fetch_sub(1, Ordering::AcqRel);
+            }
+        });
+
+    Err("The SBI firmware failed to send the interrupt.")
+}
+
+
+
+/// Halt the current hart for good if the kernel has panicked on another hart.
+fn halt_if_panicking()
+{
+    if !PANICKING.load(Ordering::Acquire)
+    {
+        return;
+    }
+
+    disable_interrupts();
+
+    loop
+    {
+        wait_for_interrupt();
+    }
+}
@@ -47,3 +47,6 @@ impl<'a, T: Locking> Drop for LockGuard<'a, T>
 
 /// The spinlock module provides a simple spinlock implementation for the xtra kernel.
 pub mod spin_lock;
+
+/// A spinlock that owns the data it protects, only handing out access while the lock is held.
+pub mod spin_mutex;
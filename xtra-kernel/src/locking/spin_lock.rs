@@ -27,6 +27,13 @@ impl SpinLock
     {
         SpinLock { locked: AtomicBool::new(false) }
     }
+
+    /// Try to acquire the lock without waiting for it. Returns true if the lock was acquired, in
+    /// which case it must be released with unlock.
+    pub fn try_lock(&self) -> bool
+    {
+        !self.locked.swap(true, Ordering::Acquire)
+    }
 }
 
 
@@ -0,0 +1,94 @@
+/// Implementation of a data owning spinlock for the xtra kernel.
+
+use core::{ cell::UnsafeCell, ops::{ Deref, DerefMut } };
+
+use crate::locking::{ Locking, spin_lock::SpinLock };
+
+
+
+/// A spinlock that owns the data it protects. Unlike the raw `SpinLock` the data can only be
+/// reached through the guard returned by `lock`, so it is impossible to touch the data without
+/// holding the lock.
+///
+/// Like the `SpinLock` this lock is not reentrant, attempting to lock it twice from the same thread
+/// will deadlock.
+pub struct SpinMutex<T>
+{
+    lock: SpinLock,       // The lock protecting the data.
+    data: UnsafeCell<T>   // The protected data itself.
+}
+
+
+
+/// The mutex only ever hands out access to one thread at a time, so it is safe to share between
+/// harts as long as the data itself can be sent between them.
+unsafe impl<T: Send> Sync for SpinMutex<T> {}
+unsafe impl<T: Send> Send for SpinMutex<T> {}
+
+
+
+impl<T> SpinMutex<T>
+{
+    /// Create a new unlocked mutex protecting the given data.
+    pub const fn new(data: T) -> Self
+    {
+        SpinMutex { lock: SpinLock::new(), data: UnsafeCell::new(data) }
+    }
+
+    /// Acquire the lock, spinning until it is available. The lock is released when the returned
+    /// guard goes out of scope.
+    pub fn lock(&self) -> SpinMutexGuard<'_, T>
+    {
+        self.lock.lock();
+        SpinMutexGuard { mutex: self }
+    }
+
+    /// Try to acquire the lock without waiting for it, for code like the panic handler that can't
+    /// risk waiting on a lock its own hart may be holding.
+    pub fn try_lock(&self) -> Option<SpinMutexGuard<'_, T>>
+    {
+        self.lock.try_lock().then(|| SpinMutexGuard { mutex: self })
+    }
+}
+
+
+
+/// Guard giving access to the data of a locked `SpinMutex`. The lock is released when the guard is
+/// dropped.
+pub struct SpinMutexGuard<'a, T>
+{
+    mutex: &'a SpinMutex<T>  // The mutex that is being held.
+}
+
+
+
+impl<'a, T> Deref for SpinMutexGuard<'a, T>
+{
+    type Target = T;
+
+    fn deref(&self) -> &T
+    {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+
+
+impl<'a, T> DerefMut for SpinMutexGuard<'a, T>
+{
+    fn deref_mut(&mut self) -> &mut T
+    {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+
+
+impl<'a, T> Drop for SpinMutexGuard<'a, T>
+{
+    /// Release the lock when the guard goes out of scope.
+    fn drop(&mut self)
+    {
+        self.mutex.lock.unlock();
+    }
+}
@@ -38,6 +38,16 @@ mod printing;
 /// All of the locking primitives used in the kernel.
 mod locking;
 
+/// A fixed size ring buffer of bytes, used by the drivers and the network stack alike.
+mod ring_buffer;
+
+/// Our copy of the information handed to us by the bootloader.
+mod boot_info;
+
+/// Parsing of the kernel command line into the kernel's options, and the registry of parameters
+/// declared by the kernel's subsystems.
+mod command_line;
+
 /// The memory management for the kernel. This includes raw page management and virtualization, as
 /// well as the heap allocator for the kernel built atop of the page allocator.
 mod memory;
@@ -52,14 +62,35 @@ mod devices;
 /// interrupt controller.
 mod interrupts;
 
+/// Inter-processor interrupts, how the harts ask each other to run code on their behalf.
+mod ipi;
+
+/// One shot timers, handlers called from the timer interrupt once their deadline has passed.
+mod timers;
+
+/// The kernel's random number generator, a ChaCha20 based CSPRNG fed from the entropy devices,
+/// timer jitter and the timing of interrupts.
+mod random;
+
 /// The file system support for the kernel. Including our implementation of FAT-32 and Ext2 file
 /// systems.
 mod filesystems;
 
+/// The TCP/IP stack, Ethernet, ARP, IPv4, ICMP, UDP and TCP over the network devices, along with
+/// the DHCP client that configures them and the sockets the rest of the kernel uses.
+mod net;
+
+/// The system calls user programs make into the kernel, and the file descriptor table they work
+/// through.
+mod syscalls;
+
 /// The scheduler for the kernel. It's here where we manage all of the user processes and their
 /// threads.
 mod scheduler;
 
+/// Bringing up the secondary harts, and parking and restarting them while the system runs.
+mod harts;
+
 
 
 /// The prelude module for the kernel, this is where we re-export commonly used types and traits
@@ -67,7 +98,12 @@ mod scheduler;
 /// import them directly.
 pub mod prelude
 {
-    pub use alloc::{ boxed::Box, collections::BTreeMap, rc::Rc, string::String, vec::Vec };
+    pub use alloc::{ boxed::Box,
+                     collections::BTreeMap,
+                     rc::Rc,
+                     string::String,
+                     sync::Arc,
+                     vec::Vec };
 
     pub use crate::printing::BufferWriter;
 }
@@ -76,17 +112,28 @@ pub mod prelude
 
 use core::{ arch::naked_asm,
             hint::spin_loop,
-            panic::PanicInfo,
-            ptr::addr_of_mut,
-            sync::atomic::{ AtomicBool, Ordering } };
+            panic::PanicInfo };
 
-use xtra_kernel_shared::{ device_tree::DeviceTree, mount_table::XtraMountTable };
+use xtra_kernel_shared::{ boot_info::XtraBootInfo, device_tree::DeviceTree };
 
-use crate::{ arch::{ get_core_index, print_cpu_info },
+use crate::{ arch::{ get_core_index, interrupts::disable_interrupts, print_cpu_info },
+             boot_info::{ get_boot_info, init_boot_info, mark_boot_successful },
+             command_line::{ get_kernel_options,
+                             init_kernel_options,
+                             initialize_kernel_parameters,
+                             report_command_line_problems,
+                             MAX_LOG_LEVEL },
              devices::{ activate_devices, initialize_device_registry, walk_device_tree },
              filesystems::initialize_filesystems,
-             interrupts::initialize_interrupts,
-             printing::init_printing,
+             harts::{ hart_online, set_boot_hart, start_secondary_harts },
+             interrupts::{ initialize_hart_interrupts, initialize_interrupts },
+             ipi::halt_other_harts,
+             printing::{ init_printing,
+                         set_log_level,
+                         switch_to_boot_uart,
+                         switch_to_console_device },
+             net::initialize_network,
+             random::initialize_random,
              memory::{ heap::initialize_heap,
                        kernel::KernelMemoryLayout,
                        memory_device::SystemMemory,
@@ -96,8 +143,8 @@ use crate::{ arch::{ get_core_index, print_cpu_info },
 
 
 /// The OS banner to print at startup, this is a simple ASCII art banner that is printed to the
-/// UART console when the bootloader starts.
-const OS_BANNER_STR: &str = include_str!("../banner.txt");
+/// UART console when the kernel starts, and drawn on the display once the console finds one.
+pub const OS_BANNER_STR: &str = include_str!("../banner.txt");
 
 /// A banner for the OS panic message when printed out the UART console.
 const OS_PANIC_STR: &str = include_str!("../panic.txt");
@@ -111,7 +158,8 @@ const KERNEL_BUILD_TIME: &str = env!("BUILD_TIME");
 /// The profile the kernel was built with.
 const KERNEL_PROFILE: &str = env!("PROFILE");
 
-/// Go with a 4KB stack size for each CPU core.
+/// Go with a 32KB stack size for each CPU core. The boot hart's stack is reserved by the linker
+/// script, the other harts have theirs allocated from the heap when they're first started.
 ///
 /// TODO: Move this into arch and make it a configurable option in the kernel config file.
 ///
@@ -120,70 +168,28 @@ const KERNEL_PROFILE: &str = env!("PROFILE");
 ///              change it in the linker script as well.
 const STACK_SIZE: usize = 0x8000;
 
-const _: () =
-    {
-        assert!(STACK_SIZE.is_power_of_two(), "The stack size must be a power of two.");
-    };
-
-/// Maximum number of cores we support in the system.
-///
-/// TODO: Move this into arch and make it a configurable option in the kernel config file.
-///
-/// **WARNING**: This **needs** to be kept in sync with the linker script as it defines the size of
-///              the .stacks section in the kernel binary layout. If we change it here we need to
-///              change it in the linker script as well.
-const MAX_CORES: usize = 4;
-
 
 
-/// Allocate the space for a stack for each core in the system.
-///
-/// TODO: Move this into arch and make it a configurable option in the kernel config file.
+/// The stack the boot hart runs on.
 #[unsafe(no_mangle)]
 #[unsafe(link_section = ".stacks")]
-static mut STACKS: [u8; STACK_SIZE * MAX_CORES] = [0; STACK_SIZE * MAX_CORES];
-
-
-
-/// Keep track of whether the system has the global initialization has completed or not. This is used
-/// to ensure that only the first hart runs the core boot process and the others wait for it to
-/// complete.
-static GLOBAL_INIT_COMPLETE: AtomicBool = AtomicBool::new(false);
-
-
-
-/// Check if the system has booted yet. This is used to ensure that only the first hart runs the
-/// boot process and the others wait for it to complete.
-fn is_global_init_completed() -> bool
-{
-    GLOBAL_INIT_COMPLETE.load(Ordering::Acquire)
-}
-
-
-
-/// Signal to the secondary harts that the system is ready for them to start running their scheduler.
-/// This is called by the first hart after it has completed the boot process.
-fn set_global_init_completed()
-{
-    GLOBAL_INIT_COMPLETE.store(true, Ordering::Release);
-}
+static mut BOOT_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
 
 
 
 // TODO: Move this into the arch module as it is architecture specific.
-/// This is the raw starting point of the bootloader, it is called directly by the host environment,
-/// in this case, QEMU. We setup a reasonable stack pointer and then jump to the main function, we
-/// expect main to never return as it is its job to find and load the actual kernel image and
-/// transfer control to it.
+/// This is the raw starting point of the kernel, it is entered in supervisor mode by the SBI
+/// firmware, in this case, our bootloader. We setup a reasonable stack pointer and then jump to the
+/// main function, we expect main to never return as it is its job to run the system from here on
+/// out.
+///
+/// Only the boot hart comes in this way, the others are started later through _secondary_start.
 #[cfg(target_arch = "riscv64")]
 #[unsafe(naked)]
 #[unsafe(no_mangle)]
 #[unsafe(link_section = ".text._start")]
 pub unsafe extern "C" fn _start() -> !
 {
-    // How much we need to shift to multiply the core index by the stack size.
-    const STACK_SHIFT: usize = STACK_SIZE.trailing_zeros() as usize;
-
     // This function is called system startup code. There is no Rust runtime available at this
     // point, so we cannot use any Rust features, we just setup the stack and then jump to the
     // proper main function.
@@ -191,27 +197,45 @@ pub unsafe extern "C" fn _start() -> !
     (
         // a0 = hart_id
         // a1 = dtb_ptr
+        // a2 = boot_info_ptr
 
-        "la t0, STACKS",               // t0 = &STACKS.
-        "slli t1, a0, {stack_shift}",  // t1 = hart_id * STACK_SIZE.
+        "mv tp, a0",                   // We can't read mhartid in supervisor mode, so keep the
+                                       // hart ID in tp for get_core_index.
 
-        "add t0, t0, t1",              // t0 = &STACKS[hart_id * STACK_SIZE].
+        "la t0, BOOT_STACK",           // t0 = &BOOT_STACK.
         "li t1, {stack_size}",         // t1 = STACK_SIZE.
 
                                        // We're setting the stack pointer to the top of stack so
                                        // that it will grow down towards zero.
-                                       // We set sp to one-past the end of this hart's stack region
-                                       // (the top), so pushes/allocas move toward lower addresses
-                                       // within the allocated slice.
 
-        "add t0, t0, t1",              // t0 = &STACKS[hart_id * STACK_SIZE + STACK_SIZE]
+        "add sp, t0, t1",              // sp = &BOOT_STACK[STACK_SIZE].
+
+        "j main",                      // main(hart_id, dtb, boot_info)
+
+        stack_size = const STACK_SIZE
+    );
+}
 
-        "mv sp, t0",                   // set sp to top of stack for this hart.
 
-        "j main",                      // main(hart_id, dtb)
 
-        stack_size = const STACK_SIZE,
-        stack_shift = const STACK_SHIFT
+// TODO: Move this into the arch module as it is architecture specific.
+/// Where the secondary harts enter the kernel when the SBI firmware starts them, both when they're
+/// first brought up and when they're restarted after being parked. The opaque value given to the
+/// firmware is the hart's record, which starts with the top of the stack allocated for it.
+#[cfg(target_arch = "riscv64")]
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn _secondary_start() -> !
+{
+    naked_asm!
+    (
+        // a0 = hart_id
+        // a1 = &Hart
+
+        "mv tp, a0",                   // Keep the hart ID in tp, like _start does.
+        "ld sp, 0(a1)",                // sp = hart.stack_top.
+
+        "j secondary_main"             // secondary_main(hart_id)
     );
 }
 
@@ -223,10 +247,19 @@ pub unsafe extern "C" fn _start() -> !
 fn kernel_panic_handler(info: &PanicInfo) -> !
 {
     // TODO: If println has not been initialized yet, we should attempt to do so here.
-    // TODO: Halt the other harts and disable interrupts.
+
+    // Nothing else gets to run now, on this hart or any other.
+    disable_interrupts();
+    halt_other_harts();
+
+    // The console may be in any state, so the panic message goes straight out the boot UART.
+    switch_to_boot_uart();
 
     let core_index = get_core_index();
 
+    // Panics are always printed, no matter what log level was asked for on the command line.
+    set_log_level(MAX_LOG_LEVEL);
+
     println!("{}", OS_PANIC_STR);
     println!("Fatal error occurred on core {:02}:\n{}", core_index, info);
 
@@ -245,17 +278,8 @@ fn kernel_panic_handler(info: &PanicInfo) -> !
 #[unsafe(no_mangle)]
 pub extern "C" fn main(core_index: usize,
                        device_tree_ptr: *const u8,
-                       mount_table_ptr: *const XtraMountTable) -> !
+                       boot_info_ptr: *const XtraBootInfo) -> !
 {
-    // Storage space for the system mount table.
-    let mut mount_table: XtraMountTable = XtraMountTable::default();
-
-    // Make sure that we can support the number of cores we have in the system.
-    assert!(core_index < MAX_CORES,
-            "Unsupported CPU hart ID: {:02}, max supported cores: {:02}.",
-            core_index,
-            MAX_CORES);
-
     // Make sure that the core index matches the one supplied by the bootloader. If it isn't then
     // something seriously wrong has happened.
     assert!(core_index == get_core_index(),
@@ -263,141 +287,172 @@ pub extern "C" fn main(core_index: usize,
             core_index,
             get_core_index());
 
-    // Make sure that we are only running the core boot process on the first hart.
-    if core_index != 0
+    // Whichever hart the firmware booted us on is the boot hart, the others are started by it once
+    // the kernel is initialized.
+    set_boot_hart(core_index);
+
+    // Initialize the device tree iterator from the pointer passed in by the host environment.
+    let device_tree = DeviceTree::new(device_tree_ptr);
+
+    // Copy the boot information from the bootloader right away so that we don't damage it when
+    // we overwrite the bootloader's memory with our own usage. We can't report a problem with
+    // it until printing is up, so hold on to the result until then.
+    let boot_info_result = init_boot_info(boot_info_ptr);
+
+    // Parse the options we need for early boot from the command line, such as which UART to
+    // log to and how much to print.
+    if boot_info_result.is_ok()
     {
-        // Wait for the boot process to complete.
-        while !is_global_init_completed()
-        {
-            // Let the compiler know that this is a busy wait. This will allow it to emit hints to
-            // the CPU to optimize this loop and minimize it's power usage.
-            spin_loop();
-        }
-
-        // Let the world know we're running.
-        println!("Core {:02} is now running.", core_index);
-
-        // We know that the memory manager has been initialized by the first hart, so we can safely
-        // switch to the kernel address space and start running the scheduler.
-        println!("Switching to kernel address space for hart {:02}.", core_index);
-
-        convert_to_kernel_address_space();
+        init_kernel_options();
     }
-    else
-    {
-        // Initialize the device tree iterator from the pointer passed in by the host environment.
-        let device_tree = DeviceTree::new(device_tree_ptr);
 
-        // Init the logging system using the device tree to find the UART device. We use the
-        // system's first UART device for system logging. Any other UART devices will be used as
-        //  consoles.
-        init_printing(&device_tree);
+    let kernel_options = get_kernel_options();
+
+    set_log_level(kernel_options.log_level);
+
+    // Init the logging system using the device tree to find the UART device. We use the UART
+    // device picked by the console= parameter, or the system's first UART device if there
+    // isn't one, for system logging. Any other UART devices will be used as consoles.
+    init_printing(&device_tree, kernel_options.console);
+
+    // Print the OS banner to the UART console.
+    print!("{}", OS_BANNER_STR);
+    println!("Kernel version:      {}", KERNEL_VERSION);
+    println!("Kernel build time:   {}", KERNEL_BUILD_TIME);
+    println!("Kernel profile:      {}", KERNEL_PROFILE);
+    println!();
 
-        // Print the OS banner to the UART console.
-        print!("{}", OS_BANNER_STR);
-        println!("Kernel version:      {}", KERNEL_VERSION);
-        println!("Kernel build time:   {}", KERNEL_BUILD_TIME);
-        println!("Kernel profile:      {}", KERNEL_PROFILE);
-        println!();
+    // Print out the CPU information for the current core.
+    print_cpu_info();
 
-        // Print out the CPU information for the current core.
-        print_cpu_info();
+    boot_info_result.expect("Failed to read the boot information passed by the bootloader");
 
-        // Copy the mount table from the bootloader right away so that we don't damage it when we
-        // overwrite the bootloader's memory with our own usage.
-        mount_table = unsafe { (*mount_table_ptr).clone() };
+    print!("{}", get_boot_info());
+    println!("Kernel options: {}", kernel_options);
 
-        // Determine where in RAM the kernel is loaded. We need to keep track of this so that we can
-        // mark these pages as used in the memory manager.
-        let kernel_memory_layout = KernelMemoryLayout::new(&device_tree);
+    report_command_line_problems();
 
-        println!("{}", kernel_memory_layout);
+    // Determine where in RAM the kernel is loaded. We need to keep track of this so that we can
+    // mark these pages as used in the memory manager.
+    let kernel_memory_layout = KernelMemoryLayout::new(&device_tree);
 
-        // Interrogate the memory to find out what we are working with.
-        let memory_info = SystemMemory::new(&device_tree);
+    println!("{}", kernel_memory_layout);
 
-        println!("{}", memory_info);
+    // Interrogate the memory to find out what we are working with.
+    let memory_info = SystemMemory::new(&device_tree);
 
-        // We now need to properly initialize the MMU and map the kernel into high memory so that we
-        // can run from our proper address. This will involve resetting the PC to the new kernel
-        // address space.
-        println!("Initializing memory manager...");
+    println!("{}", memory_info);
 
-        init_memory_manager(&kernel_memory_layout, &memory_info)
-            .expect("Failed to initialize memory manager");
+    // We now need to properly initialize the MMU and map the kernel into high memory so that we
+    // can run from our proper address. This will involve resetting the PC to the new kernel
+    // address space.
+    println!("Initializing memory manager...");
 
-        convert_to_kernel_address_space();
+    init_memory_manager(&kernel_memory_layout, &memory_info)
+        .expect("Failed to initialize memory manager");
 
-        // Now we can initialize our heap so that we can dynamically allocate memory in the Kernel.
-        println!("Initializing heap allocator...");
+    convert_to_kernel_address_space();
 
-        initialize_heap(&kernel_memory_layout)
-            .expect("Failed to initialize heap allocator");
+    // Now we can initialize our heap so that we can dynamically allocate memory in the Kernel.
+    println!("Initializing heap allocator...");
 
+    initialize_heap(&kernel_memory_layout)
+        .expect("Failed to initialize heap allocator");
 
-        // Initialize the device registry with the device drivers that we have compiled into the
-        // kernel.
-        println!("Initializing device driver subsystem...");
+    // Now that we can allocate we can build the registry of parameters declared by the
+    // kernel's subsystems, and hand them their values from the command line.
+    println!("Applying kernel parameters...");
 
-        let device_registry = initialize_device_registry()
-            .expect("Failed to initialize device driver registry");
+    initialize_kernel_parameters()
+        .expect("Failed to initialize kernel parameter registry");
 
-        // Walk the device tree and find and initialize our supported devices. Once this is done we
-        // can free the device tree pages. Any information needed from the device tree should be
-        // copied by the respective device drivers.
-        println!("  Discovering attached devices...");
+    // Initialize the device registry with the device drivers that we have compiled into the
+    // kernel.
+    println!("Initializing device driver subsystem...");
 
-        walk_device_tree(&device_tree, device_registry)
-            .expect("Failed to walk device tree and initialize devices");
+    let device_registry = initialize_device_registry()
+        .expect("Failed to initialize device driver registry");
 
-        // Initialize the interrupt controller so that we can handle interrupts and exceptions in
-        // the kernel.
-        println!("Initializing interrupt controller...");
+    // Walk the device tree and find and initialize our supported devices. Once this is done we
+    // can free the device tree pages. Any information needed from the device tree should be
+    // copied by the respective device drivers.
+    println!("  Discovering attached devices...");
 
-        initialize_interrupts()
-            .expect("Failed to initialize system interrupt subsystem");
+    walk_device_tree(&device_tree, device_registry)
+        .expect("Failed to walk device tree and initialize devices");
 
-        // Now that the drivers are allocated and the interrupt controller is initialized, we can
-        // allow the device drivers to start talking to and initializing their devices.
-        // TODO: It's at this point we can switch the UART driver from polled mode to interrupt mode
-        //       and start accepting input from the UART console.
-        println!("Initializing attached devices...");
+    // Initialize the interrupt controller so that we can handle interrupts and exceptions in
+    // the kernel.
+    println!("Initializing interrupt controller...");
 
-        activate_devices()
-            .expect("Failed to connect devices to their drivers");
+    initialize_interrupts()
+        .expect("Failed to initialize system interrupt subsystem");
 
-        // At this point we can convert the printing subsystem to use the console device driver
-        // instead of talking directly to the UART. This enables us to support multiple console
-        // devices and have a more flexible logging system. For example it is at this point we can
-        // properly support dumping boot information to the systems attached display instead of
-        // just dumping to the serial port.
+    // Now that the drivers are allocated and the interrupt controller is initialized, we can
+    // allow the device drivers to start talking to and initializing their devices. This is where
+    // the serial ports switch over to being interrupt driven and start accepting input.
+    println!("Initializing attached devices...");
 
-        // TODO: Actually do this!
+    activate_devices()
+        .expect("Failed to connect devices to their drivers");
 
-        // Now that we have all the devices initialized, we can initialize the file systems and
-        // mount the root file system. We will need to find the boot volume and find the partition
-        // mapping so that we can map all partitions to where they need to go.
-        println!("Initializing and mounting file systems...");
+    // At this point we can convert the printing subsystem to use the console device driver
+    // instead of talking directly to the UART. This enables us to support multiple console
+    // devices and have a more flexible logging system. From here on the rest of the boot
+    // information is shown on the system's attached display, as well as the serial port.
+    switch_to_console_device()
+        .expect("Failed to switch printing over to the console");
 
-        initialize_filesystems()
-            .expect("Failed to initialize file systems and mount root file system");
+    // Seed the random number generator now that the entropy devices are up, before anything that
+    // needs unpredictable numbers, such as the network stack, gets going.
+    println!("Initializing random number generator...");
 
-        // At this point we can start process 0, the idle process. If there is no other process that
-        // can be run at any given time, the idle process will run. This is a simple process
-        // that just spins and does nothing. It is used to keep the CPU busy when there are no
-        // other processes to run. This is useful for power management. The CPU can safely run at a
-        // lower frequency and power state when it is running the idle process.
-        println!("Mounting filesystems...");
+    initialize_random();
 
-        println!("Mount Table\n{}", mount_table);
+    // Now that we have all the devices initialized, we can initialize the file systems and
+    // mount the root file system. We will need to find the boot volume and find the partition
+    // mapping so that we can map all partitions to where they need to go.
+    println!("Initializing and mounting file systems...");
 
-        // We have a root file system at this point, we can now look under /bin and find the init
-        // program and prepare it for execution.
+    let mount_table = &get_boot_info().mount_table;
 
-        // Let other harts know that the boot process is complete.
-        set_global_init_completed();
+    println!("Mount Table\n{}", mount_table);
+
+    initialize_filesystems(mount_table)
+        .expect("Failed to initialize file systems and mount root file system");
+
+    // Bring up the network stack on whatever network devices were found, and get the interfaces
+    // their addresses.
+    println!("Initializing network stack...");
+
+    initialize_network()
+        .expect("Failed to initialize the network stack");
+
+    // We've got far enough that the bootloader doesn't need to fall back to another kernel next
+    // time.
+    match mark_boot_successful()
+    {
+        Ok(true)   => println!("Marked boot entry {} as successful.",
+                               get_boot_info().boot_entry_str()),
+        Ok(false)  => {},
+        Err(error) => println!("Warning: Failed to mark the boot as successful: {}", error)
     }
 
+    // At this point we can start process 0, the idle process. If there is no other process that
+    // can be run at any given time, the idle process will run. This is a simple process
+    // that just spins and does nothing. It is used to keep the CPU busy when there are no
+    // other processes to run. This is useful for power management. The CPU can safely run at a
+    // lower frequency and power state when it is running the idle process.
+    //
+    // We have a root file system at this point, we can now look under /bin and find the init
+    // program and prepare it for execution.
+    println!("Init program: {}", kernel_options.init);
+
+    // The kernel is ready for the rest of the harts, bring up the ones the device tree lists.
+    println!("Starting secondary harts...");
+
+    start_secondary_harts();
+
     // Finally initialize the scheduler for this CPU core and start it running. The scheduler's run
     // method will never return.
     //
@@ -409,3 +464,33 @@ pub extern "C" fn main(core_index: usize,
 
     scheduler.run();
 }
+
+
+
+/// Where the secondary harts go once _secondary_start has given them a stack. The boot hart has
+/// already initialized the kernel, so all that's left is to switch to the kernel's address space
+/// and join the scheduler.
+#[unsafe(no_mangle)]
+pub extern "C" fn secondary_main(core_index: usize) -> !
+{
+    // Make sure that the core index matches the one supplied by the firmware.
+    assert!(core_index == get_core_index(),
+            "Firmware supplied Hart ID {:02} does not match current core index {:02}.",
+            core_index,
+            get_core_index());
+
+    convert_to_kernel_address_space();
+
+    // Get ready to take the calls the other harts send us before anyone knows we're here.
+    initialize_hart_interrupts();
+
+    // Let the world know we're running.
+    hart_online(core_index);
+
+    println!("Core {:02} is now running.", core_index);
+    println!("Starting scheduler for hart {:02}.", core_index);
+
+    let scheduler = Scheduler::new();
+
+    scheduler.run();
+}
@@ -52,6 +52,7 @@ pub struct KernelMemoryLayout
     pub bss: SectionLayout,         // The uninitialized data section of the kernel.
     pub stack: SectionLayout,       // The stacks for each hart in the system.
     pub heap: SectionLayout,        // Where the kernel's heap is located and its size.
+    pub firmware: SectionLayout,    // The RAM below the kernel that belongs to the SBI firmware.
     pub device_tree: SectionLayout  // The location and size of the device tree blob in memory.
 }
 
@@ -79,6 +80,8 @@ impl KernelMemoryLayout
             static _stack_end: u8;
             static _heap_start: u8;
             static _heap_end: u8;
+            static _firmware_start: u8;
+            static _firmware_end: u8;
         }
 
         let kernel_start = unsafe { &_kernel_start as *const u8 as usize };
@@ -96,6 +99,8 @@ impl KernelMemoryLayout
         let stack_end = unsafe { &_stack_end as *const u8 as usize };
         let heap_start = unsafe { &_heap_start as *const u8 as usize };
         let heap_end = unsafe { &_heap_end as *const u8 as usize };
+        let firmware_start = unsafe { &_firmware_start as *const u8 as usize };
+        let firmware_end = unsafe { &_firmware_end as *const u8 as usize };
 
         KernelMemoryLayout
             {
@@ -155,6 +160,14 @@ impl KernelMemoryLayout
                         size: heap_end - heap_start
                     },
 
+                firmware:
+                    SectionLayout
+                    {
+                        start: firmware_start,
+                        end: firmware_end,
+                        size: firmware_end - firmware_start
+                    },
+
                 device_tree:
                     SectionLayout
                     {
@@ -177,6 +190,7 @@ impl KernelMemoryLayout
                 bss: SectionLayout::zeroed(),
                 stack: SectionLayout::zeroed(),
                 heap: SectionLayout::zeroed(),
+                firmware: SectionLayout::zeroed(),
                 device_tree: SectionLayout::zeroed()
             }
     }
@@ -234,6 +248,12 @@ impl Display for KernelMemoryLayout
         writeln!(f)?;
 
         writeln!(f, "  External:")?;
+        write!(f, "    .firmware:       0x{:08x} - 0x{:08x}: ",
+                 self.firmware.start,
+                 self.firmware.end)?;
+        write_size!(f, self.firmware.size)?;
+        writeln!(f)?;
+
         write!(f, "    .device_tree:    0x{:08x} - 0x{:08x}: ",
                  self.device_tree.start,
                  self.device_tree.end)?;
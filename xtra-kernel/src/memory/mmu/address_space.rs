@@ -7,6 +7,8 @@
 // manage the pages of free memory in the system.
 
 use crate::{ arch::mmu::{ page_table::{ PageManagement, PageTable } },
+             boot_info::get_boot_info,
+             ipi::shootdown_tlb,
              locking::{ LockGuard, spin_lock::SpinLock },
              memory::{ mmu::{ allocate_page,
                               free_page,
@@ -182,6 +184,35 @@ impl AddressSpace
                                         .build(),
                   false);
 
+        // The device tree blob and the modules loaded by the bootloader are still read through
+        // their physical addresses once paging is enabled, so they are mapped read only at the same
+        // addresses.
+        let device_tree_start = kernel_memory.device_tree.start & !(PAGE_SIZE - 1);
+        let device_tree_end = (kernel_memory.device_tree.start + kernel_memory.device_tree.size)
+                              .next_multiple_of(PAGE_SIZE);
+
+        add_range(&mut address_space,
+                  device_tree_start,
+                  device_tree_end - device_tree_start,
+                  Permissions::builder().readable()
+                                        .globally_accessible()
+                                        .build(),
+                  false);
+
+        for module in get_boot_info().modules()
+        {
+            let module_start = module.range.base as usize & !(PAGE_SIZE - 1);
+            let module_end = (module.range.end() as usize).next_multiple_of(PAGE_SIZE);
+
+            add_range(&mut address_space,
+                      module_start,
+                      module_end - module_start,
+                      Permissions::builder().readable()
+                                            .globally_accessible()
+                                            .build(),
+                      false);
+        }
+
         // Map the kernel's virtual memory area. All physical pages of RAM will be mapped here so
         // that the kernel can access them directly.
         for device in get_system_memory_layout().memory_devices
@@ -270,6 +301,9 @@ impl AddressSpace
                 self.page_table.unmap_page(virtual_address)?
             };
 
+        // Make sure that no hart can still reach the page before it's reused.
+        shootdown_tlb(virtual_address);
+
         // Check if the page was owned by the page table.
         if let Some(page) = page
         {
@@ -308,9 +342,14 @@ impl AddressSpace
     {
         // Lock the address space to ensure that we don't have multiple threads trying to manage
         // pages at the same time.
-        let _guard = LockGuard::new(&self.lock);
+        let page =
+            {
+                let _guard = LockGuard::new(&self.lock);
+
+                self.page_table.unmap_page(virtual_address)?
+            };
 
-        let page = self.page_table.unmap_page(virtual_address)?;
+        shootdown_tlb(virtual_address);
 
         // If we didn't get an address back then the page was owned by the page table.
         assert!(page.is_some(),
@@ -15,10 +15,11 @@
 
 use core::mem::size_of;
 
-use crate::memory::{ PAGE_SIZE,
-                     kernel::KernelMemoryLayout,
-                     memory_device::SystemMemory,
-                     mmu::virtual_page_ptr::VirtualPagePtr };
+use crate::{ boot_info::get_boot_info,
+             memory::{ PAGE_SIZE,
+                       kernel::KernelMemoryLayout,
+                       memory_device::SystemMemory,
+                       mmu::virtual_page_ptr::VirtualPagePtr } };
 
 
 
@@ -725,7 +726,8 @@ pub fn init_free_page_list(kernel_memory: &KernelMemoryLayout,
                            system_memory: &SystemMemory)
 {
     /// Check if the address is within the kernel memory range, or part of the heap that will be
-    /// used by the kernel later.
+    /// used by the kernel later. The RAM owned by the SBI firmware is also off limits, it is
+    /// protected by PMP and any access to it from supervisor mode would fault.
     fn is_kernel_page(address: usize, kernel_memory: &KernelMemoryLayout) -> bool
     {
         (   address >= kernel_memory.kernel.start
@@ -733,6 +735,11 @@ pub fn init_free_page_list(kernel_memory: &KernelMemoryLayout,
 
         ||
 
+        (  address >= kernel_memory.firmware.start
+        && address <  kernel_memory.firmware.start + kernel_memory.firmware.size)
+
+        ||
+
         (  address >= kernel_memory.device_tree.start
         && address <  kernel_memory.device_tree.start + kernel_memory.device_tree.size)
     }
@@ -757,6 +764,13 @@ pub fn init_free_page_list(kernel_memory: &KernelMemoryLayout,
         false
     }
 
+    // Check if the address is holding a module loaded by the bootloader or is in memory that the
+    // bootloader has reserved.
+    fn is_boot_reserved_page(address: usize) -> bool
+    {
+        get_boot_info().is_reserved(address as u64)
+    }
+
     // Ok, lets iterate all the memory devices we've detected in the system and add their memory to
     // our free page list.
     for memory_device in &system_memory.memory_devices
@@ -792,6 +806,7 @@ pub fn init_free_page_list(kernel_memory: &KernelMemoryLayout,
             {
                 if    !is_kernel_page(page_address, kernel_memory)
                    && !is_mmio_page(page_address, system_memory)
+                   && !is_boot_reserved_page(page_address)
                 {
                     let simple_page_ptr = SimplePagePtr::try_from(page_address)
                         .expect("Failed to create SimplePagePtr from address.");
@@ -12,7 +12,8 @@
 
 use core::sync::atomic::{ AtomicBool, Ordering };
 
-use crate::{ arch::{ get_core_index, mmu::{ ADDRESSABLE_MEMORY_SIZE, HIGHEST_VIRTUAL_ADDRESS } },
+use crate::{ arch::mmu::{ ADDRESSABLE_MEMORY_SIZE, HIGHEST_VIRTUAL_ADDRESS },
+             harts::is_boot_hart,
              locking::{ LockGuard, spin_lock::SpinLock },
              memory::{ kernel::KernelMemoryLayout, memory_device::SystemMemory, PAGE_SIZE } };
 
@@ -56,8 +57,11 @@ pub mod page_box;
 
 use crate::memory::mmu::{ address_space::{ AddressSpace },
                           free_page_list::init_free_page_list,
-                          virtual_page_ptr::{ init_virtual_base_offset,
-                                              set_kernel_in_virtual_mode } };
+                          virtual_page_ptr::{ devirtualize_address,
+                                              init_virtual_base_offset,
+                                              is_kernel_in_virtual_mode,
+                                              set_kernel_in_virtual_mode,
+                                              VirtualPagePtr } };
 
 
 
@@ -188,8 +192,8 @@ pub fn convert_to_kernel_address_space()
         }
     }
 
-    // If we are on the first core then we need to signal that the kernel is now in virtual mode.
-    if get_core_index() == 0
+    // If we are on the boot hart then we need to signal that the kernel is now in virtual mode.
+    if is_boot_hart()
     {
         // Signal the swap to the virtual address space is complete.
         set_kernel_in_virtual_mode();
@@ -261,3 +265,23 @@ pub fn free_n_pages(contiguous_pages: ContiguousPages)
 
     free_page_list::add_n_free_pages(contiguous_pages);
 }
+
+
+
+/// Convert a kernel pointer into the physical address that a device would need to see in order to
+/// access the same memory, for example when handing buffers to a DMA capable device.
+///
+/// The kernel image, its stacks and the heap are all identity mapped so their addresses are already
+/// physical. Only pages reached through the kernel's virtual RAM window need to be translated.
+pub fn physical_address_of(address: usize) -> usize
+{
+    if    is_kernel_in_virtual_mode()
+       && VirtualPagePtr::<u8>::is_in_virtual_address_space(address)
+    {
+        devirtualize_address(address)
+    }
+    else
+    {
+        address
+    }
+}
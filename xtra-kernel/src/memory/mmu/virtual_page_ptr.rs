@@ -238,6 +238,14 @@ pub fn virtualize_address(address: usize) -> usize
 
 
 
+/// Convert an address in the kernel's virtual RAM window back into the physical address it maps.
+pub fn devirtualize_address(address: usize) -> usize
+{
+    address - virtual_base_offset()
+}
+
+
+
 /// A struct that maintains addresses for our pages of physical memory. These addresses can be
 /// either within the virtual address space or in the physical address space depending on the mode
 /// kernel is in.
@@ -0,0 +1,389 @@
+// The address resolution protocol, which finds the MAC address of a host on one of the attached
+// networks from its IPv4 address.
+//
+// The answers are kept in a small cache, and entries that aren't refreshed are forgotten after a
+// few minutes. A packet for a host that isn't in the cache yet is held, in one of a few buffers set
+// aside for it, while the host is asked for its address. It's sent when the answer comes back, or
+// dropped if none does.
+
+use alloc::{ vec, vec::Vec };
+
+use crate::{ devices::network_devices::{ ChecksumRequest, MacAddress, ETHERNET_HEADER_SIZE },
+             net::{ ethernet::{ ETHERTYPE_ARP, ETHERTYPE_IPV4 },
+                    ipv4::Ipv4Address,
+                    NetError,
+                    NetResult,
+                    NetworkStack },
+             timers::current_milliseconds };
+
+
+
+/// The most hosts remembered.
+const ARP_CACHE_SIZE: usize = 32;
+
+/// The most packets held waiting for an address.
+const ARP_HELD_PACKETS: usize = 8;
+
+/// How long an address is remembered for, in milliseconds.
+const ARP_ENTRY_LIFETIME_MS: u64 = 5 * 60 * 1000;
+
+/// How long to wait for an answer before asking again, in milliseconds.
+const ARP_RETRY_MS: u64 = 1000;
+
+/// How many times a host is asked before giving up on it.
+const ARP_MAX_REQUESTS: u32 = 3;
+
+// The ARP packet for Ethernet and IPv4.
+const ARP_PACKET_SIZE:       usize = 28;
+const ARP_HARDWARE_ETHERNET: u16   = 1;
+const ARP_REQUEST:           u16   = 1;
+const ARP_REPLY:             u16   = 2;
+
+
+
+/// Where an entry is in being resolved.
+#[derive(Clone, Copy)]
+enum ArpState
+{
+    /// The host's MAC address is known.
+    Resolved(MacAddress),
+
+    /// The host has been asked, and is being waited on.
+    Resolving { requests: u32, next_request: u64 }
+}
+
+
+
+/// A host in the cache.
+#[derive(Clone, Copy)]
+struct ArpEntry
+{
+    /// The interface the host is on, and its address.
+    interface: usize,
+    address: Ipv4Address,
+
+    state: ArpState,
+
+    /// When the entry was last updated.
+    updated: u64
+}
+
+
+
+/// A packet waiting for the MAC address of the host it's being sent to.
+struct HeldPacket
+{
+    /// The interface and host the packet is going to.
+    interface: usize,
+    next_hop: Ipv4Address,
+
+    /// The whole frame, its Ethernet header is filled in when it's sent. Empty when the buffer
+    /// isn't being used.
+    length: usize,
+    frame: Vec<u8>,
+
+    checksum: Option<ChecksumRequest>
+}
+
+
+
+/// The ARP cache, and the packets held waiting for it.
+pub struct ArpCache
+{
+    entries: [Option<ArpEntry>; ARP_CACHE_SIZE],
+    held: Vec<HeldPacket>
+}
+
+
+
+impl ArpCache
+{
+    /// Create an empty cache, with buffers for frames of the given size.
+    pub fn new(frame_size: usize) -> ArpCache
+    {
+        let held = (0..ARP_HELD_PACKETS)
+            .map(|_| HeldPacket
+                {
+                    interface: 0,
+                    next_hop: Ipv4Address::UNSPECIFIED,
+                    length: 0,
+                    frame: vec![0; frame_size],
+                    checksum: None
+                })
+            .collect();
+
+        ArpCache { entries: [None; ARP_CACHE_SIZE], held }
+    }
+
+    /// Find a host's entry.
+    fn find(&mut self, interface: usize, address: Ipv4Address) -> Option<&mut ArpEntry>
+    {
+        self.entries
+            .iter_mut()
+            .flatten()
+            .find(|entry| entry.interface == interface && entry.address == address)
+    }
+
+    /// Add an entry, pushing out the oldest one if the cache is full.
+    fn insert(&mut self, entry: ArpEntry)
+    {
+        let slot = match self.entries.iter().position(|entry| entry.is_none())
+            {
+                Some(slot) => slot,
+                None       => self.entries
+                                  .iter()
+                                  .enumerate()
+                                  .min_by_key(|(_, entry)| entry.map_or(0, |entry| entry.updated))
+                                  .map_or(0, |(slot, _)| slot)
+            };
+
+        self.entries[slot] = Some(entry);
+    }
+
+    /// Forget everything about the hosts on an interface, for when its address changes.
+    pub fn flush_interface(&mut self, interface: usize)
+    {
+        for entry in self.entries.iter_mut()
+        {
+            if entry.is_some_and(|entry| entry.interface == interface)
+            {
+                *entry = None;
+            }
+        }
+
+        for packet in self.held.iter_mut()
+        {
+            if packet.interface == interface
+            {
+                packet.length = 0;
+            }
+        }
+    }
+}
+
+
+
+impl NetworkStack
+{
+    /// Find the MAC address of a host, starting to ask for it if it isn't known.
+    pub fn resolve(&mut self, interface: usize, address: Ipv4Address) -> Option<MacAddress>
+    {
+        let now = current_milliseconds();
+
+        match self.arp.find(interface, address).map(|entry| entry.state)
+        {
+            Some(ArpState::Resolved(mac_address)) => return Some(mac_address),
+            Some(ArpState::Resolving { .. })      => return None,
+            None                                  => {}
+        }
+
+        self.arp.insert(ArpEntry
+            {
+                interface,
+                address,
+                state: ArpState::Resolving { requests: 1, next_request: now + ARP_RETRY_MS },
+                updated: now
+            });
+
+        let _ = self.send_arp(interface, ARP_REQUEST, MacAddress::BROADCAST, address);
+
+        None
+    }
+
+    /// Hold a frame until the MAC address of the host it's going to is known. If all of the
+    /// buffers are in use the frame is dropped, as a busy network would, and the protocols that
+    /// care send it again.
+    pub fn hold_for_resolution(&mut self,
+                               interface: usize,
+                               next_hop: Ipv4Address,
+                               frame: &[u8],
+                               checksum: Option<ChecksumRequest>) -> NetResult<()>
+    {
+        if let Some(packet) = self.arp.held.iter_mut().find(|packet| packet.length == 0)
+        {
+            if frame.len() > packet.frame.len()
+            {
+                return Err(NetError::MessageTooLong);
+            }
+
+            packet.interface = interface;
+            packet.next_hop = next_hop;
+            packet.length = frame.len();
+            packet.frame[..frame.len()].copy_from_slice(frame);
+            packet.checksum = checksum;
+        }
+
+        Ok(())
+    }
+
+    /// Handle a received ARP packet. We learn the address of whoever sent it, and answer if it
+    /// was asking for ours.
+    pub fn handle_arp(&mut self, interface: usize, packet: &[u8])
+    {
+        if    packet.len() < ARP_PACKET_SIZE
+           || u16::from_be_bytes([ packet[0], packet[1] ]) != ARP_HARDWARE_ETHERNET
+           || u16::from_be_bytes([ packet[2], packet[3] ]) != ETHERTYPE_IPV4
+           || packet[4] != 6
+           || packet[5] != 4
+        {
+            return;
+        }
+
+        let operation = u16::from_be_bytes([ packet[6], packet[7] ]);
+        let sender_mac = MacAddress([ packet[8], packet[9], packet[10],
+                                      packet[11], packet[12], packet[13] ]);
+        let sender = Ipv4Address::from_bytes(&packet[14..18]);
+        let target = Ipv4Address::from_bytes(&packet[24..28]);
+
+        if sender.is_unspecified()
+        {
+            return;
+        }
+
+        let now = current_milliseconds();
+        let for_us = self.interfaces[interface].address
+                                               .is_some_and(|address| address.address == target);
+
+        // Hosts we already know about are updated, and hosts talking to us are remembered.
+        match self.arp.find(interface, sender)
+        {
+            Some(entry) =>
+                {
+                    entry.state = ArpState::Resolved(sender_mac);
+                    entry.updated = now;
+                },
+
+            None if for_us =>
+                self.arp.insert(ArpEntry
+                    {
+                        interface,
+                        address: sender,
+                        state: ArpState::Resolved(sender_mac),
+                        updated: now
+                    }),
+
+            None => {}
+        }
+
+        self.send_held_packets(interface, sender, sender_mac);
+
+        if    for_us
+           && operation == ARP_REQUEST
+        {
+            let _ = self.send_arp(interface, ARP_REPLY, sender_mac, sender);
+        }
+    }
+
+    /// Send the packets that were waiting for a host's address.
+    fn send_held_packets(&mut self, interface: usize, address: Ipv4Address, mac_address: MacAddress)
+    {
+        for index in 0..self.arp.held.len()
+        {
+            let packet = &self.arp.held[index];
+
+            if    packet.length == 0
+               || packet.interface != interface
+               || packet.next_hop != address
+            {
+                continue;
+            }
+
+            let mut frame = core::mem::take(&mut self.arp.held[index].frame);
+            let length = self.arp.held[index].length;
+            let checksum = self.arp.held[index].checksum;
+            let ether_type = u16::from_be_bytes([ frame[12], frame[13] ]);
+
+            let _ = self.send_frame(interface,
+                                    mac_address,
+                                    ether_type,
+                                    &mut frame[..length],
+                                    checksum);
+
+            self.arp.held[index].frame = frame;
+            self.arp.held[index].length = 0;
+        }
+    }
+
+    /// Send an ARP request or reply from an interface.
+    fn send_arp(&self,
+                interface: usize,
+                operation: u16,
+                target_mac: MacAddress,
+                target: Ipv4Address) -> NetResult<()>
+    {
+        let sender = self.interfaces[interface].address.ok_or(NetError::NetworkUnreachable)?;
+        let sender_mac = self.interfaces[interface].mac_address;
+        let mut frame = [ 0; ETHERNET_HEADER_SIZE + ARP_PACKET_SIZE ];
+        let packet = &mut frame[ETHERNET_HEADER_SIZE..];
+
+        packet[0..2].copy_from_slice(&ARP_HARDWARE_ETHERNET.to_be_bytes());
+        packet[2..4].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        packet[4] = 6;
+        packet[5] = 4;
+        packet[6..8].copy_from_slice(&operation.to_be_bytes());
+        packet[8..14].copy_from_slice(&sender_mac.0);
+        packet[14..18].copy_from_slice(&sender.address.0);
+
+        // A request leaves the target's MAC address zeroed, that's what it's asking for.
+        if operation == ARP_REPLY
+        {
+            packet[18..24].copy_from_slice(&target_mac.0);
+        }
+
+        packet[24..28].copy_from_slice(&target.0);
+
+        self.send_frame(interface, target_mac, ETHERTYPE_ARP, &mut frame, None)
+    }
+
+    /// Ask again for the addresses that haven't been answered, give up on the hosts that never
+    /// answer, and forget the entries that have grown old.
+    pub fn run_arp_timers(&mut self, now: u64)
+    {
+        for slot in 0..ARP_CACHE_SIZE
+        {
+            let Some(entry) = self.arp.entries[slot] else { continue };
+
+            match entry.state
+            {
+                ArpState::Resolved(_) if now >= entry.updated + ARP_ENTRY_LIFETIME_MS =>
+                    self.arp.entries[slot] = None,
+
+                ArpState::Resolving { requests, next_request } if now >= next_request =>
+                    {
+                        if requests >= ARP_MAX_REQUESTS
+                        {
+                            self.arp.entries[slot] = None;
+
+                            for packet in self.arp.held.iter_mut()
+                            {
+                                if    packet.interface == entry.interface
+                                   && packet.next_hop == entry.address
+                                {
+                                    packet.length = 0;
+                                }
+                            }
+
+                            continue;
+                        }
+
+                        self.arp.entries[slot] = Some(ArpEntry
+                            {
+                                state: ArpState::Resolving
+                                    {
+                                        requests: requests + 1,
+                                        next_request: now + ARP_RETRY_MS
+                                    },
+                                ..entry
+                            });
+
+                        let _ = self.send_arp(entry.interface,
+                                              ARP_REQUEST,
+                                              MacAddress::BROADCAST,
+                                              entry.address);
+                    },
+
+                _ => {}
+            }
+        }
+    }
+}
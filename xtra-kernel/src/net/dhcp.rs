@@ -0,0 +1,558 @@
+// The DHCP client, RFC 2131, that gets the interfaces their addresses. It broadcasts a DISCOVER,
+// takes the first OFFER that comes back and asks for it with a REQUEST, and once the server's ACK
+// arrives the interface is configured with the address, netmask and router from the lease. The
+// lease is renewed from the server that gave it halfway through, and from any server once seven
+// eighths of it have gone. If it runs out the interface loses its address and starts over.
+//
+// The client runs entirely from the stack's timer and the datagrams sent to port 68, nothing
+// waits for the server to answer.
+
+use crate::{ devices::network_devices::MacAddress,
+             net::{ ipv4::{ Ipv4Address, Ipv4Subnet },
+                    socket::SocketAddress,
+                    NetworkStack,
+                    MAX_DNS_SERVERS },
+             random::get_random_u32,
+             timers::current_milliseconds };
+
+
+
+/// The port DHCP servers listen on.
+pub const DHCP_SERVER_PORT: u16 = 67;
+
+/// The port DHCP clients listen on.
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+// The BOOTP operations, and the hardware type of Ethernet.
+const BOOT_REQUEST:       u8 = 1;
+const BOOT_REPLY:         u8 = 2;
+const HARDWARE_ETHERNET:  u8 = 1;
+
+/// The value that marks the start of the options.
+const MAGIC_COOKIE: [u8; 4] = [ 99, 130, 83, 99 ];
+
+/// The size of the fixed part of a message, before the magic cookie and options.
+const FIXED_PART_SIZE: usize = 236;
+
+/// The size of the messages we send, the smallest a BOOTP relay has to handle.
+const MESSAGE_SIZE: usize = 300;
+
+/// Asks the server to broadcast its replies, as we can't receive unicasts before we have an
+/// address.
+const FLAG_BROADCAST: u16 = 0x8000;
+
+// The message types.
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER:    u8 = 2;
+const DHCP_REQUEST:  u8 = 3;
+const DHCP_ACK:      u8 = 5;
+const DHCP_NAK:      u8 = 6;
+
+// The options we send or look at.
+const OPTION_PAD:            u8 = 0;
+const OPTION_SUBNET_MASK:    u8 = 1;
+const OPTION_ROUTER:         u8 = 3;
+const OPTION_DNS_SERVERS:    u8 = 6;
+const OPTION_HOST_NAME:      u8 = 12;
+const OPTION_REQUESTED_IP:   u8 = 50;
+const OPTION_LEASE_TIME:     u8 = 51;
+const OPTION_MESSAGE_TYPE:   u8 = 53;
+const OPTION_SERVER_ID:      u8 = 54;
+const OPTION_PARAMETER_LIST: u8 = 55;
+const OPTION_RENEWAL_TIME:   u8 = 58;
+const OPTION_REBINDING_TIME: u8 = 59;
+const OPTION_END:            u8 = 255;
+
+/// The host name we give the server.
+const HOST_NAME: &[u8] = b"xtra";
+
+// How long to wait for an answer before asking again, doubling each time, in milliseconds.
+const INITIAL_RETRY_MS: u64 = 2000;
+const MAX_RETRY_MS:     u64 = 32_000;
+
+/// How many REQUESTs are sent for an offer before starting over with a DISCOVER.
+const MAX_REQUESTS: u32 = 4;
+
+/// The lease assumed when the server doesn't say how long it is, in seconds.
+const DEFAULT_LEASE_SECONDS: u32 = 24 * 60 * 60;
+
+/// A lease time that means the lease never runs out.
+const INFINITE_LEASE: u32 = u32::MAX;
+
+
+
+/// Where the client is in getting or keeping a lease.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DhcpState
+{
+    /// Broadcasting DISCOVERs, waiting for an offer.
+    Selecting,
+
+    /// Asking for the offered address, waiting for the ACK.
+    Requesting,
+
+    /// Using the address.
+    Bound,
+
+    /// Asking the server that gave the lease to renew it.
+    Renewing,
+
+    /// Asking any server to renew the lease.
+    Rebinding
+}
+
+
+
+/// What a server gave us in an ACK.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct DhcpLease
+{
+    subnet: Ipv4Subnet,
+    router: Option<Ipv4Address>,
+    dns_servers: [Option<Ipv4Address>; MAX_DNS_SERVERS],
+    server: Option<Ipv4Address>,
+
+    /// How long the lease lasts, and when to start renewing and rebinding it, in seconds.
+    lease_seconds: u32,
+    renewal_seconds: u32,
+    rebinding_seconds: u32
+}
+
+
+
+/// The DHCP client of an interface.
+#[derive(Clone, Copy, Debug)]
+pub struct DhcpClient
+{
+    state: DhcpState,
+
+    /// The transaction the replies we're waiting for belong to.
+    transaction_id: u32,
+
+    /// The address offered to us, and the server that offered it or gave us the lease.
+    offered: Ipv4Address,
+    server: Option<Ipv4Address>,
+
+    /// The address we have, once bound.
+    address: Option<Ipv4Address>,
+
+    /// When the next message is sent, how long to wait for an answer to it and how many have
+    /// been sent for the current offer.
+    next_send: u64,
+    retry_interval: u64,
+    requests: u32,
+
+    /// When the lease is renewed, rebound and runs out, in milliseconds since the system started.
+    renew_at: u64,
+    rebind_at: u64,
+    expires_at: u64
+}
+
+
+
+impl DhcpClient
+{
+    /// Start a new client, about to send its first DISCOVER.
+    fn new(now: u64) -> DhcpClient
+    {
+        DhcpClient
+            {
+                state: DhcpState::Selecting,
+                transaction_id: get_random_u32(),
+                offered: Ipv4Address::UNSPECIFIED,
+                server: None,
+                address: None,
+                next_send: now,
+                retry_interval: INITIAL_RETRY_MS,
+                requests: 0,
+                renew_at: u64::MAX,
+                rebind_at: u64::MAX,
+                expires_at: u64::MAX
+            }
+    }
+
+    /// Wait for an answer to the message just sent, a little longer each time.
+    fn sent(&mut self, now: u64)
+    {
+        self.next_send = now + self.retry_interval;
+        self.retry_interval = (self.retry_interval * 2).min(MAX_RETRY_MS);
+        self.requests += 1;
+    }
+
+    /// Move to a new state, sending its first message right away.
+    fn enter(&mut self, state: DhcpState, now: u64)
+    {
+        self.state = state;
+        self.next_send = now;
+        self.retry_interval = INITIAL_RETRY_MS;
+        self.requests = 0;
+    }
+}
+
+
+
+/// Find an option in a message's options.
+fn find_option(options: &[u8], code: u8) -> Option<&[u8]>
+{
+    let mut offset = 0;
+
+    while offset < options.len()
+    {
+        match options[offset]
+        {
+            OPTION_PAD => offset += 1,
+            OPTION_END => break,
+
+            kind =>
+                {
+                    let size = *options.get(offset + 1)? as usize;
+                    let value = options.get(offset + 2..offset + 2 + size)?;
+
+                    if kind == code
+                    {
+                        return Some(value);
+                    }
+
+                    offset += 2 + size;
+                }
+        }
+    }
+
+    None
+}
+
+
+/// Read an option that holds an address.
+fn address_option(options: &[u8], code: u8) -> Option<Ipv4Address>
+{
+    find_option(options, code).filter(|value| value.len() >= 4)
+                              .map(|value| Ipv4Address::from_bytes(&value[..4]))
+}
+
+
+/// Read an option that holds a time in seconds.
+fn seconds_option(options: &[u8], code: u8) -> Option<u32>
+{
+    find_option(options, code).filter(|value| value.len() == 4)
+                              .map(|value| u32::from_be_bytes([ value[0], value[1],
+                                                                value[2], value[3] ]))
+}
+
+
+/// Work out the lease from the options of an ACK, for the address it gives us.
+fn parse_lease(address: Ipv4Address, options: &[u8]) -> DhcpLease
+{
+    // Without a netmask the address is taken to be on a /24, the most likely guess.
+    let subnet = address_option(options, OPTION_SUBNET_MASK)
+        .and_then(|netmask| Ipv4Subnet::from_netmask(address, netmask))
+        .unwrap_or(Ipv4Subnet { address, prefix_length: 24 });
+
+    let mut dns_servers = [None; MAX_DNS_SERVERS];
+
+    if let Some(value) = find_option(options, OPTION_DNS_SERVERS)
+    {
+        for (server, bytes) in dns_servers.iter_mut().zip(value.chunks_exact(4))
+        {
+            *server = Some(Ipv4Address::from_bytes(bytes));
+        }
+    }
+
+    let lease_seconds = seconds_option(options, OPTION_LEASE_TIME).unwrap_or(DEFAULT_LEASE_SECONDS);
+    let renewal_seconds = seconds_option(options, OPTION_RENEWAL_TIME)
+        .unwrap_or(lease_seconds / 2)
+        .min(lease_seconds);
+    let rebinding_seconds = seconds_option(options, OPTION_REBINDING_TIME)
+        .unwrap_or(lease_seconds / 8 * 7)
+        .clamp(renewal_seconds, lease_seconds);
+
+    DhcpLease
+        {
+            subnet,
+            router: address_option(options, OPTION_ROUTER),
+            dns_servers,
+            server: address_option(options, OPTION_SERVER_ID),
+            lease_seconds,
+            renewal_seconds,
+            rebinding_seconds
+        }
+}
+
+
+/// The time a number of seconds into a lease, a lease that never runs out is never renewed.
+fn lease_deadline(now: u64, lease_seconds: u32, seconds: u32) -> u64
+{
+    match lease_seconds
+    {
+        INFINITE_LEASE => u64::MAX,
+        _              => now + seconds as u64 * 1000
+    }
+}
+
+
+
+/// A DHCP message being built.
+struct DhcpMessage
+{
+    buffer: [u8; MESSAGE_SIZE],
+    size: usize
+}
+
+
+
+impl DhcpMessage
+{
+    /// Start a request from the client, with the fixed part filled in.
+    fn new(client: &DhcpClient,
+           mac_address: MacAddress,
+           message_type: u8,
+           client_address: Option<Ipv4Address>) -> DhcpMessage
+    {
+        let mut buffer = [ 0; MESSAGE_SIZE ];
+
+        buffer[0] = BOOT_REQUEST;
+        buffer[1] = HARDWARE_ETHERNET;
+        buffer[2] = mac_address.0.len() as u8;
+        buffer[4..8].copy_from_slice(&client.transaction_id.to_be_bytes());
+
+        match client_address
+        {
+            Some(address) => buffer[12..16].copy_from_slice(&address.0),
+            None          => buffer[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes())
+        }
+
+        buffer[28..34].copy_from_slice(&mac_address.0);
+        buffer[FIXED_PART_SIZE..FIXED_PART_SIZE + 4].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut message = DhcpMessage { buffer, size: FIXED_PART_SIZE + 4 };
+
+        message.add_option(OPTION_MESSAGE_TYPE, &[ message_type ]);
+        message.add_option(OPTION_HOST_NAME, HOST_NAME);
+        message.add_option(OPTION_PARAMETER_LIST, &[ OPTION_SUBNET_MASK,
+                                                     OPTION_ROUTER,
+                                                     OPTION_DNS_SERVERS,
+                                                     OPTION_LEASE_TIME,
+                                                     OPTION_RENEWAL_TIME,
+                                                     OPTION_REBINDING_TIME ]);
+
+        message
+    }
+
+    /// Add an option to the message.
+    fn add_option(&mut self, code: u8, value: &[u8])
+    {
+        let end = self.size + 2 + value.len();
+
+        self.buffer[self.size] = code;
+        self.buffer[self.size + 1] = value.len() as u8;
+        self.buffer[self.size + 2..end].copy_from_slice(value);
+        self.size = end;
+    }
+
+    /// End the options and get the message to send. It's padded out to the full message size.
+    fn finish(&mut self) -> &[u8]
+    {
+        self.buffer[self.size] = OPTION_END;
+
+        &self.buffer
+    }
+}
+
+
+
+impl NetworkStack
+{
+    /// Start DHCP on an interface.
+    pub fn start_dhcp(&mut self, interface: usize, now: u64)
+    {
+        let client = DhcpClient::new(now);
+
+        self.interfaces[interface].dhcp = Some(client);
+        self.send_dhcp_message(interface, now);
+    }
+
+    /// Send the message the DHCP client's state calls for, if it's due.
+    fn send_dhcp_message(&mut self, interface: usize, now: u64)
+    {
+        let Some(mut client) = self.interfaces[interface].dhcp else { return };
+
+        if now < client.next_send
+        {
+            return;
+        }
+
+        let mac_address = self.interfaces[interface].mac_address;
+
+        let mut message = match client.state
+            {
+                DhcpState::Selecting => DhcpMessage::new(&client, mac_address, DHCP_DISCOVER, None),
+
+                DhcpState::Requesting =>
+                    {
+                        let mut message = DhcpMessage::new(&client,
+                                                           mac_address,
+                                                           DHCP_REQUEST,
+                                                           None);
+
+                        message.add_option(OPTION_REQUESTED_IP, &client.offered.0);
+
+                        if let Some(server) = client.server
+                        {
+                            message.add_option(OPTION_SERVER_ID, &server.0);
+                        }
+
+                        message
+                    },
+
+                DhcpState::Renewing | DhcpState::Rebinding =>
+                    DhcpMessage::new(&client, mac_address, DHCP_REQUEST, client.address),
+
+                DhcpState::Bound => return
+            };
+
+        let source = SocketAddress::new(client.address.unwrap_or(Ipv4Address::UNSPECIFIED),
+                                        DHCP_CLIENT_PORT);
+
+        // Renewals go to the server that gave the lease, everything else is broadcast. A failure
+        // to send is the same as a lost message, it's sent again later.
+        let _ = match (client.state, client.server)
+            {
+                (DhcpState::Renewing, Some(server)) =>
+                    self.send_udp(source,
+                                  SocketAddress::new(server, DHCP_SERVER_PORT),
+                                  message.finish()),
+
+                _ => self.transmit_udp(interface,
+                                       source,
+                                       SocketAddress::new(Ipv4Address::BROADCAST,
+                                                          DHCP_SERVER_PORT),
+                                       Ipv4Address::BROADCAST,
+                                       message.finish())
+            };
+
+        client.sent(now);
+
+        self.interfaces[interface].dhcp = Some(client);
+    }
+
+    /// Run the DHCP clients' timers, sending again whatever wasn't answered and renewing the
+    /// leases that are due.
+    pub fn run_dhcp_timers(&mut self, now: u64)
+    {
+        for interface in 0..self.interfaces.len()
+        {
+            let Some(mut client) = self.interfaces[interface].dhcp else { continue };
+
+            match client.state
+            {
+                DhcpState::Requesting if    now >= client.next_send
+                                         && client.requests >= MAX_REQUESTS =>
+                    {
+                        client = DhcpClient::new(now);
+                    },
+
+                DhcpState::Bound if now >= client.renew_at =>
+                    client.enter(DhcpState::Renewing, now),
+
+                DhcpState::Renewing if now >= client.rebind_at =>
+                    client.enter(DhcpState::Rebinding, now),
+
+                DhcpState::Rebinding if now >= client.expires_at =>
+                    {
+                        // The lease has run out, the address can't be used any more.
+                        let _ = self.configure_interface(interface, None, None);
+
+                        client = DhcpClient::new(now);
+                    },
+
+                _ => {}
+            }
+
+            self.interfaces[interface].dhcp = Some(client);
+            self.send_dhcp_message(interface, now);
+        }
+    }
+
+    /// Handle a message sent to the DHCP client of an interface.
+    pub fn handle_dhcp(&mut self, interface: usize, data: &[u8])
+    {
+        let Some(mut client) = self.interfaces[interface].dhcp else { return };
+        let mac_address = self.interfaces[interface].mac_address;
+
+        if    data.len() < FIXED_PART_SIZE + 4
+           || data[0] != BOOT_REPLY
+           || data[4..8] != client.transaction_id.to_be_bytes()
+           || data[28..34] != mac_address.0
+           || data[FIXED_PART_SIZE..FIXED_PART_SIZE + 4] != MAGIC_COOKIE
+        {
+            return;
+        }
+
+        let your_address = Ipv4Address::from_bytes(&data[16..20]);
+        let options = &data[FIXED_PART_SIZE + 4..];
+        let now = current_milliseconds();
+
+        let Some(&[ message_type ]) = find_option(options, OPTION_MESSAGE_TYPE) else { return };
+
+        match (message_type, client.state)
+        {
+            (DHCP_OFFER, DhcpState::Selecting) if !your_address.is_unspecified() =>
+                {
+                    client.offered = your_address;
+                    client.server = address_option(options, OPTION_SERVER_ID);
+                    client.enter(DhcpState::Requesting, now);
+                },
+
+            (DHCP_ACK, DhcpState::Requesting | DhcpState::Renewing | DhcpState::Rebinding)
+                if !your_address.is_unspecified() =>
+                {
+                    let lease = parse_lease(your_address, options);
+                    let interface_state = &self.interfaces[interface];
+
+                    // Renewing the same lease leaves the interface alone, so that its ARP cache
+                    // and routes aren't lost.
+                    let changed =    interface_state.address != Some(lease.subnet)
+                                  || interface_state.gateway != lease.router;
+
+                    if    changed
+                       && self.configure_interface(interface, Some(lease.subnet), lease.router)
+                              .is_err()
+                    {
+                        return;
+                    }
+
+                    if lease.dns_servers.iter().any(Option::is_some)
+                    {
+                        self.dns_servers = lease.dns_servers;
+                    }
+
+                    client.address = Some(your_address);
+                    client.server = lease.server.or(client.server);
+                    client.enter(DhcpState::Bound, now);
+                    client.renew_at = lease_deadline(now,
+                                                     lease.lease_seconds,
+                                                     lease.renewal_seconds);
+                    client.rebind_at = lease_deadline(now,
+                                                      lease.lease_seconds,
+                                                      lease.rebinding_seconds);
+                    client.expires_at = lease_deadline(now,
+                                                       lease.lease_seconds,
+                                                       lease.lease_seconds);
+                },
+
+            (DHCP_NAK, DhcpState::Requesting | DhcpState::Renewing | DhcpState::Rebinding) =>
+                {
+                    if client.address.is_some()
+                    {
+                        let _ = self.configure_interface(interface, None, None);
+                    }
+
+                    client = DhcpClient::new(now);
+                },
+
+            _ => return
+        }
+
+        self.interfaces[interface].dhcp = Some(client);
+        self.send_dhcp_message(interface, now);
+    }
+}
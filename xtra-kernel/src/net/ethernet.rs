@@ -0,0 +1,101 @@
+// Ethernet framing. Received frames are checked to be for us and handed to ARP or IPv4 by their
+// EtherType, and outgoing frames are given their header once ARP has found where they go.
+
+use crate::{ devices::network_devices::{ ChecksumRequest, MacAddress, ETHERNET_HEADER_SIZE },
+             net::{ ipv4::Ipv4Address, NetResult, NetworkStack } };
+
+
+
+// The EtherTypes of the protocols we speak.
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP:  u16 = 0x0806;
+
+
+
+/// Write an Ethernet header to the start of a frame.
+pub fn write_ethernet_header(frame: &mut [u8],
+                             destination: MacAddress,
+                             source: MacAddress,
+                             ether_type: u16)
+{
+    frame[0..6].copy_from_slice(&destination.0);
+    frame[6..12].copy_from_slice(&source.0);
+    frame[12..14].copy_from_slice(&ether_type.to_be_bytes());
+}
+
+
+
+impl NetworkStack
+{
+    /// Handle a frame received on one of the interfaces.
+    pub fn handle_frame(&mut self, interface: usize, frame: &[u8], checksum_valid: bool)
+    {
+        if frame.len() < ETHERNET_HEADER_SIZE
+        {
+            return;
+        }
+
+        let destination = MacAddress([ frame[0], frame[1], frame[2],
+                                       frame[3], frame[4], frame[5] ]);
+
+        if    destination != self.interfaces[interface].mac_address
+           && !destination.is_multicast()
+        {
+            return;
+        }
+
+        let payload = &frame[ETHERNET_HEADER_SIZE..];
+
+        match u16::from_be_bytes([ frame[12], frame[13] ])
+        {
+            ETHERTYPE_ARP  => self.handle_arp(interface, payload),
+            ETHERTYPE_IPV4 => self.handle_ipv4(interface, payload, checksum_valid),
+            _              => {}
+        }
+    }
+
+    /// Send a frame to a host on one of the attached networks. The frame's Ethernet header is
+    /// filled in here, once ARP has found the host's MAC address. If it has to be asked for the
+    /// frame is held until the answer comes back.
+    pub fn transmit_frame(&mut self,
+                          interface: usize,
+                          next_hop: Ipv4Address,
+                          ether_type: u16,
+                          frame: &mut [u8],
+                          checksum: Option<ChecksumRequest>) -> NetResult<()>
+    {
+        let is_broadcast = next_hop.is_broadcast()
+                           || self.interfaces[interface].address
+                                                        .is_some_and(|address| {
+                                                            address.broadcast() == next_hop
+                                                        });
+
+        let destination = match is_broadcast
+            {
+                true  => MacAddress::BROADCAST,
+                false => match self.resolve(interface, next_hop)
+                    {
+                        Some(destination) => destination,
+                        None              =>
+                            return self.hold_for_resolution(interface, next_hop, frame, checksum)
+                    }
+            };
+
+        self.send_frame(interface, destination, ether_type, frame, checksum)
+    }
+
+    /// Send a frame to a known MAC address.
+    pub fn send_frame(&self,
+                      interface: usize,
+                      destination: MacAddress,
+                      ether_type: u16,
+                      frame: &mut [u8],
+                      checksum: Option<ChecksumRequest>) -> NetResult<()>
+    {
+        let interface = &self.interfaces[interface];
+
+        write_ethernet_header(frame, destination, interface.mac_address, ether_type);
+
+        Ok(interface.device.send_frame(frame, checksum)?)
+    }
+}
@@ -0,0 +1,239 @@
+// ICMP, the control messages of IPv4. We answer echo requests, so that the system can be pinged,
+// send echo requests of our own and keep the replies for whoever is pinging, and tell the senders
+// of UDP datagrams to ports nobody is listening on that they went nowhere.
+
+use crate::{ devices::network_devices::ones_complement_sum,
+             net::{ ipv4::{ add_checksums, Ipv4Address, Ipv4Packet, IP_PROTOCOL_ICMP },
+                    with_network_stack,
+                    NetError,
+                    NetResult,
+                    NetworkStack },
+             timers::current_milliseconds };
+
+
+
+// The message types.
+const ICMP_ECHO_REPLY:              u8 = 0;
+const ICMP_DESTINATION_UNREACHABLE: u8 = 3;
+const ICMP_ECHO_REQUEST:            u8 = 8;
+
+/// The code of a destination unreachable message saying nothing was listening on the port.
+pub const ICMP_PORT_UNREACHABLE: u8 = 3;
+
+/// The size of the header of an echo or destination unreachable message.
+const ICMP_HEADER_SIZE: usize = 8;
+
+/// How much of the packet that couldn't be delivered is quoted back, beyond its IPv4 header.
+const QUOTED_PAYLOAD_SIZE: usize = 8;
+
+/// The largest payload that can be sent in an echo request.
+pub const MAX_ECHO_PAYLOAD: usize = 1024;
+
+/// How many echo replies are kept for the pings waiting on them.
+const ECHO_REPLY_SLOTS: usize = 8;
+
+
+
+/// A reply to one of our echo requests.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EchoReply
+{
+    /// The host that replied.
+    pub source: Ipv4Address,
+
+    /// The identifier and sequence number from the request.
+    pub identifier: u16,
+    pub sequence: u16,
+
+    /// The size of the reply's payload.
+    pub size: usize,
+
+    /// When the reply came in, in milliseconds since the system started.
+    pub received_at: u64
+}
+
+
+
+/// The most recent echo replies, the oldest is replaced when a new one comes in.
+pub struct EchoReplies
+{
+    replies: [Option<EchoReply>; ECHO_REPLY_SLOTS],
+    next: usize
+}
+
+
+
+impl EchoReplies
+{
+    /// Create an empty set of replies.
+    pub fn new() -> EchoReplies
+    {
+        EchoReplies { replies: [None; ECHO_REPLY_SLOTS], next: 0 }
+    }
+
+    /// Keep a reply.
+    fn insert(&mut self, reply: EchoReply)
+    {
+        self.replies[self.next] = Some(reply);
+        self.next = (self.next + 1) % ECHO_REPLY_SLOTS;
+    }
+
+    /// Take the oldest reply with the identifier.
+    fn take(&mut self, identifier: u16) -> Option<EchoReply>
+    {
+        let slot = self.replies
+                       .iter()
+                       .enumerate()
+                       .filter(|(_, reply)| {
+                           reply.is_some_and(|reply| reply.identifier == identifier)
+                       })
+                       .min_by_key(|(_, reply)| reply.map_or(0, |reply| reply.received_at))
+                       .map(|(slot, _)| slot)?;
+
+        self.replies[slot].take()
+    }
+}
+
+
+
+/// Fill in the checksum of an ICMP message, which covers the whole message.
+fn fill_in_icmp_checksum(header: &mut [u8], payload: &[u8])
+{
+    header[2..4].fill(0);
+
+    let checksum = !add_checksums(ones_complement_sum(header), ones_complement_sum(payload));
+
+    header[2..4].copy_from_slice(&checksum.to_be_bytes());
+}
+
+
+
+impl NetworkStack
+{
+    /// Handle a received ICMP message.
+    pub fn handle_icmp(&mut self, packet: &Ipv4Packet)
+    {
+        let message = packet.payload;
+
+        if    message.len() < ICMP_HEADER_SIZE
+           || ones_complement_sum(message) != 0xffff
+        {
+            return;
+        }
+
+        let identifier = u16::from_be_bytes([ message[4], message[5] ]);
+        let sequence = u16::from_be_bytes([ message[6], message[7] ]);
+        let payload = &message[ICMP_HEADER_SIZE..];
+
+        match message[0]
+        {
+            // Pings to a broadcast address aren't answered, so that we can't be used to flood
+            // someone with replies.
+            ICMP_ECHO_REQUEST if !packet.broadcast =>
+                {
+                    let mut header = [ 0; ICMP_HEADER_SIZE ];
+
+                    header[0] = ICMP_ECHO_REPLY;
+                    header[4..8].copy_from_slice(&message[4..8]);
+
+                    fill_in_icmp_checksum(&mut header, payload);
+
+                    let _ = self.send_ipv4(Some(packet.destination),
+                                           packet.source,
+                                           IP_PROTOCOL_ICMP,
+                                           &[ &header, payload ],
+                                           None);
+                },
+
+            ICMP_ECHO_REPLY =>
+                self.echo_replies.insert(EchoReply
+                    {
+                        source: packet.source,
+                        identifier,
+                        sequence,
+                        size: payload.len(),
+                        received_at: current_milliseconds()
+                    }),
+
+            _ => {}
+        }
+    }
+
+    /// Tell the sender of a packet that it couldn't be delivered, quoting the start of the packet
+    /// back to them.
+    pub fn send_destination_unreachable(&mut self, packet: &Ipv4Packet, code: u8)
+    {
+        // Errors are never sent about broadcasts, or about packets from nowhere.
+        if    packet.broadcast
+           || packet.source.is_unspecified()
+           || packet.source.is_broadcast()
+        {
+            return;
+        }
+
+        let mut header = [ 0; ICMP_HEADER_SIZE ];
+        let quoted = &packet.payload[..packet.payload.len().min(QUOTED_PAYLOAD_SIZE)];
+
+        header[0] = ICMP_DESTINATION_UNREACHABLE;
+        header[1] = code;
+
+        // The IPv4 header is always a whole number of words, so the quoted parts can be summed
+        // separately.
+        let quoted_sum = add_checksums(ones_complement_sum(packet.header),
+                                       ones_complement_sum(quoted));
+        let checksum = !add_checksums(ones_complement_sum(&header), quoted_sum);
+
+        header[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        let _ = self.send_ipv4(Some(packet.destination),
+                               packet.source,
+                               IP_PROTOCOL_ICMP,
+                               &[ &header, packet.header, quoted ],
+                               None);
+    }
+
+    /// Send an echo request with a payload of the given size.
+    pub fn send_echo_request(&mut self,
+                             destination: Ipv4Address,
+                             identifier: u16,
+                             sequence: u16,
+                             size: usize) -> NetResult<()>
+    {
+        if size > MAX_ECHO_PAYLOAD
+        {
+            return Err(NetError::MessageTooLong);
+        }
+
+        let mut header = [ 0; ICMP_HEADER_SIZE ];
+        let mut payload = [ 0; MAX_ECHO_PAYLOAD ];
+
+        for (index, byte) in payload.iter_mut().enumerate()
+        {
+            *byte = index as u8;
+        }
+
+        header[0] = ICMP_ECHO_REQUEST;
+        header[4..6].copy_from_slice(&identifier.to_be_bytes());
+        header[6..8].copy_from_slice(&sequence.to_be_bytes());
+
+        fill_in_icmp_checksum(&mut header, &payload[..size]);
+
+        self.send_ipv4(None, destination, IP_PROTOCOL_ICMP, &[ &header, &payload[..size] ], None)
+    }
+}
+
+
+
+/// Send an echo request, a ping, to a host. The replies are picked up with `take_echo_reply`, by
+/// the same identifier.
+pub fn ping(destination: Ipv4Address, identifier: u16, sequence: u16, size: usize) -> NetResult<()>
+{
+    with_network_stack(|stack| stack.send_echo_request(destination, identifier, sequence, size))
+}
+
+
+/// Take the oldest echo reply that has come in for the pings with the identifier.
+pub fn take_echo_reply(identifier: u16) -> Option<EchoReply>
+{
+    with_network_stack(|stack| Ok(stack.echo_replies.take(identifier))).ok().flatten()
+}
@@ -0,0 +1,223 @@
+// The network interfaces, each network device as the stack sees it. An interface has the address
+// it's been given, by DHCP or the ip= kernel parameter, and the routes for its network are added
+// to the routing table along with it.
+
+use core::fmt::{ self, Display, Formatter };
+
+use alloc::{ string::String, sync::Arc };
+
+use crate::{ devices::network_devices::{ MacAddress, NetworkDevice },
+             net::{ dhcp::DhcpClient,
+                    ipv4::{ Ipv4Address, Ipv4Subnet, Route },
+                    with_network_stack,
+                    NetError,
+                    NetResult,
+                    NetworkStack } };
+
+
+
+/// One of the stack's interfaces.
+pub struct Interface
+{
+    /// The device the interface sends and receives through.
+    pub device: Arc<dyn NetworkDevice>,
+
+    /// The device's MAC address and MTU, kept so that the device doesn't need to be asked.
+    pub mac_address: MacAddress,
+    pub mtu: usize,
+
+    /// The interface's address, if it has been given one.
+    pub address: Option<Ipv4Subnet>,
+
+    /// The router on the interface's network that the default route goes through.
+    pub gateway: Option<Ipv4Address>,
+
+    /// The DHCP client, if DHCP is configuring the interface.
+    pub dhcp: Option<DhcpClient>
+}
+
+
+
+impl Interface
+{
+    /// Create an unconfigured interface for a device.
+    pub fn new(device: Arc<dyn NetworkDevice>) -> Interface
+    {
+        Interface
+            {
+                mac_address: device.mac_address(),
+                mtu: device.mtu(),
+                device,
+                address: None,
+                gateway: None,
+                dhcp: None
+            }
+    }
+
+    /// Does the interface take packets sent to the address? That's its own address and the
+    /// broadcast addresses, or anything while it's waiting for DHCP to give it an address.
+    pub fn accepts(&self, destination: Ipv4Address) -> bool
+    {
+        match self.address
+        {
+            Some(address) =>    destination == address.address
+                             || destination == address.broadcast()
+                             || destination.is_broadcast(),
+            None          => true
+        }
+    }
+}
+
+
+
+/// A description of how an interface is configured.
+#[derive(Clone, Debug)]
+pub struct InterfaceConfiguration
+{
+    pub name: String,
+    pub mac_address: MacAddress,
+    pub mtu: usize,
+    pub link_up: bool,
+    pub address: Option<Ipv4Subnet>,
+    pub gateway: Option<Ipv4Address>,
+
+    /// Is the interface configured by DHCP?
+    pub dhcp: bool
+}
+
+
+
+impl Display for InterfaceConfiguration
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        write!(formatter, "{}: ", self.name)?;
+
+        match self.address
+        {
+            Some(address) => write!(formatter, "{}", address)?,
+            None          => write!(formatter, "unconfigured")?
+        }
+
+        if let Some(gateway) = self.gateway
+        {
+            write!(formatter, ", gateway {}", gateway)?;
+        }
+
+        if self.dhcp
+        {
+            write!(formatter, " (dhcp)")?;
+        }
+
+        if !self.link_up
+        {
+            write!(formatter, ", link down")?;
+        }
+
+        Ok(())
+    }
+}
+
+
+
+impl NetworkStack
+{
+    /// Find an interface by the name of its device.
+    pub fn interface_index(&self, name: &str) -> Option<usize>
+    {
+        self.interfaces.iter().position(|interface| interface.device.name() == name)
+    }
+
+    /// Have all of the interfaces being configured by DHCP got their addresses?
+    pub fn all_interfaces_configured(&self) -> bool
+    {
+        self.interfaces
+            .iter()
+            .all(|interface| interface.dhcp.is_none() || interface.address.is_some())
+    }
+
+    /// Give an interface its address and gateway, or take them away. The routes through the
+    /// interface are replaced by the route to its network and the default route through the
+    /// gateway.
+    pub fn configure_interface(&mut self,
+                               index: usize,
+                               address: Option<Ipv4Subnet>,
+                               gateway: Option<Ipv4Address>) -> NetResult<()>
+    {
+        if index >= self.interfaces.len()
+        {
+            return Err(NetError::InvalidArgument);
+        }
+
+        let interface = &mut self.interfaces[index];
+
+        interface.address = address;
+        interface.gateway = gateway.filter(|_| address.is_some());
+
+        self.arp.flush_interface(index);
+        self.routes.remove_interface(index);
+
+        if let Some(address) = address
+        {
+            self.routes.add(Route { destination: address, gateway: None, interface: index })?;
+
+            if let Some(gateway) = gateway
+            {
+                self.routes.add(Route
+                    {
+                        destination: Ipv4Subnet::ANY,
+                        gateway: Some(gateway),
+                        interface: index
+                    })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+
+/// The number of network interfaces.
+pub fn interface_count() -> usize
+{
+    with_network_stack(|stack| Ok(stack.interfaces.len())).unwrap_or(0)
+}
+
+
+/// Get a description of how an interface is configured.
+pub fn interface_configuration(index: usize) -> Option<InterfaceConfiguration>
+{
+    with_network_stack(|stack|
+        {
+            let interface = stack.interfaces.get(index).ok_or(NetError::InvalidArgument)?;
+
+            Ok(InterfaceConfiguration
+                {
+                    name: String::from(interface.device.name()),
+                    mac_address: interface.mac_address,
+                    mtu: interface.mtu,
+                    link_up: interface.device.is_link_up(),
+                    address: interface.address,
+                    gateway: interface.gateway,
+                    dhcp: interface.dhcp.is_some()
+                })
+        })
+        .ok()
+}
+
+
+/// Give an interface a fixed address, and optionally a gateway, stopping DHCP if it was running on
+/// the interface. Giving no address leaves the interface unconfigured.
+pub fn configure_interface(index: usize,
+                           address: Option<Ipv4Subnet>,
+                           gateway: Option<Ipv4Address>) -> NetResult<()>
+{
+    with_network_stack(|stack|
+        {
+            stack.configure_interface(index, address, gateway)?;
+            stack.interfaces[index].dhcp = None;
+
+            Ok(())
+        })
+}
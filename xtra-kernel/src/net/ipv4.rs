@@ -0,0 +1,621 @@
+// IPv4, the addresses, the packets and the routing table that decides which interface, and which
+// gateway on it, a packet is sent through.
+//
+// Packets are neither fragmented nor reassembled. Everything sent fits in the MTU of the interface,
+// (the transport protocols make sure of that,) and received fragments are dropped. Nor does the
+// stack forward packets between its interfaces, those not addressed to us are dropped.
+
+use core::fmt::{ self, Display, Formatter };
+
+use alloc::vec::Vec;
+
+use crate::{ devices::network_devices::{ ones_complement_sum,
+                                         ChecksumRequest,
+                                         ETHERNET_HEADER_SIZE },
+             net::{ ethernet::ETHERTYPE_IPV4,
+                    with_network_stack,
+                    NetError,
+                    NetResult,
+                    NetworkStack } };
+
+
+
+/// The size of an IPv4 header without options.
+pub const IPV4_HEADER_SIZE: usize = 20;
+
+// The protocols carried by IPv4.
+pub const IP_PROTOCOL_ICMP: u8 = 1;
+pub const IP_PROTOCOL_TCP:  u8 = 6;
+pub const IP_PROTOCOL_UDP:  u8 = 17;
+
+// Fields of the header.
+const VERSION_AND_LENGTH: u8  = 0x45;     // Version 4, a header of 5 words.
+const DONT_FRAGMENT:      u16 = 0x4000;
+const MORE_FRAGMENTS:     u16 = 0x2000;
+const FRAGMENT_OFFSET:    u16 = 0x1fff;
+const DEFAULT_TTL:        u8  = 64;
+
+/// The most routes the routing table holds.
+pub const MAX_ROUTES: usize = 16;
+
+
+
+/// An IPv4 address.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Debug)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+
+
+impl Ipv4Address
+{
+    /// The address meaning any address, or no address yet.
+    pub const UNSPECIFIED: Ipv4Address = Ipv4Address([ 0; 4 ]);
+
+    /// The address every host on the local network receives.
+    pub const BROADCAST: Ipv4Address = Ipv4Address([ 255; 4 ]);
+
+    /// Create an address from its four parts.
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Ipv4Address
+    {
+        Ipv4Address([ a, b, c, d ])
+    }
+
+    /// Create an address from its 32 bit value.
+    pub const fn from_bits(bits: u32) -> Ipv4Address
+    {
+        Ipv4Address(bits.to_be_bytes())
+    }
+
+    /// The 32 bit value of the address.
+    pub const fn to_bits(self) -> u32
+    {
+        u32::from_be_bytes(self.0)
+    }
+
+    /// Read an address from the first four bytes of a packet.
+    pub fn from_bytes(bytes: &[u8]) -> Ipv4Address
+    {
+        Ipv4Address([ bytes[0], bytes[1], bytes[2], bytes[3] ])
+    }
+
+    /// Is this the unspecified address, 0.0.0.0?
+    pub fn is_unspecified(&self) -> bool
+    {
+        *self == Ipv4Address::UNSPECIFIED
+    }
+
+    /// Is this the broadcast address, 255.255.255.255?
+    pub fn is_broadcast(&self) -> bool
+    {
+        *self == Ipv4Address::BROADCAST
+    }
+
+    /// Is this a multicast address?
+    pub fn is_multicast(&self) -> bool
+    {
+        self.0[0] & 0xf0 == 0xe0
+    }
+
+    /// Parse an address written the usual way, like 10.0.2.15.
+    pub fn parse(text: &str) -> Option<Ipv4Address>
+    {
+        let mut address = [ 0; 4 ];
+        let mut parts = text.split('.');
+
+        for byte in address.iter_mut()
+        {
+            *byte = parts.next()?.parse().ok()?;
+        }
+
+        match parts.next()
+        {
+            Some(_) => None,
+            None    => Some(Ipv4Address(address))
+        }
+    }
+}
+
+
+
+impl Display for Ipv4Address
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        let [ a, b, c, d ] = self.0;
+
+        write!(formatter, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+
+
+/// An address along with the length of the prefix naming its network, like 10.0.2.15/24. It's
+/// used both for an interface's address and for the networks in the routing table.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Ipv4Subnet
+{
+    pub address: Ipv4Address,
+    pub prefix_length: u8
+}
+
+
+
+impl Ipv4Subnet
+{
+    /// The subnet every address is in, as used by the default route.
+    pub const ANY: Ipv4Subnet = Ipv4Subnet { address: Ipv4Address::UNSPECIFIED, prefix_length: 0 };
+
+    /// Create a subnet, the prefix can't be longer than 32 bits.
+    pub fn new(address: Ipv4Address, prefix_length: u8) -> Option<Ipv4Subnet>
+    {
+        match prefix_length
+        {
+            0..=32 => Some(Ipv4Subnet { address, prefix_length }),
+            _      => None
+        }
+    }
+
+    /// Create a subnet from an address and its network mask, the mask has to be contiguous.
+    pub fn from_netmask(address: Ipv4Address, netmask: Ipv4Address) -> Option<Ipv4Subnet>
+    {
+        let mask = netmask.to_bits();
+        let prefix_length = mask.leading_ones();
+
+        match mask.checked_shl(prefix_length).unwrap_or(0)
+        {
+            0 => Ipv4Subnet::new(address, prefix_length as u8),
+            _ => None
+        }
+    }
+
+    /// Parse a subnet written the usual way, like 10.0.2.15/24.
+    pub fn parse(text: &str) -> Option<Ipv4Subnet>
+    {
+        let (address, prefix_length) = text.split_once('/')?;
+
+        Ipv4Subnet::new(Ipv4Address::parse(address)?, prefix_length.parse().ok()?)
+    }
+
+    /// The network mask, like 255.255.255.0.
+    pub fn netmask(&self) -> Ipv4Address
+    {
+        Ipv4Address::from_bits(u32::MAX.checked_shl(32 - self.prefix_length as u32).unwrap_or(0))
+    }
+
+    /// The subnet with the host part of the address cleared, like 10.0.2.0/24.
+    pub fn network(&self) -> Ipv4Subnet
+    {
+        let address = Ipv4Address::from_bits(self.address.to_bits() & self.netmask().to_bits());
+
+        Ipv4Subnet { address, prefix_length: self.prefix_length }
+    }
+
+    /// The address that broadcasts to every host in the subnet.
+    pub fn broadcast(&self) -> Ipv4Address
+    {
+        Ipv4Address::from_bits(self.address.to_bits() | !self.netmask().to_bits())
+    }
+
+    /// Is the address in the subnet?
+    pub fn contains(&self, address: Ipv4Address) -> bool
+    {
+        let mask = self.netmask().to_bits();
+
+        address.to_bits() & mask == self.address.to_bits() & mask
+    }
+}
+
+
+
+impl Display for Ipv4Subnet
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        write!(formatter, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+
+
+/// A route, where to send the packets for a network.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Route
+{
+    /// The network the route is for.
+    pub destination: Ipv4Subnet,
+
+    /// The router to send the packets through, or none when the network is attached to the
+    /// interface.
+    pub gateway: Option<Ipv4Address>,
+
+    /// The index of the interface to send the packets out of.
+    pub interface: usize
+}
+
+
+
+/// The routing table, packets are sent by the route with the longest prefix that matches their
+/// destination.
+pub struct RoutingTable
+{
+    routes: Vec<Route>
+}
+
+
+
+impl RoutingTable
+{
+    /// Create an empty routing table.
+    pub fn new() -> RoutingTable
+    {
+        RoutingTable { routes: Vec::with_capacity(MAX_ROUTES) }
+    }
+
+    /// The routes in the table.
+    pub fn routes(&self) -> &[Route]
+    {
+        &self.routes
+    }
+
+    /// Add a route, replacing any route for the same network.
+    pub fn add(&mut self, route: Route) -> NetResult<()>
+    {
+        let route = Route { destination: route.destination.network(), ..route };
+
+        if let Some(existing) = self.routes
+                                    .iter_mut()
+                                    .find(|existing| existing.destination == route.destination)
+        {
+            *existing = route;
+            return Ok(());
+        }
+
+        if self.routes.len() == MAX_ROUTES
+        {
+            return Err(NetError::NoBufferSpace);
+        }
+
+        self.routes.push(route);
+
+        Ok(())
+    }
+
+    /// Remove the route for a network.
+    pub fn remove(&mut self, destination: Ipv4Subnet) -> NetResult<()>
+    {
+        let destination = destination.network();
+        let count = self.routes.len();
+
+        self.routes.retain(|route| route.destination != destination);
+
+        match self.routes.len() == count
+        {
+            true  => Err(NetError::InvalidArgument),
+            false => Ok(())
+        }
+    }
+
+    /// Remove all of the routes through an interface.
+    pub fn remove_interface(&mut self, interface: usize)
+    {
+        self.routes.retain(|route| route.interface != interface);
+    }
+
+    /// Find the route for a destination.
+    pub fn lookup(&self, destination: Ipv4Address) -> Option<Route>
+    {
+        self.routes
+            .iter()
+            .filter(|route| route.destination.contains(destination))
+            .max_by_key(|route| route.destination.prefix_length)
+            .copied()
+    }
+}
+
+
+
+/// A received IPv4 packet, as handed to the protocol carried in it.
+pub struct Ipv4Packet<'packet>
+{
+    /// The index of the interface the packet came in on.
+    pub interface: usize,
+
+    pub source: Ipv4Address,
+    pub destination: Ipv4Address,
+    pub protocol: u8,
+
+    /// Was the packet sent to a broadcast or multicast address?
+    pub broadcast: bool,
+
+    /// Did the device already find the TCP or UDP checksum good?
+    pub checksum_valid: bool,
+
+    /// The IPv4 header, kept for the ICMP errors that quote it.
+    pub header: &'packet [u8],
+
+    /// What the packet carries.
+    pub payload: &'packet [u8]
+}
+
+
+
+impl Ipv4Packet<'_>
+{
+    /// Check the packet's TCP or UDP checksum, which covers the pseudo header as well as the
+    /// payload.
+    pub fn transport_checksum_ok(&self) -> bool
+    {
+        if self.checksum_valid
+        {
+            return true;
+        }
+
+        let pseudo_header = pseudo_header_sum(self.source,
+                                              self.destination,
+                                              self.protocol,
+                                              self.payload.len());
+
+        add_checksums(pseudo_header, ones_complement_sum(self.payload)) == 0xffff
+    }
+}
+
+
+
+/// The ones' complement sum of the pseudo header TCP and UDP include in their checksums.
+pub fn pseudo_header_sum(source: Ipv4Address,
+                         destination: Ipv4Address,
+                         protocol: u8,
+                         length: usize) -> u16
+{
+    let mut header = [ 0; 12 ];
+
+    header[0..4].copy_from_slice(&source.0);
+    header[4..8].copy_from_slice(&destination.0);
+    header[9] = protocol;
+    header[10..12].copy_from_slice(&(length as u16).to_be_bytes());
+
+    ones_complement_sum(&header)
+}
+
+
+/// Add two ones' complement sums together.
+pub fn add_checksums(first: u16, second: u16) -> u16
+{
+    let sum = first as u32 + second as u32;
+
+    ((sum & 0xffff) + (sum >> 16)) as u16
+}
+
+
+
+impl NetworkStack
+{
+    /// Handle a received IPv4 packet, passing it on to the protocol it carries.
+    pub fn handle_ipv4(&mut self, interface: usize, data: &[u8], checksum_valid: bool)
+    {
+        if    data.len() < IPV4_HEADER_SIZE
+           || data[0] >> 4 != 4
+        {
+            return;
+        }
+
+        let header_size = (data[0] & 0x0f) as usize * 4;
+        let total_length = u16::from_be_bytes([ data[2], data[3] ]) as usize;
+
+        if    header_size < IPV4_HEADER_SIZE
+           || total_length < header_size
+           || total_length > data.len()
+           || ones_complement_sum(&data[..header_size]) != 0xffff
+        {
+            return;
+        }
+
+        // Fragments aren't reassembled.
+        let fragment = u16::from_be_bytes([ data[6], data[7] ]);
+
+        if fragment & (MORE_FRAGMENTS | FRAGMENT_OFFSET) != 0
+        {
+            return;
+        }
+
+        let destination = Ipv4Address::from_bytes(&data[16..20]);
+
+        if !self.interfaces[interface].accepts(destination)
+        {
+            return;
+        }
+
+        let broadcast = destination.is_broadcast()
+                        || destination.is_multicast()
+                        || self.interfaces[interface].address
+                                                     .is_some_and(|address| {
+                                                         address.broadcast() == destination
+                                                     });
+
+        let packet = Ipv4Packet
+            {
+                interface,
+                source: Ipv4Address::from_bytes(&data[12..16]),
+                destination,
+                protocol: data[9],
+                broadcast,
+                checksum_valid,
+                header: &data[..header_size],
+                payload: &data[header_size..total_length]
+            };
+
+        match packet.protocol
+        {
+            IP_PROTOCOL_ICMP => self.handle_icmp(&packet),
+            IP_PROTOCOL_UDP  => self.handle_udp(&packet),
+            IP_PROTOCOL_TCP  => self.handle_tcp(&packet),
+            _                => {}
+        }
+    }
+
+    /// Find the interface and source address to use for a destination. Broadcasts go out of the
+    /// first configured interface, as there's no route for them.
+    pub fn route_to(&self, destination: Ipv4Address) -> NetResult<(usize, Ipv4Address)>
+    {
+        let interface = match destination.is_broadcast()
+            {
+                true  => self.interfaces
+                             .iter()
+                             .position(|interface| interface.address.is_some()),
+                false => self.routes.lookup(destination).map(|route| route.interface)
+            };
+
+        let interface = interface.ok_or(NetError::NetworkUnreachable)?;
+        let address = self.interfaces[interface].address.ok_or(NetError::NetworkUnreachable)?;
+
+        Ok((interface, address.address))
+    }
+
+    /// Send an IPv4 packet to wherever the routing table says it goes. The payload is made up of
+    /// the given parts. If `checksum_field` is given it's the offset into the payload of a TCP or
+    /// UDP checksum, which is filled in as the packet is sent.
+    pub fn send_ipv4(&mut self,
+                     source: Option<Ipv4Address>,
+                     destination: Ipv4Address,
+                     protocol: u8,
+                     parts: &[&[u8]],
+                     checksum_field: Option<usize>) -> NetResult<()>
+    {
+        let (interface, interface_address) = self.route_to(destination)?;
+        let next_hop = match destination.is_broadcast()
+            {
+                true  => destination,
+                false => self.routes
+                             .lookup(destination)
+                             .and_then(|route| route.gateway)
+                             .unwrap_or(destination)
+            };
+
+        let source = source.filter(|source| !source.is_unspecified())
+                           .unwrap_or(interface_address);
+
+        self.transmit_ipv4(interface,
+                           source,
+                           destination,
+                           next_hop,
+                           protocol,
+                           parts,
+                           checksum_field)
+    }
+
+    /// Send an IPv4 packet out of a given interface, to the given next hop. This is what
+    /// `send_ipv4` uses once it has found the route, and how DHCP sends before the interface has
+    /// an address.
+    #[allow(clippy::too_many_arguments)]
+    pub fn transmit_ipv4(&mut self,
+                         interface: usize,
+                         source: Ipv4Address,
+                         destination: Ipv4Address,
+                         next_hop: Ipv4Address,
+                         protocol: u8,
+                         parts: &[&[u8]],
+                         checksum_field: Option<usize>) -> NetResult<()>
+    {
+        let payload_length: usize = parts.iter().map(|part| part.len()).sum();
+        let total_length = IPV4_HEADER_SIZE + payload_length;
+        let frame_length = ETHERNET_HEADER_SIZE + total_length;
+
+        if total_length > self.interfaces[interface].mtu
+        {
+            return Err(NetError::MessageTooLong);
+        }
+
+        // The frame buffer is taken while the packet is built, so that the rest of the stack can
+        // still be used to send it.
+        let mut frame = core::mem::take(&mut self.frame);
+
+        if frame.len() < frame_length
+        {
+            self.frame = frame;
+            return Err(NetError::NoBufferSpace);
+        }
+
+        let header_start = ETHERNET_HEADER_SIZE;
+        let payload_start = header_start + IPV4_HEADER_SIZE;
+        let mut offset = payload_start;
+
+        for part in parts
+        {
+            frame[offset..offset + part.len()].copy_from_slice(part);
+            offset += part.len();
+        }
+
+        let identification = self.next_identification;
+
+        self.next_identification = identification.wrapping_add(1);
+
+        let header = &mut frame[header_start..payload_start];
+
+        header[0] = VERSION_AND_LENGTH;
+        header[1] = 0;
+        header[2..4].copy_from_slice(&(total_length as u16).to_be_bytes());
+        header[4..6].copy_from_slice(&identification.to_be_bytes());
+        header[6..8].copy_from_slice(&DONT_FRAGMENT.to_be_bytes());
+        header[8] = DEFAULT_TTL;
+        header[9] = protocol;
+        header[10..12].fill(0);
+        header[12..16].copy_from_slice(&source.0);
+        header[16..20].copy_from_slice(&destination.0);
+
+        let checksum = !ones_complement_sum(header);
+
+        header[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        // The transport checksum starts out as the sum of the pseudo header, the device or the
+        // driver finishes it off.
+        let checksum = checksum_field.map(|field|
+            {
+                let sum = pseudo_header_sum(source, destination, protocol, payload_length);
+                let field_start = payload_start + field;
+
+                frame[field_start..field_start + 2].copy_from_slice(&sum.to_be_bytes());
+
+                ChecksumRequest { start: payload_start, offset: field }
+            });
+
+        let result = self.transmit_frame(interface,
+                                         next_hop,
+                                         ETHERTYPE_IPV4,
+                                         &mut frame[..frame_length],
+                                         checksum);
+
+        self.frame = frame;
+
+        result
+    }
+}
+
+
+
+/// Add a route to the routing table, replacing any route for the same network.
+pub fn add_route(route: Route) -> NetResult<()>
+{
+    with_network_stack(|stack|
+        {
+            if route.interface >= stack.interfaces.len()
+            {
+                return Err(NetError::InvalidArgument);
+            }
+
+            stack.routes.add(route)
+        })
+}
+
+
+/// Remove the route for a network from the routing table.
+pub fn remove_route(destination: Ipv4Subnet) -> NetResult<()>
+{
+    with_network_stack(|stack| stack.routes.remove(destination))
+}
+
+
+/// Get the routes in the routing table.
+pub fn routes() -> Vec<Route>
+{
+    with_network_stack(|stack| Ok(stack.routes.routes().to_vec())).unwrap_or_default()
+}
@@ -0,0 +1,479 @@
+// The kernel's TCP/IP stack. It sits on top of the network devices, which move whole Ethernet
+// frames, and gives the rest of the kernel sockets to talk over. Each network device becomes an
+// interface of the stack, configured at boot by DHCP or by the ip= kernel parameter.
+//
+// The layers each have their own file, Ethernet framing, ARP, IPv4 with the routing table, ICMP,
+// UDP, TCP and the DHCP client, and the socket API the rest of the kernel uses is in socket.rs.
+//
+// All of the stack's state is kept in a single `NetworkStack` behind one lock, which is only ever
+// held with interrupts disabled. Frames come in from the devices' interrupt handlers, the protocol
+// timers, (retransmission, ARP and the DHCP lease,) are run from a periodic kernel timer, and the
+// socket calls come from whatever code makes them. So nothing in the stack ever waits, a call that
+// can't complete yet fails with `NetError::WouldBlock` or `NetError::InProgress` and is made again
+// later.
+//
+// The kernel heap never gives memory back, so the stack allocates its buffers up front, or the
+// first time a socket slot is used, and reuses them from then on. Nothing is allocated per packet.
+
+use core::{ fmt::{ self, Debug, Display, Formatter },
+            hint::spin_loop };
+
+use alloc::{ vec, vec::Vec };
+
+use crate::{ arch::interrupts::with_interrupts_disabled,
+             command_line::{ KernelParameter, KernelParameterRegistry },
+             devices::network_devices::{ network_devices, NetworkDevice, ETHERNET_HEADER_SIZE },
+             locking::spin_mutex::SpinMutex,
+             timers::{ current_milliseconds, start_timer } };
+
+
+
+/// Ethernet framing, and handing received frames to the protocols.
+pub mod ethernet;
+
+/// The address resolution protocol, finding the MAC addresses of the hosts on a network.
+pub mod arp;
+
+/// IPv4 addresses, packets and the routing table.
+pub mod ipv4;
+
+/// ICMP, echo requests and replies and the errors sent back for undeliverable packets.
+pub mod icmp;
+
+/// UDP datagrams.
+pub mod udp;
+
+/// TCP connections.
+pub mod tcp;
+
+/// The DHCP client that configures the interfaces.
+pub mod dhcp;
+
+/// The network interfaces, the devices as the stack sees them.
+pub mod interface;
+
+/// The socket API used by the rest of the kernel.
+pub mod socket;
+
+
+
+use crate::net::{ arp::ArpCache,
+                  icmp::EchoReplies,
+                  interface::Interface,
+                  ipv4::{ Ipv4Address, Ipv4Subnet, RoutingTable },
+                  socket::SocketTable };
+
+
+
+/// How often the protocol timers are run, in milliseconds.
+const TICK_INTERVAL_MS: u64 = 100;
+
+/// How long the boot waits for DHCP to configure the interfaces, in milliseconds.
+const DHCP_BOOT_TIMEOUT_MS: u64 = 5000;
+
+/// The most DNS servers remembered from DHCP.
+pub const MAX_DNS_SERVERS: usize = 2;
+
+
+
+/// The errors that can be reported by the network stack.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum NetError
+{
+    /// The operation can't complete yet, try again later.
+    WouldBlock,
+
+    /// A connection is being made, and isn't ready yet.
+    InProgress,
+
+    /// The socket is already connected.
+    AlreadyConnected,
+
+    /// The socket isn't connected.
+    NotConnected,
+
+    /// The remote host refused the connection.
+    ConnectionRefused,
+
+    /// The remote host reset the connection.
+    ConnectionReset,
+
+    /// The remote host stopped answering.
+    TimedOut,
+
+    /// The socket has been shut down for sending.
+    BrokenPipe,
+
+    /// Another socket is already using the address.
+    AddressInUse,
+
+    /// The address isn't one of this host's.
+    AddressNotAvailable,
+
+    /// There's no route to the network.
+    NetworkUnreachable,
+
+    /// The message is too big to send in a single packet.
+    MessageTooLong,
+
+    /// There's no room left, in a table or buffer, for what was asked for.
+    NoBufferSpace,
+
+    /// An argument to the operation is not valid.
+    InvalidArgument,
+
+    /// The handle doesn't refer to an open socket.
+    BadHandle,
+
+    /// The socket doesn't support the operation.
+    NotSupported,
+
+    /// There are no network interfaces.
+    NetworkDown,
+
+    /// The network device reported an error.
+    Device(&'static str)
+}
+
+
+
+impl Display for NetError
+{
+    /// Format the network error for display to the user when needed.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            NetError::WouldBlock          => write!(f, "Operation would block"),
+            NetError::InProgress          => write!(f, "Operation now in progress"),
+            NetError::AlreadyConnected    => write!(f, "Transport endpoint is already connected"),
+            NetError::NotConnected        => write!(f, "Transport endpoint is not connected"),
+            NetError::ConnectionRefused   => write!(f, "Connection refused"),
+            NetError::ConnectionReset     => write!(f, "Connection reset by peer"),
+            NetError::TimedOut            => write!(f, "Connection timed out"),
+            NetError::BrokenPipe          => write!(f, "Broken pipe"),
+            NetError::AddressInUse        => write!(f, "Address already in use"),
+            NetError::AddressNotAvailable => write!(f, "Cannot assign requested address"),
+            NetError::NetworkUnreachable  => write!(f, "Network is unreachable"),
+            NetError::MessageTooLong      => write!(f, "Message too long"),
+            NetError::NoBufferSpace       => write!(f, "No buffer space available"),
+            NetError::InvalidArgument     => write!(f, "Invalid argument"),
+            NetError::BadHandle           => write!(f, "Bad socket handle"),
+            NetError::NotSupported        => write!(f, "Operation not supported"),
+            NetError::NetworkDown         => write!(f, "Network is down"),
+
+            NetError::Device(message) =>
+                write!(f, "Network device error: {}", message)
+        }
+    }
+}
+
+
+
+impl Debug for NetError
+{
+    /// Format the network error for debugging purposes.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result
+    {
+        Display::fmt(self, f)
+    }
+}
+
+
+
+/// Errors from the network devices and the timers are reported as device errors.
+impl From<&'static str> for NetError
+{
+    fn from(message: &'static str) -> Self
+    {
+        NetError::Device(message)
+    }
+}
+
+
+
+/// The result type used throughout the network stack.
+pub type NetResult<T> = Result<T, NetError>;
+
+
+
+/// How the ip= kernel parameter asked for the first interface to be configured. Any others are
+/// always configured by DHCP.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum IpConfiguration
+{
+    /// Leave the interfaces unconfigured.
+    Off,
+
+    /// Ask a DHCP server for an address.
+    Dhcp,
+
+    /// Use a fixed address, and gateway if there is one.
+    Static { address: Ipv4Subnet, gateway: Option<Ipv4Address> }
+}
+
+
+
+/// The state of the whole stack.
+pub struct NetworkStack
+{
+    /// The interfaces, one for each network device, in the order of the devices.
+    interfaces: Vec<Interface>,
+
+    /// Where the packets for each network go.
+    routes: RoutingTable,
+
+    /// The MAC addresses of the hosts on the attached networks.
+    arp: ArpCache,
+
+    /// The open sockets.
+    sockets: SocketTable,
+
+    /// The echo replies received for the pings that have been sent.
+    echo_replies: EchoReplies,
+
+    /// The DNS servers given to us by DHCP.
+    dns_servers: [Option<Ipv4Address>; MAX_DNS_SERVERS],
+
+    /// The buffer outgoing packets are built in, big enough for a frame on any of the interfaces.
+    frame: Vec<u8>,
+
+    /// The identification given to the next IPv4 packet sent.
+    next_identification: u16
+}
+
+
+
+impl NetworkStack
+{
+    /// Create the stack for the given interfaces.
+    fn new(interfaces: Vec<Interface>) -> NetworkStack
+    {
+        let frame_size = interfaces.iter()
+                                   .map(|interface| ETHERNET_HEADER_SIZE + interface.mtu)
+                                   .max()
+                                   .unwrap_or(0);
+
+        NetworkStack
+            {
+                interfaces,
+                routes: RoutingTable::new(),
+                arp: ArpCache::new(frame_size),
+                sockets: SocketTable::new(),
+                echo_replies: EchoReplies::new(),
+                dns_servers: [None; MAX_DNS_SERVERS],
+                frame: vec![0; frame_size],
+                next_identification: current_milliseconds() as u16
+            }
+    }
+
+    /// Run the protocols' timers that are due.
+    fn run_timers(&mut self, now: u64)
+    {
+        self.run_arp_timers(now);
+        self.run_tcp_timers(now);
+        self.run_dhcp_timers(now);
+    }
+}
+
+
+
+/// The network stack, once it has been initialized.
+static NETWORK_STACK: SpinMutex<Option<NetworkStack>> = SpinMutex::new(None);
+
+/// How the ip= kernel parameter asked for the network to be configured.
+static IP_CONFIGURATION: SpinMutex<IpConfiguration> = SpinMutex::new(IpConfiguration::Dhcp);
+
+
+
+/// Run a function with the network stack locked, failing if there isn't a stack.
+fn with_network_stack<Value>(function: impl FnOnce(&mut NetworkStack) -> NetResult<Value>)
+    -> NetResult<Value>
+{
+    with_interrupts_disabled(||
+        {
+            let mut stack = NETWORK_STACK.lock();
+
+            match stack.as_mut()
+            {
+                Some(stack) => function(stack),
+                None        => Err(NetError::NetworkDown)
+            }
+        })
+}
+
+
+
+/// Register the kernel parameters accepted by the network stack.
+pub fn register_kernel_parameters(registry: &mut KernelParameterRegistry)
+    -> Result<(), &'static str>
+{
+    registry.insert("ip",
+                    KernelParameter
+                        {
+                            description: "the network configuration, dhcp, off or \
+                                          <address>/<prefix>[,<gateway>]",
+                            handler: |value|
+                                {
+                                    let configuration = match value
+                                        {
+                                            Some("dhcp") => IpConfiguration::Dhcp,
+                                            Some("off")  => IpConfiguration::Off,
+                                            Some(value)  => parse_static_configuration(value)
+                                                .ok_or("Expected ip=dhcp, ip=off or \
+                                                        ip=<address>/<prefix>[,<gateway>].")?,
+                                            None => return Err("Expected ip=<configuration>.")
+                                        };
+
+                                    *IP_CONFIGURATION.lock() = configuration;
+
+                                    Ok(())
+                                }
+                        });
+
+    Ok(())
+}
+
+
+/// Parse a static configuration, like 10.0.2.15/24,10.0.2.2.
+fn parse_static_configuration(value: &str) -> Option<IpConfiguration>
+{
+    let (address, gateway) = match value.split_once(',')
+        {
+            Some((address, gateway)) => (address, Some(Ipv4Address::parse(gateway)?)),
+            None                     => (value, None)
+        };
+
+    Some(IpConfiguration::Static { address: Ipv4Subnet::parse(address)?, gateway })
+}
+
+
+
+/// Bring up the network stack on the network devices that were found, and configure the
+/// interfaces. When DHCP is used the boot waits a little while for the leases, so that the system
+/// comes up with its network ready.
+pub fn initialize_network() -> NetResult<()>
+{
+    let devices = network_devices();
+
+    if devices.is_empty()
+    {
+        println!("  No network devices found.");
+        return Ok(());
+    }
+
+    let interfaces = devices.iter().map(|device| Interface::new(device.clone())).collect();
+
+    with_interrupts_disabled(|| *NETWORK_STACK.lock() = Some(NetworkStack::new(interfaces)));
+
+    for device in &devices
+    {
+        device.set_receive_handler(Some(receive_frame));
+    }
+
+    let configuration = *IP_CONFIGURATION.lock();
+
+    with_network_stack(|stack|
+        {
+            for index in 0..stack.interfaces.len()
+            {
+                match configuration
+                {
+                    IpConfiguration::Off => {},
+
+                    IpConfiguration::Static { address, gateway } if index == 0 =>
+                        stack.configure_interface(index, Some(address), gateway)?,
+
+                    _ => stack.start_dhcp(index, current_milliseconds())
+                }
+            }
+
+            Ok(())
+        })?;
+
+    start_timer(TICK_INTERVAL_MS, network_tick, 0)?;
+
+    // Wait for the DHCP servers to answer. The devices are polled here too, so this works even
+    // if their interrupts aren't being delivered.
+    let deadline = current_milliseconds() + DHCP_BOOT_TIMEOUT_MS;
+
+    while    configuration != IpConfiguration::Off
+          && current_milliseconds() < deadline
+          && !with_network_stack(|stack| Ok(stack.all_interfaces_configured()))?
+    {
+        poll_network();
+        spin_loop();
+    }
+
+    for index in 0..devices.len()
+    {
+        if let Some(configuration) = interface::interface_configuration(index)
+        {
+            println!("  {}", configuration);
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Handle whatever the network devices have done since they were last looked at, and run the
+/// protocol timers that are due. This is done periodically anyway, but can be called to make
+/// progress sooner.
+pub fn poll_network()
+{
+    // The devices are polled without the stack locked, the frames they've received are passed to
+    // the stack's receive handler which takes the lock itself.
+    for index in 0..
+    {
+        let device = with_network_stack(|stack|
+            {
+                Ok(stack.interfaces.get(index).map(|interface| interface.device.clone()))
+            });
+
+        match device
+        {
+            Ok(Some(device)) => device.poll(),
+            _                => break
+        }
+    }
+
+    let _ = with_network_stack(|stack|
+        {
+            stack.run_timers(current_milliseconds());
+            Ok(())
+        });
+}
+
+
+/// The periodic timer that keeps the stack running.
+fn network_tick(_argument: usize)
+{
+    poll_network();
+
+    // There's nowhere to report a failure from interrupt context, if the timer table is full the
+    // next socket call or poll still makes progress.
+    let _ = start_timer(TICK_INTERVAL_MS, network_tick, 0);
+}
+
+
+/// The handler the network devices call with the frames they receive.
+fn receive_frame(device: &dyn NetworkDevice, frame: &[u8], checksum_valid: bool)
+{
+    let _ = with_network_stack(|stack|
+        {
+            if let Some(index) = stack.interface_index(device.name())
+            {
+                stack.handle_frame(index, frame, checksum_valid);
+            }
+
+            Ok(())
+        });
+}
+
+
+/// Get the DNS servers given to us by DHCP.
+pub fn dns_servers() -> [Option<Ipv4Address>; MAX_DNS_SERVERS]
+{
+    with_network_stack(|stack| Ok(stack.dns_servers)).unwrap_or([None; MAX_DNS_SERVERS])
+}
@@ -0,0 +1,751 @@
+// The socket API the rest of the kernel talks to the network through. Stream sockets are TCP
+// connections and datagram sockets are UDP, and both are used much like BSD sockets. A socket is
+// referred to by a handle, which stops working once the socket is closed, even if its slot in the
+// table is reused.
+//
+// None of the calls wait. A call that can't complete yet fails with `NetError::WouldBlock`, or
+// `NetError::InProgress` for a connection being made, and is made again later. `poll_socket`
+// says which calls would succeed.
+//
+// The sockets live in a fixed table. The buffers of a slot are allocated the first time it's used
+// and kept for the sockets that use it after that, the kernel heap never gives memory back.
+
+use core::fmt::{ self, Display, Formatter };
+
+use alloc::vec::Vec;
+
+use crate::{ net::{ ipv4::Ipv4Address,
+                    tcp::{ TcpSocket, TcpState },
+                    udp::UdpSocket,
+                    with_network_stack,
+                    NetError,
+                    NetResult,
+                    NetworkStack },
+             ring_buffer::RingBuffer,
+             timers::current_milliseconds };
+
+
+
+/// The most sockets that can be open at once, including the connections waiting to be accepted
+/// and the ones that are closing.
+pub const MAX_SOCKETS: usize = 64;
+
+/// The size of each of a socket's send and receive buffers.
+pub const SOCKET_BUFFER_SIZE: usize = 16 * 1024;
+
+/// The most connections a listening socket holds waiting to be accepted.
+pub const MAX_BACKLOG: usize = 16;
+
+// The range the ports of sockets that aren't bound to a port are picked from.
+const EPHEMERAL_PORT_FIRST: u16 = 49152;
+const EPHEMERAL_PORT_LAST:  u16 = 65535;
+
+
+
+/// The kinds of socket.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SocketType
+{
+    /// A TCP connection.
+    Stream,
+
+    /// UDP datagrams.
+    Datagram
+}
+
+
+
+/// An address and port that a socket is bound or connected to.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct SocketAddress
+{
+    pub address: Ipv4Address,
+    pub port: u16
+}
+
+
+
+impl SocketAddress
+{
+    /// Any address, and no port.
+    pub const UNSPECIFIED: SocketAddress = SocketAddress
+        {
+            address: Ipv4Address::UNSPECIFIED,
+            port: 0
+        };
+
+    /// Create a socket address.
+    pub const fn new(address: Ipv4Address, port: u16) -> SocketAddress
+    {
+        SocketAddress { address, port }
+    }
+}
+
+
+
+impl Display for SocketAddress
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        write!(formatter, "{}:{}", self.address, self.port)
+    }
+}
+
+
+
+/// Which directions of a socket to shut down.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Shutdown
+{
+    Read,
+    Write,
+    Both
+}
+
+
+
+/// What a socket is ready for, as reported by `poll_socket`.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct SocketEvents
+{
+    /// Receiving, or accepting, won't block.
+    pub readable: bool,
+
+    /// Sending won't block.
+    pub writable: bool,
+
+    /// The other end has finished with the connection.
+    pub hung_up: bool,
+
+    /// The socket has an error to report.
+    pub error: bool
+}
+
+
+
+/// A handle to an open socket.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SocketHandle
+{
+    /// The socket's slot in the table.
+    index: usize,
+
+    /// The slot's generation when the socket was opened, so that old handles to the slot don't
+    /// refer to the socket using it now.
+    generation: u32
+}
+
+
+
+impl Display for SocketHandle
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        write!(formatter, "socket {}.{}", self.index, self.generation)
+    }
+}
+
+
+
+/// A socket's send and receive buffers.
+pub struct SocketBuffers
+{
+    pub send: RingBuffer,
+    pub receive: RingBuffer
+}
+
+
+
+/// A socket in the table. The sockets stay in their slots, so a TCP socket being much bigger than
+/// a UDP one costs nothing.
+#[allow(clippy::large_enum_variant)]
+pub enum Socket
+{
+    Udp(UdpSocket),
+    Tcp(TcpSocket)
+}
+
+
+
+impl Socket
+{
+    /// Give up the socket's buffers.
+    fn into_buffers(self) -> SocketBuffers
+    {
+        match self
+        {
+            Socket::Udp(socket) => socket.into_buffers(),
+            Socket::Tcp(socket) => socket.into_buffers()
+        }
+    }
+
+    /// The address and port the socket is bound to.
+    fn local(&self) -> SocketAddress
+    {
+        match self
+        {
+            Socket::Udp(socket) => socket.local,
+            Socket::Tcp(socket) => socket.local
+        }
+    }
+
+    /// The kind of socket.
+    fn socket_type(&self) -> SocketType
+    {
+        match self
+        {
+            Socket::Udp(_) => SocketType::Datagram,
+            Socket::Tcp(_) => SocketType::Stream
+        }
+    }
+}
+
+
+
+/// A slot in the socket table.
+struct SocketSlot
+{
+    /// Counts the sockets that have used the slot.
+    generation: u32,
+
+    /// Is the socket still open by whoever created it? TCP sockets can outlive their handles while
+    /// they finish closing their connections.
+    open: bool,
+
+    /// The socket using the slot, if any.
+    socket: Option<Socket>,
+
+    /// The slot's buffers, while no socket is using them.
+    spare_buffers: Option<SocketBuffers>
+}
+
+
+
+/// The table of sockets.
+pub struct SocketTable
+{
+    slots: Vec<SocketSlot>,
+
+    /// The next ephemeral port to try.
+    next_ephemeral_port: u16
+}
+
+
+
+impl SocketTable
+{
+    /// Create an empty table.
+    pub fn new() -> SocketTable
+    {
+        let slots = (0..MAX_SOCKETS)
+            .map(|_| SocketSlot { generation: 0, open: false, socket: None, spare_buffers: None })
+            .collect();
+
+        SocketTable
+            {
+                slots,
+                next_ephemeral_port: EPHEMERAL_PORT_FIRST
+                                     + (current_milliseconds() % 1000) as u16
+            }
+    }
+
+    /// Put a new socket in a free slot, it's given the slot's buffers. Returns the slot.
+    pub fn allocate(&mut self, create: impl FnOnce(SocketBuffers) -> Socket) -> NetResult<usize>
+    {
+        let index = self.slots
+                        .iter()
+                        .position(|slot| slot.socket.is_none())
+                        .ok_or(NetError::NoBufferSpace)?;
+
+        let slot = &mut self.slots[index];
+        let buffers = slot.spare_buffers.take().unwrap_or_else(||
+            {
+                SocketBuffers
+                    {
+                        send: RingBuffer::new(SOCKET_BUFFER_SIZE),
+                        receive: RingBuffer::new(SOCKET_BUFFER_SIZE)
+                    }
+            });
+
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.open = true;
+        slot.socket = Some(create(buffers));
+
+        Ok(index)
+    }
+
+    /// Free a slot, keeping its buffers for the next socket to use it.
+    pub fn free(&mut self, index: usize)
+    {
+        let slot = &mut self.slots[index];
+
+        if let Some(socket) = slot.socket.take()
+        {
+            let mut buffers = socket.into_buffers();
+
+            buffers.send.clear();
+            buffers.receive.clear();
+
+            slot.spare_buffers = Some(buffers);
+        }
+
+        slot.open = false;
+    }
+
+    /// The number of slots.
+    pub fn len(&self) -> usize
+    {
+        self.slots.len()
+    }
+
+    /// The handle for the socket in a slot.
+    pub fn handle(&self, index: usize) -> SocketHandle
+    {
+        SocketHandle { index, generation: self.slots[index].generation }
+    }
+
+    /// Find the slot of an open socket by its handle.
+    pub fn lookup(&self, handle: SocketHandle) -> NetResult<usize>
+    {
+        match self.slots.get(handle.index)
+        {
+            Some(slot) if    slot.open
+                          && slot.generation == handle.generation
+                          && slot.socket.is_some() => Ok(handle.index),
+            _                                      => Err(NetError::BadHandle)
+        }
+    }
+
+    /// Let go of a socket's handle, leaving the socket to finish up on its own.
+    pub fn orphan(&mut self, index: usize)
+    {
+        self.slots[index].open = false;
+    }
+
+    /// Is the socket in a slot still open by whoever created it?
+    pub fn is_open(&self, index: usize) -> bool
+    {
+        self.slots[index].open
+    }
+
+    /// Get the socket in a slot.
+    pub fn get(&self, index: usize) -> Option<&Socket>
+    {
+        self.slots.get(index).and_then(|slot| slot.socket.as_ref())
+    }
+
+    /// Get the socket in a slot to change it.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Socket>
+    {
+        self.slots.get_mut(index).and_then(|slot| slot.socket.as_mut())
+    }
+
+    /// Get the TCP socket in a slot.
+    pub fn tcp(&self, index: usize) -> Option<&TcpSocket>
+    {
+        match self.get(index)
+        {
+            Some(Socket::Tcp(socket)) => Some(socket),
+            _                         => None
+        }
+    }
+
+    /// Get the TCP socket in a slot to change it.
+    pub fn tcp_mut(&mut self, index: usize) -> Option<&mut TcpSocket>
+    {
+        match self.get_mut(index)
+        {
+            Some(Socket::Tcp(socket)) => Some(socket),
+            _                         => None
+        }
+    }
+
+    /// Get the UDP socket in a slot.
+    pub fn udp(&self, index: usize) -> Option<&UdpSocket>
+    {
+        match self.get(index)
+        {
+            Some(Socket::Udp(socket)) => Some(socket),
+            _                         => None
+        }
+    }
+
+    /// Get the UDP socket in a slot to change it.
+    pub fn udp_mut(&mut self, index: usize) -> Option<&mut UdpSocket>
+    {
+        match self.get_mut(index)
+        {
+            Some(Socket::Udp(socket)) => Some(socket),
+            _                         => None
+        }
+    }
+
+    /// Is a socket of the kind bound to the port? Only one socket of a kind can be bound to a
+    /// port, except for the connections accepted from a listening socket which share its port.
+    pub fn port_in_use(&self, socket_type: SocketType, port: u16) -> bool
+    {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.socket.as_ref())
+            .any(|socket| socket.socket_type() == socket_type && socket.local().port == port)
+    }
+
+    /// Pick a port for a socket that wasn't bound to one.
+    pub fn ephemeral_port(&mut self, socket_type: SocketType) -> NetResult<u16>
+    {
+        let count = (EPHEMERAL_PORT_LAST - EPHEMERAL_PORT_FIRST) as usize + 1;
+
+        for _ in 0..count
+        {
+            let port = self.next_ephemeral_port;
+
+            self.next_ephemeral_port = match port
+                {
+                    EPHEMERAL_PORT_LAST => EPHEMERAL_PORT_FIRST,
+                    _                   => port + 1
+                };
+
+            if !self.port_in_use(socket_type, port)
+            {
+                return Ok(port);
+            }
+        }
+
+        Err(NetError::AddressInUse)
+    }
+}
+
+
+
+impl NetworkStack
+{
+    /// Is the address one of ours, or the unspecified address?
+    fn is_local_address(&self, address: Ipv4Address) -> bool
+    {
+        address.is_unspecified()
+            || self.interfaces
+                   .iter()
+                   .any(|interface| interface.address.is_some_and(|own| own.address == address))
+    }
+
+    /// Bind a socket to an address and port, picking a port if it's 0.
+    fn bind_socket(&mut self, index: usize, address: SocketAddress) -> NetResult<()>
+    {
+        if !self.is_local_address(address.address)
+        {
+            return Err(NetError::AddressNotAvailable);
+        }
+
+        let socket = self.sockets.get(index).ok_or(NetError::BadHandle)?;
+        let socket_type = socket.socket_type();
+
+        if socket.local().port != 0
+        {
+            return Err(NetError::InvalidArgument);
+        }
+
+        let port = match address.port
+            {
+                0    => self.sockets.ephemeral_port(socket_type)?,
+                port if self.sockets.port_in_use(socket_type, port) =>
+                    return Err(NetError::AddressInUse),
+                port => port
+            };
+
+        let local = SocketAddress::new(address.address, port);
+
+        match self.sockets.get_mut(index)
+        {
+            Some(Socket::Udp(socket)) => socket.local = local,
+            Some(Socket::Tcp(socket)) => socket.local = local,
+            None                      => return Err(NetError::BadHandle)
+        }
+
+        Ok(())
+    }
+
+    /// Bind a socket to an ephemeral port if it isn't bound yet.
+    pub fn bind_if_unbound(&mut self, index: usize) -> NetResult<()>
+    {
+        match self.sockets.get(index)
+        {
+            Some(socket) if socket.local().port == 0 =>
+                {
+                    let address = socket.local().address;
+
+                    self.bind_socket(index, SocketAddress::new(address, 0))
+                },
+
+            Some(_) => Ok(()),
+            None    => Err(NetError::BadHandle)
+        }
+    }
+}
+
+
+
+/// Create a new socket of the given kind.
+pub fn create_socket(socket_type: SocketType) -> NetResult<SocketHandle>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.allocate(|buffers|
+                {
+                    match socket_type
+                    {
+                        SocketType::Stream   => Socket::Tcp(TcpSocket::new(buffers)),
+                        SocketType::Datagram => Socket::Udp(UdpSocket::new(buffers))
+                    }
+                })?;
+
+            Ok(stack.sockets.handle(index))
+        })
+}
+
+
+/// Bind a socket to a local address and port. An unspecified address means any of ours, and port
+/// 0 means any free port.
+pub fn bind(handle: SocketHandle, address: SocketAddress) -> NetResult<()>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+
+            stack.bind_socket(index, address)
+        })
+}
+
+
+/// Have a stream socket listen for connections, holding up to `backlog` of them waiting to be
+/// accepted. A socket that isn't bound is given an ephemeral port.
+pub fn listen(handle: SocketHandle, backlog: usize) -> NetResult<()>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+
+            stack.bind_if_unbound(index)?;
+            stack.tcp_listen(index, backlog.clamp(1, MAX_BACKLOG))
+        })
+}
+
+
+/// Accept a connection made to a listening socket, returning the connection's socket and the
+/// address it came from.
+pub fn accept(handle: SocketHandle) -> NetResult<(SocketHandle, SocketAddress)>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+            let connection = stack.tcp_accept(index)?;
+            let remote = stack.sockets.tcp(connection).map_or(SocketAddress::UNSPECIFIED,
+                                                               |socket| socket.remote);
+
+            Ok((stack.sockets.handle(connection), remote))
+        })
+}
+
+
+/// Connect a socket to a remote address. A stream socket starts making the connection and fails
+/// with `NetError::InProgress` until it's made, calling again says how it went. A datagram socket
+/// just remembers the address, for `send` and for filtering what it receives.
+pub fn connect(handle: SocketHandle, address: SocketAddress) -> NetResult<()>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+
+            if    address.address.is_unspecified()
+               || address.port == 0
+            {
+                return Err(NetError::InvalidArgument);
+            }
+
+            match stack.sockets.get(index)
+            {
+                Some(Socket::Tcp(_)) => stack.tcp_connect(index, address),
+
+                Some(Socket::Udp(_)) =>
+                    {
+                        stack.bind_if_unbound(index)?;
+
+                        if let Some(socket) = stack.sockets.udp_mut(index)
+                        {
+                            socket.remote = Some(address);
+                        }
+
+                        Ok(())
+                    },
+
+                None => Err(NetError::BadHandle)
+            }
+        })
+}
+
+
+/// Send data on a connected socket, returning how much was taken. A stream socket takes as much
+/// as fits in its send buffer, a datagram socket sends all of the data as one datagram.
+pub fn send(handle: SocketHandle, data: &[u8]) -> NetResult<usize>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+
+            match stack.sockets.get(index)
+            {
+                Some(Socket::Tcp(_)) => stack.tcp_send(index, data),
+
+                Some(Socket::Udp(socket)) =>
+                    {
+                        let remote = socket.remote.ok_or(NetError::NotConnected)?;
+
+                        stack.udp_send(index, remote, data)
+                    },
+
+                None => Err(NetError::BadHandle)
+            }
+        })
+}
+
+
+/// Send a datagram to an address. On a stream socket the address is ignored and this is the same
+/// as `send`.
+pub fn send_to(handle: SocketHandle, data: &[u8], address: SocketAddress) -> NetResult<usize>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+
+            match stack.sockets.get(index)
+            {
+                Some(Socket::Tcp(_)) => stack.tcp_send(index, data),
+                Some(Socket::Udp(_)) => stack.udp_send(index, address, data),
+                None                 => Err(NetError::BadHandle)
+            }
+        })
+}
+
+
+/// Receive data from a socket into the buffer, returning how much was received. For a stream
+/// socket 0 means the other end has finished sending. A datagram that doesn't fit in the buffer
+/// is cut short.
+pub fn receive(handle: SocketHandle, buffer: &mut [u8]) -> NetResult<usize>
+{
+    receive_from(handle, buffer).map(|(size, _)| size)
+}
+
+
+/// Receive data from a socket into the buffer, returning how much was received and where it came
+/// from.
+pub fn receive_from(handle: SocketHandle, buffer: &mut [u8]) -> NetResult<(usize, SocketAddress)>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+
+            match stack.sockets.get(index)
+            {
+                Some(Socket::Tcp(socket)) =>
+                    {
+                        let remote = socket.remote;
+
+                        Ok((stack.tcp_receive(index, buffer)?, remote))
+                    },
+
+                Some(Socket::Udp(_)) => stack.udp_receive(index, buffer),
+                None                 => Err(NetError::BadHandle)
+            }
+        })
+}
+
+
+/// Shut down one or both directions of a socket. Shutting down the sending side of a connection
+/// tells the other end that no more data is coming.
+pub fn shutdown(handle: SocketHandle, how: Shutdown) -> NetResult<()>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+
+            match stack.sockets.get_mut(index)
+            {
+                Some(Socket::Tcp(_)) => stack.tcp_shutdown(index, how),
+
+                Some(Socket::Udp(socket)) =>
+                    {
+                        socket.shutdown(how);
+                        Ok(())
+                    },
+
+                None => Err(NetError::BadHandle)
+            }
+        })
+}
+
+
+/// Close a socket. The handle stops working right away, but a connection is still closed properly,
+/// with the data already sent delivered first.
+pub fn close(handle: SocketHandle) -> NetResult<()>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+
+            match stack.sockets.get(index)
+            {
+                Some(Socket::Tcp(_)) => stack.tcp_close(index),
+                _                    => stack.sockets.free(index)
+            }
+
+            Ok(())
+        })
+}
+
+
+/// Get the local address and port a socket is bound to.
+pub fn local_address(handle: SocketHandle) -> NetResult<SocketAddress>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+
+            stack.sockets.get(index).map(|socket| socket.local()).ok_or(NetError::BadHandle)
+        })
+}
+
+
+/// Get the address and port a socket is connected to.
+pub fn peer_address(handle: SocketHandle) -> NetResult<SocketAddress>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+
+            match stack.sockets.get(index)
+            {
+                Some(Socket::Tcp(socket)) if !matches!(socket.state,
+                                                       TcpState::Closed | TcpState::Listen) =>
+                    Ok(socket.remote),
+                Some(Socket::Udp(socket)) => socket.remote.ok_or(NetError::NotConnected),
+                Some(_)                   => Err(NetError::NotConnected),
+                None                      => Err(NetError::BadHandle)
+            }
+        })
+}
+
+
+/// Find out what a socket is ready for.
+pub fn poll_socket(handle: SocketHandle) -> NetResult<SocketEvents>
+{
+    with_network_stack(|stack|
+        {
+            let index = stack.sockets.lookup(handle)?;
+
+            match stack.sockets.get(index)
+            {
+                Some(Socket::Tcp(_))      => Ok(stack.tcp_events(index)),
+                Some(Socket::Udp(socket)) => Ok(socket.events()),
+                None                      => Err(NetError::BadHandle)
+            }
+        })
+}
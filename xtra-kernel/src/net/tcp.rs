@@ -0,0 +1,1593 @@
+// TCP, reliable ordered byte streams. The connection state machine follows RFC 9293, the
+// retransmission timer RFC 6298 and the congestion control, slow start, congestion avoidance and
+// fast retransmit, RFC 5681.
+//
+// A socket's send buffer holds the data from the oldest byte not yet acknowledged on, so that
+// whatever is lost can be sent again, and its receive buffer the data waiting to be received. The
+// window we advertise is the room left in the receive buffer, and we never send more than the
+// other end's window, or the congestion window, allows.
+//
+// To keep things simple segments that arrive out of order are dropped rather than kept, the other
+// end sends them again once it has sent what was missing. Nor are window scaling, selective
+// acknowledgements or timestamps supported.
+
+use core::fmt::{ self, Display, Formatter };
+
+use crate::{ arch::{ csr::read_time, interrupts::with_interrupts_disabled },
+             locking::spin_mutex::SpinMutex,
+             net::{ ipv4::{ Ipv4Packet, IPV4_HEADER_SIZE, IP_PROTOCOL_TCP },
+                    socket::{ Shutdown, Socket, SocketAddress, SocketBuffers, SocketEvents },
+                    NetError,
+                    NetResult,
+                    NetworkStack },
+             random::{ chacha20::{ block, key_from_bytes, Key, KEY_WORDS }, get_random_bytes },
+             timers::current_milliseconds };
+
+
+
+/// The size of the TCP header without options.
+pub const TCP_HEADER_SIZE: usize = 20;
+
+// The header's flags.
+const FLAG_FIN: u8 = 0x01;
+const FLAG_SYN: u8 = 0x02;
+const FLAG_RST: u8 = 0x04;
+const FLAG_PSH: u8 = 0x08;
+const FLAG_ACK: u8 = 0x10;
+
+// The only option we send or look at, the maximum segment size.
+const OPTION_END:      u8    = 0;
+const OPTION_NOP:      u8    = 1;
+const OPTION_MSS:      u8    = 2;
+const OPTION_MSS_SIZE: usize = 4;
+
+/// Where the checksum is in the header.
+const TCP_CHECKSUM_FIELD: usize = 16;
+
+/// The segment size assumed when the other end doesn't say.
+const DEFAULT_MSS: usize = 536;
+
+/// The largest segment we send or ask for, what fits in a standard Ethernet frame.
+pub const MAX_SEGMENT_SIZE: usize = 1460;
+
+/// The largest window that can be advertised without window scaling.
+const MAX_WINDOW: usize = 65535;
+
+// The retransmission timeout, in milliseconds.
+const INITIAL_RTO_MS: u64 = 1000;
+const MIN_RTO_MS:     u64 = 200;
+const MAX_RTO_MS:     u64 = 60_000;
+
+/// How many times a segment is sent again before the connection is given up on.
+const MAX_RETRANSMISSIONS: u32 = 8;
+
+/// How many times a SYN is sent again before the connection attempt is given up on.
+const MAX_SYN_RETRANSMISSIONS: u32 = 5;
+
+/// How long a closed connection lingers in TIME-WAIT, in milliseconds. This is shorter than the
+/// usual two minutes so that the socket slots are freed sooner.
+const TIME_WAIT_MS: u64 = 30_000;
+
+/// How many duplicate acknowledgements mean a segment was lost.
+const DUPLICATE_ACK_THRESHOLD: u32 = 3;
+
+
+
+/// The secret key for the hash in the initial sequence numbers.
+static SEQUENCE_NUMBER_KEY: SpinMutex<Option<Key>> = SpinMutex::new(None);
+
+
+
+/// The states of a TCP connection.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TcpState
+{
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait
+}
+
+
+
+impl Display for TcpState
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        let name = match self
+            {
+                TcpState::Closed      => "CLOSED",
+                TcpState::Listen      => "LISTEN",
+                TcpState::SynSent     => "SYN-SENT",
+                TcpState::SynReceived => "SYN-RECEIVED",
+                TcpState::Established => "ESTABLISHED",
+                TcpState::FinWait1    => "FIN-WAIT-1",
+                TcpState::FinWait2    => "FIN-WAIT-2",
+                TcpState::CloseWait   => "CLOSE-WAIT",
+                TcpState::Closing     => "CLOSING",
+                TcpState::LastAck     => "LAST-ACK",
+                TcpState::TimeWait    => "TIME-WAIT"
+            };
+
+        write!(formatter, "{}", name)
+    }
+}
+
+
+
+/// Is sequence number a before b?
+fn sequence_less(a: u32, b: u32) -> bool
+{
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+
+/// Is sequence number a before or the same as b?
+fn sequence_less_equal(a: u32, b: u32) -> bool
+{
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+
+/// Is the sequence number in the window starting at start of the given size?
+fn sequence_in_window(sequence: u32, start: u32, size: usize) -> bool
+{
+    (sequence.wrapping_sub(start) as usize) < size
+}
+
+
+
+/// The fields of a received segment's header.
+struct TcpHeader
+{
+    source_port: u16,
+    destination_port: u16,
+    sequence: u32,
+    acknowledgment: u32,
+    flags: u8,
+    window: u16,
+
+    /// The maximum segment size option, if it was there.
+    maximum_segment_size: Option<u16>
+}
+
+
+
+impl TcpHeader
+{
+    /// Parse a segment into its header and data.
+    fn parse(segment: &[u8]) -> Option<(TcpHeader, &[u8])>
+    {
+        if segment.len() < TCP_HEADER_SIZE
+        {
+            return None;
+        }
+
+        let header_size = (segment[12] >> 4) as usize * 4;
+
+        if    header_size < TCP_HEADER_SIZE
+           || header_size > segment.len()
+        {
+            return None;
+        }
+
+        let mut maximum_segment_size = None;
+        let mut options = &segment[TCP_HEADER_SIZE..header_size];
+
+        while let [ kind, rest @ .. ] = options
+        {
+            match *kind
+            {
+                OPTION_END => break,
+                OPTION_NOP => options = rest,
+
+                _ =>
+                    {
+                        let size = *rest.first()? as usize;
+
+                        if    size < 2
+                           || size > options.len()
+                        {
+                            return None;
+                        }
+
+                        if    *kind == OPTION_MSS
+                           && size == OPTION_MSS_SIZE
+                        {
+                            maximum_segment_size = Some(u16::from_be_bytes([ options[2],
+                                                                             options[3] ]));
+                        }
+
+                        options = &options[size..];
+                    }
+            }
+        }
+
+        let header = TcpHeader
+            {
+                source_port: u16::from_be_bytes([ segment[0], segment[1] ]),
+                destination_port: u16::from_be_bytes([ segment[2], segment[3] ]),
+                sequence: u32::from_be_bytes([ segment[4], segment[5], segment[6], segment[7] ]),
+                acknowledgment: u32::from_be_bytes([ segment[8], segment[9],
+                                                     segment[10], segment[11] ]),
+                flags: segment[13],
+                window: u16::from_be_bytes([ segment[14], segment[15] ]),
+                maximum_segment_size
+            };
+
+        Some((header, &segment[header_size..]))
+    }
+
+    /// Is the flag set?
+    fn has(&self, flag: u8) -> bool
+    {
+        self.flags & flag != 0
+    }
+}
+
+
+
+/// A TCP socket, and the connection it's making or has made.
+pub struct TcpSocket
+{
+    /// Where the connection is.
+    pub state: TcpState,
+
+    /// The two ends of the connection.
+    pub local: SocketAddress,
+    pub remote: SocketAddress,
+
+    /// For a connection made to a listening socket that hasn't been accepted yet, the listening
+    /// socket's slot.
+    listener: Option<usize>,
+
+    /// How many connections a listening socket holds waiting to be accepted.
+    backlog: usize,
+
+    /// The error to report, for a connection that was refused, reset or timed out.
+    error: Option<NetError>,
+
+    buffers: SocketBuffers,
+
+    /// Our first sequence number, the oldest one not acknowledged yet and the next one to send.
+    initial_send_sequence: u32,
+    send_unacknowledged: u32,
+    send_next: u32,
+
+    /// The highest sequence number sent so far, and was it the FIN? After a retransmission timeout
+    /// sending starts again from the oldest unacknowledged byte, but what was sent before may
+    /// still be acknowledged.
+    send_maximum: u32,
+    fin_at_maximum: bool,
+
+    /// The other end's window, and the segment that last updated it.
+    send_window: usize,
+    send_window_sequence: u32,
+    send_window_acknowledgment: u32,
+
+    /// The largest segments we send, and the largest we ask to be sent.
+    send_mss: usize,
+    receive_mss: usize,
+
+    /// Has sending been shut down, and has the FIN saying so been sent?
+    fin_queued: bool,
+    fin_sent: bool,
+
+    /// The next sequence number we expect to receive.
+    receive_next: u32,
+
+    /// Has the other end finished sending? Has receiving been shut down?
+    fin_received: bool,
+    read_shutdown: bool,
+
+    /// The window we last advertised.
+    advertised_window: usize,
+
+    /// Is an acknowledgement owed to the other end?
+    ack_pending: bool,
+
+    /// When the retransmission timer goes off, and how many times it has in a row.
+    retransmit_at: Option<u64>,
+    retransmissions: u32,
+
+    /// The retransmission timeout and the round trip time estimates it's worked out from.
+    rto: u64,
+    smoothed_rtt: Option<u64>,
+    rtt_variance: u64,
+
+    /// The segment being timed, the sequence number acknowledging it and when it was sent.
+    rtt_sample: Option<(u32, u64)>,
+
+    /// The congestion control state.
+    congestion_window: usize,
+    slow_start_threshold: usize,
+    duplicate_acks: u32,
+
+    /// Is a probe being sent into a closed window?
+    probing_window: bool,
+
+    /// When a connection in TIME-WAIT is done with.
+    time_wait_until: u64
+}
+
+
+
+impl TcpSocket
+{
+    /// Create a closed socket using the given buffers.
+    pub fn new(buffers: SocketBuffers) -> TcpSocket
+    {
+        TcpSocket
+            {
+                state: TcpState::Closed,
+                local: SocketAddress::UNSPECIFIED,
+                remote: SocketAddress::UNSPECIFIED,
+                listener: None,
+                backlog: 0,
+                error: None,
+                buffers,
+                initial_send_sequence: 0,
+                send_unacknowledged: 0,
+                send_next: 0,
+                send_maximum: 0,
+                fin_at_maximum: false,
+                send_window: 0,
+                send_window_sequence: 0,
+                send_window_acknowledgment: 0,
+                send_mss: DEFAULT_MSS,
+                receive_mss: DEFAULT_MSS,
+                fin_queued: false,
+                fin_sent: false,
+                receive_next: 0,
+                fin_received: false,
+                read_shutdown: false,
+                advertised_window: 0,
+                ack_pending: false,
+                retransmit_at: None,
+                retransmissions: 0,
+                rto: INITIAL_RTO_MS,
+                smoothed_rtt: None,
+                rtt_variance: 0,
+                rtt_sample: None,
+                congestion_window: 0,
+                slow_start_threshold: MAX_WINDOW,
+                duplicate_acks: 0,
+                probing_window: false,
+                time_wait_until: 0
+            }
+    }
+
+    /// Give up the socket's buffers.
+    pub fn into_buffers(self) -> SocketBuffers
+    {
+        self.buffers
+    }
+
+    /// Set up the sequence numbers and segment sizes for a new connection.
+    fn start_connection(&mut self, now: u64, mtu: usize)
+    {
+        let sequence = initial_sequence_number(self.local, self.remote);
+
+        self.initial_send_sequence = sequence;
+        self.send_unacknowledged = sequence;
+        self.send_next = sequence;
+        self.send_maximum = sequence;
+        self.receive_mss = mtu.saturating_sub(IPV4_HEADER_SIZE + TCP_HEADER_SIZE)
+                              .min(MAX_SEGMENT_SIZE);
+        self.send_mss = self.receive_mss.min(DEFAULT_MSS);
+        self.congestion_window = 2 * self.send_mss;
+        self.rto = INITIAL_RTO_MS;
+        self.time_wait_until = now;
+    }
+
+    /// Take the segment size the other end asked for from its SYN.
+    fn set_peer_mss(&mut self, header: &TcpHeader)
+    {
+        let peer_mss = header.maximum_segment_size.map_or(DEFAULT_MSS, |mss| mss as usize);
+
+        self.send_mss = peer_mss.clamp(1, self.receive_mss.max(1));
+        self.congestion_window = 2 * self.send_mss;
+    }
+
+    /// Is the connection established, with data able to flow?
+    fn is_synchronized(&self) -> bool
+    {
+        !matches!(self.state, TcpState::Closed
+                              | TcpState::Listen
+                              | TcpState::SynSent
+                              | TcpState::SynReceived)
+    }
+
+    /// The window to advertise, the room in the receive buffer.
+    fn receive_window(&self) -> usize
+    {
+        self.buffers.receive.free_space().min(MAX_WINDOW)
+    }
+
+    /// How many bytes of the send buffer have been sent.
+    fn bytes_sent(&self) -> usize
+    {
+        if !self.is_synchronized()
+        {
+            return 0;
+        }
+
+        let outstanding = self.send_next.wrapping_sub(self.send_unacknowledged) as usize;
+
+        outstanding.saturating_sub(self.fin_sent as usize).min(self.buffers.send.len())
+    }
+
+    /// How many sequence numbers have been sent and not acknowledged.
+    fn bytes_in_flight(&self) -> usize
+    {
+        self.send_next.wrapping_sub(self.send_unacknowledged) as usize
+    }
+
+    /// Has our FIN been acknowledged?
+    fn fin_acknowledged(&self) -> bool
+    {
+        self.fin_sent && self.send_unacknowledged == self.send_next
+    }
+
+    /// Take a new round trip time measurement into the estimates.
+    fn update_rtt(&mut self, rtt: u64)
+    {
+        match self.smoothed_rtt
+        {
+            None =>
+                {
+                    self.smoothed_rtt = Some(rtt);
+                    self.rtt_variance = rtt / 2;
+                },
+
+            Some(smoothed_rtt) =>
+                {
+                    self.rtt_variance = (3 * self.rtt_variance + smoothed_rtt.abs_diff(rtt)) / 4;
+                    self.smoothed_rtt = Some((7 * smoothed_rtt + rtt) / 8);
+                }
+        }
+
+        let smoothed_rtt = self.smoothed_rtt.unwrap_or(rtt);
+
+        self.rto = (smoothed_rtt + (4 * self.rtt_variance).max(1)).clamp(MIN_RTO_MS, MAX_RTO_MS);
+    }
+}
+
+
+
+/// Pick the first sequence number for a connection, as RFC 6528 has it. It's based on the time, so
+/// that the sequence numbers of successive connections between the same ends move forward, plus a
+/// keyed hash of the ends so that nobody else can guess where they start.
+fn initial_sequence_number(local: SocketAddress, remote: SocketAddress) -> u32
+{
+    // The hash is a block of ChaCha20 keystream, with the addresses as its counter and the ports
+    // as its nonce, under a key picked the first time it's needed.
+    let key = with_interrupts_disabled(||
+        {
+            *SEQUENCE_NUMBER_KEY.lock().get_or_insert_with(||
+                {
+                    let mut bytes = [0u8; KEY_WORDS * 4];
+
+                    get_random_bytes(&mut bytes);
+                    key_from_bytes(&bytes)
+                })
+        });
+
+    let addresses = (local.address.to_bits() as u64) << 32 | remote.address.to_bits() as u64;
+    let ports = (local.port as u64) << 16 | remote.port as u64;
+    let hash = block(&key, addresses, ports);
+
+    (read_time() as u32).wrapping_add(u32::from_le_bytes([ hash[0], hash[1], hash[2], hash[3] ]))
+}
+
+
+
+impl NetworkStack
+{
+    /// Find the socket a segment is for, the connection between the two ends if there is one,
+    /// otherwise a socket listening on the port.
+    fn find_tcp_socket(&self, local: SocketAddress, remote: SocketAddress) -> Option<usize>
+    {
+        let sockets = &self.sockets;
+
+        (0..sockets.len())
+            .find(|&index| sockets.tcp(index).is_some_and(|socket|
+                {
+                    !matches!(socket.state, TcpState::Closed | TcpState::Listen)
+                        && socket.local == local
+                        && socket.remote == remote
+                }))
+            .or_else(|| (0..sockets.len()).find(|&index| sockets.tcp(index).is_some_and(|socket|
+                {
+                    socket.state == TcpState::Listen
+                        && socket.local.port == local.port
+                        && (   socket.local.address.is_unspecified()
+                            || socket.local.address == local.address)
+                })))
+    }
+
+    /// Handle a received TCP segment.
+    pub fn handle_tcp(&mut self, packet: &Ipv4Packet)
+    {
+        if    packet.broadcast
+           || !packet.transport_checksum_ok()
+        {
+            return;
+        }
+
+        let Some((header, data)) = TcpHeader::parse(packet.payload) else { return };
+
+        let local = SocketAddress::new(packet.destination, header.destination_port);
+        let remote = SocketAddress::new(packet.source, header.source_port);
+        let now = current_milliseconds();
+
+        let Some(index) = self.find_tcp_socket(local, remote)
+        else
+        {
+            self.send_reset_for(local, remote, &header, data.len());
+            return;
+        };
+
+        match self.sockets.tcp(index).map(|socket| socket.state)
+        {
+            Some(TcpState::Listen)  => self.tcp_listen_arrives(index, packet, &header, local, now),
+            Some(TcpState::SynSent) => self.tcp_syn_sent_arrives(index, &header, local, now),
+            Some(_)                 => self.tcp_segment_arrives(index, &header, data, local, now),
+            None                    => {}
+        }
+    }
+
+    /// Handle a segment for a listening socket, a SYN starts a new connection.
+    fn tcp_listen_arrives(&mut self,
+                          index: usize,
+                          packet: &Ipv4Packet,
+                          header: &TcpHeader,
+                          local: SocketAddress,
+                          now: u64)
+    {
+        let remote = SocketAddress::new(packet.source, header.source_port);
+
+        if header.has(FLAG_RST)
+        {
+            return;
+        }
+
+        if header.has(FLAG_ACK)
+        {
+            self.send_reset_for(local, remote, header, 0);
+            return;
+        }
+
+        if !header.has(FLAG_SYN)
+        {
+            return;
+        }
+
+        // Once the backlog is full new connections are ignored, their SYNs are sent again and may
+        // find room later.
+        let backlog = self.sockets.tcp(index).map_or(0, |socket| socket.backlog);
+        let waiting = (0..self.sockets.len())
+            .filter(|&child| self.sockets.tcp(child).is_some_and(|socket| {
+                socket.listener == Some(index)
+            }))
+            .count();
+
+        if waiting >= backlog
+        {
+            return;
+        }
+
+        let Ok(child) = self.sockets.allocate(|buffers| Socket::Tcp(TcpSocket::new(buffers)))
+        else
+        {
+            return;
+        };
+
+        let mtu = self.interfaces[packet.interface].mtu;
+
+        if let Some(socket) = self.sockets.tcp_mut(child)
+        {
+            socket.local = local;
+            socket.remote = remote;
+            socket.listener = Some(index);
+            socket.state = TcpState::SynReceived;
+            socket.start_connection(now, mtu);
+            socket.set_peer_mss(header);
+            socket.receive_next = header.sequence.wrapping_add(1);
+            socket.send_window = header.window as usize;
+            socket.send_window_sequence = header.sequence;
+            socket.send_window_acknowledgment = socket.initial_send_sequence;
+        }
+
+        self.tcp_output(child, now);
+    }
+
+    /// Handle a segment for a socket that has sent a SYN, hopefully the SYN-ACK answering it.
+    fn tcp_syn_sent_arrives(&mut self,
+                            index: usize,
+                            header: &TcpHeader,
+                            local: SocketAddress,
+                            now: u64)
+    {
+        let Some(socket) = self.sockets.tcp_mut(index) else { return };
+
+        let remote = socket.remote;
+
+        if    header.has(FLAG_ACK)
+           && header.acknowledgment != socket.send_next
+        {
+            if !header.has(FLAG_RST)
+            {
+                self.send_reset_for(local, remote, header, 0);
+            }
+
+            return;
+        }
+
+        if header.has(FLAG_RST)
+        {
+            if header.has(FLAG_ACK)
+            {
+                self.tcp_abort(index, NetError::ConnectionRefused);
+            }
+
+            return;
+        }
+
+        if !header.has(FLAG_SYN)
+        {
+            return;
+        }
+
+        socket.receive_next = header.sequence.wrapping_add(1);
+        socket.set_peer_mss(header);
+
+        if header.has(FLAG_ACK)
+        {
+            socket.state = TcpState::Established;
+            socket.send_unacknowledged = header.acknowledgment;
+            socket.send_window = header.window as usize;
+            socket.send_window_sequence = header.sequence;
+            socket.send_window_acknowledgment = header.acknowledgment;
+            socket.retransmit_at = None;
+            socket.retransmissions = 0;
+
+            if let Some((_, sent_at)) = socket.rtt_sample.take()
+            {
+                socket.update_rtt(now.saturating_sub(sent_at));
+            }
+
+            socket.ack_pending = true;
+        }
+        else
+        {
+            // Both ends opened the connection at once, the SYN is sent again along with the ACK.
+            socket.state = TcpState::SynReceived;
+            socket.send_next = socket.initial_send_sequence;
+        }
+
+        self.tcp_output(index, now);
+    }
+
+    /// Handle a segment for a connection that's past its SYN.
+    fn tcp_segment_arrives(&mut self,
+                           index: usize,
+                           header: &TcpHeader,
+                           data: &[u8],
+                           local: SocketAddress,
+                           now: u64)
+    {
+        let orphaned = !self.sockets.is_open(index);
+        let Some(socket) = self.sockets.tcp_mut(index) else { return };
+
+        let remote = socket.remote;
+        let window = socket.receive_window();
+        let sequence = header.sequence;
+        let segment_length = data.len()
+                             + header.has(FLAG_SYN) as usize
+                             + header.has(FLAG_FIN) as usize;
+
+        // Segments outside of the window are old duplicates, or something worse. They're
+        // answered with an ACK saying where we really are.
+        let acceptable = match (segment_length, window)
+            {
+                (0, 0) => sequence == socket.receive_next,
+                (0, _) => sequence_in_window(sequence, socket.receive_next, window),
+                (_, 0) => sequence == socket.receive_next,
+                (_, _) =>    sequence_in_window(sequence, socket.receive_next, window)
+                          || sequence_in_window(sequence.wrapping_add(segment_length as u32 - 1),
+                                                socket.receive_next,
+                                                window)
+            };
+
+        if !acceptable
+        {
+            if !header.has(FLAG_RST)
+            {
+                self.tcp_send_ack(index);
+            }
+
+            return;
+        }
+
+        if header.has(FLAG_RST)
+        {
+            match socket.state
+            {
+                // A connection that was never accepted just goes away.
+                TcpState::SynReceived if socket.listener.is_some() => self.sockets.free(index),
+
+                TcpState::Closing | TcpState::LastAck | TcpState::TimeWait =>
+                    self.tcp_finish(index),
+
+                _ => self.tcp_abort(index, NetError::ConnectionReset)
+            }
+
+            return;
+        }
+
+        if header.has(FLAG_SYN)
+        {
+            self.send_reset_for(local, remote, header, data.len());
+            self.tcp_abort(index, NetError::ConnectionReset);
+            return;
+        }
+
+        if !header.has(FLAG_ACK)
+        {
+            return;
+        }
+
+        let acknowledgment = header.acknowledgment;
+
+        if socket.state == TcpState::SynReceived
+        {
+            if    sequence_less(socket.send_unacknowledged, acknowledgment)
+               && sequence_less_equal(acknowledgment, socket.send_next)
+            {
+                socket.state = TcpState::Established;
+                socket.send_unacknowledged = socket.initial_send_sequence.wrapping_add(1);
+                socket.send_window = header.window as usize;
+                socket.send_window_sequence = sequence;
+                socket.send_window_acknowledgment = acknowledgment;
+                socket.retransmit_at = None;
+                socket.retransmissions = 0;
+
+                if let Some((_, sent_at)) = socket.rtt_sample.take()
+                {
+                    socket.update_rtt(now.saturating_sub(sent_at));
+                }
+            }
+            else
+            {
+                self.send_reset_for(local, remote, header, data.len());
+                return;
+            }
+        }
+
+        // An ACK for something we haven't sent yet.
+        if sequence_less(socket.send_maximum, acknowledgment)
+        {
+            self.tcp_send_ack(index);
+            return;
+        }
+
+        // Something sent before a retransmission timeout was received after all, sending carries
+        // on from there.
+        if sequence_less(socket.send_next, acknowledgment)
+        {
+            socket.send_next = acknowledgment;
+            socket.fin_sent =    socket.fin_at_maximum
+                              && acknowledgment == socket.send_maximum;
+        }
+
+        if sequence_less(socket.send_unacknowledged, acknowledgment)
+        {
+            let acknowledged = acknowledgment.wrapping_sub(socket.send_unacknowledged) as usize;
+            let fin_acknowledged = socket.fin_sent && acknowledgment == socket.send_next;
+            let data_acknowledged = acknowledged - fin_acknowledged as usize;
+
+            socket.buffers.send.discard(data_acknowledged);
+            socket.send_unacknowledged = acknowledgment;
+
+            let sample = socket.rtt_sample
+                               .filter(|&(timed, _)| sequence_less_equal(timed, acknowledgment));
+
+            if let Some((_, sent_at)) = sample
+            {
+                socket.rtt_sample = None;
+                socket.update_rtt(now.saturating_sub(sent_at));
+            }
+
+            // Grow the congestion window, quickly at first and then by about a segment each round
+            // trip. Coming out of a fast retransmit it goes back to the threshold.
+            if socket.duplicate_acks >= DUPLICATE_ACK_THRESHOLD
+            {
+                socket.congestion_window = socket.slow_start_threshold;
+            }
+            else if socket.congestion_window < socket.slow_start_threshold
+            {
+                socket.congestion_window += data_acknowledged.min(socket.send_mss);
+            }
+            else
+            {
+                socket.congestion_window += (socket.send_mss * socket.send_mss
+                                             / socket.congestion_window.max(1)).max(1);
+            }
+
+            socket.duplicate_acks = 0;
+            socket.retransmissions = 0;
+            socket.retransmit_at = match socket.bytes_in_flight()
+                {
+                    0 => None,
+                    _ => Some(now + socket.rto)
+                };
+        }
+        else if    acknowledgment == socket.send_unacknowledged
+                && data.is_empty()
+                && !header.has(FLAG_FIN)
+                && header.window as usize == socket.send_window
+                && socket.bytes_in_flight() > 0
+        {
+            socket.duplicate_acks += 1;
+
+            if socket.duplicate_acks == DUPLICATE_ACK_THRESHOLD
+            {
+                let in_flight = socket.bytes_in_flight();
+
+                socket.slow_start_threshold = (in_flight / 2).max(2 * socket.send_mss);
+                socket.congestion_window = socket.slow_start_threshold
+                                           + DUPLICATE_ACK_THRESHOLD as usize * socket.send_mss;
+
+                let length = socket.bytes_sent().min(socket.send_mss);
+                let retransmit = socket.send_unacknowledged;
+
+                if length > 0
+                {
+                    self.tcp_send_segment(index, retransmit, FLAG_ACK | FLAG_PSH, 0, length);
+                }
+            }
+            else if socket.duplicate_acks > DUPLICATE_ACK_THRESHOLD
+            {
+                socket.congestion_window += socket.send_mss;
+            }
+        }
+
+        let Some(socket) = self.sockets.tcp_mut(index) else { return };
+
+        if    sequence_less(socket.send_window_sequence, sequence)
+           || (   socket.send_window_sequence == sequence
+               && sequence_less_equal(socket.send_window_acknowledgment, acknowledgment))
+        {
+            socket.send_window = header.window as usize;
+            socket.send_window_sequence = sequence;
+            socket.send_window_acknowledgment = acknowledgment;
+        }
+
+        let fin_acknowledged = socket.fin_acknowledged();
+
+        match socket.state
+        {
+            TcpState::FinWait1 if fin_acknowledged => socket.state = TcpState::FinWait2,
+
+            TcpState::Closing if fin_acknowledged =>
+                {
+                    socket.state = TcpState::TimeWait;
+                    socket.time_wait_until = now + TIME_WAIT_MS;
+                },
+
+            TcpState::LastAck if fin_acknowledged =>
+                {
+                    self.tcp_finish(index);
+                    return;
+                },
+
+            _ => {}
+        }
+
+        // Take the data, as much of it as there's room for. Anything repeated from before is
+        // skipped, and anything after a gap is dropped to be sent again.
+        if    !data.is_empty()
+           && matches!(socket.state, TcpState::Established
+                                     | TcpState::FinWait1
+                                     | TcpState::FinWait2)
+        {
+            // Nobody is left to read data sent after the socket was closed.
+            if orphaned
+            {
+                self.send_reset_for(local, remote, header, data.len());
+                self.tcp_finish(index);
+                return;
+            }
+
+            socket.ack_pending = true;
+
+            if sequence_less_equal(sequence, socket.receive_next)
+            {
+                let skip = socket.receive_next.wrapping_sub(sequence) as usize;
+
+                if skip < data.len()
+                {
+                    let accepted = match socket.read_shutdown
+                        {
+                            true  => data.len() - skip,
+                            false => socket.buffers.receive.push_slice(&data[skip..])
+                        };
+
+                    socket.receive_next = socket.receive_next.wrapping_add(accepted as u32);
+                }
+            }
+        }
+
+        // The FIN only counts once all of the data before it has been taken.
+        if    header.has(FLAG_FIN)
+           && sequence.wrapping_add(data.len() as u32) == socket.receive_next
+           && !socket.fin_received
+        {
+            socket.receive_next = socket.receive_next.wrapping_add(1);
+            socket.fin_received = true;
+            socket.ack_pending = true;
+
+            match socket.state
+            {
+                TcpState::Established => socket.state = TcpState::CloseWait,
+
+                TcpState::FinWait1 if fin_acknowledged =>
+                    {
+                        socket.state = TcpState::TimeWait;
+                        socket.time_wait_until = now + TIME_WAIT_MS;
+                    },
+
+                TcpState::FinWait1 => socket.state = TcpState::Closing,
+
+                TcpState::FinWait2 =>
+                    {
+                        socket.state = TcpState::TimeWait;
+                        socket.time_wait_until = now + TIME_WAIT_MS;
+                    },
+
+                _ => {}
+            }
+        }
+
+        self.tcp_output(index, now);
+
+        if self.sockets.tcp(index).is_some_and(|socket| socket.ack_pending)
+        {
+            self.tcp_send_ack(index);
+        }
+    }
+
+    /// Send whatever the socket has that the windows allow, its SYN, data or FIN.
+    fn tcp_output(&mut self, index: usize, now: u64)
+    {
+        loop
+        {
+            let Some(socket) = self.sockets.tcp_mut(index) else { return };
+
+            match socket.state
+            {
+                TcpState::SynSent | TcpState::SynReceived =>
+                    {
+                        if socket.send_next == socket.initial_send_sequence
+                        {
+                            let flags = match socket.state
+                                {
+                                    TcpState::SynSent => FLAG_SYN,
+                                    _                 => FLAG_SYN | FLAG_ACK
+                                };
+
+                            let sequence = socket.initial_send_sequence;
+
+                            socket.send_next = sequence.wrapping_add(1);
+                            socket.send_maximum = socket.send_next;
+                            socket.retransmit_at.get_or_insert(now + socket.rto);
+
+                            if socket.retransmissions == 0
+                            {
+                                socket.rtt_sample = Some((socket.send_next, now));
+                            }
+
+                            self.tcp_send_segment(index, sequence, flags, 0, 0);
+                        }
+
+                        return;
+                    },
+
+                TcpState::Established
+                | TcpState::CloseWait
+                | TcpState::FinWait1
+                | TcpState::Closing
+                | TcpState::LastAck => {},
+
+                _ => return
+            }
+
+            let sent = socket.bytes_sent();
+            let buffered = socket.buffers.send.len();
+            let unsent = buffered - sent;
+            let in_flight = socket.bytes_in_flight();
+
+            let window = match socket.probing_window
+                {
+                    true  => socket.send_window.max(1),
+                    false => socket.send_window.min(socket.congestion_window)
+                };
+
+            let length = unsent.min(socket.send_mss)
+                               .min(window.saturating_sub(in_flight))
+                               .min(MAX_SEGMENT_SIZE);
+
+            let send_fin =    socket.fin_queued
+                           && !socket.fin_sent
+                           && sent + length == buffered;
+
+            if    length == 0
+               && !send_fin
+            {
+                // With data waiting and the window closed the timer is set to probe the window,
+                // in case the update opening it is lost.
+                if    unsent > 0
+                   && in_flight == 0
+                {
+                    socket.retransmit_at.get_or_insert(now + socket.rto);
+                }
+
+                return;
+            }
+
+            let sequence = socket.send_next;
+
+            socket.send_next = sequence.wrapping_add(length as u32 + send_fin as u32);
+
+            if sequence_less_equal(socket.send_maximum, socket.send_next)
+            {
+                socket.fin_at_maximum =    send_fin
+                                        || (   socket.fin_at_maximum
+                                            && socket.send_maximum == socket.send_next);
+                socket.send_maximum = socket.send_next;
+            }
+            socket.retransmit_at.get_or_insert(now + socket.rto);
+
+            if    socket.rtt_sample.is_none()
+               && socket.retransmissions == 0
+            {
+                socket.rtt_sample = Some((socket.send_next, now));
+            }
+
+            let mut flags = FLAG_ACK;
+
+            if length > 0
+            {
+                flags |= FLAG_PSH;
+            }
+
+            if send_fin
+            {
+                flags |= FLAG_FIN;
+                socket.fin_sent = true;
+
+                socket.state = match socket.state
+                    {
+                        TcpState::Established => TcpState::FinWait1,
+                        TcpState::CloseWait   => TcpState::LastAck,
+                        state                 => state
+                    };
+            }
+
+            self.tcp_send_segment(index, sequence, flags, sent, length);
+
+            if self.sockets.tcp(index).is_some_and(|socket| socket.probing_window)
+            {
+                return;
+            }
+        }
+    }
+
+    /// Send a segment from a socket, the data is taken from its send buffer starting at the
+    /// offset. A failure to send is left to the retransmission timer.
+    fn tcp_send_segment(&mut self,
+                        index: usize,
+                        sequence: u32,
+                        flags: u8,
+                        offset: usize,
+                        length: usize)
+    {
+        let mut data = [ 0; MAX_SEGMENT_SIZE ];
+        let mut header = [ 0; TCP_HEADER_SIZE + OPTION_MSS_SIZE ];
+
+        let Some(socket) = self.sockets.tcp_mut(index) else { return };
+
+        let length = socket.buffers.send.peek_at(offset, &mut data[..length.min(MAX_SEGMENT_SIZE)]);
+        let window = socket.receive_window();
+        let acknowledgment = match flags & FLAG_ACK
+            {
+                0 => 0,
+                _ => socket.receive_next
+            };
+
+        socket.advertised_window = window;
+        socket.ack_pending = false;
+
+        let header_size = match flags & FLAG_SYN
+            {
+                0 => TCP_HEADER_SIZE,
+                _ =>
+                    {
+                        header[20] = OPTION_MSS;
+                        header[21] = OPTION_MSS_SIZE as u8;
+                        header[22..24].copy_from_slice(&(socket.receive_mss as u16).to_be_bytes());
+
+                        TCP_HEADER_SIZE + OPTION_MSS_SIZE
+                    }
+            };
+
+        let (local, remote) = (socket.local, socket.remote);
+
+        write_tcp_header(&mut header[..header_size],
+                         local.port,
+                         remote.port,
+                         sequence,
+                         acknowledgment,
+                         flags,
+                         window as u16);
+
+        let _ = self.send_ipv4(Some(local.address),
+                               remote.address,
+                               IP_PROTOCOL_TCP,
+                               &[ &header[..header_size], &data[..length] ],
+                               Some(TCP_CHECKSUM_FIELD));
+    }
+
+    /// Send a bare ACK from a socket.
+    fn tcp_send_ack(&mut self, index: usize)
+    {
+        let Some(sequence) = self.sockets.tcp(index).map(|socket| socket.send_next) else { return };
+
+        self.tcp_send_segment(index, sequence, FLAG_ACK, 0, 0);
+    }
+
+    /// Answer a segment that doesn't belong to any connection with a reset.
+    fn send_reset_for(&mut self,
+                      local: SocketAddress,
+                      remote: SocketAddress,
+                      header: &TcpHeader,
+                      data_length: usize)
+    {
+        if header.has(FLAG_RST)
+        {
+            return;
+        }
+
+        let (sequence, acknowledgment, flags) = match header.has(FLAG_ACK)
+            {
+                true  => (header.acknowledgment, 0, FLAG_RST),
+                false =>
+                    {
+                        let length = data_length
+                                     + header.has(FLAG_SYN) as usize
+                                     + header.has(FLAG_FIN) as usize;
+
+                        (0, header.sequence.wrapping_add(length as u32), FLAG_RST | FLAG_ACK)
+                    }
+            };
+
+        let mut segment = [ 0; TCP_HEADER_SIZE ];
+
+        write_tcp_header(&mut segment, local.port, remote.port, sequence, acknowledgment, flags, 0);
+
+        let _ = self.send_ipv4(Some(local.address),
+                               remote.address,
+                               IP_PROTOCOL_TCP,
+                               &[ &segment ],
+                               Some(TCP_CHECKSUM_FIELD));
+    }
+
+    /// Give up on a connection, leaving the error for the socket's owner. Sockets nobody owns
+    /// are freed.
+    fn tcp_abort(&mut self, index: usize, error: NetError)
+    {
+        if let Some(socket) = self.sockets.tcp_mut(index)
+        {
+            socket.error = Some(error);
+        }
+
+        self.tcp_finish(index);
+    }
+
+    /// Finish with a connection. Sockets nobody owns are freed.
+    fn tcp_finish(&mut self, index: usize)
+    {
+        let open = self.sockets.is_open(index);
+        let Some(socket) = self.sockets.tcp_mut(index) else { return };
+
+        socket.state = TcpState::Closed;
+        socket.retransmit_at = None;
+
+        if    !open
+           || socket.listener.is_some()
+        {
+            self.sockets.free(index);
+        }
+    }
+
+    /// Run the retransmission and TIME-WAIT timers of the connections that are due.
+    pub fn run_tcp_timers(&mut self, now: u64)
+    {
+        for index in 0..self.sockets.len()
+        {
+            let Some(socket) = self.sockets.tcp_mut(index) else { continue };
+
+            if socket.state == TcpState::TimeWait
+            {
+                if now >= socket.time_wait_until
+                {
+                    self.tcp_finish(index);
+                }
+
+                continue;
+            }
+
+            if socket.retransmit_at.is_some_and(|deadline| now >= deadline)
+            {
+                self.tcp_retransmit(index, now);
+            }
+        }
+    }
+
+    /// The retransmission timer has gone off, send the oldest data again, or probe the window.
+    fn tcp_retransmit(&mut self, index: usize, now: u64)
+    {
+        let Some(socket) = self.sockets.tcp_mut(index) else { return };
+
+        socket.retransmit_at = None;
+        socket.rto = (socket.rto * 2).min(MAX_RTO_MS);
+
+        // Nothing in flight means the window is closed with data waiting, a probe is sent.
+        if socket.bytes_in_flight() == 0
+        {
+            socket.probing_window = true;
+            self.tcp_output(index, now);
+
+            if let Some(socket) = self.sockets.tcp_mut(index)
+            {
+                socket.probing_window = false;
+            }
+
+            return;
+        }
+
+        socket.retransmissions += 1;
+
+        let limit = match socket.is_synchronized()
+            {
+                true  => MAX_RETRANSMISSIONS,
+                false => MAX_SYN_RETRANSMISSIONS
+            };
+
+        if socket.retransmissions > limit
+        {
+            self.tcp_abort(index, NetError::TimedOut);
+            return;
+        }
+
+        // Everything not acknowledged is sent again, starting from a congestion window of one
+        // segment.
+        socket.slow_start_threshold = (socket.bytes_in_flight() / 2).max(2 * socket.send_mss);
+        socket.congestion_window = socket.send_mss;
+        socket.duplicate_acks = 0;
+        socket.rtt_sample = None;
+        socket.send_next = socket.send_unacknowledged;
+        socket.fin_sent = false;
+
+        self.tcp_output(index, now);
+    }
+
+    /// Have a socket listen for connections.
+    pub fn tcp_listen(&mut self, index: usize, backlog: usize) -> NetResult<()>
+    {
+        let socket = self.sockets.tcp_mut(index).ok_or(NetError::NotSupported)?;
+
+        match socket.state
+        {
+            TcpState::Closed | TcpState::Listen =>
+                {
+                    socket.state = TcpState::Listen;
+                    socket.backlog = backlog;
+
+                    Ok(())
+                },
+
+            _ => Err(NetError::AlreadyConnected)
+        }
+    }
+
+    /// Accept a connection made to a listening socket, returning its slot.
+    pub fn tcp_accept(&mut self, index: usize) -> NetResult<usize>
+    {
+        match self.sockets.tcp(index)
+        {
+            Some(socket) if socket.state == TcpState::Listen => {},
+            Some(_) => return Err(NetError::InvalidArgument),
+            None    => return Err(NetError::NotSupported)
+        }
+
+        let connection = (0..self.sockets.len())
+            .find(|&child| self.sockets.tcp(child).is_some_and(|socket|
+                {
+                    socket.listener == Some(index) && socket.is_synchronized()
+                }))
+            .ok_or(NetError::WouldBlock)?;
+
+        if let Some(socket) = self.sockets.tcp_mut(connection)
+        {
+            socket.listener = None;
+        }
+
+        Ok(connection)
+    }
+
+    /// Start connecting a socket to a remote address.
+    pub fn tcp_connect(&mut self, index: usize, remote: SocketAddress) -> NetResult<()>
+    {
+        let socket = self.sockets.tcp_mut(index).ok_or(NetError::BadHandle)?;
+
+        match socket.state
+        {
+            TcpState::Closed => {},
+
+            TcpState::SynSent | TcpState::SynReceived => return Err(NetError::InProgress),
+
+            TcpState::Listen => return Err(NetError::InvalidArgument),
+
+            _ if socket.remote == remote => return Ok(()),
+
+            _ => return Err(NetError::AlreadyConnected)
+        }
+
+        // A failed attempt is reported once, and the socket can then be used to try again.
+        if let Some(error) = socket.error.take()
+        {
+            return Err(error);
+        }
+
+        let (interface, source) = self.route_to(remote.address)?;
+        let mtu = self.interfaces[interface].mtu;
+        let socket = self.sockets.tcp_mut(index).ok_or(NetError::BadHandle)?;
+
+        if socket.local.address.is_unspecified()
+        {
+            socket.local.address = source;
+        }
+
+        self.bind_if_unbound(index)?;
+
+        let now = current_milliseconds();
+        let socket = self.sockets.tcp_mut(index).ok_or(NetError::BadHandle)?;
+
+        socket.remote = remote;
+        socket.state = TcpState::SynSent;
+        socket.start_connection(now, mtu);
+
+        self.tcp_output(index, now);
+
+        Err(NetError::InProgress)
+    }
+
+    /// Queue data to send on a connection, returning how much of it there was room for.
+    pub fn tcp_send(&mut self, index: usize, data: &[u8]) -> NetResult<usize>
+    {
+        let socket = self.sockets.tcp_mut(index).ok_or(NetError::BadHandle)?;
+
+        if let Some(error) = socket.error
+        {
+            return Err(error);
+        }
+
+        match socket.state
+        {
+            _ if socket.fin_queued => return Err(NetError::BrokenPipe),
+
+            TcpState::Established | TcpState::CloseWait => {},
+
+            TcpState::SynSent | TcpState::SynReceived => return Err(NetError::WouldBlock),
+
+            _ => return Err(NetError::NotConnected)
+        }
+
+        if data.is_empty()
+        {
+            return Ok(0);
+        }
+
+        let count = socket.buffers.send.push_slice(data);
+
+        if count == 0
+        {
+            return Err(NetError::WouldBlock);
+        }
+
+        self.tcp_output(index, current_milliseconds());
+
+        Ok(count)
+    }
+
+    /// Receive data from a connection, 0 once the other end has finished sending.
+    pub fn tcp_receive(&mut self, index: usize, buffer: &mut [u8]) -> NetResult<usize>
+    {
+        let socket = self.sockets.tcp_mut(index).ok_or(NetError::BadHandle)?;
+
+        if !socket.buffers.receive.is_empty()
+        {
+            let count = socket.buffers.receive.pop_slice(buffer);
+
+            // Once the window has opened up by a segment or more from what was last advertised
+            // the other end is told, in case it's waiting on a closed window.
+            let threshold = socket.receive_mss.min(socket.buffers.receive.capacity() / 2);
+
+            if    socket.is_synchronized()
+               && !socket.fin_received
+               && socket.advertised_window < threshold
+               && socket.receive_window() >= threshold
+            {
+                self.tcp_send_ack(index);
+            }
+
+            return Ok(count);
+        }
+
+        if    socket.fin_received
+           || socket.read_shutdown
+        {
+            return Ok(0);
+        }
+
+        if let Some(error) = socket.error
+        {
+            return Err(error);
+        }
+
+        match socket.state
+        {
+            TcpState::Closed | TcpState::Listen => Err(NetError::NotConnected),
+            _                                   => Err(NetError::WouldBlock)
+        }
+    }
+
+    /// Shut down receiving, sending or both on a connection.
+    pub fn tcp_shutdown(&mut self, index: usize, how: Shutdown) -> NetResult<()>
+    {
+        let socket = self.sockets.tcp_mut(index).ok_or(NetError::BadHandle)?;
+
+        if matches!(socket.state, TcpState::Closed | TcpState::Listen)
+        {
+            return Err(NetError::NotConnected);
+        }
+
+        if how != Shutdown::Write
+        {
+            socket.read_shutdown = true;
+            socket.buffers.receive.clear();
+        }
+
+        if how != Shutdown::Read
+        {
+            socket.fin_queued = true;
+            self.tcp_output(index, current_milliseconds());
+        }
+
+        Ok(())
+    }
+
+    /// Close a socket. A connection is closed properly, after the data already queued has been
+    /// sent, unless there's data that was received and never read which resets it.
+    pub fn tcp_close(&mut self, index: usize)
+    {
+        let Some(socket) = self.sockets.tcp_mut(index) else { return };
+
+        match socket.state
+        {
+            TcpState::Listen =>
+                {
+                    // The connections that were never accepted go with it.
+                    for child in 0..self.sockets.len()
+                    {
+                        if self.sockets.tcp(child).is_some_and(|socket| {
+                               socket.listener == Some(index)
+                           })
+                        {
+                            self.tcp_reset(child);
+                            self.sockets.free(child);
+                        }
+                    }
+
+                    self.sockets.free(index);
+                },
+
+            TcpState::Closed | TcpState::SynSent => self.sockets.free(index),
+
+            _ if !socket.buffers.receive.is_empty() =>
+                {
+                    self.tcp_reset(index);
+                    self.sockets.free(index);
+                },
+
+            TcpState::SynReceived | TcpState::Established | TcpState::CloseWait =>
+                {
+                    socket.fin_queued = true;
+                    self.sockets.orphan(index);
+                    self.tcp_output(index, current_milliseconds());
+                },
+
+            _ => self.sockets.orphan(index)
+        }
+    }
+
+    /// Send a reset for a connection, telling the other end it's gone.
+    fn tcp_reset(&mut self, index: usize)
+    {
+        let Some(sequence) = self.sockets.tcp(index).map(|socket| socket.send_next) else { return };
+
+        self.tcp_send_segment(index, sequence, FLAG_RST | FLAG_ACK, 0, 0);
+    }
+
+    /// What a TCP socket is ready for.
+    pub fn tcp_events(&self, index: usize) -> SocketEvents
+    {
+        let Some(socket) = self.sockets.tcp(index) else { return SocketEvents::default() };
+
+        if socket.state == TcpState::Listen
+        {
+            let ready = (0..self.sockets.len())
+                .any(|child| self.sockets.tcp(child).is_some_and(|child| {
+                    child.listener == Some(index) && child.is_synchronized()
+                }));
+
+            return SocketEvents { readable: ready, ..SocketEvents::default() };
+        }
+
+        let connected = matches!(socket.state, TcpState::Established | TcpState::CloseWait);
+
+        SocketEvents
+            {
+                readable:    !socket.buffers.receive.is_empty()
+                          || socket.fin_received
+                          || socket.read_shutdown
+                          || socket.error.is_some(),
+                writable:    connected
+                          && !socket.fin_queued
+                          && !socket.buffers.send.is_full(),
+                hung_up:    socket.fin_received
+                         || (socket.state == TcpState::Closed && socket.error.is_some()),
+                error: socket.error.is_some()
+            }
+    }
+}
+
+
+
+/// Write a TCP header, the checksum is left to be filled in as the segment is sent.
+fn write_tcp_header(header: &mut [u8],
+                    source_port: u16,
+                    destination_port: u16,
+                    sequence: u32,
+                    acknowledgment: u32,
+                    flags: u8,
+                    window: u16)
+{
+    header[0..2].copy_from_slice(&source_port.to_be_bytes());
+    header[2..4].copy_from_slice(&destination_port.to_be_bytes());
+    header[4..8].copy_from_slice(&sequence.to_be_bytes());
+    header[8..12].copy_from_slice(&acknowledgment.to_be_bytes());
+    header[12] = ((header.len() / 4) as u8) << 4;
+    header[13] = flags;
+    header[14..16].copy_from_slice(&window.to_be_bytes());
+    header[16..20].fill(0);
+}
@@ -0,0 +1,299 @@
+// UDP, datagrams sent on their own with nothing to make sure they arrive. The datagrams received
+// for a socket are queued in its receive buffer, each one behind a small record of its size and
+// where it came from, until they're received. Datagrams that don't fit are dropped.
+
+use crate::{ net::{ dhcp::DHCP_CLIENT_PORT,
+                    icmp::ICMP_PORT_UNREACHABLE,
+                    ipv4::{ Ipv4Address, Ipv4Packet, IPV4_HEADER_SIZE, IP_PROTOCOL_UDP },
+                    socket::{ Shutdown, SocketAddress, SocketBuffers, SocketEvents },
+                    NetError,
+                    NetResult,
+                    NetworkStack } };
+
+
+
+/// The size of the UDP header.
+pub const UDP_HEADER_SIZE: usize = 8;
+
+/// Where the checksum is in the header.
+const UDP_CHECKSUM_FIELD: usize = 6;
+
+/// The size of the record kept in front of each queued datagram, its size, source address and
+/// source port.
+const DATAGRAM_RECORD_SIZE: usize = 8;
+
+
+
+/// A UDP socket.
+pub struct UdpSocket
+{
+    /// The address and port the socket is bound to, port 0 until it's bound.
+    pub local: SocketAddress,
+
+    /// The address the socket is connected to, if any. Only datagrams from there are received.
+    pub remote: Option<SocketAddress>,
+
+    /// Have receiving or sending been shut down?
+    read_shutdown: bool,
+    write_shutdown: bool,
+
+    /// The queued datagrams are kept in the receive buffer, the send buffer isn't used.
+    buffers: SocketBuffers
+}
+
+
+
+impl UdpSocket
+{
+    /// Create an unbound socket using the given buffers.
+    pub fn new(buffers: SocketBuffers) -> UdpSocket
+    {
+        UdpSocket
+            {
+                local: SocketAddress::UNSPECIFIED,
+                remote: None,
+                read_shutdown: false,
+                write_shutdown: false,
+                buffers
+            }
+    }
+
+    /// Give up the socket's buffers.
+    pub fn into_buffers(self) -> SocketBuffers
+    {
+        self.buffers
+    }
+
+    /// Shut down receiving, sending or both.
+    pub fn shutdown(&mut self, how: Shutdown)
+    {
+        if how != Shutdown::Write
+        {
+            self.read_shutdown = true;
+            self.buffers.receive.clear();
+        }
+
+        if how != Shutdown::Read
+        {
+            self.write_shutdown = true;
+        }
+    }
+
+    /// What the socket is ready for.
+    pub fn events(&self) -> SocketEvents
+    {
+        SocketEvents
+            {
+                readable: !self.buffers.receive.is_empty() || self.read_shutdown,
+                writable: !self.write_shutdown,
+                hung_up: false,
+                error: false
+            }
+    }
+
+    /// Does the socket take datagrams sent to the address and port, from the source?
+    fn accepts(&self, destination: SocketAddress, source: SocketAddress) -> bool
+    {
+        self.local.port == destination.port
+            && (self.local.address.is_unspecified() || self.local.address == destination.address)
+            && self.remote.is_none_or(|remote| remote == source)
+            && !self.read_shutdown
+    }
+
+    /// Queue a received datagram, if there's room for it.
+    fn queue_datagram(&mut self, source: SocketAddress, data: &[u8])
+    {
+        if self.buffers.receive.free_space() < DATAGRAM_RECORD_SIZE + data.len()
+        {
+            return;
+        }
+
+        let mut record = [ 0; DATAGRAM_RECORD_SIZE ];
+
+        record[0..2].copy_from_slice(&(data.len() as u16).to_be_bytes());
+        record[2..6].copy_from_slice(&source.address.0);
+        record[6..8].copy_from_slice(&source.port.to_be_bytes());
+
+        self.buffers.receive.push_slice(&record);
+        self.buffers.receive.push_slice(data);
+    }
+
+    /// Take the oldest queued datagram, copying as much of it as fits into the buffer. Returns
+    /// how much was copied and where the datagram came from.
+    fn take_datagram(&mut self, buffer: &mut [u8]) -> Option<(usize, SocketAddress)>
+    {
+        let mut record = [ 0; DATAGRAM_RECORD_SIZE ];
+
+        if self.buffers.receive.pop_slice(&mut record) < DATAGRAM_RECORD_SIZE
+        {
+            return None;
+        }
+
+        let size = u16::from_be_bytes([ record[0], record[1] ]) as usize;
+        let source = SocketAddress::new(Ipv4Address::from_bytes(&record[2..6]),
+                                        u16::from_be_bytes([ record[6], record[7] ]));
+        let length = size.min(buffer.len());
+        let copied = self.buffers.receive.pop_slice(&mut buffer[..length]);
+
+        self.buffers.receive.discard(size - copied);
+
+        Some((copied, source))
+    }
+}
+
+
+
+impl NetworkStack
+{
+    /// Handle a received UDP datagram.
+    pub fn handle_udp(&mut self, packet: &Ipv4Packet)
+    {
+        let datagram = packet.payload;
+
+        if datagram.len() < UDP_HEADER_SIZE
+        {
+            return;
+        }
+
+        let length = u16::from_be_bytes([ datagram[4], datagram[5] ]) as usize;
+        let checksum = u16::from_be_bytes([ datagram[6], datagram[7] ]);
+
+        // A checksum of 0 means the sender didn't work one out.
+        if    length < UDP_HEADER_SIZE
+           || length > datagram.len()
+           || (checksum != 0 && !packet.transport_checksum_ok())
+        {
+            return;
+        }
+
+        let source = SocketAddress::new(packet.source,
+                                        u16::from_be_bytes([ datagram[0], datagram[1] ]));
+        let destination = SocketAddress::new(packet.destination,
+                                             u16::from_be_bytes([ datagram[2], datagram[3] ]));
+        let data = &datagram[UDP_HEADER_SIZE..length];
+
+        if    destination.port == DHCP_CLIENT_PORT
+           && self.interfaces[packet.interface].dhcp.is_some()
+        {
+            self.handle_dhcp(packet.interface, data);
+            return;
+        }
+
+        let socket = (0..self.sockets.len())
+            .find(|&index| self.sockets
+                               .udp(index)
+                               .is_some_and(|socket| socket.accepts(destination, source)));
+
+        match socket
+        {
+            Some(index) =>
+                if let Some(socket) = self.sockets.udp_mut(index)
+                {
+                    socket.queue_datagram(source, data);
+                },
+
+            None => self.send_destination_unreachable(packet, ICMP_PORT_UNREACHABLE)
+        }
+    }
+
+    /// Send a datagram from a socket.
+    pub fn udp_send(&mut self,
+                    index: usize,
+                    destination: SocketAddress,
+                    data: &[u8]) -> NetResult<usize>
+    {
+        if    destination.address.is_unspecified()
+           || destination.port == 0
+        {
+            return Err(NetError::InvalidArgument);
+        }
+
+        self.bind_if_unbound(index)?;
+
+        let socket = self.sockets.udp(index).ok_or(NetError::BadHandle)?;
+
+        if socket.write_shutdown
+        {
+            return Err(NetError::BrokenPipe);
+        }
+
+        let source = socket.local;
+
+        self.send_udp(source, destination, data)?;
+
+        Ok(data.len())
+    }
+
+    /// Receive a datagram on a socket.
+    pub fn udp_receive(&mut self,
+                       index: usize,
+                       buffer: &mut [u8]) -> NetResult<(usize, SocketAddress)>
+    {
+        let socket = self.sockets.udp_mut(index).ok_or(NetError::BadHandle)?;
+
+        match socket.take_datagram(buffer)
+        {
+            Some(datagram)                   => Ok(datagram),
+            None if socket.read_shutdown     => Ok((0, SocketAddress::UNSPECIFIED)),
+            None                             => Err(NetError::WouldBlock)
+        }
+    }
+
+    /// Send a datagram to wherever the routing table says it goes.
+    pub fn send_udp(&mut self,
+                    source: SocketAddress,
+                    destination: SocketAddress,
+                    data: &[u8]) -> NetResult<()>
+    {
+        let header = udp_header(source, destination, data)?;
+
+        self.send_ipv4(Some(source.address),
+                       destination.address,
+                       IP_PROTOCOL_UDP,
+                       &[ &header, data ],
+                       Some(UDP_CHECKSUM_FIELD))
+    }
+
+    /// Send a datagram out of an interface to the given next hop, regardless of the routing
+    /// table. It's how DHCP broadcasts before the interface has an address.
+    pub fn transmit_udp(&mut self,
+                        interface: usize,
+                        source: SocketAddress,
+                        destination: SocketAddress,
+                        next_hop: Ipv4Address,
+                        data: &[u8]) -> NetResult<()>
+    {
+        let header = udp_header(source, destination, data)?;
+
+        self.transmit_ipv4(interface,
+                           source.address,
+                           destination.address,
+                           next_hop,
+                           IP_PROTOCOL_UDP,
+                           &[ &header, data ],
+                           Some(UDP_CHECKSUM_FIELD))
+    }
+}
+
+
+
+/// Build the header for a datagram. Its checksum is filled in as it's sent.
+fn udp_header(source: SocketAddress,
+              destination: SocketAddress,
+              data: &[u8]) -> NetResult<[u8; UDP_HEADER_SIZE]>
+{
+    let length = UDP_HEADER_SIZE + data.len();
+
+    if length + IPV4_HEADER_SIZE > u16::MAX as usize
+    {
+        return Err(NetError::MessageTooLong);
+    }
+
+    let mut header = [ 0; UDP_HEADER_SIZE ];
+
+    header[0..2].copy_from_slice(&source.port.to_be_bytes());
+    header[2..4].copy_from_slice(&destination.port.to_be_bytes());
+    header[4..6].copy_from_slice(&(length as u16).to_be_bytes());
+
+    Ok(header)
+}
@@ -1,23 +1,37 @@
 
 // Implementation of the printing module for the Xtra kernel.
 //
-// This module provides a simple logging interface to the first UART device found in the device
-// tree. We use the simple UART implementation so that we can print from code executing  without
-// interrupts enabled.
+// This module provides a simple logging interface to the UART device selected by the console=
+// kernel parameter, or the first UART device found in the device tree if none was given. We use the
+// simple UART implementation so that we can print from code executing  without interrupts enabled.
+//
+// Once the devices are up printing switches over to the console, which sends the output to all of
+// its backends. A panic switches back to the UART, so that the panic message gets out no matter
+// what state the console is in.
+//
+// How much gets printed is controlled by the loglevel= kernel parameter. The kernel's boot messages
+// are printed at BOOT_MESSAGE_LOG_LEVEL, lower log levels silence them.
 
-use core::fmt::{ self, Write };
+use core::{ fmt::{ self, Write },
+            ptr::addr_of_mut,
+            sync::atomic::{ AtomicBool, AtomicU8, Ordering } };
 
 use xtra_kernel_shared::device_tree::DeviceTree;
 
-use crate::{ locking::spin_lock::SpinLock,
+use crate::{ command_line::{ DEFAULT_LOG_LEVEL, MAX_LOG_LEVEL },
+             devices::console::{ has_console_backends, try_flush_console, write_console },
+             locking::{ spin_lock::SpinLock, LockGuard },
              uart::SimpleUart };
 
 
 
+/// The log level the print! and println! macros print at.
+pub const BOOT_MESSAGE_LOG_LEVEL: u8 = 6;
+
+
+
 /// Global reference to the UART device used for printing. This is initialized at boot time and
-/// used throughout the kernel for logging output.
-/// TODO: Allow this to be switched over to a console device driver instead of talking directly to
-///       the UART.
+/// used for logging output until printing switches over to the console.
 pub static mut PRINTING_UART: SimpleUart = SimpleUart::zeroed();
 
 
@@ -28,6 +42,32 @@ pub static PRINTING_LOCK: SpinLock = SpinLock::new();
 
 
 
+/// The current log level, messages printed at a higher level than this are dropped.
+pub static PRINTING_LOG_LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LOG_LEVEL);
+
+
+
+/// Is printing going to the console instead of the UART?
+static PRINTING_TO_CONSOLE: AtomicBool = AtomicBool::new(false);
+
+
+
+/// Should a message at the given log level be printed?
+pub fn is_log_level_enabled(level: u8) -> bool
+{
+    level <= PRINTING_LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+
+
+/// Set how much is printed, 0 prints nothing and MAX_LOG_LEVEL prints everything.
+pub fn set_log_level(level: u8)
+{
+    PRINTING_LOG_LEVEL.store(level.min(MAX_LOG_LEVEL), Ordering::Relaxed);
+}
+
+
+
 /// A simple writer that writes to a buffer. This is used to format strings using the `write!` macro
 /// using the stack instead of heap allocation. This is useful for formatting strings in the kernel
 /// without allocating memory on the heap.
@@ -92,39 +132,21 @@ impl<'a> Write for BufferWriter<'a>
 
 
 
-/// Implement the standard print! macro for printing formatted output from the kernel to an attached
-/// UART device.
+/// Implement the standard print! macro for printing formatted output from the kernel to the
+/// console, or the UART device before the console is up.
 #[macro_export]
 macro_rules! print
 {
     ($($arg:tt)*) =>
         {{
-            use core::{ fmt::Write, ptr::addr_of_mut };
-
-            use crate::{ printing::{ PRINTING_UART, PRINTING_LOCK },
-                         locking::LockGuard };
-
-            unsafe
-            {
-                // When printing we print to the logging UART device that we initialized at boot
-                // time.
-                let uart = &mut *addr_of_mut!(PRINTING_UART);
-
-                if uart.is_initialized()
-                {
-                    // Make sure that only one hardware thread can write to the UART at a time.
-                    let _guard = LockGuard::new(&PRINTING_LOCK);
-
-                    uart.write_fmt(format_args!($($arg)*)).unwrap();
-                }
-            }
+            crate::printing::print_arguments(format_args!($($arg)*));
         }};
 }
 
 
 
-/// Implement the standard println! macro for printing formatted output from the kernel to an
-/// attached UART device. This macro appends a newline character to the end of the output.
+/// Implement the standard println! macro for printing formatted output from the kernel to the
+/// console. This macro appends a newline character to the end of the output.
 #[macro_export]
 macro_rules! println
 {
@@ -301,14 +323,16 @@ macro_rules! write_size
 
 
 
-/// Initializes the printing system by finding the first UART device in the device tree and setting
-/// it up for use. This function will panic if no UART device is found in the device tree.
-///
-/// TODO: Allow for the bootloader to be able to specify which device to use for system startup
-///       logging.
-pub fn init_printing(device_tree: &DeviceTree)
+/// Initializes the printing system by finding the UART device with the given index in the device
+/// tree and setting it up for use. If no index is given, or there aren't that many UART devices,
+/// the first UART device is used instead. This function will panic if no UART device is found in
+/// the device tree.
+pub fn init_printing(device_tree: &DeviceTree, console_index: Option<usize>)
 {
-    let mut found_uart = false;
+    let wanted_index = console_index.unwrap_or(0);
+    let mut uart_index: usize = 0;
+    let mut first_uart: Option<usize> = None;
+    let mut found_uart: Option<usize> = None;
 
     device_tree.iterate_blocks(|offset, name|
         {
@@ -352,14 +376,19 @@ pub fn init_printing(device_tree: &DeviceTree)
                 if    base_address != 0
                    && reg_range != 0
                 {
-                    unsafe
+                    if first_uart.is_none()
                     {
-                        PRINTING_UART = SimpleUart::init_new(base_address as usize);
+                        first_uart = Some(base_address as usize);
                     }
 
-                    found_uart = true;
+                    if uart_index == wanted_index
+                    {
+                        found_uart = Some(base_address as usize);
+
+                        return false;
+                    }
 
-                    return false;
+                    uart_index += 1;
                 }
             }
 
@@ -367,24 +396,102 @@ pub fn init_printing(device_tree: &DeviceTree)
             true
         });
 
-    if !found_uart
+    match found_uart.or(first_uart)
     {
-        panic!("No UART device found in the device tree for logging.");
+        Some(base_address) =>
+            {
+                unsafe
+                {
+                    PRINTING_UART = SimpleUart::init_new(base_address);
+                }
+            },
+
+        None =>
+            {
+                panic!("No UART device found in the device tree for logging.");
+            }
     }
 }
 
 
 
-/// Switch the printing system from the temporary serial device to a console device(s) so that we
-/// can print to the console instead of directly to the UART.
-///
-/// This also allows us to support multiple console devices, such as a serial console and a
-/// graphical console simultaneously.
+/// Switch the printing system from the temporary serial device to the console so that we print to
+/// all of the console's backends instead of directly to the UART. This also allows us to support
+/// multiple console devices, such as a serial console and a graphical console simultaneously.
 ///
 /// TODO: This should be a Kernel configuration passed from the bootloader from a configuration file
 ///       it discovers at system startup. Allow the system builder to specify how console devices
 ///       are defined and utilized.
-pub fn switch_to_console_device(/*console_devices: Vec<ConsoleDevice>*/) -> Result<(), &'static str>
+pub fn switch_to_console_device() -> Result<(), &'static str>
 {
+    if !has_console_backends()
+    {
+        return Err("The console has no devices to print to.");
+    }
+
+    // Take the printing lock so that nothing is half way through printing to the UART as we switch.
+    let _guard = LockGuard::new(&PRINTING_LOCK);
+
+    PRINTING_TO_CONSOLE.store(true, Ordering::Release);
+
     Ok(())
 }
+
+
+/// Switch printing back to the UART picked at boot, for the panic handler. Whatever the console
+/// still has queued is sent first, if it can be, so that the output stays in order.
+pub fn switch_to_boot_uart()
+{
+    if PRINTING_TO_CONSOLE.swap(false, Ordering::AcqRel)
+    {
+        try_flush_console();
+    }
+}
+
+
+
+/// Print formatted output, called by the print! macro. Goes to the console once printing has been
+/// switched over to it, otherwise to the UART picked at boot.
+pub fn print_arguments(arguments: fmt::Arguments)
+{
+    if !is_log_level_enabled(BOOT_MESSAGE_LOG_LEVEL)
+    {
+        return;
+    }
+
+    // Make sure that only one hardware thread prints at a time.
+    let _guard = LockGuard::new(&PRINTING_LOCK);
+
+    if PRINTING_TO_CONSOLE.load(Ordering::Acquire)
+    {
+        let _ = ConsoleWriter.write_fmt(arguments);
+        return;
+    }
+
+    unsafe
+    {
+        let uart = &mut *addr_of_mut!(PRINTING_UART);
+
+        if uart.is_initialized()
+        {
+            let _ = uart.write_fmt(arguments);
+        }
+    }
+}
+
+
+
+/// Writes formatted output to the console.
+struct ConsoleWriter;
+
+
+
+impl Write for ConsoleWriter
+{
+    fn write_str(&mut self, string: &str) -> fmt::Result
+    {
+        write_console(string.as_bytes());
+
+        Ok(())
+    }
+}
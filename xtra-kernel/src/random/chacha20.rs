@@ -0,0 +1,105 @@
+// The ChaCha20 block function, from Bernstein's ChaCha and RFC 8439. The generator's output is
+// ChaCha20 keystream, and the permutation at its heart, run without the final addition of the
+// input, is also what stirs the input pool.
+//
+// The state is laid out with the original 64-bit block counter and 64-bit nonce rather than the
+// RFC's 32-bit counter, so that a key can produce more than 256GB before its counter wraps.
+
+/// The size of a key, in 32-bit words.
+pub const KEY_WORDS: usize = 8;
+
+/// The size of a block of keystream, in bytes.
+pub const BLOCK_SIZE: usize = 64;
+
+/// The size of the state, in 32-bit words.
+pub const STATE_WORDS: usize = 16;
+
+
+
+/// The words the state starts with, "expand 32-byte k".
+const CONSTANTS: [u32; 4] = [ 0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574 ];
+
+/// The number of double rounds, a column round followed by a diagonal round, that make up ChaCha20.
+const DOUBLE_ROUNDS: usize = 10;
+
+
+
+/// A ChaCha20 key.
+pub type Key = [u32; KEY_WORDS];
+
+
+
+/// The quarter round, mixing four words of the state.
+fn quarter_round(state: &mut [u32; STATE_WORDS], a: usize, b: usize, c: usize, d: usize)
+{
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+
+/// Run the twenty rounds of ChaCha20 over the state.
+pub fn permute(state: &mut [u32; STATE_WORDS])
+{
+    for _ in 0..DOUBLE_ROUNDS
+    {
+        quarter_round(state, 0, 4,  8, 12);
+        quarter_round(state, 1, 5,  9, 13);
+        quarter_round(state, 2, 6, 10, 14);
+        quarter_round(state, 3, 7, 11, 15);
+
+        quarter_round(state, 0, 5, 10, 15);
+        quarter_round(state, 1, 6, 11, 12);
+        quarter_round(state, 2, 7,  8, 13);
+        quarter_round(state, 3, 4,  9, 14);
+    }
+}
+
+
+/// Produce a block of keystream for a key, block counter and nonce.
+pub fn block(key: &Key, counter: u64, nonce: u64) -> [u8; BLOCK_SIZE]
+{
+    let mut input = [0u32; STATE_WORDS];
+
+    input[..4].copy_from_slice(&CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    input[14] = nonce as u32;
+    input[15] = (nonce >> 32) as u32;
+
+    let mut state = input;
+
+    permute(&mut state);
+
+    let mut output = [0u8; BLOCK_SIZE];
+
+    for (index, bytes) in output.chunks_exact_mut(4).enumerate()
+    {
+        bytes.copy_from_slice(&state[index].wrapping_add(input[index]).to_le_bytes());
+    }
+
+    output
+}
+
+
+/// Read a key from the start of a block of keystream.
+pub fn key_from_bytes(bytes: &[u8]) -> Key
+{
+    let mut key = [0u32; KEY_WORDS];
+
+    for (word, bytes) in key.iter_mut().zip(bytes.chunks_exact(4))
+    {
+        *word = u32::from_le_bytes([ bytes[0], bytes[1], bytes[2], bytes[3] ]);
+    }
+
+    key
+}
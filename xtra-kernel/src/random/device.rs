@@ -0,0 +1,101 @@
+// The random and urandom character devices, the /dev/random and /dev/urandom of Unix. Reading
+// either one gives output from the random number generator, random waits for the generator to be
+// seeded first while urandom never waits. Whatever is written to them is stirred into the input
+// pool, but isn't counted as entropy as anyone can write to them.
+//
+// There's no device filesystem yet, so the initial RAM disk gives them their places under /dev.
+
+use alloc::sync::Arc;
+
+use crate::{ filesystems::{ inode::{ FileType, Inode, Metadata }, FsResult },
+             random::{ add_device_randomness, get_random_bytes, wait_until_seeded } };
+
+
+
+/// The inode numbers of the devices.
+const RANDOM_INODE_NUMBER:  u64 = 1;
+const URANDOM_INODE_NUMBER: u64 = 2;
+
+/// Anyone may read and write the devices.
+const DEVICE_MODE: u16 = 0o666;
+
+/// The preferred size for reads, a page's worth of output.
+const DEVICE_BLOCK_SIZE: u32 = 4096;
+
+
+
+/// One of the random devices.
+pub struct RandomDevice
+{
+    /// The device's inode number.
+    inode_number: u64,
+
+    /// Do reads wait for the generator to be seeded?
+    waits_for_seed: bool
+}
+
+
+
+impl RandomDevice
+{
+    /// Create the random device, whose reads wait for the generator to be seeded.
+    pub fn random() -> Arc<RandomDevice>
+    {
+        Arc::new(RandomDevice { inode_number: RANDOM_INODE_NUMBER, waits_for_seed: true })
+    }
+
+    /// Create the urandom device, whose reads never wait.
+    pub fn urandom() -> Arc<RandomDevice>
+    {
+        Arc::new(RandomDevice { inode_number: URANDOM_INODE_NUMBER, waits_for_seed: false })
+    }
+}
+
+
+
+impl Inode for RandomDevice
+{
+    fn metadata(&self) -> FsResult<Metadata>
+    {
+        Ok(Metadata
+            {
+                inode_number: self.inode_number,
+                file_type: FileType::CharacterDevice,
+                mode: DEVICE_MODE,
+                user_id: 0,
+                group_id: 0,
+                size: 0,
+                link_count: 1,
+                access_time: 0,
+                modify_time: 0,
+                change_time: 0,
+                block_size: DEVICE_BLOCK_SIZE,
+                blocks: 0
+            })
+    }
+
+    fn read_at(&self, _offset: u64, buffer: &mut [u8]) -> FsResult<usize>
+    {
+        if self.waits_for_seed
+        {
+            wait_until_seeded();
+        }
+
+        get_random_bytes(buffer);
+
+        Ok(buffer.len())
+    }
+
+    fn write_at(&self, _offset: u64, buffer: &[u8]) -> FsResult<usize>
+    {
+        add_device_randomness(buffer);
+
+        Ok(buffer.len())
+    }
+
+    // The devices have no size, so opening them to be truncated does nothing.
+    fn truncate(&self, _size: u64) -> FsResult<()>
+    {
+        Ok(())
+    }
+}
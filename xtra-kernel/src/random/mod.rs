@@ -0,0 +1,478 @@
+// The kernel's random number generator, a ChaCha20 based CSPRNG seeded from whatever entropy the
+// system has to offer. It's what ASLR, TCP sequence numbers, ASID salting and stack canaries draw
+// on through `get_random_bytes`, and what user programs get from getrandom and /dev/urandom.
+//
+// Entropy comes from three places. Hardware generators, such as virtio-rng, give us entropy we
+// trust fully. At boot we time a fixed piece of work over and over and keep the jitter in how long
+// it takes. And every interrupt adds the time it arrived, whose low bits depend on things outside
+// of the kernel's control. It's all stirred into the input pool, a sponge built on the ChaCha20
+// permutation, which keeps a conservative count of how many bits of entropy it has been given.
+//
+// The generator itself is a ChaCha20 key. It's reseeded from the pool once the pool has been given
+// enough entropy, as soon as it can be the first time and at most once a minute after that, so
+// that each reseed brings in enough new entropy that someone who knew the old key can't guess the
+// new one. Each request takes a fresh key from the generator's keystream, replacing the generator's
+// key as it does, so that nothing already handed out can be worked out from the generator's state.
+//
+// As with /dev/urandom, output is available before the generator has been seeded. Anything that
+// can't make do with weaker randomness waits for the generator with `wait_until_seeded`.
+
+use core::hint::spin_loop;
+
+use crate::{ arch::{ csr::read_time, interrupts::with_interrupts_disabled },
+             devices::entropy_devices::{ has_entropy_devices,
+                                         poll_entropy_devices,
+                                         request_entropy },
+             locking::spin_mutex::SpinMutex,
+             random::chacha20::{ block,
+                                  key_from_bytes,
+                                  permute,
+                                  Key,
+                                  BLOCK_SIZE,
+                                  KEY_WORDS,
+                                  STATE_WORDS },
+             timers::current_milliseconds };
+
+
+
+/// The ChaCha20 block function, used for the generator's output and to stir the input pool.
+pub mod chacha20;
+
+/// The random and urandom character devices.
+pub mod device;
+
+
+
+/// The source given for the timer interrupt, which doesn't have an interrupt number of its own.
+pub const TIMER_INTERRUPT: usize = usize::MAX;
+
+
+
+/// How many words of the pool's state take the input, and are read out to reseed the generator.
+/// The rest of the state is never read out directly.
+const POOL_RATE: usize = 8;
+
+/// How many bits of entropy the pool has to have been given before it's used to seed the
+/// generator. It's also as much as the pool keeps count of.
+const SEED_BITS: usize = 256;
+
+/// The shortest time between reseeds, once the generator has been seeded.
+const RESEED_INTERVAL_MS: u64 = 60_000;
+
+/// The interrupts it takes to count as a bit of entropy.
+const INTERRUPTS_PER_BIT: usize = 64;
+
+/// How many times the jitter of the fixed piece of work is measured at boot.
+const JITTER_SAMPLES: usize = 4096;
+
+/// The jitter samples it takes to count as a bit of entropy, of those that showed any jitter.
+const JITTER_SAMPLES_PER_BIT: usize = 8;
+
+/// How long we wait at boot for the entropy devices to seed the generator.
+const SEED_TIMEOUT_MS: u64 = 1000;
+
+
+
+/// The input pool, where entropy is gathered until it's used to reseed the generator. Also used by
+/// the interrupt handlers so it's only ever locked with interrupts disabled.
+struct InputPool
+{
+    /// The sponge's state. Input goes into the first POOL_RATE words.
+    state: [u32; STATE_WORDS],
+
+    /// The word of the state the next word of input goes into.
+    position: usize,
+
+    /// How many bits of entropy the pool has been given since the generator was last reseeded.
+    entropy_bits: usize,
+
+    /// The interrupts seen since the last bit of entropy was counted for them.
+    interrupt_count: usize,
+
+    /// The jitter samples seen since the last bit of entropy was counted for them.
+    jitter_count: usize
+}
+
+
+
+impl InputPool
+{
+    /// Create an empty pool.
+    const fn new() -> InputPool
+    {
+        InputPool
+            {
+                state: [0; STATE_WORDS],
+                position: 0,
+                entropy_bits: 0,
+                interrupt_count: 0,
+                jitter_count: 0
+            }
+    }
+
+    /// Stir a word into the pool. The state is permuted each time its input words have all been
+    /// used.
+    fn mix_word(&mut self, word: u32)
+    {
+        self.state[self.position] ^= word;
+        self.position += 1;
+
+        if self.position == POOL_RATE
+        {
+            permute(&mut self.state);
+            self.position = 0;
+        }
+    }
+
+    /// Stir some bytes into the pool.
+    fn mix_bytes(&mut self, bytes: &[u8])
+    {
+        for chunk in bytes.chunks(4)
+        {
+            let mut word = [0u8; 4];
+
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.mix_word(u32::from_le_bytes(word));
+        }
+    }
+
+    /// Stir the current time into the pool, along with a value saying what happened at that time.
+    fn mix_time(&mut self, value: usize)
+    {
+        let time = read_time();
+
+        self.mix_word(time as u32);
+        self.mix_word((time >> 32) as u32 ^ value as u32);
+    }
+
+    /// Count some bits of entropy as having been given to the pool.
+    fn credit(&mut self, bits: usize)
+    {
+        self.entropy_bits = (self.entropy_bits + bits).min(SEED_BITS);
+    }
+
+    /// Read a seed for the generator out of the pool. The part of the state it came from is wiped
+    /// and the state stirred again, so that the seed can't be worked back out from the pool.
+    fn extract(&mut self) -> Key
+    {
+        permute(&mut self.state);
+
+        let mut seed = [0; KEY_WORDS];
+
+        seed.copy_from_slice(&self.state[..POOL_RATE]);
+
+        self.state[..POOL_RATE].fill(0);
+        permute(&mut self.state);
+        self.position = 0;
+
+        seed
+    }
+}
+
+
+
+/// The generator that output is produced from.
+struct Generator
+{
+    /// The key the next request's key is taken from.
+    key: Key,
+
+    /// Has the generator been seeded with enough entropy?
+    is_seeded: bool,
+
+    /// When the generator was last reseeded, in milliseconds.
+    last_reseed: u64
+}
+
+
+
+impl Generator
+{
+    /// Create a generator that hasn't been seeded.
+    const fn new() -> Generator
+    {
+        Generator
+            {
+                key: [0; KEY_WORDS],
+                is_seeded: false,
+                last_reseed: 0
+            }
+    }
+
+    /// Take a key for a request. The first half of a block of the generator's keystream becomes its
+    /// new key and the second half is the request's, so the old key is gone once this returns.
+    fn take_key(&mut self) -> Key
+    {
+        let keystream = block(&self.key, 0, 0);
+
+        self.key = key_from_bytes(&keystream[..BLOCK_SIZE / 2]);
+
+        key_from_bytes(&keystream[BLOCK_SIZE / 2..])
+    }
+
+    /// Mix a seed from the pool into the generator's key.
+    fn reseed(&mut self, seed: &Key)
+    {
+        for (word, seed_word) in self.key.iter_mut().zip(seed.iter())
+        {
+            *word ^= seed_word;
+        }
+
+        // Run the combined key through the generator once, so that the seed and the old key are
+        // both gone.
+        self.key = self.take_key();
+    }
+}
+
+
+
+/// The input pool.
+static INPUT_POOL: SpinMutex<InputPool> = SpinMutex::new(InputPool::new());
+
+/// The generator. It's locked before the input pool when both are needed, and only ever with
+/// interrupts disabled as requests may come from interrupt handlers.
+static GENERATOR: SpinMutex<Generator> = SpinMutex::new(Generator::new());
+
+
+
+/// Gather the entropy we can at boot and seed the generator, waiting a little while for any
+/// entropy devices to provide enough if the timer jitter didn't.
+pub fn initialize_random()
+{
+    gather_timer_jitter();
+
+    // Even before it's seeded the generator is keyed with whatever the pool has, so that its
+    // output at least differs from boot to boot.
+    let seed = with_interrupts_disabled(|| INPUT_POOL.lock().extract());
+
+    with_interrupts_disabled(|| GENERATOR.lock().reseed(&seed));
+
+    request_entropy();
+    reseed_if_due();
+
+    if has_entropy_devices()
+    {
+        let deadline = current_milliseconds() + SEED_TIMEOUT_MS;
+
+        while    !is_seeded()
+              && current_milliseconds() < deadline
+        {
+            poll_entropy_devices();
+            reseed_if_due();
+            spin_loop();
+        }
+    }
+
+    if is_seeded()
+    {
+        println!("  Random number generator seeded.");
+    }
+    else
+    {
+        println!("  Random number generator not seeded yet, it will be once it has enough \
+                  entropy.");
+    }
+}
+
+
+
+/// Add entropy from a hardware generator to the pool. It's counted in full.
+pub fn add_hardware_randomness(bytes: &[u8])
+{
+    with_interrupts_disabled(||
+        {
+            let mut pool = INPUT_POOL.lock();
+
+            pool.mix_bytes(bytes);
+            pool.credit(bytes.len() * 8);
+        });
+}
+
+
+/// Add data that differs from system to system, or that anyone could have supplied, to the pool.
+/// It's stirred in but not counted as entropy.
+pub fn add_device_randomness(bytes: &[u8])
+{
+    with_interrupts_disabled(|| INPUT_POOL.lock().mix_bytes(bytes));
+}
+
+
+/// Add the timing of an interrupt to the pool, called from the interrupt handlers with the
+/// interrupt's number. Interrupts aren't worth waiting for, so if the pool is busy on another hart
+/// the interrupt is skipped rather than spinning in the handler.
+pub fn add_interrupt_randomness(source: usize)
+{
+    if let Some(mut pool) = INPUT_POOL.try_lock()
+    {
+        pool.mix_time(source);
+        pool.interrupt_count += 1;
+
+        if pool.interrupt_count == INTERRUPTS_PER_BIT
+        {
+            pool.interrupt_count = 0;
+            pool.credit(1);
+        }
+    }
+}
+
+
+/// Does the pool want more entropy before the generator's next reseed?
+pub fn needs_entropy() -> bool
+{
+    with_interrupts_disabled(|| INPUT_POOL.lock().entropy_bits < SEED_BITS)
+}
+
+
+/// Has the generator been seeded with enough entropy?
+pub fn is_seeded() -> bool
+{
+    with_interrupts_disabled(|| GENERATOR.lock().is_seeded)
+}
+
+
+/// Wait for the generator to be seeded. There's no scheduler to sleep on yet, so the entropy
+/// devices are polled while we wait in case their interrupts aren't available.
+pub fn wait_until_seeded()
+{
+    loop
+    {
+        reseed_if_due();
+
+        if is_seeded()
+        {
+            break;
+        }
+
+        poll_entropy_devices();
+        spin_loop();
+    }
+}
+
+
+
+/// Fill a buffer with random bytes. This never waits, even if the generator hasn't been seeded.
+pub fn get_random_bytes(buffer: &mut [u8])
+{
+    reseed_if_due();
+
+    let key = with_interrupts_disabled(|| GENERATOR.lock().take_key());
+
+    for (counter, chunk) in buffer.chunks_mut(BLOCK_SIZE).enumerate()
+    {
+        let keystream = block(&key, counter as u64, 0);
+
+        chunk.copy_from_slice(&keystream[..chunk.len()]);
+    }
+}
+
+
+/// Get a random 32-bit number.
+pub fn get_random_u32() -> u32
+{
+    let mut bytes = [0u8; 4];
+
+    get_random_bytes(&mut bytes);
+
+    u32::from_le_bytes(bytes)
+}
+
+
+/// Get a random 64-bit number.
+pub fn get_random_u64() -> u64
+{
+    let mut bytes = [0u8; 8];
+
+    get_random_bytes(&mut bytes);
+
+    u64::from_le_bytes(bytes)
+}
+
+
+
+/// Reseed the generator from the pool if the pool has enough entropy and it's been long enough
+/// since the last reseed, then ask the entropy devices to start refilling the pool.
+fn reseed_if_due()
+{
+    let now = current_milliseconds();
+
+    let is_reseeded = with_interrupts_disabled(||
+        {
+            let mut generator = GENERATOR.lock();
+
+            if    generator.is_seeded
+               && now.saturating_sub(generator.last_reseed) < RESEED_INTERVAL_MS
+            {
+                return false;
+            }
+
+            let mut pool = INPUT_POOL.lock();
+
+            if pool.entropy_bits < SEED_BITS
+            {
+                return false;
+            }
+
+            let seed = pool.extract();
+
+            pool.entropy_bits = 0;
+
+            generator.reseed(&seed);
+            generator.is_seeded = true;
+            generator.last_reseed = now;
+
+            true
+        });
+
+    if is_reseeded
+    {
+        request_entropy();
+    }
+}
+
+
+/// Gather entropy from the jitter in how long the same piece of work takes to run. The caches, the
+/// pipeline and, on a virtual machine, the host all make it vary a little from run to run. Only
+/// samples whose timing varied at every level, the time taken, how it changed from the last
+/// sample and how that changed, are counted as entropy, and only a fraction of a bit each.
+fn gather_timer_jitter()
+{
+    let mut work = [0u32; STATE_WORDS];
+    let mut last_time = read_time();
+    let mut last_delta = 0u64;
+    let mut last_change = 0u64;
+
+    for sample in 0..JITTER_SAMPLES
+    {
+        work[0] = sample as u32;
+        permute(&mut work);
+
+        let time = read_time();
+        let delta = time.wrapping_sub(last_time);
+        let change = delta.wrapping_sub(last_delta);
+        let jitter = change.wrapping_sub(last_change);
+
+        with_interrupts_disabled(||
+            {
+                let mut pool = INPUT_POOL.lock();
+
+                pool.mix_time(delta as usize ^ work[0] as usize);
+
+                if    delta != 0
+                   && change != 0
+                   && jitter != 0
+                {
+                    pool.jitter_count += 1;
+
+                    if pool.jitter_count == JITTER_SAMPLES_PER_BIT
+                    {
+                        pool.jitter_count = 0;
+                        pool.credit(1);
+                    }
+                }
+            });
+
+        last_time = time;
+        last_delta = delta;
+        last_change = change;
+    }
+}
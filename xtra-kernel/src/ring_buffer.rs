@@ -0,0 +1,154 @@
+// A fixed size ring buffer of bytes, for queueing data between whoever produces it and whoever
+// consumes it, such as a device's interrupt handler and the code reading from the device.
+
+use alloc::{ boxed::Box, vec };
+
+
+
+/// A first in, first out queue of bytes with a fixed capacity.
+pub struct RingBuffer
+{
+    /// The buffer's storage.
+    data: Box<[u8]>,
+
+    /// The index of the oldest byte in the buffer.
+    head: usize,
+
+    /// How many bytes are in the buffer.
+    length: usize
+}
+
+
+
+impl RingBuffer
+{
+    /// Create an empty buffer that can hold the given number of bytes.
+    pub fn new(capacity: usize) -> RingBuffer
+    {
+        assert!(capacity > 0, "A ring buffer needs room for at least one byte.");
+
+        RingBuffer { data: vec![0u8; capacity].into_boxed_slice(), head: 0, length: 0 }
+    }
+
+    /// How many bytes the buffer can hold.
+    pub fn capacity(&self) -> usize
+    {
+        self.data.len()
+    }
+
+    /// How many bytes are in the buffer.
+    pub fn len(&self) -> usize
+    {
+        self.length
+    }
+
+    /// Is the buffer empty?
+    pub fn is_empty(&self) -> bool
+    {
+        self.length == 0
+    }
+
+    /// How many more bytes the buffer has room for.
+    pub fn free_space(&self) -> usize
+    {
+        self.data.len() - self.length
+    }
+
+    /// Is the buffer full?
+    pub fn is_full(&self) -> bool
+    {
+        self.length == self.data.len()
+    }
+
+    /// Add a byte to the end of the buffer. Returns false if the buffer was full and the byte was
+    /// dropped.
+    pub fn push(&mut self, byte: u8) -> bool
+    {
+        if self.is_full()
+        {
+            return false;
+        }
+
+        let tail = (self.head + self.length) % self.data.len();
+
+        self.data[tail] = byte;
+        self.length += 1;
+
+        true
+    }
+
+    /// Take the oldest byte out of the buffer.
+    pub fn pop(&mut self) -> Option<u8>
+    {
+        if self.is_empty()
+        {
+            return None;
+        }
+
+        let byte = self.data[self.head];
+
+        self.head = (self.head + 1) % self.data.len();
+        self.length -= 1;
+
+        Some(byte)
+    }
+
+    /// Add as many of the bytes as fit to the end of the buffer, returning how many were added.
+    pub fn push_slice(&mut self, bytes: &[u8]) -> usize
+    {
+        bytes.iter().take_while(|&&byte| self.push(byte)).count()
+    }
+
+    /// Take as many bytes as fit in the given slice out of the buffer, returning how many were
+    /// taken.
+    pub fn pop_slice(&mut self, bytes: &mut [u8]) -> usize
+    {
+        let mut count = 0;
+
+        while count < bytes.len()
+        {
+            let Some(byte) = self.pop()
+            else
+            {
+                break;
+            };
+
+            bytes[count] = byte;
+            count += 1;
+        }
+
+        count
+    }
+
+    /// Copy bytes out of the buffer without taking them, starting the given number of bytes after
+    /// the oldest. Returns how many were copied.
+    pub fn peek_at(&self, offset: usize, bytes: &mut [u8]) -> usize
+    {
+        let count = bytes.len().min(self.length.saturating_sub(offset));
+
+        for (index, byte) in bytes[..count].iter_mut().enumerate()
+        {
+            *byte = self.data[(self.head + offset + index) % self.data.len()];
+        }
+
+        count
+    }
+
+    /// Throw away up to the given number of the oldest bytes, returning how many were thrown away.
+    pub fn discard(&mut self, count: usize) -> usize
+    {
+        let count = count.min(self.length);
+
+        self.head = (self.head + count) % self.data.len();
+        self.length -= count;
+
+        count
+    }
+
+    /// Throw away everything in the buffer.
+    pub fn clear(&mut self)
+    {
+        self.head = 0;
+        self.length = 0;
+    }
+}
@@ -1,4 +1,7 @@
 
+use crate::{ arch::wait_for_interrupt, ipi::{ call_on_harts, CallMode, IpiTarget } };
+
+
 
 pub struct Scheduler
 {
@@ -16,9 +19,21 @@ impl Scheduler
     {
         // Placeholder for the scheduler's run logic.
         // This will manage tasks and their execution.
-
+        //
+        // Until then the hart idles. Anything the other harts want of it arrives as an interrupt.
         loop
         {
+            wait_for_interrupt();
         }
     }
 }
+
+
+
+/// Wake up another hart's scheduler so that it takes another look at the work it has, for when
+/// work has been moved onto an idle hart. The call itself does nothing, the interrupt is enough to
+/// bring the hart out of its idle loop.
+pub fn kick_hart(hart_id: usize) -> Result<(), &'static str>
+{
+    call_on_harts(IpiTarget::Hart(hart_id), CallMode::Asynchronous, || {})
+}
@@ -0,0 +1,112 @@
+// The file descriptor table. A descriptor is a small number standing for something a program has
+// open, a file from the VFS or a socket from the network stack. New descriptors take the lowest
+// free number, as programs expect.
+
+use alloc::sync::Arc;
+
+use crate::{ filesystems::file::File,
+             net::socket::{ self, SocketHandle },
+             syscalls::{ errno::Errno, SyscallResult } };
+
+
+
+/// The most descriptors a program can have open at once.
+pub const MAX_DESCRIPTORS: usize = 256;
+
+
+
+/// What a descriptor stands for.
+#[derive(Clone)]
+pub enum Descriptor
+{
+    /// An open file.
+    File(Arc<File>),
+
+    /// A socket. Calls on a non-blocking socket fail with EAGAIN instead of waiting.
+    Socket { handle: SocketHandle, nonblocking: bool }
+}
+
+
+
+/// The descriptors a program has open.
+pub struct FileDescriptorTable
+{
+    descriptors: [Option<Descriptor>; MAX_DESCRIPTORS]
+}
+
+
+
+impl FileDescriptorTable
+{
+    /// Create an empty table.
+    pub const fn new() -> FileDescriptorTable
+    {
+        FileDescriptorTable { descriptors: [ const { None }; MAX_DESCRIPTORS ] }
+    }
+
+    /// Add a descriptor to the table, returning its number.
+    pub fn insert(&mut self, descriptor: Descriptor) -> SyscallResult<usize>
+    {
+        let number = self.descriptors
+                         .iter()
+                         .position(Option::is_none)
+                         .ok_or(Errno::EMFILE)?;
+
+        self.descriptors[number] = Some(descriptor);
+
+        Ok(number)
+    }
+
+    /// Get a copy of a descriptor, so that it can be used without the table being held.
+    pub fn get(&self, number: usize) -> SyscallResult<Descriptor>
+    {
+        self.descriptors
+            .get(number)
+            .and_then(Option::clone)
+            .ok_or(Errno::EBADF)
+    }
+
+    /// Take a descriptor out of the table.
+    pub fn remove(&mut self, number: usize) -> SyscallResult<Descriptor>
+    {
+        self.descriptors
+            .get_mut(number)
+            .and_then(Option::take)
+            .ok_or(Errno::EBADF)
+    }
+
+    /// Get the socket a descriptor stands for, and whether it's non-blocking.
+    pub fn socket(&self, number: usize) -> SyscallResult<(SocketHandle, bool)>
+    {
+        match self.get(number)?
+        {
+            Descriptor::Socket { handle, nonblocking } => Ok((handle, nonblocking)),
+            Descriptor::File(_)                        => Err(Errno::ENOTSOCK)
+        }
+    }
+
+    /// Close every descriptor in the table.
+    pub fn close_all(&mut self)
+    {
+        for descriptor in self.descriptors.iter_mut()
+        {
+            if let Some(descriptor) = descriptor.take()
+            {
+                let _ = close_descriptor(descriptor);
+            }
+        }
+    }
+}
+
+
+
+/// Close what a descriptor stands for once it has been taken out of the table. A file is closed
+/// when the last reference to it goes, a socket is closed right away.
+pub fn close_descriptor(descriptor: Descriptor) -> SyscallResult<()>
+{
+    match descriptor
+    {
+        Descriptor::File(_)                => Ok(()),
+        Descriptor::Socket { handle, .. } => Ok(socket::close(handle)?)
+    }
+}
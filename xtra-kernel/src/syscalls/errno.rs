@@ -0,0 +1,135 @@
+// The error numbers the calls return, negated, to user programs. They're the Linux numbers, as the
+// C libraries we'll be porting expect, and the kernel's own errors are mapped onto them here.
+
+use core::fmt::{ self, Display, Formatter };
+
+use crate::{ filesystems::FsError, net::NetError };
+
+
+
+/// An error number.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Errno(pub isize);
+
+
+
+impl Errno
+{
+    pub const EPERM:           Errno = Errno(1);
+    pub const ENOENT:          Errno = Errno(2);
+    pub const EIO:             Errno = Errno(5);
+    pub const EBADF:           Errno = Errno(9);
+    pub const EAGAIN:          Errno = Errno(11);
+    pub const EACCES:          Errno = Errno(13);
+    pub const EFAULT:          Errno = Errno(14);
+    pub const EBUSY:           Errno = Errno(16);
+    pub const EEXIST:          Errno = Errno(17);
+    pub const ENODEV:          Errno = Errno(19);
+    pub const ENOTDIR:         Errno = Errno(20);
+    pub const EISDIR:          Errno = Errno(21);
+    pub const EINVAL:          Errno = Errno(22);
+    pub const EMFILE:          Errno = Errno(24);
+    pub const EFBIG:           Errno = Errno(27);
+    pub const ENOSPC:          Errno = Errno(28);
+    pub const EROFS:           Errno = Errno(30);
+    pub const EPIPE:           Errno = Errno(32);
+    pub const ENAMETOOLONG:    Errno = Errno(36);
+    pub const ENOSYS:          Errno = Errno(38);
+    pub const ENOTEMPTY:       Errno = Errno(39);
+    pub const ELOOP:           Errno = Errno(40);
+    pub const ENOTSOCK:        Errno = Errno(88);
+    pub const EMSGSIZE:        Errno = Errno(90);
+    pub const EPROTONOSUPPORT: Errno = Errno(93);
+    pub const EOPNOTSUPP:      Errno = Errno(95);
+    pub const EAFNOSUPPORT:    Errno = Errno(97);
+    pub const EADDRINUSE:      Errno = Errno(98);
+    pub const EADDRNOTAVAIL:   Errno = Errno(99);
+    pub const ENETDOWN:        Errno = Errno(100);
+    pub const ENETUNREACH:     Errno = Errno(101);
+    pub const ECONNRESET:      Errno = Errno(104);
+    pub const ENOBUFS:         Errno = Errno(105);
+    pub const EISCONN:         Errno = Errno(106);
+    pub const ENOTCONN:        Errno = Errno(107);
+    pub const ETIMEDOUT:       Errno = Errno(110);
+    pub const ECONNREFUSED:    Errno = Errno(111);
+    pub const EINPROGRESS:     Errno = Errno(115);
+}
+
+
+
+impl Display for Errno
+{
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result
+    {
+        write!(formatter, "errno {}", self.0)
+    }
+}
+
+
+
+impl From<NetError> for Errno
+{
+    fn from(error: NetError) -> Self
+    {
+        match error
+        {
+            NetError::WouldBlock          => Errno::EAGAIN,
+            NetError::InProgress          => Errno::EINPROGRESS,
+            NetError::AlreadyConnected    => Errno::EISCONN,
+            NetError::NotConnected        => Errno::ENOTCONN,
+            NetError::ConnectionRefused   => Errno::ECONNREFUSED,
+            NetError::ConnectionReset     => Errno::ECONNRESET,
+            NetError::TimedOut            => Errno::ETIMEDOUT,
+            NetError::BrokenPipe          => Errno::EPIPE,
+            NetError::AddressInUse        => Errno::EADDRINUSE,
+            NetError::AddressNotAvailable => Errno::EADDRNOTAVAIL,
+            NetError::NetworkUnreachable  => Errno::ENETUNREACH,
+            NetError::MessageTooLong      => Errno::EMSGSIZE,
+            NetError::NoBufferSpace       => Errno::ENOBUFS,
+            NetError::InvalidArgument     => Errno::EINVAL,
+            NetError::BadHandle           => Errno::EBADF,
+            NetError::NotSupported        => Errno::EOPNOTSUPP,
+            NetError::NetworkDown         => Errno::ENETDOWN,
+            NetError::Device(_)           => Errno::EIO
+        }
+    }
+}
+
+
+
+impl From<FsError> for Errno
+{
+    fn from(error: FsError) -> Self
+    {
+        match error
+        {
+            FsError::NotFound             => Errno::ENOENT,
+            FsError::AlreadyExists        => Errno::EEXIST,
+            FsError::NotADirectory        => Errno::ENOTDIR,
+            FsError::IsADirectory         => Errno::EISDIR,
+            FsError::DirectoryNotEmpty    => Errno::ENOTEMPTY,
+            FsError::ReadOnly             => Errno::EROFS,
+            FsError::PermissionDenied     => Errno::EACCES,
+            FsError::BadFileMode          => Errno::EBADF,
+            FsError::InvalidPath          => Errno::EINVAL,
+            FsError::NameTooLong          => Errno::ENAMETOOLONG,
+            FsError::TooManySymbolicLinks => Errno::ELOOP,
+            FsError::NoSpace              => Errno::ENOSPC,
+            FsError::FileTooLarge         => Errno::EFBIG,
+            FsError::Busy                 => Errno::EBUSY,
+            FsError::InvalidArgument      => Errno::EINVAL,
+            FsError::Unsupported          => Errno::EOPNOTSUPP,
+            FsError::NotMounted           => Errno::EINVAL,
+
+            FsError::NoRootFilesystem
+            | FsError::DeviceNotFound { .. }
+            | FsError::PartitionNotFound { .. }
+            | FsError::PartitionUuidNotFound { .. }
+            | FsError::LabelNotFound { .. }
+            | FsError::UnknownFilesystemType
+            | FsError::NoFilesystemDriver { .. } => Errno::ENODEV,
+
+            FsError::Corrupted(_) | FsError::Io(_) => Errno::EIO
+        }
+    }
+}
@@ -0,0 +1,50 @@
+// The calls that work on any file descriptor. Reading and writing a file goes through the VFS, and
+// reading and writing a socket is the same as recv and send without flags.
+
+use crate::syscalls::{ descriptors::{ close_descriptor, Descriptor },
+                       sockets::{ socket_receive, socket_send },
+                       user_memory::{ user_buffer, user_buffer_mut },
+                       with_descriptors,
+                       SyscallResult };
+
+
+
+/// read(fd, buffer, count), read from a file or socket into the buffer.
+pub fn sys_read(descriptor: usize, buffer: usize, count: usize) -> SyscallResult<usize>
+{
+    let buffer = user_buffer_mut(buffer, count)?;
+
+    match with_descriptors(|table| table.get(descriptor))?
+    {
+        Descriptor::File(file) => Ok(file.read(buffer)?),
+
+        Descriptor::Socket { handle, nonblocking } =>
+            socket_receive(handle, nonblocking, buffer).map(|(count, _)| count)
+    }
+}
+
+
+/// write(fd, buffer, count), write the buffer to a file or socket.
+pub fn sys_write(descriptor: usize, buffer: usize, count: usize) -> SyscallResult<usize>
+{
+    let buffer = user_buffer(buffer, count)?;
+
+    match with_descriptors(|table| table.get(descriptor))?
+    {
+        Descriptor::File(file) => Ok(file.write(buffer)?),
+
+        Descriptor::Socket { handle, nonblocking } =>
+            socket_send(handle, nonblocking, buffer, None)
+    }
+}
+
+
+/// close(fd), close a file or socket. The descriptor is gone even if closing reports an error.
+pub fn sys_close(descriptor: usize) -> SyscallResult<usize>
+{
+    let descriptor = with_descriptors(|table| table.remove(descriptor))?;
+
+    close_descriptor(descriptor)?;
+
+    Ok(0)
+}
@@ -0,0 +1,111 @@
+// The system calls user programs make into the kernel. A call arrives as an ecall with its number
+// in a7 and up to six arguments in a0 to a5, and its result goes back in a0, following the Linux
+// RISC-V calling convention and numbering so that ordinary C libraries can be ported to xtra. A
+// negative result is an error number, anything else is the call's value.
+//
+// Once the trap handler takes ecalls from user mode it passes them to `handle_syscall` here, which
+// works out which call was made and runs it. The calls find what they operate on through the file
+// descriptor table, where open files and sockets sit side by side so that read, write and close
+// work the same on both.
+//
+// There's no scheduler to put a thread to sleep yet, so a call that has to wait, a blocking read
+// on a socket for instance, polls the network stack until it can complete.
+
+use crate::locking::spin_mutex::SpinMutex;
+
+
+
+/// The error numbers returned to user programs, and how kernel errors map onto them.
+pub mod errno;
+
+/// The table of file descriptors, the files and sockets a program has open.
+pub mod descriptors;
+
+/// Reaching the buffers and structures user programs pass to the calls.
+pub mod user_memory;
+
+/// The calls that work on any file descriptor, read, write and close.
+pub mod files;
+
+/// The BSD socket calls.
+pub mod sockets;
+
+/// The getrandom call.
+pub mod random;
+
+
+
+use crate::syscalls::{ descriptors::FileDescriptorTable, errno::Errno };
+
+
+
+// The numbers of the calls, as in the Linux RISC-V ABI. There's no separate send or recv, they're
+// sendto and recvfrom without an address, and poll is ppoll.
+pub const SYS_CLOSE:     usize = 57;
+pub const SYS_READ:      usize = 63;
+pub const SYS_WRITE:     usize = 64;
+pub const SYS_PPOLL:     usize = 73;
+pub const SYS_SOCKET:    usize = 198;
+pub const SYS_BIND:      usize = 200;
+pub const SYS_LISTEN:    usize = 201;
+pub const SYS_ACCEPT:    usize = 202;
+pub const SYS_CONNECT:   usize = 203;
+pub const SYS_SENDTO:    usize = 206;
+pub const SYS_RECVFROM:  usize = 207;
+pub const SYS_SHUTDOWN:  usize = 210;
+pub const SYS_GETRANDOM: usize = 278;
+
+/// The number of arguments a call can take.
+pub const SYSCALL_ARGUMENTS: usize = 6;
+
+
+
+/// The result of a call, its value or the error to report.
+pub type SyscallResult<T> = Result<T, Errno>;
+
+
+
+/// The file descriptors. Until there are processes there's the one table, which moves into the
+/// process once there are.
+static DESCRIPTORS: SpinMutex<FileDescriptorTable> = SpinMutex::new(FileDescriptorTable::new());
+
+
+
+/// Run a function with the file descriptor table locked. The table must not be held while a call
+/// waits, the calls copy out what they need and let it go.
+pub fn with_descriptors<Value>(function: impl FnOnce(&mut FileDescriptorTable) -> Value) -> Value
+{
+    function(&mut DESCRIPTORS.lock())
+}
+
+
+
+/// Run a call, returning the value to give back in a0.
+pub fn handle_syscall(number: usize, arguments: [usize; SYSCALL_ARGUMENTS]) -> isize
+{
+    let [ a0, a1, a2, a3, a4, a5 ] = arguments;
+
+    let result = match number
+        {
+            SYS_CLOSE     => files::sys_close(a0),
+            SYS_READ      => files::sys_read(a0, a1, a2),
+            SYS_WRITE     => files::sys_write(a0, a1, a2),
+            SYS_PPOLL     => sockets::sys_ppoll(a0, a1, a2, a3, a4),
+            SYS_SOCKET    => sockets::sys_socket(a0, a1, a2),
+            SYS_BIND      => sockets::sys_bind(a0, a1, a2),
+            SYS_LISTEN    => sockets::sys_listen(a0, a1),
+            SYS_ACCEPT    => sockets::sys_accept(a0, a1, a2),
+            SYS_CONNECT   => sockets::sys_connect(a0, a1, a2),
+            SYS_SENDTO    => sockets::sys_sendto(a0, a1, a2, a3, a4, a5),
+            SYS_RECVFROM  => sockets::sys_recvfrom(a0, a1, a2, a3, a4, a5),
+            SYS_SHUTDOWN  => sockets::sys_shutdown(a0, a1),
+            SYS_GETRANDOM => random::sys_getrandom(a0, a1, a2),
+            _             => Err(Errno::ENOSYS)
+        };
+
+    match result
+    {
+        Ok(value)  => value as isize,
+        Err(error) => -error.0
+    }
+}
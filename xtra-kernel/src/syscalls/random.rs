@@ -0,0 +1,42 @@
+// The getrandom call, how programs get random bytes without needing /dev/urandom. Unlike reading
+// /dev/urandom it waits for the random number generator to be seeded, unless told not to.
+
+use crate::{ random::{ get_random_bytes, is_seeded, wait_until_seeded },
+             syscalls::{ errno::Errno, user_memory::user_buffer_mut, SyscallResult } };
+
+
+
+// The flags getrandom takes.
+pub const GRND_NONBLOCK: usize = 0x01;      // Fail with EAGAIN rather than wait for the seed.
+pub const GRND_RANDOM:   usize = 0x02;      // Historical, /dev/random rather than /dev/urandom.
+pub const GRND_INSECURE: usize = 0x04;      // Don't wait for the seed, and don't fail either.
+
+
+
+/// getrandom(buffer, length, flags), fill the buffer with random bytes. There's only the one
+/// generator, so GRND_RANDOM makes no difference.
+pub fn sys_getrandom(buffer: usize, length: usize, flags: usize) -> SyscallResult<usize>
+{
+    if    flags & !(GRND_NONBLOCK | GRND_RANDOM | GRND_INSECURE) != 0
+       || flags & (GRND_RANDOM | GRND_INSECURE) == (GRND_RANDOM | GRND_INSECURE)
+    {
+        return Err(Errno::EINVAL);
+    }
+
+    let buffer = user_buffer_mut(buffer, length)?;
+
+    if    flags & GRND_INSECURE == 0
+       && !is_seeded()
+    {
+        if flags & GRND_NONBLOCK != 0
+        {
+            return Err(Errno::EAGAIN);
+        }
+
+        wait_until_seeded();
+    }
+
+    get_random_bytes(buffer);
+
+    Ok(buffer.len())
+}
@@ -0,0 +1,492 @@
+// The BSD socket calls, on top of the network stack's socket API. Only IPv4 is supported, stream
+// sockets are TCP and datagram sockets UDP. Addresses are passed as the usual struct sockaddr_in.
+//
+// The stack never waits, so a call on a blocking socket that can't complete yet polls the network
+// until it can. A socket created with SOCK_NONBLOCK, or a send or receive with MSG_DONTWAIT, fails
+// with EAGAIN instead.
+
+use core::{ hint::spin_loop, mem::size_of };
+
+use crate::{ net::{ ipv4::Ipv4Address,
+                    poll_network,
+                    socket::{ self,
+                              Shutdown,
+                              SocketAddress,
+                              SocketHandle,
+                              SocketType },
+                    NetError,
+                    NetResult },
+             syscalls::{ descriptors::{ Descriptor, MAX_DESCRIPTORS },
+                         errno::Errno,
+                         files::sys_close,
+                         user_memory::{ read_user, user_buffer, user_buffer_mut, write_user },
+                         with_descriptors,
+                         SyscallResult },
+             timers::current_milliseconds };
+
+
+
+/// The IPv4 address family.
+const AF_INET: u16 = 2;
+
+// The socket types, and the flags that can be or'd into them.
+const SOCK_STREAM:    usize = 1;
+const SOCK_DGRAM:     usize = 2;
+const SOCK_TYPE_MASK: usize = 0xf;
+const SOCK_NONBLOCK:  usize = 0o4000;
+const SOCK_CLOEXEC:   usize = 0o2000000;
+
+// The protocols, 0 picks the one for the socket type.
+const IPPROTO_TCP: usize = 6;
+const IPPROTO_UDP: usize = 17;
+
+/// Don't wait, for this send or receive only.
+const MSG_DONTWAIT: usize = 0x40;
+
+// How shutdown is asked to shut the socket down.
+const SHUT_RD:   usize = 0;
+const SHUT_WR:   usize = 1;
+const SHUT_RDWR: usize = 2;
+
+// The events of poll.
+const POLLIN:   i16 = 0x01;
+const POLLOUT:  i16 = 0x04;
+const POLLERR:  i16 = 0x08;
+const POLLHUP:  i16 = 0x10;
+const POLLNVAL: i16 = 0x20;
+
+
+
+/// struct sockaddr_in, an IPv4 address and port.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockAddrIn
+{
+    family: u16,
+    port: [u8; 2],
+    address: [u8; 4],
+    zero: [u8; 8]
+}
+
+
+
+/// struct pollfd, a descriptor to poll and the events that happened on it.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PollFd
+{
+    descriptor: i32,
+    events: i16,
+    returned_events: i16
+}
+
+
+
+/// struct timespec, the timeout of ppoll.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TimeSpec
+{
+    seconds: i64,
+    nanoseconds: i64
+}
+
+
+
+/// Read a socket address passed in by the program.
+fn read_socket_address(address: usize, length: usize) -> SyscallResult<SocketAddress>
+{
+    if length < size_of::<SockAddrIn>()
+    {
+        return Err(Errno::EINVAL);
+    }
+
+    let socket_address: SockAddrIn = read_user(address)?;
+
+    if socket_address.family != AF_INET
+    {
+        return Err(Errno::EAFNOSUPPORT);
+    }
+
+    Ok(SocketAddress::new(Ipv4Address(socket_address.address),
+                          u16::from_be_bytes(socket_address.port)))
+}
+
+
+/// Give a socket address back to the program, if it asked for it. As much of the address as fits
+/// in the program's buffer is written, and its length is set to the full size of the address.
+fn write_socket_address(address: usize,
+                        length_address: usize,
+                        value: SocketAddress) -> SyscallResult<()>
+{
+    if address == 0
+    {
+        return Ok(());
+    }
+
+    let size = size_of::<SockAddrIn>();
+    let length = read_user::<u32>(length_address)? as usize;
+    let count = length.min(size);
+
+    // The address is built up as bytes, so that it can be cut short to fit the program's buffer.
+    let mut bytes = [ 0; size_of::<SockAddrIn>() ];
+
+    bytes[0..2].copy_from_slice(&AF_INET.to_ne_bytes());
+    bytes[2..4].copy_from_slice(&value.port.to_be_bytes());
+    bytes[4..8].copy_from_slice(&value.address.0);
+
+    user_buffer_mut(address, count)?.copy_from_slice(&bytes[..count]);
+    write_user(length_address, size as u32)
+}
+
+
+/// Run a socket operation, polling the network and trying again while it would block, unless the
+/// socket is non-blocking.
+fn wait_for<Value>(nonblocking: bool,
+                   mut operation: impl FnMut() -> NetResult<Value>) -> SyscallResult<Value>
+{
+    loop
+    {
+        match operation()
+        {
+            Err(NetError::WouldBlock) if !nonblocking => {},
+            result                                    => return Ok(result?)
+        }
+
+        poll_network();
+        spin_loop();
+    }
+}
+
+
+/// Send data on a socket, to the destination if there is one. A blocking stream socket waits until
+/// all of the data has been taken, otherwise as much as there was room for is sent.
+pub fn socket_send(handle: SocketHandle,
+                   nonblocking: bool,
+                   data: &[u8],
+                   destination: Option<SocketAddress>) -> SyscallResult<usize>
+{
+    let mut sent = 0;
+
+    loop
+    {
+        let result = match destination
+            {
+                Some(destination) => socket::send_to(handle, &data[sent..], destination),
+                None              => socket::send(handle, &data[sent..])
+            };
+
+        match result
+        {
+            Ok(count) =>
+                {
+                    sent += count;
+
+                    if    sent == data.len()
+                       || nonblocking
+                    {
+                        return Ok(sent);
+                    }
+                },
+
+            Err(NetError::WouldBlock) if !nonblocking => {},
+
+            // Once some of the data has gone the error is left for the next call to report.
+            Err(_) if sent > 0 => return Ok(sent),
+            Err(error)         => return Err(error.into())
+        }
+
+        poll_network();
+        spin_loop();
+    }
+}
+
+
+/// Receive data from a socket, returning how much was received and where it came from.
+pub fn socket_receive(handle: SocketHandle,
+                      nonblocking: bool,
+                      buffer: &mut [u8]) -> SyscallResult<(usize, SocketAddress)>
+{
+    wait_for(nonblocking, || socket::receive_from(handle, buffer))
+}
+
+
+/// Work out the poll events of a descriptor, out of the ones asked for. Errors and hang ups are
+/// always reported.
+fn poll_descriptor(descriptor: i32, events: i16) -> i16
+{
+    if descriptor < 0
+    {
+        return 0;
+    }
+
+    let happened = match with_descriptors(|table| table.get(descriptor as usize))
+        {
+            // Files can always be read and written without waiting.
+            Ok(Descriptor::File(_)) => POLLIN | POLLOUT,
+
+            Ok(Descriptor::Socket { handle, .. }) =>
+                match socket::poll_socket(handle)
+                {
+                    Ok(events) => [ (events.readable, POLLIN),
+                                    (events.writable, POLLOUT),
+                                    (events.hung_up, POLLHUP),
+                                    (events.error, POLLERR) ]
+                        .iter()
+                        .filter(|(happened, _)| *happened)
+                        .fold(0, |happened, (_, event)| happened | event),
+
+                    Err(_) => POLLNVAL
+                },
+
+            Err(_) => POLLNVAL
+        };
+
+    happened & (events | POLLERR | POLLHUP | POLLNVAL)
+}
+
+
+
+/// socket(domain, type, protocol), create a socket.
+pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> SyscallResult<usize>
+{
+    if domain != AF_INET as usize
+    {
+        return Err(Errno::EAFNOSUPPORT);
+    }
+
+    if socket_type & !(SOCK_TYPE_MASK | SOCK_NONBLOCK | SOCK_CLOEXEC) != 0
+    {
+        return Err(Errno::EINVAL);
+    }
+
+    // There's no exec yet, so close on exec is accepted and has nothing to do.
+    let nonblocking = socket_type & SOCK_NONBLOCK != 0;
+
+    let socket_type = match (socket_type & SOCK_TYPE_MASK, protocol)
+        {
+            (SOCK_STREAM, 0 | IPPROTO_TCP) => SocketType::Stream,
+            (SOCK_DGRAM, 0 | IPPROTO_UDP)  => SocketType::Datagram,
+            (SOCK_STREAM | SOCK_DGRAM, _)  => return Err(Errno::EPROTONOSUPPORT),
+            _                              => return Err(Errno::EINVAL)
+        };
+
+    let handle = socket::create_socket(socket_type)?;
+    let result = with_descriptors(|table| table.insert(Descriptor::Socket { handle, nonblocking }));
+
+    if result.is_err()
+    {
+        let _ = socket::close(handle);
+    }
+
+    result
+}
+
+
+/// bind(fd, address, length), bind a socket to a local address and port.
+pub fn sys_bind(descriptor: usize, address: usize, length: usize) -> SyscallResult<usize>
+{
+    let (handle, _) = with_descriptors(|table| table.socket(descriptor))?;
+
+    socket::bind(handle, read_socket_address(address, length)?)?;
+
+    Ok(0)
+}
+
+
+/// listen(fd, backlog), have a stream socket listen for connections.
+pub fn sys_listen(descriptor: usize, backlog: usize) -> SyscallResult<usize>
+{
+    let (handle, _) = with_descriptors(|table| table.socket(descriptor))?;
+
+    socket::listen(handle, (backlog as i32).max(0) as usize)?;
+
+    Ok(0)
+}
+
+
+/// accept(fd, address, length), accept a connection, returning the new socket's descriptor. The
+/// address it came from is written to the address given, if there is one.
+pub fn sys_accept(descriptor: usize, address: usize, length: usize) -> SyscallResult<usize>
+{
+    let (handle, nonblocking) = with_descriptors(|table| table.socket(descriptor))?;
+    let (connection, remote) = wait_for(nonblocking, || socket::accept(handle))?;
+
+    let new_descriptor = Descriptor::Socket { handle: connection, nonblocking: false };
+
+    let number = match with_descriptors(|table| table.insert(new_descriptor))
+        {
+            Ok(number) => number,
+            Err(error) =>
+                {
+                    let _ = socket::close(connection);
+                    return Err(error);
+                }
+        };
+
+    // The program can't be told about a connection it can't be given the address of.
+    if let Err(error) = write_socket_address(address, length, remote)
+    {
+        let _ = sys_close(number);
+        return Err(error);
+    }
+
+    Ok(number)
+}
+
+
+/// connect(fd, address, length), connect a socket. A blocking stream socket waits until the
+/// connection is made or fails, a non-blocking one fails with EINPROGRESS while it's being made.
+pub fn sys_connect(descriptor: usize, address: usize, length: usize) -> SyscallResult<usize>
+{
+    let (handle, nonblocking) = with_descriptors(|table| table.socket(descriptor))?;
+    let remote = read_socket_address(address, length)?;
+
+    loop
+    {
+        match socket::connect(handle, remote)
+        {
+            Err(NetError::InProgress | NetError::WouldBlock) if !nonblocking => {},
+            result                                                        => break result?
+        }
+
+        poll_network();
+        spin_loop();
+    }
+
+    Ok(0)
+}
+
+
+/// sendto(fd, buffer, length, flags, address, address_length), send data on a socket, to the
+/// address if there is one. Without an address this is send.
+pub fn sys_sendto(descriptor: usize,
+                  buffer: usize,
+                  length: usize,
+                  flags: usize,
+                  address: usize,
+                  address_length: usize) -> SyscallResult<usize>
+{
+    let (handle, nonblocking) = with_descriptors(|table| table.socket(descriptor))?;
+    let data = user_buffer(buffer, length)?;
+
+    let destination = match address
+        {
+            0       => None,
+            address => Some(read_socket_address(address, address_length)?)
+        };
+
+    socket_send(handle, nonblocking || flags & MSG_DONTWAIT != 0, data, destination)
+}
+
+
+/// recvfrom(fd, buffer, length, flags, address, address_length), receive data from a socket,
+/// writing where it came from to the address if there is one. Without an address this is recv.
+pub fn sys_recvfrom(descriptor: usize,
+                    buffer: usize,
+                    length: usize,
+                    flags: usize,
+                    address: usize,
+                    address_length: usize) -> SyscallResult<usize>
+{
+    let (handle, nonblocking) = with_descriptors(|table| table.socket(descriptor))?;
+    let buffer = user_buffer_mut(buffer, length)?;
+
+    let (count, source) = socket_receive(handle,
+                                         nonblocking || flags & MSG_DONTWAIT != 0,
+                                         buffer)?;
+
+    write_socket_address(address, address_length, source)?;
+
+    Ok(count)
+}
+
+
+/// shutdown(fd, how), shut down receiving, sending or both on a socket.
+pub fn sys_shutdown(descriptor: usize, how: usize) -> SyscallResult<usize>
+{
+    let (handle, _) = with_descriptors(|table| table.socket(descriptor))?;
+
+    let how = match how
+        {
+            SHUT_RD   => Shutdown::Read,
+            SHUT_WR   => Shutdown::Write,
+            SHUT_RDWR => Shutdown::Both,
+            _         => return Err(Errno::EINVAL)
+        };
+
+    socket::shutdown(handle, how)?;
+
+    Ok(0)
+}
+
+
+/// ppoll(fds, count, timeout, signal_mask, signal_mask_size), wait for events on a set of
+/// descriptors, returning how many have something to report. A null timeout waits for as long as
+/// it takes. There are no signals yet, so the mask is ignored.
+pub fn sys_ppoll(descriptors: usize,
+                 count: usize,
+                 timeout: usize,
+                 _signal_mask: usize,
+                 _signal_mask_size: usize) -> SyscallResult<usize>
+{
+    if count > MAX_DESCRIPTORS
+    {
+        return Err(Errno::EINVAL);
+    }
+
+    let deadline = match timeout
+        {
+            0 => None,
+
+            timeout =>
+                {
+                    let timeout: TimeSpec = read_user(timeout)?;
+
+                    if    timeout.seconds < 0
+                       || !(0..1_000_000_000).contains(&timeout.nanoseconds)
+                    {
+                        return Err(Errno::EINVAL);
+                    }
+
+                    // A timeout too long to have a deadline we can count to is as good as none.
+                    (timeout.seconds as u64).checked_mul(1000)
+                        .and_then(|milliseconds|
+                            {
+                                milliseconds.checked_add(timeout.nanoseconds as u64 / 1_000_000)
+                            })
+                        .and_then(|milliseconds| current_milliseconds().checked_add(milliseconds))
+                }
+        };
+
+    loop
+    {
+        let mut ready = 0;
+
+        for index in 0..count
+        {
+            let address = index.checked_mul(size_of::<PollFd>())
+                               .and_then(|offset| descriptors.checked_add(offset))
+                               .ok_or(Errno::EFAULT)?;
+            let mut entry: PollFd = read_user(address)?;
+
+            entry.returned_events = poll_descriptor(entry.descriptor, entry.events);
+
+            write_user(address, entry)?;
+
+            if entry.returned_events != 0
+            {
+                ready += 1;
+            }
+        }
+
+        if    ready > 0
+           || deadline.is_some_and(|deadline| current_milliseconds() >= deadline)
+        {
+            return Ok(ready);
+        }
+
+        poll_network();
+        spin_loop();
+    }
+}
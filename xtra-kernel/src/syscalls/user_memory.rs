@@ -0,0 +1,75 @@
+// Reaching the memory user programs pass to the calls, their buffers and structures. Nothing a
+// program hands us can be trusted, so every address is checked before it's used and anything that
+// isn't valid fails the call with EFAULT rather than faulting the kernel.
+//
+// Programs don't have address spaces of their own yet, so for now the checks can only catch null
+// and wrapping ranges. This is where the range is checked against the process's mappings once they
+// exist.
+
+use core::{ mem::size_of, slice };
+
+use crate::syscalls::{ errno::Errno, SyscallResult };
+
+
+
+/// Check that a range of user memory is one we can reach.
+fn check_range(address: usize, length: usize) -> SyscallResult<()>
+{
+    match address.checked_add(length)
+    {
+        _ if address == 0   => Err(Errno::EFAULT),
+        Some(_)             => Ok(()),
+        None                => Err(Errno::EFAULT)
+    }
+}
+
+
+
+/// Borrow a buffer the program passed in to be read. An empty buffer may have any address.
+pub fn user_buffer<'a>(address: usize, length: usize) -> SyscallResult<&'a [u8]>
+{
+    if length == 0
+    {
+        return Ok(&[]);
+    }
+
+    check_range(address, length)?;
+
+    // The range has been checked, and the program can't touch it while the call runs.
+    Ok(unsafe { slice::from_raw_parts(address as *const u8, length) })
+}
+
+
+/// Borrow a buffer the program passed in to be written to. An empty buffer may have any address.
+pub fn user_buffer_mut<'a>(address: usize, length: usize) -> SyscallResult<&'a mut [u8]>
+{
+    if length == 0
+    {
+        return Ok(&mut []);
+    }
+
+    check_range(address, length)?;
+
+    // As above, the range has been checked and the program is stopped in the call.
+    Ok(unsafe { slice::from_raw_parts_mut(address as *mut u8, length) })
+}
+
+
+/// Read a value from user memory. User structures need not be aligned.
+pub fn read_user<Value: Copy>(address: usize) -> SyscallResult<Value>
+{
+    check_range(address, size_of::<Value>())?;
+
+    Ok(unsafe { (address as *const Value).read_unaligned() })
+}
+
+
+/// Write a value to user memory.
+pub fn write_user<Value: Copy>(address: usize, value: Value) -> SyscallResult<()>
+{
+    check_range(address, size_of::<Value>())?;
+
+    unsafe { (address as *mut Value).write_unaligned(value) };
+
+    Ok(())
+}
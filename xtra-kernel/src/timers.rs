@@ -0,0 +1,162 @@
+// One shot kernel timers. A timer calls its handler from the timer interrupt once its deadline has
+// passed, so like the device interrupt handlers the timer handlers need to be quick and must only
+// take locks that are held with interrupts disabled everywhere else.
+//
+// The timers are kept in a fixed size table so that starting one doesn't allocate, they're started
+// often for things like key repeat. The timers are shared by all of the harts. A hart arms its own
+// timer when it starts a timer that's due before the others, and whichever hart takes the timer
+// interrupt runs the handlers that are due and arms itself for the next deadline.
+
+use core::sync::atomic::{ AtomicUsize, Ordering };
+
+use crate::{ arch::{ csr::read_time,
+                     interrupts::with_interrupts_disabled,
+                     sbi::set_timer },
+             devices::cpu_devices::timebase_frequency,
+             locking::spin_mutex::SpinMutex,
+             random::{ add_interrupt_randomness, TIMER_INTERRUPT } };
+
+
+
+/// The function called when a timer goes off, given the argument the timer was started with.
+pub type TimerHandler = fn(argument: usize);
+
+
+
+/// The most timers that can be running at once.
+pub const MAX_TIMERS: usize = 32;
+
+
+
+/// A running timer.
+#[derive(Clone, Copy)]
+struct Timer
+{
+    /// The ID the timer was given when it was started.
+    id: usize,
+
+    /// The value of the time CSR the timer goes off at.
+    deadline: u64,
+
+    /// The function to call when it goes off, and its argument.
+    handler: TimerHandler,
+    argument: usize
+}
+
+
+
+/// The running timers.
+static TIMERS: SpinMutex<[Option<Timer>; MAX_TIMERS]> = SpinMutex::new([None; MAX_TIMERS]);
+
+/// The IDs handed out to the timers, 0 is never used so that it can mean no timer.
+static NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(1);
+
+
+
+/// Convert a number of milliseconds into ticks of the time CSR.
+pub fn milliseconds_to_ticks(milliseconds: u64) -> u64
+{
+    timebase_frequency() * milliseconds / 1000
+}
+
+
+/// The number of milliseconds since the time CSR started counting, at about the time the system
+/// was started.
+pub fn current_milliseconds() -> u64
+{
+    let ticks = read_time() as u128;
+
+    (ticks * 1000 / timebase_frequency().max(1) as u128) as u64
+}
+
+
+/// Start a timer that calls the handler with the argument once the given number of milliseconds
+/// have passed. Returns the timer's ID, which can be used to cancel it.
+pub fn start_timer(milliseconds: u64,
+                   handler: TimerHandler,
+                   argument: usize) -> Result<usize, &'static str>
+{
+    let deadline = read_time() + milliseconds_to_ticks(milliseconds);
+    let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+
+    with_interrupts_disabled(||
+        {
+            let mut timers = TIMERS.lock();
+
+            let slot = timers.iter_mut()
+                .find(|slot| slot.is_none())
+                .ok_or("There are too many timers running.")?;
+
+            *slot = Some(Timer { id, deadline, handler, argument });
+
+            // Only arm the timer if this is the next one due, otherwise whichever hart runs the
+            // earlier timers takes care of it.
+            if next_deadline(&timers) == Some(deadline)
+            {
+                let _ = set_timer(deadline);
+            }
+
+            Ok(id)
+        })
+}
+
+
+/// Stop a timer before it goes off. Returns false if it had already gone off or didn't exist.
+pub fn cancel_timer(id: usize) -> bool
+{
+    with_interrupts_disabled(||
+        {
+            let mut timers = TIMERS.lock();
+
+            match timers.iter_mut().find(|slot| matches!(slot, Some(timer) if timer.id == id))
+            {
+                Some(slot) =>
+                    {
+                        *slot = None;
+                        true
+                    },
+
+                None => false
+            }
+        })
+}
+
+
+
+/// Handle the timer interrupt, called from the trap handler. The timers that are due are taken
+/// out of the table and their handlers called without the lock held, so that the handlers can
+/// start new timers.
+pub fn handle_timer_interrupt()
+{
+    add_interrupt_randomness(TIMER_INTERRUPT);
+
+    loop
+    {
+        let now = read_time();
+
+        let due = TIMERS.lock()
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(timer) if timer.deadline <= now))
+            .and_then(Option::take);
+
+        match due
+        {
+            Some(timer) => (timer.handler)(timer.argument),
+            None        => break
+        }
+    }
+
+    // Arm the timer for the next deadline, or push it out of the way if there isn't one so that
+    // the interrupt is no longer pending.
+    let timers = TIMERS.lock();
+
+    let _ = set_timer(next_deadline(&timers).unwrap_or(u64::MAX));
+}
+
+
+
+/// Find the deadline of the next timer to go off.
+fn next_deadline(timers: &[Option<Timer>; MAX_TIMERS]) -> Option<u64>
+{
+    timers.iter().flatten().map(|timer| timer.deadline).min()
+}